@@ -0,0 +1,594 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*! This is a library for replaying WebGPU traces.
+ *
+ * The `Replayer` goes through the trace one action at a time, and allows
+ * stopping at any of them to read back the contents of buffers and textures.
+ *
+ * # Notes
+ * - we call device_maintain_ids() before creating any refcounted resource,
+ *   which is basically everything except for BGL and shader modules,
+ *   so that we don't accidentally try to use the same ID.
+!*/
+
+use wgc::{device::trace, id::TypedId as _};
+
+use std::{ffi::CString, fmt::Debug, marker::PhantomData, path::PathBuf, ptr};
+
+macro_rules! gfx_select {
+    ($id:expr => $global:ident.$method:ident( $($param:expr),+ )) => {
+        match $id.backend() {
+            #[cfg(not(any(target_os = "ios", target_os = "macos")))]
+            wgt::Backend::Vulkan => $global.$method::<wgc::backend::Vulkan>( $($param),+ ),
+            #[cfg(any(target_os = "ios", target_os = "macos"))]
+            wgt::Backend::Metal => $global.$method::<wgc::backend::Metal>( $($param),+ ),
+            #[cfg(windows)]
+            wgt::Backend::Dx12 => $global.$method::<wgc::backend::Dx12>( $($param),+ ),
+            #[cfg(windows)]
+            wgt::Backend::Dx11 => $global.$method::<wgc::backend::Dx11>( $($param),+ ),
+            _ => unreachable!()
+        }
+    };
+}
+
+mod replay;
+mod surface;
+
+pub use replay::Replayer;
+
+struct Label(Option<CString>);
+impl Label {
+    fn new(text: &str) -> Self {
+        Self(if text.is_empty() {
+            None
+        } else {
+            Some(CString::new(text).expect("invalid label"))
+        })
+    }
+
+    fn as_ptr(&self) -> *const std::os::raw::c_char {
+        match self.0 {
+            Some(ref c_string) => c_string.as_ptr(),
+            None => ptr::null(),
+        }
+    }
+}
+
+struct OwnedProgrammableStage {
+    desc: wgc::pipeline::ProgrammableStageDescriptor,
+    #[allow(dead_code)]
+    entry_point: CString,
+}
+
+impl From<trace::ProgrammableStageDescriptor> for OwnedProgrammableStage {
+    fn from(stage: trace::ProgrammableStageDescriptor) -> Self {
+        let entry_point = CString::new(stage.entry_point.as_str()).unwrap();
+        OwnedProgrammableStage {
+            desc: wgc::pipeline::ProgrammableStageDescriptor {
+                module: stage.module,
+                entry_point: entry_point.as_ptr(),
+            },
+            entry_point,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IdentityPassThrough<I>(PhantomData<I>);
+
+impl<I: Clone + Debug + wgc::id::TypedId> wgc::hub::IdentityHandler<I> for IdentityPassThrough<I> {
+    type Input = I;
+    fn process(&self, id: I, backend: wgt::Backend) -> I {
+        let (index, epoch, _backend) = id.unzip();
+        I::zip(index, epoch, backend)
+    }
+    fn free(&self, _id: I) {}
+}
+
+struct IdentityPassThroughFactory;
+
+impl<I: Clone + Debug + wgc::id::TypedId> wgc::hub::IdentityHandlerFactory<I>
+    for IdentityPassThroughFactory
+{
+    type Filter = IdentityPassThrough<I>;
+    fn spawn(&self, _min_index: u32) -> Self::Filter {
+        IdentityPassThrough(PhantomData)
+    }
+}
+impl wgc::hub::GlobalIdentityHandlerFactory for IdentityPassThroughFactory {}
+
+type Global = wgc::hub::Global<IdentityPassThroughFactory>;
+
+/// Source of buffer and texture IDs that are not used by the trace.
+struct SpareIds {
+    next_index: u32,
+}
+
+impl SpareIds {
+    fn new<'a>(actions: impl Iterator<Item = &'a trace::Action>) -> Self {
+        let next_index = actions
+            .filter_map(|action| match *action {
+                trace::Action::CreateBuffer { id, .. } => Some(id.unzip().0),
+                trace::Action::CreateTexture { id, .. } => Some(id.unzip().0),
+                _ => None,
+            })
+            .max()
+            .map_or(0, |index| index + 1);
+        SpareIds { next_index }
+    }
+
+    fn alloc<I: wgc::id::TypedId>(&mut self, backend: wgt::Backend) -> I {
+        self.next_index += 1;
+        I::zip(self.next_index - 1, 1, backend)
+    }
+}
+
+/// Run a recorded pass, listing its commands with their contexts if it fails.
+fn run_pass<C: Debug>(
+    commands: &[C],
+    contexts: &[wgc::command::PassCommandContext],
+    run: impl FnOnce(),
+) {
+    if let Err(cause) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run)) {
+        log::error!("Pass failed to replay, recorded commands:");
+        for (command, context) in commands.iter().zip(contexts) {
+            log::error!("\t{} {:?}", context, command);
+        }
+        std::panic::resume_unwind(cause);
+    }
+}
+
+trait GlobalExt {
+    fn encode_commands<B: wgc::hub::GfxBackend>(
+        &self,
+        encoder: wgc::id::CommandEncoderId,
+        commands: Vec<trace::Command>,
+    ) -> wgc::id::CommandBufferId;
+    fn process<B: wgc::hub::GfxBackend>(
+        &self,
+        device: wgc::id::DeviceId,
+        action: trace::Action,
+        dir: &PathBuf,
+        comb_manager: &mut wgc::hub::IdentityManager,
+    );
+}
+
+impl GlobalExt for Global {
+    fn encode_commands<B: wgc::hub::GfxBackend>(
+        &self,
+        encoder: wgc::id::CommandEncoderId,
+        commands: Vec<trace::Command>,
+    ) -> wgc::id::CommandBufferId {
+        for command in commands {
+            match command {
+                trace::Command::CopyBufferToBuffer {
+                    src,
+                    src_offset,
+                    dst,
+                    dst_offset,
+                    size,
+                } => self
+                    .command_encoder_copy_buffer_to_buffer::<B>(
+                        encoder, src, src_offset, dst, dst_offset, size,
+                    )
+                    .unwrap(),
+                trace::Command::CopyBufferRegions { src, dst, regions } => self
+                    .command_encoder_copy_buffer_regions::<B>(encoder, src, dst, &regions)
+                    .unwrap(),
+                trace::Command::CopyBufferToTexture { src, dst, size } => self
+                    .command_encoder_copy_buffer_to_texture::<B>(encoder, &src, &dst, size)
+                    .unwrap(),
+                trace::Command::CopyTextureToBuffer { src, dst, size } => self
+                    .command_encoder_copy_texture_to_buffer::<B>(encoder, &src, &dst, size)
+                    .unwrap(),
+                trace::Command::CopyTextureToTexture { src, dst, size } => self
+                    .command_encoder_copy_texture_to_texture::<B>(encoder, &src, &dst, size)
+                    .unwrap(),
+                trace::Command::RunComputePass {
+                    commands,
+                    dynamic_offsets,
+                    debug_labels,
+                    contexts,
+                } => unsafe {
+                    let mut offsets = &dynamic_offsets[..];
+                    let mut labels = debug_labels.iter();
+                    let mut pass = self.command_encoder_begin_compute_pass::<B>(
+                        encoder,
+                        &wgc::command::ComputePassDescriptor {
+                            label: std::ptr::null(),
+                        },
+                    );
+                    for com in commands.iter() {
+                        pass.encode(com);
+                        match *com {
+                            wgc::command::ComputeCommand::SetBindGroup {
+                                num_dynamic_offsets,
+                                ..
+                            } => {
+                                pass.encode_slice(&offsets[..num_dynamic_offsets as usize]);
+                                offsets = &offsets[num_dynamic_offsets as usize..];
+                            }
+                            wgc::command::ComputeCommand::PushDebugGroup { .. } => {
+                                pass.encode_slice(labels.next().unwrap().as_bytes());
+                            }
+                            _ => {}
+                        }
+                    }
+                    let (data, _) = pass.finish_compute();
+                    run_pass(&commands, &contexts, || {
+                        self.command_encoder_run_compute_pass::<B>(encoder, &data)
+                            .unwrap()
+                    });
+                },
+                trace::Command::RunRenderPass {
+                    target_colors,
+                    target_depth_stencil,
+                    commands,
+                    dynamic_offsets,
+                    inline_data,
+                    debug_labels,
+                    contexts,
+                } => unsafe {
+                    let mut offsets = &dynamic_offsets[..];
+                    let mut data = &inline_data[..];
+                    let mut labels = debug_labels.iter();
+                    let mut pass = self.command_encoder_begin_render_pass::<B>(
+                        encoder,
+                        &wgc::command::RenderPassDescriptor {
+                            color_attachments: target_colors.as_ptr(),
+                            color_attachments_length: target_colors.len(),
+                            depth_stencil_attachment: target_depth_stencil.as_ref(),
+                            label: std::ptr::null(),
+                        },
+                    );
+                    for com in commands.iter() {
+                        pass.encode(com);
+                        match *com {
+                            wgc::command::RenderCommand::SetBindGroup {
+                                num_dynamic_offsets,
+                                ..
+                            } => {
+                                pass.encode_slice(&offsets[..num_dynamic_offsets as usize]);
+                                offsets = &offsets[num_dynamic_offsets as usize..];
+                            }
+                            wgc::command::RenderCommand::PushDebugGroup { .. } => {
+                                pass.encode_slice(labels.next().unwrap().as_bytes());
+                            }
+                            wgc::command::RenderCommand::WriteInlineUniform {
+                                data_length, ..
+                            } => {
+                                pass.encode_slice(&data[..data_length as usize]);
+                                data = &data[data_length as usize..];
+                            }
+                            _ => {}
+                        }
+                    }
+                    let (data, _) = pass.finish_render();
+                    run_pass(&commands, &contexts, || {
+                        self.command_encoder_run_render_pass::<B>(encoder, &data)
+                            .unwrap()
+                    });
+                },
+            }
+        }
+        self.command_encoder_finish::<B>(encoder, &wgt::CommandBufferDescriptor { todo: 0 })
+            .unwrap()
+    }
+
+    fn process<B: wgc::hub::GfxBackend>(
+        &self,
+        device: wgc::id::DeviceId,
+        action: trace::Action,
+        dir: &PathBuf,
+        comb_manager: &mut wgc::hub::IdentityManager,
+    ) {
+        use wgc::device::trace::Action as A;
+        match action {
+            A::Init { .. } => panic!("Unexpected Action::Init: has to be the first action only"),
+            A::CreateSwapChain { .. } | A::PresentSwapChain(_) | A::DiscardSwapChainFrame(_) => {
+                panic!("Unexpected SwapChain action: has to be handled by the surface")
+            }
+            A::CreateBuffer { id, desc } => {
+                let label = Label::new(&desc.label);
+                self.device_maintain_ids::<B>(device);
+                self.device_create_buffer::<B>(device, &desc.map_label(|_| label.as_ptr()), id)
+                    .unwrap();
+            }
+            A::DestroyBuffer(id) => {
+                self.buffer_destroy::<B>(id);
+            }
+            A::CreateTexture { id, desc } => {
+                let label = Label::new(&desc.label);
+                self.device_maintain_ids::<B>(device);
+                self.device_create_texture::<B>(device, &desc.map_label(|_| label.as_ptr()), id)
+                    .unwrap();
+            }
+            A::DestroyTexture(id) => {
+                self.texture_destroy::<B>(id);
+            }
+            A::CreateTextureView {
+                id,
+                parent_id,
+                desc,
+            } => {
+                let label = desc.as_ref().map_or(Label(None), |d| Label::new(&d.label));
+                self.device_maintain_ids::<B>(device);
+                self.texture_create_view::<B>(
+                    parent_id,
+                    desc.map(|d| d.map_label(|_| label.as_ptr())).as_ref(),
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyTextureView(id) => {
+                self.texture_view_destroy::<B>(id);
+            }
+            A::CreateSampler { id, desc } => {
+                let label = Label::new(&desc.label);
+                self.device_maintain_ids::<B>(device);
+                self.device_create_sampler::<B>(device, &desc.map_label(|_| label.as_ptr()), id)
+                    .unwrap();
+            }
+            A::DestroySampler(id) => {
+                self.sampler_destroy::<B>(id);
+            }
+            // Objects are replayed without deduplication, and only destroyed
+            // once the last handle is, so the extra handles need nothing.
+            A::ReuseSampler(_) | A::ReuseBindGroup(_) => {}
+            A::GetSwapChainTexture { id, parent_id } => {
+                self.swap_chain_get_next_texture::<B>(parent_id, id)
+                    .unwrap();
+            }
+            A::CreateBindGroupLayout { id, label, entries } => {
+                let label = Label::new(&label);
+                self.device_create_bind_group_layout::<B>(
+                    device,
+                    &wgc::binding_model::BindGroupLayoutDescriptor {
+                        label: label.as_ptr(),
+                        entries: entries.as_ptr(),
+                        entries_length: entries.len(),
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyBindGroupLayout(id) => {
+                self.bind_group_layout_destroy::<B>(id);
+            }
+            A::CreatePipelineLayout {
+                id,
+                bind_group_layouts,
+            } => {
+                self.device_maintain_ids::<B>(device);
+                self.device_create_pipeline_layout::<B>(
+                    device,
+                    &wgc::binding_model::PipelineLayoutDescriptor {
+                        bind_group_layouts: bind_group_layouts.as_ptr(),
+                        bind_group_layouts_length: bind_group_layouts.len(),
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyPipelineLayout(id) => {
+                self.pipeline_layout_destroy::<B>(id);
+            }
+            A::CreateBindGroup {
+                id,
+                label,
+                layout_id,
+                entries,
+            } => {
+                use wgc::binding_model as bm;
+                let label = Label::new(&label);
+                let entry_vec = entries
+                    .into_iter()
+                    .map(|(binding, res)| wgc::binding_model::BindGroupEntry {
+                        binding,
+                        resource: match res {
+                            trace::BindingResource::Buffer { id, offset, size } => {
+                                bm::BindingResource::Buffer(bm::BufferBinding {
+                                    buffer: id,
+                                    offset,
+                                    size,
+                                })
+                            }
+                            trace::BindingResource::Sampler(id) => bm::BindingResource::Sampler(id),
+                            trace::BindingResource::TextureView(id) => {
+                                bm::BindingResource::TextureView(id)
+                            }
+                        },
+                    })
+                    .collect::<Vec<_>>();
+                self.device_maintain_ids::<B>(device);
+                self.device_create_bind_group::<B>(
+                    device,
+                    &wgc::binding_model::BindGroupDescriptor {
+                        label: label.as_ptr(),
+                        layout: layout_id,
+                        entries: entry_vec.as_ptr(),
+                        entries_length: entry_vec.len(),
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyBindGroup(id) => {
+                self.bind_group_destroy::<B>(id);
+            }
+            A::CreateShaderModule { id, label, data } => {
+                let label = Label::new(&label);
+                let spv = wgt::read_spirv(File::open(dir.join(data)).unwrap()).unwrap();
+                self.device_create_shader_module::<B>(
+                    device,
+                    &wgc::pipeline::ShaderModuleDescriptor {
+                        code: wgc::U32Array {
+                            bytes: spv.as_ptr(),
+                            length: spv.len(),
+                        },
+                        label: label.as_ptr(),
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyShaderModule(id) => {
+                self.shader_module_destroy::<B>(id);
+            }
+            A::CreateComputePipeline { id, desc } => {
+                let cs_stage = OwnedProgrammableStage::from(desc.compute_stage);
+                self.device_maintain_ids::<B>(device);
+                self.device_create_compute_pipeline::<B>(
+                    device,
+                    &wgc::pipeline::ComputePipelineDescriptor {
+                        layout: desc.layout,
+                        compute_stage: cs_stage.desc,
+                        cache: None,
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyComputePipeline(id) => {
+                self.compute_pipeline_destroy::<B>(id);
+            }
+            A::CreateRenderPipeline { id, desc } => {
+                let vs_stage = OwnedProgrammableStage::from(desc.vertex_stage);
+                let fs_stage = desc.fragment_stage.map(OwnedProgrammableStage::from);
+                let vertex_buffers = desc
+                    .vertex_state
+                    .vertex_buffers
+                    .iter()
+                    .map(|vb| wgc::pipeline::VertexBufferLayoutDescriptor {
+                        array_stride: vb.array_stride,
+                        step_mode: vb.step_mode,
+                        step_rate: vb.step_rate,
+                        attributes: vb.attributes.as_ptr(),
+                        attributes_length: vb.attributes.len(),
+                    })
+                    .collect::<Vec<_>>();
+                self.device_maintain_ids::<B>(device);
+                self.device_create_render_pipeline::<B>(
+                    device,
+                    &wgc::pipeline::RenderPipelineDescriptor {
+                        layout: desc.layout,
+                        vertex_stage: vs_stage.desc,
+                        fragment_stage: fs_stage.as_ref().map_or(ptr::null(), |s| &s.desc),
+                        primitive_topology: desc.primitive_topology,
+                        strip_index_format: desc
+                            .strip_index_format
+                            .as_ref()
+                            .map_or(ptr::null(), |format| format),
+                        rasterization_state: desc
+                            .rasterization_state
+                            .as_ref()
+                            .map_or(ptr::null(), |rs| rs),
+                        color_states: desc.color_states.as_ptr(),
+                        color_states_length: desc.color_states.len(),
+                        depth_stencil_state: desc
+                            .depth_stencil_state
+                            .as_ref()
+                            .map_or(ptr::null(), |ds| ds),
+                        vertex_state: wgc::pipeline::VertexStateDescriptor {
+                            index_format: desc.vertex_state.index_format,
+                            vertex_buffers: vertex_buffers.as_ptr(),
+                            vertex_buffers_length: vertex_buffers.len(),
+                        },
+                        sample_count: desc.sample_count,
+                        sample_mask: desc.sample_mask,
+                        alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
+                        alpha_to_one_enabled: desc.alpha_to_one_enabled,
+                        min_sample_shading: desc.min_sample_shading,
+                        cache: None,
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyRenderPipeline(id) => {
+                self.render_pipeline_destroy::<B>(id);
+            }
+            A::CreateMeshPipeline { id, desc } => {
+                let task_stage = desc.task_stage.map(OwnedProgrammableStage::from);
+                let mesh_stage = OwnedProgrammableStage::from(desc.mesh_stage);
+                let fragment_stage = desc.fragment_stage.map(OwnedProgrammableStage::from);
+                self.device_maintain_ids::<B>(device);
+                self.device_create_mesh_pipeline::<B>(
+                    device,
+                    &wgc::pipeline::MeshPipelineDescriptor {
+                        layout: desc.layout,
+                        task_stage: task_stage.as_ref().map_or(ptr::null(), |s| &s.desc),
+                        mesh_stage: mesh_stage.desc,
+                        fragment_stage: fragment_stage.as_ref().map_or(ptr::null(), |s| &s.desc),
+                        primitive_topology: desc.primitive_topology,
+                        rasterization_state: desc
+                            .rasterization_state
+                            .as_ref()
+                            .map_or(ptr::null(), |rs| rs),
+                        color_states: desc.color_states.as_ptr(),
+                        color_states_length: desc.color_states.len(),
+                        depth_stencil_state: desc
+                            .depth_stencil_state
+                            .as_ref()
+                            .map_or(ptr::null(), |ds| ds),
+                        sample_count: desc.sample_count,
+                        sample_mask: desc.sample_mask,
+                        alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
+                        alpha_to_one_enabled: desc.alpha_to_one_enabled,
+                        min_sample_shading: desc.min_sample_shading,
+                    },
+                    id,
+                )
+                .unwrap();
+            }
+            A::DestroyMeshPipeline(id) => {
+                self.mesh_pipeline_destroy::<B>(id);
+            }
+            A::WriteBuffer {
+                id,
+                data,
+                range,
+                queued,
+            } => {
+                let bin = std::fs::read(dir.join(data)).unwrap();
+                let size = (range.end - range.start) as usize;
+                if queued {
+                    self.queue_write_buffer::<B>(device, &bin, id, range.start)
+                        .unwrap();
+                } else {
+                    self.device_wait_for_buffer::<B>(device, id);
+                    self.device_set_buffer_sub_data::<B>(device, id, range.start, &bin[..size]);
+                }
+            }
+            A::WriteTexture {
+                id,
+                data,
+                regions,
+                layout,
+            } => {
+                let bin = std::fs::read(dir.join(data)).unwrap();
+                self.queue_write_texture_regions::<B>(device, id, &regions, &bin, layout)
+                    .unwrap();
+            }
+            A::Submit(_index, commands) => {
+                let encoder = self
+                    .device_create_command_encoder::<B>(
+                        device,
+                        &wgt::CommandEncoderDescriptor { label: ptr::null() },
+                        comb_manager.alloc(device.backend()),
+                    )
+                    .unwrap();
+                let comb = self.encode_commands::<B>(encoder, commands);
+                self.queue_submit::<B>(device, &[comb]).unwrap();
+            }
+            A::FlushPendingWrites(_index) => {
+                self.queue_flush_pending_writes::<B>(device).unwrap();
+            }
+            A::Counters(counters) => {
+                log::info!("Counters at capture: {:?}", counters);
+            }
+        }
+    }
+}
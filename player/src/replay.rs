@@ -0,0 +1,425 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*! Step-by-step replay of a trace.
+ *
+ * Every stop point flushes the pending writes and waits for the device
+ * to go idle, so the resources read back at that point reflect the state
+ * right after the last replayed action.
+ *
+ * Buffers with `MAP_READ` usage are mapped for reading. The other buffers,
+ * and textures, are copied through the pending writes of the queue, which
+ * needs the `COPY_SRC` usage. The replayer only adds it to the traced usages
+ * when asked to with `set_readable`.
+!*/
+
+use crate::{surface, Global, GlobalExt as _, IdentityPassThroughFactory, SpareIds};
+use wgc::device::trace;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    slice,
+};
+
+enum Surface {
+    /// Swap chain frames are ordinary textures.
+    Fake(surface::FakeSurface),
+    /// Swap chain frames are presented to a window.
+    Window(wgc::id::SurfaceId),
+}
+
+struct BufferInfo {
+    size: wgt::BufferAddress,
+    usage: wgt::BufferUsage,
+}
+
+pub struct Replayer {
+    global: Global,
+    device: wgc::id::DeviceId,
+    dir: PathBuf,
+    /// Actions that are not replayed yet, in reverse order.
+    actions: Vec<trace::Action>,
+    action_count: usize,
+    comb_manager: wgc::hub::IdentityManager,
+    spare_ids: SpareIds,
+    surface: Surface,
+    swap_chain_desc: Option<wgt::SwapChainDescriptor>,
+    buffers: HashMap<wgc::id::BufferId, BufferInfo>,
+    textures: HashMap<wgc::id::TextureId, wgt::TextureDescriptor<()>>,
+    readable: bool,
+}
+
+unsafe extern "C" fn copy_read_data(
+    status: wgc::resource::BufferMapAsyncStatus,
+    data: *const u8,
+    size: usize,
+    userdata: *mut u8,
+) {
+    let output = &mut *(userdata as *mut Vec<u8>);
+    match status {
+        wgc::resource::BufferMapAsyncStatus::Success => {
+            output.extend_from_slice(slice::from_raw_parts(data, size));
+        }
+        _ => log::error!("Unable to read the buffer back: {:?}", status),
+    }
+}
+
+impl Replayer {
+    /// Load the trace from `dir`, replaying the swap chain frames into
+    /// ordinary textures, and writing them into `dump_dir` if it's given.
+    pub fn new(dir: &Path, dump_dir: Option<PathBuf>) -> Self {
+        let global = Global::new("player", IdentityPassThroughFactory);
+        Self::init(
+            global,
+            dir,
+            Surface::Fake(surface::FakeSurface::new(dump_dir)),
+        )
+    }
+
+    /// Load the trace from `dir`, presenting the swap chain frames to the window.
+    pub fn with_window(dir: &Path, window: &impl raw_window_handle::HasRawWindowHandle) -> Self {
+        let global = Global::new("player", IdentityPassThroughFactory);
+        let surface = global
+            .instance_create_surface(window, wgc::id::TypedId::zip(0, 1, wgt::Backend::Empty));
+        Self::init(global, dir, Surface::Window(surface))
+    }
+
+    fn init(global: Global, dir: &Path, surface: Surface) -> Self {
+        log::info!("Loading trace '{:?}'", dir);
+        let file = File::open(dir.join(trace::FILE_NAME)).unwrap();
+        let mut actions: Vec<trace::Action> = ron::de::from_reader(file).unwrap();
+        actions.reverse(); // allows us to pop from the top
+        log::info!("Found {} actions", actions.len());
+
+        let device = match actions.pop() {
+            Some(trace::Action::Init {
+                mut desc,
+                backend,
+                deterministic_ids,
+            }) => {
+                log::info!("Initializing the device for backend: {:?}", backend);
+                if !deterministic_ids {
+                    log::warn!(
+                        "The trace was captured without deterministic IDs, \
+                        so its IDs can't be compared with other traces"
+                    );
+                }
+                let compatible_surface = match surface {
+                    Surface::Fake(_) => None,
+                    Surface::Window(surface) => Some(surface),
+                };
+                let adapter = global
+                    .pick_adapter(
+                        &wgc::instance::RequestAdapterOptions {
+                            power_preference: wgt::PowerPreference::Default,
+                            compatible_surface,
+                            force_fallback_adapter: false,
+                        },
+                        wgc::instance::AdapterInputs::IdSet(
+                            &[wgc::id::TypedId::zip(0, 0, backend)],
+                            |id| id.backend(),
+                        ),
+                    )
+                    .expect("Unable to find an adapter for selected backend");
+
+                let info = gfx_select!(adapter => global.adapter_get_info(adapter)).unwrap();
+                log::info!("Picked '{}'", info.name);
+                // Deduplicated objects are not recorded, and the IDs come from the trace.
+                desc.deduplicate_objects = false;
+                gfx_select!(adapter => global.adapter_request_device(
+                    adapter,
+                    &desc,
+                    None,
+                    wgc::id::TypedId::zip(1, 0, wgt::Backend::Empty)
+                ))
+                .unwrap()
+            }
+            _ => panic!("Expected Action::Init"),
+        };
+
+        Replayer {
+            global,
+            device,
+            dir: dir.to_path_buf(),
+            action_count: actions.len() + 1,
+            spare_ids: SpareIds::new(actions.iter()),
+            actions,
+            comb_manager: wgc::hub::IdentityManager::default(),
+            surface,
+            swap_chain_desc: None,
+            buffers: HashMap::new(),
+            textures: HashMap::new(),
+            readable: false,
+        }
+    }
+
+    /// Add the `COPY_SRC` usage to the buffers and textures created from now on,
+    /// unless it can't be combined with their traced usage, so that they can
+    /// be read back.
+    ///
+    /// Changing the usages may change how the resources are allocated and
+    /// laid out, so the traced usages are kept by default.
+    pub fn set_readable(&mut self, readable: bool) {
+        self.readable = readable;
+    }
+
+    /// Number of actions in the trace, including the initial `Action::Init`.
+    pub fn action_count(&self) -> usize {
+        self.action_count
+    }
+
+    /// Index of the action to be replayed next.
+    pub fn action_index(&self) -> usize {
+        self.action_count - self.actions.len()
+    }
+
+    /// Action to be replayed next, if the trace is not over.
+    pub fn current_action(&self) -> Option<&trace::Action> {
+        self.actions.last()
+    }
+
+    /// Index of the next action presenting a swap chain frame.
+    pub fn next_present_index(&self) -> Option<usize> {
+        self.actions
+            .iter()
+            .rev()
+            .position(|action| match *action {
+                trace::Action::PresentSwapChain(_) => true,
+                _ => false,
+            })
+            .map(|offset| self.action_index() + offset)
+    }
+
+    /// Descriptor of the swap chain created by the replayed actions, if any.
+    pub fn swap_chain_desc(&self) -> Option<&wgt::SwapChainDescriptor> {
+        self.swap_chain_desc.as_ref()
+    }
+
+    /// Replay the current action, and wait for the device to get done with it.
+    ///
+    /// Returns false if the trace is over.
+    pub fn step(&mut self) -> bool {
+        let replayed = self.replay_next();
+        self.wait_idle();
+        replayed
+    }
+
+    /// Replay all the actions before `action_index`, and wait for the device
+    /// to get done with them.
+    pub fn run_until(&mut self, action_index: usize) {
+        while self.action_index() < action_index && self.replay_next() {}
+        self.wait_idle();
+    }
+
+    /// Read back the contents of a buffer.
+    pub fn read_buffer(&mut self, id: wgc::id::BufferId) -> Vec<u8> {
+        let (size, usage) = match self.buffers.get(&id) {
+            Some(info) => (info.size, info.usage),
+            None => panic!("Buffer {:?} is not created by the replayed actions", id),
+        };
+        let (global, device) = (&self.global, self.device);
+        let mut data = Vec::with_capacity(size as usize);
+        assert!(
+            usage.intersects(wgt::BufferUsage::MAP_READ | wgt::BufferUsage::COPY_SRC),
+            "Buffer {:?} of usage {:?} can't be read back, see `Replayer::set_readable`",
+            id,
+            usage
+        );
+        if usage.contains(wgt::BufferUsage::MAP_READ) {
+            data.resize(size as usize, 0);
+            gfx_select!(device => global.buffer_map_async(
+                id,
+                0..size,
+                wgc::resource::BufferMapOperation::Read {
+                    callback: surface::copy_mapped_data,
+                    userdata: &mut data as *mut Vec<u8> as *mut u8,
+                }
+            ));
+            gfx_select!(device => global.device_poll(device, true));
+            gfx_select!(device => global.buffer_unmap(id));
+        } else {
+            gfx_select!(device => global.queue_read_buffer(
+                device,
+                id,
+                0..size,
+                copy_read_data,
+                &mut data as *mut Vec<u8> as *mut u8
+            ))
+            .unwrap();
+            gfx_select!(device => global.queue_flush_pending_writes(device)).unwrap();
+            gfx_select!(device => global.device_poll(device, true));
+        }
+        data
+    }
+
+    /// Read back the contents of a single mip level of a texture array layer.
+    ///
+    /// For 3D textures, `layer` selects the depth slice of the mip level.
+    /// The rows of the data are padded according to the returned layout.
+    pub fn read_texture(
+        &mut self,
+        id: wgc::id::TextureId,
+        mip_level: u32,
+        layer: u32,
+    ) -> (wgc::command::TextureDataLayout, Vec<u8>) {
+        let desc = match self.textures.get(&id) {
+            Some(desc) => desc,
+            None => panic!("Texture {:?} is not created by the replayed actions", id),
+        };
+        assert!(
+            desc.usage.contains(wgt::TextureUsage::COPY_SRC),
+            "Texture {:?} of usage {:?} can't be read back, see `Replayer::set_readable`",
+            id,
+            desc.usage
+        );
+        assert!(
+            mip_level < desc.mip_level_count,
+            "Mip level {} is out of range, texture {:?} has {} levels",
+            mip_level,
+            id,
+            desc.mip_level_count
+        );
+        let width = (desc.size.width >> mip_level).max(1);
+        let height = (desc.size.height >> mip_level).max(1);
+        let (layer_count, array_layer, origin_z) = match desc.dimension {
+            wgt::TextureDimension::D3 => ((desc.size.depth >> mip_level).max(1), 0, layer),
+            _ => (desc.size.depth, layer, 0),
+        };
+        assert!(
+            layer < layer_count,
+            "Layer {} is out of range, mip level {} of texture {:?} has {} layers",
+            layer,
+            mip_level,
+            id,
+            layer_count
+        );
+
+        let (global, device) = (&self.global, self.device);
+        let mut data = Vec::new();
+        let layout = gfx_select!(device => global.queue_read_texture(
+            device,
+            &wgc::command::TextureCopyView {
+                texture: id,
+                mip_level,
+                array_layer,
+                origin: wgt::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: origin_z,
+                },
+            },
+            wgt::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            copy_read_data,
+            &mut data as *mut Vec<u8> as *mut u8
+        ))
+        .unwrap();
+        gfx_select!(device => global.queue_flush_pending_writes(device)).unwrap();
+        gfx_select!(device => global.device_poll(device, true));
+        (layout, data)
+    }
+
+    fn replay_next(&mut self) -> bool {
+        let mut action = match self.actions.pop() {
+            Some(action) => action,
+            None => return false,
+        };
+        match action {
+            trace::Action::CreateBuffer { id, ref mut desc } => {
+                // `MAP_READ` can only be combined with `COPY_DST`.
+                if self.readable && !desc.usage.contains(wgt::BufferUsage::MAP_READ) {
+                    desc.usage |= wgt::BufferUsage::COPY_SRC;
+                }
+                self.buffers.insert(
+                    id,
+                    BufferInfo {
+                        size: desc.size,
+                        usage: desc.usage,
+                    },
+                );
+            }
+            trace::Action::DestroyBuffer(id) => {
+                self.buffers.remove(&id);
+            }
+            trace::Action::CreateTexture { id, ref mut desc } => {
+                let copyable = match desc.format {
+                    wgt::TextureFormat::Depth24Plus | wgt::TextureFormat::Depth24PlusStencil8 => {
+                        false
+                    }
+                    _ => {
+                        desc.sample_count == 1
+                            && !desc.usage.contains(wgt::TextureUsage::TRANSIENT_ATTACHMENT)
+                    }
+                };
+                if self.readable && copyable {
+                    desc.usage |= wgt::TextureUsage::COPY_SRC;
+                }
+                self.textures.insert(id, desc.map_label(|_| ()));
+            }
+            trace::Action::DestroyTexture(id) => {
+                self.textures.remove(&id);
+            }
+            trace::Action::CreateSwapChain { ref desc, .. } => {
+                self.swap_chain_desc = Some(desc.clone());
+            }
+            _ => {}
+        }
+        let device = self.device;
+        gfx_select!(device => self.replay(action));
+        true
+    }
+
+    fn replay<B: wgc::hub::GfxBackend>(&mut self, action: trace::Action) {
+        let action = match self.surface {
+            Surface::Fake(ref mut surface) => surface.process::<B>(
+                &self.global,
+                self.device,
+                action,
+                &mut self.comb_manager,
+                &mut self.spare_ids,
+            ),
+            Surface::Window(surface) => match action {
+                trace::Action::CreateSwapChain { id, desc } => {
+                    log::info!("Initializing the swapchain");
+                    assert_eq!(id.to_surface_id(), surface);
+                    self.global
+                        .device_create_swap_chain::<B>(self.device, surface, &desc)
+                        .unwrap();
+                    None
+                }
+                trace::Action::PresentSwapChain(id) => {
+                    self.global.swap_chain_present::<B>(id);
+                    None
+                }
+                trace::Action::DiscardSwapChainFrame(id) => {
+                    self.global.swap_chain_discard_frame::<B>(id);
+                    None
+                }
+                other => Some(other),
+            },
+        };
+        if let Some(action) = action {
+            self.global
+                .process::<B>(self.device, action, &self.dir, &mut self.comb_manager);
+        }
+    }
+
+    fn wait_idle(&self) {
+        let (global, device) = (&self.global, self.device);
+        gfx_select!(device => global.queue_flush_pending_writes(device)).unwrap();
+        gfx_select!(device => global.device_poll(device, true));
+    }
+}
+
+impl Drop for Replayer {
+    fn drop(&mut self) {
+        let (global, device) = (&self.global, self.device);
+        gfx_select!(device => global.device_poll(device, true));
+    }
+}
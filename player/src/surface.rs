@@ -0,0 +1,264 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*! Headless stand-in for the window surface.
+ *
+ * Swap chain frames are replaced by ordinary textures, and presenting
+ * a frame only writes it into a PNG file, if a dump directory is given.
+!*/
+
+use crate::{Global, SpareIds};
+use wgc::device::trace;
+
+use std::{fs::File, io::BufWriter, path::PathBuf, ptr};
+
+struct FakeSwapChain {
+    desc: wgt::SwapChainDescriptor,
+    texture: wgc::id::TextureId,
+    /// Buffer to read the presented frames back into, if they are dumped.
+    readback: Option<wgc::id::BufferId>,
+    acquired_view: Option<wgc::id::TextureViewId>,
+}
+
+pub(crate) struct FakeSurface {
+    swap_chain: Option<FakeSwapChain>,
+    frame_count: usize,
+    dump_dir: Option<PathBuf>,
+}
+
+pub(crate) unsafe extern "C" fn copy_mapped_data(
+    status: wgc::resource::BufferMapAsyncStatus,
+    data: *const u8,
+    userdata: *mut u8,
+) {
+    let output = &mut *(userdata as *mut Vec<u8>);
+    match status {
+        wgc::resource::BufferMapAsyncStatus::Success => {
+            ptr::copy_nonoverlapping(data, output.as_mut_ptr(), output.len());
+        }
+        _ => log::error!("Unable to map the frame for reading: {:?}", status),
+    }
+}
+
+fn bytes_per_row(desc: &wgt::SwapChainDescriptor) -> u32 {
+    let alignment = wgt::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (desc.width * 4 + alignment - 1) / alignment * alignment
+}
+
+impl FakeSurface {
+    pub(crate) fn new(dump_dir: Option<PathBuf>) -> Self {
+        FakeSurface {
+            swap_chain: None,
+            frame_count: 0,
+            dump_dir,
+        }
+    }
+
+    /// Handle the swap chain actions, returning any other action back.
+    pub(crate) fn process<B: wgc::hub::GfxBackend>(
+        &mut self,
+        global: &Global,
+        device: wgc::id::DeviceId,
+        action: trace::Action,
+        comb_manager: &mut wgc::hub::IdentityManager,
+        spare_ids: &mut SpareIds,
+    ) -> Option<trace::Action> {
+        use wgc::device::trace::Action as A;
+        match action {
+            A::CreateSwapChain { id: _, desc } => {
+                log::info!(
+                    "Initializing the fake swapchain of {}x{}",
+                    desc.width,
+                    desc.height
+                );
+                if let Some(sc) = self.swap_chain.take() {
+                    if let Some(buffer) = sc.readback {
+                        global.buffer_destroy::<B>(buffer);
+                    }
+                    global.texture_destroy::<B>(sc.texture);
+                }
+                let texture = spare_ids.alloc(device.backend());
+                global.device_maintain_ids::<B>(device);
+                global
+                    .device_create_texture::<B>(
+                        device,
+                        &wgt::TextureDescriptor {
+                            label: ptr::null(),
+                            size: wgt::Extent3d {
+                                width: desc.width,
+                                height: desc.height,
+                                depth: 1,
+                            },
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: wgt::TextureDimension::D2,
+                            format: desc.format,
+                            usage: desc.usage | wgt::TextureUsage::COPY_SRC,
+                            transient: false,
+                        },
+                        texture,
+                    )
+                    .unwrap();
+                let readback = match (&self.dump_dir, desc.format) {
+                    (None, _) => None,
+                    (Some(_), wgt::TextureFormat::Rgba8Unorm)
+                    | (Some(_), wgt::TextureFormat::Rgba8UnormSrgb)
+                    | (Some(_), wgt::TextureFormat::Bgra8Unorm)
+                    | (Some(_), wgt::TextureFormat::Bgra8UnormSrgb) => {
+                        let buffer = spare_ids.alloc(device.backend());
+                        global
+                            .device_create_buffer::<B>(
+                                device,
+                                &wgt::BufferDescriptor {
+                                    label: ptr::null(),
+                                    size: bytes_per_row(&desc) as wgt::BufferAddress
+                                        * desc.height as wgt::BufferAddress,
+                                    usage: wgt::BufferUsage::MAP_READ | wgt::BufferUsage::COPY_DST,
+                                    memory_hint: wgt::MemoryHint::Download,
+                                    transient: false,
+                                },
+                                buffer,
+                            )
+                            .unwrap();
+                        Some(buffer)
+                    }
+                    (Some(_), format) => {
+                        log::warn!("Unable to dump frames of format {:?}", format);
+                        None
+                    }
+                };
+                self.swap_chain = Some(FakeSwapChain {
+                    desc,
+                    texture,
+                    readback,
+                    acquired_view: None,
+                });
+            }
+            A::GetSwapChainTexture { id, parent_id: _ } => {
+                let sc = self.swap_chain.as_mut().expect("Swap chain is not created");
+                assert!(
+                    sc.acquired_view.is_none(),
+                    "Swap chain frame is already acquired"
+                );
+                global.device_maintain_ids::<B>(device);
+                global
+                    .texture_create_view::<B>(sc.texture, None, id)
+                    .unwrap();
+                sc.acquired_view = Some(id);
+            }
+            A::PresentSwapChain(_) => {
+                self.frame_count += 1;
+                log::debug!("Presenting frame {}", self.frame_count);
+                self.dump_frame::<B>(global, device, comb_manager);
+                self.release_frame::<B>(global, device);
+            }
+            A::DiscardSwapChainFrame(_) => {
+                log::debug!("Discarding frame {}", self.frame_count + 1);
+                self.release_frame::<B>(global, device);
+            }
+            other => return Some(other),
+        }
+        None
+    }
+
+    /// Destroy the view of the acquired frame, making sure the view ID
+    /// is free by the time the trace acquires the next frame.
+    fn release_frame<B: wgc::hub::GfxBackend>(
+        &mut self,
+        global: &Global,
+        device: wgc::id::DeviceId,
+    ) {
+        let view = self
+            .swap_chain
+            .as_mut()
+            .and_then(|sc| sc.acquired_view.take())
+            .expect("Swap chain frame is not acquired");
+        global.texture_view_destroy::<B>(view);
+        global.device_poll::<B>(device, true);
+    }
+
+    fn dump_frame<B: wgc::hub::GfxBackend>(
+        &self,
+        global: &Global,
+        device: wgc::id::DeviceId,
+        comb_manager: &mut wgc::hub::IdentityManager,
+    ) {
+        let (sc, dir) = match (&self.swap_chain, &self.dump_dir) {
+            (Some(sc), Some(dir)) => (sc, dir),
+            _ => return,
+        };
+        let buffer = match sc.readback {
+            Some(buffer) => buffer,
+            None => return,
+        };
+        let (width, height) = (sc.desc.width, sc.desc.height);
+
+        let encoder = global
+            .device_create_command_encoder::<B>(
+                device,
+                &wgt::CommandEncoderDescriptor { label: ptr::null() },
+                comb_manager.alloc(device.backend()),
+            )
+            .unwrap();
+        let layout = global
+            .command_encoder_copy_texture_to_buffer_with_layout::<B>(
+                encoder,
+                &wgc::command::TextureCopyView {
+                    texture: sc.texture,
+                    mip_level: 0,
+                    array_layer: 0,
+                    origin: wgt::Origin3d::ZERO,
+                },
+                buffer,
+                0,
+                wgt::Extent3d {
+                    width,
+                    height,
+                    depth: 1,
+                },
+            )
+            .unwrap();
+        let comb = global
+            .command_encoder_finish::<B>(encoder, &wgt::CommandBufferDescriptor { todo: 0 })
+            .unwrap();
+        global.queue_submit::<B>(device, &[comb]).unwrap();
+
+        let mut data = vec![0u8; layout.required_size as usize];
+        global.buffer_map_async::<B>(
+            buffer,
+            0..layout.required_size,
+            wgc::resource::BufferMapOperation::Read {
+                callback: copy_mapped_data,
+                userdata: &mut data as *mut Vec<u8> as *mut u8,
+            },
+        );
+        global.device_poll::<B>(device, true);
+        global.buffer_unmap::<B>(buffer);
+
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in data.chunks(layout.bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..width as usize * 4]);
+        }
+        match sc.desc.format {
+            wgt::TextureFormat::Bgra8Unorm | wgt::TextureFormat::Bgra8UnormSrgb => {
+                for texel in pixels.chunks_mut(4) {
+                    texel.swap(0, 2);
+                }
+            }
+            _ => {}
+        }
+
+        let path = dir.join(format!("frame-{:04}.png", self.frame_count));
+        log::info!("Writing frame {} into {:?}", self.frame_count, path);
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(File::create(path).unwrap()), width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&pixels)
+            .unwrap();
+    }
+}
@@ -0,0 +1,36 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wgpu_core::bench::BindingScope;
+
+const GROUP_COUNTS: &[u32] = &[1, 4, 8];
+const DRAWS: usize = 1_000;
+
+fn draw(c: &mut Criterion) {
+    let mut group = c.benchmark_group("draw");
+    for &count in GROUP_COUNTS {
+        let mut scope = BindingScope::new(count);
+        group.bench_function(BenchmarkId::new("unchanged", count), |b| {
+            b.iter(|| scope.draw_repeatedly(DRAWS))
+        });
+        // Changing a bind group before every draw forces the full check,
+        // which is what every draw used to pay for.
+        let mut scope = BindingScope::new(count);
+        group.bench_function(BenchmarkId::new("rebound", count), |b| {
+            b.iter(|| {
+                (0..DRAWS)
+                    .map(|_| {
+                        scope.rebind(count as usize - 1);
+                        scope.draw_repeatedly(1)
+                    })
+                    .sum::<usize>()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, draw);
+criterion_main!(benches);
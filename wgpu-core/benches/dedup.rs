@@ -0,0 +1,24 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wgpu_core::bench::SamplerDedup;
+
+const SAMPLERS: usize = 10_000;
+
+fn samplers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("samplers");
+    // Only the first creation misses the cache, the others return its handle.
+    group.bench_function(BenchmarkId::new("identical", SAMPLERS), |b| {
+        b.iter(|| SamplerDedup::new().create(SAMPLERS, true))
+    });
+    // Every creation misses the cache, which is the overhead without duplicates.
+    group.bench_function(BenchmarkId::new("distinct", SAMPLERS), |b| {
+        b.iter(|| SamplerDedup::new().create(SAMPLERS, false))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, samplers);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wgpu_core::bench::SubmissionLife;
+
+/// Number of command buffer groups submitted every frame.
+const GROUPS: usize = 5;
+const FRAMES: usize = 100;
+
+fn frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frames");
+    // One `queue_submit` per group, each tracked on its own.
+    let mut life = SubmissionLife::new();
+    group.bench_function(BenchmarkId::new("sequential", GROUPS), |b| {
+        b.iter(|| (0..FRAMES).map(|_| life.frame(GROUPS)).sum::<usize>())
+    });
+    // A single `queue_submit_batch` of all the groups, tracked once.
+    let mut life = SubmissionLife::new();
+    group.bench_function(BenchmarkId::new("batched", GROUPS), |b| {
+        b.iter(|| (0..FRAMES).map(|_| life.frame(1)).sum::<usize>())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, frames);
+criterion_main!(benches);
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use wgpu_core::bench::{BufferScope, TextureScope};
+
+const SIZES: &[usize] = &[1_000, 10_000, 100_000];
+
+fn merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    for &size in SIZES {
+        let scope = BufferScope::new(size);
+        group.bench_with_input(BenchmarkId::new("empty", size), &scope, |b, scope| {
+            b.iter(|| scope.merge_into_empty())
+        });
+        let mut target = BufferScope::new(size);
+        group.bench_with_input(BenchmarkId::new("overlapping", size), &scope, |b, scope| {
+            b.iter(|| scope.merge_into(&mut target))
+        });
+    }
+    group.finish();
+}
+
+fn reuse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reuse");
+    for &size in SIZES {
+        let mut scope = BufferScope::new(size);
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| scope.use_repeatedly(4))
+        });
+    }
+    group.finish();
+}
+
+/// Texture tracking with a single state per texture, which is the common case
+/// that per-subresource tracking must keep cheap, and with a state per layer.
+fn textures(c: &mut Criterion) {
+    const LAYERS: u32 = 6;
+    let mut group = c.benchmark_group("textures");
+    for &size in SIZES {
+        for &(name, split) in &[("single", false), ("split", true)] {
+            let scope = TextureScope::new(size, LAYERS, split);
+            group.bench_with_input(
+                BenchmarkId::new(format!("merge-{}", name), size),
+                &scope,
+                |b, scope| b.iter(|| scope.merge_into_empty()),
+            );
+            let mut scope = TextureScope::new(size, LAYERS, split);
+            group.bench_function(BenchmarkId::new(format!("reuse-{}", name), size), |b| {
+                b.iter(|| scope.use_repeatedly(4))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, merge, reuse, textures);
+criterion_main!(benches);
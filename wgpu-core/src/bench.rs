@@ -0,0 +1,258 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! Entry points for `benches/`, which can't reach the tracking internals.
+
+use crate::{
+    backend::Empty,
+    command::Binder,
+    device::{DedupCache, LifetimeTracker, SamplerKey, SuspectedResources},
+    id::{BindGroupId, BindGroupLayoutId, BufferId, SamplerId, TextureId, TypedId},
+    resource::{BufferUse, TextureUse},
+    track::{BufferState, ResourceTracker, TextureState},
+    LifeGuard, RefCount, Stored,
+};
+
+use std::iter;
+
+const BACKEND: wgt::Backend = wgt::Backend::Empty;
+
+/// A usage scope of buffers, all used as uniforms.
+pub struct BufferScope {
+    tracker: ResourceTracker<BufferState>,
+    buffers: Vec<(BufferId, RefCount)>,
+}
+
+impl BufferScope {
+    pub fn new(count: usize) -> Self {
+        let mut tracker = ResourceTracker::new(BACKEND);
+        let buffers = (0..count)
+            .map(|index| {
+                let id = BufferId::zip(index as u32, 1, BACKEND);
+                let ref_count = LifeGuard::new().add_ref();
+                tracker
+                    .init(
+                        id,
+                        ref_count.clone(),
+                        BufferState::with_usage(BufferUse::UNIFORM),
+                    )
+                    .unwrap();
+                (id, ref_count)
+            })
+            .collect();
+        BufferScope { tracker, buffers }
+    }
+
+    /// Merge into a new scope, returning the number of tracked buffers.
+    pub fn merge_into_empty(&self) -> usize {
+        let mut scope = ResourceTracker::new(BACKEND);
+        scope.merge_extend(&self.tracker).unwrap();
+        scope.used().count()
+    }
+
+    /// Merge into a scope that already tracks the same buffers.
+    pub fn merge_into(&self, other: &mut Self) {
+        other.tracker.merge_extend(&self.tracker).unwrap();
+    }
+
+    /// Extend the usage of each buffer `repeat` times in a row.
+    pub fn use_repeatedly(&mut self, repeat: usize) {
+        for &(id, ref ref_count) in self.buffers.iter() {
+            for _ in 0..repeat {
+                self.tracker
+                    .change_extend(id, ref_count, (), BufferUse::UNIFORM)
+                    .unwrap();
+            }
+        }
+    }
+}
+
+/// A usage scope of array textures.
+pub struct TextureScope {
+    tracker: ResourceTracker<TextureState>,
+    textures: Vec<(TextureId, RefCount)>,
+}
+
+impl TextureScope {
+    /// Track `count` textures of `layers` array layers. Unless `split`, all
+    /// the layers are sampled, which keeps a single state per texture.
+    /// Otherwise, every other layer is used as a read-only storage texture,
+    /// so that each layer has its own state.
+    pub fn new(count: usize, layers: hal::image::Layer, split: bool) -> Self {
+        let mut tracker = ResourceTracker::new(BACKEND);
+        let textures = (0..count)
+            .map(|index| {
+                let id = TextureId::zip(index as u32, 1, BACKEND);
+                let ref_count = LifeGuard::new().add_ref();
+                #[allow(clippy::range_plus_one)]
+                for layer in 0..layers {
+                    let usage = if split && layer % 2 == 1 {
+                        TextureUse::STORAGE_LOAD
+                    } else {
+                        TextureUse::SAMPLED
+                    };
+                    tracker
+                        .change_extend(id, &ref_count, layer_range(layer..layer + 1), usage)
+                        .unwrap();
+                }
+                (id, ref_count)
+            })
+            .collect();
+        TextureScope { tracker, textures }
+    }
+
+    /// Merge into a new scope, returning the number of tracked textures.
+    pub fn merge_into_empty(&self) -> usize {
+        let mut scope = ResourceTracker::new(BACKEND);
+        scope.merge_extend(&self.tracker).unwrap();
+        scope.used().count()
+    }
+
+    /// Sample the first layer of each texture `repeat` times in a row,
+    /// like binding a view of it for every draw.
+    pub fn use_repeatedly(&mut self, repeat: usize) {
+        for &(id, ref ref_count) in self.textures.iter() {
+            for _ in 0..repeat {
+                self.tracker
+                    .change_extend(id, ref_count, layer_range(0..1), TextureUse::SAMPLED)
+                    .unwrap();
+            }
+        }
+    }
+}
+
+fn layer_range(layers: std::ops::Range<hal::image::Layer>) -> hal::image::SubresourceRange {
+    hal::image::SubresourceRange {
+        aspects: hal::format::Aspects::COLOR,
+        levels: 0..1,
+        layers,
+    }
+}
+
+/// Bind groups set on a pass, all compatible with the pipeline layout.
+pub struct BindingScope {
+    binder: Binder,
+    life_guard: LifeGuard,
+}
+
+impl BindingScope {
+    pub fn new(count: u32) -> Self {
+        let mut scope = BindingScope {
+            binder: Binder::new(count),
+            life_guard: LifeGuard::new(),
+        };
+        for index in 0..count as usize {
+            scope.rebind(index);
+        }
+        scope
+    }
+
+    /// Set the bind group at `index` again, as if it changed.
+    pub fn rebind(&mut self, index: usize) {
+        let group_id = Stored {
+            value: BindGroupId::zip(index as u32, 1, BACKEND),
+            ref_count: self.life_guard.add_ref(),
+        };
+        let layout_id = BindGroupLayoutId::zip(index as u32, 1, BACKEND);
+        self.binder.provide_compatible(index, layout_id, group_id);
+    }
+
+    /// Validate the bind groups for `repeat` draws in a row, returning
+    /// the number of draws that passed.
+    pub fn draw_repeatedly(&mut self, repeat: usize) -> usize {
+        (0..repeat)
+            .filter(|_| self.binder.valid_count() == self.binder.entries.len())
+            .count()
+    }
+}
+
+/// Sampler creations going through the deduplication cache of a device.
+pub struct SamplerDedup {
+    cache: DedupCache<SamplerKey, SamplerId>,
+    created: u32,
+}
+
+impl SamplerDedup {
+    pub fn new() -> Self {
+        SamplerDedup {
+            cache: DedupCache::new(),
+            created: 0,
+        }
+    }
+
+    /// Create `count` samplers, with identical descriptors or not, returning
+    /// the number of samplers that the device would have created.
+    pub fn create(&mut self, count: usize, identical: bool) -> usize {
+        for index in 0..count {
+            let desc = wgt::SamplerDescriptor {
+                label: (),
+                address_mode_u: wgt::AddressMode::Repeat,
+                address_mode_v: wgt::AddressMode::Repeat,
+                address_mode_w: wgt::AddressMode::ClampToEdge,
+                mag_filter: wgt::FilterMode::Linear,
+                min_filter: wgt::FilterMode::Linear,
+                mipmap_filter: wgt::FilterMode::Nearest,
+                lod_min_clamp: 0.0,
+                lod_max_clamp: if identical { 32.0 } else { index as f32 },
+                compare: wgt::CompareFunction::Undefined,
+            };
+            let key = SamplerKey::new(&desc);
+            if self.cache.find(&key).is_none() {
+                let id = SamplerId::zip(self.created, 1, BACKEND);
+                self.created += 1;
+                self.cache.insert(key, id);
+            }
+        }
+        self.cache.len()
+    }
+}
+
+impl Default for SamplerDedup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Submissions going through the life tracker of a device. The native
+/// submissions and fences are left out, since the empty backend has none.
+pub struct SubmissionLife {
+    tracker: LifetimeTracker<Empty>,
+    suspected: SuspectedResources,
+    last_index: usize,
+}
+
+impl SubmissionLife {
+    pub fn new() -> Self {
+        SubmissionLife {
+            tracker: LifetimeTracker::new(),
+            suspected: SuspectedResources::default(),
+            last_index: 0,
+        }
+    }
+
+    /// Track a frame of `submissions` submissions, then retire them all as done,
+    /// returning the lowest submission index that was active.
+    pub fn frame(&mut self, submissions: usize) -> usize {
+        for _ in 0..submissions {
+            self.last_index += 1;
+            self.tracker.track_submission(
+                self.last_index,
+                (),
+                &self.suspected,
+                iter::empty(),
+                iter::empty(),
+                iter::empty(),
+            );
+        }
+        let lowest_active_index = self.tracker.lowest_active_submission();
+        self.tracker.retire(submissions);
+        lowest_active_index
+    }
+}
+
+impl Default for SubmissionLife {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,20 +3,21 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    id::{BindGroupLayoutId, BufferId, DeviceId, SamplerId, TextureViewId},
-    track::{TrackerSet, DUMMY_SELECTOR},
+    device::{InvalidIdError, WrongDeviceError},
+    id::{BindGroupId, BindGroupLayoutId, BufferId, DeviceId, SamplerId, TextureViewId},
+    track::{ScopeStamp, TrackerSet, DUMMY_SELECTOR},
     FastHashMap, LifeGuard, RefCount, Stored,
 };
 
 use arrayvec::ArrayVec;
 use gfx_descriptor::{DescriptorCounts, DescriptorSet};
-use wgt::{BufferAddress, TextureComponentType};
+use wgt::{BufferAddress, BufferSize, DynamicOffset, TextureComponentType};
 
 #[cfg(feature = "replay")]
 use serde::Deserialize;
 #[cfg(feature = "trace")]
 use serde::Serialize;
-use std::borrow::Borrow;
+use std::{borrow::Borrow, fmt};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
@@ -31,6 +32,8 @@ pub enum BindingType {
     SampledTexture = 5,
     ReadonlyStorageTexture = 6,
     WriteonlyStorageTexture = 7,
+    /// Sampler that doesn't filter, which can be used with any sampled texture.
+    NonFilteringSampler = 8,
 }
 
 #[repr(C)]
@@ -44,6 +47,7 @@ pub struct BindGroupLayoutEntry {
     pub multisampled: bool,
     pub has_dynamic_offset: bool,
     pub view_dimension: wgt::TextureViewDimension,
+    /// Expected component type of the views bound to a `SampledTexture`.
     pub texture_component_type: TextureComponentType,
     pub storage_texture_format: wgt::TextureFormat,
 }
@@ -63,7 +67,8 @@ pub struct BindGroupLayout<B: hal::Backend> {
     pub(crate) life_guard: LifeGuard,
     pub(crate) entries: FastHashMap<u32, BindGroupLayoutEntry>,
     pub(crate) desc_counts: DescriptorCounts,
-    pub(crate) dynamic_count: usize,
+    #[cfg(feature = "trace")]
+    pub(crate) label: String,
 }
 
 #[repr(C)]
@@ -81,6 +86,134 @@ pub struct PipelineLayout<B: hal::Backend> {
     pub(crate) bind_group_layout_ids: ArrayVec<[Stored<BindGroupLayoutId>; wgt::MAX_BIND_GROUPS]>,
 }
 
+/// A device limit that the bind group layouts of a pipeline layout go over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitExceeded {
+    pub limit: &'static str,
+    /// Stage the bindings are counted for, or `None` for the per-group limits.
+    pub stage: Option<wgt::ShaderStage>,
+    pub count: u32,
+    pub allowed: u32,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is {}, but the layout needs {}",
+            self.limit, self.allowed, self.count
+        )?;
+        if let Some(stage) = self.stage {
+            write!(f, " for the {:?} stage", stage)?;
+        }
+        write!(f, ", exceeding it by {}", self.count - self.allowed)
+    }
+}
+
+/// Check the entries of all the bind group layouts of a pipeline layout
+/// against the per-stage descriptor limits and the binding number limit,
+/// returning every limit that is exceeded.
+pub(crate) fn check_pipeline_layout_limits<'a>(
+    entries: impl Iterator<Item = &'a BindGroupLayoutEntry> + Clone,
+    limits: &wgt::Limits,
+) -> Vec<LimitExceeded> {
+    let mut exceeded = Vec::new();
+    let binding_count = entries
+        .clone()
+        .map(|entry| entry.binding + 1)
+        .max()
+        .unwrap_or(0);
+    if binding_count > limits.max_bindings_per_bind_group {
+        exceeded.push(LimitExceeded {
+            limit: "max_bindings_per_bind_group",
+            stage: None,
+            count: binding_count,
+            allowed: limits.max_bindings_per_bind_group,
+        });
+    }
+
+    let stage_limits = [
+        (
+            "max_uniform_buffers_per_shader_stage",
+            limits.max_uniform_buffers_per_shader_stage,
+        ),
+        (
+            "max_storage_buffers_per_shader_stage",
+            limits.max_storage_buffers_per_shader_stage,
+        ),
+        (
+            "max_samplers_per_shader_stage",
+            limits.max_samplers_per_shader_stage,
+        ),
+        (
+            "max_sampled_textures_per_shader_stage",
+            limits.max_sampled_textures_per_shader_stage,
+        ),
+        (
+            "max_storage_textures_per_shader_stage",
+            limits.max_storage_textures_per_shader_stage,
+        ),
+    ];
+    for &stage in &[
+        wgt::ShaderStage::VERTEX,
+        wgt::ShaderStage::FRAGMENT,
+        wgt::ShaderStage::COMPUTE,
+    ] {
+        let mut counts = [0u32; 5];
+        for entry in entries
+            .clone()
+            .filter(|entry| entry.visibility.contains(stage))
+        {
+            let index = match entry.ty {
+                BindingType::UniformBuffer => 0,
+                BindingType::StorageBuffer | BindingType::ReadonlyStorageBuffer => 1,
+                BindingType::Sampler
+                | BindingType::ComparisonSampler
+                | BindingType::NonFilteringSampler => 2,
+                BindingType::SampledTexture => 3,
+                BindingType::ReadonlyStorageTexture | BindingType::WriteonlyStorageTexture => 4,
+            };
+            counts[index] += 1;
+        }
+        for (&(limit, allowed), &count) in stage_limits.iter().zip(counts.iter()) {
+            if count > allowed {
+                exceeded.push(LimitExceeded {
+                    limit,
+                    stage: Some(stage),
+                    count,
+                    allowed,
+                });
+            }
+        }
+    }
+    exceeded
+}
+
+/// Find the texture and the sampler of a bind group layout that are necessarily
+/// sampled together: the only sampled texture and the only sampler, when that
+/// sampler is allowed to filter. Returns their bindings.
+pub(crate) fn sampled_together<'a>(
+    entries: impl Iterator<Item = &'a BindGroupLayoutEntry>,
+) -> Option<(u32, u32)> {
+    let mut textures = Vec::new();
+    let mut samplers = Vec::new();
+    for entry in entries {
+        match entry.ty {
+            BindingType::SampledTexture => textures.push(entry),
+            BindingType::Sampler
+            | BindingType::ComparisonSampler
+            | BindingType::NonFilteringSampler => samplers.push(entry),
+            _ => {}
+        }
+    }
+    match (textures.as_slice(), samplers.as_slice()) {
+        (&[texture], &[sampler]) if sampler.ty == BindingType::Sampler => {
+            Some((texture.binding, sampler.binding))
+        }
+        _ => None,
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 #[cfg_attr(feature = "trace", derive(Serialize))]
@@ -88,7 +221,9 @@ pub struct PipelineLayout<B: hal::Backend> {
 pub struct BufferBinding {
     pub buffer: BufferId,
     pub offset: BufferAddress,
-    pub size: BufferAddress,
+    /// Size of the bound range. `None` binds the rest of the buffer after `offset`,
+    /// shrunk by the dynamic offset given at bind time, if any.
+    pub size: Option<BufferSize>,
 }
 
 #[repr(C)]
@@ -119,14 +254,219 @@ pub struct BindGroupDescriptor {
     pub entries_length: usize,
 }
 
+/// Error of creating a bind group.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BindGroupError {
+    /// The device, the layout or one of the bound resources is invalid.
+    InvalidId(InvalidIdError),
+    /// The view bound to a sampled texture can't be sampled as the component type
+    /// expected by the layout entry.
+    IncompatibleTextureView {
+        binding: u32,
+        expected: TextureComponentType,
+        actual: TextureComponentType,
+    },
+    /// The sample count of the bound view doesn't match the multisampled flag
+    /// of the layout entry.
+    MultisampledMismatch {
+        binding: u32,
+        multisampled: bool,
+        samples: u8,
+    },
+    /// A filtering sampler is bound where a non-filtering sampler is expected.
+    FilteringSampler { binding: u32 },
+    /// The bind group has a single sampler and a single sampled texture,
+    /// so they are sampled together, but the sampler filters and the texture
+    /// data can't be filtered.
+    FilteringSamplerWithUnfilterableTexture { texture: u32, sampler: u32 },
+    /// The layout or one of the bound resources belongs to another device.
+    WrongDevice(WrongDeviceError),
+}
+
+impl From<InvalidIdError> for BindGroupError {
+    fn from(error: InvalidIdError) -> Self {
+        BindGroupError::InvalidId(error)
+    }
+}
+
+impl From<WrongDeviceError> for BindGroupError {
+    fn from(error: WrongDeviceError) -> Self {
+        BindGroupError::WrongDevice(error)
+    }
+}
+
 #[derive(Debug)]
 pub struct BindGroup<B: hal::Backend> {
     pub(crate) raw: DescriptorSet<B>,
     pub(crate) device_id: Stored<DeviceId>,
     pub(crate) layout_id: BindGroupLayoutId,
     pub(crate) life_guard: LifeGuard,
+    /// Handles returned by deduplication, each destroyed separately.
+    pub(crate) extra_handles: usize,
+    /// Key of the bind group in the deduplication cache of the device.
+    pub(crate) cache_key: Option<crate::device::BindGroupKey>,
     pub(crate) used: TrackerSet,
-    pub(crate) dynamic_count: usize,
+    /// Last render pass scope this group was merged into.
+    pub(crate) scope_stamp: ScopeStamp,
+    /// Dynamic bindings, in binding order.
+    pub(crate) dynamic_bindings: Vec<DynamicBinding>,
+    /// Copies of `raw` with the ranges of unsized dynamic bindings re-derived
+    /// for specific dynamic offsets, since the range is baked into descriptors.
+    pub(crate) derived_sets: FastHashMap<Vec<DynamicOffset>, DescriptorSet<B>>,
+    /// First member destroyed by the user while the group is alive.
+    pub(crate) destroyed_member: Option<BindGroupMember>,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_desc: crate::device::trace::BindGroupDescriptor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BindGroupMember {
+    Buffer(BufferId),
+    TextureView(TextureViewId),
+}
+
+/// Bind groups that each buffer and texture view is bound in.
+///
+/// Entries are added when a bind group is created and removed when it's
+/// destroyed, so destroying a member only has to look up its own entry.
+#[derive(Debug, Default)]
+pub(crate) struct BindGroupMembers {
+    buffers: FastHashMap<BufferId, Vec<BindGroupId>>,
+    texture_views: FastHashMap<TextureViewId, Vec<BindGroupId>>,
+}
+
+fn unlink<K: Copy + Eq + std::hash::Hash>(
+    map: &mut FastHashMap<K, Vec<BindGroupId>>,
+    key: K,
+    bind_group_id: BindGroupId,
+) {
+    if let Some(groups) = map.get_mut(&key) {
+        groups.retain(|&id| id != bind_group_id);
+        if groups.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
+
+impl BindGroupMembers {
+    pub fn insert(
+        &mut self,
+        bind_group_id: BindGroupId,
+        buffers: impl Iterator<Item = BufferId>,
+        texture_views: impl Iterator<Item = TextureViewId>,
+    ) {
+        for id in buffers {
+            self.buffers.entry(id).or_default().push(bind_group_id);
+        }
+        for id in texture_views {
+            self.texture_views
+                .entry(id)
+                .or_default()
+                .push(bind_group_id);
+        }
+    }
+
+    pub fn remove(
+        &mut self,
+        bind_group_id: BindGroupId,
+        buffers: impl Iterator<Item = BufferId>,
+        texture_views: impl Iterator<Item = TextureViewId>,
+    ) {
+        for id in buffers {
+            unlink(&mut self.buffers, id, bind_group_id);
+        }
+        for id in texture_views {
+            unlink(&mut self.texture_views, id, bind_group_id);
+        }
+    }
+
+    /// Forget the bind groups of a destroyed member, returning them.
+    pub fn take(&mut self, member: BindGroupMember) -> Vec<BindGroupId> {
+        match member {
+            BindGroupMember::Buffer(id) => self.buffers.remove(&id),
+            BindGroupMember::TextureView(id) => self.texture_views.remove(&id),
+        }
+        .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DynamicBinding {
+    pub binding: u32,
+    pub buffer_id: BufferId,
+    pub buffer_size: BufferAddress,
+    pub offset: BufferAddress,
+    pub size: Option<BufferSize>,
+    pub alignment: BufferAddress,
+}
+
+impl DynamicBinding {
+    /// Size of the range seen by shaders with the given dynamic offset,
+    /// or `None` if the range doesn't fit into the buffer.
+    pub(crate) fn bound_size(&self, dynamic_offset: DynamicOffset) -> Option<BufferAddress> {
+        let start = self.offset + dynamic_offset as BufferAddress;
+        match self.size {
+            Some(size) if start + size.get() <= self.buffer_size => Some(size.get()),
+            Some(_) => None,
+            None if start < self.buffer_size => Some(self.buffer_size - start),
+            None => None,
+        }
+    }
+}
+
+impl<B: hal::Backend> BindGroup<B> {
+    pub(crate) fn validate_dynamic_offsets(&self, offsets: &[DynamicOffset]) {
+        assert_eq!(
+            self.dynamic_bindings.len(),
+            offsets.len(),
+            "Bind group expects {} dynamic offsets, but {} were given",
+            self.dynamic_bindings.len(),
+            offsets.len()
+        );
+        for (&offset, binding) in offsets.iter().zip(&self.dynamic_bindings) {
+            assert_eq!(
+                offset as BufferAddress % binding.alignment,
+                0,
+                "Misaligned dynamic buffer offset: {} does not align with {}",
+                offset,
+                binding.alignment
+            );
+            assert!(
+                binding.bound_size(offset).is_some(),
+                "Dynamic offset {} moves binding {} out of the bounds of buffer {:?} of size {}",
+                offset,
+                binding.binding,
+                binding.buffer_id,
+                binding.buffer_size
+            );
+        }
+    }
+
+    pub(crate) fn validate_members(&self, id: BindGroupId) {
+        if let Some(member) = self.destroyed_member {
+            panic!(
+                "Bind group {:?} is used after its member {:?} was destroyed",
+                id, member
+            );
+        }
+    }
+
+    /// Returns true if binding with these offsets requires a derived descriptor set.
+    pub(crate) fn needs_derived_set(&self, offsets: &[DynamicOffset]) -> bool {
+        offsets.len() == self.dynamic_bindings.len()
+            && offsets
+                .iter()
+                .zip(&self.dynamic_bindings)
+                .any(|(&offset, binding)| binding.size.is_none() && offset != 0)
+    }
+
+    /// The descriptor set to bind with the given dynamic offsets.
+    pub(crate) fn raw_for_offsets(&self, offsets: &[DynamicOffset]) -> &B::DescriptorSet {
+        match self.derived_sets.get(offsets) {
+            Some(set) => set.raw(),
+            None => self.raw.raw(),
+        }
+    }
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for BindGroup<B> {
@@ -140,3 +480,154 @@ impl<B: hal::Backend> Borrow<()> for BindGroup<B> {
         &DUMMY_SELECTOR
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::id::Id;
+
+    fn dynamic_binding(offset: BufferAddress, size: Option<BufferAddress>) -> DynamicBinding {
+        DynamicBinding {
+            binding: 0,
+            buffer_id: Id::default(),
+            buffer_size: 1024,
+            offset,
+            size: size.and_then(BufferSize::new),
+            alignment: 256,
+        }
+    }
+
+    #[test]
+    fn unsized_shrinks_by_dynamic_offset() {
+        let binding = dynamic_binding(256, None);
+        assert_eq!(binding.bound_size(0), Some(768));
+        assert_eq!(binding.bound_size(256), Some(512));
+        assert_eq!(binding.bound_size(512), Some(256));
+        assert_eq!(binding.bound_size(768), None);
+    }
+
+    #[test]
+    fn sized_must_fit() {
+        let binding = dynamic_binding(256, Some(256));
+        assert_eq!(binding.bound_size(0), Some(256));
+        assert_eq!(binding.bound_size(512), Some(256));
+        assert_eq!(binding.bound_size(768), None);
+    }
+
+    #[test]
+    fn pipeline_layout_limits() {
+        let entry = |binding, visibility, ty| BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty,
+            multisampled: false,
+            has_dynamic_offset: false,
+            view_dimension: wgt::TextureViewDimension::D2,
+            texture_component_type: TextureComponentType::Float,
+            storage_texture_format: wgt::TextureFormat::Rgba8Unorm,
+        };
+        let limits = wgt::Limits {
+            max_sampled_textures_per_shader_stage: 2,
+            max_bindings_per_bind_group: 8,
+            ..wgt::Limits::default()
+        };
+        let mut entries = vec![
+            entry(0, wgt::ShaderStage::FRAGMENT, BindingType::SampledTexture),
+            entry(1, wgt::ShaderStage::FRAGMENT, BindingType::SampledTexture),
+            entry(2, wgt::ShaderStage::VERTEX, BindingType::SampledTexture),
+        ];
+        assert!(check_pipeline_layout_limits(entries.iter(), &limits).is_empty());
+
+        entries.push(entry(
+            9,
+            wgt::ShaderStage::VERTEX | wgt::ShaderStage::FRAGMENT,
+            BindingType::SampledTexture,
+        ));
+        assert_eq!(
+            check_pipeline_layout_limits(entries.iter(), &limits),
+            [
+                LimitExceeded {
+                    limit: "max_bindings_per_bind_group",
+                    stage: None,
+                    count: 10,
+                    allowed: 8,
+                },
+                LimitExceeded {
+                    limit: "max_sampled_textures_per_shader_stage",
+                    stage: Some(wgt::ShaderStage::FRAGMENT),
+                    count: 3,
+                    allowed: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn texture_sampled_with_sampler() {
+        let entry = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: wgt::ShaderStage::FRAGMENT,
+            ty,
+            multisampled: false,
+            has_dynamic_offset: false,
+            view_dimension: wgt::TextureViewDimension::D2,
+            texture_component_type: TextureComponentType::UnfilterableFloat,
+            storage_texture_format: wgt::TextureFormat::Rgba8Unorm,
+        };
+        let mut entries = vec![
+            entry(0, BindingType::UniformBuffer),
+            entry(1, BindingType::SampledTexture),
+            entry(2, BindingType::Sampler),
+        ];
+        assert_eq!(sampled_together(entries.iter()), Some((1, 2)));
+
+        // Samplers that can't filter don't need to be checked.
+        entries[2].ty = BindingType::NonFilteringSampler;
+        assert_eq!(sampled_together(entries.iter()), None);
+        entries[2].ty = BindingType::ComparisonSampler;
+        assert_eq!(sampled_together(entries.iter()), None);
+
+        // With several textures or samplers, the pairs are only known to the shaders.
+        entries[2].ty = BindingType::Sampler;
+        entries.push(entry(3, BindingType::SampledTexture));
+        assert_eq!(sampled_together(entries.iter()), None);
+        entries[3].ty = BindingType::ComparisonSampler;
+        assert_eq!(sampled_together(entries.iter()), None);
+    }
+
+    #[test]
+    fn members_are_unlinked() {
+        use crate::id::TypedId;
+        let buffer = |index| BufferId::zip(index, 1, wgt::Backend::Empty);
+        let group = |index| BindGroupId::zip(index, 1, wgt::Backend::Empty);
+        let view = TextureViewId::zip(0, 1, wgt::Backend::Empty);
+        let mut members = BindGroupMembers::default();
+
+        members.insert(
+            group(0),
+            vec![buffer(0), buffer(1)].into_iter(),
+            None.into_iter(),
+        );
+        members.insert(
+            group(1),
+            vec![buffer(1)].into_iter(),
+            Some(view).into_iter(),
+        );
+        assert_eq!(
+            members.take(BindGroupMember::Buffer(buffer(1))),
+            vec![group(0), group(1)]
+        );
+        members.remove(
+            group(0),
+            vec![buffer(0), buffer(1)].into_iter(),
+            None.into_iter(),
+        );
+        members.remove(
+            group(1),
+            vec![buffer(1)].into_iter(),
+            Some(view).into_iter(),
+        );
+        assert!(members.buffers.is_empty());
+        assert!(members.texture_views.is_empty());
+    }
+}
@@ -2,7 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use super::CommandBuffer;
+use super::{CommandBuffer, CommandEncoderStatus};
 use crate::{
     hub::GfxBackend, id::DeviceId, track::TrackerSet, FastHashMap, PrivateFeatures, Stored,
     SubmissionIndex,
@@ -11,9 +11,27 @@ use crate::{
 use hal::{command::CommandBuffer as _, device::Device as _, pool::CommandPool as _};
 use parking_lot::Mutex;
 
-use std::thread;
+use std::{iter, thread};
 
 const GROW_AMOUNT: usize = 20;
+/// Maximum number of recycled command buffers kept around by a pool.
+/// Anything above that is freed back to the native pool.
+const MAX_AVAILABLE: usize = 64;
+/// Number of completed submissions after which an idle pool
+/// of a thread other than the internal one is destroyed.
+const POOL_IDLE_SUBMISSIONS: SubmissionIndex = 16;
+
+/// Check if a recycled command buffer should be kept for reuse,
+/// given the number of command buffers already available in the pool.
+fn is_kept_on_recycle(available: usize) -> bool {
+    available < MAX_AVAILABLE
+}
+
+/// Check if an idle pool, last used by submission `last_used`,
+/// has been left alone long enough to be destroyed.
+fn is_orphaned(last_used: SubmissionIndex, last_done_index: SubmissionIndex) -> bool {
+    last_used + POOL_IDLE_SUBMISSIONS <= last_done_index
+}
 
 #[derive(Debug)]
 struct CommandPool<B: hal::Backend> {
@@ -21,6 +39,8 @@ struct CommandPool<B: hal::Backend> {
     total: usize,
     available: Vec<B::CommandBuffer>,
     pending: Vec<(B::CommandBuffer, SubmissionIndex)>,
+    /// Index of the last submission that used command buffers from this pool.
+    last_used: SubmissionIndex,
 }
 
 impl<B: hal::Backend> CommandPool<B> {
@@ -39,12 +59,23 @@ impl<B: hal::Backend> CommandPool<B> {
     }
 
     fn recycle(&mut self, mut raw: B::CommandBuffer) {
+        if !is_kept_on_recycle(self.available.len()) {
+            self.total -= 1;
+            unsafe {
+                self.raw.free(iter::once(raw));
+            }
+            return;
+        }
         unsafe {
             raw.reset(false);
         }
         self.available.push(raw);
     }
 
+    fn is_idle(&self) -> bool {
+        self.total == self.available.len()
+    }
+
     fn allocate(&mut self) -> B::CommandBuffer {
         if self.available.is_empty() {
             self.total += GROW_AMOUNT;
@@ -63,6 +94,13 @@ impl<B: hal::Backend> CommandPool<B> {
 #[derive(Debug)]
 struct Inner<B: hal::Backend> {
     pools: FastHashMap<thread::ThreadId, CommandPool<B>>,
+    /// Device-wide pool of the command buffers that stitch the resource
+    /// transitions in front of each submitted command buffer.
+    transit: CommandPool<B>,
+    /// Number of transit command buffers allocated from the native pool.
+    transit_allocated: usize,
+    /// Number of transit command buffers reused after their submission completed.
+    transit_reused: usize,
 }
 
 #[derive(Debug)]
@@ -78,6 +116,7 @@ impl<B: GfxBackend> CommandAllocator<B> {
         device_id: Stored<DeviceId>,
         device: &B::Device,
         limits: wgt::Limits,
+        downlevel: wgt::DownlevelCapabilities,
         private_features: PrivateFeatures,
         #[cfg(feature = "trace")] enable_tracing: bool,
     ) -> CommandBuffer<B> {
@@ -100,18 +139,24 @@ impl<B: GfxBackend> CommandAllocator<B> {
                 total: 0,
                 available: Vec::new(),
                 pending: Vec::new(),
+                last_used: 0,
             })
             .allocate();
 
         CommandBuffer {
             raw: vec![init],
-            is_recording: true,
+            status: CommandEncoderStatus::Recording,
             recorded_thread_id: thread_id,
             device_id,
             trackers: TrackerSet::new(B::VARIANT),
             used_swap_chain: None,
             limits,
+            downlevel,
             private_features,
+            passes: Vec::new(),
+            open_pass: None,
+            error: None,
+            inline_uniforms: Vec::new(),
             #[cfg(feature = "trace")]
             commands: if enable_tracing {
                 Some(Vec::new())
@@ -141,12 +186,32 @@ impl<B: hal::Backend> CommandAllocator<B> {
                 total: 0,
                 available: Vec::new(),
                 pending: Vec::new(),
+                last_used: 0,
             },
         );
+        let transit = CommandPool {
+            raw: unsafe {
+                device
+                    .create_command_pool(
+                        queue_family,
+                        hal::pool::CommandPoolCreateFlags::RESET_INDIVIDUAL,
+                    )
+                    .unwrap()
+            },
+            total: 0,
+            available: Vec::new(),
+            pending: Vec::new(),
+            last_used: 0,
+        };
         CommandAllocator {
             queue_family,
             internal_thread_id,
-            inner: Mutex::new(Inner { pools }),
+            inner: Mutex::new(Inner {
+                pools,
+                transit,
+                transit_allocated: 0,
+                transit_reused: 0,
+            }),
         }
     }
 
@@ -163,6 +228,32 @@ impl<B: hal::Backend> CommandAllocator<B> {
         self.allocate_for_thread_id(cmd_buf.recorded_thread_id)
     }
 
+    /// Get a command buffer for the transitions of a submitted command buffer.
+    ///
+    /// These are only allocated when none of the previous ones are available.
+    pub fn allocate_transit(&self) -> B::CommandBuffer {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        match inner.transit.available.pop() {
+            Some(raw) => {
+                inner.transit_reused += 1;
+                raw
+            }
+            None => {
+                inner.transit_allocated += 1;
+                inner.transit.total += 1;
+                let mut raws = Vec::with_capacity(1);
+                unsafe {
+                    inner
+                        .transit
+                        .raw
+                        .allocate(1, hal::command::Level::Primary, &mut raws);
+                }
+                raws.pop().unwrap()
+            }
+        }
+    }
+
     pub fn discard_internal(&self, raw: B::CommandBuffer) {
         let mut inner = self.inner.lock();
         inner
@@ -183,37 +274,54 @@ impl<B: hal::Backend> CommandAllocator<B> {
 
     pub fn after_submit_internal(&self, raw: B::CommandBuffer, submit_index: SubmissionIndex) {
         let mut inner = self.inner.lock();
-        inner
-            .pools
-            .get_mut(&thread::current().id())
-            .unwrap()
-            .pending
-            .push((raw, submit_index));
+        let pool = inner.pools.get_mut(&thread::current().id()).unwrap();
+        pool.last_used = submit_index;
+        pool.pending.push((raw, submit_index));
     }
 
-    pub fn after_submit(&self, cmd_buf: CommandBuffer<B>, submit_index: SubmissionIndex) {
-        // Record this command buffer as pending
+    /// Record the command buffer as pending.
+    ///
+    /// The first native command buffer is expected to be the transit one
+    /// inserted at submission, which goes back to the device-wide pool.
+    pub fn after_submit(&self, mut cmd_buf: CommandBuffer<B>, submit_index: SubmissionIndex) {
+        let transit = cmd_buf.raw.remove(0);
         let mut inner = self.inner.lock();
-        inner
-            .pools
-            .get_mut(&cmd_buf.recorded_thread_id)
-            .unwrap()
-            .pending
+        inner.transit.last_used = submit_index;
+        inner.transit.pending.push((transit, submit_index));
+        let pool = inner.pools.get_mut(&cmd_buf.recorded_thread_id).unwrap();
+        pool.last_used = submit_index;
+        pool.pending
             .extend(cmd_buf.raw.into_iter().map(|raw| (raw, submit_index)));
     }
 
-    pub fn maintain(&self, device: &B::Device, lowest_active_index: SubmissionIndex) {
+    /// Recycle the command buffers of finished submissions, and destroy the pools
+    /// of threads that haven't recorded anything for a while.
+    ///
+    /// There is no way to know if a thread is still alive from its ID, so a pool
+    /// is considered orphaned once it's idle and its last use is at least
+    /// `POOL_IDLE_SUBMISSIONS` behind `last_done_index`. If the thread comes back,
+    /// a new pool is simply created for it.
+    pub fn maintain(
+        &self,
+        device: &B::Device,
+        lowest_active_index: SubmissionIndex,
+        last_done_index: SubmissionIndex,
+    ) {
         let mut inner = self.inner.lock();
+        inner.transit.maintain(lowest_active_index);
         let mut remove_threads = Vec::new();
         for (thread_id, pool) in inner.pools.iter_mut() {
             pool.maintain(lowest_active_index);
-            if pool.total == pool.available.len() {
+            if *thread_id != self.internal_thread_id
+                && pool.is_idle()
+                && is_orphaned(pool.last_used, last_done_index)
+            {
                 assert!(pool.pending.is_empty());
                 remove_threads.push(*thread_id);
             }
         }
         for thread_id in remove_threads {
-            log::info!("Removing from thread {:?}", thread_id);
+            log::info!("Removing idle pool of thread {:?}", thread_id);
             let mut pool = inner.pools.remove(&thread_id).unwrap();
             unsafe {
                 pool.raw.free(pool.available);
@@ -222,9 +330,25 @@ impl<B: hal::Backend> CommandAllocator<B> {
         }
     }
 
+    /// Returns the number of live pools, and the total number
+    /// of command buffers allocated from them, including the transit ones.
+    pub fn pool_stats(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        let buffers = inner.pools.values().map(|pool| pool.total).sum::<usize>();
+        (inner.pools.len(), buffers + inner.transit.total)
+    }
+
+    /// Returns the number of transit command buffers allocated,
+    /// and the number of times one was reused instead.
+    pub fn transit_stats(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        (inner.transit_allocated, inner.transit_reused)
+    }
+
     pub fn destroy(self, device: &B::Device) {
-        let mut inner = self.inner.lock();
-        for (_, mut pool) in inner.pools.drain() {
+        let mut inner = self.inner.into_inner();
+        let transit = iter::once(inner.transit);
+        for mut pool in inner.pools.drain().map(|(_, pool)| pool).chain(transit) {
             while let Some((raw, _)) = pool.pending.pop() {
                 pool.recycle(raw);
             }
@@ -242,3 +366,29 @@ impl<B: hal::Backend> CommandAllocator<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recycle_cap() {
+        assert!(is_kept_on_recycle(0));
+        assert!(is_kept_on_recycle(MAX_AVAILABLE - 1));
+        assert!(!is_kept_on_recycle(MAX_AVAILABLE));
+        assert!(!is_kept_on_recycle(MAX_AVAILABLE + GROW_AMOUNT));
+    }
+
+    #[test]
+    fn orphaned_pools() {
+        // A pool that was never used is kept until enough submissions are done.
+        assert!(!is_orphaned(0, 0));
+        assert!(!is_orphaned(0, POOL_IDLE_SUBMISSIONS - 1));
+        assert!(is_orphaned(0, POOL_IDLE_SUBMISSIONS));
+        // A pool used recently is kept, regardless of its history.
+        assert!(!is_orphaned(100, 100));
+        assert!(!is_orphaned(100, 100 + POOL_IDLE_SUBMISSIONS - 1));
+        assert!(is_orphaned(100, 100 + POOL_IDLE_SUBMISSIONS));
+        assert!(is_orphaned(100, 1000));
+    }
+}
@@ -4,17 +4,19 @@
 
 use crate::{
     binding_model::BindGroup,
+    command::CommandEncoderError,
     hub::GfxBackend,
-    id::{BindGroupId, BindGroupLayoutId, PipelineLayoutId},
-    Stored,
+    id::{BindGroupId, BindGroupLayoutId, BufferId, PipelineLayoutId, TypedId as _},
+    resource::BufferUse,
+    track::{BufferState, PendingTransition, ResourceTracker},
+    RefCount, Stored,
 };
 
 use smallvec::{smallvec, SmallVec};
-use std::slice;
+use std::ops::Range;
 use wgt::DynamicOffset;
 
 pub const DEFAULT_BIND_GROUPS: usize = 4;
-type BindGroupMask = u8;
 
 #[derive(Clone, Debug)]
 pub struct BindGroupPair {
@@ -22,94 +24,17 @@ pub struct BindGroupPair {
     group_id: Stored<BindGroupId>,
 }
 
-#[derive(Debug)]
-pub enum LayoutChange<'a> {
-    Unchanged,
-    Match(BindGroupId, &'a [DynamicOffset]),
-    Mismatch,
-}
-
-#[derive(Debug)]
-pub enum Provision {
-    Unchanged,
-    Changed { was_compatible: bool },
-}
-
-#[derive(Clone)]
-pub struct FollowUpIter<'a> {
-    iter: slice::Iter<'a, BindGroupEntry>,
-}
-impl<'a> Iterator for FollowUpIter<'a> {
-    type Item = (BindGroupId, &'a [DynamicOffset]);
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter
-            .next()
-            .and_then(|entry| Some((entry.actual_value()?, entry.dynamic_offsets.as_slice())))
-    }
-}
-
 #[derive(Clone, Default, Debug)]
 pub struct BindGroupEntry {
     expected_layout_id: Option<BindGroupLayoutId>,
     provided: Option<BindGroupPair>,
     dynamic_offsets: Vec<DynamicOffset>,
+    /// The provided group is bound to the command buffer, and the pipeline
+    /// layouts bound since were compatible up to this entry.
+    bound: bool,
 }
 
 impl BindGroupEntry {
-    fn provide<B: GfxBackend>(
-        &mut self,
-        bind_group_id: BindGroupId,
-        bind_group: &BindGroup<B>,
-        offsets: &[DynamicOffset],
-    ) -> Provision {
-        debug_assert_eq!(B::VARIANT, bind_group_id.backend());
-
-        let was_compatible = match self.provided {
-            Some(BindGroupPair {
-                layout_id,
-                ref group_id,
-            }) => {
-                if group_id.value == bind_group_id && offsets == self.dynamic_offsets.as_slice() {
-                    assert_eq!(layout_id, bind_group.layout_id);
-                    return Provision::Unchanged;
-                }
-                self.expected_layout_id == Some(layout_id)
-            }
-            None => false,
-        };
-
-        self.provided = Some(BindGroupPair {
-            layout_id: bind_group.layout_id,
-            group_id: Stored {
-                value: bind_group_id,
-                ref_count: bind_group.life_guard.add_ref(),
-            },
-        });
-        //TODO: validate the count of dynamic offsets to match the layout
-        self.dynamic_offsets.clear();
-        self.dynamic_offsets.extend_from_slice(offsets);
-
-        Provision::Changed { was_compatible }
-    }
-
-    pub fn expect_layout(&mut self, bind_group_layout_id: BindGroupLayoutId) -> LayoutChange {
-        let some = Some(bind_group_layout_id);
-        if self.expected_layout_id != some {
-            self.expected_layout_id = some;
-            match self.provided {
-                Some(BindGroupPair {
-                    layout_id,
-                    ref group_id,
-                }) if layout_id == bind_group_layout_id => {
-                    LayoutChange::Match(group_id.value, &self.dynamic_offsets)
-                }
-                Some(_) | None => LayoutChange::Mismatch,
-            }
-        } else {
-            LayoutChange::Unchanged
-        }
-    }
-
     fn is_valid(&self) -> bool {
         match (self.expected_layout_id, self.provided.as_ref()) {
             (None, _) => true,
@@ -117,24 +42,26 @@ impl BindGroupEntry {
             (Some(layout), Some(pair)) => layout == pair.layout_id,
         }
     }
-
-    fn actual_value(&self) -> Option<BindGroupId> {
-        self.expected_layout_id.and_then(|layout_id| {
-            self.provided.as_ref().and_then(|pair| {
-                if pair.layout_id == layout_id {
-                    Some(pair.group_id.value)
-                } else {
-                    None
-                }
-            })
-        })
-    }
 }
 
+/// Bind groups set on a pass, and their compatibility with the pipeline layout.
+///
+/// Compatibility is only checked by draws and dispatches, regardless of the
+/// order in which the bind groups and the pipeline are set. The bind groups
+/// are bound to the command buffer right before, by `flush`.
 #[derive(Debug)]
 pub struct Binder {
     pub(crate) pipeline_layout_id: Option<PipelineLayoutId>, //TODO: strongly `Stored`
     pub(crate) entries: SmallVec<[BindGroupEntry; DEFAULT_BIND_GROUPS]>,
+    /// Number of bind group layouts of the pipeline layout.
+    layout_length: usize,
+    /// Number of leading entries known to be compatible with the pipeline
+    /// layout, or `None` if a bind group or the layout changed since.
+    valid_up_to: Option<usize>,
+    /// The buffer usages of the bind groups were checked since the last change.
+    usage_checked: bool,
+    /// Tracker reused by the buffer usage checks.
+    usage_scope: Option<ResourceTracker<BufferState>>,
 }
 
 impl Binder {
@@ -142,63 +69,220 @@ impl Binder {
         Self {
             pipeline_layout_id: None,
             entries: smallvec![Default::default(); max_bind_groups as usize],
+            layout_length: 0,
+            valid_up_to: None,
+            usage_checked: false,
+            usage_scope: None,
         }
     }
 
-    pub(crate) fn reset_expectations(&mut self, length: usize) {
-        for entry in self.entries[length..].iter_mut() {
-            entry.expected_layout_id = None;
+    /// Switch to the layout of a new pipeline, with `bind_group_layout_ids`.
+    ///
+    /// Bind group layouts are compatible up to the first index at which they
+    /// differ from the previous pipeline layout. The bind groups below stay
+    /// bound, and the ones from that index on need to be bound again.
+    pub(crate) fn change_pipeline_layout(
+        &mut self,
+        pipeline_layout_id: PipelineLayoutId,
+        bind_group_layout_ids: &[Stored<BindGroupLayoutId>],
+    ) {
+        if self.pipeline_layout_id == Some(pipeline_layout_id) {
+            return;
+        }
+        self.pipeline_layout_id = Some(pipeline_layout_id);
+        self.layout_length = bind_group_layout_ids.len();
+        self.valid_up_to = None;
+        self.usage_checked = false;
+
+        let compatible = self
+            .entries
+            .iter()
+            .zip(bind_group_layout_ids)
+            .take_while(|&(entry, bgl_id)| entry.expected_layout_id == Some(bgl_id.value))
+            .count();
+        log::trace!("\tPipeline layouts are compatible up to {}", compatible);
+        for (index, entry) in self.entries.iter_mut().enumerate().skip(compatible) {
+            entry.expected_layout_id = bind_group_layout_ids.get(index).map(|id| id.value);
+            entry.bound = false;
         }
     }
 
-    /// Attempt to set the value of the specified bind group index.
-    /// Returns Some() when the new bind group is ready to be actually bound
-    /// (i.e. compatible with current expectations). Also returns an iterator
-    /// of bind group IDs to be bound with it: those are compatible bind groups
-    /// that were previously blocked because the current one was incompatible.
-    pub(crate) fn provide_entry<'a, B: GfxBackend>(
-        &'a mut self,
+    /// Set the bind group at `index`, which is bound by the next `flush`.
+    pub(crate) fn provide_entry<B: GfxBackend>(
+        &mut self,
         index: usize,
         bind_group_id: BindGroupId,
         bind_group: &BindGroup<B>,
         offsets: &[DynamicOffset],
-    ) -> Option<(PipelineLayoutId, FollowUpIter<'a>)> {
+    ) {
         log::trace!("\tBinding [{}] = group {:?}", index, bind_group_id);
         debug_assert_eq!(B::VARIANT, bind_group_id.backend());
+        self.provide(
+            index,
+            bind_group_id,
+            bind_group.layout_id,
+            || bind_group.life_guard.add_ref(),
+            offsets,
+        );
+    }
+
+    fn provide(
+        &mut self,
+        index: usize,
+        bind_group_id: BindGroupId,
+        layout_id: BindGroupLayoutId,
+        add_ref: impl FnOnce() -> RefCount,
+        offsets: &[DynamicOffset],
+    ) {
+        let entry = &mut self.entries[index];
+        if let Some(ref pair) = entry.provided {
+            if pair.group_id.value == bind_group_id && offsets == entry.dynamic_offsets.as_slice() {
+                assert_eq!(pair.layout_id, layout_id);
+                return;
+            }
+        }
+        entry.provided = Some(BindGroupPair {
+            layout_id,
+            group_id: Stored {
+                value: bind_group_id,
+                ref_count: add_ref(),
+            },
+        });
+        //TODO: validate the count of dynamic offsets to match the layout
+        entry.dynamic_offsets.clear();
+        entry.dynamic_offsets.extend_from_slice(offsets);
+        entry.bound = false;
+        self.valid_up_to = None;
+        self.usage_checked = false;
+    }
+
+    /// Bind the group at `index` again on the next `flush`, with
+    /// a different descriptor set than before.
+    pub(crate) fn invalidate(&mut self, index: usize) {
+        self.entries[index].bound = false;
+    }
 
-        match self.entries[index].provide(bind_group_id, bind_group, offsets) {
-            Provision::Unchanged => None,
-            Provision::Changed { was_compatible, .. } => {
-                let compatible_count = self.compatible_count();
-                if index < compatible_count {
-                    let end = compatible_count.min(if was_compatible {
-                        index + 1
-                    } else {
-                        self.entries.len()
-                    });
-                    log::trace!("\t\tbinding up to {}", end);
-                    Some((
-                        self.pipeline_layout_id?,
-                        FollowUpIter {
-                            iter: self.entries[index + 1..end].iter(),
-                        },
-                    ))
-                } else {
-                    log::trace!("\t\tskipping above compatible {}", compatible_count);
-                    None
+    /// Mark the bind groups of the pipeline layout as bound, returning the
+    /// range of them that has to be bound to the command buffer first, if any.
+    ///
+    /// Must only be called once all of them are valid.
+    pub(crate) fn flush(&mut self) -> Option<(PipelineLayoutId, Range<usize>)> {
+        let pipeline_layout_id = self.pipeline_layout_id?;
+        let end = self.layout_length;
+        let start = self.entries[..end].iter().position(|entry| !entry.bound)?;
+        for entry in self.entries[start..end].iter_mut() {
+            entry.bound = true;
+        }
+        log::trace!("\tFlushing bind groups {}..{}", start, end);
+        Some((pipeline_layout_id, start..end))
+    }
+
+    /// The bind groups in `range`, with their dynamic offsets.
+    pub(crate) fn groups(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (BindGroupId, &[DynamicOffset])> + Clone {
+        self.entries[range].iter().map(|entry| {
+            let pair = entry.provided.as_ref().unwrap();
+            (pair.group_id.value, entry.dynamic_offsets.as_slice())
+        })
+    }
+
+    /// The bind group set at `index`, with its dynamic offsets.
+    pub(crate) fn entry(&self, index: usize) -> Option<(BindGroupId, &[DynamicOffset])> {
+        let entry = self.entries.get(index)?;
+        let pair = entry.provided.as_ref()?;
+        Some((pair.group_id.value, &entry.dynamic_offsets))
+    }
+
+    /// Check that the buffers of the bind groups of the pipeline layout can be
+    /// used together, and with the `bound` index and vertex buffers, within a
+    /// single draw or dispatch, where `buffers` gives the buffer usages of a
+    /// bind group. Read-only usages can always be combined.
+    ///
+    /// The check only runs again once a bind group, the pipeline layout or
+    /// the bound buffers changed.
+    pub(crate) fn check_buffer_usage<'a>(
+        &mut self,
+        buffers: impl Fn(BindGroupId) -> &'a ResourceTracker<BufferState>,
+        bound: impl Iterator<Item = (BufferId, BufferUse)>,
+    ) -> Result<(), CommandEncoderError> {
+        if self.usage_checked {
+            return Ok(());
+        }
+        let mut groups = self.entries[..self.layout_length]
+            .iter()
+            .filter_map(|entry| Some(entry.provided.as_ref()?.group_id.value))
+            .peekable();
+        let backend = match groups.peek() {
+            Some(id) => id.backend(),
+            None => {
+                // The bound buffers are only read.
+                self.usage_checked = true;
+                return Ok(());
+            }
+        };
+        let scope = self
+            .usage_scope
+            .get_or_insert_with(|| ResourceTracker::new(backend));
+        scope.clear();
+        for id in groups {
+            scope.merge_extend(buffers(id))?;
+        }
+        for (id, usage) in bound {
+            if let Some(old) = scope.query(id, ()) {
+                PendingTransition {
+                    id,
+                    selector: (),
+                    usage: old..usage,
                 }
+                .collapse()?;
             }
         }
+        self.usage_checked = true;
+        Ok(())
     }
 
-    pub(crate) fn invalid_mask(&self) -> BindGroupMask {
-        self.entries.iter().enumerate().fold(0, |mask, (i, entry)| {
-            if entry.is_valid() {
-                mask
-            } else {
-                mask | 1u8 << i
+    /// Check the buffer usages again on the next draw, after the index
+    /// or vertex buffers changed.
+    pub(crate) fn invalidate_buffer_usage(&mut self) {
+        self.usage_checked = false;
+    }
+
+    /// Return the number of leading bind groups compatible with the pipeline
+    /// layout, which is the length of `entries` when all of them are.
+    ///
+    /// The count is cached until a bind group or the pipeline layout changes,
+    /// so consecutive draws and dispatches skip the per-group checks.
+    pub(crate) fn valid_count(&mut self) -> usize {
+        match self.valid_up_to {
+            Some(count) => count,
+            None => {
+                let count = self.compatible_count();
+                self.valid_up_to = Some(count);
+                count
             }
-        })
+        }
+    }
+
+    /// Bind a group at `index` that matches the expected layout, without
+    /// going through a device.
+    #[cfg(feature = "bench")]
+    pub(crate) fn provide_compatible(
+        &mut self,
+        index: usize,
+        layout_id: BindGroupLayoutId,
+        group_id: Stored<BindGroupId>,
+    ) {
+        let entry = &mut self.entries[index];
+        entry.expected_layout_id = Some(layout_id);
+        entry.provided = Some(BindGroupPair {
+            layout_id,
+            group_id,
+        });
+        entry.bound = false;
+        self.valid_up_to = None;
+        self.usage_checked = false;
     }
 
     fn compatible_count(&self) -> usize {
@@ -208,3 +292,179 @@ impl Binder {
             .unwrap_or_else(|| self.entries.len())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{id::TypedId, LifeGuard};
+    use std::iter;
+
+    const BACKEND: wgt::Backend = wgt::Backend::Empty;
+
+    fn layouts(indices: &[u32]) -> Vec<Stored<BindGroupLayoutId>> {
+        indices
+            .iter()
+            .map(|&index| Stored {
+                value: BindGroupLayoutId::zip(index, 1, BACKEND),
+                ref_count: LifeGuard::new().add_ref(),
+            })
+            .collect()
+    }
+
+    /// Set a bind group of the layout with `layout_index` at `index`.
+    fn provide(binder: &mut Binder, index: usize, layout_index: u32) {
+        binder.provide(
+            index,
+            BindGroupId::zip(layout_index, 1, BACKEND),
+            BindGroupLayoutId::zip(layout_index, 1, BACKEND),
+            || LifeGuard::new().add_ref(),
+            &[],
+        );
+    }
+
+    fn pipeline_layout(index: u32) -> PipelineLayoutId {
+        PipelineLayoutId::zip(index, 1, BACKEND)
+    }
+
+    #[test]
+    fn pipeline_switches() {
+        let mut binder = Binder::new(4);
+        // Bind groups can be set before the pipeline.
+        for index in 0..3 {
+            provide(&mut binder, index, index as u32);
+        }
+        assert_eq!(binder.flush(), None);
+        binder.change_pipeline_layout(pipeline_layout(0), &layouts(&[0, 1, 2]));
+        assert_eq!(binder.valid_count(), 4);
+        assert_eq!(binder.flush(), Some((pipeline_layout(0), 0..3)));
+
+        // All the bind group layouts are shared, nothing is bound again.
+        binder.change_pipeline_layout(pipeline_layout(1), &layouts(&[0, 1, 2]));
+        assert_eq!(binder.valid_count(), 4);
+        assert_eq!(binder.flush(), None);
+
+        // Only the first one is shared, the rest has to be set again.
+        binder.change_pipeline_layout(pipeline_layout(2), &layouts(&[0, 3, 4]));
+        assert_eq!(binder.valid_count(), 1);
+        provide(&mut binder, 2, 4);
+        assert_eq!(binder.valid_count(), 1);
+        provide(&mut binder, 1, 3);
+        assert_eq!(binder.valid_count(), 4);
+        assert_eq!(binder.flush(), Some((pipeline_layout(2), 1..3)));
+
+        // None is shared, but the group at index 1 still matches once the
+        // one at index 0 is replaced.
+        binder.change_pipeline_layout(pipeline_layout(3), &layouts(&[5, 3]));
+        assert_eq!(binder.valid_count(), 0);
+        provide(&mut binder, 0, 5);
+        assert_eq!(binder.valid_count(), 4);
+        assert_eq!(binder.flush(), Some((pipeline_layout(3), 0..2)));
+
+        // Setting the same group again doesn't need a flush.
+        provide(&mut binder, 1, 3);
+        assert_eq!(binder.flush(), None);
+        // Unless it's bound with another descriptor set.
+        binder.invalidate(1);
+        assert_eq!(binder.flush(), Some((pipeline_layout(3), 1..2)));
+        provide(&mut binder, 1, 6);
+        assert_eq!(binder.valid_count(), 1);
+    }
+
+    /// Trackers of the buffers used by one bind group each, given as pairs
+    /// of buffer index and usage.
+    fn buffer_usages(usages: &[&[(u32, BufferUse)]]) -> Vec<ResourceTracker<BufferState>> {
+        let ref_count = LifeGuard::new().add_ref();
+        usages
+            .iter()
+            .map(|buffers| {
+                let mut tracker = ResourceTracker::new(BACKEND);
+                for &(index, usage) in buffers.iter() {
+                    tracker
+                        .change_extend(BufferId::zip(index, 1, BACKEND), &ref_count, (), usage)
+                        .unwrap();
+                }
+                tracker
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buffer_usage_within_layout() {
+        let usages = buffer_usages(&[
+            &[(0, BufferUse::UNIFORM)],
+            &[(0, BufferUse::STORAGE_LOAD), (1, BufferUse::STORAGE_STORE)],
+            &[(1, BufferUse::UNIFORM)],
+        ]);
+        let buffers = |id: BindGroupId| &usages[id.unzip().0 as usize];
+        let mut binder = Binder::new(4);
+        for index in 0..3 {
+            provide(&mut binder, index, index as u32);
+        }
+        // The group at index 2 conflicts, but isn't part of the layout.
+        binder.change_pipeline_layout(pipeline_layout(0), &layouts(&[0, 1]));
+        assert_eq!(binder.check_buffer_usage(buffers, iter::empty()), Ok(()));
+        // Nothing changed, so it's not checked again.
+        binder.usage_scope = None;
+        assert_eq!(binder.check_buffer_usage(buffers, iter::empty()), Ok(()));
+        assert!(binder.usage_scope.is_none());
+    }
+
+    fn conflict(index: u32, first: BufferUse, second: BufferUse) -> CommandEncoderError {
+        CommandEncoderError::ConflictingBufferUsage {
+            buffer: BufferId::zip(index, 1, BACKEND),
+            first: format!("{:?}", first),
+            second: format!("{:?}", second),
+        }
+    }
+
+    #[test]
+    fn buffer_usage_conflict() {
+        let usages = buffer_usages(&[&[(0, BufferUse::UNIFORM)], &[(0, BufferUse::STORAGE_STORE)]]);
+        let buffers = |id: BindGroupId| &usages[id.unzip().0 as usize];
+        let mut binder = Binder::new(4);
+        provide(&mut binder, 0, 0);
+        binder.change_pipeline_layout(pipeline_layout(0), &layouts(&[0, 1]));
+        assert_eq!(binder.check_buffer_usage(buffers, iter::empty()), Ok(()));
+        provide(&mut binder, 1, 1);
+        assert_eq!(
+            binder.check_buffer_usage(buffers, iter::empty()),
+            Err(conflict(0, BufferUse::UNIFORM, BufferUse::STORAGE_STORE))
+        );
+    }
+
+    #[test]
+    fn buffer_usage_with_bound_buffers() {
+        let usages = buffer_usages(&[&[(0, BufferUse::STORAGE_STORE)], &[(1, BufferUse::UNIFORM)]]);
+        let buffers = |id: BindGroupId| &usages[id.unzip().0 as usize];
+        let bound = |index, usage| iter::once((BufferId::zip(index, 1, BACKEND), usage));
+        let mut binder = Binder::new(4);
+        provide(&mut binder, 0, 0);
+        provide(&mut binder, 1, 1);
+        binder.change_pipeline_layout(pipeline_layout(0), &layouts(&[0, 1]));
+        // The uniform buffer can be read as vertices or indices too.
+        assert_eq!(
+            binder.check_buffer_usage(buffers, bound(1, BufferUse::VERTEX)),
+            Ok(())
+        );
+        binder.invalidate_buffer_usage();
+        assert_eq!(
+            binder.check_buffer_usage(buffers, bound(1, BufferUse::INDEX)),
+            Ok(())
+        );
+        // The bound buffers are only checked again once they change.
+        assert_eq!(
+            binder.check_buffer_usage(buffers, bound(0, BufferUse::VERTEX)),
+            Ok(())
+        );
+        binder.invalidate_buffer_usage();
+        assert_eq!(
+            binder.check_buffer_usage(buffers, bound(0, BufferUse::VERTEX)),
+            Err(conflict(0, BufferUse::STORAGE_STORE, BufferUse::VERTEX))
+        );
+        // A failed check runs again on the next draw.
+        assert_eq!(
+            binder.check_buffer_usage(buffers, bound(0, BufferUse::INDEX)),
+            Err(conflict(0, BufferUse::STORAGE_STORE, BufferUse::INDEX))
+        );
+    }
+}
@@ -3,19 +3,18 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    command::{
-        bind::{Binder, LayoutChange},
-        CommandBuffer, PhantomSlice,
-    },
+    binding_model::{BindGroup, PipelineLayout},
+    command::{bind::Binder, CommandBuffer, PhantomSlice},
     device::all_buffer_stages,
-    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Token},
+    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Storage, Token},
     id,
-    resource::BufferUse,
+    resource::{BufferUse, TextureUse},
+    RawString,
 };
 
 use hal::command::CommandBuffer as _;
 use peek_poke::{Peek, PeekPoke, Poke};
-use wgt::{BufferAddress, BufferUsage, DynamicOffset, BIND_BUFFER_ALIGNMENT};
+use wgt::{BufferAddress, BufferUsage, DynamicOffset};
 
 use std::iter;
 
@@ -42,6 +41,15 @@ pub enum ComputeCommand {
         buffer_id: id::BufferId,
         offset: BufferAddress,
     },
+    PushDebugGroup {
+        label_length: u32,
+        #[cfg_attr(any(feature = "trace", feature = "replay"), serde(skip))]
+        phantom_label: PhantomSlice<u8>,
+    },
+    PopDebugGroup,
+    SetUserTag(u32),
+    /// Make all the shader writes of the previous dispatches visible to the next ones.
+    MemoryBarrier,
     End,
 }
 
@@ -63,24 +71,251 @@ impl super::RawPass {
 }
 
 #[repr(C)]
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 pub struct ComputePassDescriptor {
-    pub todo: u32,
+    pub label: RawString,
+}
+
+/// Find the pipelines, and the bind groups set with non-zero dynamic offsets, used by a pass,
+/// along with the other objects it names.
+fn used_resources(
+    raw_data: &[u8],
+) -> (
+    Vec<id::ComputePipelineId>,
+    Vec<(id::BindGroupId, Vec<DynamicOffset>)>,
+    super::PassIds,
+) {
+    let mut pipelines = Vec::new();
+    let mut bind_groups = Vec::new();
+    let mut ids = super::PassIds::default();
+    let mut peeker = raw_data.as_ptr();
+    let raw_data_end = unsafe { raw_data.as_ptr().add(raw_data.len()) };
+    let mut command = ComputeCommand::End;
+    // Malformed data is reported by the pass itself.
+    while unsafe { peeker.add(ComputeCommand::max_size()) } <= raw_data_end {
+        peeker = unsafe { ComputeCommand::peek_from(peeker, &mut command) };
+        match command {
+            ComputeCommand::SetBindGroup {
+                num_dynamic_offsets,
+                bind_group_id,
+                phantom_offsets,
+                ..
+            } => {
+                let (new_peeker, offsets) = unsafe {
+                    phantom_offsets.decode_unaligned(
+                        peeker,
+                        num_dynamic_offsets as usize,
+                        raw_data_end,
+                    )
+                };
+                peeker = new_peeker;
+                super::PassIds::add(&mut ids.bind_groups, bind_group_id);
+                if offsets.iter().any(|&offset| offset != 0) {
+                    let entry = (bind_group_id, offsets.to_vec());
+                    if !bind_groups.contains(&entry) {
+                        bind_groups.push(entry);
+                    }
+                }
+            }
+            ComputeCommand::DispatchIndirect { buffer_id, .. } => {
+                super::PassIds::add(&mut ids.buffers, buffer_id);
+            }
+            ComputeCommand::SetPipeline(pipeline_id) => {
+                if !pipelines.contains(&pipeline_id) {
+                    pipelines.push(pipeline_id);
+                }
+            }
+            ComputeCommand::PushDebugGroup {
+                label_length,
+                phantom_label,
+            } => {
+                peeker =
+                    unsafe { phantom_label.decode_label(peeker, label_length, raw_data_end) }.0;
+            }
+            ComputeCommand::End => break,
+            _ => {}
+        }
+    }
+    (pipelines, bind_groups, ids)
+}
+
+/// Bind the bind groups that changed since the last dispatch, or that the
+/// pipeline layout made incompatible, once their buffer usages are checked.
+fn flush_bind_groups<B: GfxBackend>(
+    raw: &mut B::CommandBuffer,
+    binder: &mut Binder,
+    bind_group_guard: &Storage<BindGroup<B>, id::BindGroupId>,
+    pipeline_layout_guard: &Storage<PipelineLayout<B>, id::PipelineLayoutId>,
+) -> Result<(), super::CommandEncoderError> {
+    binder.check_buffer_usage(|id| &bind_group_guard[id].used.buffers, iter::empty())?;
+    if let Some((pipeline_layout_id, range)) = binder.flush() {
+        let start = range.start;
+        let groups = binder.groups(range);
+        unsafe {
+            raw.bind_compute_descriptor_sets(
+                &pipeline_layout_guard[pipeline_layout_id].raw,
+                start,
+                groups
+                    .clone()
+                    .map(|(bg_id, offsets)| bind_group_guard[bg_id].raw_for_offsets(offsets)),
+                groups.flat_map(|(_, offsets)| offsets).cloned(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Storage resources written by the dispatches of a pass since its last memory barrier.
+///
+/// Bind groups only get barriers when they are set, so the dispatches sharing
+/// them need barriers of their own to see each other's writes.
+#[derive(Debug, Default)]
+struct DispatchHazards {
+    buffers: Vec<id::BufferId>,
+    textures: Vec<id::TextureId>,
+}
+
+impl DispatchHazards {
+    /// Add a dispatch that uses `buffers` and `textures`, and return true
+    /// if it accesses anything written by the earlier dispatches, so that
+    /// it has to wait for them.
+    fn add_dispatch(
+        &mut self,
+        buffers: &[(id::BufferId, BufferUse)],
+        textures: &[(id::TextureId, TextureUse)],
+    ) -> bool {
+        let hazard = buffers.iter().any(|&(id, _)| self.buffers.contains(&id))
+            || textures.iter().any(|&(id, _)| self.textures.contains(&id));
+        if hazard {
+            self.clear();
+        }
+        self.buffers.extend(
+            buffers
+                .iter()
+                .filter(|&&(_, usage)| usage.contains(BufferUse::STORAGE_STORE))
+                .map(|&(id, _)| id),
+        );
+        self.textures.extend(
+            textures
+                .iter()
+                .filter(|&&(_, usage)| usage.contains(TextureUse::STORAGE_STORE))
+                .map(|&(id, _)| id),
+        );
+        hazard
+    }
+
+    fn clear(&mut self) {
+        self.buffers.clear();
+        self.textures.clear();
+    }
+}
+
+/// Collect the buffers and textures used by the bind groups of a dispatch.
+fn dispatch_resources<B: GfxBackend>(
+    binder: &Binder,
+    bind_group_guard: &Storage<BindGroup<B>, id::BindGroupId>,
+) -> (
+    Vec<(id::BufferId, BufferUse)>,
+    Vec<(id::TextureId, TextureUse)>,
+) {
+    let mut buffers = Vec::new();
+    let mut textures = Vec::new();
+    for (bind_group_id, _) in (0..binder.entries.len()).filter_map(|index| binder.entry(index)) {
+        let used = &bind_group_guard[bind_group_id].used;
+        buffers.extend(
+            used.buffers
+                .used_states()
+                .map(|(id, state)| (id, state.last_usage())),
+        );
+        textures.extend(
+            used.textures
+                .used_states()
+                .map(|(id, state)| (id, state.last_usage())),
+        );
+    }
+    (buffers, textures)
+}
+
+/// Wait for the shader writes of the previous dispatches before the next ones.
+fn memory_barrier<B: hal::Backend>(raw: &mut B::CommandBuffer) {
+    use hal::{buffer::Access as BufferAccess, image::Access as ImageAccess};
+    unsafe {
+        raw.pipeline_barrier(
+            hal::pso::PipelineStage::COMPUTE_SHADER..hal::pso::PipelineStage::COMPUTE_SHADER,
+            hal::memory::Dependencies::empty(),
+            &[
+                hal::memory::Barrier::AllBuffers(
+                    BufferAccess::SHADER_WRITE
+                        ..BufferAccess::SHADER_READ | BufferAccess::SHADER_WRITE,
+                ),
+                hal::memory::Barrier::AllImages(
+                    ImageAccess::SHADER_WRITE..ImageAccess::SHADER_READ | ImageAccess::SHADER_WRITE,
+                ),
+            ],
+        );
+    }
 }
 
 // Common routines between render/compute
 
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
+    /// Begin a compute pass, which stays open on the encoder until
+    /// it's run by `command_encoder_run_compute_pass`, or dropped.
+    pub fn command_encoder_begin_compute_pass<B: GfxBackend>(
+        &self,
+        encoder_id: id::CommandEncoderId,
+        desc: &ComputePassDescriptor,
+    ) -> super::RawPass {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+        // The pass is still returned, and running it reports the error.
+        match super::recording_encoder(&mut *cmb_guard, encoder_id) {
+            Ok(cmb) => cmb.begin_pass(super::PassKind::Compute, desc.label),
+            Err(e) => log::error!("Unable to begin a compute pass: {:?}", e),
+        }
+        unsafe { super::RawPass::new_compute(encoder_id) }
+    }
+
     pub fn command_encoder_run_compute_pass<B: GfxBackend>(
         &self,
         encoder_id: id::CommandEncoderId,
         raw_data: &[u8],
-    ) {
+    ) -> Result<(), super::CommandEncoderError> {
         let hub = B::hub(self);
+        let device_id = {
+            let mut token = Token::root();
+            let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+            super::recording_encoder(&mut *cmb_guard, encoder_id)?
+                .device_id
+                .value
+        };
+        let (used_pipelines, used_bind_groups, used_ids) = used_resources(raw_data);
+        let checked = self
+            .check_pass_ids::<B>(device_id, &used_ids)
+            .and_then(|()| self.wait_for_pipelines::<B>(device_id, &[], &used_pipelines))
+            .and_then(|()| {
+                self.prepare_derived_bind_groups::<B>(&used_bind_groups)
+                    .map_err(super::CommandEncoderError::from)
+            });
+        if let Err(e) = checked {
+            let mut token = Token::root();
+            let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+            cmb_guard[encoder_id].end_pass(super::PassKind::Compute);
+            return Err(e);
+        }
+
         let mut token = Token::root();
 
         let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
         let cmb = &mut cmb_guard[encoder_id];
+        cmb.end_pass(super::PassKind::Compute);
+        let first_work_index = cmb.work_count();
+        assert!(
+            cmb.downlevel.compute_shaders,
+            "This adapter does not support compute shaders"
+        );
+        let downlevel = cmb.downlevel;
         let raw = cmb.raw.last_mut().unwrap();
         let mut binder = Binder::new(cmb.limits.max_bind_groups);
 
@@ -91,13 +326,20 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let (texture_guard, _) = hub.textures.read(&mut token);
 
         let mut pipeline_state = PipelineState::Required;
+        let mut hazards = DispatchHazards::default();
 
         let mut peeker = raw_data.as_ptr();
         let raw_data_end = unsafe { raw_data.as_ptr().add(raw_data.len()) };
         let mut command = ComputeCommand::Dispatch([0; 3]); // dummy
+        let mut dispatches = 0;
+        let mut pipeline_switches = 0;
+        let mut bind_group_switches = 0;
+        let mut context = super::PassCommandContext::default();
+        let mut pass_error = None;
         loop {
             assert!(unsafe { peeker.add(ComputeCommand::max_size()) } <= raw_data_end);
             peeker = unsafe { ComputeCommand::peek_from(peeker, &mut command) };
+            context.index = first_work_index + dispatches;
             match command {
                 ComputeCommand::SetBindGroup {
                     index,
@@ -113,25 +355,21 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         )
                     };
                     peeker = new_peeker;
-
-                    if cfg!(debug_assertions) {
-                        for off in offsets {
-                            assert_eq!(
-                                *off as BufferAddress % BIND_BUFFER_ALIGNMENT,
-                                0,
-                                "Misaligned dynamic buffer offset: {} does not align with {}",
-                                off,
-                                BIND_BUFFER_ALIGNMENT
-                            );
-                        }
-                    }
+                    bind_group_switches += 1;
+                    assert!(
+                        u32::from(index) < cmb.limits.max_bind_groups,
+                        "Bind group index {} is out of the max_bind_groups limit {}",
+                        index,
+                        cmb.limits.max_bind_groups
+                    );
 
                     let bind_group = cmb
                         .trackers
                         .bind_groups
                         .use_extend(&*bind_group_guard, bind_group_id, (), ())
                         .unwrap();
-                    assert_eq!(bind_group.dynamic_count, offsets.len());
+                    bind_group.validate_members(bind_group_id);
+                    bind_group.validate_dynamic_offsets(offsets);
 
                     log::trace!(
                         "Encoding barriers on binding of {:?} to {:?}",
@@ -146,29 +384,11 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         &*texture_guard,
                     );
 
-                    if let Some((pipeline_layout_id, follow_ups)) =
-                        binder.provide_entry(index as usize, bind_group_id, bind_group, offsets)
-                    {
-                        let bind_groups = iter::once(bind_group.raw.raw()).chain(
-                            follow_ups
-                                .clone()
-                                .map(|(bg_id, _)| bind_group_guard[bg_id].raw.raw()),
-                        );
-                        unsafe {
-                            raw.bind_compute_descriptor_sets(
-                                &pipeline_layout_guard[pipeline_layout_id].raw,
-                                index as usize,
-                                bind_groups,
-                                offsets
-                                    .iter()
-                                    .chain(follow_ups.flat_map(|(_, offsets)| offsets))
-                                    .cloned(),
-                            );
-                        }
-                    }
+                    binder.provide_entry(index as usize, bind_group_id, bind_group, offsets);
                 }
                 ComputeCommand::SetPipeline(pipeline_id) => {
                     pipeline_state = PipelineState::Set;
+                    pipeline_switches += 1;
                     let pipeline = cmb
                         .trackers
                         .compute_pipes
@@ -176,58 +396,80 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         .unwrap();
 
                     unsafe {
-                        raw.bind_compute_pipeline(&pipeline.raw);
+                        raw.bind_compute_pipeline(pipeline.raw.ready(pipeline_id));
                     }
 
-                    // Rebind resources
-                    if binder.pipeline_layout_id != Some(pipeline.layout_id.value) {
-                        let pipeline_layout = &pipeline_layout_guard[pipeline.layout_id.value];
-                        binder.pipeline_layout_id = Some(pipeline.layout_id.value);
-                        binder.reset_expectations(pipeline_layout.bind_group_layout_ids.len());
-                        let mut is_compatible = true;
-
-                        for (index, (entry, bgl_id)) in binder
-                            .entries
-                            .iter_mut()
-                            .zip(&pipeline_layout.bind_group_layout_ids)
-                            .enumerate()
-                        {
-                            match entry.expect_layout(bgl_id.value) {
-                                LayoutChange::Match(bg_id, offsets) if is_compatible => {
-                                    let desc_set = bind_group_guard[bg_id].raw.raw();
-                                    unsafe {
-                                        raw.bind_compute_descriptor_sets(
-                                            &pipeline_layout.raw,
-                                            index,
-                                            iter::once(desc_set),
-                                            offsets.iter().cloned(),
-                                        );
-                                    }
-                                }
-                                LayoutChange::Match(..) | LayoutChange::Unchanged => {}
-                                LayoutChange::Mismatch => {
-                                    is_compatible = false;
-                                }
-                            }
-                        }
-                    }
+                    binder.change_pipeline_layout(
+                        pipeline.layout_id.value,
+                        &pipeline_layout_guard[pipeline.layout_id.value].bind_group_layout_ids,
+                    );
                 }
                 ComputeCommand::Dispatch(groups) => {
                     assert_eq!(
                         pipeline_state,
                         PipelineState::Set,
-                        "Dispatch error: Pipeline is missing"
+                        "Dispatch {} error: Pipeline is missing",
+                        context
+                    );
+                    let valid_count = binder.valid_count();
+                    assert_eq!(
+                        valid_count,
+                        binder.entries.len(),
+                        "Dispatch {} error: bind group at index {} is incompatible with the pipeline layout",
+                        context,
+                        valid_count
                     );
+                    if let Err(error) = flush_bind_groups::<B>(
+                        raw,
+                        &mut binder,
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    let (buffers, textures) = dispatch_resources(&binder, &*bind_group_guard);
+                    if hazards.add_dispatch(&buffers, &textures) {
+                        memory_barrier::<B>(raw);
+                    }
                     unsafe {
                         raw.dispatch(groups);
                     }
+                    dispatches += 1;
                 }
                 ComputeCommand::DispatchIndirect { buffer_id, offset } => {
+                    assert!(
+                        downlevel.indirect_execution,
+                        "This adapter does not support indirect dispatches"
+                    );
                     assert_eq!(
                         pipeline_state,
                         PipelineState::Set,
-                        "Dispatch error: Pipeline is missing"
+                        "Dispatch {} error: Pipeline is missing",
+                        context
                     );
+                    let valid_count = binder.valid_count();
+                    assert_eq!(
+                        valid_count,
+                        binder.entries.len(),
+                        "Dispatch {} error: bind group at index {} is incompatible with the pipeline layout",
+                        context,
+                        valid_count
+                    );
+                    if let Err(error) = flush_bind_groups::<B>(
+                        raw,
+                        &mut binder,
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    // The indirect buffer itself goes through the tracker below.
+                    let (buffers, textures) = dispatch_resources(&binder, &*bind_group_guard);
+                    if hazards.add_dispatch(&buffers, &textures) {
+                        memory_barrier::<B>(raw);
+                    }
                     let (src_buffer, src_pending) = cmb.trackers.buffers.use_replace(
                         &*buffer_guard,
                         buffer_id,
@@ -246,16 +488,61 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         );
                         raw.dispatch_indirect(&src_buffer.raw, offset);
                     }
+                    dispatches += 1;
+                }
+                ComputeCommand::PushDebugGroup {
+                    label_length,
+                    phantom_label,
+                } => {
+                    let (new_peeker, label) =
+                        unsafe { phantom_label.decode_label(peeker, label_length, raw_data_end) };
+                    peeker = new_peeker;
+                    unsafe {
+                        raw.begin_debug_marker(&label, 0);
+                    }
+                    context.debug_groups.push(label);
+                }
+                ComputeCommand::PopDebugGroup => {
+                    assert!(
+                        context.debug_groups.pop().is_some(),
+                        "Compute pass command {} pops a debug group that isn't pushed",
+                        context
+                    );
+                    unsafe {
+                        raw.end_debug_marker();
+                    }
+                }
+                ComputeCommand::SetUserTag(tag) => {
+                    context.user_tag = Some(tag);
+                }
+                ComputeCommand::MemoryBarrier => {
+                    memory_barrier::<B>(raw);
+                    hazards.clear();
                 }
                 ComputeCommand::End => break,
             }
         }
+        if let Some(error) = pass_error {
+            cmb.invalidate(error.clone());
+            return Err(error);
+        }
+        assert!(
+            context.debug_groups.is_empty(),
+            "Debug groups {:?} are not popped at the end of the compute pass",
+            context.debug_groups
+        );
 
         #[cfg(feature = "trace")]
         match cmb.commands {
             Some(ref mut list) => {
                 let mut pass_commands = Vec::new();
                 let mut pass_dynamic_offsets = Vec::new();
+                let mut pass_debug_labels = Vec::new();
+                let mut pass_contexts = Vec::new();
+                let mut context = super::PassCommandContext {
+                    index: first_work_index,
+                    ..super::PassCommandContext::default()
+                };
                 peeker = raw_data.as_ptr();
                 loop {
                     peeker = unsafe { ComputeCommand::peek_from(peeker, &mut command) };
@@ -275,18 +562,53 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             peeker = new_peeker;
                             pass_dynamic_offsets.extend_from_slice(offsets);
                         }
+                        ComputeCommand::PushDebugGroup {
+                            label_length,
+                            phantom_label,
+                        } => {
+                            let (new_peeker, label) = unsafe {
+                                phantom_label.decode_label(peeker, label_length, raw_data_end)
+                            };
+                            peeker = new_peeker;
+                            pass_debug_labels.push(label.clone());
+                            context.debug_groups.push(label);
+                        }
+                        ComputeCommand::PopDebugGroup => {
+                            context.debug_groups.pop();
+                        }
+                        ComputeCommand::SetUserTag(tag) => {
+                            context.user_tag = Some(tag);
+                        }
                         ComputeCommand::End => break,
                         _ => {}
                     }
                     pass_commands.push(command);
+                    pass_contexts.push(context.clone());
+                    match command {
+                        ComputeCommand::Dispatch(..) | ComputeCommand::DispatchIndirect { .. } => {
+                            context.index += 1
+                        }
+                        _ => {}
+                    }
                 }
                 list.push(crate::device::trace::Command::RunComputePass {
                     commands: pass_commands,
                     dynamic_offsets: pass_dynamic_offsets,
+                    debug_labels: pass_debug_labels,
+                    contexts: pass_contexts,
                 });
             }
             None => {}
         }
+
+        cmb.passes.push(super::PassReport {
+            kind: super::PassKind::Compute,
+            dispatches,
+            pipeline_switches,
+            bind_group_switches,
+            ..super::PassReport::default()
+        });
+        Ok(())
     }
 }
 
@@ -296,7 +618,7 @@ pub mod compute_ffi {
         ComputeCommand,
     };
     use crate::{id, RawString};
-    use std::{convert::TryInto, slice};
+    use std::{convert::TryInto, ffi, slice};
     use wgt::{BufferAddress, DynamicOffset};
 
     /// # Safety
@@ -350,13 +672,38 @@ pub mod compute_ffi {
     }
 
     #[no_mangle]
-    pub extern "C" fn wgpu_compute_pass_push_debug_group(_pass: &mut RawPass, _label: RawString) {
-        //TODO
+    pub unsafe extern "C" fn wgpu_compute_pass_push_debug_group(
+        pass: &mut RawPass,
+        label: RawString,
+    ) {
+        let bytes = ffi::CStr::from_ptr(label).to_bytes();
+        pass.encode(&ComputeCommand::PushDebugGroup {
+            label_length: bytes.len().try_into().unwrap(),
+            phantom_label: PhantomSlice::default(),
+        });
+        pass.encode_slice(bytes);
     }
 
     #[no_mangle]
-    pub extern "C" fn wgpu_compute_pass_pop_debug_group(_pass: &mut RawPass) {
-        //TODO
+    pub unsafe extern "C" fn wgpu_compute_pass_pop_debug_group(pass: &mut RawPass) {
+        pass.encode(&ComputeCommand::PopDebugGroup);
+    }
+
+    /// Tag the following commands of the pass, for debugging and in traces.
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_compute_pass_set_user_tag(pass: &mut RawPass, tag: u32) {
+        pass.encode(&ComputeCommand::SetUserTag(tag));
+    }
+
+    /// Make all the shader writes of the previous dispatches of the pass
+    /// visible to the next ones.
+    ///
+    /// Dispatches already wait for the earlier dispatches of the pass that
+    /// wrote to the resources of their bind groups, so this is only needed
+    /// to order other side effects.
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_compute_pass_memory_barrier(pass: &mut RawPass) {
+        pass.encode(&ComputeCommand::MemoryBarrier);
     }
 
     #[no_mangle]
@@ -377,3 +724,35 @@ pub mod compute_ffi {
         pass.base
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::id::TypedId as _;
+
+    #[test]
+    fn dispatch_hazards() {
+        let input = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let output = id::BufferId::zip(1, 1, wgt::Backend::Empty);
+        let image = id::TextureId::zip(0, 1, wgt::Backend::Empty);
+        let mut hazards = DispatchHazards::default();
+
+        // reading the same input over and over needs no barrier
+        let read = [(input, BufferUse::STORAGE_LOAD)];
+        assert!(!hazards.add_dispatch(&read, &[]));
+        assert!(!hazards.add_dispatch(&read, &[]));
+        // the first writer doesn't wait, the next user of the output does
+        let write = [
+            (input, BufferUse::STORAGE_LOAD),
+            (output, BufferUse::STORAGE_STORE),
+        ];
+        assert!(!hazards.add_dispatch(&write, &[]));
+        assert!(hazards.add_dispatch(&[(output, BufferUse::STORAGE_LOAD)], &[]));
+        // the barrier covered the earlier writes
+        assert!(!hazards.add_dispatch(&read, &[]));
+
+        assert!(!hazards.add_dispatch(&[], &[(image, TextureUse::STORAGE_STORE)]));
+        hazards.clear();
+        assert!(!hazards.add_dispatch(&[], &[(image, TextureUse::SAMPLED)]));
+    }
+}
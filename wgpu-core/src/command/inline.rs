@@ -0,0 +1,141 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{id, FastHashMap};
+
+use gfx_descriptor::DescriptorSet;
+use gfx_memory::MemoryBlock;
+use wgt::{BufferAddress, DynamicOffset};
+
+/// A bind group with some of its dynamic bindings pointing to the inline
+/// uniform ring of a pass instead of the buffers it was created with.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub(crate) struct InlineSetKey {
+    pub bind_group_id: id::BindGroupId,
+    /// Dynamic offsets the bind group was set with, which pick
+    /// the descriptor set the other bindings are copied from.
+    pub offsets: Vec<DynamicOffset>,
+    /// Bindings pointing to the ring, sorted.
+    pub bindings: Vec<u32>,
+}
+
+/// Bindings redirected to the ring by the inline uniform writes,
+/// for each bind group index of a pass.
+///
+/// The redirection lasts until a bind group is set again at that index.
+#[derive(Debug, Default)]
+pub(crate) struct InlineBindings {
+    entries: Vec<Option<InlineSetKey>>,
+}
+
+impl InlineBindings {
+    /// Forget the redirection at `index`, returning whether there was one.
+    pub(crate) fn reset(&mut self, index: usize) -> bool {
+        match self.entries.get_mut(index) {
+            Some(entry) => entry.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Redirect `binding` of the bind group set at `index` with `offsets`.
+    pub(crate) fn redirect(
+        &mut self,
+        index: usize,
+        bind_group_id: id::BindGroupId,
+        offsets: &[DynamicOffset],
+        binding: u32,
+    ) -> &InlineSetKey {
+        if self.entries.len() <= index {
+            self.entries.resize(index + 1, None);
+        }
+        let key = self.entries[index].get_or_insert_with(|| InlineSetKey {
+            bind_group_id,
+            offsets: offsets.to_vec(),
+            bindings: Vec::new(),
+        });
+        debug_assert_eq!(key.bind_group_id, bind_group_id);
+        if let Err(position) = key.bindings.binary_search(&binding) {
+            key.bindings.insert(position, binding);
+        }
+        key
+    }
+
+    /// The redirection at `index`, if any.
+    pub(crate) fn key(&self, index: usize) -> Option<&InlineSetKey> {
+        self.entries.get(index)?.as_ref()
+    }
+}
+
+/// Offset of a new slot of `size` bytes at the end of the ring, where
+/// `cursor` is the end of the previous slot.
+pub(crate) fn suballocate(
+    cursor: &mut BufferAddress,
+    size: BufferAddress,
+    alignment: BufferAddress,
+) -> BufferAddress {
+    let offset = (*cursor + alignment - 1) / alignment * alignment;
+    *cursor = offset + size;
+    offset
+}
+
+/// Buffer holding the data of the inline uniform writes of a render pass.
+///
+/// Each write gets its own slot, in recording order, so the draws recorded
+/// before it keep seeing the previous data. It's sized for the whole pass
+/// before the pass is run, and lives as long as the command buffer.
+#[derive(Debug)]
+pub(crate) struct InlineUniformRing<B: hal::Backend> {
+    pub(crate) raw: B::Buffer,
+    pub(crate) memory: MemoryBlock<B>,
+    pub(crate) size: BufferAddress,
+    /// Copies of the bind groups with bindings pointing to the ring.
+    pub(crate) sets: FastHashMap<InlineSetKey, DescriptorSet<B>>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::id::TypedId as _;
+
+    fn bind_group(index: u32) -> id::BindGroupId {
+        id::BindGroupId::zip(index, 1, wgt::Backend::Empty)
+    }
+
+    #[test]
+    fn slots() {
+        let mut cursor = 0;
+        assert_eq!(suballocate(&mut cursor, 64, 256), 0);
+        assert_eq!(suballocate(&mut cursor, 16, 256), 256);
+        assert_eq!(cursor, 272);
+        // Every slot starts at the alignment of its binding.
+        assert_eq!(suballocate(&mut cursor, 4, 64), 320);
+        assert_eq!(suballocate(&mut cursor, 4, 4), 324);
+        assert_eq!(cursor, 328);
+    }
+
+    #[test]
+    fn redirections() {
+        let mut bindings = InlineBindings::default();
+        assert_eq!(bindings.key(1), None);
+        assert!(!bindings.reset(1));
+
+        let key = bindings.redirect(1, bind_group(0), &[256], 2).clone();
+        assert_eq!(key.offsets, vec![256]);
+        assert_eq!(key.bindings, vec![2]);
+        assert_eq!(bindings.key(0), None);
+        // The offsets of the bind group are kept from the first write,
+        // and later ones add to the redirected bindings.
+        let key = bindings.redirect(1, bind_group(0), &[512], 0).clone();
+        assert_eq!(key.offsets, vec![256]);
+        assert_eq!(key.bindings, vec![0, 2]);
+        assert_eq!(bindings.redirect(1, bind_group(0), &[768], 2), &key);
+
+        // Setting a bind group again drops the redirection.
+        assert!(bindings.reset(1));
+        assert_eq!(bindings.key(1), None);
+        let key = bindings.redirect(1, bind_group(1), &[0], 2);
+        assert_eq!(key.bind_group_id, bind_group(1));
+        assert_eq!(key.bindings, vec![2]);
+    }
+}
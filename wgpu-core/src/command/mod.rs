@@ -5,26 +5,32 @@
 mod allocator;
 mod bind;
 mod compute;
+mod inline;
 mod render;
 mod transfer;
 
 pub(crate) use self::allocator::CommandAllocator;
+#[cfg(feature = "bench")]
+pub(crate) use self::bind::Binder;
 pub use self::compute::*;
+pub(crate) use self::inline::{suballocate, InlineSetKey, InlineUniformRing};
 pub use self::render::*;
 pub use self::transfer::*;
 
 use crate::{
-    device::{all_buffer_stages, all_image_stages, MAX_COLOR_TARGETS},
+    conv,
+    device::{check_device, AllocationError, WrongDeviceError, MAX_COLOR_TARGETS},
     hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Storage, Token},
     id,
     resource::{Buffer, Texture},
-    track::TrackerSet,
-    PrivateFeatures, Stored,
+    swap_chain::FrameIndex,
+    track::{BufferState, PendingTransition, TrackerSet},
+    PrivateFeatures, RawString, Stored,
 };
 
 use peek_poke::PeekPoke;
 
-use std::{marker::PhantomData, mem, ptr, slice, thread::ThreadId};
+use std::{ffi, fmt, marker::PhantomData, mem, ptr, slice, thread::ThreadId};
 
 #[derive(Clone, Copy, Debug, PeekPoke)]
 pub struct PhantomSlice<T>(PhantomData<T>);
@@ -56,6 +62,46 @@ impl<T> PhantomSlice<T> {
     }
 }
 
+impl PhantomSlice<u8> {
+    /// Decode a label encoded after its command.
+    unsafe fn decode_label(
+        self,
+        pointer: *const u8,
+        length: u32,
+        bound: *const u8,
+    ) -> (*const u8, String) {
+        let (end, bytes) = self.decode_unaligned(pointer, length as usize, bound);
+        (end, String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+/// Where a pass command was recorded, to map GPU work back to the application.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+#[cfg_attr(feature = "replay", derive(serde::Deserialize))]
+pub struct PassCommandContext {
+    /// Index of the draw or dispatch in the command buffer.
+    /// Other commands have the index of the next draw or dispatch.
+    pub index: usize,
+    /// The last tag set by the application in the pass.
+    pub user_tag: Option<u32>,
+    /// Labels of the debug groups the command is nested in.
+    pub debug_groups: Vec<String>,
+}
+
+impl fmt::Display for PassCommandContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.index)?;
+        if let Some(tag) = self.user_tag {
+            write!(f, " (user tag {})", tag)?;
+        }
+        if !self.debug_groups.is_empty() {
+            write!(f, " in debug groups {:?}", self.debug_groups)?;
+        }
+        Ok(())
+    }
+}
+
 #[repr(C)]
 pub struct RawPass {
     data: *mut u8,
@@ -147,6 +193,153 @@ impl RawPass {
     }
 }
 
+/// Kind of the commands grouped into a pass of a `PassReport`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+pub enum PassKind {
+    Render,
+    Compute,
+    /// Consecutive copies recorded outside of passes.
+    Transfer,
+}
+
+impl Default for PassKind {
+    fn default() -> Self {
+        PassKind::Transfer
+    }
+}
+
+/// Number of commands recorded by a pass.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+pub struct PassReport {
+    pub kind: PassKind,
+    pub draws: usize,
+    pub dispatches: usize,
+    pub copies: usize,
+    /// Triangles of the direct draws, indirect draws aren't counted.
+    pub triangles: u64,
+    pub pipeline_switches: usize,
+    pub bind_group_switches: usize,
+}
+
+/// A pass begun on a command encoder, which isn't ended yet.
+#[derive(Clone, Debug)]
+pub(crate) struct OpenPass {
+    kind: PassKind,
+    label: String,
+}
+
+/// Recording state of a command encoder.
+///
+/// Submitted or destroyed encoders are unregistered, so they don't have
+/// a state, and are reported as `CommandEncoderError::Consumed`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandEncoderStatus {
+    /// Commands can be recorded.
+    Recording,
+    /// The encoder is finished, and its command buffer can be submitted.
+    Finished,
+    /// The encoder is invalidated, and can neither record nor be finished.
+    Error,
+}
+
+/// Error that invalidates a command encoder, reported by `command_encoder_finish`,
+/// or that rejects a command on an encoder that isn't recording.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CommandEncoderError {
+    /// A pass was dropped, or left open, without being ended.
+    PassNotEnded {
+        kind: PassKind,
+        label: String,
+    },
+    /// A command was recorded on the encoder itself while a pass is open.
+    EncoderLocked {
+        command: &'static str,
+        pass_kind: PassKind,
+        pass_label: String,
+    },
+    /// The encoder is already finished.
+    Finished,
+    /// The encoder is in the error state, and `command_encoder_finish`
+    /// reports the error that invalidated it.
+    Invalid,
+    /// The command buffer of the encoder is already submitted or destroyed.
+    Consumed,
+    /// A buffer used by the command doesn't exist, or is already destroyed.
+    /// The command is rejected, and the encoder stays valid.
+    InvalidBuffer(id::BufferId),
+    /// A texture used by the command doesn't exist, or is already destroyed.
+    /// The command is rejected, and the encoder stays valid.
+    InvalidTexture(id::TextureId),
+    /// A resource used by the pass doesn't exist, or is already destroyed.
+    /// The pass is dropped, and the encoder stays valid.
+    InvalidTextureView(id::TextureViewId),
+    InvalidBindGroup(id::BindGroupId),
+    InvalidRenderPipeline(id::RenderPipelineId),
+    InvalidComputePipeline(id::ComputePipelineId),
+    /// A pipeline used by the pass is still being compiled, and the device
+    /// rejects pending pipelines. The pass is dropped, and the encoder stays valid.
+    RenderPipelineNotReady(id::RenderPipelineId),
+    ComputePipelineNotReady(id::ComputePipelineId),
+    /// A pipeline used by the pass failed to compile.
+    /// The pass is dropped, and the encoder stays valid.
+    RenderPipelineFailed(id::RenderPipelineId),
+    ComputePipelineFailed(id::ComputePipelineId),
+    /// A resource used by the command or the pass belongs to another device
+    /// than the encoder. The command or pass is rejected, and the encoder stays valid.
+    WrongDevice(WrongDeviceError),
+    /// A buffer is written while being used otherwise by the same draw or
+    /// dispatch, through its bind groups and index or vertex buffers, or by the
+    /// same render pass. The usages are given as debug strings.
+    /// The pass is dropped, and the encoder is invalidated.
+    ConflictingBufferUsage {
+        buffer: id::BufferId,
+        first: String,
+        second: String,
+    },
+    /// The descriptor sets or the inline uniform ring of the pass couldn't
+    /// be allocated. The pass is dropped, and the encoder stays valid.
+    Allocation(AllocationError),
+}
+
+impl From<WrongDeviceError> for CommandEncoderError {
+    fn from(error: WrongDeviceError) -> Self {
+        CommandEncoderError::WrongDevice(error)
+    }
+}
+
+impl From<AllocationError> for CommandEncoderError {
+    fn from(error: AllocationError) -> Self {
+        CommandEncoderError::Allocation(error)
+    }
+}
+
+impl From<PendingTransition<BufferState>> for CommandEncoderError {
+    fn from(conflict: PendingTransition<BufferState>) -> Self {
+        CommandEncoderError::ConflictingBufferUsage {
+            buffer: conflict.id,
+            first: format!("{:?}", conflict.usage.start),
+            second: format!("{:?}", conflict.usage.end),
+        }
+    }
+}
+
+/// Resources used by a finished command buffer, with the usages they are left in.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+pub struct CommandBufferUsageReport {
+    pub buffers: Vec<(id::BufferId, wgt::BufferUsage)>,
+    /// Textures with the usages of all their subresources combined.
+    pub textures: Vec<(id::TextureId, wgt::TextureUsage)>,
+    pub texture_views: Vec<id::TextureViewId>,
+    pub bind_groups: Vec<id::BindGroupId>,
+    pub samplers: Vec<id::SamplerId>,
+    pub compute_pipelines: Vec<id::ComputePipelineId>,
+    pub render_pipelines: Vec<id::RenderPipelineId>,
+    pub passes: Vec<PassReport>,
+}
+
 pub struct RenderBundle<B: hal::Backend> {
     _raw: B::CommandBuffer,
 }
@@ -154,18 +347,111 @@ pub struct RenderBundle<B: hal::Backend> {
 #[derive(Debug)]
 pub struct CommandBuffer<B: hal::Backend> {
     pub(crate) raw: Vec<B::CommandBuffer>,
-    is_recording: bool,
+    pub(crate) status: CommandEncoderStatus,
     recorded_thread_id: ThreadId,
     pub(crate) device_id: Stored<id::DeviceId>,
     pub(crate) trackers: TrackerSet,
-    pub(crate) used_swap_chain: Option<(Stored<id::SwapChainId>, B::Framebuffer)>,
+    pub(crate) used_swap_chain: Option<(Stored<id::SwapChainId>, FrameIndex, B::Framebuffer)>,
     limits: wgt::Limits,
+    downlevel: wgt::DownlevelCapabilities,
     private_features: PrivateFeatures,
+    /// Command counts of the passes, in recording order.
+    pub(crate) passes: Vec<PassReport>,
+    open_pass: Option<OpenPass>,
+    /// The first error that invalidated the encoder.
+    error: Option<CommandEncoderError>,
+    /// Rings of the render passes with inline uniform writes.
+    pub(crate) inline_uniforms: Vec<InlineUniformRing<B>>,
     #[cfg(feature = "trace")]
     pub(crate) commands: Option<Vec<crate::device::trace::Command>>,
 }
 
 impl<B: GfxBackend> CommandBuffer<B> {
+    /// Number of draws and dispatches recorded so far.
+    pub(crate) fn work_count(&self) -> usize {
+        self.passes
+            .iter()
+            .map(|pass| pass.draws + pass.dispatches)
+            .sum()
+    }
+
+    /// Count a copy, merging consecutive ones into a single transfer pass.
+    pub(crate) fn count_copy(&mut self) {
+        match self.passes.last_mut() {
+            Some(pass) if pass.kind == PassKind::Transfer => pass.copies += 1,
+            _ => self.passes.push(PassReport {
+                kind: PassKind::Transfer,
+                copies: 1,
+                ..PassReport::default()
+            }),
+        }
+    }
+
+    fn invalidate(&mut self, error: CommandEncoderError) {
+        log::error!("Command encoder is invalidated: {:?}", error);
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+        self.status = CommandEncoderStatus::Error;
+    }
+
+    /// Check that commands can be recorded on the encoder.
+    pub(crate) fn check_recording(&self) -> Result<(), CommandEncoderError> {
+        match self.status {
+            CommandEncoderStatus::Recording => Ok(()),
+            CommandEncoderStatus::Finished => Err(CommandEncoderError::Finished),
+            CommandEncoderStatus::Error => Err(CommandEncoderError::Invalid),
+        }
+    }
+
+    /// Mark a pass as open, until its commands are run.
+    pub(crate) fn begin_pass(&mut self, kind: PassKind, label: RawString) {
+        let label = if label.is_null() {
+            String::new()
+        } else {
+            unsafe { ffi::CStr::from_ptr(label) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        if let Some(open) = self.open_pass.replace(OpenPass { kind, label }) {
+            self.invalidate(CommandEncoderError::PassNotEnded {
+                kind: open.kind,
+                label: open.label,
+            });
+        }
+    }
+
+    /// End the open pass when the commands of a pass of `kind` are run.
+    ///
+    /// Passes encoded without being begun on the encoder are still allowed.
+    pub(crate) fn end_pass(&mut self, kind: PassKind) {
+        match self.open_pass.take() {
+            Some(open) if open.kind != kind => {
+                self.invalidate(CommandEncoderError::PassNotEnded {
+                    kind: open.kind,
+                    label: open.label,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Check that no pass is open before recording `command` on the encoder itself.
+    ///
+    /// Otherwise the encoder is invalidated, and the command has to be skipped.
+    pub(crate) fn check_no_open_pass(&mut self, command: &'static str) -> bool {
+        let error = match self.open_pass {
+            Some(ref open) => CommandEncoderError::EncoderLocked {
+                command,
+                pass_kind: open.kind,
+                pass_label: open.label.clone(),
+            },
+            None => return true,
+        };
+        self.invalidate(error);
+        false
+    }
+
     pub(crate) fn insert_barriers(
         raw: &mut B::CommandBuffer,
         base: &mut TrackerSet,
@@ -178,14 +464,29 @@ impl<B: GfxBackend> CommandBuffer<B> {
         debug_assert_eq!(B::VARIANT, base.backend());
         debug_assert_eq!(B::VARIANT, head.backend());
 
-        let buffer_barriers = base.buffers.merge_replace(&head.buffers).map(|pending| {
-            let buf = &buffer_guard[pending.id];
-            pending.into_hal(buf)
-        });
-        let texture_barriers = base.textures.merge_replace(&head.textures).map(|pending| {
-            let tex = &texture_guard[pending.id];
-            pending.into_hal(tex)
-        });
+        // Only wait for the stages that actually touch the resources in transition.
+        let mut src_stages = hal::pso::PipelineStage::empty();
+        let mut dst_stages = hal::pso::PipelineStage::empty();
+        let buffer_barriers = base
+            .buffers
+            .merge_replace(&head.buffers)
+            .map(|pending| {
+                src_stages |= conv::map_buffer_stages(pending.usage.start);
+                dst_stages |= conv::map_buffer_stages(pending.usage.end);
+                let buf = &buffer_guard[pending.id];
+                pending.into_hal(buf)
+            })
+            .collect::<Vec<_>>();
+        let texture_barriers = base
+            .textures
+            .merge_replace(&head.textures)
+            .map(|pending| {
+                src_stages |= conv::map_texture_stages(pending.usage.start);
+                dst_stages |= conv::map_texture_stages(pending.usage.end);
+                let tex = &texture_guard[pending.id];
+                pending.into_hal(tex)
+            })
+            .collect::<Vec<_>>();
         base.views.merge_extend(&head.views).unwrap();
         base.bind_groups.merge_extend(&head.bind_groups).unwrap();
         base.samplers.merge_extend(&head.samplers).unwrap();
@@ -194,17 +495,34 @@ impl<B: GfxBackend> CommandBuffer<B> {
             .unwrap();
         base.render_pipes.merge_extend(&head.render_pipes).unwrap();
 
-        let stages = all_buffer_stages() | all_image_stages();
+        if src_stages.is_empty() {
+            src_stages = hal::pso::PipelineStage::TOP_OF_PIPE;
+        }
+        if dst_stages.is_empty() {
+            dst_stages = hal::pso::PipelineStage::BOTTOM_OF_PIPE;
+        }
         unsafe {
             raw.pipeline_barrier(
-                stages..stages,
+                src_stages..dst_stages,
                 hal::memory::Dependencies::empty(),
-                buffer_barriers.chain(texture_barriers),
+                buffer_barriers.into_iter().chain(texture_barriers),
             );
         }
     }
 }
 
+/// Get the command buffer of an encoder that is recording.
+pub(crate) fn recording_encoder<B: GfxBackend>(
+    storage: &mut Storage<CommandBuffer<B>, id::CommandEncoderId>,
+    encoder_id: id::CommandEncoderId,
+) -> Result<&mut CommandBuffer<B>, CommandEncoderError> {
+    let cmb = storage
+        .get_mut(encoder_id)
+        .ok_or(CommandEncoderError::Consumed)?;
+    cmb.check_recording()?;
+    Ok(cmb)
+}
+
 #[repr(C)]
 #[derive(PeekPoke)]
 struct PassComponent<T> {
@@ -247,22 +565,85 @@ struct RawRenderTargets {
     depth_stencil: RawRenderPassDepthStencilAttachmentDescriptor,
 }
 
+/// Objects, other than pipelines, named by the commands of an encoded pass.
+#[derive(Debug, Default, PartialEq)]
+struct PassIds {
+    texture_views: Vec<id::TextureViewId>,
+    bind_groups: Vec<id::BindGroupId>,
+    buffers: Vec<id::BufferId>,
+}
+
+impl PassIds {
+    fn add<I: PartialEq>(ids: &mut Vec<I>, id: I) {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+}
+
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
+    /// Make sure the objects used by a pass exist, and belong to the device
+    /// of the encoder, before it is recorded.
+    fn check_pass_ids<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        ids: &PassIds,
+    ) -> Result<(), CommandEncoderError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (bind_group_guard, mut token) = hub.bind_groups.read(&mut token);
+        let (buffer_guard, mut token) = hub.buffers.read(&mut token);
+        let (_texture_guard, mut token) = hub.textures.read(&mut token);
+        let (view_guard, _) = hub.texture_views.read(&mut token);
+        for &id in ids.texture_views.iter() {
+            let view = view_guard
+                .get(id)
+                .ok_or(CommandEncoderError::InvalidTextureView(id))?;
+            check_device(device_id, id, view.device_id.value)?;
+        }
+        for &id in ids.bind_groups.iter() {
+            let bind_group = bind_group_guard
+                .get(id)
+                .ok_or(CommandEncoderError::InvalidBindGroup(id))?;
+            check_device(device_id, id, bind_group.device_id.value)?;
+        }
+        for &id in ids.buffers.iter() {
+            let buffer = buffer_guard
+                .get(id)
+                .ok_or(CommandEncoderError::InvalidBuffer(id))?;
+            check_device(device_id, id, buffer.device_id.value)?;
+        }
+        Ok(())
+    }
+
     pub fn command_encoder_finish<B: GfxBackend>(
         &self,
         encoder_id: id::CommandEncoderId,
         _desc: &wgt::CommandBufferDescriptor,
-    ) -> id::CommandBufferId {
+    ) -> Result<id::CommandBufferId, CommandEncoderError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (swap_chain_guard, mut token) = hub.swap_chains.read(&mut token);
         //TODO: actually close the last recorded command buffer
         let (mut comb_guard, _) = hub.command_buffers.write(&mut token);
-        let comb = &mut comb_guard[encoder_id];
-        assert!(comb.is_recording, "Command buffer must be recording");
-        comb.is_recording = false;
+        let comb = comb_guard
+            .get_mut(encoder_id)
+            .ok_or(CommandEncoderError::Consumed)?;
+        if comb.status == CommandEncoderStatus::Finished {
+            return Err(CommandEncoderError::Finished);
+        }
+        if let Some(open) = comb.open_pass.take() {
+            comb.invalidate(CommandEncoderError::PassNotEnded {
+                kind: open.kind,
+                label: open.label,
+            });
+        }
+        if let Some(ref error) = comb.error {
+            return Err(error.clone());
+        }
+        comb.status = CommandEncoderStatus::Finished;
         // stop tracking the swapchain image, if used
-        if let Some((ref sc_id, _)) = comb.used_swap_chain {
+        if let Some((ref sc_id, _, _)) = comb.used_swap_chain {
             let view_id = swap_chain_guard[sc_id.value]
                 .acquired_view_id
                 .as_ref()
@@ -270,6 +651,64 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             comb.trackers.views.remove(view_id.value);
         }
         log::debug!("Command buffer {:?} {:#?}", encoder_id, comb.trackers);
-        encoder_id
+        Ok(encoder_id)
+    }
+
+    /// Drop a pass without running it.
+    ///
+    /// The commands encoded so far are discarded, and the encoder is invalidated.
+    pub fn command_encoder_drop_pass<B: GfxBackend>(&self, mut pass: RawPass) {
+        let encoder_id = pass.parent;
+        drop(unsafe { pass.invalidate() });
+
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut comb_guard, _) = hub.command_buffers.write(&mut token);
+        let comb = match comb_guard.get_mut(encoder_id) {
+            Some(comb) => comb,
+            None => return,
+        };
+        if let Some(open) = comb.open_pass.take() {
+            comb.invalidate(CommandEncoderError::PassNotEnded {
+                kind: open.kind,
+                label: open.label,
+            });
+        }
+    }
+
+    pub fn command_buffer_get_usage_report<B: GfxBackend>(
+        &self,
+        command_buffer_id: id::CommandBufferId,
+    ) -> Result<CommandBufferUsageReport, CommandEncoderError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (comb_guard, _) = hub.command_buffers.read(&mut token);
+        let comb = comb_guard
+            .get(command_buffer_id)
+            .ok_or(CommandEncoderError::Consumed)?;
+        assert_eq!(
+            comb.status,
+            CommandEncoderStatus::Finished,
+            "Command buffer must be finished"
+        );
+        let trackers = &comb.trackers;
+        Ok(CommandBufferUsageReport {
+            buffers: trackers
+                .buffers
+                .used_states()
+                .map(|(id, state)| (id, state.last_usage().to_public()))
+                .collect(),
+            textures: trackers
+                .textures
+                .used_states()
+                .map(|(id, state)| (id, state.last_usage().to_public()))
+                .collect(),
+            texture_views: trackers.views.used().collect(),
+            bind_groups: trackers.bind_groups.used().collect(),
+            samplers: trackers.samplers.used().collect(),
+            compute_pipelines: trackers.compute_pipes.used().collect(),
+            render_pipelines: trackers.render_pipes.used().collect(),
+            passes: comb.passes.clone(),
+        })
     }
 }
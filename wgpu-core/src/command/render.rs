@@ -3,8 +3,10 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
+    binding_model::{BindGroup, PipelineLayout},
     command::{
-        bind::{Binder, LayoutChange},
+        bind::Binder,
+        inline::{suballocate, InlineBindings, InlineSetKey, InlineUniformRing},
         PassComponent, PhantomSlice, RawRenderPassColorAttachmentDescriptor,
         RawRenderPassDepthStencilAttachmentDescriptor, RawRenderTargets,
     },
@@ -12,12 +14,13 @@ use crate::{
     device::{
         FramebufferKey, RenderPassContext, RenderPassKey, MAX_COLOR_TARGETS, MAX_VERTEX_BUFFERS,
     },
-    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Token},
+    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Storage, Token},
     id,
     pipeline::PipelineFlags,
     resource::{BufferUse, TextureUse, TextureViewInner},
-    track::TrackerSet,
-    Stored,
+    swap_chain::FrameIndex,
+    track::{PendingTransition, TrackerSet},
+    RawString, Stored,
 };
 
 use arrayvec::ArrayVec;
@@ -26,11 +29,14 @@ use peek_poke::{Peek, PeekPoke, Poke};
 use smallvec::SmallVec;
 use wgt::{
     BufferAddress, BufferUsage, Color, DynamicOffset, IndexFormat, InputStepMode, LoadOp,
-    RenderPassColorAttachmentDescriptorBase, RenderPassDepthStencilAttachmentDescriptorBase,
-    TextureUsage, BIND_BUFFER_ALIGNMENT,
+    PrimitiveTopology, RenderPassColorAttachmentDescriptorBase,
+    RenderPassDepthStencilAttachmentDescriptorBase, StoreOp, TextureUsage,
 };
 
-use std::{borrow::Borrow, collections::hash_map::Entry, fmt, iter, mem, ops::Range, slice};
+use std::{
+    borrow::Borrow, collections::hash_map::Entry, fmt, iter, mem, ops::Range, slice,
+    sync::atomic::Ordering,
+};
 
 pub type RenderPassColorAttachmentDescriptor =
     RenderPassColorAttachmentDescriptorBase<id::TextureViewId>;
@@ -43,8 +49,12 @@ pub struct RenderPassDescriptor<'a> {
     pub color_attachments: *const RenderPassColorAttachmentDescriptor,
     pub color_attachments_length: usize,
     pub depth_stencil_attachment: Option<&'a RenderPassDepthStencilAttachmentDescriptor>,
+    pub label: RawString,
 }
 
+/// Maximum size in bytes of the data of an inline uniform write.
+pub const MAX_INLINE_UNIFORM_SIZE: usize = 256;
+
 #[derive(Clone, Copy, Debug, Default, PeekPoke)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
@@ -79,8 +89,18 @@ pub enum RenderCommand {
         offset: BufferAddress,
         size: BufferAddress,
     },
+    UnsetVertexBuffer {
+        slot: u32,
+    },
     SetBlendColor(Color),
     SetStencilReference(u32),
+    SetStencilReadMask(u32),
+    SetStencilWriteMask(u32),
+    SetDepthBias {
+        constant: i32,
+        slope_scale: f32,
+        clamp: f32,
+    },
     SetViewport {
         rect: Rect<f32>,
         //TODO: use half-float to reduce the size?
@@ -112,6 +132,20 @@ pub enum RenderCommand {
     DrawMeshTasks {
         tasks_count: u32,
     },
+    PushDebugGroup {
+        label_length: u32,
+        #[cfg_attr(any(feature = "trace", feature = "replay"), serde(skip))]
+        phantom_label: PhantomSlice<u8>,
+    },
+    PopDebugGroup,
+    SetUserTag(u32),
+    WriteInlineUniform {
+        index: u8,
+        binding: u32,
+        data_length: u32,
+        #[cfg_attr(any(feature = "trace", feature = "replay"), serde(skip))]
+        phantom_data: PhantomSlice<u8>,
+    },
     End,
 }
 
@@ -187,7 +221,13 @@ impl OptionalState {
 enum DrawError {
     MissingBlendColor,
     MissingStencilReference,
+    MissingStencilReadMask,
+    MissingStencilWriteMask,
+    MissingDepthBias,
     MissingPipeline,
+    MissingVertexBuffer {
+        slot: u32,
+    },
     IncompatibleBindGroup {
         index: u32,
         //expected: BindGroupLayoutId,
@@ -200,20 +240,213 @@ impl fmt::Debug for DrawError {
         match self {
             DrawError::MissingBlendColor => write!(f, "MissingBlendColor. A blend color is required to be set using RenderPass::set_blend_color."),
             DrawError::MissingStencilReference => write!(f, "MissingStencilReference. A stencil reference is required to be set using RenderPass::set_stencil_reference."),
+            DrawError::MissingStencilReadMask => write!(f, "MissingStencilReadMask. A stencil read mask is required to be set using RenderPass::set_stencil_read_mask."),
+            DrawError::MissingStencilWriteMask => write!(f, "MissingStencilWriteMask. A stencil write mask is required to be set using RenderPass::set_stencil_write_mask."),
+            DrawError::MissingDepthBias => write!(f, "MissingDepthBias. A depth bias is required to be set using RenderPass::set_depth_bias."),
             DrawError::MissingPipeline => write!(f, "MissingPipeline. You must first set the render pipeline using RenderPass::set_pipeline."),
+            DrawError::MissingVertexBuffer { slot } => write!(f, "MissingVertexBuffer. The current render pipeline requires a vertex buffer at slot {} to be set using RenderPass::set_vertex_buffer.", slot),
             DrawError::IncompatibleBindGroup { index } => write!(f, "IncompatibleBindGroup. The current render pipeline has a layout which is incompatible with a currently set bind group. They first differ at entry index {}.", index),
         }
     }
 }
 
+/// An attachment of a render pass, as reported by `AttachmentError`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AttachmentSlot {
+    Color(usize),
+    /// Resolve target of the color attachment with this index.
+    Resolve(usize),
+    DepthStencil,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttachmentError {
+    TooManyColorAttachments {
+        count: usize,
+        limit: u32,
+    },
+    ExtentMismatch {
+        first: AttachmentSlot,
+        first_extent: hal::image::Extent,
+        second: AttachmentSlot,
+        second_extent: hal::image::Extent,
+    },
+    SampleCountMismatch {
+        first: AttachmentSlot,
+        first_samples: hal::image::NumSamples,
+        second: AttachmentSlot,
+        second_samples: hal::image::NumSamples,
+    },
+    LayerCountMismatch {
+        first: AttachmentSlot,
+        first_layers: hal::image::Layer,
+        second: AttachmentSlot,
+        second_layers: hal::image::Layer,
+    },
+    /// The color attachment with this index has a resolve target, but isn't multisampled.
+    ResolveSourceNotMultisampled(usize),
+    /// The resolve target of the color attachment with this index is multisampled.
+    ResolveTargetMultisampled(usize),
+    /// The attachment views more than one array layer, which requires
+    /// `Capabilities::LAYERED_RENDERING`.
+    LayeredRenderingUnsupported {
+        slot: AttachmentSlot,
+        layers: hal::image::Layer,
+    },
+}
+
+/// Properties of an attachment view that have to be consistent across the pass.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct AttachmentDims {
+    extent: hal::image::Extent,
+    samples: hal::image::NumSamples,
+    layers: hal::image::Layer,
+}
+
+impl AttachmentDims {
+    fn new<B: hal::Backend>(view: &crate::resource::TextureView<B>) -> Self {
+        AttachmentDims {
+            extent: view.extent,
+            samples: view.samples,
+            layers: view.range.layers.end - view.range.layers.start,
+        }
+    }
+}
+
+/// Check that all the attachments of a pass describe the same render area.
+///
+/// Every attachment is compared to the first one, and the first mismatch is returned.
+/// Attachments can only have several layers if `layered_rendering` is supported.
+fn validate_attachments(
+    colors: &[(AttachmentDims, Option<AttachmentDims>)],
+    depth_stencil: Option<AttachmentDims>,
+    max_color_attachments: u32,
+    layered_rendering: bool,
+) -> Result<(), AttachmentError> {
+    if colors.len() > max_color_attachments as usize {
+        return Err(AttachmentError::TooManyColorAttachments {
+            count: colors.len(),
+            limit: max_color_attachments,
+        });
+    }
+
+    let mut first = None::<(AttachmentSlot, AttachmentDims)>;
+    let mut check = |slot: AttachmentSlot, dims: AttachmentDims, same_samples: bool| {
+        let (first_slot, first_dims) = match first {
+            Some(first) => first,
+            None => {
+                first = Some((slot, dims));
+                if dims.layers > 1 && !layered_rendering {
+                    return Err(AttachmentError::LayeredRenderingUnsupported {
+                        slot,
+                        layers: dims.layers,
+                    });
+                }
+                return Ok(());
+            }
+        };
+        if dims.extent != first_dims.extent {
+            Err(AttachmentError::ExtentMismatch {
+                first: first_slot,
+                first_extent: first_dims.extent,
+                second: slot,
+                second_extent: dims.extent,
+            })
+        } else if dims.layers != first_dims.layers {
+            Err(AttachmentError::LayerCountMismatch {
+                first: first_slot,
+                first_layers: first_dims.layers,
+                second: slot,
+                second_layers: dims.layers,
+            })
+        } else if same_samples && dims.samples != first_dims.samples {
+            Err(AttachmentError::SampleCountMismatch {
+                first: first_slot,
+                first_samples: first_dims.samples,
+                second: slot,
+                second_samples: dims.samples,
+            })
+        } else {
+            Ok(())
+        }
+    };
+
+    for (i, &(color, resolve)) in colors.iter().enumerate() {
+        check(AttachmentSlot::Color(i), color, true)?;
+        if let Some(resolve) = resolve {
+            if color.samples == 1 {
+                return Err(AttachmentError::ResolveSourceNotMultisampled(i));
+            }
+            if resolve.samples != 1 {
+                return Err(AttachmentError::ResolveTargetMultisampled(i));
+            }
+            check(AttachmentSlot::Resolve(i), resolve, false)?;
+        }
+    }
+    if let Some(depth_stencil) = depth_stencil {
+        check(AttachmentSlot::DepthStencil, depth_stencil, true)?;
+    }
+    Ok(())
+}
+
+/// Bytes of memory traffic that an attachment skips by not being loaded,
+/// or not being stored, at the end of a pass.
+fn skipped_attachment_bytes(
+    extent: hal::image::Extent,
+    samples: hal::image::NumSamples,
+    format_bits: u32,
+    loaded: bool,
+    stored: bool,
+) -> u64 {
+    let size = extent.width as u64
+        * extent.height as u64
+        * extent.depth as u64
+        * samples as u64
+        * format_bits as u64
+        / 8;
+    let skips = (!loaded) as u64 + (!stored) as u64;
+    size * skips
+}
+
+/// Transient attachments only live in tile memory, so a pass can
+/// neither load their contents nor store them.
+fn transient_ops_allowed(load_op: LoadOp, store_op: StoreOp) -> bool {
+    load_op != LoadOp::Load && store_op != StoreOp::Store
+}
+
+/// Number of triangles rasterized by a direct draw of `vertex_count` vertices or indices.
+///
+/// Primitive restart in indexed strips isn't accounted for.
+fn triangle_count(topology: PrimitiveTopology, vertex_count: u32, instance_count: u32) -> u64 {
+    let per_instance = match topology {
+        PrimitiveTopology::TriangleList => vertex_count / 3,
+        PrimitiveTopology::TriangleStrip => vertex_count.saturating_sub(2),
+        PrimitiveTopology::PointList
+        | PrimitiveTopology::LineList
+        | PrimitiveTopology::LineStrip => 0,
+    };
+    per_instance as u64 * instance_count as u64
+}
+
 #[derive(Debug)]
 pub struct IndexState {
     bound_buffer_view: Option<(id::BufferId, Range<BufferAddress>)>,
     format: IndexFormat,
+    strip_format: Option<IndexFormat>,
     limit: u32,
 }
 
 impl IndexState {
+    fn check_strip_format(&self) {
+        if let Some(strip_format) = self.strip_format {
+            assert_eq!(
+                strip_format, self.format,
+                "Pipeline strip index format {:?} doesn't match the index format {:?}",
+                strip_format, self.format
+            );
+        }
+    }
+
     fn update_limit(&mut self) {
         self.limit = match self.bound_buffer_view {
             Some((_, ref range)) => {
@@ -221,11 +454,18 @@ impl IndexState {
                     IndexFormat::Uint16 => 1,
                     IndexFormat::Uint32 => 2,
                 };
-                ((range.end - range.start) >> shift) as u32
+                // Indices are 32-bit at most, so larger buffers can't be used up.
+                ((range.end - range.start) >> shift).min(u32::MAX as BufferAddress) as u32
             }
             None => 0,
         }
     }
+
+    /// Check if the indices `first_index .. first_index + index_count`
+    /// go past the end of the bound index buffer range.
+    fn is_overrun(&self, first_index: u32, index_count: u32) -> bool {
+        first_index as u64 + index_count as u64 > self.limit as u64
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -233,6 +473,11 @@ pub struct VertexBufferState {
     total_size: BufferAddress,
     stride: BufferAddress,
     rate: InputStepMode,
+    step_rate: u32,
+    /// Whether the current pipeline has a vertex buffer in this slot.
+    required: bool,
+    /// The buffer bound to this slot, if any.
+    buffer: Option<id::BufferId>,
 }
 
 impl VertexBufferState {
@@ -240,6 +485,9 @@ impl VertexBufferState {
         total_size: 0,
         stride: 0,
         rate: InputStepMode::Vertex,
+        step_rate: 1,
+        required: false,
+        buffer: None,
     };
 }
 
@@ -247,44 +495,58 @@ impl VertexBufferState {
 pub struct VertexState {
     inputs: SmallVec<[VertexBufferState; MAX_VERTEX_BUFFERS]>,
     vertex_limit: u32,
-    instance_limit: u32,
 }
 
 impl VertexState {
     fn update_limits(&mut self) {
         self.vertex_limit = !0;
-        self.instance_limit = !0;
         for vbs in &self.inputs {
-            if vbs.stride == 0 {
+            if vbs.stride == 0 || vbs.rate != InputStepMode::Vertex {
                 continue;
             }
-            let limit = (vbs.total_size / vbs.stride) as u32;
-            match vbs.rate {
-                InputStepMode::Vertex => self.vertex_limit = self.vertex_limit.min(limit),
-                InputStepMode::Instance => self.instance_limit = self.instance_limit.min(limit),
-            }
+            let limit = (vbs.total_size / vbs.stride).min(u32::MAX as BufferAddress) as u32;
+            self.vertex_limit = self.vertex_limit.min(limit);
         }
     }
+
+    /// Find the slot of a per-instance buffer that is too small for the instances
+    /// of a draw. Instances advance through a buffer by its step rate,
+    /// starting from `first_instance`.
+    fn instance_overrun(&self, first_instance: u32, instance_count: u32) -> Option<usize> {
+        self.inputs.iter().position(|vbs| {
+            if vbs.stride == 0 || vbs.rate != InputStepMode::Instance {
+                return false;
+            }
+            let step_rate = BufferAddress::from(vbs.step_rate);
+            let elements = BufferAddress::from(first_instance)
+                + (BufferAddress::from(instance_count) + step_rate - 1) / step_rate;
+            elements > vbs.total_size / vbs.stride
+        })
+    }
 }
 
 #[derive(Debug)]
 struct State {
     binder: Binder,
+    inline: InlineBindings,
     blend_color: OptionalState,
     stencil_reference: OptionalState,
+    stencil_read_mask: OptionalState,
+    stencil_write_mask: OptionalState,
+    depth_bias: OptionalState,
     pipeline: OptionalState,
     index: IndexState,
     vertex: VertexState,
 }
 
 impl State {
-    fn is_ready(&self) -> Result<(), DrawError> {
+    fn is_ready(&mut self) -> Result<(), DrawError> {
         //TODO: vertex buffers
-        let bind_mask = self.binder.invalid_mask();
-        if bind_mask != 0 {
+        let valid_count = self.binder.valid_count();
+        if valid_count != self.binder.entries.len() {
             //let (expected, provided) = self.binder.entries[index as usize].info();
             return Err(DrawError::IncompatibleBindGroup {
-                index: bind_mask.trailing_zeros(),
+                index: valid_count as u32,
             });
         }
         if self.pipeline == OptionalState::Required {
@@ -296,19 +558,244 @@ impl State {
         if self.stencil_reference == OptionalState::Required {
             return Err(DrawError::MissingStencilReference);
         }
+        if self.stencil_read_mask == OptionalState::Required {
+            return Err(DrawError::MissingStencilReadMask);
+        }
+        if self.stencil_write_mask == OptionalState::Required {
+            return Err(DrawError::MissingStencilWriteMask);
+        }
+        if self.depth_bias == OptionalState::Required {
+            return Err(DrawError::MissingDepthBias);
+        }
+        if let Some(slot) = self
+            .vertex
+            .inputs
+            .iter()
+            .position(|vbs| vbs.required && vbs.buffer.is_none())
+        {
+            return Err(DrawError::MissingVertexBuffer { slot: slot as u32 });
+        }
         Ok(())
     }
 }
 
+/// Find the pipelines, and the bind groups set with non-zero dynamic offsets, used by a pass,
+/// along with the bind groups and bindings of its inline uniform writes, in recording order.
+fn used_resources(
+    raw_data: &[u8],
+) -> (
+    Vec<id::RenderPipelineId>,
+    Vec<(id::BindGroupId, Vec<DynamicOffset>)>,
+    Vec<(InlineSetKey, u32)>,
+    super::PassIds,
+) {
+    let mut pipelines = Vec::new();
+    let mut bind_groups = Vec::new();
+    let mut inline_writes = Vec::new();
+    let mut ids = super::PassIds::default();
+    let mut set_bind_groups = Vec::<Option<(id::BindGroupId, Vec<DynamicOffset>)>>::new();
+    let mut inline_bindings = InlineBindings::default();
+    let mut peeker = raw_data.as_ptr();
+    let raw_data_end = unsafe { raw_data.as_ptr().add(raw_data.len()) };
+
+    // Malformed data is reported by the pass itself.
+    if unsafe { peeker.add(RawRenderTargets::max_size()) } > raw_data_end {
+        return (pipelines, bind_groups, inline_writes, ids);
+    }
+    let mut targets: RawRenderTargets = unsafe { mem::zeroed() };
+    peeker = unsafe { RawRenderTargets::peek_from(peeker, &mut targets) };
+    for at in targets.colors.iter().take_while(|at| at.attachment != 0) {
+        let views = [at.attachment, at.resolve_target];
+        for view_id in views
+            .iter()
+            .filter_map(|&raw| id::TextureViewId::from_raw(raw))
+        {
+            super::PassIds::add(&mut ids.texture_views, view_id);
+        }
+    }
+    if let Some(view_id) = id::TextureViewId::from_raw(targets.depth_stencil.attachment) {
+        super::PassIds::add(&mut ids.texture_views, view_id);
+    }
+
+    let mut command = RenderCommand::End;
+    while unsafe { peeker.add(RenderCommand::max_size()) } <= raw_data_end {
+        peeker = unsafe { RenderCommand::peek_from(peeker, &mut command) };
+        match command {
+            RenderCommand::SetBindGroup {
+                index,
+                num_dynamic_offsets,
+                bind_group_id,
+                phantom_offsets,
+            } => {
+                let (new_peeker, offsets) = unsafe {
+                    phantom_offsets.decode_unaligned(
+                        peeker,
+                        num_dynamic_offsets as usize,
+                        raw_data_end,
+                    )
+                };
+                peeker = new_peeker;
+                super::PassIds::add(&mut ids.bind_groups, bind_group_id);
+                if offsets.iter().any(|&offset| offset != 0) {
+                    let entry = (bind_group_id, offsets.to_vec());
+                    if !bind_groups.contains(&entry) {
+                        bind_groups.push(entry);
+                    }
+                }
+                let index = index as usize;
+                if set_bind_groups.len() <= index {
+                    set_bind_groups.resize(index + 1, None);
+                }
+                set_bind_groups[index] = Some((bind_group_id, offsets.to_vec()));
+                inline_bindings.reset(index);
+            }
+            RenderCommand::SetIndexBuffer { buffer_id, .. }
+            | RenderCommand::SetVertexBuffer { buffer_id, .. }
+            | RenderCommand::DrawIndirect { buffer_id, .. }
+            | RenderCommand::DrawIndexedIndirect { buffer_id, .. } => {
+                super::PassIds::add(&mut ids.buffers, buffer_id);
+            }
+            RenderCommand::SetPipeline(pipeline_id)
+            | RenderCommand::SetMeshPipeline(pipeline_id) => {
+                if !pipelines.contains(&pipeline_id) {
+                    pipelines.push(pipeline_id);
+                }
+            }
+            RenderCommand::PushDebugGroup {
+                label_length,
+                phantom_label,
+            } => {
+                peeker =
+                    unsafe { phantom_label.decode_label(peeker, label_length, raw_data_end) }.0;
+            }
+            RenderCommand::WriteInlineUniform {
+                index,
+                binding,
+                data_length,
+                phantom_data,
+            } => {
+                peeker = unsafe {
+                    phantom_data.decode_unaligned(peeker, data_length as usize, raw_data_end)
+                }
+                .0;
+                let index = index as usize;
+                if let Some(&Some((bind_group_id, ref offsets))) = set_bind_groups.get(index) {
+                    let key = inline_bindings.redirect(index, bind_group_id, offsets, binding);
+                    inline_writes.push((key.clone(), binding));
+                }
+            }
+            RenderCommand::End => break,
+            _ => {}
+        }
+    }
+    (pipelines, bind_groups, inline_writes, ids)
+}
+
+/// The index and vertex buffers bound for the next draw, with their usages.
+fn bound_buffers<'a>(
+    index: &'a IndexState,
+    vertex: &'a VertexState,
+) -> impl Iterator<Item = (id::BufferId, BufferUse)> + 'a {
+    let index_buffer = index
+        .bound_buffer_view
+        .as_ref()
+        .map(|&(id, _)| (id, BufferUse::INDEX));
+    let vertex_buffers = vertex
+        .inputs
+        .iter()
+        .filter_map(|vbs| Some((vbs.buffer?, BufferUse::VERTEX)));
+    index_buffer.into_iter().chain(vertex_buffers)
+}
+
+/// Bind the bind groups that changed since the last draw, or that the
+/// pipeline layout made incompatible, once their buffer usages are checked
+/// against each other and the `bound` index and vertex buffers.
+///
+/// The bind groups with bindings redirected by inline uniform writes
+/// are bound with their copy from the ring.
+fn flush_bind_groups<B: GfxBackend>(
+    raw: &mut B::CommandBuffer,
+    binder: &mut Binder,
+    inline_bindings: &InlineBindings,
+    inline_ring: Option<&InlineUniformRing<B>>,
+    bind_group_guard: &Storage<BindGroup<B>, id::BindGroupId>,
+    pipeline_layout_guard: &Storage<PipelineLayout<B>, id::PipelineLayoutId>,
+    bound: impl Iterator<Item = (id::BufferId, BufferUse)>,
+) -> Result<(), super::CommandEncoderError> {
+    binder.check_buffer_usage(|id| &bind_group_guard[id].used.buffers, bound)?;
+    if let Some((pipeline_layout_id, range)) = binder.flush() {
+        let start = range.start;
+        let groups = binder.groups(range);
+        unsafe {
+            raw.bind_graphics_descriptor_sets(
+                &pipeline_layout_guard[pipeline_layout_id].raw,
+                start,
+                groups.clone().enumerate().map(|(i, (bg_id, offsets))| {
+                    match (inline_bindings.key(start + i), inline_ring) {
+                        (Some(key), Some(ring)) => ring.sets[key].raw(),
+                        _ => bind_group_guard[bg_id].raw_for_offsets(offsets),
+                    }
+                }),
+                groups.flat_map(|(_, offsets)| offsets).cloned(),
+            );
+        }
+    }
+    Ok(())
+}
+
 // Common routines between render/compute
 
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
+    /// Begin a render pass, which stays open on the encoder until
+    /// it's run by `command_encoder_run_render_pass`, or dropped.
+    pub fn command_encoder_begin_render_pass<B: GfxBackend>(
+        &self,
+        encoder_id: id::CommandEncoderId,
+        desc: &RenderPassDescriptor,
+    ) -> super::RawPass {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+        // The pass is still returned, and running it reports the error.
+        match super::recording_encoder(&mut *cmb_guard, encoder_id) {
+            Ok(cmb) => cmb.begin_pass(super::PassKind::Render, desc.label),
+            Err(e) => log::error!("Unable to begin a render pass: {:?}", e),
+        }
+        unsafe { super::RawPass::new_render(encoder_id, desc) }
+    }
+
     pub fn command_encoder_run_render_pass<B: GfxBackend>(
         &self,
         encoder_id: id::CommandEncoderId,
         raw_data: &[u8],
-    ) {
+    ) -> Result<(), super::CommandEncoderError> {
         let hub = B::hub(self);
+        let device_id = {
+            let mut token = Token::root();
+            let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+            super::recording_encoder(&mut *cmb_guard, encoder_id)?
+                .device_id
+                .value
+        };
+        let (used_pipelines, used_bind_groups, inline_writes, used_ids) = used_resources(raw_data);
+        let prepared = self
+            .check_pass_ids::<B>(device_id, &used_ids)
+            .and_then(|()| self.wait_for_pipelines::<B>(device_id, &used_pipelines, &[]))
+            .and_then(|()| {
+                self.prepare_derived_bind_groups::<B>(&used_bind_groups)?;
+                self.prepare_inline_uniforms::<B>(device_id, &inline_writes)
+                    .map_err(super::CommandEncoderError::from)
+            });
+        let mut inline_ring = match prepared {
+            Ok(inline_ring) => inline_ring,
+            Err(e) => {
+                let mut token = Token::root();
+                let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+                cmb_guard[encoder_id].end_pass(super::PassKind::Render);
+                return Err(e);
+            }
+        };
+
         let mut token = Token::root();
 
         let (adapter_guard, mut token) = hub.adapters.read(&mut token);
@@ -317,7 +804,11 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let mut trackers = TrackerSet::new(B::VARIANT);
         let cmb = &mut cmb_guard[encoder_id];
-        let device = &device_guard[cmb.device_id.value];
+        cmb.end_pass(super::PassKind::Render);
+        let first_work_index = cmb.work_count();
+        let downlevel = cmb.downlevel;
+        let device = &device_guard[device_id];
+        let validation = self.instance.validation && !device.extensions.trusted_content;
         let mut raw = device.com_allocator.extend(cmb);
 
         unsafe {
@@ -373,6 +864,38 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             };
             Some(&depth_stencil_attachment_body)
         };
+        for at in color_attachments.iter() {
+            device.check_presented_view(at.attachment);
+            if let Some(resolve_target) = at.resolve_target {
+                device.check_presented_view(resolve_target);
+            }
+        }
+        if let Some(at) = depth_stencil_attachment {
+            device.check_presented_view(at.attachment);
+        }
+        let attachment_layers = {
+            let dims = |view_id| AttachmentDims::new(&view_guard[view_id]);
+            let colors = color_attachments
+                .iter()
+                .map(|at| (dims(at.attachment), at.resolve_target.map(dims)))
+                .collect::<ArrayVec<[_; MAX_COLOR_TARGETS]>>();
+            let depth_stencil = depth_stencil_attachment.map(|at| dims(at.attachment));
+            if let Err(e) = validate_attachments(
+                &colors,
+                depth_stencil,
+                cmb.limits.max_color_attachments,
+                device
+                    .capabilities
+                    .contains(wgt::Capabilities::LAYERED_RENDERING),
+            ) {
+                panic!("Invalid render pass attachments: {:?}", e);
+            }
+            colors
+                .first()
+                .map(|&(color, _)| color)
+                .or(depth_stencil)
+                .map_or(1, |dims| dims.layers)
+        };
 
         let (context, sample_count) = {
             use hal::{adapter::PhysicalDevice as _, device::Device as _};
@@ -385,7 +908,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             let base_trackers = &cmb.trackers;
 
             let mut extent = None;
-            let mut used_swap_chain = None::<Stored<id::SwapChainId>>;
+            let mut used_swap_chain = None::<(Stored<id::SwapChainId>, FrameIndex)>;
 
             let sample_count = color_attachments
                 .get(0)
@@ -404,6 +927,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             );
             let mut output_attachments =
                 ArrayVec::<[OutputAttachment; MAX_TOTAL_ATTACHMENTS]>::new();
+            let mut skipped_bytes = 0;
 
             log::trace!(
                 "Encoding render pass begin in command buffer {:?}",
@@ -416,17 +940,50 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             .views
                             .use_extend(&*view_guard, at.attachment, (), ())
                             .unwrap();
-                        if let Some(ex) = extent {
-                            assert_eq!(ex, view.extent, "Extent state must match extent from view");
-                        } else {
-                            extent = Some(view.extent);
-                        }
+                        extent = Some(view.extent);
+                        assert_eq!(
+                            view.range.levels.end - view.range.levels.start,
+                            1,
+                            "Depth stencil attachment {:?} must have a single mip level",
+                            at.attachment
+                        );
+                        assert!(
+                            view.usage.contains(TextureUsage::OUTPUT_ATTACHMENT),
+                            "Depth stencil attachment {:?} usage {:?} must contain the usage flag OUTPUT_ATTACHMENT",
+                            at.attachment,
+                            view.usage
+                        );
+                        // Framebuffers of the backends need views of all the aspects.
+                        let format_desc =
+                            conv::map_texture_format(view.format, device.private_features)
+                                .surface_desc();
+                        assert_eq!(
+                            view.range.aspects,
+                            format_desc.aspects,
+                            "Depth stencil attachment {:?} of format {:?} must view all of its aspects",
+                            at.attachment,
+                            view.format
+                        );
                         let source_id = match view.inner {
                             TextureViewInner::Native { ref source_id, .. } => source_id,
                             TextureViewInner::SwapChain { .. } => {
                                 panic!("Unexpected depth/stencil use of swapchain image!")
                             }
                         };
+                        if texture_guard[source_id.value]
+                            .usage
+                            .contains(TextureUsage::TRANSIENT_ATTACHMENT)
+                        {
+                            assert!(
+                                transient_ops_allowed(at.depth_load_op, at.depth_store_op)
+                                    && transient_ops_allowed(
+                                        at.stencil_load_op,
+                                        at.stencil_store_op
+                                    ),
+                                "Transient depth stencil attachment {:?} can't be loaded or stored",
+                                at.attachment
+                            );
+                        }
 
                         // Using render pass for transition.
                         let consistent_use = base_trackers
@@ -444,6 +1001,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             }
                             None => hal::image::Layout::DepthStencilAttachmentOptimal,
                         };
+                        skipped_bytes += skipped_attachment_bytes(
+                            view.extent,
+                            view.samples,
+                            format_desc.bits as u32,
+                            at.depth_load_op == LoadOp::Load || at.stencil_load_op == LoadOp::Load,
+                            at.depth_store_op == StoreOp::Store
+                                || at.stencil_store_op == StoreOp::Store,
+                        );
 
                         Some(hal::pass::Attachment {
                             format: Some(conv::map_texture_format(
@@ -470,18 +1035,32 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         .views
                         .use_extend(&*view_guard, at.attachment, (), ())
                         .unwrap();
-                    if let Some(ex) = extent {
-                        assert_eq!(ex, view.extent, "Extent state must match extent from view");
-                    } else {
-                        extent = Some(view.extent);
-                    }
+                    extent = Some(view.extent);
                     assert_eq!(
-                        view.samples, sample_count,
-                        "All attachments must have the same sample_count"
+                        view.range.levels.end - view.range.levels.start,
+                        1,
+                        "Color attachment {:?} must have a single mip level",
+                        at.attachment
+                    );
+                    assert!(
+                        view.usage.contains(TextureUsage::OUTPUT_ATTACHMENT),
+                        "Color attachment {:?} usage {:?} must contain the usage flag OUTPUT_ATTACHMENT",
+                        at.attachment,
+                        view.usage
                     );
 
                     let layouts = match view.inner {
                         TextureViewInner::Native { ref source_id, .. } => {
+                            if texture_guard[source_id.value]
+                                .usage
+                                .contains(TextureUsage::TRANSIENT_ATTACHMENT)
+                            {
+                                assert!(
+                                    transient_ops_allowed(at.load_op, at.store_op),
+                                    "Transient color attachment {:?} can't be loaded or stored",
+                                    at.attachment
+                                );
+                            }
                             let consistent_use = base_trackers
                                 .textures
                                 .query(source_id.value, view.range.clone());
@@ -495,25 +1074,38 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             };
                             old_layout..hal::image::Layout::ColorAttachmentOptimal
                         }
-                        TextureViewInner::SwapChain { ref source_id, .. } => {
-                            if let Some((ref sc_id, _)) = cmb.used_swap_chain {
+                        TextureViewInner::SwapChain {
+                            ref source_id,
+                            frame,
+                            ..
+                        } => {
+                            if let Some((ref sc_id, _, _)) = cmb.used_swap_chain {
                                 assert_eq!(
                                     source_id.value, sc_id.value,
                                     "Texture view's swap chain must match swap chain in use"
                                 );
                             } else {
                                 assert!(used_swap_chain.is_none());
-                                used_swap_chain = Some(source_id.clone());
+                                used_swap_chain = Some((source_id.clone(), frame));
                             }
 
                             let end = hal::image::Layout::Present;
                             let start = match at.load_op {
-                                LoadOp::Clear => hal::image::Layout::Undefined,
+                                LoadOp::Clear | LoadOp::DontCare => hal::image::Layout::Undefined,
                                 LoadOp::Load => end,
                             };
                             start..end
                         }
                     };
+                    skipped_bytes += skipped_attachment_bytes(
+                        view.extent,
+                        view.samples,
+                        conv::map_texture_format(view.format, device.private_features)
+                            .surface_desc()
+                            .bits as u32,
+                        at.load_op == LoadOp::Load,
+                        at.store_op == StoreOp::Store,
+                    );
 
                     colors.push(hal::pass::Attachment {
                         format: Some(conv::map_texture_format(
@@ -533,17 +1125,27 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         .use_extend(&*view_guard, resolve_target, (), ())
                         .unwrap();
                     assert_eq!(
-                        extent,
-                        Some(view.extent),
-                        "Extent state must match extent from view"
+                        view.range.levels.end - view.range.levels.start,
+                        1,
+                        "Resolve target {:?} must have a single mip level",
+                        resolve_target
                     );
-                    assert_eq!(
-                        view.samples, 1,
-                        "All resolve_targets must have a sample_count of 1"
+                    assert!(
+                        view.usage.contains(TextureUsage::OUTPUT_ATTACHMENT),
+                        "Resolve target {:?} usage {:?} must contain the usage flag OUTPUT_ATTACHMENT",
+                        resolve_target,
+                        view.usage
                     );
 
                     let layouts = match view.inner {
                         TextureViewInner::Native { ref source_id, .. } => {
+                            assert!(
+                                !texture_guard[source_id.value]
+                                    .usage
+                                    .contains(TextureUsage::TRANSIENT_ATTACHMENT),
+                                "Resolve target {:?} can't be a transient attachment, since it's stored",
+                                resolve_target
+                            );
                             let consistent_use = base_trackers
                                 .textures
                                 .query(source_id.value, view.range.clone());
@@ -557,15 +1159,19 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             };
                             old_layout..hal::image::Layout::ColorAttachmentOptimal
                         }
-                        TextureViewInner::SwapChain { ref source_id, .. } => {
-                            if let Some((ref sc_id, _)) = cmb.used_swap_chain {
+                        TextureViewInner::SwapChain {
+                            ref source_id,
+                            frame,
+                            ..
+                        } => {
+                            if let Some((ref sc_id, _, _)) = cmb.used_swap_chain {
                                 assert_eq!(
                                     source_id.value, sc_id.value,
                                     "Texture view's swap chain must match swap chain in use"
                                 );
                             } else {
                                 assert!(used_swap_chain.is_none());
-                                used_swap_chain = Some(source_id.clone());
+                                used_swap_chain = Some((source_id.clone(), frame));
                             }
                             hal::image::Layout::Undefined..hal::image::Layout::Present
                         }
@@ -593,14 +1199,11 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 }
             };
 
-            for (source_id, view_range, consistent_use) in output_attachments {
-                let texture = &texture_guard[source_id.value];
-                assert!(
-                    texture.usage.contains(TextureUsage::OUTPUT_ATTACHMENT),
-                    "Texture usage {:?} must contain the usage flag OUTPUT_ATTACHMENT",
-                    texture.usage
-                );
+            device
+                .skipped_attachment_bytes
+                .fetch_add(skipped_bytes, Ordering::Relaxed);
 
+            for (source_id, view_range, consistent_use) in output_attachments {
                 let usage = consistent_use.unwrap_or(TextureUse::OUTPUT_ATTACHMENT);
                 // this is important to record the `first` state.
                 let _ = trackers.textures.change_replace(
@@ -694,8 +1297,13 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 depth_stencil: depth_stencil_attachment.map(|at| at.attachment),
             };
 
+            // The framebuffer covers all the attached layers, selected by the shaders.
+            let fb_extent = hal::image::Extent {
+                depth: attachment_layers as u32,
+                ..extent.unwrap()
+            };
             let framebuffer = match used_swap_chain.take() {
-                Some(sc_id) => {
+                Some((sc_id, frame)) => {
                     assert!(cmb.used_swap_chain.is_none());
                     // Always create a new framebuffer and delete it after presentation.
                     let attachments = fb_key.all().map(|&id| match view_guard[id].inner {
@@ -705,11 +1313,11 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     let framebuffer = unsafe {
                         device
                             .raw
-                            .create_framebuffer(&render_pass, attachments, extent.unwrap())
+                            .create_framebuffer(&render_pass, attachments, fb_extent)
                             .unwrap()
                     };
-                    cmb.used_swap_chain = Some((sc_id, framebuffer));
-                    &mut cmb.used_swap_chain.as_mut().unwrap().1
+                    cmb.used_swap_chain = Some((sc_id, frame, framebuffer));
+                    &mut cmb.used_swap_chain.as_mut().unwrap().2
                 }
                 None => {
                     // Cache framebuffers by the device.
@@ -729,7 +1337,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                                     device.raw.create_framebuffer(
                                         &render_pass,
                                         attachments,
-                                        extent.unwrap(),
+                                        fb_extent,
                                     )
                                 }
                                 .unwrap()
@@ -755,7 +1363,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 .zip(&rp_key.colors)
                 .flat_map(|(at, key)| {
                     match at.load_op {
-                        LoadOp::Load => None,
+                        LoadOp::Load | LoadOp::DontCare => None,
                         LoadOp::Clear => {
                             use hal::format::ChannelType;
                             //TODO: validate sign/unsign and normalized ranges of the color values
@@ -782,7 +1390,6 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 })
                 .chain(depth_stencil_attachment.and_then(|at| {
                     match (at.depth_load_op, at.stencil_load_op) {
-                        (LoadOp::Load, LoadOp::Load) => None,
                         (LoadOp::Clear, _) | (_, LoadOp::Clear) => {
                             let value = hal::command::ClearDepthStencil {
                                 depth: at.clear_depth,
@@ -792,6 +1399,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                                 depth_stencil: value,
                             })
                         }
+                        _ => None,
                     }
                 }));
 
@@ -829,20 +1437,26 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             (context, sample_count)
         };
 
+        let max_vertex_buffers = cmb.limits.max_vertex_buffers;
+        let max_bind_groups = cmb.limits.max_bind_groups;
         let mut state = State {
             binder: Binder::new(cmb.limits.max_bind_groups),
+            inline: InlineBindings::default(),
             blend_color: OptionalState::Unused,
             stencil_reference: OptionalState::Unused,
+            stencil_read_mask: OptionalState::Unused,
+            stencil_write_mask: OptionalState::Unused,
+            depth_bias: OptionalState::Unused,
             pipeline: OptionalState::Required,
             index: IndexState {
                 bound_buffer_view: None,
                 format: IndexFormat::Uint16,
+                strip_format: None,
                 limit: 0,
             },
             vertex: VertexState {
                 inputs: SmallVec::new(),
                 vertex_limit: 0,
-                instance_limit: 0,
             },
         };
 
@@ -852,6 +1466,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             first_vertex: 0,
             first_instance: 0,
         };
+        let mut draws = 0;
+        let mut triangles = 0;
+        let mut pipeline_switches = 0;
+        let mut bind_group_switches = 0;
+        let mut topology = PrimitiveTopology::PointList;
+        let mut command_context = super::PassCommandContext::default();
+        let mut inline_cursor = 0;
+        let mut pass_error = None;
 
         loop {
             assert!(
@@ -861,6 +1483,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 raw_data.len()
             );
             peeker = unsafe { RenderCommand::peek_from(peeker, &mut command) };
+            command_context.index = first_work_index + draws;
             match command {
                 RenderCommand::SetBindGroup {
                     index,
@@ -876,58 +1499,48 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         )
                     };
                     peeker = new_peeker;
+                    bind_group_switches += 1;
+                    assert!(
+                        u32::from(index) < max_bind_groups,
+                        "Bind group index {} is out of the max_bind_groups limit {}",
+                        index,
+                        max_bind_groups
+                    );
 
-                    if cfg!(debug_assertions) {
-                        for off in offsets {
-                            assert_eq!(
-                                *off as BufferAddress % BIND_BUFFER_ALIGNMENT,
-                                0,
-                                "Misaligned dynamic buffer offset: {} does not align with {}",
-                                off,
-                                BIND_BUFFER_ALIGNMENT
-                            );
-                        }
-                    }
-
-                    let bind_group = trackers
-                        .bind_groups
-                        .use_extend(&*bind_group_guard, bind_group_id, (), ())
-                        .unwrap();
-
-                    assert_eq!(bind_group.dynamic_count, offsets.len());
+                    let bind_group = &bind_group_guard[bind_group_id];
+                    bind_group.validate_members(bind_group_id);
 
-                    trackers.merge_extend(&bind_group.used);
+                    bind_group.validate_dynamic_offsets(offsets);
 
-                    if let Some((pipeline_layout_id, follow_ups)) = state.binder.provide_entry(
-                        index as usize,
-                        bind_group_id,
-                        bind_group,
-                        offsets,
-                    ) {
-                        let bind_groups = iter::once(bind_group.raw.raw()).chain(
-                            follow_ups
-                                .clone()
-                                .map(|(bg_id, _)| bind_group_guard[bg_id].raw.raw()),
-                        );
-                        unsafe {
-                            raw.bind_graphics_descriptor_sets(
-                                &&pipeline_layout_guard[pipeline_layout_id].raw,
-                                index as usize,
-                                bind_groups,
-                                offsets
-                                    .iter()
-                                    .chain(follow_ups.flat_map(|(_, offsets)| offsets))
-                                    .cloned(),
-                            );
+                    // Re-binding a group within the same pass doesn't change
+                    // any usages, so only merge it the first time around.
+                    if bind_group.scope_stamp.enter(trackers.scope()) {
+                        trackers
+                            .bind_groups
+                            .use_extend(&*bind_group_guard, bind_group_id, (), ())
+                            .unwrap();
+                        if let Err(conflict) = trackers.merge_extend(&bind_group.used) {
+                            pass_error = Some(conflict.into());
+                            break;
                         }
-                    };
+                    }
+
+                    state
+                        .binder
+                        .provide_entry(index as usize, bind_group_id, bind_group, offsets);
+                    // The copy from the ring may have been bound with the same offsets.
+                    if state.inline.reset(index as usize) {
+                        state.binder.invalidate(index as usize);
+                    }
                 }
                 RenderCommand::SetPipeline(pipeline_id) => {
                     state.pipeline = OptionalState::Set;
+                    pipeline_switches += 1;
                     let pipeline = trackers
                         .render_pipes
                         .use_extend(&*pipeline_guard, pipeline_id, (), ())
                         .unwrap();
+                    topology = pipeline.topology;
 
                     assert!(
                         context.compatible(&pipeline.pass_context),
@@ -944,46 +1557,31 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     state
                         .stencil_reference
                         .require(pipeline.flags.contains(PipelineFlags::STENCIL_REFERENCE));
+                    // A static depth bias overrides the one set by the pass.
+                    if pipeline.flags.contains(PipelineFlags::DEPTH_BIAS) {
+                        state.depth_bias.require(true);
+                    } else {
+                        state.depth_bias = OptionalState::Unused;
+                    }
+                    // Static stencil masks override the ones set by the pass as well.
+                    if pipeline.flags.contains(PipelineFlags::STENCIL_MASKS) {
+                        state.stencil_read_mask.require(true);
+                        state.stencil_write_mask.require(true);
+                    } else {
+                        state.stencil_read_mask = OptionalState::Unused;
+                        state.stencil_write_mask = OptionalState::Unused;
+                    }
 
                     unsafe {
-                        raw.bind_graphics_pipeline(&pipeline.raw);
+                        raw.bind_graphics_pipeline(pipeline.raw.ready(pipeline_id));
                     }
 
-                    // Rebind resource
-                    if state.binder.pipeline_layout_id != Some(pipeline.layout_id.value) {
-                        let pipeline_layout = &pipeline_layout_guard[pipeline.layout_id.value];
-                        state.binder.pipeline_layout_id = Some(pipeline.layout_id.value);
-                        state
-                            .binder
-                            .reset_expectations(pipeline_layout.bind_group_layout_ids.len());
-                        let mut is_compatible = true;
-
-                        for (index, (entry, bgl_id)) in state
-                            .binder
-                            .entries
-                            .iter_mut()
-                            .zip(&pipeline_layout.bind_group_layout_ids)
-                            .enumerate()
-                        {
-                            match entry.expect_layout(bgl_id.value) {
-                                LayoutChange::Match(bg_id, offsets) if is_compatible => {
-                                    let desc_set = bind_group_guard[bg_id].raw.raw();
-                                    unsafe {
-                                        raw.bind_graphics_descriptor_sets(
-                                            &pipeline_layout.raw,
-                                            index,
-                                            iter::once(desc_set),
-                                            offsets.iter().cloned(),
-                                        );
-                                    }
-                                }
-                                LayoutChange::Match(..) | LayoutChange::Unchanged => {}
-                                LayoutChange::Mismatch => {
-                                    is_compatible = false;
-                                }
-                            }
-                        }
-                    }
+                    state.binder.change_pipeline_layout(
+                        pipeline.layout_id.value,
+                        &pipeline_layout_guard[pipeline.layout_id.value].bind_group_layout_ids,
+                    );
+
+                    state.index.strip_format = pipeline.strip_index_format;
 
                     // Rebind index buffer if the index format has changed with the pipeline switch
                     if state.index.format != pipeline.index_format {
@@ -1011,16 +1609,25 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         }
                     }
                     // Update vertex buffer limits
-                    for (vbs, &(stride, rate)) in
+                    let vertex_strides_len = pipeline.vertex_strides.len();
+                    let empty_slots = vertex_strides_len.saturating_sub(state.vertex.inputs.len());
+                    state
+                        .vertex
+                        .inputs
+                        .extend(iter::repeat(VertexBufferState::EMPTY).take(empty_slots));
+                    for (vbs, &(stride, rate, step_rate)) in
                         state.vertex.inputs.iter_mut().zip(&pipeline.vertex_strides)
                     {
                         vbs.stride = stride;
                         vbs.rate = rate;
+                        vbs.step_rate = step_rate;
+                        vbs.required = true;
                     }
-                    let vertex_strides_len = pipeline.vertex_strides.len();
                     for vbs in state.vertex.inputs.iter_mut().skip(vertex_strides_len) {
                         vbs.stride = 0;
                         vbs.rate = InputStepMode::Vertex;
+                        vbs.step_rate = 1;
+                        vbs.required = false;
                     }
                     state.vertex.update_limits();
                 }
@@ -1039,6 +1646,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         pipeline.sample_count, sample_count,
                         "The mesh pipeline and renderpass have mismatching sample_count"
                     );
+                    // Mesh pipelines don't read vertex buffers.
+                    for vbs in state.vertex.inputs.iter_mut() {
+                        vbs.required = false;
+                    }
 
                     state
                         .blend_color
@@ -1046,65 +1657,72 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     state
                         .stencil_reference
                         .require(pipeline.flags.contains(PipelineFlags::STENCIL_REFERENCE));
+                    // A static depth bias overrides the one set by the pass.
+                    if pipeline.flags.contains(PipelineFlags::DEPTH_BIAS) {
+                        state.depth_bias.require(true);
+                    } else {
+                        state.depth_bias = OptionalState::Unused;
+                    }
+                    // Static stencil masks override the ones set by the pass as well.
+                    if pipeline.flags.contains(PipelineFlags::STENCIL_MASKS) {
+                        state.stencil_read_mask.require(true);
+                        state.stencil_write_mask.require(true);
+                    } else {
+                        state.stencil_read_mask = OptionalState::Unused;
+                        state.stencil_write_mask = OptionalState::Unused;
+                    }
 
                     unsafe {
-                        raw.bind_graphics_pipeline(&pipeline.raw);
+                        raw.bind_graphics_pipeline(pipeline.raw.ready(pipeline_id));
                     }
 
-                    // Rebind resource
-                    if state.binder.pipeline_layout_id != Some(pipeline.layout_id.value) {
-                        let pipeline_layout = &pipeline_layout_guard[pipeline.layout_id.value];
-                        state.binder.pipeline_layout_id = Some(pipeline.layout_id.value);
-                        state
-                            .binder
-                            .reset_expectations(pipeline_layout.bind_group_layout_ids.len());
-                        let mut is_compatible = true;
-
-                        for (index, (entry, bgl_id)) in state
-                            .binder
-                            .entries
-                            .iter_mut()
-                            .zip(&pipeline_layout.bind_group_layout_ids)
-                            .enumerate()
-                        {
-                            match entry.expect_layout(bgl_id.value) {
-                                LayoutChange::Match(bg_id, offsets) if is_compatible => {
-                                    let desc_set = bind_group_guard[bg_id].raw.raw();
-                                    unsafe {
-                                        raw.bind_graphics_descriptor_sets(
-                                            &pipeline_layout.raw,
-                                            index,
-                                            iter::once(desc_set),
-                                            offsets.iter().cloned(),
-                                        );
-                                    }
-                                }
-                                LayoutChange::Match(..) | LayoutChange::Unchanged => {}
-                                LayoutChange::Mismatch => {
-                                    is_compatible = false;
-                                }
-                            }
-                        }
-                    }
+                    state.binder.change_pipeline_layout(
+                        pipeline.layout_id.value,
+                        &pipeline_layout_guard[pipeline.layout_id.value].bind_group_layout_ids,
+                    );
                 }
                 RenderCommand::SetIndexBuffer {
                     buffer_id,
                     offset,
                     size,
                 } => {
-                    let buffer = trackers
-                        .buffers
-                        .use_extend(&*buffer_guard, buffer_id, (), BufferUse::INDEX)
-                        .unwrap();
+                    let buffer = match trackers.buffers.use_extend(
+                        &*buffer_guard,
+                        buffer_id,
+                        (),
+                        BufferUse::INDEX,
+                    ) {
+                        Ok(buffer) => buffer,
+                        Err(old) => {
+                            pass_error = Some(
+                                PendingTransition {
+                                    id: buffer_id,
+                                    selector: (),
+                                    usage: old..BufferUse::INDEX,
+                                }
+                                .into(),
+                            );
+                            break;
+                        }
+                    };
                     assert!(buffer.usage.contains(BufferUsage::INDEX), "An invalid setIndexBuffer call has been made. The buffer usage is {:?} which does not contain required usage INDEX", buffer.usage);
 
                     let end = if size != 0 {
-                        offset + size
+                        offset.checked_add(size).unwrap_or(BufferAddress::MAX)
                     } else {
                         buffer.size
                     };
+                    assert!(
+                        offset <= end && end <= buffer.size,
+                        "Index buffer range {}..{} is out of bounds of {:?} with size {}",
+                        offset,
+                        end,
+                        buffer_id,
+                        buffer.size
+                    );
                     state.index.bound_buffer_view = Some((buffer_id, offset..end));
                     state.index.update_limit();
+                    state.binder.invalidate_buffer_usage();
 
                     let view = hal::buffer::IndexBufferView {
                         buffer: &buffer.raw,
@@ -1125,21 +1743,47 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     offset,
                     size,
                 } => {
-                    let buffer = trackers
-                        .buffers
-                        .use_extend(&*buffer_guard, buffer_id, (), BufferUse::VERTEX)
-                        .unwrap();
+                    assert!(
+                        slot < max_vertex_buffers,
+                        "Vertex buffer slot {} is out of the max_vertex_buffers limit {}",
+                        slot,
+                        max_vertex_buffers
+                    );
+                    // A buffer replaced in its slot stays in the usage scope of the pass,
+                    // since the draws recorded before still read from it.
+                    let buffer = match trackers.buffers.use_extend(
+                        &*buffer_guard,
+                        buffer_id,
+                        (),
+                        BufferUse::VERTEX,
+                    ) {
+                        Ok(buffer) => buffer,
+                        Err(old) => {
+                            pass_error = Some(
+                                PendingTransition {
+                                    id: buffer_id,
+                                    selector: (),
+                                    usage: old..BufferUse::VERTEX,
+                                }
+                                .into(),
+                            );
+                            break;
+                        }
+                    };
                     assert!(buffer.usage.contains(BufferUsage::VERTEX), "An invalid setVertexBuffer call has been made. The buffer usage is {:?} which does not contain required usage VERTEX", buffer.usage);
                     let empty_slots = (1 + slot as usize).saturating_sub(state.vertex.inputs.len());
                     state
                         .vertex
                         .inputs
                         .extend(iter::repeat(VertexBufferState::EMPTY).take(empty_slots));
-                    state.vertex.inputs[slot as usize].total_size = if size != 0 {
+                    let vbs = &mut state.vertex.inputs[slot as usize];
+                    vbs.total_size = if size != 0 {
                         size
                     } else {
                         buffer.size - offset
                     };
+                    vbs.buffer = Some(buffer_id);
+                    state.binder.invalidate_buffer_usage();
 
                     let range = hal::buffer::SubRange {
                         offset,
@@ -1150,6 +1794,22 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     }
                     state.vertex.update_limits();
                 }
+                RenderCommand::UnsetVertexBuffer { slot } => {
+                    assert!(
+                        slot < max_vertex_buffers,
+                        "Vertex buffer slot {} is out of the max_vertex_buffers limit {}",
+                        slot,
+                        max_vertex_buffers
+                    );
+                    // The backends can't unbind a vertex buffer, the slot is only
+                    // required to be set again before the next draw that reads it.
+                    if let Some(vbs) = state.vertex.inputs.get_mut(slot as usize) {
+                        vbs.total_size = 0;
+                        vbs.buffer = None;
+                    }
+                    state.binder.invalidate_buffer_usage();
+                    state.vertex.update_limits();
+                }
                 RenderCommand::SetBlendColor(ref color) => {
                     state.blend_color = OptionalState::Set;
                     unsafe {
@@ -1162,6 +1822,52 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         raw.set_stencil_reference(hal::pso::Face::all(), value);
                     }
                 }
+                RenderCommand::SetStencilReadMask(value) => {
+                    assert_ne!(
+                        state.stencil_read_mask,
+                        OptionalState::Unused,
+                        "Stencil read mask can only be set while a pipeline with dynamic stencil masks is bound"
+                    );
+                    state.stencil_read_mask = OptionalState::Set;
+                    unsafe {
+                        raw.set_stencil_read_mask(hal::pso::Face::all(), value);
+                    }
+                }
+                RenderCommand::SetStencilWriteMask(value) => {
+                    assert_ne!(
+                        state.stencil_write_mask,
+                        OptionalState::Unused,
+                        "Stencil write mask can only be set while a pipeline with dynamic stencil masks is bound"
+                    );
+                    state.stencil_write_mask = OptionalState::Set;
+                    unsafe {
+                        raw.set_stencil_write_mask(hal::pso::Face::all(), value);
+                    }
+                }
+                RenderCommand::SetDepthBias {
+                    constant,
+                    slope_scale,
+                    clamp,
+                } => {
+                    assert_ne!(
+                        state.depth_bias,
+                        OptionalState::Unused,
+                        "Depth bias can only be set while a pipeline with dynamic depth bias is bound"
+                    );
+                    assert!(
+                        clamp == 0.0 || device.downlevel.depth_bias_clamp,
+                        "Depth bias clamp {} is not supported by the adapter",
+                        clamp
+                    );
+                    state.depth_bias = OptionalState::Set;
+                    unsafe {
+                        raw.set_depth_bias(hal::pso::DepthBias {
+                            const_factor: constant as f32,
+                            slope_factor: slope_scale,
+                            clamp,
+                        });
+                    }
+                }
                 RenderCommand::SetViewport {
                     ref rect,
                     depth_min,
@@ -1202,25 +1908,56 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     first_vertex,
                     first_instance,
                 } => {
-                    state.is_ready().unwrap();
-                    assert!(
-                        first_vertex + vertex_count <= state.vertex.vertex_limit,
-                        "Vertex {} extends beyond limit {}",
-                        first_vertex + vertex_count,
-                        state.vertex.vertex_limit
-                    );
+                    state
+                        .is_ready()
+                        .unwrap_or_else(|e| panic!("Draw {} is invalid: {:?}", command_context, e));
+                    if let Err(error) = flush_bind_groups::<B>(
+                        &mut raw,
+                        &mut state.binder,
+                        &state.inline,
+                        inline_ring.as_ref(),
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                        bound_buffers(&state.index, &state.vertex),
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    draws += 1;
                     assert!(
-                        first_instance + instance_count <= state.vertex.instance_limit,
-                        "Instance {} extends beyond limit {}",
-                        first_instance + instance_count,
-                        state.vertex.instance_limit
+                        downlevel.base_vertex_instance || first_instance == 0,
+                        "This adapter does not support drawing with a non-zero first instance"
                     );
-
-                    unsafe {
-                        raw.draw(
-                            first_vertex..first_vertex + vertex_count,
-                            first_instance..first_instance + instance_count,
+                    if validation {
+                        assert!(
+                            first_vertex + vertex_count <= state.vertex.vertex_limit,
+                            "Vertex {} extends beyond limit {}",
+                            first_vertex + vertex_count,
+                            state.vertex.vertex_limit
                         );
+                        if let Some(slot) = state
+                            .vertex
+                            .instance_overrun(first_instance, instance_count)
+                        {
+                            panic!(
+                                "Instances {}..{} extend beyond the vertex buffer at slot {}",
+                                first_instance,
+                                first_instance + instance_count,
+                                slot
+                            );
+                        }
+                    }
+
+                    triangles += triangle_count(topology, vertex_count, instance_count);
+
+                    // Empty draws are valid, but not every backend accepts them.
+                    if vertex_count != 0 && instance_count != 0 {
+                        unsafe {
+                            raw.draw(
+                                first_vertex..first_vertex + vertex_count,
+                                first_instance..first_instance + instance_count,
+                            );
+                        }
                     }
                 }
                 RenderCommand::DrawIndexed {
@@ -1230,32 +1967,91 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     base_vertex,
                     first_instance,
                 } => {
-                    state.is_ready().unwrap();
-
-                    //TODO: validate that base_vertex + max_index() is within the provided range
-                    assert!(
-                        first_index + index_count <= state.index.limit,
-                        "Index {} extends beyond limit {}",
-                        first_index + index_count,
-                        state.index.limit
-                    );
+                    state
+                        .is_ready()
+                        .unwrap_or_else(|e| panic!("Draw {} is invalid: {:?}", command_context, e));
+                    if let Err(error) = flush_bind_groups::<B>(
+                        &mut raw,
+                        &mut state.binder,
+                        &state.inline,
+                        inline_ring.as_ref(),
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                        bound_buffers(&state.index, &state.vertex),
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    draws += 1;
+                    state.index.check_strip_format();
                     assert!(
-                        first_instance + instance_count <= state.vertex.instance_limit,
-                        "Instance {} extends beyond limit {}",
-                        first_instance + instance_count,
-                        state.vertex.instance_limit
+                        downlevel.base_vertex_instance || (base_vertex == 0 && first_instance == 0),
+                        "This adapter does not support drawing with a non-zero base vertex or first instance"
                     );
 
-                    unsafe {
-                        raw.draw_indexed(
-                            first_index..first_index + index_count,
-                            base_vertex,
-                            first_instance..first_instance + instance_count,
+                    //TODO: validate that base_vertex + max_index() is within the provided range
+                    if validation && state.index.is_overrun(first_index, index_count) {
+                        let (buffer_id, range) = state
+                            .index
+                            .bound_buffer_view
+                            .clone()
+                            .expect("Index buffer is not bound");
+                        panic!(
+                            "Indices {}..{} of the indexed draw extend beyond the {} {:?} indices in range {:?} of {:?}",
+                            first_index,
+                            first_index as u64 + index_count as u64,
+                            state.index.limit,
+                            state.index.format,
+                            range,
+                            buffer_id
                         );
                     }
+                    if validation {
+                        if let Some(slot) = state
+                            .vertex
+                            .instance_overrun(first_instance, instance_count)
+                        {
+                            panic!(
+                                "Instances {}..{} extend beyond the vertex buffer at slot {}",
+                                first_instance,
+                                first_instance + instance_count,
+                                slot
+                            );
+                        }
+                    }
+                    triangles += triangle_count(topology, index_count, instance_count);
+
+                    if index_count != 0 && instance_count != 0 {
+                        unsafe {
+                            raw.draw_indexed(
+                                first_index..first_index + index_count,
+                                base_vertex,
+                                first_instance..first_instance + instance_count,
+                            );
+                        }
+                    }
                 }
                 RenderCommand::DrawIndirect { buffer_id, offset } => {
-                    state.is_ready().unwrap();
+                    state
+                        .is_ready()
+                        .unwrap_or_else(|e| panic!("Draw {} is invalid: {:?}", command_context, e));
+                    if let Err(error) = flush_bind_groups::<B>(
+                        &mut raw,
+                        &mut state.binder,
+                        &state.inline,
+                        inline_ring.as_ref(),
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                        bound_buffers(&state.index, &state.vertex),
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    draws += 1;
+                    assert!(
+                        downlevel.indirect_execution,
+                        "This adapter does not support indirect draws"
+                    );
 
                     let buffer = trackers
                         .buffers
@@ -1268,7 +2064,27 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     }
                 }
                 RenderCommand::DrawIndexedIndirect { buffer_id, offset } => {
-                    state.is_ready().unwrap();
+                    state
+                        .is_ready()
+                        .unwrap_or_else(|e| panic!("Draw {} is invalid: {:?}", command_context, e));
+                    if let Err(error) = flush_bind_groups::<B>(
+                        &mut raw,
+                        &mut state.binder,
+                        &state.inline,
+                        inline_ring.as_ref(),
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                        bound_buffers(&state.index, &state.vertex),
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    draws += 1;
+                    state.index.check_strip_format();
+                    assert!(
+                        downlevel.indirect_execution,
+                        "This adapter does not support indirect draws"
+                    );
 
                     let buffer = trackers
                         .buffers
@@ -1283,23 +2099,186 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 RenderCommand::DrawMeshTasks {
                     tasks_count,
                 } => {
-                    state.is_ready().unwrap();
+                    state
+                        .is_ready()
+                        .unwrap_or_else(|e| panic!("Draw {} is invalid: {:?}", command_context, e));
+                    if let Err(error) = flush_bind_groups::<B>(
+                        &mut raw,
+                        &mut state.binder,
+                        &state.inline,
+                        inline_ring.as_ref(),
+                        &*bind_group_guard,
+                        &*pipeline_layout_guard,
+                        bound_buffers(&state.index, &state.vertex),
+                    ) {
+                        pass_error = Some(error);
+                        break;
+                    }
+                    draws += 1;
+                    if tasks_count != 0 {
+                        unsafe {
+                            raw.draw_mesh_tasks(tasks_count, 0);
+                        }
+                    }
+                }
+                RenderCommand::PushDebugGroup {
+                    label_length,
+                    phantom_label,
+                } => {
+                    let (new_peeker, label) =
+                        unsafe { phantom_label.decode_label(peeker, label_length, raw_data_end) };
+                    peeker = new_peeker;
                     unsafe {
-                        raw.draw_mesh_tasks(
-                            tasks_count,
-                            0,
+                        raw.begin_debug_marker(&label, 0);
+                    }
+                    command_context.debug_groups.push(label);
+                }
+                RenderCommand::PopDebugGroup => {
+                    assert!(
+                        command_context.debug_groups.pop().is_some(),
+                        "Render pass command {} pops a debug group that isn't pushed",
+                        command_context
+                    );
+                    unsafe {
+                        raw.end_debug_marker();
+                    }
+                }
+                RenderCommand::SetUserTag(tag) => {
+                    command_context.user_tag = Some(tag);
+                }
+                RenderCommand::WriteInlineUniform {
+                    index,
+                    binding,
+                    data_length,
+                    phantom_data,
+                } => {
+                    let (new_peeker, data) = unsafe {
+                        phantom_data.decode_unaligned(peeker, data_length as usize, raw_data_end)
+                    };
+                    peeker = new_peeker;
+
+                    assert!(
+                        device
+                            .capabilities
+                            .contains(wgt::Capabilities::INLINE_UNIFORM_WRITES),
+                        "Inline uniform writes are not supported by the adapter"
+                    );
+                    assert!(
+                        data.len() <= MAX_INLINE_UNIFORM_SIZE && data.len() % 4 == 0,
+                        "Inline uniform data of {} bytes must be a multiple of 4 and at most {} bytes",
+                        data.len(),
+                        MAX_INLINE_UNIFORM_SIZE
+                    );
+                    let index = index as usize;
+                    let (bind_group_id, mut offsets) = match state.binder.entry(index) {
+                        Some((id, offsets)) => (id, offsets.to_vec()),
+                        None => panic!(
+                            "Inline uniform write {} needs a bind group at index {}",
+                            command_context, index
+                        ),
+                    };
+                    let bind_group = &bind_group_guard[bind_group_id];
+                    let (position, dynamic) = bind_group
+                        .dynamic_bindings
+                        .iter()
+                        .enumerate()
+                        .find(|(_, dynamic)| dynamic.binding == binding)
+                        .unwrap_or_else(|| {
+                            panic!(
+                                "Binding {} of bind group {:?} isn't a dynamic buffer",
+                                binding, bind_group_id
+                            )
+                        });
+                    let slot_size = match dynamic.size {
+                        Some(size)
+                            if data.len() as BufferAddress <= size.get()
+                                && size.get() <= MAX_INLINE_UNIFORM_SIZE as BufferAddress =>
+                        {
+                            size.get()
+                        }
+                        _ => panic!(
+                            "Inline uniform data of {} bytes doesn't fit into binding {} of size {:?}, \
+                            or the binding is larger than {} bytes",
+                            data.len(),
+                            binding,
+                            dynamic.size,
+                            MAX_INLINE_UNIFORM_SIZE
+                        ),
+                    };
+
+                    // Every write gets its own slot in the ring of the pass, so
+                    // the draws recorded before it keep seeing the previous data.
+                    // The bind group is replaced by its copy pointing to the ring.
+                    let key = state
+                        .inline
+                        .redirect(index, bind_group_id, &offsets, binding);
+                    let dynamic_offset =
+                        suballocate(&mut inline_cursor, slot_size, dynamic.alignment);
+                    let ring = match inline_ring {
+                        Some(ref ring)
+                            if dynamic_offset + slot_size <= ring.size
+                                && ring.sets.contains_key(key) =>
+                        {
+                            ring
+                        }
+                        _ => panic!(
+                            "Inline uniform write {} isn't prepared for bind group {:?}",
+                            command_context, bind_group_id
+                        ),
+                    };
+
+                    // The data is copied before the pass begins, and the rest
+                    // of the slot is cleared.
+                    let mut slot_data = [0u8; MAX_INLINE_UNIFORM_SIZE];
+                    slot_data[..data.len()].copy_from_slice(data);
+                    unsafe {
+                        cmb.raw.last_mut().unwrap().update_buffer(
+                            &ring.raw,
+                            dynamic_offset,
+                            &slot_data[..slot_size as usize],
                         );
                     }
+
+                    offsets[position] = dynamic_offset as DynamicOffset;
+                    state
+                        .binder
+                        .provide_entry(index, bind_group_id, bind_group, &offsets);
+                    state.binder.invalidate(index);
                 }
                 RenderCommand::End => break,
             }
         }
+        if let Some(error) = pass_error {
+            // The pass is dropped, its command buffer is only kept to be freed with the encoder.
+            unsafe {
+                raw.end_render_pass();
+                raw.finish();
+            }
+            cmb.raw.push(raw);
+            if let Some(ring) = inline_ring.take() {
+                cmb.inline_uniforms.push(ring);
+            }
+            cmb.invalidate(error.clone());
+            return Err(error);
+        }
+        assert!(
+            command_context.debug_groups.is_empty(),
+            "Debug groups {:?} are not popped at the end of the render pass",
+            command_context.debug_groups
+        );
 
         #[cfg(feature = "trace")]
         match cmb.commands {
             Some(ref mut list) => {
                 let mut pass_commands = Vec::new();
                 let mut pass_dynamic_offsets = Vec::new();
+                let mut pass_inline_data = Vec::new();
+                let mut pass_debug_labels = Vec::new();
+                let mut pass_contexts = Vec::new();
+                let mut context = super::PassCommandContext {
+                    index: first_work_index,
+                    ..super::PassCommandContext::default()
+                };
                 peeker = command_peeker_base;
                 loop {
                     peeker = unsafe { RenderCommand::peek_from(peeker, &mut command) };
@@ -1319,16 +2298,60 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             peeker = new_peeker;
                             pass_dynamic_offsets.extend_from_slice(offsets);
                         }
+                        RenderCommand::PushDebugGroup {
+                            label_length,
+                            phantom_label,
+                        } => {
+                            let (new_peeker, label) = unsafe {
+                                phantom_label.decode_label(peeker, label_length, raw_data_end)
+                            };
+                            peeker = new_peeker;
+                            pass_debug_labels.push(label.clone());
+                            context.debug_groups.push(label);
+                        }
+                        RenderCommand::PopDebugGroup => {
+                            context.debug_groups.pop();
+                        }
+                        RenderCommand::SetUserTag(tag) => {
+                            context.user_tag = Some(tag);
+                        }
+                        RenderCommand::WriteInlineUniform {
+                            data_length,
+                            phantom_data,
+                            ..
+                        } => {
+                            let (new_peeker, data) = unsafe {
+                                phantom_data.decode_unaligned(
+                                    peeker,
+                                    data_length as usize,
+                                    raw_data_end,
+                                )
+                            };
+                            peeker = new_peeker;
+                            pass_inline_data.extend_from_slice(data);
+                        }
                         RenderCommand::End => break,
                         _ => {}
                     }
                     pass_commands.push(command);
+                    pass_contexts.push(context.clone());
+                    match command {
+                        RenderCommand::Draw { .. }
+                        | RenderCommand::DrawIndexed { .. }
+                        | RenderCommand::DrawIndirect { .. }
+                        | RenderCommand::DrawIndexedIndirect { .. }
+                        | RenderCommand::DrawMeshTasks { .. } => context.index += 1,
+                        _ => {}
+                    }
                 }
                 list.push(crate::device::trace::Command::RunRenderPass {
                     target_colors: color_attachments.into_iter().collect(),
                     target_depth_stencil: depth_stencil_attachment.cloned(),
                     commands: pass_commands,
                     dynamic_offsets: pass_dynamic_offsets,
+                    inline_data: pass_inline_data,
+                    debug_labels: pass_debug_labels,
+                    contexts: pass_contexts,
                 });
             }
             None => {}
@@ -1345,10 +2368,37 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             &*buffer_guard,
             &*texture_guard,
         );
+        if let Some(ring) = inline_ring.take() {
+            unsafe {
+                cmb.raw.last_mut().unwrap().pipeline_barrier(
+                    hal::pso::PipelineStage::TRANSFER
+                        ..hal::pso::PipelineStage::VERTEX_SHADER
+                            | hal::pso::PipelineStage::FRAGMENT_SHADER,
+                    hal::memory::Dependencies::empty(),
+                    iter::once(hal::memory::Barrier::Buffer {
+                        states: hal::buffer::Access::TRANSFER_WRITE
+                            ..hal::buffer::Access::UNIFORM_READ,
+                        target: &ring.raw,
+                        range: hal::buffer::SubRange::WHOLE,
+                        families: None,
+                    }),
+                );
+            }
+            cmb.inline_uniforms.push(ring);
+        }
         unsafe {
             cmb.raw.last_mut().unwrap().finish();
         }
         cmb.raw.push(raw);
+        cmb.passes.push(super::PassReport {
+            kind: super::PassKind::Render,
+            draws,
+            triangles,
+            pipeline_switches,
+            bind_group_switches,
+            ..super::PassReport::default()
+        });
+        Ok(())
     }
 }
 
@@ -1358,7 +2408,7 @@ pub mod render_ffi {
         RenderCommand,
     };
     use crate::{id, RawString};
-    use std::{convert::TryInto, slice};
+    use std::{convert::TryInto, ffi, slice};
     use wgt::{BufferAddress, Color, DynamicOffset};
 
     /// # Safety
@@ -1384,6 +2434,39 @@ pub mod render_ffi {
         pass.encode_slice(slice::from_raw_parts(offsets, offset_length));
     }
 
+    /// Write `size` bytes of uniform data for the following draws, into the
+    /// dynamic buffer `binding` of the bind group set at `index`.
+    ///
+    /// Every write takes the next slot of the binding size in a ring owned by
+    /// the encoder, and is copied there before the pass begins. The bind group
+    /// is then bound with the binding pointing to that slot, until it's set
+    /// again, while the bound buffer itself is left untouched.
+    ///
+    /// `size` has to be a multiple of 4 and fit into the binding, which has to
+    /// have a fixed size of at most `MAX_INLINE_UNIFORM_SIZE`. The adapter needs
+    /// `Capabilities::INLINE_UNIFORM_WRITES`.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe as there is no guarantee that the given pointer is
+    /// valid for `size` bytes.
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_write_inline_uniform(
+        pass: &mut RawPass,
+        index: u32,
+        binding: u32,
+        data: *const u8,
+        size: usize,
+    ) {
+        pass.encode(&RenderCommand::WriteInlineUniform {
+            index: index.try_into().unwrap(),
+            binding,
+            data_length: size.try_into().unwrap(),
+            phantom_data: PhantomSlice::default(),
+        });
+        pass.encode_slice(slice::from_raw_parts(data, size));
+    }
+
     #[no_mangle]
     pub unsafe extern "C" fn wgpu_render_pass_set_pipeline(
         pass: &mut RawPass,
@@ -1430,6 +2513,11 @@ pub mod render_ffi {
         });
     }
 
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_unset_vertex_buffer(pass: &mut RawPass, slot: u32) {
+        pass.encode(&RenderCommand::UnsetVertexBuffer { slot });
+    }
+
     #[no_mangle]
     pub unsafe extern "C" fn wgpu_render_pass_set_blend_color(pass: &mut RawPass, color: &Color) {
         pass.encode(&RenderCommand::SetBlendColor(*color));
@@ -1443,6 +2531,36 @@ pub mod render_ffi {
         pass.encode(&RenderCommand::SetStencilReference(value));
     }
 
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_set_stencil_read_mask(
+        pass: &mut RawPass,
+        value: u32,
+    ) {
+        pass.encode(&RenderCommand::SetStencilReadMask(value));
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_set_stencil_write_mask(
+        pass: &mut RawPass,
+        value: u32,
+    ) {
+        pass.encode(&RenderCommand::SetStencilWriteMask(value));
+    }
+
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_set_depth_bias(
+        pass: &mut RawPass,
+        constant: i32,
+        slope_scale: f32,
+        clamp: f32,
+    ) {
+        pass.encode(&RenderCommand::SetDepthBias {
+            constant,
+            slope_scale,
+            clamp,
+        });
+    }
+
     #[no_mangle]
     pub unsafe extern "C" fn wgpu_render_pass_set_viewport(
         pass: &mut RawPass,
@@ -1471,6 +2589,8 @@ pub mod render_ffi {
         pass.encode(&RenderCommand::SetScissor(Rect { x, y, w, h }));
     }
 
+    /// Draws with no vertices or instances are valid, and do nothing
+    /// once the bound state is validated.
     #[no_mangle]
     pub unsafe extern "C" fn wgpu_render_pass_draw(
         pass: &mut RawPass,
@@ -1514,6 +2634,10 @@ pub mod render_ffi {
         pass.encode(&RenderCommand::DrawIndirect { buffer_id, offset });
     }
 
+    /// Draw indexed primitives with the parameters read from `buffer_id`.
+    ///
+    /// Unlike `wgpu_render_pass_draw_indexed`, the index range is only known
+    /// to the GPU, so it is not validated against the bound index buffer.
     #[no_mangle]
     pub unsafe extern "C" fn wgpu_render_pass_draw_indexed_indirect(
         pass: &mut RawPass,
@@ -1543,13 +2667,27 @@ pub mod render_ffi {
     }
 
     #[no_mangle]
-    pub extern "C" fn wgpu_render_pass_push_debug_group(_pass: &mut RawPass, _label: RawString) {
-        //TODO
+    pub unsafe extern "C" fn wgpu_render_pass_push_debug_group(
+        pass: &mut RawPass,
+        label: RawString,
+    ) {
+        let bytes = ffi::CStr::from_ptr(label).to_bytes();
+        pass.encode(&RenderCommand::PushDebugGroup {
+            label_length: bytes.len().try_into().unwrap(),
+            phantom_label: PhantomSlice::default(),
+        });
+        pass.encode_slice(bytes);
     }
 
     #[no_mangle]
-    pub extern "C" fn wgpu_render_pass_pop_debug_group(_pass: &mut RawPass) {
-        //TODO
+    pub unsafe extern "C" fn wgpu_render_pass_pop_debug_group(pass: &mut RawPass) {
+        pass.encode(&RenderCommand::PopDebugGroup);
+    }
+
+    /// Tag the following commands of the pass, for debugging and in traces.
+    #[no_mangle]
+    pub unsafe extern "C" fn wgpu_render_pass_set_user_tag(pass: &mut RawPass, tag: u32) {
+        pass.encode(&RenderCommand::SetUserTag(tag));
     }
 
     #[no_mangle]
@@ -1567,3 +2705,191 @@ pub mod render_ffi {
         pass.base
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::id::TypedId;
+
+    fn dims(width: u32, samples: hal::image::NumSamples) -> AttachmentDims {
+        AttachmentDims {
+            extent: hal::image::Extent {
+                width,
+                height: 64,
+                depth: 1,
+            },
+            samples,
+            layers: 1,
+        }
+    }
+
+    #[test]
+    fn instance_step_rate() {
+        let instances = |size, step_rate| VertexBufferState {
+            total_size: size,
+            stride: 16,
+            rate: InputStepMode::Instance,
+            step_rate,
+            required: true,
+            buffer: Some(id::BufferId::zip(0, 1, wgt::Backend::Empty)),
+        };
+        let mut state = VertexState {
+            inputs: [instances(64, 1), instances(64, 3)]
+                .iter()
+                .cloned()
+                .collect(),
+            vertex_limit: 0,
+        };
+        // 4 elements each
+        assert_eq!(state.instance_overrun(0, 4), None);
+        assert_eq!(state.instance_overrun(1, 4), Some(0));
+        state.inputs.remove(0);
+        assert_eq!(state.instance_overrun(0, 12), None);
+        assert_eq!(state.instance_overrun(0, 13), Some(0));
+        assert_eq!(state.instance_overrun(1, 9), None);
+        assert_eq!(state.instance_overrun(1, 10), Some(0));
+    }
+
+    #[test]
+    fn skipped_bytes() {
+        let extent = hal::image::Extent {
+            width: 16,
+            height: 8,
+            depth: 1,
+        };
+        assert_eq!(skipped_attachment_bytes(extent, 1, 32, true, true), 0);
+        assert_eq!(skipped_attachment_bytes(extent, 1, 32, false, true), 512);
+        assert_eq!(skipped_attachment_bytes(extent, 4, 32, false, false), 4096);
+    }
+
+    #[test]
+    fn triangles() {
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleList, 7, 2), 4);
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleStrip, 7, 2), 10);
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleStrip, 1, 2), 0);
+        assert_eq!(triangle_count(PrimitiveTopology::LineList, 6, 1), 0);
+    }
+
+    #[test]
+    fn index_range() {
+        use crate::id::TypedId as _;
+        let mut state = IndexState {
+            bound_buffer_view: Some((id::BufferId::zip(0, 1, wgt::Backend::Empty), 4..20)),
+            format: IndexFormat::Uint16,
+            strip_format: None,
+            limit: 0,
+        };
+        state.update_limit();
+        assert!(!state.is_overrun(0, 8));
+        assert!(!state.is_overrun(8, 0));
+        assert!(state.is_overrun(1, 8));
+        assert!(state.is_overrun(!0, 2));
+        state.format = IndexFormat::Uint32;
+        state.update_limit();
+        assert!(!state.is_overrun(2, 2));
+        assert!(state.is_overrun(0, 5));
+    }
+
+    #[test]
+    fn empty_pass_keeps_clears() {
+        use crate::id::TypedId as _;
+        let color = RenderPassColorAttachmentDescriptor {
+            attachment: id::TextureViewId::zip(0, 1, wgt::Backend::Empty),
+            resolve_target: None,
+            load_op: LoadOp::Clear,
+            store_op: wgt::StoreOp::Store,
+            clear_color: wgt::Color::GREEN,
+        };
+        let desc = RenderPassDescriptor {
+            color_attachments: &color,
+            color_attachments_length: 1,
+            depth_stencil_attachment: None,
+            label: std::ptr::null(),
+        };
+        let encoder_id = id::CommandEncoderId::zip(0, 1, wgt::Backend::Empty);
+        let (raw_data, _) =
+            unsafe { super::super::RawPass::new_render(encoder_id, &desc).finish_render() };
+        let (pipelines, bind_groups, _, ids) = used_resources(&raw_data);
+        assert!(pipelines.is_empty() && bind_groups.is_empty());
+        assert_eq!(ids.texture_views, [color.attachment]);
+
+        let mut targets: RawRenderTargets = unsafe { mem::zeroed() };
+        let mut command = RenderCommand::SetBlendColor(wgt::Color::TRANSPARENT);
+        unsafe {
+            let peeker = RawRenderTargets::peek_from(raw_data.as_ptr(), &mut targets);
+            RenderCommand::peek_from(peeker, &mut command);
+        }
+        assert_eq!(targets.colors[0].attachment, color.attachment.into_raw());
+        assert_eq!(targets.colors[0].component.load_op, LoadOp::Clear);
+        match command {
+            RenderCommand::End => {}
+            other => panic!("Expected the pass to end right away, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn attachment_mismatch() {
+        let colors = [(dims(64, 4), Some(dims(64, 1))), (dims(32, 4), None)];
+        assert_eq!(
+            validate_attachments(&colors, None, 4, false),
+            Err(AttachmentError::ExtentMismatch {
+                first: AttachmentSlot::Color(0),
+                first_extent: dims(64, 4).extent,
+                second: AttachmentSlot::Color(1),
+                second_extent: dims(32, 4).extent,
+            })
+        );
+        assert_eq!(
+            validate_attachments(&colors[..1], Some(dims(64, 1)), 4, false),
+            Err(AttachmentError::SampleCountMismatch {
+                first: AttachmentSlot::Color(0),
+                first_samples: 4,
+                second: AttachmentSlot::DepthStencil,
+                second_samples: 1,
+            })
+        );
+        assert_eq!(
+            validate_attachments(&colors[..1], Some(dims(64, 4)), 4, false),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn layered_attachments() {
+        let layered = |layers| AttachmentDims {
+            layers,
+            ..dims(64, 1)
+        };
+        let colors = [(layered(6), None), (layered(6), None)];
+        assert_eq!(
+            validate_attachments(&colors, None, 4, false),
+            Err(AttachmentError::LayeredRenderingUnsupported {
+                slot: AttachmentSlot::Color(0),
+                layers: 6,
+            })
+        );
+        assert_eq!(validate_attachments(&colors, None, 4, true), Ok(()));
+        assert_eq!(
+            validate_attachments(&colors[..1], Some(layered(4)), 4, true),
+            Err(AttachmentError::LayerCountMismatch {
+                first: AttachmentSlot::Color(0),
+                first_layers: 6,
+                second: AttachmentSlot::DepthStencil,
+                second_layers: 4,
+            })
+        );
+    }
+
+    #[test]
+    fn resolve_requires_multisampling() {
+        let colors = [(dims(64, 1), Some(dims(64, 1)))];
+        assert_eq!(
+            validate_attachments(&colors, None, 4, false),
+            Err(AttachmentError::ResolveSourceNotMultisampled(0))
+        );
+        assert_eq!(
+            validate_attachments(&colors, None, 0, false),
+            Err(AttachmentError::TooManyColorAttachments { count: 1, limit: 0 })
+        );
+    }
+}
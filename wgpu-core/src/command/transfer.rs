@@ -5,17 +5,22 @@
 #[cfg(feature = "trace")]
 use crate::device::trace::Command as TraceCommand;
 use crate::{
+    command::{recording_encoder, CommandEncoderError},
     conv,
-    device::{all_buffer_stages, all_image_stages},
+    device::{all_buffer_stages, all_image_stages, check_device, WrongDeviceError},
     hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Token},
     id::{BufferId, CommandEncoderId, TextureId},
-    resource::{BufferUse, TextureUse},
+    resource::{mip_level_extent, BufferUse, Texture, TextureUse},
+    PrivateFeatures,
 };
 
 use hal::command::CommandBuffer as _;
-use wgt::{BufferAddress, BufferUsage, Extent3d, Origin3d, TextureUsage};
+use wgt::{
+    BufferAddress, BufferUsage, Extent3d, Origin3d, TextureFormat, TextureUsage,
+    COPY_BUFFER_ALIGNMENT, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
 
-use std::iter;
+use std::{iter, ops::Range};
 
 const BITS_PER_BYTE: u32 = 8;
 
@@ -41,6 +46,425 @@ pub struct TextureCopyView {
     pub origin: Origin3d,
 }
 
+/// Region of a batched buffer-to-buffer copy.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+#[cfg_attr(feature = "replay", derive(serde::Deserialize))]
+pub struct BufferCopyRegion {
+    pub src_offset: BufferAddress,
+    pub dst_offset: BufferAddress,
+    pub size: BufferAddress,
+}
+
+/// Layout of the texture data in a buffer, as chosen by
+/// `command_encoder_copy_texture_to_buffer_with_layout`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureDataLayout {
+    pub offset: BufferAddress,
+    /// Size of a row of texel blocks, padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+    pub bytes_per_row: u32,
+    /// Number of texel rows in each image, rounded up to whole blocks,
+    /// like `BufferCopyView::rows_per_image`.
+    pub rows_per_image: u32,
+    /// Number of bytes occupied in the buffer, starting at `offset`.
+    pub required_size: BufferAddress,
+}
+
+impl TextureDataLayout {
+    /// Layout of a texture region of `size` at `offset`, in a format of
+    /// `block_dim` texel blocks of `block_bits` each, with the rows of
+    /// blocks padded to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+    fn packed(block_dim: (u8, u8), block_bits: u16, offset: BufferAddress, size: Extent3d) -> Self {
+        let (block_width, block_height) = (block_dim.0 as u32, block_dim.1 as u32);
+        let blocks_per_row = (size.width + block_width - 1) / block_width;
+        let block_rows = (size.height + block_height - 1) / block_height;
+        let row_size = blocks_per_row * (block_bits as u32 / BITS_PER_BYTE);
+        let alignment = COPY_BYTES_PER_ROW_ALIGNMENT;
+        let bytes_per_row = (row_size + alignment - 1) / alignment * alignment;
+        TextureDataLayout {
+            offset,
+            bytes_per_row,
+            rows_per_image: block_rows * block_height,
+            required_size: bytes_per_row as BufferAddress
+                * block_rows as BufferAddress
+                * size.depth as BufferAddress,
+        }
+    }
+
+    /// Check if the data fits into a buffer of `buffer_size`.
+    fn fits(&self, buffer_size: BufferAddress) -> bool {
+        self.offset
+            .checked_add(self.required_size)
+            .map_or(false, |end| end <= buffer_size)
+    }
+}
+
+/// Error encountered when validating a buffer-to-buffer copy.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BufferCopyError {
+    /// The source buffer usage doesn't contain `COPY_SRC`.
+    MissingCopySrcUsage(BufferUsage),
+    /// The destination buffer usage doesn't contain `COPY_DST`.
+    MissingCopyDstUsage(BufferUsage),
+    /// The copy size isn't a multiple of `COPY_BUFFER_ALIGNMENT`.
+    UnalignedCopySize(BufferAddress),
+    /// The source offset isn't a multiple of `COPY_BUFFER_ALIGNMENT`.
+    UnalignedSourceOffset(BufferAddress),
+    /// The destination offset isn't a multiple of `COPY_BUFFER_ALIGNMENT`.
+    UnalignedDestinationOffset(BufferAddress),
+    /// The copied range doesn't fit into the source buffer.
+    SourceOutOfBounds {
+        offset: BufferAddress,
+        size: BufferAddress,
+        buffer_size: BufferAddress,
+    },
+    /// The copied range doesn't fit into the destination buffer.
+    DestinationOutOfBounds {
+        offset: BufferAddress,
+        size: BufferAddress,
+        buffer_size: BufferAddress,
+    },
+    /// The source and destination are the same buffer, and the ranges overlap.
+    OverlappingRanges,
+    /// The destination range of a region overlaps the source or destination
+    /// range of another region in the same batch.
+    OverlappingRegions { first: usize, second: usize },
+    /// The buffer doesn't exist, or is already destroyed.
+    InvalidBuffer(BufferId),
+    /// The buffer belongs to another device than the command encoder.
+    WrongDevice(WrongDeviceError),
+    /// The command encoder isn't recording.
+    Encoder(CommandEncoderError),
+}
+
+impl From<WrongDeviceError> for BufferCopyError {
+    fn from(error: WrongDeviceError) -> Self {
+        BufferCopyError::WrongDevice(error)
+    }
+}
+
+/// One side of a buffer-to-buffer copy.
+struct BufferCopySide {
+    usage: BufferUsage,
+    buffer_size: BufferAddress,
+    offset: BufferAddress,
+}
+
+impl BufferCopySide {
+    fn end(&self, size: BufferAddress) -> Option<BufferAddress> {
+        self.offset
+            .checked_add(size)
+            .filter(|&end| end <= self.buffer_size)
+    }
+}
+
+fn validate_buffer_copy(
+    src: &BufferCopySide,
+    dst: &BufferCopySide,
+    size: BufferAddress,
+    same_buffer: bool,
+) -> Result<(), BufferCopyError> {
+    if !src.usage.contains(BufferUsage::COPY_SRC) {
+        return Err(BufferCopyError::MissingCopySrcUsage(src.usage));
+    }
+    if !dst.usage.contains(BufferUsage::COPY_DST) {
+        return Err(BufferCopyError::MissingCopyDstUsage(dst.usage));
+    }
+    if size % COPY_BUFFER_ALIGNMENT != 0 {
+        return Err(BufferCopyError::UnalignedCopySize(size));
+    }
+    if src.offset % COPY_BUFFER_ALIGNMENT != 0 {
+        return Err(BufferCopyError::UnalignedSourceOffset(src.offset));
+    }
+    if dst.offset % COPY_BUFFER_ALIGNMENT != 0 {
+        return Err(BufferCopyError::UnalignedDestinationOffset(dst.offset));
+    }
+    let src_end = src.end(size).ok_or(BufferCopyError::SourceOutOfBounds {
+        offset: src.offset,
+        size,
+        buffer_size: src.buffer_size,
+    })?;
+    let dst_end = dst.end(size).ok_or(BufferCopyError::DestinationOutOfBounds {
+        offset: dst.offset,
+        size,
+        buffer_size: dst.buffer_size,
+    })?;
+    if same_buffer && src.offset < dst_end && dst.offset < src_end {
+        return Err(BufferCopyError::OverlappingRanges);
+    }
+    Ok(())
+}
+
+/// Validate all the regions of a batched copy, including the overlaps
+/// between the regions, which are executed as a single copy.
+fn validate_buffer_copy_regions(
+    src: &BufferCopySide,
+    dst: &BufferCopySide,
+    regions: &[BufferCopyRegion],
+    same_buffer: bool,
+) -> Result<(), BufferCopyError> {
+    // (start, end, is destination, region index)
+    let mut ranges = Vec::with_capacity(regions.len() * 2);
+    for (index, region) in regions.iter().enumerate() {
+        validate_buffer_copy(
+            &BufferCopySide {
+                offset: region.src_offset,
+                ..*src
+            },
+            &BufferCopySide {
+                offset: region.dst_offset,
+                ..*dst
+            },
+            region.size,
+            same_buffer,
+        )?;
+        if region.size == 0 {
+            continue;
+        }
+        ranges.push((
+            region.dst_offset,
+            region.dst_offset + region.size,
+            true,
+            index,
+        ));
+        if same_buffer {
+            ranges.push((
+                region.src_offset,
+                region.src_offset + region.size,
+                false,
+                index,
+            ));
+        }
+    }
+
+    // Sweep the ranges in the order of their start, tracking the furthest
+    // reaching destination and source ranges seen so far. Sources are
+    // allowed to overlap each other.
+    ranges.sort_by_key(|&(start, ..)| start);
+    let mut furthest_dst = None::<(BufferAddress, usize)>;
+    let mut furthest_src = None::<(BufferAddress, usize)>;
+    for &(start, end, is_dst, index) in ranges.iter() {
+        let conflict = match furthest_dst {
+            Some((dst_end, other)) if start < dst_end => Some(other),
+            _ => match furthest_src {
+                Some((src_end, other)) if is_dst && start < src_end => Some(other),
+                _ => None,
+            },
+        };
+        if let Some(other) = conflict {
+            return Err(BufferCopyError::OverlappingRegions {
+                first: other.min(index),
+                second: other.max(index),
+            });
+        }
+        let furthest = if is_dst {
+            &mut furthest_dst
+        } else {
+            &mut furthest_src
+        };
+        match *furthest {
+            Some((furthest_end, _)) if furthest_end >= end => (),
+            _ => *furthest = Some((end, index)),
+        }
+    }
+    Ok(())
+}
+
+/// Error encountered when validating the texture side of a copy.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureCopyError {
+    /// The mip level doesn't exist in the texture.
+    InvalidMipLevel { level: u32, level_count: u32 },
+    /// The origin isn't a multiple of the texel block size.
+    UnalignedOrigin(Origin3d),
+    /// The copy size isn't a multiple of the texel block size.
+    UnalignedSize(Extent3d),
+    /// The copied region doesn't fit into the mip level, rounded up to whole blocks.
+    OutOfBounds {
+        origin: Origin3d,
+        size: Extent3d,
+        level_extent: Extent3d,
+    },
+    /// The source and destination regions overlap within the same subresource.
+    OverlappingRegions {
+        level: u32,
+        layer: u32,
+        src: (Origin3d, Extent3d),
+        dst: (Origin3d, Extent3d),
+    },
+    /// The source and destination are disjoint regions of the same subresource,
+    /// which can't be in the transfer source and destination layouts at once.
+    SameSubresource { level: u32, layer: u32 },
+    /// The texels of the source format can't be copied into the destination format.
+    IncompatibleFormats {
+        src: TextureFormat,
+        dst: TextureFormat,
+    },
+}
+
+/// Strip the sRGB encoding off a format, which doesn't change the stored bits.
+fn linear_format(format: TextureFormat) -> TextureFormat {
+    match format {
+        TextureFormat::Rgba8UnormSrgb => TextureFormat::Rgba8Unorm,
+        TextureFormat::Bgra8UnormSrgb => TextureFormat::Bgra8Unorm,
+        other => other,
+    }
+}
+
+/// Check that a texture of `src` format can be copied into one of `dst`
+/// format on `backend`, as raw bits without any conversion.
+///
+/// Vulkan and Metal copy between any color formats with the same texel block
+/// size and dimensions, which reinterprets the bits, for example to swap the
+/// channels of `Rgba8Unorm` into `Bgra8Unorm`. Depth and stencil formats have
+/// to match exactly everywhere.
+///
+/// D3D11 and D3D12 only copy between formats of the same typeless family.
+/// Those are approximated by the formats that only differ in the sRGB encoding.
+fn validate_texture_copy_formats(
+    src: TextureFormat,
+    dst: TextureFormat,
+    backend: wgt::Backend,
+    private_features: PrivateFeatures,
+) -> Result<(), TextureCopyError> {
+    let src_desc = conv::map_texture_format(src, private_features).surface_desc();
+    let dst_desc = conv::map_texture_format(dst, private_features).surface_desc();
+    let compatible = if src == dst {
+        true
+    } else if src_desc.aspects != hal::format::Aspects::COLOR
+        || dst_desc.aspects != hal::format::Aspects::COLOR
+    {
+        false
+    } else {
+        match backend {
+            wgt::Backend::Dx11 | wgt::Backend::Dx12 => linear_format(src) == linear_format(dst),
+            _ => src_desc.bits == dst_desc.bits && src_desc.dim == dst_desc.dim,
+        }
+    };
+    if compatible {
+        Ok(())
+    } else {
+        Err(TextureCopyError::IncompatibleFormats { src, dst })
+    }
+}
+
+/// Check that a copy region is within a mip level of a texture of `extent`.
+///
+/// The last blocks of a compressed mip level may extend past its edges,
+/// so whole blocks are always copied.
+pub(crate) fn validate_texture_copy_range(
+    view: &TextureCopyView,
+    extent: hal::image::Extent,
+    level_count: hal::image::Level,
+    (block_width, block_height): (u8, u8),
+    size: &Extent3d,
+) -> Result<(), TextureCopyError> {
+    if view.mip_level >= u32::from(level_count) {
+        return Err(TextureCopyError::InvalidMipLevel {
+            level: view.mip_level,
+            level_count: u32::from(level_count),
+        });
+    }
+    let (block_width, block_height) = (u32::from(block_width), u32::from(block_height));
+    if view.origin.x % block_width != 0 || view.origin.y % block_height != 0 {
+        return Err(TextureCopyError::UnalignedOrigin(view.origin));
+    }
+    if size.width % block_width != 0 || size.height % block_height != 0 {
+        return Err(TextureCopyError::UnalignedSize(*size));
+    }
+    let level_extent = mip_level_extent(extent, view.mip_level as hal::image::Level);
+    let round_up = |size: u32, block: u32| (size + block - 1) / block * block;
+    let physical = Extent3d {
+        width: round_up(level_extent.width, block_width),
+        height: round_up(level_extent.height, block_height),
+        depth: level_extent.depth,
+    };
+    let fits = |origin: u32, size: u32, limit: u32| {
+        origin.checked_add(size).map_or(false, |end| end <= limit)
+    };
+    if !fits(view.origin.x, size.width, physical.width)
+        || !fits(view.origin.y, size.height, physical.height)
+        || !fits(view.origin.z, size.depth, physical.depth)
+    {
+        return Err(TextureCopyError::OutOfBounds {
+            origin: view.origin,
+            size: *size,
+            level_extent: physical,
+        });
+    }
+    Ok(())
+}
+
+fn validate_texture_copy<B: hal::Backend>(
+    view: &TextureCopyView,
+    texture: &Texture<B>,
+    private_features: PrivateFeatures,
+    size: &Extent3d,
+) {
+    let block_dims = conv::map_texture_format(texture.format, private_features)
+        .surface_desc()
+        .dim;
+    if let Err(err) = validate_texture_copy_range(
+        view,
+        texture.kind.extent(),
+        texture.full_range.levels.end,
+        block_dims,
+        size,
+    ) {
+        panic!("Invalid copy of texture {:?}: {:?}", view.texture, err);
+    }
+}
+
+/// Check that a copy within a single texture doesn't read and write the same subresource.
+///
+/// Copies between different mip levels or array layers are fine, since the
+/// tracker keeps separate states for them. Regions are compared in whole
+/// texel blocks, since that is the granularity of compressed copies.
+fn validate_texture_copy_overlap(
+    source: &TextureCopyView,
+    destination: &TextureCopyView,
+    (block_width, block_height): (u8, u8),
+    size: &Extent3d,
+) -> Result<(), TextureCopyError> {
+    if source.texture != destination.texture
+        || source.mip_level != destination.mip_level
+        || source.array_layer != destination.array_layer
+        || size.width == 0
+        || size.height == 0
+        || size.depth == 0
+    {
+        return Ok(());
+    }
+    let blocks = |origin: u32, size: u32, block: u8| {
+        let block = u32::from(block);
+        origin / block..(origin + size + block - 1) / block
+    };
+    let intersects = |a: Range<u32>, b: Range<u32>| a.start < b.end && b.start < a.end;
+    let (src, dst) = (source.origin, destination.origin);
+    if intersects(
+        blocks(src.x, size.width, block_width),
+        blocks(dst.x, size.width, block_width),
+    ) && intersects(
+        blocks(src.y, size.height, block_height),
+        blocks(dst.y, size.height, block_height),
+    ) && intersects(src.z..src.z + size.depth, dst.z..dst.z + size.depth)
+    {
+        Err(TextureCopyError::OverlappingRegions {
+            level: source.mip_level,
+            layer: source.array_layer,
+            src: (src, *size),
+            dst: (dst, *size),
+        })
+    } else {
+        Err(TextureCopyError::SameSubresource {
+            level: source.mip_level,
+            layer: source.array_layer,
+        })
+    }
+}
+
 impl TextureCopyView {
     //TODO: we currently access each texture twice for a transfer,
     // once only to get the aspect flags, which is unfortunate.
@@ -85,12 +509,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         destination: BufferId,
         destination_offset: BufferAddress,
         size: BufferAddress,
-    ) {
+    ) -> Result<(), BufferCopyError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
-        let cmb = &mut cmb_guard[command_encoder_id];
+        let cmb = recording_encoder(&mut *cmb_guard, command_encoder_id)
+            .map_err(BufferCopyError::Encoder)?;
+        if !cmb.check_no_open_pass("copy_buffer_to_buffer") {
+            return Ok(());
+        }
         let (buffer_guard, _) = hub.buffers.read(&mut token);
         // we can't hold both src_pending and dst_pending in scope because they
         // borrow the buffer tracker mutably...
@@ -108,26 +536,45 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             None => (),
         }
 
+        {
+            let src_buffer = buffer_guard
+                .get(source)
+                .ok_or(BufferCopyError::InvalidBuffer(source))?;
+            let dst_buffer = buffer_guard
+                .get(destination)
+                .ok_or(BufferCopyError::InvalidBuffer(destination))?;
+            check_device(cmb.device_id.value, source, src_buffer.device_id.value)?;
+            check_device(cmb.device_id.value, destination, dst_buffer.device_id.value)?;
+            validate_buffer_copy(
+                &BufferCopySide {
+                    usage: src_buffer.usage,
+                    buffer_size: src_buffer.size,
+                    offset: source_offset,
+                },
+                &BufferCopySide {
+                    usage: dst_buffer.usage,
+                    buffer_size: dst_buffer.size,
+                    offset: destination_offset,
+                },
+                size,
+                source == destination,
+            )?;
+        }
+        if size == 0 {
+            log::trace!("Ignoring copy_buffer_to_buffer of size 0");
+            return Ok(());
+        }
+
         let (src_buffer, src_pending) =
             cmb.trackers
                 .buffers
                 .use_replace(&*buffer_guard, source, (), BufferUse::COPY_SRC);
-        assert!(
-            src_buffer.usage.contains(BufferUsage::COPY_SRC),
-            "Source buffer usage {:?} must contain usage flag COPY_SRC",
-            src_buffer.usage
-        );
         barriers.extend(src_pending.map(|pending| pending.into_hal(src_buffer)));
 
         let (dst_buffer, dst_pending) =
             cmb.trackers
                 .buffers
                 .use_replace(&*buffer_guard, destination, (), BufferUse::COPY_DST);
-        assert!(
-            dst_buffer.usage.contains(BufferUsage::COPY_DST),
-            "Destination buffer usage {:?} must contain usage flag COPY_DST",
-            dst_buffer.usage
-        );
         barriers.extend(dst_pending.map(|pending| pending.into_hal(dst_buffer)));
 
         let region = hal::command::BufferCopy {
@@ -135,6 +582,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             dst: destination_offset,
             size,
         };
+        cmb.count_copy();
         let cmb_raw = cmb.raw.last_mut().unwrap();
         unsafe {
             cmb_raw.pipeline_barrier(
@@ -144,6 +592,102 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             );
             cmb_raw.copy_buffer(&src_buffer.raw, &dst_buffer.raw, iter::once(region));
         }
+        Ok(())
+    }
+
+    /// Copy many regions between two buffers at once.
+    ///
+    /// The regions are validated together, the buffers are transitioned
+    /// once, and the copy is recorded as a single command.
+    pub fn command_encoder_copy_buffer_regions<B: GfxBackend>(
+        &self,
+        command_encoder_id: CommandEncoderId,
+        source: BufferId,
+        destination: BufferId,
+        regions: &[BufferCopyRegion],
+    ) -> Result<(), BufferCopyError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+
+        let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
+        let cmb = recording_encoder(&mut *cmb_guard, command_encoder_id)
+            .map_err(BufferCopyError::Encoder)?;
+        if !cmb.check_no_open_pass("copy_buffer_regions") {
+            return Ok(());
+        }
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+        let mut barriers = Vec::new();
+
+        #[cfg(feature = "trace")]
+        match cmb.commands {
+            Some(ref mut list) => list.push(TraceCommand::CopyBufferRegions {
+                src: source,
+                dst: destination,
+                regions: regions.to_vec(),
+            }),
+            None => (),
+        }
+
+        {
+            let src_buffer = buffer_guard
+                .get(source)
+                .ok_or(BufferCopyError::InvalidBuffer(source))?;
+            let dst_buffer = buffer_guard
+                .get(destination)
+                .ok_or(BufferCopyError::InvalidBuffer(destination))?;
+            check_device(cmb.device_id.value, source, src_buffer.device_id.value)?;
+            check_device(cmb.device_id.value, destination, dst_buffer.device_id.value)?;
+            validate_buffer_copy_regions(
+                &BufferCopySide {
+                    usage: src_buffer.usage,
+                    buffer_size: src_buffer.size,
+                    offset: 0,
+                },
+                &BufferCopySide {
+                    usage: dst_buffer.usage,
+                    buffer_size: dst_buffer.size,
+                    offset: 0,
+                },
+                regions,
+                source == destination,
+            )?;
+        }
+        if regions.iter().all(|region| region.size == 0) {
+            log::trace!("Ignoring copy_buffer_regions without any data");
+            return Ok(());
+        }
+
+        let (src_buffer, src_pending) =
+            cmb.trackers
+                .buffers
+                .use_replace(&*buffer_guard, source, (), BufferUse::COPY_SRC);
+        barriers.extend(src_pending.map(|pending| pending.into_hal(src_buffer)));
+
+        let (dst_buffer, dst_pending) =
+            cmb.trackers
+                .buffers
+                .use_replace(&*buffer_guard, destination, (), BufferUse::COPY_DST);
+        barriers.extend(dst_pending.map(|pending| pending.into_hal(dst_buffer)));
+
+        let hal_regions = regions
+            .iter()
+            .filter(|region| region.size != 0)
+            .map(|region| hal::command::BufferCopy {
+                src: region.src_offset,
+                dst: region.dst_offset,
+                size: region.size,
+            });
+        cmb.count_copy();
+        let cmb_raw = cmb.raw.last_mut().unwrap();
+        unsafe {
+            cmb_raw.pipeline_barrier(
+                all_buffer_stages()..all_buffer_stages(),
+                hal::memory::Dependencies::empty(),
+                barriers,
+            );
+            cmb_raw.copy_buffer(&src_buffer.raw, &dst_buffer.raw, hal_regions);
+        }
+        Ok(())
     }
 
     pub fn command_encoder_copy_buffer_to_texture<B: GfxBackend>(
@@ -152,14 +696,34 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         source: &BufferCopyView,
         destination: &TextureCopyView,
         copy_size: Extent3d,
-    ) {
+    ) -> Result<(), CommandEncoderError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
-        let cmb = &mut cmb_guard[command_encoder_id];
+        let cmb = recording_encoder(&mut *cmb_guard, command_encoder_id)?;
+        if !cmb.check_no_open_pass("copy_buffer_to_texture") {
+            return Ok(());
+        }
         let (buffer_guard, mut token) = hub.buffers.read(&mut token);
         let (texture_guard, _) = hub.textures.read(&mut token);
-        let aspects = texture_guard[destination.texture].full_range.aspects;
+        let src_buffer = buffer_guard
+            .get(source.buffer)
+            .ok_or(CommandEncoderError::InvalidBuffer(source.buffer))?;
+        check_device(
+            cmb.device_id.value,
+            source.buffer,
+            src_buffer.device_id.value,
+        )?;
+        let dst_texture = texture_guard
+            .get(destination.texture)
+            .ok_or(CommandEncoderError::InvalidTexture(destination.texture))?;
+        check_device(
+            cmb.device_id.value,
+            destination.texture,
+            dst_texture.device_id.value,
+        )?;
+        let aspects = dst_texture.full_range.aspects;
+        validate_texture_copy(destination, dst_texture, cmb.private_features, &copy_size);
 
         #[cfg(feature = "trace")]
         match cmb.commands {
@@ -209,6 +773,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             image_offset: conv::map_origin(destination.origin),
             image_extent: conv::map_extent(copy_size),
         };
+        cmb.count_copy();
         let cmb_raw = cmb.raw.last_mut().unwrap();
         let stages = all_buffer_stages() | all_image_stages();
         unsafe {
@@ -224,6 +789,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 iter::once(region),
             );
         }
+        Ok(())
     }
 
     pub fn command_encoder_copy_texture_to_buffer<B: GfxBackend>(
@@ -232,14 +798,34 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         source: &TextureCopyView,
         destination: &BufferCopyView,
         copy_size: Extent3d,
-    ) {
+    ) -> Result<(), CommandEncoderError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
-        let cmb = &mut cmb_guard[command_encoder_id];
+        let cmb = recording_encoder(&mut *cmb_guard, command_encoder_id)?;
+        if !cmb.check_no_open_pass("copy_texture_to_buffer") {
+            return Ok(());
+        }
         let (buffer_guard, mut token) = hub.buffers.read(&mut token);
         let (texture_guard, _) = hub.textures.read(&mut token);
-        let aspects = texture_guard[source.texture].full_range.aspects;
+        let dst_buffer = buffer_guard
+            .get(destination.buffer)
+            .ok_or(CommandEncoderError::InvalidBuffer(destination.buffer))?;
+        check_device(
+            cmb.device_id.value,
+            destination.buffer,
+            dst_buffer.device_id.value,
+        )?;
+        let src_texture = texture_guard
+            .get(source.texture)
+            .ok_or(CommandEncoderError::InvalidTexture(source.texture))?;
+        check_device(
+            cmb.device_id.value,
+            source.texture,
+            src_texture.device_id.value,
+        )?;
+        let aspects = src_texture.full_range.aspects;
+        validate_texture_copy(source, src_texture, cmb.private_features, &copy_size);
 
         #[cfg(feature = "trace")]
         match cmb.commands {
@@ -297,6 +883,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             image_offset: conv::map_origin(source.origin),
             image_extent: conv::map_extent(copy_size),
         };
+        cmb.count_copy();
         let cmb_raw = cmb.raw.last_mut().unwrap();
         let stages = all_buffer_stages() | all_image_stages();
         unsafe {
@@ -312,6 +899,68 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 iter::once(region),
             );
         }
+        Ok(())
+    }
+
+    /// Copy a texture region into a buffer, computing the buffer layout
+    /// instead of expecting it from the caller.
+    ///
+    /// Rows are tightly packed, up to the required row pitch alignment.
+    pub fn command_encoder_copy_texture_to_buffer_with_layout<B: GfxBackend>(
+        &self,
+        command_encoder_id: CommandEncoderId,
+        source: &TextureCopyView,
+        destination: BufferId,
+        destination_offset: BufferAddress,
+        copy_size: Extent3d,
+    ) -> Result<TextureDataLayout, CommandEncoderError> {
+        let layout = {
+            let hub = B::hub(self);
+            let mut token = Token::root();
+            let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
+            let private_features =
+                recording_encoder(&mut *cmb_guard, command_encoder_id)?.private_features;
+            let (buffer_guard, mut token) = hub.buffers.read(&mut token);
+            let (texture_guard, _) = hub.textures.read(&mut token);
+            let buffer_size = buffer_guard
+                .get(destination)
+                .ok_or(CommandEncoderError::InvalidBuffer(destination))?
+                .size;
+            let texture = texture_guard
+                .get(source.texture)
+                .ok_or(CommandEncoderError::InvalidTexture(source.texture))?;
+
+            let format_desc =
+                conv::map_texture_format(texture.format, private_features).surface_desc();
+            let layout = TextureDataLayout::packed(
+                format_desc.dim,
+                format_desc.bits,
+                destination_offset,
+                copy_size,
+            );
+
+            assert!(
+                layout.fits(buffer_size),
+                "Destination buffer of size {} can't fit {} bytes at offset {}",
+                buffer_size,
+                layout.required_size,
+                destination_offset
+            );
+            layout
+        };
+
+        self.command_encoder_copy_texture_to_buffer::<B>(
+            command_encoder_id,
+            source,
+            &BufferCopyView {
+                buffer: destination,
+                offset: layout.offset,
+                bytes_per_row: layout.bytes_per_row,
+                rows_per_image: layout.rows_per_image,
+            },
+            copy_size,
+        )?;
+        Ok(layout)
     }
 
     pub fn command_encoder_copy_texture_to_texture<B: GfxBackend>(
@@ -320,19 +969,61 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         source: &TextureCopyView,
         destination: &TextureCopyView,
         copy_size: Extent3d,
-    ) {
+    ) -> Result<(), CommandEncoderError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (mut cmb_guard, mut token) = hub.command_buffers.write(&mut token);
-        let cmb = &mut cmb_guard[command_encoder_id];
+        let cmb = recording_encoder(&mut *cmb_guard, command_encoder_id)?;
+        if !cmb.check_no_open_pass("copy_texture_to_texture") {
+            return Ok(());
+        }
         let (_, mut token) = hub.buffers.read(&mut token); // skip token
         let (texture_guard, _) = hub.textures.read(&mut token);
         // we can't hold both src_pending and dst_pending in scope because they
         // borrow the buffer tracker mutably...
         let mut barriers = Vec::new();
+        for view in &[source, destination] {
+            let texture = texture_guard
+                .get(view.texture)
+                .ok_or(CommandEncoderError::InvalidTexture(view.texture))?;
+            check_device(cmb.device_id.value, view.texture, texture.device_id.value)?;
+        }
         let aspects = texture_guard[source.texture].full_range.aspects
             & texture_guard[destination.texture].full_range.aspects;
+        for view in &[source, destination] {
+            validate_texture_copy(
+                view,
+                &texture_guard[view.texture],
+                cmb.private_features,
+                &copy_size,
+            );
+        }
+        if let Err(err) = validate_texture_copy_formats(
+            texture_guard[source.texture].format,
+            texture_guard[destination.texture].format,
+            source.texture.backend(),
+            cmb.private_features,
+        ) {
+            panic!(
+                "Invalid copy from texture {:?} to {:?}: {:?}",
+                source.texture, destination.texture, err
+            );
+        }
+        {
+            let texture = &texture_guard[source.texture];
+            let block_dims = conv::map_texture_format(texture.format, cmb.private_features)
+                .surface_desc()
+                .dim;
+            if let Err(err) =
+                validate_texture_copy_overlap(source, destination, block_dims, &copy_size)
+            {
+                panic!(
+                    "Invalid copy within texture {:?}: {:?}",
+                    source.texture, err
+                );
+            }
+        }
 
         #[cfg(feature = "trace")]
         match cmb.commands {
@@ -377,6 +1068,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             dst_offset: conv::map_origin(destination.origin),
             extent: conv::map_extent(copy_size),
         };
+        cmb.count_copy();
         let cmb_raw = cmb.raw.last_mut().unwrap();
         unsafe {
             cmb_raw.pipeline_barrier(
@@ -392,5 +1084,453 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 iter::once(region),
             );
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packed_texture_data_layout() {
+        let size = Extent3d {
+            width: 100,
+            height: 8,
+            depth: 2,
+        };
+        // 400-byte rows of RGBA8 texels are padded to 512 bytes
+        let layout = TextureDataLayout::packed((1, 1), 32, 0, size);
+        assert_eq!((layout.bytes_per_row, layout.rows_per_image), (512, 8));
+        assert_eq!(layout.required_size, 512 * 8 * 2);
+        assert!(layout.fits(512 * 8 * 2));
+        assert!(!layout.fits(512 * 8 * 2 - 1));
+
+        // Partial BC1 blocks count as whole ones: 3 blocks of 8 bytes in 2 rows,
+        // which are 8 rows of texels
+        let size = Extent3d {
+            width: 10,
+            height: 6,
+            depth: 1,
+        };
+        let layout = TextureDataLayout::packed((4, 4), 64, 256, size);
+        assert_eq!((layout.bytes_per_row, layout.rows_per_image), (256, 8));
+        assert_eq!(layout.required_size, 512);
+        assert!(layout.fits(768));
+        assert!(!layout.fits(512));
+
+        // Rows that are already aligned are not padded further.
+        let size = Extent3d {
+            width: 64,
+            height: 1,
+            depth: 1,
+        };
+        let layout = TextureDataLayout::packed((1, 1), 32, BufferAddress::max_value(), size);
+        assert_eq!(layout.bytes_per_row, 256);
+        // The end of the data overflows at the very end of the address space.
+        assert!(!layout.fits(BufferAddress::max_value()));
+    }
+
+    const SRC: BufferCopySide = BufferCopySide {
+        usage: BufferUsage::COPY_SRC,
+        buffer_size: 256,
+        offset: 0,
+    };
+    const DST: BufferCopySide = BufferCopySide {
+        usage: BufferUsage::COPY_DST,
+        buffer_size: 256,
+        offset: 0,
+    };
+
+    #[test]
+    fn buffer_copy_usage() {
+        let src = BufferCopySide {
+            usage: BufferUsage::COPY_DST,
+            ..SRC
+        };
+        assert_eq!(
+            validate_buffer_copy(&src, &DST, 4, false),
+            Err(BufferCopyError::MissingCopySrcUsage(BufferUsage::COPY_DST))
+        );
+        let dst = BufferCopySide {
+            usage: BufferUsage::COPY_SRC,
+            ..DST
+        };
+        assert_eq!(
+            validate_buffer_copy(&SRC, &dst, 4, false),
+            Err(BufferCopyError::MissingCopyDstUsage(BufferUsage::COPY_SRC))
+        );
+    }
+
+    #[test]
+    fn buffer_copy_alignment() {
+        assert_eq!(
+            validate_buffer_copy(&SRC, &DST, 6, false),
+            Err(BufferCopyError::UnalignedCopySize(6))
+        );
+        let src = BufferCopySide { offset: 2, ..SRC };
+        assert_eq!(
+            validate_buffer_copy(&src, &DST, 4, false),
+            Err(BufferCopyError::UnalignedSourceOffset(2))
+        );
+        let dst = BufferCopySide { offset: 1, ..DST };
+        assert_eq!(
+            validate_buffer_copy(&SRC, &dst, 4, false),
+            Err(BufferCopyError::UnalignedDestinationOffset(1))
+        );
+    }
+
+    #[test]
+    fn buffer_copy_bounds() {
+        // ending exactly at the end of the buffer is fine
+        let src = BufferCopySide { offset: 252, ..SRC };
+        assert_eq!(validate_buffer_copy(&src, &DST, 4, false), Ok(()));
+        assert_eq!(
+            validate_buffer_copy(&src, &DST, 8, false),
+            Err(BufferCopyError::SourceOutOfBounds {
+                offset: 252,
+                size: 8,
+                buffer_size: 256,
+            })
+        );
+        let dst = BufferCopySide { offset: 256, ..DST };
+        assert_eq!(validate_buffer_copy(&SRC, &dst, 0, false), Ok(()));
+        assert_eq!(
+            validate_buffer_copy(&SRC, &dst, 4, false),
+            Err(BufferCopyError::DestinationOutOfBounds {
+                offset: 256,
+                size: 4,
+                buffer_size: 256,
+            })
+        );
+    }
+
+    #[test]
+    fn buffer_copy_overflow() {
+        let max_offset = !0 - (!0 % COPY_BUFFER_ALIGNMENT);
+        let src = BufferCopySide {
+            offset: max_offset,
+            ..SRC
+        };
+        assert_eq!(
+            validate_buffer_copy(&src, &DST, 4, false),
+            Err(BufferCopyError::SourceOutOfBounds {
+                offset: max_offset,
+                size: 4,
+                buffer_size: 256,
+            })
+        );
+        let dst = BufferCopySide {
+            offset: max_offset,
+            ..DST
+        };
+        assert_eq!(
+            validate_buffer_copy(&SRC, &dst, max_offset, false),
+            Err(BufferCopyError::SourceOutOfBounds {
+                offset: 0,
+                size: max_offset,
+                buffer_size: 256,
+            })
+        );
+    }
+
+    #[test]
+    fn buffer_copy_overlap() {
+        let both = BufferUsage::COPY_SRC | BufferUsage::COPY_DST;
+        let src = BufferCopySide {
+            usage: both,
+            ..SRC
+        };
+        let dst = BufferCopySide {
+            usage: both,
+            offset: 60,
+            ..DST
+        };
+        assert_eq!(
+            validate_buffer_copy(&src, &dst, 64, true),
+            Err(BufferCopyError::OverlappingRanges)
+        );
+        // adjacent ranges don't overlap
+        assert_eq!(validate_buffer_copy(&src, &dst, 60, true), Ok(()));
+        // different buffers are never overlapping
+        assert_eq!(validate_buffer_copy(&src, &dst, 64, false), Ok(()));
+        // zero-sized copies are a no-op
+        assert_eq!(validate_buffer_copy(&src, &src, 0, true), Ok(()));
+    }
+
+    #[test]
+    fn buffer_copy_region_overlap() {
+        let both = BufferCopySide {
+            usage: BufferUsage::COPY_SRC | BufferUsage::COPY_DST,
+            ..SRC
+        };
+        let region = |src_offset, dst_offset, size| BufferCopyRegion {
+            src_offset,
+            dst_offset,
+            size,
+        };
+        // sources may overlap each other, destinations may not
+        let regions = [region(0, 64, 16), region(8, 128, 16), region(0, 136, 8)];
+        assert_eq!(
+            validate_buffer_copy_regions(&SRC, &DST, &regions, false),
+            Err(BufferCopyError::OverlappingRegions {
+                first: 1,
+                second: 2,
+            })
+        );
+        assert_eq!(
+            validate_buffer_copy_regions(&SRC, &DST, &regions[..2], false),
+            Ok(())
+        );
+        // within the same buffer, a source may not overlap another destination
+        let regions = [region(0, 128, 16), region(132, 64, 8), region(200, 240, 0)];
+        assert_eq!(
+            validate_buffer_copy_regions(&both, &both, &regions, true),
+            Err(BufferCopyError::OverlappingRegions {
+                first: 0,
+                second: 1,
+            })
+        );
+        assert_eq!(
+            validate_buffer_copy_regions(&both, &both, &regions, false),
+            Ok(())
+        );
+        // regions are validated individually too
+        assert_eq!(
+            validate_buffer_copy_regions(&SRC, &DST, &[region(0, 250, 8)], false),
+            Err(BufferCopyError::DestinationOutOfBounds {
+                offset: 250,
+                size: 8,
+                buffer_size: 256,
+            })
+        );
+    }
+
+    fn copy_view(mip_level: u32, x: u32, y: u32) -> TextureCopyView {
+        use crate::id::TypedId as _;
+        TextureCopyView {
+            texture: TextureId::zip(0, 1, wgt::Backend::Empty),
+            mip_level,
+            array_layer: 0,
+            origin: Origin3d { x, y, z: 0 },
+        }
+    }
+
+    fn extent(width: u32, height: u32, depth: u32) -> Extent3d {
+        Extent3d {
+            width,
+            height,
+            depth,
+        }
+    }
+
+    #[test]
+    fn texture_copy_thin_mips() {
+        let texture = conv::map_extent(extent(1, 100, 1));
+        for level in 0..7 {
+            let height = (100 >> level).max(1);
+            let view = copy_view(level, 0, 0);
+            let full = extent(1, height, 1);
+            assert_eq!(
+                validate_texture_copy_range(&view, texture, 7, (1, 1), &full),
+                Ok(())
+            );
+            assert_eq!(
+                validate_texture_copy_range(&view, texture, 7, (1, 1), &extent(2, height, 1)),
+                Err(TextureCopyError::OutOfBounds {
+                    origin: view.origin,
+                    size: extent(2, height, 1),
+                    level_extent: full,
+                })
+            );
+        }
+        assert_eq!(
+            validate_texture_copy_range(&copy_view(7, 0, 0), texture, 7, (1, 1), &extent(1, 1, 1)),
+            Err(TextureCopyError::InvalidMipLevel {
+                level: 7,
+                level_count: 7,
+            })
+        );
+    }
+
+    #[test]
+    fn texture_copy_3d_depth() {
+        let texture = conv::map_extent(extent(16, 16, 6));
+        let view = copy_view(1, 0, 0);
+        assert_eq!(
+            validate_texture_copy_range(&view, texture, 5, (1, 1), &extent(8, 8, 3)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_texture_copy_range(&view, texture, 5, (1, 1), &extent(8, 8, 4)),
+            Err(TextureCopyError::OutOfBounds {
+                origin: view.origin,
+                size: extent(8, 8, 4),
+                level_extent: extent(8, 8, 3),
+            })
+        );
+        // 6 -> 3 -> 1, rounding down
+        let view = copy_view(2, 0, 0);
+        assert_eq!(
+            validate_texture_copy_range(&view, texture, 5, (1, 1), &extent(4, 4, 1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn texture_copy_compressed_edge_blocks() {
+        let texture = conv::map_extent(extent(18, 18, 1));
+        // level 2 is 4x4 texels, level 3 is 2x2, both a single block
+        for level in 2..5 {
+            assert_eq!(
+                validate_texture_copy_range(
+                    &copy_view(level, 0, 0),
+                    texture,
+                    5,
+                    (4, 4),
+                    &extent(4, 4, 1)
+                ),
+                Ok(())
+            );
+        }
+        // level 1 is 9x9 texels, covered by 3x3 blocks
+        assert_eq!(
+            validate_texture_copy_range(&copy_view(1, 8, 8), texture, 5, (4, 4), &extent(4, 4, 1)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_texture_copy_range(&copy_view(3, 0, 0), texture, 5, (4, 4), &extent(2, 2, 1)),
+            Err(TextureCopyError::UnalignedSize(extent(2, 2, 1)))
+        );
+        assert_eq!(
+            validate_texture_copy_range(&copy_view(3, 2, 0), texture, 5, (4, 4), &extent(4, 4, 1)),
+            Err(TextureCopyError::UnalignedOrigin(copy_view(3, 2, 0).origin))
+        );
+        assert_eq!(
+            validate_texture_copy_range(&copy_view(3, 4, 0), texture, 5, (4, 4), &extent(4, 4, 1)),
+            Err(TextureCopyError::OutOfBounds {
+                origin: copy_view(3, 4, 0).origin,
+                size: extent(4, 4, 1),
+                level_extent: extent(4, 4, 1),
+            })
+        );
+    }
+
+    #[test]
+    fn texture_copy_overlap() {
+        let size = extent(8, 8, 1);
+        // different mip levels or layers are disjoint subresources
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &copy_view(1, 0, 0), (1, 1), &size),
+            Ok(())
+        );
+        let layer = TextureCopyView {
+            array_layer: 1,
+            ..copy_view(0, 0, 0)
+        };
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &layer, (1, 1), &size),
+            Ok(())
+        );
+        // different textures never overlap
+        let other = TextureCopyView {
+            texture: {
+                use crate::id::TypedId as _;
+                TextureId::zip(1, 1, wgt::Backend::Empty)
+            },
+            ..copy_view(0, 0, 0)
+        };
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &other, (1, 1), &size),
+            Ok(())
+        );
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &copy_view(0, 4, 4), (1, 1), &size),
+            Err(TextureCopyError::OverlappingRegions {
+                level: 0,
+                layer: 0,
+                src: (copy_view(0, 0, 0).origin, size),
+                dst: (copy_view(0, 4, 4).origin, size),
+            })
+        );
+        // adjacent regions only touch, but still share the subresource
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &copy_view(0, 8, 0), (1, 1), &size),
+            Err(TextureCopyError::SameSubresource { level: 0, layer: 0 })
+        );
+        // 3D slices are compared too
+        let slice = TextureCopyView {
+            origin: Origin3d { x: 0, y: 0, z: 1 },
+            ..copy_view(0, 0, 0)
+        };
+        assert_eq!(
+            validate_texture_copy_overlap(&copy_view(0, 0, 0), &slice, (1, 1), &size),
+            Err(TextureCopyError::SameSubresource { level: 0, layer: 0 })
+        );
+        // compressed regions are compared in whole blocks
+        assert_eq!(
+            validate_texture_copy_overlap(
+                &copy_view(0, 0, 0),
+                &copy_view(0, 6, 0),
+                (4, 4),
+                &extent(6, 4, 1)
+            ),
+            Err(TextureCopyError::OverlappingRegions {
+                level: 0,
+                layer: 0,
+                src: (copy_view(0, 0, 0).origin, extent(6, 4, 1)),
+                dst: (copy_view(0, 6, 0).origin, extent(6, 4, 1)),
+            })
+        );
+        // zero-sized copies are a no-op
+        assert_eq!(
+            validate_texture_copy_overlap(
+                &copy_view(0, 0, 0),
+                &copy_view(0, 0, 0),
+                (1, 1),
+                &extent(0, 8, 1)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn texture_copy_formats() {
+        use TextureFormat as Tf;
+        let features = PrivateFeatures {
+            supports_texture_d24_s8: true,
+        };
+        let check =
+            |src, dst, backend| validate_texture_copy_formats(src, dst, backend, features).is_ok();
+        for &backend in &[
+            wgt::Backend::Vulkan,
+            wgt::Backend::Metal,
+            wgt::Backend::Dx12,
+        ] {
+            assert!(check(Tf::Rgba8Unorm, Tf::Rgba8UnormSrgb, backend));
+            assert!(check(Tf::Bgra8UnormSrgb, Tf::Bgra8Unorm, backend));
+            assert!(check(Tf::Depth32Float, Tf::Depth32Float, backend));
+            assert!(!check(Tf::Depth32Float, Tf::R32Float, backend));
+            assert!(!check(Tf::Rgba8Unorm, Tf::Rg32Float, backend));
+        }
+        // channels are swapped by reinterpreting the bits
+        assert!(check(
+            Tf::Rgba8Unorm,
+            Tf::Bgra8UnormSrgb,
+            wgt::Backend::Vulkan
+        ));
+        assert!(check(Tf::R32Float, Tf::Rgba8Uint, wgt::Backend::Metal));
+        assert_eq!(
+            validate_texture_copy_formats(
+                Tf::Rgba8Unorm,
+                Tf::Bgra8Unorm,
+                wgt::Backend::Dx12,
+                features
+            ),
+            Err(TextureCopyError::IncompatibleFormats {
+                src: Tf::Rgba8Unorm,
+                dst: Tf::Bgra8Unorm,
+            })
+        );
     }
 }
@@ -43,6 +43,58 @@ pub fn map_buffer_usage(usage: wgt::BufferUsage) -> (hal::buffer::Usage, hal::me
     (hal_usage, hal_memory)
 }
 
+/// Pick the memory of a buffer, from its usage and the memory hint.
+///
+/// Mappable buffers always end up in host-visible memory.
+pub fn map_buffer_memory_usage(
+    usage: wgt::BufferUsage,
+    hint: wgt::MemoryHint,
+) -> gfx_memory::MemoryUsage {
+    use gfx_memory::MemoryUsage as Mu;
+    use wgt::{BufferUsage as Bu, MemoryHint as Mh};
+
+    let mappable = usage.intersects(Bu::MAP_READ | Bu::MAP_WRITE);
+    match hint {
+        Mh::Performance if !mappable => Mu::Private,
+        Mh::Performance if (Bu::MAP_WRITE | Bu::COPY_SRC).contains(usage) => {
+            Mu::Staging { read_back: false }
+        }
+        Mh::Performance if (Bu::MAP_READ | Bu::COPY_DST).contains(usage) => {
+            Mu::Staging { read_back: true }
+        }
+        Mh::Performance => Mu::Dynamic {
+            sparse_updates: false,
+        },
+        Mh::MemoryUsage => Mu::Staging {
+            read_back: usage.contains(Bu::MAP_READ),
+        },
+        Mh::Upload => Mu::Dynamic {
+            sparse_updates: false,
+        },
+        Mh::Download => Mu::Staging { read_back: true },
+    }
+}
+
+pub fn map_memory_properties(properties: hal::memory::Properties) -> wgt::MemoryProperties {
+    use hal::memory::Properties as P;
+    use wgt::MemoryProperties as M;
+
+    let mut memory = M::empty();
+    if properties.contains(P::DEVICE_LOCAL) {
+        memory |= M::DEVICE_LOCAL;
+    }
+    if properties.contains(P::CPU_VISIBLE) {
+        memory |= M::HOST_VISIBLE;
+    }
+    if properties.contains(P::COHERENT) {
+        memory |= M::HOST_COHERENT;
+    }
+    if properties.contains(P::CPU_CACHED) {
+        memory |= M::HOST_CACHED;
+    }
+    memory
+}
+
 pub fn map_texture_usage(
     usage: wgt::TextureUsage,
     aspects: hal::format::Aspects,
@@ -70,8 +122,11 @@ pub fn map_texture_usage(
             value |= U::COLOR_ATTACHMENT;
         }
     }
+    if usage.contains(W::TRANSIENT_ATTACHMENT) {
+        value |= U::TRANSIENT_ATTACHMENT;
+    }
     // Note: TextureUsage::Present does not need to be handled explicitly
-    // TODO: HAL Transient Attachment, HAL Input Attachment
+    // TODO: HAL Input Attachment
     value
 }
 
@@ -97,7 +152,9 @@ pub fn map_binding_type(binding: &binding_model::BindGroupLayoutEntry) -> hal::p
                 dynamic_offset: binding.has_dynamic_offset,
             },
         },
-        Bt::Sampler | Bt::ComparisonSampler => pso::DescriptorType::Sampler,
+        Bt::Sampler | Bt::ComparisonSampler | Bt::NonFilteringSampler => {
+            pso::DescriptorType::Sampler
+        }
         Bt::SampledTexture => pso::DescriptorType::Image {
             ty: pso::ImageDescriptorType::Sampled {
                 with_sampler: false,
@@ -258,6 +315,7 @@ pub fn map_depth_stencil_state_descriptor(
         depth_bounds: false, // TODO
         stencil: if desc.stencil_read_mask != !0
             || desc.stencil_write_mask != !0
+            || desc.dynamic_stencil_masks
             || desc.stencil_front != wgt::StencilStateFaceDescriptor::IGNORE
             || desc.stencil_back != wgt::StencilStateFaceDescriptor::IGNORE
         {
@@ -266,8 +324,16 @@ pub fn map_depth_stencil_state_descriptor(
                     front: map_stencil_face(&desc.stencil_front),
                     back: map_stencil_face(&desc.stencil_back),
                 },
-                read_masks: hal::pso::State::Static(hal::pso::Sided::new(desc.stencil_read_mask)),
-                write_masks: hal::pso::State::Static(hal::pso::Sided::new(desc.stencil_write_mask)),
+                read_masks: if desc.dynamic_stencil_masks {
+                    hal::pso::State::Dynamic
+                } else {
+                    hal::pso::State::Static(hal::pso::Sided::new(desc.stencil_read_mask))
+                },
+                write_masks: if desc.dynamic_stencil_masks {
+                    hal::pso::State::Dynamic
+                } else {
+                    hal::pso::State::Static(hal::pso::Sided::new(desc.stencil_write_mask))
+                },
                 reference_values: if desc.needs_stencil_reference() {
                     hal::pso::State::Dynamic
                 } else {
@@ -432,6 +498,15 @@ pub fn map_vertex_format(vertex_format: wgt::VertexFormat) -> hal::format::Forma
         Vf::Int2 => H::Rg32Sint,
         Vf::Int3 => H::Rgb32Sint,
         Vf::Int4 => H::Rgba32Sint,
+        Vf::Uchar => H::R8Uint,
+        Vf::Char => H::R8Sint,
+        Vf::UcharNorm => H::R8Unorm,
+        Vf::CharNorm => H::R8Snorm,
+        Vf::Ushort => H::R16Uint,
+        Vf::Short => H::R16Sint,
+        Vf::UshortNorm => H::R16Unorm,
+        Vf::ShortNorm => H::R16Snorm,
+        Vf::Half => H::R16Sfloat,
     }
 }
 
@@ -525,6 +600,85 @@ pub(crate) fn map_buffer_state(usage: resource::BufferUse) -> hal::buffer::State
     access
 }
 
+/// Map the visibility of a storage texture binding to the
+/// `TextureUse::STORAGE_STAGES` it's accessed from.
+pub(crate) fn map_storage_stages(visibility: wgt::ShaderStage) -> resource::TextureUse {
+    use crate::resource::TextureUse as W;
+
+    // Other stages aren't tracked separately, so they could be anything.
+    if visibility.contains(wgt::ShaderStage::VERTEX) {
+        return W::empty();
+    }
+    let mut usage = W::empty();
+    if visibility.contains(wgt::ShaderStage::FRAGMENT) {
+        usage |= W::STORAGE_FRAGMENT;
+    }
+    if visibility.contains(wgt::ShaderStage::COMPUTE) {
+        usage |= W::STORAGE_COMPUTE;
+    }
+    usage
+}
+
+pub(crate) fn map_buffer_stages(usage: resource::BufferUse) -> hal::pso::PipelineStage {
+    use crate::resource::BufferUse as W;
+    use hal::pso::PipelineStage as Ps;
+
+    let mut stages = Ps::empty();
+    if usage.intersects(W::MAP_READ | W::MAP_WRITE) {
+        stages |= Ps::HOST;
+    }
+    if usage.intersects(W::COPY_SRC | W::COPY_DST) {
+        stages |= Ps::TRANSFER;
+    }
+    if usage.intersects(W::INDEX | W::VERTEX) {
+        stages |= Ps::VERTEX_INPUT;
+    }
+    if usage.intersects(W::UNIFORM | W::STORAGE_LOAD | W::STORAGE_STORE) {
+        stages |= all_shader_stages();
+    }
+    if usage.contains(W::INDIRECT) {
+        stages |= Ps::DRAW_INDIRECT;
+    }
+    stages
+}
+
+pub(crate) fn map_texture_stages(usage: resource::TextureUse) -> hal::pso::PipelineStage {
+    use crate::resource::TextureUse as W;
+    use hal::pso::PipelineStage as Ps;
+
+    if usage == W::UNINITIALIZED {
+        return Ps::empty();
+    }
+    let mut stages = Ps::empty();
+    if usage.intersects(W::COPY_SRC | W::COPY_DST) {
+        stages |= Ps::TRANSFER;
+    }
+    if usage.contains(W::SAMPLED) {
+        stages |= all_shader_stages();
+    }
+    if usage.contains(W::OUTPUT_ATTACHMENT) {
+        stages |= Ps::EARLY_FRAGMENT_TESTS | Ps::LATE_FRAGMENT_TESTS | Ps::COLOR_ATTACHMENT_OUTPUT;
+    }
+    if usage.intersects(W::STORAGE_LOAD | W::STORAGE_STORE) {
+        if usage.intersects(W::STORAGE_STAGES) {
+            if usage.contains(W::STORAGE_FRAGMENT) {
+                stages |= Ps::FRAGMENT_SHADER;
+            }
+            if usage.contains(W::STORAGE_COMPUTE) {
+                stages |= Ps::COMPUTE_SHADER;
+            }
+        } else {
+            stages |= all_shader_stages();
+        }
+    }
+    stages
+}
+
+fn all_shader_stages() -> hal::pso::PipelineStage {
+    use hal::pso::PipelineStage as Ps;
+    Ps::VERTEX_SHADER | Ps::TASK_SHADER | Ps::MESH_SHADER | Ps::FRAGMENT_SHADER | Ps::COMPUTE_SHADER
+}
+
 pub(crate) fn map_texture_state(
     usage: resource::TextureUse,
     aspects: hal::format::Aspects,
@@ -576,9 +730,10 @@ pub fn map_load_store_ops(load: wgt::LoadOp, store: wgt::StoreOp) -> hal::pass::
         load: match load {
             wgt::LoadOp::Clear => hal::pass::AttachmentLoadOp::Clear,
             wgt::LoadOp::Load => hal::pass::AttachmentLoadOp::Load,
+            wgt::LoadOp::DontCare => hal::pass::AttachmentLoadOp::DontCare,
         },
         store: match store {
-            wgt::StoreOp::Clear => hal::pass::AttachmentStoreOp::DontCare, //TODO!
+            wgt::StoreOp::Clear => hal::pass::AttachmentStoreOp::DontCare,
             wgt::StoreOp::Store => hal::pass::AttachmentStoreOp::Store,
         },
     }
@@ -642,7 +797,9 @@ pub fn map_rasterization_state_descriptor(
             wgt::FrontFace::Ccw => pso::FrontFace::CounterClockwise,
             wgt::FrontFace::Cw => pso::FrontFace::Clockwise,
         },
-        depth_bias: if desc.depth_bias != 0
+        depth_bias: if desc.dynamic_depth_bias {
+            Some(pso::State::Dynamic)
+        } else if desc.depth_bias != 0
             || desc.depth_bias_slope_scale != 0.0
             || desc.depth_bias_clamp != 0.0
         {
@@ -654,7 +811,7 @@ pub fn map_rasterization_state_descriptor(
         } else {
             None
         },
-        conservative: false,
+        conservative: desc.conservative,
         line_width: pso::State::Static(1.0),
     }
 }
@@ -0,0 +1,661 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use crate::{
+    conv,
+    device::{
+        all_buffer_stages, all_image_stages, map_buffer,
+        trace::{Action, Trace},
+        unmap_buffer, Device, HostMap, TextureWriteLayout, TextureWriteRegion,
+    },
+    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Storage, Token},
+    id,
+    resource::{
+        mip_level_extent, Buffer, BufferMapState, BufferUse, Texture, TextureUse, TextureViewInner,
+    },
+};
+
+use gfx_memory::{Block, MemoryBlock};
+use hal::{command::CommandBuffer as _, device::Device as _, queue::CommandQueue as _};
+use std::{iter, path::Path, ptr, slice, sync::atomic::Ordering};
+
+impl<G: GlobalIdentityHandlerFactory> Global<G> {
+    /// Start tracing the device into the `path` directory.
+    ///
+    /// The trace begins with the creation of every resource currently living
+    /// on the device, followed by the contents of its buffers and textures, so
+    /// that it can be replayed on its own. Encoders that are still recording
+    /// are traced from now on.
+    pub fn device_start_capture<B: GfxBackend>(&self, device_id: id::DeviceId, path: &Path) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = match device_guard.get_mut(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!("Capturing an invalid device {:?}", device_id);
+                return;
+            }
+        };
+
+        if device.trace.lock().is_some() {
+            log::warn!("Device {:?} is already traced", device_id);
+            return;
+        }
+        // Flush the pending writes, so that the snapshot includes them.
+        device.flush_pending_writes(&mut token);
+        let mut trace = match Trace::new(path) {
+            Ok(trace) => trace,
+            Err(e) => {
+                log::warn!("Unable to start a trace in '{:?}': {:?}", path, e);
+                return;
+            }
+        };
+        trace.add(Action::Init {
+            desc: wgt::DeviceDescriptor {
+                extensions: device.extensions.clone(),
+                limits: device.limits.clone(),
+                deduplicate_objects: device.deduplicate_objects,
+                staging_chunk_size: Some(device.staging_chunk_size),
+                hang_timeout_ms: device
+                    .hang_timeout
+                    .map(|timeout| timeout.as_millis() as u64),
+                max_submissions_in_flight: Some(device.max_submissions_in_flight as u32),
+                nonblocking_submit: device.nonblocking_submit,
+                strict_webgpu: device.strict_webgpu,
+            },
+            backend: B::VARIANT,
+            deterministic_ids: self.deterministic_ids,
+        });
+
+        // The commands of the encoders that are still recording are traced from
+        // now on, and the ones they recorded so far are missing from the trace.
+        {
+            let (mut cmb_guard, _) = hub.command_buffers.write(&mut token);
+            let encoder_ids = cmb_guard
+                .iter(B::VARIANT)
+                .filter(|&(_, cmb)| cmb.device_id.value == device_id && cmb.commands.is_none())
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+            for id in encoder_ids {
+                let cmb = &mut cmb_guard[id];
+                if !cmb.passes.is_empty() {
+                    log::warn!(
+                        "Commands recorded by encoder {:?} before the capture are not traced",
+                        id
+                    );
+                }
+                cmb.commands = Some(Vec::new());
+            }
+        }
+
+        // Resources are listed in the order of their dependencies.
+        {
+            let (shader_module_guard, _) = hub.shader_modules.read(&mut token);
+            for (id, module) in shader_module_guard.iter(B::VARIANT) {
+                if module.device_id.value != device_id {
+                    continue;
+                }
+                let data = trace.make_binary("spv", unsafe {
+                    slice::from_raw_parts(module.code.as_ptr() as *const u8, module.code.len() * 4)
+                });
+                trace.add(Action::CreateShaderModule {
+                    id,
+                    label: module.label.clone(),
+                    data,
+                });
+            }
+        }
+        {
+            let (bind_group_layout_guard, _) = hub.bind_group_layouts.read(&mut token);
+            for (id, layout) in bind_group_layout_guard.iter(B::VARIANT) {
+                if layout.device_id.value != device_id {
+                    continue;
+                }
+                let mut entries = layout.entries.values().cloned().collect::<Vec<_>>();
+                entries.sort_by_key(|entry| entry.binding);
+                trace.add(Action::CreateBindGroupLayout {
+                    id,
+                    label: layout.label.clone(),
+                    entries,
+                });
+            }
+        }
+        {
+            let (pipeline_layout_guard, _) = hub.pipeline_layouts.read(&mut token);
+            for (id, layout) in pipeline_layout_guard.iter(B::VARIANT) {
+                if layout.device_id.value != device_id {
+                    continue;
+                }
+                trace.add(Action::CreatePipelineLayout {
+                    id,
+                    bind_group_layouts: layout
+                        .bind_group_layout_ids
+                        .iter()
+                        .map(|stored| stored.value)
+                        .collect(),
+                });
+            }
+        }
+
+        let mut readback_ids = Vec::new();
+        {
+            let (buffer_guard, _) = hub.buffers.read(&mut token);
+            for (id, buffer) in buffer_guard.iter(B::VARIANT) {
+                if buffer.device_id.value != device_id {
+                    continue;
+                }
+                let mut usage = buffer.usage;
+                if can_read_back(buffer) {
+                    // The contents are restored with `queue_write_buffer`.
+                    usage |= wgt::BufferUsage::COPY_DST;
+                    readback_ids.push(id);
+                } else {
+                    log::warn!("Contents of buffer {:?} can't be captured", id);
+                }
+                trace.add(Action::CreateBuffer {
+                    id,
+                    desc: wgt::BufferDescriptor {
+                        label: buffer.label.clone(),
+                        size: buffer.size,
+                        usage,
+                        memory_hint: buffer.memory_hint,
+                        transient: buffer.memory.is_transient(),
+                    },
+                });
+            }
+        }
+        let mut texture_readback_ids = Vec::new();
+        {
+            let (texture_guard, mut token) = hub.textures.read(&mut token);
+            for (id, texture) in texture_guard.iter(B::VARIANT) {
+                if texture.device_id.value != device_id {
+                    continue;
+                }
+                let mut usage = texture.usage;
+                if can_read_back_texture(texture) {
+                    // The contents are restored with `queue_write_texture_regions`.
+                    usage |= wgt::TextureUsage::COPY_DST;
+                    texture_readback_ids.push(id);
+                } else {
+                    log::warn!("Contents of texture {:?} can't be captured", id);
+                }
+                let (dimension, size, sample_count) = match texture.kind {
+                    hal::image::Kind::D1(width, layers) => (
+                        wgt::TextureDimension::D1,
+                        wgt::Extent3d {
+                            width,
+                            height: 1,
+                            depth: layers as u32,
+                        },
+                        1,
+                    ),
+                    hal::image::Kind::D2(width, height, layers, samples) => (
+                        wgt::TextureDimension::D2,
+                        wgt::Extent3d {
+                            width,
+                            height,
+                            depth: layers as u32,
+                        },
+                        samples as u32,
+                    ),
+                    hal::image::Kind::D3(width, height, depth) => (
+                        wgt::TextureDimension::D3,
+                        wgt::Extent3d {
+                            width,
+                            height,
+                            depth,
+                        },
+                        1,
+                    ),
+                };
+                trace.add(Action::CreateTexture {
+                    id,
+                    desc: wgt::TextureDescriptor {
+                        label: texture.label.clone(),
+                        size,
+                        mip_level_count: texture.full_range.levels.end as u32,
+                        sample_count,
+                        dimension,
+                        format: texture.format,
+                        usage,
+                        transient: texture.memory.is_transient(),
+                    },
+                });
+            }
+
+            let (texture_view_guard, _) = hub.texture_views.read(&mut token);
+            for (id, view) in texture_view_guard.iter(B::VARIANT) {
+                match view.inner {
+                    TextureViewInner::Native { ref source_id, .. }
+                        if texture_guard[source_id.value].device_id.value == device_id =>
+                    {
+                        trace.add(Action::CreateTextureView {
+                            id,
+                            parent_id: source_id.value,
+                            desc: view.trace_desc.clone(),
+                        });
+                    }
+                    // Swap chain images are re-acquired by the replay.
+                    _ => {}
+                }
+            }
+        }
+        {
+            let (sampler_guard, _) = hub.samplers.read(&mut token);
+            for (id, sampler) in sampler_guard.iter(B::VARIANT) {
+                if sampler.device_id.value != device_id {
+                    continue;
+                }
+                trace.add(Action::CreateSampler {
+                    id,
+                    desc: sampler.trace_desc.clone(),
+                });
+            }
+        }
+        {
+            let (bind_group_guard, _) = hub.bind_groups.read(&mut token);
+            for (id, bind_group) in bind_group_guard.iter(B::VARIANT) {
+                if bind_group.device_id.value != device_id {
+                    continue;
+                }
+                trace.add(bind_group.trace_desc.clone().into_action(id));
+            }
+        }
+        {
+            let (compute_pipeline_guard, _) = hub.compute_pipelines.read(&mut token);
+            for (id, pipeline) in compute_pipeline_guard.iter(B::VARIANT) {
+                if pipeline.device_id.value != device_id {
+                    continue;
+                }
+                trace.add(Action::CreateComputePipeline {
+                    id,
+                    desc: pipeline.trace_desc.clone(),
+                });
+            }
+        }
+        {
+            let (render_pipeline_guard, _) = hub.render_pipelines.read(&mut token);
+            for (id, pipeline) in render_pipeline_guard.iter(B::VARIANT) {
+                if pipeline.device_id.value != device_id {
+                    continue;
+                }
+                trace.add(pipeline.trace_desc.clone().into_action(id));
+            }
+        }
+
+        if !readback_ids.is_empty() {
+            let (mut buffer_guard, _) = hub.buffers.write(&mut token);
+            read_back_buffers(device, &mut *buffer_guard, &readback_ids, &mut trace);
+        }
+        if !texture_readback_ids.is_empty() {
+            let (texture_guard, _) = hub.textures.read(&mut token);
+            read_back_textures(device, &*texture_guard, &texture_readback_ids, &mut trace);
+        }
+
+        *device.trace.lock() = Some(trace);
+    }
+
+    /// Stop tracing the device, finishing the trace file.
+    pub fn device_stop_capture<B: GfxBackend>(&self, device_id: id::DeviceId) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+
+        match device_guard.get(device_id) {
+            Some(device) => match device.trace.lock().take() {
+                Some(_) => log::info!("Stopped tracing device {:?}", device_id),
+                None => log::warn!("Device {:?} is not traced", device_id),
+            },
+            None => log::error!("Stopping the capture of an invalid device {:?}", device_id),
+        }
+    }
+}
+
+/// Check if the buffer contents are reachable, either with a GPU copy or by mapping.
+fn can_read_back<B: hal::Backend>(buffer: &Buffer<B>) -> bool {
+    // Dropped buffers don't have a ref count to register in the tracker with.
+    if buffer.life_guard.ref_count.is_none() {
+        return false;
+    }
+    if buffer.usage.contains(wgt::BufferUsage::COPY_SRC) {
+        return true;
+    }
+    match buffer.map_state {
+        BufferMapState::Idle => buffer.usage.contains(wgt::BufferUsage::MAP_READ),
+        _ => false,
+    }
+}
+
+/// Check if the texture contents can be copied out, and written back on replay.
+fn can_read_back_texture<B: hal::Backend>(texture: &Texture<B>) -> bool {
+    texture.life_guard.ref_count.is_some()
+        && texture.usage.contains(wgt::TextureUsage::COPY_SRC)
+        && texture.kind.num_samples() == 1
+        && texture.full_range.aspects == hal::format::Aspects::COLOR
+}
+
+/// Create a buffer of `size` bytes in host-visible memory, to copy into.
+fn create_readback_buffer<B: GfxBackend>(
+    device: &Device<B>,
+    size: wgt::BufferAddress,
+) -> (B::Buffer, MemoryBlock<B>) {
+    let mut raw = unsafe {
+        device
+            .raw
+            .create_buffer(size, hal::buffer::Usage::TRANSFER_DST)
+            .unwrap()
+    };
+    let requirements = unsafe { device.raw.get_buffer_requirements(&raw) };
+    let memory = device
+        .mem_allocator
+        .lock()
+        .allocate(
+            &device.raw,
+            &requirements,
+            gfx_memory::MemoryUsage::Staging { read_back: true },
+            gfx_memory::Kind::Linear,
+        )
+        .unwrap();
+    unsafe {
+        device.raw.set_buffer_name(&mut raw, "<capture_temp>");
+        device
+            .raw
+            .bind_buffer_memory(memory.memory(), memory.segment().offset, &mut raw)
+            .unwrap();
+    }
+    (raw, memory)
+}
+
+/// Finish the copies recorded into `comb` with a barrier making `targets`
+/// visible to the host, submit them, and wait for them to complete.
+fn submit_readback<'a, B: GfxBackend>(
+    device: &mut Device<B>,
+    mut comb: B::CommandBuffer,
+    targets: impl Iterator<Item = &'a B::Buffer>,
+) {
+    unsafe {
+        comb.pipeline_barrier(
+            hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::HOST,
+            hal::memory::Dependencies::empty(),
+            targets.map(|raw| hal::memory::Barrier::Buffer {
+                states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::HOST_READ,
+                target: raw,
+                range: hal::buffer::SubRange::WHOLE,
+                families: None,
+            }),
+        );
+        comb.finish();
+
+        let fence = device.raw.create_fence(false).unwrap();
+        device.queue_group.queues[0].submit_without_semaphores(iter::once(&comb), Some(&fence));
+        device.raw.wait_for_fence(&fence, !0).unwrap();
+        device.raw.destroy_fence(fence);
+    }
+    device.com_allocator.discard_internal(comb);
+}
+
+/// Read the first `size` bytes of a buffer created by `create_readback_buffer`,
+/// and free it.
+fn finish_readback<B: GfxBackend>(
+    device: &Device<B>,
+    raw: B::Buffer,
+    mut memory: MemoryBlock<B>,
+    size: wgt::BufferAddress,
+) -> Vec<u8> {
+    let (ptr, segment, needs_sync) = {
+        let mapped = memory.map(&device.raw, hal::memory::Segment::ALL).unwrap();
+        let mr = mapped.range();
+        let segment = hal::memory::Segment {
+            offset: mr.start,
+            size: Some(mr.end - mr.start),
+        };
+        (mapped.ptr(), segment, !mapped.is_coherent())
+    };
+    let mut data = vec![0u8; size as usize];
+    unsafe {
+        if needs_sync {
+            device
+                .raw
+                .invalidate_mapped_memory_ranges(iter::once((memory.memory(), segment)))
+                .unwrap();
+        }
+        ptr::copy_nonoverlapping(ptr.as_ptr(), data.as_mut_ptr(), data.len());
+    }
+
+    device.mem_allocator.lock().free(&device.raw, memory);
+    unsafe {
+        device.raw.destroy_buffer(raw);
+    }
+    data
+}
+
+/// Record the current contents of the buffers into the trace.
+///
+/// Copyable buffers are copied into staging memory on the GPU, and the
+/// device waits for the copies to finish. The others are mapped directly,
+/// once the submissions in flight that use them are done.
+fn read_back_buffers<B: GfxBackend>(
+    device: &mut Device<B>,
+    buffer_guard: &mut Storage<Buffer<B>, id::BufferId>,
+    buffer_ids: &[id::BufferId],
+    trace: &mut Trace,
+) {
+    let mut staging = Vec::new();
+    for &id in buffer_ids {
+        let buffer = &mut buffer_guard[id];
+        if buffer.usage.contains(wgt::BufferUsage::COPY_SRC) {
+            let (raw, memory) = create_readback_buffer(device, buffer.size);
+            staging.push((id, raw, memory));
+            continue;
+        }
+
+        // Unlike the copies, mapping isn't ordered after the submissions in flight.
+        let last_use = buffer.life_guard.submission_index.load(Ordering::Acquire);
+        match device
+            .life_tracker
+            .get_mut()
+            .wait_for_submission(&device.raw, last_use, !0)
+        {
+            Ok(true) => {}
+            Ok(false) | Err(_) => {
+                log::error!("failed to wait for buffer {:?} to be idle", id);
+                continue;
+            }
+        }
+
+        match map_buffer(
+            &device.raw,
+            buffer,
+            hal::buffer::SubRange::WHOLE,
+            HostMap::Read,
+        ) {
+            Ok(ptr) => {
+                let data = unsafe { slice::from_raw_parts(ptr, buffer.size as usize) };
+                let data_path = trace.make_binary("bin", data);
+                trace.add(Action::WriteBuffer {
+                    id,
+                    data: data_path,
+                    range: 0..buffer.size,
+                    queued: true,
+                });
+                unmap_buffer(&device.raw, buffer);
+            }
+            Err(e) => log::error!("failed to map buffer {:?}: {:?}", id, e),
+        }
+    }
+
+    if staging.is_empty() {
+        return;
+    }
+
+    let mut comb = device.com_allocator.allocate_internal();
+    unsafe {
+        comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+        let mut trackers = device.trackers.lock();
+        for &(id, ref raw, _) in staging.iter() {
+            let (buffer, transition) =
+                trackers
+                    .buffers
+                    .use_replace(&*buffer_guard, id, (), BufferUse::COPY_SRC);
+            comb.pipeline_barrier(
+                all_buffer_stages()..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                transition.map(|pending| pending.into_hal(buffer)),
+            );
+            comb.copy_buffer(
+                &buffer.raw,
+                raw,
+                iter::once(hal::command::BufferCopy {
+                    src: 0,
+                    dst: 0,
+                    size: buffer.size,
+                }),
+            );
+        }
+    }
+    submit_readback(device, comb, staging.iter().map(|&(_, ref raw, _)| raw));
+
+    for (id, raw, memory) in staging {
+        let size = buffer_guard[id].size;
+        let data = finish_readback(device, raw, memory, size);
+        let data_path = trace.make_binary("bin", &data);
+        trace.add(Action::WriteBuffer {
+            id,
+            data: data_path,
+            range: 0..size,
+            queued: true,
+        });
+    }
+}
+
+/// Layout of a mip level of a texture in its readback buffer.
+struct LevelReadback {
+    level: hal::image::Level,
+    offset: wgt::BufferAddress,
+    /// Size of the level, in texels rounded up to whole blocks.
+    size: wgt::Extent3d,
+    bytes_per_row: u32,
+    /// Size of one array layer of the level.
+    layer_size: wgt::BufferAddress,
+}
+
+/// Record the current contents of the textures into the trace.
+///
+/// All the mip levels of a texture are copied into staging memory on the GPU,
+/// with tightly packed rows, and the device waits for the copies to finish.
+/// Each level is then written back by one region per array layer.
+fn read_back_textures<B: GfxBackend>(
+    device: &mut Device<B>,
+    texture_guard: &Storage<Texture<B>, id::TextureId>,
+    texture_ids: &[id::TextureId],
+    trace: &mut Trace,
+) {
+    let mut staging = Vec::with_capacity(texture_ids.len());
+    for &id in texture_ids {
+        let texture = &texture_guard[id];
+        let format_desc =
+            conv::map_texture_format(texture.format, device.private_features).surface_desc();
+        let (block_width, block_height) =
+            (u32::from(format_desc.dim.0), u32::from(format_desc.dim.1));
+        let bytes_per_block = wgt::BufferAddress::from(format_desc.bits / 8);
+        let alignment = bytes_per_block.max(wgt::COPY_BUFFER_ALIGNMENT);
+        let layer_count = wgt::BufferAddress::from(texture.full_range.layers.end);
+
+        let mut levels = Vec::new();
+        let mut size = 0;
+        for level in texture.full_range.levels.clone() {
+            let extent = mip_level_extent(texture.kind.extent(), level);
+            let round_up = |size: u32, block: u32| (size + block - 1) / block * block;
+            let (width, height) = (
+                round_up(extent.width, block_width),
+                round_up(extent.height, block_height),
+            );
+            let row_size = wgt::BufferAddress::from(width / block_width) * bytes_per_block;
+            let layer_size = row_size
+                * wgt::BufferAddress::from(height / block_height)
+                * wgt::BufferAddress::from(extent.depth);
+            let offset = (size + alignment - 1) / alignment * alignment;
+            size = offset + layer_size * layer_count;
+            levels.push(LevelReadback {
+                level,
+                offset,
+                size: wgt::Extent3d {
+                    width,
+                    height,
+                    depth: extent.depth,
+                },
+                bytes_per_row: row_size as u32,
+                layer_size,
+            });
+        }
+        let (raw, memory) = create_readback_buffer(device, size);
+        staging.push((id, raw, memory, size, levels));
+    }
+
+    let mut comb = device.com_allocator.allocate_internal();
+    unsafe {
+        comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+        let mut trackers = device.trackers.lock();
+        for &(id, ref raw, _, _, ref levels) in staging.iter() {
+            let full_range = texture_guard[id].full_range.clone();
+            let (texture, transition) = trackers.textures.use_replace(
+                &*texture_guard,
+                id,
+                full_range.clone(),
+                TextureUse::COPY_SRC,
+            );
+            comb.pipeline_barrier(
+                all_image_stages()..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                transition.map(|pending| pending.into_hal(texture)),
+            );
+            comb.copy_image_to_buffer(
+                &texture.raw,
+                hal::image::Layout::TransferSrcOptimal,
+                raw,
+                levels.iter().map(|level| hal::command::BufferImageCopy {
+                    buffer_offset: level.offset,
+                    buffer_width: level.size.width,
+                    buffer_height: level.size.height,
+                    image_layers: hal::image::SubresourceLayers {
+                        aspects: full_range.aspects,
+                        level: level.level,
+                        layers: full_range.layers.clone(),
+                    },
+                    image_offset: conv::map_origin(wgt::Origin3d::ZERO),
+                    image_extent: conv::map_extent(level.size),
+                }),
+            );
+        }
+    }
+    submit_readback(device, comb, staging.iter().map(|&(_, ref raw, ..)| raw));
+
+    for (id, raw, memory, size, levels) in staging {
+        let data = finish_readback(device, raw, memory, size);
+        let layer_count = u32::from(texture_guard[id].full_range.layers.end);
+        for level in levels {
+            let level_size = level.layer_size * wgt::BufferAddress::from(layer_count);
+            let start = level.offset as usize;
+            let data_path = trace.make_binary("bin", &data[start..start + level_size as usize]);
+            trace.add(Action::WriteTexture {
+                id,
+                data: data_path,
+                regions: (0..layer_count)
+                    .map(|layer| TextureWriteRegion {
+                        mip_level: u32::from(level.level),
+                        array_layer: layer,
+                        origin: wgt::Origin3d::ZERO,
+                        size: level.size,
+                        data_offset: level.layer_size * wgt::BufferAddress::from(layer),
+                    })
+                    .collect(),
+                layout: TextureWriteLayout {
+                    bytes_per_row: level.bytes_per_row,
+                    rows_per_image: 0,
+                },
+            });
+        }
+    }
+}
@@ -0,0 +1,295 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+/*! Resource creation and destruction events, for memory profilers.
+ *
+ * Events are queued on the device while its locks are held, and are
+ * delivered in order by `device_poll` and `poll_all_devices`, after all
+ * the locks are released, so the callback is free to call back into wgpu.
+ *
+ * A resource is reported as destroyed right before its memory is given back,
+ * once it's dropped by the user and the submissions using it are done.
+ * The resources that are still alive when the device is destroyed are
+ * reported last, by `device_destroy`.
+!*/
+
+use super::Label;
+use crate::{id::Id, FastHashMap};
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    ptr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Buffer = 0,
+    Texture = 1,
+    TextureView = 2,
+    Sampler = 3,
+    BindGroup = 4,
+    RenderPipeline = 5,
+    ComputePipeline = 6,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceEventType {
+    Created = 0,
+    Destroyed = 1,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct ResourceEvent {
+    pub ty: ResourceEventType,
+    pub kind: ResourceKind,
+    /// Raw value of the resource ID, which is unique among the live resources of a kind.
+    pub id: u64,
+    /// Label the resource was created with, or null. Only valid during the callback.
+    pub label: *const c_char,
+    /// Bytes of memory bound to the resource, or 0 if it doesn't own memory.
+    pub size: u64,
+    /// Time of the event in nanoseconds since the UNIX epoch.
+    pub timestamp_ns: u64,
+}
+
+pub type ResourceEventCallback = unsafe extern "C" fn(event: &ResourceEvent, userdata: *mut u8);
+
+#[derive(Clone, Copy, Debug)]
+struct ResourceEventHook {
+    callback: ResourceEventCallback,
+    userdata: *mut u8,
+}
+
+unsafe impl Send for ResourceEventHook {}
+unsafe impl Sync for ResourceEventHook {}
+
+#[derive(Debug)]
+struct PendingEvent {
+    ty: ResourceEventType,
+    kind: ResourceKind,
+    id: u64,
+    label: Option<CString>,
+    size: u64,
+    timestamp_ns: u64,
+}
+
+/// Events that are ready to be delivered.
+#[derive(Debug)]
+pub(crate) struct ResourceEventBatch {
+    hook: ResourceEventHook,
+    events: Vec<PendingEvent>,
+}
+
+impl ResourceEventBatch {
+    //Note: must be called with nothing locked.
+    pub(crate) fn fire(self) {
+        for event in self.events {
+            let raw = ResourceEvent {
+                ty: event.ty,
+                kind: event.kind,
+                id: event.id,
+                label: event
+                    .label
+                    .as_ref()
+                    .map_or(ptr::null(), |label| label.as_ptr()),
+                size: event.size,
+                timestamp_ns: event.timestamp_ns,
+            };
+            unsafe {
+                (self.hook.callback)(&raw, self.hook.userdata);
+            }
+        }
+    }
+}
+
+/// Resource events of a device, recorded only while a callback is registered.
+#[derive(Debug, Default)]
+pub(crate) struct ResourceEvents {
+    hook: Option<ResourceEventHook>,
+    pending: Vec<PendingEvent>,
+    /// Label and size of the resources reported as created, by kind and ID.
+    live: FastHashMap<(ResourceKind, u64), (Option<CString>, u64)>,
+}
+
+fn timestamp_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64)
+}
+
+impl ResourceEvents {
+    pub(crate) fn set_callback(
+        &mut self,
+        callback: Option<ResourceEventCallback>,
+        userdata: *mut u8,
+    ) {
+        self.hook = callback.map(|callback| ResourceEventHook { callback, userdata });
+        if self.hook.is_none() {
+            self.pending.clear();
+            self.live.clear();
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.hook.is_some()
+    }
+
+    pub(crate) fn created<T>(&mut self, kind: ResourceKind, id: Id<T>, label: Label, size: u64) {
+        if self.hook.is_none() {
+            return;
+        }
+        let label = if label.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(label) }.to_owned())
+        };
+        let id = id.into_raw();
+        self.live.insert((kind, id), (label.clone(), size));
+        self.pending.push(PendingEvent {
+            ty: ResourceEventType::Created,
+            kind,
+            id,
+            label,
+            size,
+            timestamp_ns: timestamp_ns(),
+        });
+    }
+
+    /// Report the destruction of a resource, given its raw ID, carrying over
+    /// its label and size.
+    ///
+    /// Resources created before the callback got registered are not reported,
+    /// so every destruction event is paired with a creation event.
+    pub(crate) fn destroyed(&mut self, kind: ResourceKind, id: u64) {
+        if let Some((label, size)) = self.live.remove(&(kind, id)) {
+            self.pending.push(PendingEvent {
+                ty: ResourceEventType::Destroyed,
+                kind,
+                id,
+                label,
+                size,
+                timestamp_ns: timestamp_ns(),
+            });
+        }
+    }
+
+    /// Report the destruction of all the resources still alive, in the order of their IDs.
+    pub(crate) fn destroyed_all(&mut self) {
+        let mut live = self.live.keys().cloned().collect::<Vec<_>>();
+        live.sort_by_key(|&(kind, id)| (id, kind as u8));
+        for (kind, id) in live {
+            let (label, size) = self.live.remove(&(kind, id)).unwrap();
+            self.pending.push(PendingEvent {
+                ty: ResourceEventType::Destroyed,
+                kind,
+                id,
+                label,
+                size,
+                timestamp_ns: timestamp_ns(),
+            });
+        }
+    }
+
+    pub(crate) fn take(&mut self) -> Option<ResourceEventBatch> {
+        match self.hook {
+            Some(hook) if !self.pending.is_empty() => Some(ResourceEventBatch {
+                hook,
+                events: self.pending.drain(..).collect(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::id::{BufferId, TextureId, TypedId};
+
+    unsafe extern "C" fn collect(event: &ResourceEvent, userdata: *mut u8) {
+        let events =
+            &mut *(userdata as *mut Vec<(ResourceEventType, ResourceKind, u64, String, u64)>);
+        let label = if event.label.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(event.label).to_string_lossy().into_owned()
+        };
+        events.push((event.ty, event.kind, event.id, label, event.size));
+    }
+
+    #[test]
+    fn destruction_is_paired() {
+        let mut received = Vec::new();
+        let mut events = ResourceEvents::default();
+        let label = CString::new("terrain-vertices").unwrap();
+        let early = BufferId::zip(0, 1, wgt::Backend::Empty);
+        let buffer = BufferId::zip(1, 1, wgt::Backend::Empty);
+        let texture = TextureId::zip(1, 1, wgt::Backend::Empty);
+
+        events.created(ResourceKind::Buffer, early, ptr::null(), 16);
+        assert!(events.take().is_none());
+
+        events.set_callback(Some(collect), &mut received as *mut _ as *mut u8);
+        events.created(ResourceKind::Buffer, buffer, label.as_ptr(), 4 << 20);
+        events.created(ResourceKind::Texture, texture, ptr::null(), 256);
+        // Destroyed by the life tracker on a later `maintain`, after the submissions
+        // using them are done. The buffer created before registration is skipped.
+        events.destroyed(ResourceKind::Buffer, early.into_raw());
+        events.destroyed(ResourceKind::Texture, texture.into_raw());
+        events.destroyed(ResourceKind::Buffer, buffer.into_raw());
+        events.take().unwrap().fire();
+
+        let (buffer, texture) = (buffer.into_raw(), texture.into_raw());
+        let name = "terrain-vertices".to_string();
+        use self::{ResourceEventType::*, ResourceKind::*};
+        assert_eq!(
+            received,
+            vec![
+                (Created, Buffer, buffer, name.clone(), 4 << 20),
+                (Created, Texture, texture, String::new(), 256),
+                (Destroyed, Texture, texture, String::new(), 256),
+                (Destroyed, Buffer, buffer, name, 4 << 20),
+            ]
+        );
+        assert!(events.take().is_none());
+    }
+
+    #[test]
+    fn device_destruction() {
+        let mut received = Vec::new();
+        let mut events = ResourceEvents::default();
+        events.set_callback(Some(collect), &mut received as *mut _ as *mut u8);
+        let buffer = BufferId::zip(1, 1, wgt::Backend::Empty);
+        let texture = TextureId::zip(0, 1, wgt::Backend::Empty);
+        let dropped = TextureId::zip(2, 1, wgt::Backend::Empty);
+        events.created(ResourceKind::Buffer, buffer, ptr::null(), 64);
+        events.created(ResourceKind::Texture, texture, ptr::null(), 256);
+        events.created(ResourceKind::Texture, dropped, ptr::null(), 256);
+        events.destroyed(ResourceKind::Texture, dropped.into_raw());
+        // The resources still alive go away with the device.
+        events.destroyed_all();
+        events.take().unwrap().fire();
+
+        let destroyed = received
+            .iter()
+            .filter(|event| event.0 == ResourceEventType::Destroyed)
+            .map(|event| (event.1, event.2))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            destroyed,
+            [
+                (ResourceKind::Texture, dropped.into_raw()),
+                (ResourceKind::Texture, texture.into_raw()),
+                (ResourceKind::Buffer, buffer.into_raw()),
+            ]
+        );
+        events.destroyed_all();
+        assert!(events.take().is_none());
+    }
+}
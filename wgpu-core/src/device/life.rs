@@ -2,9 +2,18 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+#[cfg(feature = "resource-events")]
+use crate::device::events::{ResourceEvents, ResourceKind};
 #[cfg(feature = "trace")]
 use crate::device::trace;
 use crate::{
+    command::InlineUniformRing,
+    device::{
+        queue::PendingReadback,
+        staging::{StagingBelt, StagingChunk},
+        transient::{BlockRing, TransientAllocation, TransientPool},
+        SubmittedWorkDoneClosure,
+    },
     hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Token},
     id, resource,
     track::TrackerSet,
@@ -13,13 +22,30 @@ use crate::{
 
 use copyless::VecHelper as _;
 use gfx_descriptor::{DescriptorAllocator, DescriptorSet};
-use gfx_memory::{Heaps, MemoryBlock};
+use gfx_memory::Heaps;
 use hal::device::Device as _;
 use parking_lot::Mutex;
 
-use std::sync::atomic::Ordering;
+use std::{
+    iter, mem, ptr,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+pub(super) const CLEANUP_WAIT_MS: u64 = 5000;
 
-const CLEANUP_WAIT_MS: u64 = 5000;
+/// Duration of the next fence wait, when `elapsed` out of the total `timeout`
+/// is already spent, and whether it's the last wait before the timeout.
+fn next_wait(timeout: Option<Duration>, elapsed: Duration) -> (Duration, bool) {
+    let slice = Duration::from_millis(CLEANUP_WAIT_MS);
+    match timeout {
+        Some(timeout) => {
+            let remaining = timeout.checked_sub(elapsed).unwrap_or_default();
+            (remaining.min(slice), remaining <= slice)
+        }
+        None => (slice, false),
+    }
+}
 
 /// A struct that keeps lists of resources that are no longer needed by the user.
 #[derive(Debug, Default)]
@@ -68,68 +94,121 @@ impl SuspectedResources {
 /// A struct that keeps lists of resources that are no longer needed.
 #[derive(Debug)]
 struct NonReferencedResources<B: hal::Backend> {
-    buffers: Vec<(B::Buffer, MemoryBlock<B>)>,
-    images: Vec<(B::Image, MemoryBlock<B>)>,
+    buffers: Vec<B::Buffer>,
+    /// Staging buffers, to be kept for the next uploads and readbacks.
+    staging: Vec<StagingChunk<B>>,
+    images: Vec<B::Image>,
+    /// Memory of the buffers and images, freed after them.
+    memory: Vec<resource::ResourceMemory<B>>,
+    /// Space of the transient buffers and images, given back to the pool after them.
+    transient: Vec<TransientAllocation>,
     // Note: we keep the associated ID here in order to be able to check
     // at any point what resources are used in a submission.
     image_views: Vec<(id::TextureViewId, B::ImageView)>,
     samplers: Vec<B::Sampler>,
     framebuffers: Vec<B::Framebuffer>,
+    semaphores: Vec<B::Semaphore>,
     desc_sets: Vec<DescriptorSet<B>>,
     compute_pipes: Vec<B::ComputePipeline>,
     graphics_pipes: Vec<B::GraphicsPipeline>,
     descriptor_set_layouts: Vec<B::DescriptorSetLayout>,
     pipeline_layouts: Vec<B::PipelineLayout>,
+    /// Resources to report as destroyed, once these are freed.
+    #[cfg(feature = "resource-events")]
+    destroyed: Vec<(ResourceKind, u64)>,
 }
 
 impl<B: hal::Backend> NonReferencedResources<B> {
     fn new() -> Self {
         NonReferencedResources {
             buffers: Vec::new(),
+            staging: Vec::new(),
             images: Vec::new(),
+            memory: Vec::new(),
+            transient: Vec::new(),
             image_views: Vec::new(),
             samplers: Vec::new(),
             framebuffers: Vec::new(),
+            semaphores: Vec::new(),
             desc_sets: Vec::new(),
             compute_pipes: Vec::new(),
             graphics_pipes: Vec::new(),
             descriptor_set_layouts: Vec::new(),
             pipeline_layouts: Vec::new(),
+            #[cfg(feature = "resource-events")]
+            destroyed: Vec::new(),
         }
     }
 
     fn extend(&mut self, other: Self) {
         self.buffers.extend(other.buffers);
+        self.staging.extend(other.staging);
         self.images.extend(other.images);
+        self.memory.extend(other.memory);
+        self.transient.extend(other.transient);
         self.image_views.extend(other.image_views);
         self.samplers.extend(other.samplers);
         self.framebuffers.extend(other.framebuffers);
+        self.semaphores.extend(other.semaphores);
         self.desc_sets.extend(other.desc_sets);
         self.compute_pipes.extend(other.compute_pipes);
         self.graphics_pipes.extend(other.graphics_pipes);
+        #[cfg(feature = "resource-events")]
+        self.destroyed.extend(other.destroyed);
         assert!(other.descriptor_set_layouts.is_empty());
         assert!(other.pipeline_layouts.is_empty());
     }
 
+    fn add_memory(&mut self, memory: resource::ResourceMemory<B>) {
+        match memory {
+            resource::ResourceMemory::Transient(allocation) => self.transient.push(allocation),
+            other => self.memory.push(other),
+        }
+    }
+
+    /// Give the transient space back, which only needs the bookkeeping of the pool.
+    fn release_transient(&mut self, ring: &mut BlockRing) {
+        for allocation in self.transient.drain(..) {
+            ring.release(allocation);
+        }
+    }
+
     unsafe fn clean(
         &mut self,
         device: &B::Device,
         heaps_mutex: &Mutex<Heaps<B>>,
+        transient_pool_mutex: &Mutex<TransientPool<B>>,
+        staging_belt_mutex: &Mutex<StagingBelt<B>>,
         descriptor_allocator_mutex: &Mutex<DescriptorAllocator<B>>,
     ) {
-        if !self.buffers.is_empty() {
+        for raw in self.buffers.drain(..) {
+            log::trace!("Buffer {:?} is destroyed", raw);
+            device.destroy_buffer(raw);
+        }
+        for raw in self.images.drain(..) {
+            device.destroy_image(raw);
+        }
+        if !self.memory.is_empty() || !self.transient.is_empty() {
             let mut heaps = heaps_mutex.lock();
-            for (raw, memory) in self.buffers.drain(..) {
-                log::trace!("Buffer {:?} is destroyed with memory {:?}", raw, memory);
-                device.destroy_buffer(raw);
-                heaps.free(device, memory);
+            let mut transient_pool = transient_pool_mutex.lock();
+            self.release_transient(&mut transient_pool.ring);
+            for memory in self.memory.drain(..) {
+                match memory {
+                    resource::ResourceMemory::Block(memory) => heaps.free(device, memory),
+                    resource::ResourceMemory::Transient(allocation) => {
+                        transient_pool.release(allocation)
+                    }
+                    resource::ResourceMemory::Lazy(allocation) => {
+                        transient_pool.free_lazy(device, allocation)
+                    }
+                }
             }
         }
-        if !self.images.is_empty() {
+        if !self.staging.is_empty() {
             let mut heaps = heaps_mutex.lock();
-            for (raw, memory) in self.images.drain(..) {
-                device.destroy_image(raw);
-                heaps.free(device, memory);
+            let mut staging_belt = staging_belt_mutex.lock();
+            for chunk in self.staging.drain(..) {
+                staging_belt.recycle(device, &mut heaps, chunk);
             }
         }
 
@@ -142,6 +221,9 @@ impl<B: hal::Backend> NonReferencedResources<B> {
         for raw in self.framebuffers.drain(..) {
             device.destroy_framebuffer(raw);
         }
+        for raw in self.semaphores.drain(..) {
+            device.destroy_semaphore(raw);
+        }
 
         if !self.desc_sets.is_empty() {
             descriptor_allocator_mutex
@@ -170,6 +252,21 @@ struct ActiveSubmission<B: hal::Backend> {
     fence: B::Fence,
     last_resources: NonReferencedResources<B>,
     mapped: Vec<id::BufferId>,
+    readbacks: Vec<PendingReadback<B>>,
+    work_done: Vec<SubmittedWorkDoneClosure>,
+}
+
+/// The resources freed once the submission `submit_index` is done, or at the
+/// next `cleanup` if it's not in flight, as when no submission ever used them.
+fn resources_after<'a, B: hal::Backend>(
+    active: &'a mut [ActiveSubmission<B>],
+    free_resources: &'a mut NonReferencedResources<B>,
+    submit_index: SubmissionIndex,
+) -> &'a mut NonReferencedResources<B> {
+    active
+        .iter_mut()
+        .find(|a| a.index == submit_index)
+        .map_or(free_resources, |a| &mut a.last_resources)
 }
 
 /// A struct responsible for tracking resource lifetimes.
@@ -194,6 +291,10 @@ pub struct LifetimeTracker<B: hal::Backend> {
     /// actual deletion.
     free_resources: NonReferencedResources<B>,
     ready_to_map: Vec<id::BufferId>,
+    /// Readbacks of retired submissions, waiting for `handle_readbacks`.
+    ready_readbacks: Vec<PendingReadback<B>>,
+    /// Callbacks of retired submissions, waiting for `handle_work_done`.
+    ready_work_done: Vec<SubmittedWorkDoneClosure>,
 }
 
 impl<B: hal::Backend> LifetimeTracker<B> {
@@ -204,6 +305,8 @@ impl<B: hal::Backend> LifetimeTracker<B> {
             active: Vec::new(),
             free_resources: NonReferencedResources::new(),
             ready_to_map: Vec::new(),
+            ready_readbacks: Vec::new(),
+            ready_work_done: Vec::new(),
         }
     }
 
@@ -212,19 +315,67 @@ impl<B: hal::Backend> LifetimeTracker<B> {
         index: SubmissionIndex,
         fence: B::Fence,
         new_suspects: &SuspectedResources,
-        temp_buffers: impl Iterator<Item = (B::Buffer, MemoryBlock<B>)>,
+        temp_buffers: impl Iterator<Item = StagingChunk<B>>,
+        readbacks: impl Iterator<Item = PendingReadback<B>>,
+        work_done: impl Iterator<Item = SubmittedWorkDoneClosure>,
     ) {
         let mut last_resources = NonReferencedResources::new();
-        last_resources.buffers.extend(temp_buffers);
+        last_resources.staging.extend(temp_buffers);
         self.suspected_resources.extend(new_suspects);
         self.active.alloc().init(ActiveSubmission {
             index,
             fence,
             last_resources,
             mapped: Vec::new(),
+            readbacks: readbacks.collect(),
+            work_done: work_done.collect(),
         });
     }
 
+    /// Call `closure` once the latest submission is done, or right at
+    /// the next `handle_work_done` if there is none in flight.
+    pub fn add_work_done(&mut self, closure: SubmittedWorkDoneClosure) {
+        match self.active.last_mut() {
+            Some(a) => a.work_done.push(closure),
+            None => self.ready_work_done.push(closure),
+        }
+    }
+
+    /// Destroy semaphores once the submissions signaling them are done.
+    pub fn retire_semaphores(
+        &mut self,
+        semaphores: impl Iterator<Item = (B::Semaphore, SubmissionIndex)>,
+    ) {
+        for (semaphore, submit_index) in semaphores {
+            resources_after(&mut self.active, &mut self.free_resources, submit_index)
+                .semaphores
+                .push(semaphore);
+        }
+    }
+
+    /// Free the inline uniform rings of a submission once it's done.
+    pub fn retire_inline_uniforms(
+        &mut self,
+        rings: impl Iterator<Item = InlineUniformRing<B>>,
+        submit_index: SubmissionIndex,
+    ) {
+        let resources = resources_after(&mut self.active, &mut self.free_resources, submit_index);
+        for ring in rings {
+            resources.buffers.push(ring.raw);
+            resources.add_memory(resource::ResourceMemory::Block(ring.memory));
+            resources
+                .desc_sets
+                .extend(ring.sets.into_iter().map(|(_, set)| set));
+        }
+    }
+
+    /// Recycle a staging buffer once the submission using it is done.
+    pub fn retire_staging(&mut self, chunk: StagingChunk<B>, submit_index: SubmissionIndex) {
+        resources_after(&mut self.active, &mut self.free_resources, submit_index)
+            .staging
+            .push(chunk);
+    }
+
     pub fn map(&mut self, buffer: id::BufferId, ref_count: RefCount) {
         self.mapped.push(Stored {
             value: buffer,
@@ -232,6 +383,85 @@ impl<B: hal::Backend> LifetimeTracker<B> {
         });
     }
 
+    /// Whether a mapping request of the buffer is queued, until
+    /// `handle_mapping` resolves it.
+    fn is_mapping_queued(&self, buffer: id::BufferId) -> bool {
+        self.mapped.iter().any(|stored| stored.value == buffer)
+            || self.ready_to_map.contains(&buffer)
+            || self.active.iter().any(|a| a.mapped.contains(&buffer))
+    }
+
+    /// Take out the suspected buffers to triage. The ones with a queued mapping
+    /// request stay suspected, to be triaged again once it's resolved.
+    fn take_suspected_buffers(&mut self) -> Vec<id::BufferId> {
+        let buffers = mem::take(&mut self.suspected_resources.buffers);
+        let (waiting, buffers) = buffers
+            .into_iter()
+            .partition(|&id| self.is_mapping_queued(id));
+        self.suspected_resources.buffers = waiting;
+        buffers
+    }
+
+    /// Take out all the buffers with a pending mapping request,
+    /// whichever submission they are waiting on.
+    pub fn drain_mappings(&mut self) -> Vec<id::BufferId> {
+        let mut buffers = self
+            .mapped
+            .drain(..)
+            .map(|stored| stored.value)
+            .collect::<Vec<_>>();
+        buffers.extend(self.ready_to_map.drain(..));
+        for a in self.active.iter_mut() {
+            buffers.extend(a.mapped.drain(..));
+        }
+        buffers
+    }
+
+    /// Take out all the readbacks that didn't complete, whichever submission
+    /// they are waiting on. Their buffers are freed with that submission.
+    pub fn drain_readbacks(&mut self) -> Vec<resource::BufferReadOperation> {
+        let mut operations = Vec::new();
+        for readback in self.ready_readbacks.drain(..) {
+            self.free_resources.staging.push(readback.chunk);
+            operations.push(readback.op);
+        }
+        for a in self.active.iter_mut() {
+            for readback in a.readbacks.drain(..) {
+                a.last_resources.staging.push(readback.chunk);
+                operations.push(readback.op);
+            }
+        }
+        operations
+    }
+
+    /// Take out all the work done callbacks, whichever submission they are waiting on.
+    pub fn drain_work_done(&mut self) -> Vec<SubmittedWorkDoneClosure> {
+        let mut closures = self.handle_work_done();
+        for a in self.active.iter_mut() {
+            closures.extend(a.work_done.drain(..));
+        }
+        closures
+    }
+
+    /// Take out the work done callbacks of the retired submissions.
+    pub fn handle_work_done(&mut self) -> Vec<SubmittedWorkDoneClosure> {
+        self.ready_work_done.drain(..).collect()
+    }
+
+    /// Number of submissions that weren't done as of the last triage.
+    pub fn active_count(&self) -> usize {
+        self.active.len()
+    }
+
+    /// Wait for the oldest active submission to complete, leaving it active.
+    pub fn wait_for_oldest(&self, device: &B::Device) {
+        if let Some(a) = self.active.first() {
+            unsafe {
+                device.wait_for_fence(&a.fence, !0).unwrap();
+            }
+        }
+    }
+
     /// Find the pending entry with the lowest active index. If none can be found that means
     /// everything in the allocator can be cleaned up, so std::usize::MAX is correct.
     pub fn lowest_active_submission(&self) -> SubmissionIndex {
@@ -240,25 +470,62 @@ impl<B: hal::Backend> LifetimeTracker<B> {
             .fold(std::usize::MAX, |v, active| active.index.min(v))
     }
 
-    fn wait_idle(&self, device: &B::Device) {
-        if !self.active.is_empty() {
-            log::debug!("Waiting for IDLE...");
-            let status = unsafe {
-                device.wait_for_fences(
-                    self.active.iter().map(|a| &a.fence),
-                    hal::device::WaitFor::All,
-                    CLEANUP_WAIT_MS * 1_000_000,
-                )
-            };
-            log::debug!("...Done");
-            assert_eq!(status, Ok(true), "GPU got stuck :(");
+    /// Wait for all the active submissions to complete, leaving them active.
+    ///
+    /// Returns false if they didn't complete within `timeout_ns`.
+    pub fn wait_for_submissions(&self, device: &B::Device, timeout_ns: u64) -> bool {
+        if self.active.is_empty() {
+            return true;
+        }
+        log::debug!("Waiting for IDLE...");
+        let status = unsafe {
+            device.wait_for_fences(
+                self.active.iter().map(|a| &a.fence),
+                hal::device::WaitFor::All,
+                timeout_ns,
+            )
+        };
+        log::debug!("...Done");
+        status == Ok(true)
+    }
+
+    /// Wait for the submission `index` to complete, leaving it active.
+    ///
+    /// Returns false if it didn't complete within `timeout_ns`.
+    pub fn wait_for_submission(
+        &self,
+        device: &B::Device,
+        index: SubmissionIndex,
+        timeout_ns: u64,
+    ) -> Result<bool, hal::device::OomOrDeviceLost> {
+        match self.active.iter().find(|a| a.index == index) {
+            Some(a) => unsafe { device.wait_for_fence(&a.fence, timeout_ns) },
+            None => Ok(true),
+        }
+    }
+
+    /// Wait for all the active submissions to complete, in slices of
+    /// `CLEANUP_WAIT_MS`, so that a busy GPU is reported while waiting.
+    ///
+    /// Returns false if they didn't complete within `timeout`, if any.
+    pub fn wait_idle(&self, device: &B::Device, timeout: Option<Duration>) -> bool {
+        let start = Instant::now();
+        loop {
+            let (wait, is_last) = next_wait(timeout, start.elapsed());
+            if self.wait_for_submissions(device, wait.as_nanos() as u64) {
+                return true;
+            }
+            if is_last {
+                return false;
+            }
+            log::warn!("GPU is still busy after {:?}", start.elapsed());
         }
     }
 
     /// Returns the last submission index that is done.
     pub fn triage_submissions(&mut self, device: &B::Device, force_wait: bool) -> SubmissionIndex {
         if force_wait {
-            self.wait_idle(device);
+            self.wait_idle(device, None);
         }
         //TODO: enable when `is_sorted_by_key` is stable
         //debug_assert!(self.active.is_sorted_by_key(|a| a.index));
@@ -273,27 +540,57 @@ impl<B: hal::Backend> LifetimeTracker<B> {
             return 0;
         };
 
+        for fence in self.retire(done_count) {
+            unsafe {
+                device.destroy_fence(fence);
+            }
+        }
+
+        last_done
+    }
+
+    /// Move everything the first `done_count` submissions kept alive to be
+    /// freed or fired, returning their fences.
+    pub(crate) fn retire(&mut self, done_count: usize) -> Vec<B::Fence> {
+        let mut fences = Vec::with_capacity(done_count);
         for a in self.active.drain(..done_count) {
             log::trace!("Active submission {} is done", a.index);
             self.free_resources.extend(a.last_resources);
             self.ready_to_map.extend(a.mapped);
-            unsafe {
-                device.destroy_fence(a.fence);
-            }
+            self.ready_readbacks.extend(a.readbacks);
+            self.ready_work_done.extend(a.work_done);
+            fences.push(a.fence);
         }
+        fences
+    }
 
-        last_done
+    /// Report the destruction of the resources that are about to be freed.
+    #[cfg(feature = "resource-events")]
+    fn report_destroyed(&mut self, events: &mut ResourceEvents) {
+        for (kind, id) in self.free_resources.destroyed.drain(..) {
+            events.destroyed(kind, id);
+        }
     }
 
     pub fn cleanup(
         &mut self,
         device: &B::Device,
         heaps_mutex: &Mutex<Heaps<B>>,
+        transient_pool_mutex: &Mutex<TransientPool<B>>,
+        staging_belt_mutex: &Mutex<StagingBelt<B>>,
         descriptor_allocator_mutex: &Mutex<DescriptorAllocator<B>>,
+        #[cfg(feature = "resource-events")] events: &Mutex<ResourceEvents>,
     ) {
+        #[cfg(feature = "resource-events")]
+        self.report_destroyed(&mut events.lock());
         unsafe {
-            self.free_resources
-                .clean(device, heaps_mutex, descriptor_allocator_mutex);
+            self.free_resources.clean(
+                device,
+                heaps_mutex,
+                transient_pool_mutex,
+                staging_belt_mutex,
+                descriptor_allocator_mutex,
+            );
             descriptor_allocator_mutex.lock().cleanup(device);
         }
     }
@@ -304,7 +601,7 @@ impl<B: GfxBackend> LifetimeTracker<B> {
         &mut self,
         global: &Global<G>,
         trackers: &Mutex<TrackerSet>,
-        #[cfg(feature = "trace")] trace: Option<&Mutex<trace::Trace>>,
+        #[cfg(feature = "trace")] trace: &Mutex<Option<trace::Trace>>,
         token: &mut Token<super::Device<B>>,
     ) {
         let hub = B::hub(global);
@@ -316,7 +613,9 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.bind_groups.drain(..) {
                 if trackers.bind_groups.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyBindGroup(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyBindGroup(id));
+                    }
                     hub.bind_groups.free_id(id);
                     let res = guard.remove(id).unwrap();
 
@@ -335,12 +634,15 @@ impl<B: GfxBackend> LifetimeTracker<B> {
                         .extend(res.used.samplers.used());
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .desc_sets
-                        .push(res.raw);
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::BindGroup, id.into_raw()));
+                    resources.desc_sets.extend(
+                        iter::once(res.raw).chain(res.derived_sets.into_iter().map(|(_, set)| set)),
+                    );
                 }
             }
         }
@@ -352,7 +654,9 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.texture_views.drain(..) {
                 if trackers.views.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyTextureView(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyTextureView(id));
+                    }
                     hub.texture_views.free_id(id);
                     let res = guard.remove(id).unwrap();
 
@@ -365,12 +669,13 @@ impl<B: GfxBackend> LifetimeTracker<B> {
                     };
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .image_views
-                        .push((id, raw));
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::TextureView, id.into_raw()));
+                    resources.image_views.push((id, raw));
                 }
             }
         }
@@ -382,17 +687,21 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.textures.drain(..) {
                 if trackers.textures.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyTexture(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyTexture(id));
+                    }
                     hub.textures.free_id(id);
                     let res = guard.remove(id).unwrap();
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .images
-                        .push((res.raw, res.memory));
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::Texture, id.into_raw()));
+                    resources.images.push(res.raw);
+                    resources.add_memory(res.memory);
                 }
             }
         }
@@ -404,17 +713,20 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.samplers.drain(..) {
                 if trackers.samplers.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroySampler(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroySampler(id));
+                    }
                     hub.samplers.free_id(id);
                     let res = guard.remove(id).unwrap();
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .samplers
-                        .push(res.raw);
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::Sampler, id.into_raw()));
+                    resources.samplers.push(res.raw);
                 }
             }
         }
@@ -423,21 +735,29 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             let mut trackers = trackers.lock();
             let (mut guard, _) = hub.buffers.write(token);
 
-            for id in self.suspected_resources.buffers.drain(..) {
+            for id in self.take_suspected_buffers() {
+                // The buffer may have been freed by `handle_mapping` since it was suspected.
+                if !guard.contains(id) {
+                    continue;
+                }
                 if trackers.buffers.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyBuffer(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyBuffer(id));
+                    }
                     hub.buffers.free_id(id);
                     let res = guard.remove(id).unwrap();
                     log::debug!("Buffer {:?} is detached", id);
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .buffers
-                        .push((res.raw, res.memory));
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::Buffer, id.into_raw()));
+                    resources.buffers.push(res.raw);
+                    resources.add_memory(res.memory);
                 }
             }
         }
@@ -449,17 +769,20 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.compute_pipelines.drain(..) {
                 if trackers.compute_pipes.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyComputePipeline(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyComputePipeline(id));
+                    }
                     hub.compute_pipelines.free_id(id);
                     let res = guard.remove(id).unwrap();
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .compute_pipes
-                        .push(res.raw);
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::ComputePipeline, id.into_raw()));
+                    resources.compute_pipes.extend(res.raw.into_ready());
                 }
             }
         }
@@ -471,17 +794,20 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             for id in self.suspected_resources.render_pipelines.drain(..) {
                 if trackers.render_pipes.remove_abandoned(id) {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyRenderPipeline(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyRenderPipeline(id));
+                    }
                     hub.render_pipelines.free_id(id);
                     let res = guard.remove(id).unwrap();
 
                     let submit_index = res.life_guard.submission_index.load(Ordering::Acquire);
-                    self.active
-                        .iter_mut()
-                        .find(|a| a.index == submit_index)
-                        .map_or(&mut self.free_resources, |a| &mut a.last_resources)
-                        .graphics_pipes
-                        .push(res.raw);
+                    let resources =
+                        resources_after(&mut self.active, &mut self.free_resources, submit_index);
+                    #[cfg(feature = "resource-events")]
+                    resources
+                        .destroyed
+                        .push((ResourceKind::RenderPipeline, id.into_raw()));
+                    resources.graphics_pipes.extend(res.raw.into_ready());
                 }
             }
         }
@@ -497,7 +823,9 @@ impl<B: GfxBackend> LifetimeTracker<B> {
                 //Note: this has to happen after all the suspected pipelines are destroyed
                 if ref_count.load() == 1 {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyBindGroupLayout(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyBindGroupLayout(id));
+                    }
                     hub.bind_group_layouts.free_id(id);
                     let layout = guard.remove(id).unwrap();
                     self.free_resources.descriptor_set_layouts.push(layout.raw);
@@ -516,7 +844,9 @@ impl<B: GfxBackend> LifetimeTracker<B> {
                 //Note: this has to happen after all the suspected pipelines are destroyed
                 if ref_count.load() == 1 {
                     #[cfg(feature = "trace")]
-                    trace.map(|t| t.lock().add(trace::Action::DestroyPipelineLayout(id)));
+                    if let Some(ref mut trace) = *trace.lock() {
+                        trace.add(trace::Action::DestroyPipelineLayout(id));
+                    }
                     hub.pipeline_layouts.free_id(id);
                     let layout = guard.remove(id).unwrap();
                     self.free_resources.pipeline_layouts.push(layout.raw);
@@ -612,10 +942,7 @@ impl<B: GfxBackend> LifetimeTracker<B> {
         log::debug!("Free framebuffers {:?}", remove_list);
         for (ref key, submit_index) in remove_list {
             let framebuffer = framebuffers.remove(key).unwrap();
-            self.active
-                .iter_mut()
-                .find(|a| a.index == submit_index)
-                .map_or(&mut self.free_resources, |a| &mut a.last_resources)
+            resources_after(&mut self.active, &mut self.free_resources, submit_index)
                 .framebuffers
                 .push(framebuffer);
         }
@@ -640,37 +967,328 @@ impl<B: GfxBackend> LifetimeTracker<B> {
             let buffer = &mut buffer_guard[buffer_id];
             if buffer.life_guard.ref_count.is_none() && trackers.buffers.remove_abandoned(buffer_id)
             {
-                buffer.map_state = resource::BufferMapState::Idle;
-                log::debug!("Mapping request is dropped because the buffer is destroyed.");
+                log::debug!("Mapping request is aborted because the buffer is destroyed.");
+                if let Some(mapping) = buffer.map_state.take_pending() {
+                    pending_callbacks.push((
+                        mapping.op,
+                        resource::BufferMapAsyncStatus::Aborted,
+                        ptr::null_mut(),
+                    ));
+                }
                 hub.buffers.free_id(buffer_id);
                 let buffer = buffer_guard.remove(buffer_id).unwrap();
+                #[cfg(feature = "resource-events")]
                 self.free_resources
-                    .buffers
-                    .push((buffer.raw, buffer.memory));
+                    .destroyed
+                    .push((ResourceKind::Buffer, buffer_id.into_raw()));
+                self.free_resources.buffers.push(buffer.raw);
+                self.free_resources.add_memory(buffer.memory);
             } else {
-                let mapping = match std::mem::replace(
-                    &mut buffer.map_state,
-                    resource::BufferMapState::Idle,
-                ) {
-                    resource::BufferMapState::Waiting(pending_mapping) => pending_mapping,
-                    _ => panic!("No pending mapping."),
+                let mapping = match buffer.map_state.take_pending() {
+                    Some(pending_mapping) => pending_mapping,
+                    // The buffer got unmapped, which already fired the callback.
+                    None => continue,
                 };
                 log::debug!("Buffer {:?} map state -> Active", buffer_id);
                 let host = match mapping.op {
                     resource::BufferMapOperation::Read { .. } => super::HostMap::Read,
                     resource::BufferMapOperation::Write { .. } => super::HostMap::Write,
                 };
-                let result = super::map_buffer(raw, buffer, mapping.sub_range.clone(), host);
-                if let Ok(ptr) = result {
-                    buffer.map_state = resource::BufferMapState::Active {
-                        ptr,
-                        sub_range: mapping.sub_range,
-                        host,
+                let (status, ptr) =
+                    match super::map_buffer(raw, buffer, mapping.sub_range.clone(), host) {
+                        Ok(ptr) => {
+                            buffer.map_state = resource::BufferMapState::Active {
+                                ptr,
+                                sub_range: mapping.sub_range,
+                                host,
+                            };
+                            (resource::BufferMapAsyncStatus::Success, ptr)
+                        }
+                        Err(e) => {
+                            log::error!("failed to map buffer: {:?}", e);
+                            (resource::BufferMapAsyncStatus::Error, ptr::null_mut())
+                        }
                     };
-                }
-                pending_callbacks.push((mapping.op, result));
+                pending_callbacks.push((mapping.op, status, ptr));
             }
         }
         pending_callbacks
     }
+
+    /// Copy the data of the completed readbacks out of their staging memory.
+    pub(crate) fn handle_readbacks(
+        &mut self,
+        raw: &B::Device,
+    ) -> Vec<super::BufferReadPendingCallback> {
+        let mut pending_callbacks = Vec::with_capacity(self.ready_readbacks.len());
+        for mut readback in self.ready_readbacks.drain(..) {
+            let size = readback.size as usize;
+            let result = readback
+                .chunk
+                .memory
+                .map(raw, hal::memory::Segment::ALL)
+                .and_then(|mut mapped| {
+                    let slice = unsafe { mapped.read(raw, hal::memory::Segment::ALL) }?;
+                    Ok(slice[..size].to_vec())
+                });
+            let (status, data) = match result {
+                Ok(data) => (resource::BufferMapAsyncStatus::Success, data),
+                Err(e) => {
+                    log::error!("failed to map readback memory: {:?}", e);
+                    (resource::BufferMapAsyncStatus::Error, Vec::new())
+                }
+            };
+            self.free_resources.staging.push(readback.chunk);
+            pending_callbacks.push((readback.op, status, data));
+        }
+        pending_callbacks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{device::SubmittedWorkDoneStatus, id::TypedId as _, LifeGuard};
+
+    #[test]
+    fn drop_device_while_waiting() {
+        loom::model(|| {
+            let guard = LifeGuard::new();
+            let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+            let requested = id::BufferId::zip(1, 0, wgt::Backend::Empty);
+            let ready = id::BufferId::zip(2, 0, wgt::Backend::Empty);
+            tracker.map(requested, guard.add_ref());
+            tracker.ready_to_map.push(ready);
+
+            // Every pending mapping is handed out exactly once.
+            assert_eq!(tracker.drain_mappings(), [requested, ready]);
+            assert!(tracker.drain_mappings().is_empty());
+        });
+    }
+
+    #[test]
+    fn work_done_without_submissions() {
+        unsafe extern "C" fn count(_status: SubmittedWorkDoneStatus, userdata: *mut u8) {
+            *(userdata as *mut u32) += 1;
+        }
+
+        let mut fired = 0u32;
+        let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+        tracker.add_work_done(SubmittedWorkDoneClosure {
+            callback: count,
+            userdata: &mut fired as *mut u32 as *mut u8,
+        });
+
+        // Nothing is in flight, so the callback is ready right away, and only once.
+        let ready = tracker.handle_work_done();
+        assert_eq!(ready.len(), 1);
+        assert!(tracker.drain_work_done().is_empty());
+        for closure in ready {
+            closure.fire(SubmittedWorkDoneStatus::Success);
+        }
+        assert_eq!(fired, 1);
+    }
+
+    /// Free some memory once the submission `index` is done.
+    fn release(tracker: &mut LifetimeTracker<gfx_backend_empty::Backend>, index: SubmissionIndex) {
+        let memory = resource::ResourceMemory::Transient(BlockRing::allocation(0));
+        resources_after(&mut tracker.active, &mut tracker.free_resources, index).add_memory(memory);
+    }
+
+    #[test]
+    fn released_without_submission() {
+        let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+        tracker.track_submission(
+            1,
+            (),
+            &SuspectedResources::default(),
+            iter::empty(),
+            iter::empty(),
+            iter::empty(),
+        );
+        // A resource that no submission used is freed at the next cleanup,
+        // while the one used by the submission in flight waits for it.
+        release(&mut tracker, 0);
+        release(&mut tracker, 1);
+        assert_eq!(tracker.free_resources.transient.len(), 1);
+        assert_eq!(tracker.active[0].last_resources.transient.len(), 1);
+
+        // Once the submission is done, the resources it used are freed right away too.
+        assert_eq!(tracker.retire(1).len(), 1);
+        release(&mut tracker, 1);
+        assert_eq!(tracker.free_resources.transient.len(), 3);
+    }
+
+    #[test]
+    fn waiting_buffers_stay_suspected() {
+        let guard = LifeGuard::new();
+        let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+        let waiting = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let idle = id::BufferId::zip(1, 1, wgt::Backend::Empty);
+        tracker.map(waiting, guard.add_ref());
+        tracker.suspected_resources.buffers.extend(&[waiting, idle]);
+
+        // The buffer waiting to be mapped stays suspected, until the mapping is resolved.
+        assert_eq!(tracker.take_suspected_buffers(), [idle]);
+        assert_eq!(tracker.suspected_resources.buffers, [waiting]);
+        tracker.track_submission(
+            1,
+            (),
+            &SuspectedResources::default(),
+            iter::empty(),
+            iter::empty(),
+            iter::empty(),
+        );
+        tracker.mapped.clear();
+        tracker.active[0].mapped.push(waiting);
+        assert!(tracker.take_suspected_buffers().is_empty());
+        assert_eq!(tracker.retire(1).len(), 1);
+        assert!(tracker.take_suspected_buffers().is_empty());
+
+        // `handle_mapping` takes it out of the queue.
+        tracker.ready_to_map.clear();
+        assert_eq!(tracker.take_suspected_buffers(), [waiting]);
+        assert!(tracker.suspected_resources.buffers.is_empty());
+    }
+
+    #[cfg(feature = "resource-events")]
+    #[test]
+    fn deferred_destruction_events() {
+        use crate::device::events::{ResourceEvent, ResourceEventType};
+
+        unsafe extern "C" fn collect(event: &ResourceEvent, userdata: *mut u8) {
+            let events = &mut *(userdata as *mut Vec<(ResourceEventType, u64)>);
+            events.push((event.ty, event.id));
+        }
+
+        let mut received = Vec::new();
+        let mut events = ResourceEvents::default();
+        events.set_callback(Some(collect), &mut received as *mut _ as *mut u8);
+        let in_flight = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let idle = id::BufferId::zip(1, 1, wgt::Backend::Empty);
+        events.created(ResourceKind::Buffer, in_flight, ptr::null(), 16);
+        events.created(ResourceKind::Buffer, idle, ptr::null(), 16);
+
+        let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+        tracker.track_submission(
+            1,
+            (),
+            &SuspectedResources::default(),
+            iter::empty(),
+            iter::empty(),
+            iter::empty(),
+        );
+        // Both are dropped by the user, but the first one is still used by the GPU.
+        tracker.active[0]
+            .last_resources
+            .destroyed
+            .push((ResourceKind::Buffer, in_flight.into_raw()));
+        tracker
+            .free_resources
+            .destroyed
+            .push((ResourceKind::Buffer, idle.into_raw()));
+        tracker.report_destroyed(&mut events);
+        events.take().unwrap().fire();
+        assert_eq!(
+            received.last(),
+            Some(&(ResourceEventType::Destroyed, idle.into_raw()))
+        );
+
+        tracker.report_destroyed(&mut events);
+        assert!(events.take().is_none());
+        assert_eq!(tracker.retire(1).len(), 1);
+        tracker.report_destroyed(&mut events);
+        events.take().unwrap().fire();
+
+        let (created, destroyed): (Vec<_>, Vec<_>) = received
+            .iter()
+            .partition(|&&(ty, _)| ty == ResourceEventType::Created);
+        assert_eq!(created.len(), 2);
+        assert_eq!(
+            destroyed,
+            [
+                &(ResourceEventType::Destroyed, idle.into_raw()),
+                &(ResourceEventType::Destroyed, in_flight.into_raw()),
+            ]
+        );
+    }
+
+    #[test]
+    fn hang_timeout_slices() {
+        let slice = Duration::from_millis(CLEANUP_WAIT_MS);
+        // without a timeout, the waits never end
+        assert_eq!(next_wait(None, slice * 100), (slice, false));
+        // long timeouts are split into slices, the last one is partial
+        let timeout = Some(slice * 2 + Duration::from_millis(10));
+        assert_eq!(next_wait(timeout, Duration::default()), (slice, false));
+        assert_eq!(
+            next_wait(timeout, slice * 2),
+            (Duration::from_millis(10), true)
+        );
+        // an expired timeout still checks the fences once
+        assert_eq!(next_wait(timeout, slice * 3), (Duration::default(), true));
+        // short timeouts are waited at once
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(
+            next_wait(timeout, Duration::default()),
+            (Duration::from_millis(10), true)
+        );
+    }
+
+    #[test]
+    fn transient_memory_follows_submissions() {
+        const FRAMES_IN_FLIGHT: usize = 2;
+        const TARGET_SIZE: u64 = 0x80_0000;
+        let memory_type = hal::MemoryTypeId(0);
+        let mut tracker = LifetimeTracker::<gfx_backend_empty::Backend>::new();
+        let mut ring = BlockRing::default();
+        // Placements of the submissions that the GPU isn't done with.
+        let mut in_flight = std::collections::VecDeque::<Vec<(usize, u64)>>::new();
+        let mut warm_blocks = 0;
+
+        for index in 1..=100 {
+            if index == 10 {
+                warm_blocks = ring.block_count();
+            }
+            let targets = (0..6)
+                .map(|_| match ring.place(memory_type, TARGET_SIZE, 0x100) {
+                    Some(place) => place,
+                    None => (ring.add(memory_type, TARGET_SIZE), 0),
+                })
+                .collect::<Vec<_>>();
+            for target in &targets {
+                assert!(
+                    !in_flight.iter().flatten().any(|place| place == target),
+                    "Submission {} aliases the memory of a submission in flight",
+                    index
+                );
+            }
+
+            // The render targets are dropped right after their submission,
+            // which keeps their memory until it's done.
+            tracker.track_submission(
+                index,
+                (),
+                &SuspectedResources::default(),
+                iter::empty(),
+                iter::empty(),
+                iter::empty(),
+            );
+            let resources = &mut tracker.active.last_mut().unwrap().last_resources;
+            for &(block, _) in &targets {
+                let allocation = BlockRing::allocation(block);
+                resources.add_memory(resource::ResourceMemory::Transient(allocation));
+            }
+            in_flight.push_back(targets);
+
+            // The GPU is `FRAMES_IN_FLIGHT` submissions behind.
+            if in_flight.len() > FRAMES_IN_FLIGHT {
+                assert_eq!(tracker.retire(1).len(), 1);
+                tracker.free_resources.release_transient(&mut ring);
+                in_flight.pop_front();
+            }
+        }
+
+        assert_eq!(ring.block_count(), warm_blocks);
+    }
 }
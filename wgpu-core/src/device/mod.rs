@@ -4,35 +4,61 @@
 
 use crate::{
     binding_model, command, conv,
-    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Input, Token},
-    id, pipeline, resource, swap_chain,
-    track::{BufferState, TextureState, TrackerSet},
+    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Input, Storage, Token},
+    id,
+    instance::Adapter,
+    pipeline, resource, swap_chain,
+    track::{BufferState, ScopeStamp, TextureState, TrackerSet},
     FastHashMap, LifeGuard, PrivateFeatures, Stored
 };
 
 use arrayvec::ArrayVec;
 use copyless::VecHelper as _;
-use gfx_descriptor::DescriptorAllocator;
-use gfx_memory::{Block, Heaps};
+use gfx_descriptor::{DescriptorAllocator, DescriptorSet};
+use gfx_memory::{Block, Heaps, MemoryBlock};
 use hal::{
     command::CommandBuffer as _,
     device::Device as _,
     window::{PresentationSurface as _, Surface as _},
 };
 use parking_lot::{Mutex, MutexGuard};
-use wgt::{BufferAddress, InputStepMode, TextureDimension, TextureFormat, BIND_BUFFER_ALIGNMENT};
+use wgt::{BufferAddress, InputStepMode, TextureDimension, TextureFormat};
 
 use std::{
-    collections::hash_map::Entry, ffi, iter, marker::PhantomData, ptr, slice,
-    sync::atomic::Ordering,
+    collections::{hash_map::Entry, VecDeque},
+    ffi, fmt, iter,
+    marker::PhantomData,
+    ops::Range,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 
 use spirv_headers::ExecutionModel;
 
+#[cfg(feature = "trace")]
+mod capture;
+#[cfg(feature = "resource-events")]
+pub mod events;
 mod life;
 mod queue;
+mod staging;
 #[cfg(any(feature = "trace", feature = "replay"))]
 pub mod trace;
+mod transient;
+
+#[cfg(feature = "bench")]
+pub(crate) use life::{LifetimeTracker, SuspectedResources};
+pub use queue::{
+    QueueBufferError, QueueSubmitError, StagingBufferError, SubmissionToken, TextureReadError,
+    TextureWriteError, TextureWriteLayout, TextureWriteRegion,
+};
+pub(crate) use staging::StagingChunk;
+pub(crate) use transient::{LazyAllocation, TransientAllocation};
 
 #[cfg(feature = "trace")]
 use trace::{Action, Trace};
@@ -52,6 +78,8 @@ fn own_label(label: &Label) -> String {
 pub const MAX_COLOR_TARGETS: usize = 4;
 pub const MAX_MIP_LEVELS: usize = 16;
 pub const MAX_VERTEX_BUFFERS: usize = 16;
+pub const DEFAULT_STAGING_CHUNK_SIZE: BufferAddress = 64 << 20;
+pub const DEFAULT_MAX_SUBMISSIONS_IN_FLIGHT: u32 = 32;
 
 pub fn all_buffer_stages() -> hal::pso::PipelineStage {
     use hal::pso::PipelineStage as Ps;
@@ -78,6 +106,34 @@ pub fn all_image_stages() -> hal::pso::PipelineStage {
         | Ps::TRANSFER
 }
 
+/// A resource is used with a device other than the one that created it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WrongDeviceError {
+    /// The resource, as formatted by `Debug`.
+    pub resource: String,
+    /// The device it's used with.
+    pub expected: id::DeviceId,
+    /// The device that created it.
+    pub actual: id::DeviceId,
+}
+
+/// Make sure that a resource owned by `owner_id` is used on the same device.
+pub(crate) fn check_device<I: fmt::Debug>(
+    device_id: id::DeviceId,
+    resource_id: I,
+    owner_id: id::DeviceId,
+) -> Result<(), WrongDeviceError> {
+    if owner_id == device_id {
+        Ok(())
+    } else {
+        Err(WrongDeviceError {
+            resource: format!("{:?}", resource_id),
+            expected: device_id,
+            actual: owner_id,
+        })
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum HostMap {
     Read,
@@ -109,12 +165,124 @@ impl RenderPassContext {
 
 pub(crate) type RenderPassKey = AttachmentData<hal::pass::Attachment>;
 pub(crate) type FramebufferKey = AttachmentData<id::TextureViewId>;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct SamplerKey {
+    address_modes: [wgt::AddressMode; 3],
+    mag_filter: wgt::FilterMode,
+    min_filter: wgt::FilterMode,
+    mipmap_filter: wgt::FilterMode,
+    lod_clamp_bits: [u32; 2],
+    compare: wgt::CompareFunction,
+}
+
+impl SamplerKey {
+    pub(crate) fn new<L>(desc: &wgt::SamplerDescriptor<L>) -> Self {
+        SamplerKey {
+            address_modes: [desc.address_mode_u, desc.address_mode_v, desc.address_mode_w],
+            mag_filter: desc.mag_filter,
+            min_filter: desc.min_filter,
+            mipmap_filter: desc.mipmap_filter,
+            lod_clamp_bits: [desc.lod_min_clamp.to_bits(), desc.lod_max_clamp.to_bits()],
+            compare: desc.compare,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) enum BindingKey {
+    Buffer {
+        id: id::BufferId,
+        offset: BufferAddress,
+        size: Option<wgt::BufferSize>,
+    },
+    Sampler(id::SamplerId),
+    TextureView(id::TextureViewId),
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct BindGroupKey {
+    layout_id: id::BindGroupLayoutId,
+    entries: Vec<(u32, BindingKey)>,
+}
+
+impl BindGroupKey {
+    fn new(layout_id: id::BindGroupLayoutId, entries: &[binding_model::BindGroupEntry]) -> Self {
+        let mut entries = entries
+            .iter()
+            .map(|entry| {
+                let key = match entry.resource {
+                    binding_model::BindingResource::Buffer(ref bb) => BindingKey::Buffer {
+                        id: bb.buffer,
+                        offset: bb.offset,
+                        size: bb.size,
+                    },
+                    binding_model::BindingResource::Sampler(id) => BindingKey::Sampler(id),
+                    binding_model::BindingResource::TextureView(id) => BindingKey::TextureView(id),
+                };
+                (entry.binding, key)
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|&(binding, _)| binding);
+        BindGroupKey { layout_id, entries }
+    }
+}
+
+/// Live objects of a device, by the key of their descriptor, that are returned
+/// again when an identical object is created with `deduplicate_objects`.
+///
+/// The objects remember their key, so that destroying them is a single lookup.
+#[derive(Debug)]
+pub(crate) struct DedupCache<K, I> {
+    objects: FastHashMap<K, I>,
+}
+
+impl<K: std::hash::Hash + Eq, I: Copy + PartialEq> DedupCache<K, I> {
+    pub(crate) fn new() -> Self {
+        DedupCache {
+            objects: FastHashMap::default(),
+        }
+    }
+
+    pub(crate) fn find(&self, key: &K) -> Option<I> {
+        self.objects.get(key).cloned()
+    }
+
+    pub(crate) fn insert(&mut self, key: K, id: I) {
+        self.objects.insert(key, id);
+    }
+
+    /// Forget the object `id`, unless another object took its key since.
+    pub(crate) fn remove(&mut self, key: &K, id: I) {
+        if self.objects.get(key) == Some(&id) {
+            self.objects.remove(key);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.objects.len()
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub(crate) struct TextureViewKey {
+    format: TextureFormat,
+    dimension: wgt::TextureViewDimension,
+    aspects: hal::format::Aspects,
+    levels: Range<hal::image::Level>,
+    layers: Range<hal::image::Layer>,
+    usage: wgt::TextureUsage,
+}
 pub(crate) type RenderPassContext = AttachmentData<TextureFormat>;
 
 // This typedef is needed to work around cbindgen limitations.
 type RawBufferMut = *mut u8;
 type BufferMapResult = Result<RawBufferMut, hal::device::MapError>;
-type BufferMapPendingCallback = (resource::BufferMapOperation, BufferMapResult);
+type BufferMapPendingCallback = (
+    resource::BufferMapOperation,
+    resource::BufferMapAsyncStatus,
+    RawBufferMut,
+);
 
 pub type BufferMapReadCallback = unsafe extern "C" fn(
     status: resource::BufferMapAsyncStatus,
@@ -123,6 +291,112 @@ pub type BufferMapReadCallback = unsafe extern "C" fn(
 );
 pub type BufferMapWriteCallback =
     unsafe extern "C" fn(status: resource::BufferMapAsyncStatus, data: *mut u8, userdata: *mut u8);
+/// Receives the `size` bytes read by `queue_read_buffer` or `queue_read_texture`,
+/// which are only valid during the call.
+pub type BufferReadCallback = unsafe extern "C" fn(
+    status: resource::BufferMapAsyncStatus,
+    data: *const u8,
+    size: usize,
+    userdata: *mut u8,
+);
+pub(crate) type BufferReadPendingCallback = (
+    resource::BufferReadOperation,
+    resource::BufferMapAsyncStatus,
+    Vec<u8>,
+);
+
+/// Status passed to the `SubmittedWorkDoneCallback`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubmittedWorkDoneStatus {
+    Success = 0,
+    /// The device was lost or destroyed before the work was seen done.
+    DeviceLost = 1,
+}
+
+/// Called by `queue_on_submitted_work_done` once the work is done.
+pub type SubmittedWorkDoneCallback =
+    unsafe extern "C" fn(status: SubmittedWorkDoneStatus, userdata: *mut u8);
+
+#[derive(Debug)]
+pub(crate) struct SubmittedWorkDoneClosure {
+    pub callback: SubmittedWorkDoneCallback,
+    pub userdata: *mut u8,
+}
+
+unsafe impl Send for SubmittedWorkDoneClosure {}
+unsafe impl Sync for SubmittedWorkDoneClosure {}
+
+impl SubmittedWorkDoneClosure {
+    //Note: must be called with nothing locked.
+    pub(crate) fn fire(self, status: SubmittedWorkDoneStatus) {
+        unsafe {
+            (self.callback)(status, self.userdata);
+        }
+    }
+}
+
+pub(crate) type SubmittedWorkDonePendingCallback =
+    (SubmittedWorkDoneClosure, SubmittedWorkDoneStatus);
+
+/// Called whenever a device gets work that only completes after a poll,
+/// i.e. a queue submission or a buffer mapping request.
+///
+/// This lets the embedder schedule a `device_poll` instead of polling blindly.
+pub type DeviceMaintainHook = unsafe extern "C" fn(device_id: id::DeviceId, userdata: *mut u8);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MaintainHook {
+    callback: DeviceMaintainHook,
+    userdata: *mut u8,
+}
+
+unsafe impl Send for MaintainHook {}
+unsafe impl Sync for MaintainHook {}
+
+impl MaintainHook {
+    //Note: must be called with nothing locked, the hook is free to
+    // call back into wgpu, including `device_poll`.
+    pub(crate) fn fire(self, device_id: id::DeviceId) {
+        unsafe {
+            (self.callback)(device_id, self.userdata);
+        }
+    }
+}
+
+/// Reason of a device loss, passed to the `DeviceLostCallback`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceLostReason {
+    /// The submitted work didn't complete within the hang timeout
+    /// of the device descriptor.
+    Timeout,
+}
+
+/// Called once when the device is lost.
+///
+/// The pending buffer mappings of the device are then aborted with
+/// `BufferMapAsyncStatus::DeviceLost`, as well as any later ones.
+pub type DeviceLostCallback =
+    unsafe extern "C" fn(device_id: id::DeviceId, reason: DeviceLostReason, userdata: *mut u8);
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LostHook {
+    callback: DeviceLostCallback,
+    userdata: *mut u8,
+}
+
+unsafe impl Send for LostHook {}
+unsafe impl Sync for LostHook {}
+
+impl LostHook {
+    //Note: must be called with nothing locked.
+    pub(crate) fn fire(self, device_id: id::DeviceId, reason: DeviceLostReason) {
+        unsafe {
+            (self.callback)(device_id, reason, self.userdata);
+        }
+    }
+}
 
 fn map_buffer<B: hal::Backend>(
     raw: &B::Device,
@@ -135,7 +409,7 @@ fn map_buffer<B: hal::Backend>(
             offset: sub_range.offset,
             size: sub_range.size,
         };
-        let mapped = buffer.memory.map(raw, segment)?;
+        let mapped = buffer.memory.block_mut().map(raw, segment)?;
         let mr = mapped.range();
         let segment = hal::memory::Segment {
             offset: mr.start,
@@ -146,8 +420,11 @@ fn map_buffer<B: hal::Backend>(
 
     buffer.sync_mapped_writes = match kind {
         HostMap::Read if needs_sync => unsafe {
-            raw.invalidate_mapped_memory_ranges(iter::once((buffer.memory.memory(), segment)))
-                .unwrap();
+            raw.invalidate_mapped_memory_ranges(iter::once((
+                buffer.memory.block_mut().memory(),
+                segment,
+            )))
+            .unwrap();
             None
         },
         HostMap::Write if needs_sync => Some(segment),
@@ -159,8 +436,11 @@ fn map_buffer<B: hal::Backend>(
 fn unmap_buffer<B: hal::Backend>(raw: &B::Device, buffer: &mut resource::Buffer<B>) {
     if let Some(segment) = buffer.sync_mapped_writes.take() {
         unsafe {
-            raw.flush_mapped_memory_ranges(iter::once((buffer.memory.memory(), segment)))
-                .unwrap()
+            raw.flush_mapped_memory_ranges(iter::once((
+                buffer.memory.block_mut().memory(),
+                segment,
+            )))
+            .unwrap()
         };
     }
 }
@@ -168,25 +448,178 @@ fn unmap_buffer<B: hal::Backend>(raw: &B::Device, buffer: &mut resource::Buffer<
 //Note: this logic is specifically moved out of `handle_mapping()` in order to
 // have nothing locked by the time we execute users callback code.
 fn fire_map_callbacks<I: IntoIterator<Item = BufferMapPendingCallback>>(callbacks: I) {
-    for (operation, result) in callbacks {
-        let (status, ptr) = match result {
-            Ok(ptr) => (resource::BufferMapAsyncStatus::Success, ptr),
-            Err(e) => {
-                log::error!("failed to map buffer: {:?}", e);
-                (resource::BufferMapAsyncStatus::Error, ptr::null_mut())
-            }
-        };
-        match operation {
-            resource::BufferMapOperation::Read { callback, userdata } => unsafe {
-                callback(status, ptr, userdata)
-            },
-            resource::BufferMapOperation::Write { callback, userdata } => unsafe {
-                callback(status, ptr, userdata)
-            },
+    for (operation, status, ptr) in callbacks {
+        operation.fire(status, ptr);
+    }
+}
+
+pub(crate) fn fire_read_callbacks<I: IntoIterator<Item = BufferReadPendingCallback>>(callbacks: I) {
+    for (operation, status, data) in callbacks {
+        operation.fire(status, &data);
+    }
+}
+
+/// Callbacks still pending when the global is dropped, which are all
+/// aborted with `DeviceLost` before the hubs are cleared.
+#[derive(Debug, Default)]
+pub(crate) struct AbortedCallbacks {
+    mappings: Vec<resource::BufferMapOperation>,
+    readbacks: Vec<resource::BufferReadOperation>,
+    work_done: Vec<SubmittedWorkDoneClosure>,
+    pipelines: Vec<pipeline::PipelineCallback>,
+}
+
+impl AbortedCallbacks {
+    //Note: must be called with nothing locked.
+    pub(crate) fn fire(self) {
+        for operation in self.mappings {
+            operation.fire(resource::BufferMapAsyncStatus::DeviceLost, ptr::null_mut());
+        }
+        for operation in self.readbacks {
+            operation.fire(resource::BufferMapAsyncStatus::DeviceLost, &[]);
+        }
+        for closure in self.work_done {
+            closure.fire(SubmittedWorkDoneStatus::DeviceLost);
+        }
+        for callback in self.pipelines {
+            callback.fire(pipeline::PipelineCreationStatus::DeviceLost);
         }
     }
 }
 
+pub(crate) fn fire_work_done_callbacks<I: IntoIterator<Item = SubmittedWorkDonePendingCallback>>(
+    callbacks: I,
+) {
+    for (closure, status) in callbacks {
+        closure.fire(status);
+    }
+}
+
+/// Result of a graphics debugger capture request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebuggerCaptureStatus {
+    /// The request was passed to the backend, which does nothing
+    /// if no graphics debugger is attached.
+    Requested,
+    /// A capture is already in progress on this device.
+    AlreadyCapturing,
+    /// There is no capture in progress on this device.
+    NotCapturing,
+    /// The device id is invalid.
+    InvalidDevice,
+}
+
+/// Error of a `Global` function given an id that doesn't name a live object
+/// of its type: a zero or forged id, the id of a destroyed object, or an id
+/// of a backend that isn't enabled.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvalidIdError {
+    Adapter(id::AdapterId),
+    Device(id::DeviceId),
+    Buffer(id::BufferId),
+    Texture(id::TextureId),
+    TextureView(id::TextureViewId),
+    Sampler(id::SamplerId),
+    BindGroupLayout(id::BindGroupLayoutId),
+    PipelineLayout(id::PipelineLayoutId),
+    ShaderModule(id::ShaderModuleId),
+    PipelineCache(id::PipelineCacheId),
+    Surface(id::SurfaceId),
+    /// The object is alive, but belongs to another device than the one it's used with.
+    WrongDevice(WrongDeviceError),
+}
+
+impl From<WrongDeviceError> for InvalidIdError {
+    fn from(error: WrongDeviceError) -> Self {
+        InvalidIdError::WrongDevice(error)
+    }
+}
+
+/// Error of a device memory allocation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AllocationError {
+    /// The device is out of memory, even after freeing the resources
+    /// the GPU is done with.
+    OutOfMemory {
+        /// Kind of the resource being created.
+        resource: &'static str,
+        /// Number of bytes requested.
+        size: BufferAddress,
+    },
+    /// The descriptor pools are out of memory, even after freeing
+    /// the descriptor sets the GPU is done with.
+    OutOfDescriptors {
+        /// Kind of the resource being created.
+        resource: &'static str,
+    },
+    /// The resource was to be created on an invalid device.
+    InvalidId(InvalidIdError),
+}
+
+impl From<InvalidIdError> for AllocationError {
+    fn from(error: InvalidIdError) -> Self {
+        AllocationError::InvalidId(error)
+    }
+}
+
+/// Statistics about the internal state of a device.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceReport {
+    /// Number of native command pools, one per recording thread.
+    pub command_pools: usize,
+    /// Number of native command buffers allocated from all the pools.
+    pub command_buffers: usize,
+    /// Number of memory blocks backing the transient buffers and textures.
+    pub transient_blocks: usize,
+    /// Bytes of attachment memory that render passes didn't load or store,
+    /// because of their `LoadOp` and `StoreOp`.
+    pub skipped_attachment_bytes: u64,
+    /// Bytes of the live transient attachments in lazily allocated memory,
+    /// which the driver doesn't have to back with actual memory.
+    pub lazy_attachment_bytes: u64,
+    /// Bytes of the free staging buffers kept for the next uploads and readbacks.
+    pub free_staging_bytes: u64,
+    /// Number of submissions the GPU wasn't known to be done with
+    /// as of the last submission or poll.
+    pub submissions_in_flight: usize,
+    /// Number of native command buffers allocated for stitching
+    /// the resource transitions at submission.
+    pub transit_buffers_allocated: usize,
+    /// Number of times a transit command buffer of a completed
+    /// submission was reused instead of allocating a new one.
+    pub transit_buffers_reused: usize,
+}
+
+/// Work submitted to a device since the counters were last reset.
+///
+/// Only accumulated with the `counters` feature, and zero otherwise.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+#[cfg_attr(feature = "replay", derive(serde::Deserialize))]
+pub struct InternalCounters {
+    pub draws: u64,
+    pub dispatches: u64,
+    /// Triangles of the direct draws.
+    pub triangles: u64,
+    pub pipeline_switches: u64,
+    pub bind_group_switches: u64,
+    /// Bytes written by `queue_write_buffer` and `queue_write_staging_buffer`.
+    pub uploaded_bytes: u64,
+    /// Number of submissions that flushed the pending queue writes.
+    pub pending_write_flushes: u64,
+}
+
+impl InternalCounters {
+    #[cfg(feature = "counters")]
+    fn add_pass(&mut self, pass: &command::PassReport) {
+        self.draws += pass.draws as u64;
+        self.dispatches += pass.dispatches as u64;
+        self.triangles += pass.triangles;
+        self.pipeline_switches += pass.pipeline_switches as u64;
+        self.bind_group_switches += pass.bind_group_switches as u64;
+    }
+}
+
 #[derive(Debug)]
 pub struct Device<B: hal::Backend> {
     pub(crate) raw: B::Device,
@@ -195,37 +628,74 @@ pub struct Device<B: hal::Backend> {
     pub(crate) com_allocator: command::CommandAllocator<B>,
     mem_allocator: Mutex<Heaps<B>>,
     desc_allocator: Mutex<DescriptorAllocator<B>>,
+    transient_pool: Mutex<transient::TransientPool<B>>,
+    staging_belt: Mutex<staging::StagingBelt<B>>,
     life_guard: LifeGuard,
     pub(crate) trackers: Mutex<TrackerSet>,
     pub(crate) render_passes: Mutex<FastHashMap<RenderPassKey, B::RenderPass>>,
     pub(crate) framebuffers: Mutex<FastHashMap<FramebufferKey, B::Framebuffer>>,
+    // Shared with the threads compiling pipelines, which don't hold the device lock.
+    pub(crate) pipeline_queue: Arc<pipeline::PipelineQueue>,
+    pipeline_cache_header: Vec<u8>,
+    deduplicate_objects: bool,
+    staging_chunk_size: BufferAddress,
+    sampler_cache: Mutex<DedupCache<SamplerKey, id::SamplerId>>,
+    bind_group_cache: Mutex<DedupCache<BindGroupKey, id::BindGroupId>>,
+    bind_group_members: Mutex<binding_model::BindGroupMembers>,
+    pub(crate) presented_frames: Mutex<VecDeque<swap_chain::PresentedFrame>>,
+    pub(crate) maintain_hook: Mutex<Option<MaintainHook>>,
+    lost_hook: Mutex<Option<LostHook>>,
+    hang_timeout: Option<Duration>,
+    max_submissions_in_flight: usize,
+    nonblocking_submit: bool,
+    pub(crate) strict_webgpu: bool,
+    // Set once the device is hung, nothing completes on it any more.
+    lost: AtomicBool,
     // Life tracker should be locked right after the device and before anything else.
     life_tracker: Mutex<life::LifetimeTracker<B>>,
     temp_suspected: life::SuspectedResources,
     pub(crate) private_features: PrivateFeatures,
+    unsupported_vertex_formats: Vec<wgt::VertexFormat>,
+    pub(crate) capabilities: wgt::Capabilities,
+    pub(crate) downlevel: wgt::DownlevelCapabilities,
+    shader_features: pipeline::ShaderFeatures,
     limits: wgt::Limits,
-    extensions: wgt::Extensions,
+    pub(crate) extensions: wgt::Extensions,
     pending_writes: queue::PendingWrites<B>,
+    // Set while a graphics debugger capture is in progress.
+    debugger_capture: AtomicBool,
+    pub(crate) skipped_attachment_bytes: AtomicU64,
+    #[cfg(feature = "counters")]
+    pub(crate) counters: Mutex<InternalCounters>,
+    #[cfg(feature = "resource-events")]
+    pub(crate) resource_events: Mutex<events::ResourceEvents>,
     #[cfg(feature = "trace")]
-    pub(crate) trace: Option<Mutex<Trace>>,
+    pub(crate) trace: Mutex<Option<Trace>>,
 }
 
 impl<B: GfxBackend> Device<B> {
     pub(crate) fn new(
         raw: B::Device,
         adapter_id: Stored<id::AdapterId>,
+        adapter_info: &crate::instance::AdapterInfo,
         queue_group: hal::queue::QueueGroup<B>,
         mem_props: hal::adapter::MemoryProperties,
         non_coherent_atom_size: u64,
         supports_texture_d24_s8: bool,
+        unsupported_vertex_formats: Vec<wgt::VertexFormat>,
+        capabilities: wgt::Capabilities,
+        downlevel: wgt::DownlevelCapabilities,
+        shader_features: pipeline::ShaderFeatures,
         desc: &wgt::DeviceDescriptor,
         trace_path: Option<&std::path::Path>,
+        deterministic_ids: bool,
     ) -> Self {
         // don't start submission index at zero
         let life_guard = LifeGuard::new();
         life_guard.submission_index.fetch_add(1, Ordering::Relaxed);
 
         let com_allocator = command::CommandAllocator::new(queue_group.family, &raw);
+        let transient_pool = transient::TransientPool::new(mem_props.memory_types.clone());
         let heaps = unsafe {
             Heaps::new(
                 &mem_props,
@@ -245,6 +715,8 @@ impl<B: GfxBackend> Device<B> {
             Some(_) => log::warn!("Tracing feature is not enabled"),
             None => (),
         }
+        #[cfg(not(feature = "trace"))]
+        let _ = deterministic_ids;
 
         Device {
             raw,
@@ -252,34 +724,212 @@ impl<B: GfxBackend> Device<B> {
             com_allocator,
             mem_allocator: Mutex::new(heaps),
             desc_allocator: Mutex::new(DescriptorAllocator::new()),
+            transient_pool: Mutex::new(transient_pool),
+            staging_belt: Mutex::new(staging::StagingBelt::new()),
             queue_group,
             life_guard,
             trackers: Mutex::new(TrackerSet::new(B::VARIANT)),
             render_passes: Mutex::new(FastHashMap::default()),
             framebuffers: Mutex::new(FastHashMap::default()),
+            pipeline_queue: Arc::new(pipeline::PipelineQueue::default()),
+            pipeline_cache_header: pipeline::pipeline_cache_header(adapter_info),
+            deduplicate_objects: desc.deduplicate_objects,
+            staging_chunk_size: desc
+                .staging_chunk_size
+                .unwrap_or(DEFAULT_STAGING_CHUNK_SIZE),
+            sampler_cache: Mutex::new(DedupCache::new()),
+            bind_group_cache: Mutex::new(DedupCache::new()),
+            bind_group_members: Mutex::new(binding_model::BindGroupMembers::default()),
+            presented_frames: Mutex::new(VecDeque::new()),
+            maintain_hook: Mutex::new(None),
+            lost_hook: Mutex::new(None),
+            hang_timeout: desc.hang_timeout_ms.map(Duration::from_millis),
+            max_submissions_in_flight: desc
+                .max_submissions_in_flight
+                .unwrap_or(DEFAULT_MAX_SUBMISSIONS_IN_FLIGHT)
+                .max(1) as usize,
+            nonblocking_submit: desc.nonblocking_submit,
+            strict_webgpu: desc.strict_webgpu,
+            lost: AtomicBool::new(false),
             life_tracker: Mutex::new(life::LifetimeTracker::new()),
             temp_suspected: life::SuspectedResources::default(),
             #[cfg(feature = "trace")]
-            trace: trace_path.and_then(|path| match Trace::new(path) {
+            trace: Mutex::new(trace_path.and_then(|path| match Trace::new(path) {
                 Ok(mut trace) => {
                     trace.add(Action::Init {
                         desc: desc.clone(),
                         backend: B::VARIANT,
+                        deterministic_ids,
                     });
-                    Some(Mutex::new(trace))
+                    Some(trace)
                 }
                 Err(e) => {
                     log::warn!("Unable to start a trace in '{:?}': {:?}", path, e);
                     None
                 }
-            }),
+            })),
             private_features: PrivateFeatures {
                 supports_texture_d24_s8,
             },
+            unsupported_vertex_formats,
+            capabilities,
+            downlevel,
+            shader_features,
             limits: desc.limits.clone(),
             extensions: desc.extensions.clone(),
             pending_writes: queue::PendingWrites::new(),
+            debugger_capture: AtomicBool::new(false),
+            skipped_attachment_bytes: AtomicU64::new(0),
+            #[cfg(feature = "counters")]
+            counters: Mutex::new(InternalCounters::default()),
+            #[cfg(feature = "resource-events")]
+            resource_events: Mutex::new(events::ResourceEvents::default()),
+        }
+    }
+
+    /// Mark the bind groups containing a destroyed member as unusable.
+    fn invalidate_bind_groups(
+        &self,
+        bind_group_guard: &mut Storage<binding_model::BindGroup<B>, id::BindGroupId>,
+        member: binding_model::BindGroupMember,
+    ) {
+        for id in self.bind_group_members.lock().take(member) {
+            bind_group_guard[id].destroyed_member.get_or_insert(member);
+        }
+    }
+
+    /// Panics if the view belongs to a swap chain frame that was already presented.
+    pub(crate) fn check_presented_view(&self, view_id: id::TextureViewId) {
+        if let Some(presented) = self
+            .presented_frames
+            .lock()
+            .iter()
+            .find(|presented| presented.view_id == view_id)
+        {
+            panic!(
+                "Texture view {:?} is frame {} of swap chain {:?}, which has already been presented",
+                view_id, presented.frame, presented.swap_chain_id
+            );
+        }
+    }
+
+    fn validate_rasterization_state(&self, state: &wgt::RasterizationStateDescriptor) {
+        // Polygons are always filled, which is the only mode conservative rasterization allows.
+        if state.conservative {
+            assert!(
+                self.extensions.conservative_rasterization,
+                "Conservative rasterization requires the conservative_rasterization extension"
+            );
+        }
+        if state.depth_bias_clamp != 0.0 && !state.dynamic_depth_bias {
+            assert!(
+                self.downlevel.depth_bias_clamp,
+                "Depth bias clamp {} is not supported by the adapter",
+                state.depth_bias_clamp
+            );
+        }
+    }
+
+    /// Free the resources the GPU is done with, to make room for new allocations.
+    ///
+    /// Note: the life tracker is locked, so nothing but the device should be.
+    fn free_completed_resources(&self) {
+        let mut life_tracker = self.life_tracker.lock();
+        life_tracker.triage_submissions(&self.raw, false);
+        life_tracker.cleanup(
+            &self.raw,
+            &self.mem_allocator,
+            &self.transient_pool,
+            &self.staging_belt,
+            &self.desc_allocator,
+            #[cfg(feature = "resource-events")]
+            &self.resource_events,
+        );
+    }
+
+    /// Allocate a descriptor set of the bind group `layout`, retrying once
+    /// after freeing the resources the GPU is done with.
+    pub(crate) fn allocate_descriptor_set(
+        &self,
+        layout: &binding_model::BindGroupLayout<B>,
+        resource: &'static str,
+    ) -> Result<DescriptorSet<B>, AllocationError> {
+        let allocate = || {
+            let mut desc_sets = ArrayVec::<[_; 1]>::new();
+            unsafe {
+                self.desc_allocator.lock().allocate(
+                    &self.raw,
+                    &layout.raw,
+                    &layout.desc_counts,
+                    1,
+                    &mut desc_sets,
+                )
+            }
+            .map(|_| desc_sets.pop().unwrap())
+        };
+        if let Ok(desc_set) = allocate() {
+            return Ok(desc_set);
+        }
+        self.free_completed_resources();
+        allocate().map_err(|e| {
+            log::error!("Out of descriptors for a {}: {:?}", resource, e);
+            AllocationError::OutOfDescriptors { resource }
+        })
+    }
+
+    /// Allocate device memory, retrying once after freeing the resources
+    /// the GPU is done with.
+    pub(crate) fn allocate_memory(
+        &self,
+        requirements: &hal::memory::Requirements,
+        usage: gfx_memory::MemoryUsage,
+        kind: gfx_memory::Kind,
+        resource: &'static str,
+    ) -> Result<MemoryBlock<B>, AllocationError> {
+        match self
+            .mem_allocator
+            .lock()
+            .allocate(&self.raw, requirements, usage, kind)
+        {
+            Ok(block) => return Ok(block),
+            Err(e) => log::warn!(
+                "Allocation of {} bytes for a {} failed ({:?}), freeing completed resources",
+                requirements.size,
+                resource,
+                e
+            ),
         }
+        self.free_completed_resources();
+        self.mem_allocator
+            .lock()
+            .allocate(&self.raw, requirements, usage, kind)
+            .map_err(|e| {
+                log::error!("Out of memory for a {}: {:?}", resource, e);
+                AllocationError::OutOfMemory {
+                    resource,
+                    size: requirements.size,
+                }
+            })
+    }
+
+    /// Place a transient resource in the pool with `bind`, retrying once
+    /// after freeing the resources the GPU is done with.
+    fn bind_transient(
+        &self,
+        size: u64,
+        resource: &'static str,
+        mut bind: impl FnMut(
+            &mut transient::TransientPool<B>,
+        ) -> Result<TransientAllocation, hal::device::AllocationError>,
+    ) -> Result<TransientAllocation, AllocationError> {
+        if let Ok(allocation) = bind(&mut self.transient_pool.lock()) {
+            return Ok(allocation);
+        }
+        self.free_completed_resources();
+        bind(&mut self.transient_pool.lock()).map_err(|e| {
+            log::error!("Out of memory for a {}: {:?}", resource, e);
+            AllocationError::OutOfMemory { resource, size }
+        })
     }
 
     fn lock_life_internal<'this, 'token: 'this>(
@@ -296,60 +946,268 @@ impl<B: GfxBackend> Device<B> {
         Self::lock_life_internal(&self.life_tracker, token)
     }
 
+    /// Queue resources for destruction, and destroy the ones that no submission
+    /// in flight uses right away, instead of waiting for the next `maintain`.
+    fn release<'this, 'token: 'this, G: GlobalIdentityHandlerFactory>(
+        &'this self,
+        global: &Global<G>,
+        token: &mut Token<'token, Self>,
+        suspect: impl FnOnce(&mut life::SuspectedResources),
+    ) {
+        let mut life_tracker = self.lock_life(token);
+        suspect(&mut life_tracker.suspected_resources);
+        life_tracker.triage_submissions(&self.raw, false);
+        life_tracker.triage_suspected(
+            global,
+            &self.trackers,
+            #[cfg(feature = "trace")]
+            &self.trace,
+            token,
+        );
+        life_tracker.cleanup(
+            &self.raw,
+            &self.mem_allocator,
+            &self.transient_pool,
+            &self.staging_belt,
+            &self.desc_allocator,
+            #[cfg(feature = "resource-events")]
+            &self.resource_events,
+        );
+    }
+
     fn maintain<'this, 'token: 'this, G: GlobalIdentityHandlerFactory>(
         &'this self,
         global: &Global<G>,
         force_wait: bool,
         token: &mut Token<'token, Self>,
-    ) -> Vec<BufferMapPendingCallback> {
+    ) -> (
+        Vec<BufferMapPendingCallback>,
+        Vec<BufferReadPendingCallback>,
+        Vec<SubmittedWorkDonePendingCallback>,
+        Option<LostHook>,
+    ) {
         let mut life_tracker = self.lock_life(token);
 
         life_tracker.triage_suspected(
             global,
             &self.trackers,
             #[cfg(feature = "trace")]
-            self.trace.as_ref(),
+            &self.trace,
             token,
         );
         life_tracker.triage_mapped(global, token);
         life_tracker.triage_framebuffers(global, &mut *self.framebuffers.lock(), token);
-        let _last_done = life_tracker.triage_submissions(&self.raw, force_wait);
-        let callbacks = life_tracker.handle_mapping(global, &self.raw, &self.trackers, token);
-        life_tracker.cleanup(&self.raw, &self.mem_allocator, &self.desc_allocator);
+        // A lost device never completes its work, so there is nothing to wait for.
+        let mut lost_hook = None;
+        if force_wait
+            && !self.lost.load(Ordering::Acquire)
+            && !life_tracker.wait_idle(&self.raw, self.hang_timeout)
+        {
+            log::error!(
+                "Device is hung: the submitted work didn't complete in {:?}",
+                self.hang_timeout.unwrap()
+            );
+            self.lost.store(true, Ordering::Release);
+            lost_hook = *self.lost_hook.lock();
+        }
+        let _last_done = life_tracker.triage_submissions(&self.raw, false);
+        let mut callbacks = life_tracker.handle_mapping(global, &self.raw, &self.trackers, token);
+        let mut readbacks = life_tracker.handle_readbacks(&self.raw);
+        let mut work_done = life_tracker
+            .handle_work_done()
+            .into_iter()
+            .map(|closure| (closure, SubmittedWorkDoneStatus::Success))
+            .collect::<Vec<_>>();
+        if self.lost.load(Ordering::Acquire) {
+            work_done.extend(
+                life_tracker
+                    .drain_work_done()
+                    .into_iter()
+                    .map(|closure| (closure, SubmittedWorkDoneStatus::DeviceLost)),
+            );
+            readbacks.extend(
+                life_tracker
+                    .drain_readbacks()
+                    .into_iter()
+                    .map(|op| (op, resource::BufferMapAsyncStatus::DeviceLost, Vec::new())),
+            );
+            let pending = life_tracker.drain_mappings();
+            let (mut buffer_guard, _) = B::hub(global).buffers.write(token);
+            callbacks.extend(
+                pending
+                    .into_iter()
+                    .filter_map(|id| buffer_guard[id].map_state.take_pending())
+                    .map(|mapping| {
+                        (
+                            mapping.op,
+                            resource::BufferMapAsyncStatus::DeviceLost,
+                            ptr::null_mut(),
+                        )
+                    }),
+            );
+        }
+        life_tracker.cleanup(
+            &self.raw,
+            &self.mem_allocator,
+            &self.transient_pool,
+            &self.staging_belt,
+            &self.desc_allocator,
+            #[cfg(feature = "resource-events")]
+            &self.resource_events,
+        );
 
+        let lowest_active_index = life_tracker.lowest_active_submission();
+        let last_done_index = if lowest_active_index == std::usize::MAX {
+            self.life_guard.submission_index.load(Ordering::Acquire)
+        } else {
+            lowest_active_index - 1
+        };
         self.com_allocator
-            .maintain(&self.raw, life_tracker.lowest_active_submission());
-        callbacks
+            .maintain(&self.raw, lowest_active_index, last_done_index);
+        (callbacks, readbacks, work_done, lost_hook)
+    }
+
+    fn create_raw_render_pipeline(
+        &self,
+        state: pipeline::RenderPipelineState,
+        layout: &B::PipelineLayout,
+        vertex: (&str, &B::ShaderModule),
+        fragment: Option<(&str, &B::ShaderModule)>,
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<B::GraphicsPipeline, hal::pso::CreationError> {
+        let color_count = state.rp_key.colors.len();
+        let has_depth_stencil = state.rp_key.depth_stencil.is_some();
+        let mut render_pass_cache = self.render_passes.lock();
+        let main_pass = match render_pass_cache.entry(state.rp_key) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let color_ids = [
+                    (0, hal::image::Layout::ColorAttachmentOptimal),
+                    (1, hal::image::Layout::ColorAttachmentOptimal),
+                    (2, hal::image::Layout::ColorAttachmentOptimal),
+                    (3, hal::image::Layout::ColorAttachmentOptimal),
+                ];
+
+                let depth_id = (
+                    color_count,
+                    hal::image::Layout::DepthStencilAttachmentOptimal,
+                );
+
+                let subpass = hal::pass::SubpassDesc {
+                    colors: &color_ids[..color_count],
+                    depth_stencil: if has_depth_stencil {
+                        Some(&depth_id)
+                    } else {
+                        None
+                    },
+                    inputs: &[],
+                    resolves: &[],
+                    preserves: &[],
+                };
+
+                let pass =
+                    unsafe { self.raw.create_render_pass(e.key().all(), &[subpass], &[]) }.unwrap();
+                e.insert(pass)
+            }
+        };
+
+        let subpass = hal::pass::Subpass {
+            index: 0,
+            main_pass,
+        };
+
+        // TODO
+        let flags = hal::pso::PipelineCreationFlags::empty();
+        // TODO
+        let parent = hal::pso::BasePipeline::None;
+
+        let primitive_assembler = hal::pso::PrimitiveAssembler::Vertex {
+            buffers: state.vertex_buffers,
+            attributes: state.attributes,
+            input_assembler: state.input_assembler,
+            vertex: hal::pso::EntryPoint::<B> {
+                entry: vertex.0, // TODO
+                module: vertex.1,
+                specialization: hal::pso::Specialization::EMPTY,
+            },
+            tessellation: None,
+            geometry: None,
+        };
+
+        let pipeline_desc = hal::pso::GraphicsPipelineDesc {
+            primitive_assembler,
+            rasterizer: state.rasterizer,
+            fragment: fragment.map(|(entry, module)| hal::pso::EntryPoint::<B> {
+                entry, // TODO
+                module,
+                specialization: hal::pso::Specialization::EMPTY,
+            }),
+            blender: state.blender,
+            depth_stencil: state.depth_stencil,
+            multisampling: state.multisampling,
+            baked_states: state.baked_states,
+            layout,
+            subpass,
+            flags,
+            parent,
+        };
+
+        unsafe { self.raw.create_graphics_pipeline(&pipeline_desc, cache) }
+    }
+
+    fn create_raw_compute_pipeline(
+        &self,
+        layout: &B::PipelineLayout,
+        stage: (&str, &B::ShaderModule),
+        cache: Option<&B::PipelineCache>,
+    ) -> Result<B::ComputePipeline, hal::pso::CreationError> {
+        let shader = hal::pso::EntryPoint::<B> {
+            entry: stage.0, // TODO
+            module: stage.1,
+            specialization: hal::pso::Specialization::EMPTY,
+        };
+
+        // TODO
+        let flags = hal::pso::PipelineCreationFlags::empty();
+        // TODO
+        let parent = hal::pso::BasePipeline::None;
+
+        let pipeline_desc = hal::pso::ComputePipelineDesc {
+            shader,
+            layout,
+            flags,
+            parent,
+        };
+
+        unsafe { self.raw.create_compute_pipeline(&pipeline_desc, cache) }
     }
 
     fn create_buffer(
         &self,
         self_id: id::DeviceId,
         desc: &wgt::BufferDescriptor<Label>,
-    ) -> resource::Buffer<B> {
-        use gfx_memory::{Kind, MemoryUsage};
+    ) -> Result<resource::Buffer<B>, AllocationError> {
+        use gfx_memory::Kind;
 
         debug_assert_eq!(self_id.backend(), B::VARIANT);
+        assert!(
+            desc.size <= self.limits.max_buffer_size,
+            "Buffer size {} is higher than the max_buffer_size limit {}",
+            desc.size,
+            self.limits.max_buffer_size
+        );
+        assert!(
+            !desc.transient
+                || !desc
+                    .usage
+                    .intersects(wgt::BufferUsage::MAP_READ | wgt::BufferUsage::MAP_WRITE),
+            "Transient buffers can't be mapped, but the usage is {:?}",
+            desc.usage
+        );
         let (usage, _memory_properties) = conv::map_buffer_usage(desc.usage);
-        let (kind, mem_usage) = {
-            use wgt::BufferUsage as Bu;
-
-            //TODO: use linear allocation when we can ensure the freeing is linear
-            if !desc.usage.intersects(Bu::MAP_READ | Bu::MAP_WRITE) {
-                (Kind::General, MemoryUsage::Private)
-            } else if (Bu::MAP_WRITE | Bu::COPY_SRC).contains(desc.usage) {
-                (Kind::General, MemoryUsage::Staging { read_back: false })
-            } else if (Bu::MAP_READ | Bu::COPY_DST).contains(desc.usage) {
-                (Kind::General, MemoryUsage::Staging { read_back: true })
-            } else {
-                (
-                    Kind::General,
-                    MemoryUsage::Dynamic {
-                        sparse_updates: false,
-                    },
-                )
-            }
-        };
+        //TODO: use linear allocation when we can ensure the freeing is linear
+        let kind = Kind::General;
+        let mem_usage = conv::map_buffer_memory_usage(desc.usage, desc.memory_hint);
 
         let mut buffer = unsafe { self.raw.create_buffer(desc.size, usage).unwrap() };
         if !desc.label.is_null() {
@@ -359,46 +1217,86 @@ impl<B: GfxBackend> Device<B> {
             };
         }
         let requirements = unsafe { self.raw.get_buffer_requirements(&buffer) };
-        let memory = self
-            .mem_allocator
-            .lock()
-            .allocate(
-                &self.raw,
-                &requirements,
-                mem_usage,
-                kind,
-            )
-            .unwrap();
-
-        unsafe {
-            self.raw
-                .bind_buffer_memory(memory.memory(), memory.segment().offset, &mut buffer)
-                .unwrap()
+        let memory = if desc.transient {
+            self.bind_transient(requirements.size, "transient buffer", |pool| {
+                pool.bind_buffer(&self.raw, &requirements, &mut buffer)
+            })
+            .map(resource::ResourceMemory::Transient)
+        } else {
+            self.allocate_memory(&requirements, mem_usage, kind, "buffer")
+                .map(|memory| {
+                    unsafe {
+                        self.raw
+                            .bind_buffer_memory(
+                                memory.memory(),
+                                memory.segment().offset,
+                                &mut buffer,
+                            )
+                            .unwrap()
+                    };
+                    resource::ResourceMemory::Block(memory)
+                })
+        };
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.raw.destroy_buffer(buffer) };
+                return Err(e);
+            }
         };
 
-        resource::Buffer {
+        Ok(resource::Buffer {
             raw: buffer,
             device_id: Stored {
                 value: self_id,
                 ref_count: self.life_guard.add_ref(),
             },
             usage: desc.usage,
+            memory_hint: desc.memory_hint,
             memory,
             size: desc.size,
             full_range: (),
             sync_mapped_writes: None,
             map_state: resource::BufferMapState::Idle,
             life_guard: LifeGuard::new(),
-        }
+            #[cfg(feature = "trace")]
+            label: own_label(&desc.label),
+        })
     }
 
     fn create_texture(
         &self,
         self_id: id::DeviceId,
         desc: &wgt::TextureDescriptor<Label>,
-    ) -> resource::Texture<B> {
+        format_features: Option<wgt::TextureFormatFeatures>,
+    ) -> Result<resource::Texture<B>, AllocationError> {
         debug_assert_eq!(self_id.backend(), B::VARIANT);
 
+        if let Err(e) = resource::check_texture_size(desc.dimension, desc.size, &self.limits) {
+            panic!(
+                "Texture size {:?} exceeds the device limits: {}",
+                desc.size, e
+            );
+        }
+
+        if let Some(features) = format_features {
+            assert!(
+                features.allowed_usages.contains(desc.usage),
+                "Texture usage {:?} is not supported by the adapter for format {:?}, allowed usage is {:?}",
+                desc.usage,
+                desc.format,
+                features.allowed_usages
+            );
+            assert!(
+                desc.sample_count.is_power_of_two()
+                    && features.sample_counts & desc.sample_count != 0,
+                "Sample count {} is not supported by the adapter for format {:?}, supported mask is {:#b}",
+                desc.sample_count,
+                desc.format,
+                features.sample_counts
+            );
+        }
+
         // Ensure `D24Plus` textures cannot be copied
         match desc.format {
             TextureFormat::Depth24Plus | TextureFormat::Depth24PlusStencil8 => {
@@ -412,6 +1310,27 @@ impl<B: GfxBackend> Device<B> {
             _ => {}
         }
 
+        if self.strict_webgpu {
+            assert!(!desc.usage.is_empty(), "Texture usage can't be empty");
+            assert!(
+                !desc.usage.contains(wgt::TextureUsage::TRANSIENT_ATTACHMENT),
+                "Transient attachments are native-only, and can't be used in strict WebGPU mode"
+            );
+        }
+        if desc.usage.contains(wgt::TextureUsage::TRANSIENT_ATTACHMENT) {
+            assert!(
+                desc.usage.contains(wgt::TextureUsage::OUTPUT_ATTACHMENT)
+                    && !desc.usage.intersects(
+                        wgt::TextureUsage::COPY_SRC
+                            | wgt::TextureUsage::COPY_DST
+                            | wgt::TextureUsage::SAMPLED
+                            | wgt::TextureUsage::STORAGE
+                    ),
+                "Transient attachment usage {:?} must contain OUTPUT_ATTACHMENT, and can't be combined with copies, sampling, or storage",
+                desc.usage
+            );
+        }
+
         let kind = conv::map_texture_dimension_size(desc.dimension, desc.size, desc.sample_count);
         let format = conv::map_texture_format(desc.format, self.private_features);
         let aspects = format.surface_desc().aspects;
@@ -452,25 +1371,55 @@ impl<B: GfxBackend> Device<B> {
             image
         };
         let requirements = unsafe { self.raw.get_image_requirements(&image) };
-
-        let memory = self
-            .mem_allocator
-            .lock()
-            .allocate(
-                &self.raw,
+        let lazy_memory_type = if desc.usage.contains(wgt::TextureUsage::TRANSIENT_ATTACHMENT) {
+            self.transient_pool
+                .lock()
+                .lazy_memory_type(requirements.type_mask)
+        } else {
+            None
+        };
+        let memory = if let Some(memory_type) = lazy_memory_type {
+            self.transient_pool
+                .lock()
+                .bind_lazy_image(&self.raw, memory_type, &requirements, &mut image)
+                .map(resource::ResourceMemory::Lazy)
+                .map_err(|e| {
+                    log::error!("Out of memory for a transient attachment: {:?}", e);
+                    AllocationError::OutOfMemory {
+                        resource: "transient attachment",
+                        size: requirements.size,
+                    }
+                })
+        } else if desc.transient {
+            self.bind_transient(requirements.size, "transient texture", |pool| {
+                pool.bind_image(&self.raw, &requirements, &mut image)
+            })
+            .map(resource::ResourceMemory::Transient)
+        } else {
+            self.allocate_memory(
                 &requirements,
                 gfx_memory::MemoryUsage::Private,
                 gfx_memory::Kind::General,
+                "texture",
             )
-            .unwrap();
-
-        unsafe {
-            self.raw
-                .bind_image_memory(memory.memory(), memory.segment().offset, &mut image)
-                .unwrap()
+            .map(|memory| {
+                unsafe {
+                    self.raw
+                        .bind_image_memory(memory.memory(), memory.segment().offset, &mut image)
+                        .unwrap()
+                };
+                resource::ResourceMemory::Block(memory)
+            })
+        };
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.raw.destroy_image(image) };
+                return Err(e);
+            }
         };
 
-        resource::Texture {
+        Ok(resource::Texture {
             raw: image,
             device_id: Stored {
                 value: self_id,
@@ -486,41 +1435,126 @@ impl<B: GfxBackend> Device<B> {
             },
             memory,
             life_guard: LifeGuard::new(),
-        }
+            view_cache: Mutex::new(FastHashMap::default()),
+            #[cfg(feature = "trace")]
+            label: own_label(&desc.label),
+        })
     }
 }
 
 impl<B: hal::Backend> Device<B> {
     pub(crate) fn destroy_bind_group(&self, bind_group: binding_model::BindGroup<B>) {
         unsafe {
-            self.desc_allocator.lock().free(iter::once(bind_group.raw));
+            self.desc_allocator.lock().free(
+                iter::once(bind_group.raw)
+                    .chain(bind_group.derived_sets.into_iter().map(|(_, set)| set)),
+            );
+        }
+    }
+
+    /// Free an inline uniform ring that the GPU doesn't use.
+    pub(crate) fn destroy_inline_uniform_ring(&self, ring: command::InlineUniformRing<B>) {
+        unsafe {
+            self.desc_allocator
+                .lock()
+                .free(ring.sets.into_iter().map(|(_, set)| set));
+            self.mem_allocator.lock().free(&self.raw, ring.memory);
+            self.raw.destroy_buffer(ring.raw);
         }
     }
 
     pub(crate) fn destroy_buffer(&self, buffer: resource::Buffer<B>) {
         unsafe {
-            self.mem_allocator.lock().free(&self.raw, buffer.memory);
             self.raw.destroy_buffer(buffer.raw);
         }
+        self.free_memory(buffer.memory);
+    }
+
+    fn free_memory(&self, memory: resource::ResourceMemory<B>) {
+        match memory {
+            resource::ResourceMemory::Block(memory) => {
+                self.mem_allocator.lock().free(&self.raw, memory)
+            }
+            resource::ResourceMemory::Transient(allocation) => {
+                self.transient_pool.lock().release(allocation)
+            }
+            resource::ResourceMemory::Lazy(allocation) => {
+                self.transient_pool.lock().free_lazy(&self.raw, allocation)
+            }
+        }
+    }
+
+    pub(crate) fn destroy_staging_buffer(&self, staging: resource::StagingBuffer<B>) {
+        let mut heaps = self.mem_allocator.lock();
+        self.staging_belt
+            .lock()
+            .recycle(&self.raw, &mut heaps, staging.chunk);
     }
 
     pub(crate) fn destroy_texture(&self, texture: resource::Texture<B>) {
         unsafe {
-            self.mem_allocator.lock().free(&self.raw, texture.memory);
             self.raw.destroy_image(texture.raw);
         }
+        self.free_memory(texture.memory);
     }
 
     /// Wait for idle and remove resources that we can, before we die.
-    pub(crate) fn prepare_to_die(&mut self) {
+    /// Wait for the submitted work and free whatever it used, returning
+    /// the readbacks and the work done callbacks it completed.
+    ///
+    /// The readbacks and work done callbacks waiting on pending writes
+    /// get `DeviceLost`, since the writes are never submitted.
+    pub(crate) fn prepare_to_die(
+        &mut self,
+    ) -> (
+        Vec<BufferReadPendingCallback>,
+        Vec<SubmittedWorkDonePendingCallback>,
+    ) {
         let mut life_tracker = self.life_tracker.lock();
-        life_tracker.triage_submissions(&self.raw, true);
-        life_tracker.cleanup(&self.raw, &self.mem_allocator, &self.desc_allocator);
+        // Resources still used by the GPU can't be destroyed, so if the fences
+        // don't signal in time, fall back to waiting on the whole device.
+        if !life_tracker.wait_for_submissions(&self.raw, life::CLEANUP_WAIT_MS * 1_000_000) {
+            log::warn!("Submissions are still in flight, waiting for the device to idle");
+            self.raw.wait_idle().unwrap();
+        }
+        life_tracker.triage_submissions(&self.raw, false);
+        let mut readbacks = life_tracker.handle_readbacks(&self.raw);
+        readbacks.extend(
+            self.pending_writes
+                .abort_readbacks()
+                .into_iter()
+                .map(|op| (op, resource::BufferMapAsyncStatus::DeviceLost, Vec::new())),
+        );
+        let work_done = life_tracker
+            .handle_work_done()
+            .into_iter()
+            .map(|closure| (closure, SubmittedWorkDoneStatus::Success))
+            .chain(
+                self.pending_writes
+                    .work_done
+                    .drain(..)
+                    .map(|closure| (closure, SubmittedWorkDoneStatus::DeviceLost)),
+            )
+            .collect();
+        life_tracker.cleanup(
+            &self.raw,
+            &self.mem_allocator,
+            &self.transient_pool,
+            &self.staging_belt,
+            &self.desc_allocator,
+            #[cfg(feature = "resource-events")]
+            &self.resource_events,
+        );
+        (readbacks, work_done)
     }
 
     pub(crate) fn dispose(self) {
         let mut desc_alloc = self.desc_allocator.into_inner();
         let mut mem_alloc = self.mem_allocator.into_inner();
+        self.transient_pool.into_inner().dispose(&self.raw);
+        self.staging_belt
+            .into_inner()
+            .dispose(&self.raw, &mut mem_alloc);
         self.pending_writes
             .dispose(&self.raw, &self.com_allocator, &mut mem_alloc);
         self.com_allocator.destroy(&self.raw);
@@ -534,6 +1568,47 @@ impl<B: hal::Backend> Device<B> {
                 self.raw.destroy_framebuffer(fbo);
             }
         }
+        // Everything left is destroyed along with the device.
+        #[cfg(feature = "resource-events")]
+        {
+            let mut events = self.resource_events.into_inner();
+            events.destroyed_all();
+            if let Some(batch) = events.take() {
+                batch.fire();
+            }
+        }
+    }
+}
+
+/// Usages that a buffer can't combine with its mappable usages,
+/// unless the `mappable_primary_buffers` extension is enabled.
+fn conflicting_map_usage(usage: wgt::BufferUsage) -> wgt::BufferUsage {
+    use wgt::BufferUsage as Bu;
+    let mut conflicts = Bu::empty();
+    if usage.contains(Bu::MAP_READ) {
+        conflicts |= usage - (Bu::MAP_READ | Bu::COPY_DST);
+    }
+    if usage.contains(Bu::MAP_WRITE) {
+        conflicts |= usage - (Bu::MAP_WRITE | Bu::COPY_SRC);
+    }
+    conflicts
+}
+
+impl<B: GfxBackend> Device<B> {
+    fn validate_buffer_usage(&self, usage: wgt::BufferUsage) {
+        if self.strict_webgpu {
+            assert!(!usage.is_empty(), "Buffer usage can't be empty");
+        }
+        if self.extensions.mappable_primary_buffers {
+            return;
+        }
+        let conflicts = conflicting_map_usage(usage);
+        assert!(
+            conflicts.is_empty(),
+            "Buffer usage {:?} combines a mappable usage with {:?}, which requires the mappable_primary_buffers extension",
+            usage,
+            conflicts
+        );
     }
 }
 
@@ -543,22 +1618,32 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &wgt::BufferDescriptor<Label>,
         id_in: Input<G, id::BufferId>,
-    ) -> id::BufferId {
+    ) -> Result<id::BufferId, AllocationError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         log::info!("Create buffer {:?} with ID {:?}", desc, id_in);
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
-        let buffer = device.create_buffer(device_id, desc);
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        device.validate_buffer_usage(desc.usage);
+        let buffer = device.create_buffer(device_id, desc)?;
         let ref_count = buffer.life_guard.add_ref();
 
         let id = hub.buffers.register_identity(id_in, buffer, &mut token);
         log::info!("Created buffer {:?} with {:?}", id, desc);
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::Buffer,
+            id,
+            desc.label,
+            desc.size,
+        );
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateBuffer {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateBuffer {
                 id,
                 desc: desc.map_label(own_label),
             }),
@@ -575,7 +1660,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 BufferState::with_usage(resource::BufferUse::EMPTY),
             )
             .unwrap();
-        id
+        Ok(id)
     }
 
     pub fn device_create_buffer_mapped<B: GfxBackend>(
@@ -583,15 +1668,19 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &wgt::BufferDescriptor<Label>,
         id_in: Input<G, id::BufferId>,
-    ) -> (id::BufferId, *mut u8) {
+    ) -> Result<(id::BufferId, *mut u8), AllocationError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let mut desc = desc.clone();
-        desc.usage |= wgt::BufferUsage::MAP_WRITE;
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
-        let mut buffer = device.create_buffer(device_id, &desc);
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        // Being mapped at creation doesn't restrict the other usages.
+        device.validate_buffer_usage(desc.usage);
+        desc.usage |= wgt::BufferUsage::MAP_WRITE;
+        let mut buffer = device.create_buffer(device_id, &desc)?;
         let ref_count = buffer.life_guard.add_ref();
 
         let pointer = match map_buffer(
@@ -616,9 +1705,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let id = hub.buffers.register_identity(id_in, buffer, &mut token);
         log::info!("Created mapped buffer {:?} with {:?}", id, desc);
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::Buffer,
+            id,
+            desc.label,
+            desc.size,
+        );
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateBuffer {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateBuffer {
                 id,
                 desc: desc.map_label(own_label),
             }),
@@ -636,7 +1732,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             )
             .unwrap();
 
-        (id, pointer)
+        Ok((id, pointer))
     }
 
     #[cfg(feature = "replay")]
@@ -650,13 +1746,22 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let last_submission = {
             let (buffer_guard, _) = hub.buffers.write(&mut token);
-            buffer_guard[buffer_id]
-                .life_guard
-                .submission_index
-                .load(Ordering::Acquire)
+            match buffer_guard.get(buffer_id) {
+                Some(buffer) => buffer.life_guard.submission_index.load(Ordering::Acquire),
+                None => {
+                    log::error!("Waiting for an invalid buffer {:?}", buffer_id);
+                    return;
+                }
+            }
         };
 
-        let device = &device_guard[device_id];
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!("Waiting for a buffer on an invalid device {:?}", device_id);
+                return;
+            }
+        };
         let mut life_lock = device.lock_life(&mut token);
         if life_lock.lowest_active_submission() <= last_submission {
             log::info!(
@@ -680,8 +1785,18 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (mut buffer_guard, _) = hub.buffers.write(&mut token);
-        let device = &device_guard[device_id];
-        let mut buffer = &mut buffer_guard[buffer_id];
+        let (device, mut buffer) =
+            match (device_guard.get(device_id), buffer_guard.get_mut(buffer_id)) {
+                (Some(device), Some(buffer)) => (device, buffer),
+                _ => {
+                    log::error!(
+                        "Writing to an invalid buffer {:?} of device {:?}",
+                        buffer_id,
+                        device_id
+                    );
+                    return;
+                }
+            };
         assert!(
             buffer.usage.contains(wgt::BufferUsage::MAP_WRITE),
             "Buffer usage {:?} must contain usage flag MAP_WRITE",
@@ -690,9 +1805,8 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         //assert!(buffer isn't used by the GPU);
 
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => {
-                let mut trace = trace.lock();
+        match *device.trace.lock() {
+            Some(ref mut trace) => {
                 let data_path = trace.make_binary("bin", data);
                 trace.add(trace::Action::WriteBuffer {
                     id: buffer_id,
@@ -737,8 +1851,18 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (mut buffer_guard, _) = hub.buffers.write(&mut token);
-        let device = &device_guard[device_id];
-        let mut buffer = &mut buffer_guard[buffer_id];
+        let (device, mut buffer) =
+            match (device_guard.get(device_id), buffer_guard.get_mut(buffer_id)) {
+                (Some(device), Some(buffer)) => (device, buffer),
+                _ => {
+                    log::error!(
+                        "Reading from an invalid buffer {:?} of device {:?}",
+                        buffer_id,
+                        device_id
+                    );
+                    return;
+                }
+            };
         assert!(
             buffer.usage.contains(wgt::BufferUsage::MAP_READ),
             "Buffer usage {:?} must contain usage flag MAP_READ",
@@ -767,24 +1891,74 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         unmap_buffer(&device.raw, buffer);
     }
 
+    pub fn buffer_get_size<B: GfxBackend>(
+        &self,
+        buffer_id: id::BufferId,
+    ) -> Result<BufferAddress, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+        match buffer_guard.get(buffer_id) {
+            Some(buffer) => Ok(buffer.size),
+            None => Err(InvalidIdError::Buffer(buffer_id)),
+        }
+    }
+
+    pub fn buffer_get_usage<B: GfxBackend>(
+        &self,
+        buffer_id: id::BufferId,
+    ) -> Result<wgt::BufferUsage, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+        match buffer_guard.get(buffer_id) {
+            Some(buffer) => Ok(buffer.usage),
+            None => Err(InvalidIdError::Buffer(buffer_id)),
+        }
+    }
+
+    /// Properties of the memory the buffer ended up in, which depends
+    /// on its usage, its memory hint, and the memory types of the adapter.
+    pub fn buffer_get_memory_properties<B: GfxBackend>(
+        &self,
+        buffer_id: id::BufferId,
+    ) -> Result<wgt::MemoryProperties, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+        match buffer_guard.get(buffer_id) {
+            Some(buffer) => Ok(conv::map_memory_properties(buffer.memory.properties())),
+            None => Err(InvalidIdError::Buffer(buffer_id)),
+        }
+    }
+
     pub fn buffer_destroy<B: GfxBackend>(&self, buffer_id: id::BufferId) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         log::info!("Buffer {:?} is dropped", buffer_id);
+        let (device_guard, mut token) = hub.devices.read(&mut token);
         let device_id = {
+            let (mut bind_group_guard, mut token) = hub.bind_groups.write(&mut token);
             let (mut buffer_guard, _) = hub.buffers.write(&mut token);
-            let buffer = &mut buffer_guard[buffer_id];
+            let buffer = match buffer_guard.get_mut(buffer_id) {
+                Some(buffer) => buffer,
+                None => {
+                    log::error!("Destroying an invalid buffer {:?}", buffer_id);
+                    return;
+                }
+            };
             buffer.life_guard.ref_count.take();
+            device_guard[buffer.device_id.value].invalidate_bind_groups(
+                &mut *bind_group_guard,
+                binding_model::BindGroupMember::Buffer(buffer_id),
+            );
             buffer.device_id.value
         };
 
-        let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .buffers
-            .push(buffer_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.buffers.push(buffer_id)
+        });
     }
 
     pub fn device_create_texture<B: GfxBackend>(
@@ -792,20 +1966,42 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &wgt::TextureDescriptor<Label>,
         id_in: Input<G, id::TextureId>,
-    ) -> id::TextureId {
+    ) -> Result<id::TextureId, AllocationError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
+        let (adapter_guard, mut token) = hub.adapters.read(&mut token);
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
-        let texture = device.create_texture(device_id, desc);
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        let format_features = if device.extensions.adapter_specific_format_features {
+            let adapter = &adapter_guard[device.adapter_id.value];
+            Some(Adapter::texture_format_features(&adapter.raw, desc.format))
+        } else {
+            None
+        };
+        let texture = device.create_texture(device_id, desc, format_features)?;
         let range = texture.full_range.clone();
         let ref_count = texture.life_guard.add_ref();
+        #[cfg(feature = "resource-events")]
+        let memory_size = if device.resource_events.lock().is_enabled() {
+            unsafe { device.raw.get_image_requirements(&texture.raw) }.size
+        } else {
+            0
+        };
 
         let id = hub.textures.register_identity(id_in, texture, &mut token);
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::Texture,
+            id,
+            desc.label,
+            memory_size,
+        );
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateTexture {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateTexture {
                 id,
                 desc: desc.map_label(own_label),
             }),
@@ -818,7 +2014,57 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .textures
             .init(id, ref_count, TextureState::with_range(&range))
             .unwrap();
-        id
+        Ok(id)
+    }
+
+    /// Describe an existing texture. The label isn't kept, so it's not returned.
+    pub fn texture_get_descriptor<B: GfxBackend>(
+        &self,
+        texture_id: id::TextureId,
+    ) -> Result<wgt::TextureDescriptor<()>, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (texture_guard, _) = hub.textures.read(&mut token);
+        let texture = texture_guard
+            .get(texture_id)
+            .ok_or(InvalidIdError::Texture(texture_id))?;
+
+        let (dimension, size) = match texture.kind {
+            hal::image::Kind::D1(width, layers) => (
+                TextureDimension::D1,
+                wgt::Extent3d {
+                    width,
+                    height: 1,
+                    depth: layers as u32,
+                },
+            ),
+            hal::image::Kind::D2(width, height, layers, _) => (
+                TextureDimension::D2,
+                wgt::Extent3d {
+                    width,
+                    height,
+                    depth: layers as u32,
+                },
+            ),
+            hal::image::Kind::D3(width, height, depth) => (
+                TextureDimension::D3,
+                wgt::Extent3d {
+                    width,
+                    height,
+                    depth,
+                },
+            ),
+        };
+        Ok(wgt::TextureDescriptor {
+            label: (),
+            size,
+            mip_level_count: texture.full_range.levels.end as u32,
+            sample_count: texture.kind.num_samples() as u32,
+            dimension,
+            format: texture.format,
+            usage: texture.usage,
+            transient: texture.memory.is_transient(),
+        })
     }
 
     pub fn texture_destroy<B: GfxBackend>(&self, texture_id: id::TextureId) {
@@ -827,17 +2073,21 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let device_id = {
             let (mut texture_guard, _) = hub.textures.write(&mut token);
-            let texture = &mut texture_guard[texture_id];
+            let texture = match texture_guard.get_mut(texture_id) {
+                Some(texture) => texture,
+                None => {
+                    log::error!("Destroying an invalid texture {:?}", texture_id);
+                    return;
+                }
+            };
             texture.life_guard.ref_count.take();
             texture.device_id.value
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .textures
-            .push(texture_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.textures.push(texture_id)
+        });
     }
 
     pub fn texture_create_view<B: GfxBackend>(
@@ -845,45 +2095,132 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         texture_id: id::TextureId,
         desc: Option<&wgt::TextureViewDescriptor<Label>>,
         id_in: Input<G, id::TextureViewId>,
-    ) -> id::TextureViewId {
+    ) -> Result<id::TextureViewId, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (texture_guard, mut token) = hub.textures.read(&mut token);
-        let texture = &texture_guard[texture_id];
+        let texture = texture_guard
+            .get(texture_id)
+            .ok_or(InvalidIdError::Texture(texture_id))?;
         let device = &device_guard[texture.device_id.value];
 
-        let (format, view_kind, range) = match desc {
+        let usage = match desc.and_then(|desc| desc.usage) {
+            Some(usage) => {
+                assert!(
+                    texture.usage.contains(usage),
+                    "Texture view usage {:?} must be a subset of the texture usage {:?}",
+                    usage,
+                    texture.usage
+                );
+                usage
+            }
+            None => texture.usage,
+        };
+
+        let (format, dimension, view_kind, range) = match desc {
             Some(desc) => {
+                if desc.dimension == wgt::TextureViewDimension::CubeArray {
+                    assert!(
+                        device.downlevel.cube_array_textures,
+                        "This adapter does not support cube array textures"
+                    );
+                }
                 let kind = conv::map_texture_view_dimension(desc.dimension);
+                let num_levels = texture.full_range.levels.end as u32;
+                let num_layers = texture.full_range.layers.end as u32;
+                assert!(
+                    desc.base_mip_level < num_levels,
+                    "Base mip level {} is out of range of the texture with {} levels",
+                    desc.base_mip_level,
+                    num_levels
+                );
+                assert!(
+                    desc.base_array_layer < num_layers,
+                    "Base array layer {} is out of range of the texture with {} layers",
+                    desc.base_array_layer,
+                    num_layers
+                );
                 let end_level = if desc.level_count == 0 {
                     texture.full_range.levels.end
                 } else {
-                    (desc.base_mip_level + desc.level_count) as u8
+                    let end = desc.base_mip_level as u64 + desc.level_count as u64;
+                    assert!(
+                        end <= num_levels as u64,
+                        "Mip levels {}..{} are out of range of the texture with {} levels",
+                        desc.base_mip_level,
+                        end,
+                        num_levels
+                    );
+                    end as u8
                 };
                 let end_layer = if desc.array_layer_count == 0 {
                     texture.full_range.layers.end
                 } else {
-                    (desc.base_array_layer + desc.array_layer_count) as u16
+                    let end = desc.base_array_layer as u64 + desc.array_layer_count as u64;
+                    assert!(
+                        end <= num_layers as u64,
+                        "Array layers {}..{} are out of range of the texture with {} layers",
+                        desc.base_array_layer,
+                        end,
+                        num_layers
+                    );
+                    end as u16
                 };
+                let aspects = match desc.aspect {
+                    wgt::TextureAspect::All => texture.full_range.aspects,
+                    wgt::TextureAspect::DepthOnly => hal::format::Aspects::DEPTH,
+                    wgt::TextureAspect::StencilOnly => hal::format::Aspects::STENCIL,
+                };
+                assert!(
+                    texture.full_range.aspects.contains(aspects),
+                    "Texture {:?} of format {:?} has no {:?} aspect",
+                    texture_id,
+                    texture.format,
+                    desc.aspect
+                );
                 let range = hal::image::SubresourceRange {
-                    aspects: texture.full_range.aspects,
+                    aspects,
                     levels: desc.base_mip_level as u8..end_level,
                     layers: desc.base_array_layer as u16..end_layer,
                 };
-                (desc.format, kind, range)
+                (desc.format, desc.dimension, kind, range)
             }
             None => {
-                let kind = match texture.kind {
-                    hal::image::Kind::D1(_, 1) => hal::image::ViewKind::D1,
-                    hal::image::Kind::D1(..) => hal::image::ViewKind::D1Array,
-                    hal::image::Kind::D2(_, _, 1, _) => hal::image::ViewKind::D2,
-                    hal::image::Kind::D2(..) => hal::image::ViewKind::D2Array,
-                    hal::image::Kind::D3(..) => hal::image::ViewKind::D3,
+                use wgt::TextureViewDimension as Vd;
+                let (dimension, kind) = match texture.kind {
+                    hal::image::Kind::D1(_, 1) => (Vd::D1, hal::image::ViewKind::D1),
+                    hal::image::Kind::D1(..) => (Vd::D1, hal::image::ViewKind::D1Array),
+                    hal::image::Kind::D2(_, _, 1, _) => (Vd::D2, hal::image::ViewKind::D2),
+                    hal::image::Kind::D2(..) => (Vd::D2Array, hal::image::ViewKind::D2Array),
+                    hal::image::Kind::D3(..) => (Vd::D3, hal::image::ViewKind::D3),
                 };
-                (texture.format, kind, texture.full_range.clone())
+                (texture.format, dimension, kind, texture.full_range.clone())
+            }
+        };
+
+        let key = if device.deduplicate_objects {
+            let key = TextureViewKey {
+                format,
+                dimension,
+                aspects: range.aspects,
+                levels: range.levels.clone(),
+                layers: range.layers.clone(),
+                usage,
+            };
+            let (mut texture_view_guard, _) = hub.texture_views.write(&mut token);
+            if let Some(&id) = texture.view_cache.lock().get(&key) {
+                let view = &mut texture_view_guard[id];
+                // The last handle may be getting destroyed right now.
+                if view.life_guard.ref_count.is_some() {
+                    view.extra_handles += 1;
+                    return Ok(id);
+                }
             }
+            Some(key)
+        } else {
+            None
         };
 
         let raw = unsafe {
@@ -907,21 +2244,39 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     ref_count: texture.life_guard.add_ref(),
                 },
             },
+            device_id: Stored {
+                value: texture.device_id.value,
+                ref_count: device.life_guard.add_ref(),
+            },
             format: texture.format,
-            extent: texture.kind.extent().at_level(range.levels.start),
+            dimension,
+            extent: resource::mip_level_extent(texture.kind.extent(), range.levels.start),
             samples: texture.kind.num_samples(),
             range,
+            usage,
             life_guard: LifeGuard::new(),
+            extra_handles: 0,
+            #[cfg(feature = "trace")]
+            trace_desc: desc.map(|d| d.map_label(own_label)),
         };
         let ref_count = view.life_guard.add_ref();
+        #[cfg(feature = "trace")]
+        let trace_desc = view.trace_desc.clone();
 
         let id = hub.texture_views.register_identity(id_in, view, &mut token);
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::TextureView,
+            id,
+            desc.map_or(ptr::null(), |desc| desc.label),
+            0,
+        );
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateTextureView {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateTextureView {
                 id,
                 parent_id: texture_id,
-                desc: desc.map(|d| d.map_label(own_label)),
+                desc: trace_desc,
             }),
             None => (),
         };
@@ -932,22 +2287,82 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .views
             .init(id, ref_count, PhantomData)
             .unwrap();
-        id
+        if let Some(key) = key {
+            texture.view_cache.lock().insert(key, id);
+        }
+        Ok(id)
+    }
+
+    /// Describe an existing texture view, with the level and layer counts resolved.
+    /// The label isn't kept, so it's not returned.
+    pub fn texture_view_get_descriptor<B: GfxBackend>(
+        &self,
+        texture_view_id: id::TextureViewId,
+    ) -> Result<wgt::TextureViewDescriptor<()>, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (_, mut token) = hub.textures.read(&mut token);
+        let (texture_view_guard, _) = hub.texture_views.read(&mut token);
+        let view = texture_view_guard
+            .get(texture_view_id)
+            .ok_or(InvalidIdError::TextureView(texture_view_id))?;
+
+        let aspects = view.range.aspects;
+        Ok(wgt::TextureViewDescriptor {
+            label: (),
+            format: view.format,
+            dimension: view.dimension,
+            aspect: if aspects == hal::format::Aspects::DEPTH {
+                wgt::TextureAspect::DepthOnly
+            } else if aspects == hal::format::Aspects::STENCIL {
+                wgt::TextureAspect::StencilOnly
+            } else {
+                wgt::TextureAspect::All
+            },
+            base_mip_level: view.range.levels.start as u32,
+            level_count: (view.range.levels.end - view.range.levels.start) as u32,
+            base_array_layer: view.range.layers.start as u32,
+            array_layer_count: (view.range.layers.end - view.range.layers.start) as u32,
+            usage: Some(view.usage),
+        })
     }
 
     pub fn texture_view_destroy<B: GfxBackend>(&self, texture_view_id: id::TextureViewId) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
+        let (device_guard, mut token) = hub.devices.read(&mut token);
         let device_id = {
+            let (mut bind_group_guard, mut token) = hub.bind_groups.write(&mut token);
+            let (_, mut token) = hub.buffers.read(&mut token); //skip token
             let (texture_guard, mut token) = hub.textures.read(&mut token);
             let (mut texture_view_guard, _) = hub.texture_views.write(&mut token);
 
-            let view = &mut texture_view_guard[texture_view_id];
+            let view = match texture_view_guard.get_mut(texture_view_id) {
+                Some(view) => view,
+                None => {
+                    log::error!("Destroying an invalid texture view {:?}", texture_view_id);
+                    return;
+                }
+            };
+            if view.extra_handles != 0 {
+                view.extra_handles -= 1;
+                return;
+            }
             view.life_guard.ref_count.take();
             match view.inner {
                 resource::TextureViewInner::Native { ref source_id, .. } => {
-                    texture_guard[source_id.value].device_id.value
+                    if device_guard[view.device_id.value].deduplicate_objects {
+                        texture_guard[source_id.value]
+                            .view_cache
+                            .lock()
+                            .retain(|_, &mut id| id != texture_view_id);
+                    }
+                    device_guard[view.device_id.value].invalidate_bind_groups(
+                        &mut *bind_group_guard,
+                        binding_model::BindGroupMember::TextureView(texture_view_id),
+                    );
+                    view.device_id.value
                 }
                 resource::TextureViewInner::SwapChain { .. } => {
                     panic!("Can't destroy a swap chain image")
@@ -955,12 +2370,9 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
         };
 
-        let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .texture_views
-            .push(texture_view_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.texture_views.push(texture_view_id)
+        });
     }
 
     pub fn device_create_sampler<B: GfxBackend>(
@@ -968,11 +2380,34 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &wgt::SamplerDescriptor<Label>,
         id_in: Input<G, id::SamplerId>,
-    ) -> id::SamplerId {
+    ) -> Result<id::SamplerId, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+
+        let key = if device.deduplicate_objects {
+            let key = SamplerKey::new(desc);
+            let (mut sampler_guard, _) = hub.samplers.write(&mut token);
+            if let Some(id) = device.sampler_cache.lock().find(&key) {
+                let sampler = &mut sampler_guard[id];
+                // The last handle may be getting destroyed right now.
+                if sampler.life_guard.ref_count.is_some() {
+                    sampler.extra_handles += 1;
+                    #[cfg(feature = "trace")]
+                    match *device.trace.lock() {
+                        Some(ref mut trace) => trace.add(trace::Action::ReuseSampler(id)),
+                        None => (),
+                    };
+                    return Ok(id);
+                }
+            }
+            Some(key)
+        } else {
+            None
+        };
 
         let info = hal::image::SamplerDesc {
             min_filter: conv::map_filter(desc.min_filter),
@@ -997,16 +2432,29 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 value: device_id,
                 ref_count: device.life_guard.add_ref(),
             },
+            filtering: [desc.min_filter, desc.mag_filter, desc.mipmap_filter]
+                .contains(&wgt::FilterMode::Linear),
             life_guard: LifeGuard::new(),
+            extra_handles: 0,
+            cache_key: key.clone(),
+            #[cfg(feature = "trace")]
+            trace_desc: desc.map_label(own_label),
         };
         let ref_count = sampler.life_guard.add_ref();
+        #[cfg(feature = "trace")]
+        let trace_desc = sampler.trace_desc.clone();
 
         let id = hub.samplers.register_identity(id_in, sampler, &mut token);
+        #[cfg(feature = "resource-events")]
+        device
+            .resource_events
+            .lock()
+            .created(events::ResourceKind::Sampler, id, desc.label, 0);
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateSampler {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateSampler {
                 id,
-                desc: desc.map_label(own_label),
+                desc: trace_desc,
             }),
             None => (),
         };
@@ -1017,34 +2465,49 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .samplers
             .init(id, ref_count, PhantomData)
             .unwrap();
-        id
+        if let Some(key) = key {
+            device.sampler_cache.lock().insert(key, id);
+        }
+        Ok(id)
     }
 
     pub fn sampler_destroy<B: GfxBackend>(&self, sampler_id: id::SamplerId) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
-        let device_id = {
+        let (device_id, cache_key) = {
             let (mut sampler_guard, _) = hub.samplers.write(&mut token);
-            let sampler = &mut sampler_guard[sampler_id];
+            let sampler = match sampler_guard.get_mut(sampler_id) {
+                Some(sampler) => sampler,
+                None => {
+                    log::error!("Destroying an invalid sampler {:?}", sampler_id);
+                    return;
+                }
+            };
+            if sampler.extra_handles != 0 {
+                sampler.extra_handles -= 1;
+                return;
+            }
             sampler.life_guard.ref_count.take();
-            sampler.device_id.value
+            (sampler.device_id.value, sampler.cache_key.take())
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .samplers
-            .push(sampler_id);
-    }
+        let device = &device_guard[device_id];
+        if let Some(key) = cache_key {
+            device.sampler_cache.lock().remove(&key, sampler_id);
+        }
+        device.release(self, &mut token, |suspected| {
+            suspected.samplers.push(sampler_id)
+        });
+    }
 
     pub fn device_create_bind_group_layout<B: GfxBackend>(
         &self,
         device_id: id::DeviceId,
         desc: &binding_model::BindGroupLayoutDescriptor,
         id_in: Input<G, id::BindGroupLayoutId>,
-    ) -> id::BindGroupLayoutId {
+    ) -> Result<id::BindGroupLayoutId, InvalidIdError> {
         let mut token = Token::root();
         let hub = B::hub(self);
         let entries = unsafe { slice::from_raw_parts(desc.entries, desc.entries_length) };
@@ -1061,7 +2524,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 .find(|(_, bgl)| bgl.entries == entry_map);
 
             if let Some((id, _)) = bind_group_layout_id {
-                return id;
+                return Ok(id);
             }
         }
 
@@ -1077,7 +2540,9 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .collect::<Vec<_>>(); //TODO: avoid heap allocation
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
         let raw = unsafe {
             let mut raw_layout = device
                 .raw
@@ -1101,22 +2566,23 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             life_guard: LifeGuard::new(),
             entries: entry_map,
             desc_counts: raw_bindings.iter().cloned().collect(),
-            dynamic_count: entries.iter().filter(|b| b.has_dynamic_offset).count(),
+            #[cfg(feature = "trace")]
+            label: own_label(&desc.label),
         };
 
         let id = hub
             .bind_group_layouts
             .register_identity(id_in, layout, &mut token);
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateBindGroupLayout {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateBindGroupLayout {
                 id,
                 label: own_label(&desc.label),
                 entries: entries.to_owned(),
             }),
             None => (),
         };
-        id
+        Ok(id)
     }
 
     pub fn bind_group_layout_destroy<B: GfxBackend>(
@@ -1127,7 +2593,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let mut token = Token::root();
         let (device_id, ref_count) = {
             let (mut bind_group_layout_guard, _) = hub.bind_group_layouts.write(&mut token);
-            let layout = &mut bind_group_layout_guard[bind_group_layout_id];
+            let layout = match bind_group_layout_guard.get_mut(bind_group_layout_id) {
+                Some(layout) => layout,
+                None => {
+                    log::error!(
+                        "Destroying an invalid bind group layout {:?}",
+                        bind_group_layout_id
+                    );
+                    return;
+                }
+            };
             (
                 layout.device_id.value,
                 layout.life_guard.ref_count.take().unwrap(),
@@ -1135,14 +2610,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .bind_group_layouts
-            .push(Stored {
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.bind_group_layouts.push(Stored {
                 value: bind_group_layout_id,
                 ref_count,
-            });
+            })
+        });
     }
 
     pub fn device_create_pipeline_layout<B: GfxBackend>(
@@ -1150,12 +2623,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &binding_model::PipelineLayoutDescriptor,
         id_in: Input<G, id::PipelineLayoutId>,
-    ) -> id::PipelineLayoutId {
+    ) -> Result<id::PipelineLayoutId, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
         let bind_group_layout_ids = unsafe {
             slice::from_raw_parts(desc.bind_group_layouts, desc.bind_group_layouts_length)
         };
@@ -1170,6 +2645,45 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         // TODO: push constants
         let pipeline_layout = {
             let (bind_group_layout_guard, _) = hub.bind_group_layouts.read(&mut token);
+            for &id in bind_group_layout_ids {
+                let layout = bind_group_layout_guard
+                    .get(id)
+                    .ok_or(InvalidIdError::BindGroupLayout(id))?;
+                check_device(device_id, id, layout.device_id.value)?;
+            }
+            let exceeded = binding_model::check_pipeline_layout_limits(
+                bind_group_layout_ids
+                    .iter()
+                    .flat_map(|&id| bind_group_layout_guard[id].entries.values()),
+                &device.limits,
+            );
+            if !exceeded.is_empty() {
+                let reasons = exceeded
+                    .iter()
+                    .map(|limit| limit.to_string())
+                    .collect::<Vec<_>>();
+                panic!(
+                    "Pipeline layout exceeds the device limits: {}",
+                    reasons.join("; ")
+                );
+            }
+            if !device.downlevel.fragment_writable_storage {
+                for &id in bind_group_layout_ids {
+                    for entry in bind_group_layout_guard[id].entries.values() {
+                        let writable = match entry.ty {
+                            binding_model::BindingType::StorageBuffer
+                            | binding_model::BindingType::WriteonlyStorageTexture => true,
+                            _ => false,
+                        };
+                        assert!(
+                            !writable || !entry.visibility.contains(wgt::ShaderStage::FRAGMENT),
+                            "Binding {} of {:?} is writable storage visible to the fragment stage, which this adapter does not support",
+                            entry.binding,
+                            id
+                        );
+                    }
+                }
+            }
             let descriptor_set_layouts = bind_group_layout_ids
                 .iter()
                 .map(|&id| &bind_group_layout_guard[id].raw);
@@ -1204,14 +2718,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .pipeline_layouts
             .register_identity(id_in, layout, &mut token);
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreatePipelineLayout {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreatePipelineLayout {
                 id,
                 bind_group_layouts: bind_group_layout_ids.to_owned(),
             }),
             None => (),
         };
-        id
+        Ok(id)
     }
 
     pub fn pipeline_layout_destroy<B: GfxBackend>(&self, pipeline_layout_id: id::PipelineLayoutId) {
@@ -1219,7 +2733,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let mut token = Token::root();
         let (device_id, ref_count) = {
             let (mut pipeline_layout_guard, _) = hub.pipeline_layouts.write(&mut token);
-            let layout = &mut pipeline_layout_guard[pipeline_layout_id];
+            let layout = match pipeline_layout_guard.get_mut(pipeline_layout_id) {
+                Some(layout) => layout,
+                None => {
+                    log::error!(
+                        "Destroying an invalid pipeline layout {:?}",
+                        pipeline_layout_id
+                    );
+                    return;
+                }
+            };
             (
                 layout.device_id.value,
                 layout.life_guard.ref_count.take().unwrap(),
@@ -1227,14 +2750,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .pipeline_layouts
-            .push(Stored {
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.pipeline_layouts.push(Stored {
                 value: pipeline_layout_id,
                 ref_count,
-            });
+            })
+        });
     }
 
     pub fn device_create_bind_group<B: GfxBackend>(
@@ -1242,15 +2763,121 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &binding_model::BindGroupDescriptor,
         id_in: Input<G, id::BindGroupId>,
-    ) -> id::BindGroupId {
+    ) -> Result<id::BindGroupId, binding_model::BindGroupError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
         let (bind_group_layout_guard, mut token) = hub.bind_group_layouts.read(&mut token);
-        let bind_group_layout = &bind_group_layout_guard[desc.layout];
+        let bind_group_layout = bind_group_layout_guard
+            .get(desc.layout)
+            .ok_or(InvalidIdError::BindGroupLayout(desc.layout))?;
+        check_device(device_id, desc.layout, bind_group_layout.device_id.value)?;
         let entries = unsafe { slice::from_raw_parts(desc.entries, desc.entries_length) };
+
+        // Reject unknown and incompatible resources before anything is allocated
+        // for the bind group.
+        {
+            let (buffer_guard, mut token) = hub.buffers.read(&mut token);
+            let (_, mut token) = hub.textures.read(&mut token);
+            let (texture_view_guard, mut token) = hub.texture_views.read(&mut token);
+            let (sampler_guard, _) = hub.samplers.read(&mut token);
+            let mut filtering_samplers = Vec::new();
+            let mut unfilterable_textures = Vec::new();
+            for entry in entries {
+                let decl = bind_group_layout.entries.get(&entry.binding);
+                match entry.resource {
+                    binding_model::BindingResource::Buffer(ref bb) => {
+                        let buffer = buffer_guard
+                            .get(bb.buffer)
+                            .ok_or(InvalidIdError::Buffer(bb.buffer))?;
+                        check_device(device_id, bb.buffer, buffer.device_id.value)?;
+                    }
+                    binding_model::BindingResource::Sampler(id) => {
+                        let sampler = sampler_guard.get(id).ok_or(InvalidIdError::Sampler(id))?;
+                        check_device(device_id, id, sampler.device_id.value)?;
+                        if sampler.filtering {
+                            if decl.map(|decl| decl.ty)
+                                == Some(binding_model::BindingType::NonFilteringSampler)
+                            {
+                                return Err(binding_model::BindGroupError::FilteringSampler {
+                                    binding: entry.binding,
+                                });
+                            }
+                            filtering_samplers.push(entry.binding);
+                        }
+                    }
+                    binding_model::BindingResource::TextureView(id) => {
+                        let view = texture_view_guard
+                            .get(id)
+                            .ok_or(InvalidIdError::TextureView(id))?;
+                        check_device(device_id, id, view.device_id.value)?;
+                        let decl = match decl {
+                            Some(decl) if decl.ty == binding_model::BindingType::SampledTexture => {
+                                decl
+                            }
+                            _ => continue,
+                        };
+                        let actual = view.component_type();
+                        if !decl.texture_component_type.accepts(actual) {
+                            return Err(binding_model::BindGroupError::IncompatibleTextureView {
+                                binding: entry.binding,
+                                expected: decl.texture_component_type,
+                                actual,
+                            });
+                        }
+                        if decl.multisampled != (view.samples > 1) {
+                            return Err(binding_model::BindGroupError::MultisampledMismatch {
+                                binding: entry.binding,
+                                multisampled: decl.multisampled,
+                                samples: view.samples,
+                            });
+                        }
+                        if !actual.is_filterable() {
+                            unfilterable_textures.push(entry.binding);
+                        }
+                    }
+                }
+            }
+            if let Some((texture, sampler)) =
+                binding_model::sampled_together(bind_group_layout.entries.values())
+            {
+                if filtering_samplers.contains(&sampler) && unfilterable_textures.contains(&texture)
+                {
+                    return Err(
+                        binding_model::BindGroupError::FilteringSamplerWithUnfilterableTexture {
+                            texture,
+                            sampler,
+                        },
+                    );
+                }
+            }
+        }
+
+        let key = if device.deduplicate_objects {
+            let key = BindGroupKey::new(desc.layout, entries);
+            let (mut bind_group_guard, _) = hub.bind_groups.write(&mut token);
+            if let Some(id) = device.bind_group_cache.lock().find(&key) {
+                let bind_group = &mut bind_group_guard[id];
+                // The last handle may be getting destroyed right now.
+                if bind_group.life_guard.ref_count.is_some() {
+                    bind_group.extra_handles += 1;
+                    #[cfg(feature = "trace")]
+                    match *device.trace.lock() {
+                        Some(ref mut trace) => trace.add(trace::Action::ReuseBindGroup(id)),
+                        None => (),
+                    };
+                    return Ok(id);
+                }
+            }
+            Some(key)
+        } else {
+            None
+        };
+
         assert_eq!(entries.len(), bind_group_layout.entries.len(), "Bind group has {} entries and bind group layout has {} entries, they should be the same.", entries.len(), bind_group_layout.entries.len());
 
         let desc_set = unsafe {
@@ -1279,9 +2906,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         // fill out the descriptors
         let mut used = TrackerSet::new(B::VARIANT);
+        let mut dynamic_bindings = Vec::new();
         {
             let (buffer_guard, mut token) = hub.buffers.read(&mut token);
-            let (texture_guard, mut token) = hub.textures.read(&mut token); //skip token
+            let (_, mut token) = hub.textures.read(&mut token);
             let (texture_view_guard, mut token) = hub.texture_views.read(&mut token);
             let (sampler_guard, _) = hub.samplers.read(&mut token);
 
@@ -1296,22 +2924,23 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     binding_model::BindingResource::Buffer(ref bb) => {
                         let (alignment, pub_usage, internal_use) = match decl.ty {
                             binding_model::BindingType::UniformBuffer => (
-                                BIND_BUFFER_ALIGNMENT,
+                                device.limits.min_uniform_buffer_offset_alignment as BufferAddress,
                                 wgt::BufferUsage::UNIFORM,
                                 resource::BufferUse::UNIFORM,
                             ),
                             binding_model::BindingType::StorageBuffer => (
-                                BIND_BUFFER_ALIGNMENT,
+                                device.limits.min_storage_buffer_offset_alignment as BufferAddress,
                                 wgt::BufferUsage::STORAGE,
                                 resource::BufferUse::STORAGE_STORE,
                             ),
                             binding_model::BindingType::ReadonlyStorageBuffer => (
-                                BIND_BUFFER_ALIGNMENT,
+                                device.limits.min_storage_buffer_offset_alignment as BufferAddress,
                                 wgt::BufferUsage::STORAGE,
                                 resource::BufferUse::STORAGE_LOAD,
                             ),
                             binding_model::BindingType::Sampler
                             | binding_model::BindingType::ComparisonSampler
+                            | binding_model::BindingType::NonFilteringSampler
                             | binding_model::BindingType::SampledTexture
                             | binding_model::BindingType::ReadonlyStorageTexture
                             | binding_model::BindingType::WriteonlyStorageTexture => {
@@ -1328,7 +2957,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         let buffer = used
                             .buffers
                             .use_extend(&*buffer_guard, bb.buffer, (), internal_use)
-                            .unwrap();
+                            .unwrap_or_else(|old_use| {
+                                panic!(
+                                    "Buffer {:?} is already used as {:?} in the bind group, can't be used as {:?}",
+                                    bb.buffer, old_use, internal_use
+                                )
+                            });
                         assert!(
                             buffer.usage.contains(pub_usage),
                             "Buffer usage {:?} must contain usage flag(s) {:?}",
@@ -1338,26 +2972,46 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
                         let sub_range = hal::buffer::SubRange {
                             offset: bb.offset,
-                            size: if bb.size == 0 {
-                                None
-                            } else {
-                                let end = bb.offset + bb.size;
-                                assert!(
-                                    end <= buffer.size,
-                                    "Bound buffer range {:?} does not fit in buffer size {}",
-                                    bb.offset..end,
-                                    buffer.size
-                                );
-                                Some(bb.size)
+                            size: match bb.size {
+                                Some(size) => {
+                                    let end = bb.offset + size.get();
+                                    assert!(
+                                        end <= buffer.size,
+                                        "Bound buffer range {:?} does not fit in buffer size {}",
+                                        bb.offset..end,
+                                        buffer.size
+                                    );
+                                    Some(size.get())
+                                }
+                                None => {
+                                    assert!(
+                                        bb.offset < buffer.size,
+                                        "Bound buffer offset {} is not within buffer size {}",
+                                        bb.offset,
+                                        buffer.size
+                                    );
+                                    None
+                                }
                             },
                         };
+                        if decl.has_dynamic_offset {
+                            dynamic_bindings.push(binding_model::DynamicBinding {
+                                binding: b.binding,
+                                buffer_id: bb.buffer,
+                                buffer_size: buffer.size,
+                                offset: bb.offset,
+                                size: bb.size,
+                                alignment,
+                            });
+                        }
                         hal::pso::Descriptor::Buffer(&buffer.raw, sub_range)
                     }
                     binding_model::BindingResource::Sampler(id) => {
                         match decl.ty {
                             binding_model::BindingType::Sampler
-                            | binding_model::BindingType::ComparisonSampler => {}
-                            _ => panic!("Mismatched sampler binding type in {:?}. Expected a type of Sampler or ComparisonSampler", decl.ty),
+                            | binding_model::BindingType::ComparisonSampler
+                            | binding_model::BindingType::NonFilteringSampler => {}
+                            _ => panic!("Mismatched sampler binding type in {:?}. Expected a type of Sampler, ComparisonSampler or NonFilteringSampler", decl.ty),
                         }
                         let sampler = used
                             .samplers
@@ -1384,6 +3038,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                             ),
                             _ => panic!("Mismatched texture binding type in {:?}. Expected a type of SampledTexture, ReadonlyStorageTexture or WriteonlyStorageTexture", decl),
                         };
+                        let internal_use = if internal_use == resource::TextureUse::SAMPLED {
+                            internal_use
+                        } else {
+                            internal_use | conv::map_storage_stages(decl.visibility)
+                        };
+                        device.check_presented_view(id);
                         let view = used
                             .views
                             .use_extend(&*texture_view_guard, id, (), ())
@@ -1393,9 +3053,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                                 ref raw,
                                 ref source_id,
                             } => {
-                                // Careful here: the texture may no longer have its own ref count,
-                                // if it was deleted by the user.
-                                let texture = &texture_guard[source_id.value];
+                                if internal_use != resource::TextureUse::SAMPLED {
+                                    assert_eq!(
+                                        view.range.levels.end - view.range.levels.start,
+                                        1,
+                                        "Storage texture view {:?} must have a single mip level",
+                                        id
+                                    );
+                                }
                                 used.textures
                                     .change_extend(
                                         source_id.value,
@@ -1405,9 +3070,9 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                                     )
                                     .unwrap();
                                 assert!(
-                                    texture.usage.contains(pub_usage),
-                                    "Texture usage {:?} must contain usage flag(s) {:?}",
-                                    texture.usage,
+                                    view.usage.contains(pub_usage),
+                                    "Texture view usage {:?} must contain usage flag(s) {:?}",
+                                    view.usage,
                                     pub_usage
                                 );
 
@@ -1432,6 +3097,35 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
         }
 
+        #[cfg(feature = "trace")]
+        let trace_desc = trace::BindGroupDescriptor {
+            label: own_label(&desc.label),
+            layout_id: desc.layout,
+            entries: entries
+                .iter()
+                .map(|entry| {
+                    let res = match entry.resource {
+                        binding_model::BindingResource::Buffer(ref b) => {
+                            trace::BindingResource::Buffer {
+                                id: b.buffer,
+                                offset: b.offset,
+                                size: b.size,
+                            }
+                        }
+                        binding_model::BindingResource::TextureView(id) => {
+                            trace::BindingResource::TextureView(id)
+                        }
+                        binding_model::BindingResource::Sampler(id) => {
+                            trace::BindingResource::Sampler(id)
+                        }
+                    };
+                    (entry.binding, res)
+                })
+                .collect(),
+        };
+
+        dynamic_bindings.sort_by_key(|binding| binding.binding);
+
         let bind_group = binding_model::BindGroup {
             raw: desc_set,
             device_id: Stored {
@@ -1440,47 +3134,38 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             },
             layout_id: desc.layout,
             life_guard: LifeGuard::new(),
+            extra_handles: 0,
+            cache_key: key.clone(),
             used,
-            dynamic_count: bind_group_layout.dynamic_count,
+            scope_stamp: ScopeStamp::default(),
+            dynamic_bindings,
+            derived_sets: FastHashMap::default(),
+            destroyed_member: None,
+            #[cfg(feature = "trace")]
+            trace_desc: trace_desc.clone(),
         };
         let ref_count = bind_group.life_guard.add_ref();
 
         let id = hub
             .bind_groups
             .register_identity(id_in, bind_group, &mut token);
-        log::debug!(
-            "Bind group {:?} {:#?}",
-            id,
-            hub.bind_groups.read(&mut token).0[id].used
-        );
+        #[cfg(feature = "resource-events")]
+        device
+            .resource_events
+            .lock()
+            .created(events::ResourceKind::BindGroup, id, desc.label, 0);
+        {
+            let (bind_group_guard, _) = hub.bind_groups.read(&mut token);
+            let used = &bind_group_guard[id].used;
+            log::debug!("Bind group {:?} {:#?}", id, used);
+            device
+                .bind_group_members
+                .lock()
+                .insert(id, used.buffers.used(), used.views.used());
+        }
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateBindGroup {
-                id,
-                label: own_label(&desc.label),
-                layout_id: desc.layout,
-                entries: entries
-                    .iter()
-                    .map(|entry| {
-                        let res = match entry.resource {
-                            binding_model::BindingResource::Buffer(ref b) => {
-                                trace::BindingResource::Buffer {
-                                    id: b.buffer,
-                                    offset: b.offset,
-                                    size: b.size,
-                                }
-                            }
-                            binding_model::BindingResource::TextureView(id) => {
-                                trace::BindingResource::TextureView(id)
-                            }
-                            binding_model::BindingResource::Sampler(id) => {
-                                trace::BindingResource::Sampler(id)
-                            }
-                        };
-                        (entry.binding, res)
-                    })
-                    .collect(),
-            }),
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace_desc.into_action(id)),
             None => (),
         };
 
@@ -1490,26 +3175,49 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .bind_groups
             .init(id, ref_count, PhantomData)
             .unwrap();
-        id
+        if let Some(key) = key {
+            device.bind_group_cache.lock().insert(key, id);
+        }
+        Ok(id)
     }
 
     pub fn bind_group_destroy<B: GfxBackend>(&self, bind_group_id: id::BindGroupId) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
-        let device_id = {
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let (device_id, cache_key) = {
             let (mut bind_group_guard, _) = hub.bind_groups.write(&mut token);
-            let bind_group = &mut bind_group_guard[bind_group_id];
+            let bind_group = match bind_group_guard.get_mut(bind_group_id) {
+                Some(bind_group) => bind_group,
+                None => {
+                    log::error!("Destroying an invalid bind group {:?}", bind_group_id);
+                    return;
+                }
+            };
+            if bind_group.extra_handles != 0 {
+                bind_group.extra_handles -= 1;
+                return;
+            }
             bind_group.life_guard.ref_count.take();
-            bind_group.device_id.value
+            device_guard[bind_group.device_id.value]
+                .bind_group_members
+                .lock()
+                .remove(
+                    bind_group_id,
+                    bind_group.used.buffers.used(),
+                    bind_group.used.views.used(),
+                );
+            (bind_group.device_id.value, bind_group.cache_key.take())
         };
 
-        let (device_guard, mut token) = hub.devices.read(&mut token);
-        device_guard[device_id]
-            .lock_life(&mut token)
-            .suspected_resources
-            .bind_groups
-            .push(bind_group_id);
+        let device = &device_guard[device_id];
+        if let Some(key) = cache_key {
+            device.bind_group_cache.lock().remove(&key, bind_group_id);
+        }
+        device.release(self, &mut token, |suspected| {
+            suspected.bind_groups.push(bind_group_id)
+        });
     }
 
     pub fn device_create_shader_module<B: GfxBackend>(
@@ -1517,11 +3225,13 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &pipeline::ShaderModuleDescriptor,
         id_in: Input<G, id::ShaderModuleId>,
-    ) -> id::ShaderModuleId {
+    ) -> Result<id::ShaderModuleId, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
 
         let spv = unsafe { slice::from_raw_parts(desc.code.bytes, desc.code.length) };
         let raw = unsafe { device.raw.create_shader_module(spv).unwrap() };
@@ -1544,38 +3254,53 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 value: device_id,
                 ref_count: device.life_guard.add_ref(),
             },
+            label: own_label(&desc.label),
             module,
+            required_features: pipeline::ShaderFeatures::from_spirv(spv),
+            code: spv.to_vec(),
         };
 
         let id = hub
             .shader_modules
             .register_identity(id_in, shader, &mut token);
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => {
-                let mut trace = trace.lock();
+        match *device.trace.lock() {
+            Some(ref mut trace) => {
                 let data = trace.make_binary("spv", unsafe {
                     slice::from_raw_parts(desc.code.bytes as *const u8, desc.code.length * 4)
                 });
-                trace.add(trace::Action::CreateShaderModule { id, data });
+                trace.add(trace::Action::CreateShaderModule {
+                    id,
+                    label: own_label(&desc.label),
+                    data,
+                });
             }
             None => {}
         };
-        id
+        Ok(id)
     }
 
     pub fn shader_module_destroy<B: GfxBackend>(&self, shader_module_id: id::ShaderModuleId) {
+        self.flush_pipelines_using_module::<B>(shader_module_id);
+
         let hub = B::hub(self);
         let mut token = Token::root();
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let (module, _) = hub.shader_modules.unregister(shader_module_id, &mut token);
+        let module = match hub
+            .shader_modules
+            .try_unregister(shader_module_id, &mut token)
+        {
+            Some((module, _)) => module,
+            None => {
+                log::error!("Destroying an invalid shader module {:?}", shader_module_id);
+                return;
+            }
+        };
 
         let device = &device_guard[module.device_id.value];
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace
-                .lock()
-                .add(trace::Action::DestroyShaderModule(shader_module_id)),
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::DestroyShaderModule(shader_module_id)),
             None => (),
         };
         unsafe {
@@ -1583,17 +3308,118 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         }
     }
 
+    /// Create a pipeline cache, optionally seeded with the data previously
+    /// returned by `pipeline_cache_get_data`.
+    ///
+    /// Data produced on a different adapter or driver, or that the driver
+    /// rejects, is ignored. The cache starts empty then.
+    pub fn device_create_pipeline_cache<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        initial_data: Option<&[u8]>,
+        id_in: Input<G, id::PipelineCacheId>,
+    ) -> Result<id::PipelineCacheId, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+
+        let data = if pipeline::backend_supports_pipeline_caches(B::VARIANT) {
+            initial_data.and_then(|data| {
+                let stripped =
+                    pipeline::strip_pipeline_cache_header(&device.pipeline_cache_header, data);
+                if stripped.is_none() {
+                    log::warn!("Pipeline cache data is from another adapter or driver, ignoring");
+                }
+                stripped
+            })
+        } else {
+            None
+        };
+        let raw = unsafe {
+            match device.raw.create_pipeline_cache(data) {
+                Ok(raw) => raw,
+                Err(error) if data.is_some() => {
+                    log::warn!("Pipeline cache data was rejected: {:?}, ignoring", error);
+                    device.raw.create_pipeline_cache(None).unwrap()
+                }
+                Err(error) => panic!("Unable to create a pipeline cache: {:?}", error),
+            }
+        };
+
+        let cache = pipeline::PipelineCache {
+            raw,
+            device_id: Stored {
+                value: device_id,
+                ref_count: device.life_guard.add_ref(),
+            },
+        };
+        Ok(hub
+            .pipeline_caches
+            .register_identity(id_in, cache, &mut token))
+    }
+
+    /// Serialize the pipeline cache contents, for loading in a future run.
+    ///
+    /// Returns nothing if the backend doesn't support pipeline caches.
+    pub fn pipeline_cache_get_data<B: GfxBackend>(
+        &self,
+        cache_id: id::PipelineCacheId,
+    ) -> Result<Vec<u8>, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let (cache_guard, _) = hub.pipeline_caches.read(&mut token);
+        let cache = cache_guard
+            .get(cache_id)
+            .ok_or(InvalidIdError::PipelineCache(cache_id))?;
+        if !pipeline::backend_supports_pipeline_caches(B::VARIANT) {
+            return Ok(Vec::new());
+        }
+        let device = &device_guard[cache.device_id.value];
+
+        let raw_data = unsafe { device.raw.get_pipeline_cache_data(&cache.raw).unwrap() };
+        if raw_data.is_empty() {
+            return Ok(raw_data);
+        }
+        let mut data = device.pipeline_cache_header.clone();
+        data.extend_from_slice(&raw_data);
+        Ok(data)
+    }
+
+    pub fn pipeline_cache_destroy<B: GfxBackend>(&self, cache_id: id::PipelineCacheId) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let cache = match hub.pipeline_caches.try_unregister(cache_id, &mut token) {
+            Some((cache, _)) => cache,
+            None => {
+                log::error!("Destroying an invalid pipeline cache {:?}", cache_id);
+                return;
+            }
+        };
+
+        let device = &device_guard[cache.device_id.value];
+        unsafe {
+            device.raw.destroy_pipeline_cache(cache.raw);
+        }
+    }
+
     pub fn device_create_command_encoder<B: GfxBackend>(
         &self,
         device_id: id::DeviceId,
         desc: &wgt::CommandEncoderDescriptor,
         id_in: Input<G, id::CommandEncoderId>,
-    ) -> id::CommandEncoderId {
+    ) -> Result<id::CommandEncoderId, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
 
         let dev_stored = Stored {
             value: device_id,
@@ -1604,9 +3430,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             dev_stored,
             &device.raw,
             device.limits.clone(),
+            device.downlevel,
             device.private_features,
             #[cfg(feature = "trace")]
-            device.trace.is_some(),
+            device.trace.lock().is_some(),
         );
 
         unsafe {
@@ -1620,16 +3447,29 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             raw_command_buffer.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
         }
 
-        hub.command_buffers
-            .register_identity(id_in, command_buffer, &mut token)
+        Ok(hub
+            .command_buffers
+            .register_identity(id_in, command_buffer, &mut token))
     }
 
+    /// Drop a command encoder or command buffer that will not be submitted.
+    ///
+    /// This works regardless of whether the encoder was finished: the raw
+    /// command buffers are handed straight back to the allocator, and the
+    /// ID is removed, so `queue_submit` would reject it as invalid afterwards.
     pub fn command_encoder_destroy<B: GfxBackend>(&self, command_encoder_id: id::CommandEncoderId) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
-        let comb = {
+        let mut comb = {
             let (mut command_buffer_guard, _) = hub.command_buffers.write(&mut token);
+            if !command_buffer_guard.contains(command_encoder_id) {
+                log::error!(
+                    "Destroying an invalid command encoder {:?}",
+                    command_encoder_id
+                );
+                return;
+            }
             command_buffer_guard.remove(command_encoder_id).unwrap()
         };
 
@@ -1688,6 +3528,9 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .lock_life(&mut token)
             .suspected_resources
             .extend(&device.temp_suspected);
+        for ring in comb.inline_uniforms.drain(..) {
+            device.destroy_inline_uniform_ring(ring);
+        }
         device.com_allocator.discard(comb);
     }
 
@@ -1700,7 +3543,40 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &pipeline::RenderPipelineDescriptor,
         id_in: Input<G, id::RenderPipelineId>,
-    ) -> id::RenderPipelineId {
+    ) -> Result<id::RenderPipelineId, pipeline::PipelineError> {
+        self.create_render_pipeline::<B>(device_id, desc, id_in, None)
+    }
+
+    /// Create a render pipeline, compiling the native pipeline on a background thread.
+    ///
+    /// The descriptor is validated right away. The returned pipeline is pending
+    /// until it's compiled, either by the background thread or by a pass that
+    /// uses it, at which point `callback` is invoked from that thread.
+    /// The global needs to live forever, since the thread refers to it.
+    pub fn device_create_render_pipeline_async<B: GfxBackend>(
+        &'static self,
+        device_id: id::DeviceId,
+        desc: &pipeline::RenderPipelineDescriptor,
+        id_in: Input<G, id::RenderPipelineId>,
+        callback: pipeline::PipelineCreatedCallback,
+        userdata: *mut u8,
+    ) -> Result<id::RenderPipelineId, pipeline::PipelineError>
+    where
+        Self: Sync,
+    {
+        let callback = pipeline::PipelineCallback { callback, userdata };
+        let id = self.create_render_pipeline::<B>(device_id, desc, id_in, Some(callback))?;
+        self.spawn_pipeline_compiler::<B>(device_id);
+        Ok(id)
+    }
+
+    fn create_render_pipeline<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        desc: &pipeline::RenderPipelineDescriptor,
+        id_in: Input<G, id::RenderPipelineId>,
+        callback: Option<pipeline::PipelineCallback>,
+    ) -> Result<id::RenderPipelineId, pipeline::PipelineError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
@@ -1716,11 +3592,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             unsafe { slice::from_raw_parts(desc.color_states, desc.color_states_length) };
         let depth_stencil_state = unsafe { desc.depth_stencil_state.as_ref() };
 
-        let rasterizer = conv::map_rasterization_state_descriptor(
-            &unsafe { desc.rasterization_state.as_ref() }
-                .cloned()
-                .unwrap_or_default(),
-        );
+        let rasterization_state = unsafe { desc.rasterization_state.as_ref() }
+            .cloned()
+            .unwrap_or_default();
+        let rasterizer = conv::map_rasterization_state_descriptor(&rasterization_state);
 
         let desc_vbs = unsafe {
             slice::from_raw_parts(
@@ -1732,18 +3607,29 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let mut vertex_buffers = Vec::with_capacity(desc_vbs.len());
         let mut attributes = Vec::new();
         for (i, vb_state) in desc_vbs.iter().enumerate() {
-            vertex_strides
-                .alloc()
-                .init((vb_state.array_stride, vb_state.step_mode));
+            vertex_strides.alloc().init((
+                vb_state.array_stride,
+                vb_state.step_mode,
+                vb_state.step_rate,
+            ));
             if vb_state.attributes_length == 0 {
                 continue;
             }
+            assert_eq!(
+                0,
+                vb_state.array_stride >> 32,
+                "Array stride of vertex buffer {} must be < 2^32, but was {}",
+                i,
+                vb_state.array_stride
+            );
             vertex_buffers.alloc().init(hal::pso::VertexBufferDesc {
                 binding: i as u32,
                 stride: vb_state.array_stride as u32,
                 rate: match vb_state.step_mode {
                     InputStepMode::Vertex => hal::pso::VertexInputRate::Vertex,
-                    InputStepMode::Instance => hal::pso::VertexInputRate::Instance(1),
+                    InputStepMode::Instance => {
+                        hal::pso::VertexInputRate::Instance(vb_state.step_rate)
+                    }
                 },
             });
             let desc_atts =
@@ -1756,6 +3642,21 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     attribute,
                     attribute.offset
                 );
+                assert_eq!(
+                    0,
+                    attribute.offset % attribute.format.alignment(),
+                    "Offset for attribute {:?} must be a multiple of {}",
+                    attribute,
+                    attribute.format.alignment()
+                );
+                if vb_state.array_stride != 0 {
+                    assert!(
+                        attribute.offset + attribute.format.size() <= vb_state.array_stride,
+                        "Attribute {:?} doesn't fit into the array stride {}",
+                        attribute,
+                        vb_state.array_stride
+                    );
+                }
                 attributes.alloc().init(hal::pso::AttributeDesc {
                     location: attribute.shader_location,
                     binding: i as u32,
@@ -1767,10 +3668,25 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
         }
 
+        let strip_index_format = unsafe { desc.strip_index_format.as_ref() }.cloned();
+        if desc.primitive_topology.is_strip() {
+            assert!(
+                strip_index_format.is_some(),
+                "Strip topology {:?} requires a strip index format",
+                desc.primitive_topology
+            );
+        } else {
+            assert!(
+                strip_index_format.is_none(),
+                "Strip index format can't be used with topology {:?}",
+                desc.primitive_topology
+            );
+        }
+
         let input_assembler = hal::pso::InputAssemblerDesc {
             primitive: conv::map_primitive_topology(desc.primitive_topology),
             with_adjacency: false,
-            restart_index: None, //TODO
+            restart_index: strip_index_format.map(conv::map_index_format),
         };
 
         let blender = hal::pso::BlendDesc {
@@ -1789,10 +3705,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         } else {
             Some(hal::pso::Multisampling {
                 rasterization_samples: sc,
-                sample_shading: None,
+                sample_shading: if desc.min_sample_shading > 0.0 {
+                    Some(desc.min_sample_shading)
+                } else {
+                    None
+                },
                 sample_mask: desc.sample_mask as u64,
                 alpha_coverage: desc.alpha_to_coverage_enabled,
-                alpha_to_one: false,
+                alpha_to_one: desc.alpha_to_one_enabled,
             })
         };
 
@@ -1805,18 +3725,139 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        device.validate_rasterization_state(&rasterization_state);
+        assert!(
+            desc_vbs.len() <= device.limits.max_vertex_buffers as usize,
+            "Render pipeline has {} vertex buffers, more than the max_vertex_buffers limit {}",
+            desc_vbs.len(),
+            device.limits.max_vertex_buffers
+        );
+        for vb_state in desc_vbs {
+            match vb_state.step_mode {
+                InputStepMode::Vertex => assert_eq!(
+                    vb_state.step_rate, 1,
+                    "Per-vertex buffers must have a step rate of 1"
+                ),
+                InputStepMode::Instance => {
+                    assert_ne!(vb_state.step_rate, 0, "Step rate must not be 0");
+                    assert!(
+                        vb_state.step_rate == 1
+                            || device
+                                .capabilities
+                                .contains(wgt::Capabilities::VERTEX_INSTANCE_RATE),
+                        "Step rate {} requires the VERTEX_INSTANCE_RATE capability",
+                        vb_state.step_rate
+                    );
+                    assert!(
+                        vb_state.step_rate <= device.limits.max_vertex_step_rate,
+                        "Step rate {} is higher than the max_vertex_step_rate limit {}",
+                        vb_state.step_rate,
+                        device.limits.max_vertex_step_rate
+                    );
+                }
+            }
+            let desc_atts =
+                unsafe { slice::from_raw_parts(vb_state.attributes, vb_state.attributes_length) };
+            for attribute in desc_atts {
+                assert!(
+                    !device.unsupported_vertex_formats.contains(&attribute.format),
+                    "Vertex format {:?} is not supported by the {:?} backend",
+                    attribute.format,
+                    B::VARIANT
+                );
+            }
+        }
+        validate_blend_targets(&blender.targets, &device.downlevel)?;
+        assert!(
+            desc.min_sample_shading >= 0.0 && desc.min_sample_shading <= 1.0,
+            "Invalid min_sample_shading of {}; must be within 0.0 and 1.0",
+            desc.min_sample_shading
+        );
+        if desc.min_sample_shading > 0.0 {
+            assert!(
+                device
+                    .capabilities
+                    .contains(wgt::Capabilities::SAMPLE_SHADING),
+                "Per-sample shading is not supported by the adapter"
+            );
+        }
+        let mut pending = None;
         let (raw_pipeline, layout_ref_count) = {
             let (pipeline_layout_guard, mut token) = hub.pipeline_layouts.read(&mut token);
-            let layout = &pipeline_layout_guard[desc.layout];
-            let (shader_module_guard, _) = hub.shader_modules.read(&mut token);
-
-            let rp_key = RenderPassKey {
-                colors: color_states
-                    .iter()
-                    .map(|at| hal::pass::Attachment {
-                        format: Some(conv::map_texture_format(at.format, device.private_features)),
-                        samples: sc,
+            let layout = pipeline_layout_guard
+                .get(desc.layout)
+                .ok_or(InvalidIdError::PipelineLayout(desc.layout))?;
+            check_device(device_id, desc.layout, layout.device_id.value)?;
+            let (shader_module_guard, mut token) = hub.shader_modules.read(&mut token);
+            let fragment_stage = unsafe { desc.fragment_stage.as_ref() };
+            for stage in iter::once(&desc.vertex_stage).chain(fragment_stage) {
+                let shader_module = shader_module_guard
+                    .get(stage.module)
+                    .ok_or(InvalidIdError::ShaderModule(stage.module))?;
+                check_device(device_id, stage.module, shader_module.device_id.value)?;
+                check_shader_features(
+                    stage.module,
+                    shader_module.required_features,
+                    device.shader_features,
+                )?;
+            }
+            {
+                let (bind_group_layout_guard, _) = hub.bind_group_layouts.read(&mut token);
+                let fragment_stage = unsafe { desc.fragment_stage.as_ref() };
+                let stages = iter::once((&desc.vertex_stage, ExecutionModel::Vertex))
+                    .chain(fragment_stage.map(|stage| (stage, ExecutionModel::Fragment)));
+                for (stage, execution_model) in stages {
+                    let entry_point_name = unsafe { ffi::CStr::from_ptr(stage.entry_point) }
+                        .to_str()
+                        .unwrap();
+                    if let Some(ref module) = shader_module_guard[stage.module].module {
+                        let pairs =
+                            texture_sampler_pairs(module, entry_point_name, execution_model);
+                        validate_texture_sampler_pairs(&pairs, |(group, binding)| {
+                            let bgl_id = layout.bind_group_layout_ids.get(group as usize)?;
+                            bind_group_layout_guard[bgl_id.value].entries.get(&binding)
+                        })?;
+                        let used = used_bindings(module, entry_point_name, execution_model);
+                        validate_binding_visibility(
+                            &used,
+                            shader_stage(execution_model),
+                            layout_entries(layout, &*bind_group_layout_guard),
+                        )?;
+                    }
+                    if execution_model == ExecutionModel::Fragment {
+                        let modes = pipeline::FragmentDepthModes::from_spirv(
+                            &shader_module_guard[stage.module].code,
+                            entry_point_name,
+                        );
+                        validate_fragment_depth_modes(
+                            modes,
+                            depth_stencil_state,
+                            &device.downlevel,
+                        )?;
+                    }
+                }
+            }
+            let (pipeline_cache_guard, _) = hub.pipeline_caches.read(&mut token);
+            let cache = match desc.cache {
+                Some(cache_id) => {
+                    let cache = pipeline_cache_guard
+                        .get(cache_id)
+                        .ok_or(InvalidIdError::PipelineCache(cache_id))?;
+                    check_device(device_id, cache_id, cache.device_id.value)?;
+                    Some(&cache.raw)
+                }
+                None => None,
+            };
+
+            let rp_key = RenderPassKey {
+                colors: color_states
+                    .iter()
+                    .map(|at| hal::pass::Attachment {
+                        format: Some(conv::map_texture_format(at.format, device.private_features)),
+                        samples: sc,
                         ops: hal::pass::AttachmentOps::PRESERVE,
                         stencil_ops: hal::pass::AttachmentOps::DONT_CARE,
                         layouts: hal::image::Layout::General..hal::image::Layout::General,
@@ -1836,40 +3877,6 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 }),
             };
 
-            let mut render_pass_cache = device.render_passes.lock();
-            let main_pass = match render_pass_cache.entry(rp_key) {
-                Entry::Occupied(e) => e.into_mut(),
-                Entry::Vacant(e) => {
-                    let color_ids = [
-                        (0, hal::image::Layout::ColorAttachmentOptimal),
-                        (1, hal::image::Layout::ColorAttachmentOptimal),
-                        (2, hal::image::Layout::ColorAttachmentOptimal),
-                        (3, hal::image::Layout::ColorAttachmentOptimal),
-                    ];
-
-                    let depth_id = (
-                        desc.color_states_length,
-                        hal::image::Layout::DepthStencilAttachmentOptimal,
-                    );
-
-                    let subpass = hal::pass::SubpassDesc {
-                        colors: &color_ids[..desc.color_states_length],
-                        depth_stencil: depth_stencil_state.map(|_| &depth_id),
-                        inputs: &[],
-                        resolves: &[],
-                        preserves: &[],
-                    };
-
-                    let pass = unsafe {
-                        device
-                            .raw
-                            .create_render_pass(e.key().all(), &[subpass], &[])
-                    }
-                    .unwrap();
-                    e.insert(pass)
-                }
-            };
-
             let vertex = {
                 let entry_point_name =
                     unsafe { ffi::CStr::from_ptr(desc.vertex_stage.entry_point) }
@@ -1887,11 +3894,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     }
                 }
 
-                hal::pso::EntryPoint::<B> {
-                    entry: entry_point_name, // TODO
-                    module: &shader_module.raw,
-                    specialization: hal::pso::Specialization::EMPTY,
-                }
+                (entry_point_name, shader_module)
             };
 
             let fragment = {
@@ -1912,53 +3915,66 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         }
                     }
 
-                    hal::pso::EntryPoint::<B> {
-                        entry: entry_point_name, // TODO
-                        module: &shader_module.raw,
-                        specialization: hal::pso::Specialization::EMPTY,
-                    }
+                    (entry_point_name, shader_module)
                 })
             };
 
-            let subpass = hal::pass::Subpass {
-                index: 0,
-                main_pass,
-            };
-
-            // TODO
-            let flags = hal::pso::PipelineCreationFlags::empty();
-            // TODO
-            let parent = hal::pso::BasePipeline::None;
-
-            let primitive_assembler = hal::pso::PrimitiveAssembler::Vertex {
-                buffers: vertex_buffers,
+            let state = pipeline::RenderPipelineState {
+                rp_key,
+                vertex_buffers,
                 attributes,
                 input_assembler,
-                vertex,
-                tessellation: None,
-                geometry: None,
-            };
-
-            let pipeline_desc = hal::pso::GraphicsPipelineDesc {
-                primitive_assembler,
                 rasterizer,
-                fragment,
                 blender,
                 depth_stencil,
                 multisampling,
                 baked_states,
-                layout: &layout.raw,
-                subpass,
-                flags,
-                parent,
             };
-
-            // TODO: cache
-            let pipeline = unsafe {
-                device
-                    .raw
-                    .create_graphics_pipeline(&pipeline_desc, None)
-                    .unwrap()
+            let pipeline = match callback {
+                Some(callback) => {
+                    pending = Some(pipeline::PendingRenderPipeline {
+                        layout_id: desc.layout,
+                        cache_id: desc.cache,
+                        state,
+                        vertex: pipeline::PendingStage {
+                            module: desc.vertex_stage.module,
+                            entry_point: vertex.0.to_string(),
+                        },
+                        fragment: fragment.map(|(entry_point, _)| pipeline::PendingStage {
+                            module: unsafe { &*desc.fragment_stage }.module,
+                            entry_point: entry_point.to_string(),
+                        }),
+                        callback,
+                    });
+                    pipeline::PipelineRaw::Pending
+                }
+                None => {
+                    let raw = device
+                        .create_raw_render_pipeline(
+                            state,
+                            &layout.raw,
+                            (vertex.0, &vertex.1.raw),
+                            fragment.map(|(entry, module)| (entry, &module.raw)),
+                            cache,
+                        )
+                        .map_err(|error| {
+                            let stages = iter::once(pipeline::StageContext::new(
+                                wgt::ShaderStage::VERTEX,
+                                vertex.0,
+                                vertex.1,
+                            ))
+                            .chain(fragment.map(|(entry_point, module)| {
+                                pipeline::StageContext::new(
+                                    wgt::ShaderStage::FRAGMENT,
+                                    entry_point,
+                                    module,
+                                )
+                            }))
+                            .collect::<Vec<_>>();
+                            pipeline::PipelineError::from_hal(error, &stages)
+                        })?;
+                    pipeline::PipelineRaw::Ready(raw)
+                }
             };
             (pipeline, layout.life_guard.add_ref())
         };
@@ -1979,6 +3995,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             if ds.needs_stencil_reference() {
                 flags |= pipeline::PipelineFlags::STENCIL_REFERENCE;
             }
+            if ds.dynamic_stencil_masks {
+                flags |= pipeline::PipelineFlags::STENCIL_MASKS;
+            }
+        }
+        if rasterization_state.dynamic_depth_bias {
+            flags |= pipeline::PipelineFlags::DEPTH_BIAS;
         }
 
         let pipeline = pipeline::RenderPipeline {
@@ -1994,52 +4016,85 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             pass_context,
             flags,
             index_format: desc.vertex_state.index_format,
+            strip_index_format,
+            topology: desc.primitive_topology,
             vertex_strides,
             sample_count: sc,
             life_guard: LifeGuard::new(),
+            #[cfg(feature = "trace")]
+            trace_desc: trace::GraphicsPipelineDescriptor::Render(trace::RenderPipelineDescriptor {
+                layout: desc.layout,
+                vertex_stage: trace::ProgrammableStageDescriptor::new(&desc.vertex_stage),
+                fragment_stage: unsafe { desc.fragment_stage.as_ref() }
+                    .map(trace::ProgrammableStageDescriptor::new),
+                primitive_topology: desc.primitive_topology,
+                strip_index_format,
+                rasterization_state: unsafe { desc.rasterization_state.as_ref() }.cloned(),
+                color_states: color_states.to_vec(),
+                depth_stencil_state: depth_stencil_state.cloned(),
+                vertex_state: trace::VertexStateDescriptor {
+                    index_format: desc.vertex_state.index_format,
+                    vertex_buffers: desc_vbs
+                        .iter()
+                        .map(|vbl| trace::VertexBufferLayoutDescriptor {
+                            array_stride: vbl.array_stride,
+                            step_mode: vbl.step_mode,
+                            step_rate: vbl.step_rate,
+                            attributes: unsafe {
+                                slice::from_raw_parts(vbl.attributes, vbl.attributes_length)
+                            }
+                            .iter()
+                            .cloned()
+                            .collect(),
+                        })
+                        .collect(),
+                },
+                sample_count: desc.sample_count,
+                sample_mask: desc.sample_mask,
+                alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
+                alpha_to_one_enabled: desc.alpha_to_one_enabled,
+                min_sample_shading: desc.min_sample_shading,
+            }),
         };
+        #[cfg(feature = "trace")]
+        let trace_desc = pipeline.trace_desc.clone();
 
         let id = hub
             .render_pipelines
             .register_identity(id_in, pipeline, &mut token);
+        if let Some(job) = pending {
+            device.pipeline_queue.pending.lock().render.insert(id, job);
+        }
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::RenderPipeline,
+            id,
+            ptr::null(),
+            0,
+        );
 
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateRenderPipeline {
-                id,
-                desc: trace::RenderPipelineDescriptor {
-                    layout: desc.layout,
-                    vertex_stage: trace::ProgrammableStageDescriptor::new(&desc.vertex_stage),
-                    fragment_stage: unsafe { desc.fragment_stage.as_ref() }
-                        .map(trace::ProgrammableStageDescriptor::new),
-                    primitive_topology: desc.primitive_topology,
-                    rasterization_state: unsafe { desc.rasterization_state.as_ref() }.cloned(),
-                    color_states: color_states.to_vec(),
-                    depth_stencil_state: depth_stencil_state.cloned(),
-                    vertex_state: trace::VertexStateDescriptor {
-                        index_format: desc.vertex_state.index_format,
-                        vertex_buffers: desc_vbs
-                            .iter()
-                            .map(|vbl| trace::VertexBufferLayoutDescriptor {
-                                array_stride: vbl.array_stride,
-                                step_mode: vbl.step_mode,
-                                attributes: unsafe {
-                                    slice::from_raw_parts(vbl.attributes, vbl.attributes_length)
-                                }
-                                .iter()
-                                .cloned()
-                                .collect(),
-                            })
-                            .collect(),
-                    },
-                    sample_count: desc.sample_count,
-                    sample_mask: desc.sample_mask,
-                    alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
-                },
-            }),
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace_desc.into_action(id)),
             None => (),
         };
-        id
+        Ok(id)
+    }
+
+    /// The error that the compilation of the render pipeline failed with,
+    /// if it did, for the `PipelineCreationStatus::Error` of its callback.
+    pub fn render_pipeline_error<B: GfxBackend>(
+        &self,
+        render_pipeline_id: id::RenderPipelineId,
+    ) -> Option<pipeline::PipelineError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (_device_guard, mut token) = hub.devices.read(&mut token);
+        let (pipeline_guard, _) = hub.render_pipelines.read(&mut token);
+        match pipeline_guard.get(render_pipeline_id)?.raw {
+            pipeline::PipelineRaw::Failed(ref error) => Some(error.clone()),
+            pipeline::PipelineRaw::Pending | pipeline::PipelineRaw::Ready(_) => None,
+        }
     }
 
     pub fn render_pipeline_destroy<B: GfxBackend>(&self, render_pipeline_id: id::RenderPipelineId) {
@@ -2049,20 +4104,24 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_id, layout_id) = {
             let (mut pipeline_guard, _) = hub.render_pipelines.write(&mut token);
-            let pipeline = &mut pipeline_guard[render_pipeline_id];
+            let pipeline = match pipeline_guard.get_mut(render_pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => {
+                    log::error!(
+                        "Destroying an invalid render pipeline {:?}",
+                        render_pipeline_id
+                    );
+                    return;
+                }
+            };
             pipeline.life_guard.ref_count.take();
             (pipeline.device_id.value, pipeline.layout_id.clone())
         };
 
-        let mut life_lock = device_guard[device_id].lock_life(&mut token);
-        life_lock
-            .suspected_resources
-            .render_pipelines
-            .push(render_pipeline_id);
-        life_lock
-            .suspected_resources
-            .pipeline_layouts
-            .push(layout_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.render_pipelines.push(render_pipeline_id);
+            suspected.pipeline_layouts.push(layout_id);
+        });
     }
 
     pub fn device_create_mesh_pipeline<B: GfxBackend>(
@@ -2070,7 +4129,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &pipeline::MeshPipelineDescriptor,
         id_in: Input<G, id::RenderPipelineId>,
-    ) -> id::RenderPipelineId {
+    ) -> Result<id::RenderPipelineId, pipeline::PipelineError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
@@ -2086,11 +4145,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             unsafe { slice::from_raw_parts(desc.color_states, desc.color_states_length) };
         let depth_stencil_state = unsafe { desc.depth_stencil_state.as_ref() };
 
-        let rasterizer = conv::map_rasterization_state_descriptor(
-            &unsafe { desc.rasterization_state.as_ref() }
-                .cloned()
-                .unwrap_or_default(),
-        );
+        let rasterization_state = unsafe { desc.rasterization_state.as_ref() }
+            .cloned()
+            .unwrap_or_default();
+        let rasterizer = conv::map_rasterization_state_descriptor(&rasterization_state);
 
         let blender = hal::pso::BlendDesc {
             logic_op: None, // TODO
@@ -2108,10 +4166,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         } else {
             Some(hal::pso::Multisampling {
                 rasterization_samples: sc,
-                sample_shading: None,
+                sample_shading: if desc.min_sample_shading > 0.0 {
+                    Some(desc.min_sample_shading)
+                } else {
+                    None
+                },
                 sample_mask: desc.sample_mask as u64,
                 alpha_coverage: desc.alpha_to_coverage_enabled,
-                alpha_to_one: false,
+                alpha_to_one: desc.alpha_to_one_enabled,
             })
         };
 
@@ -2124,11 +4186,48 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         };
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        device.validate_rasterization_state(&rasterization_state);
+        validate_blend_targets(&blender.targets, &device.downlevel)?;
+        assert!(
+            desc.min_sample_shading >= 0.0 && desc.min_sample_shading <= 1.0,
+            "Invalid min_sample_shading of {}; must be within 0.0 and 1.0",
+            desc.min_sample_shading
+        );
+        if desc.min_sample_shading > 0.0 {
+            assert!(
+                device
+                    .capabilities
+                    .contains(wgt::Capabilities::SAMPLE_SHADING),
+                "Per-sample shading is not supported by the adapter"
+            );
+        }
         let (raw_pipeline, layout_ref_count) = {
             let (pipeline_layout_guard, mut token) = hub.pipeline_layouts.read(&mut token);
-            let layout = &pipeline_layout_guard[desc.layout];
+            let layout = pipeline_layout_guard
+                .get(desc.layout)
+                .ok_or(InvalidIdError::PipelineLayout(desc.layout))?;
+            check_device(device_id, desc.layout, layout.device_id.value)?;
             let (shader_module_guard, _) = hub.shader_modules.read(&mut token);
+            let task_stage = unsafe { desc.task_stage.as_ref() };
+            let fragment_stage = unsafe { desc.fragment_stage.as_ref() };
+            let stages = task_stage
+                .into_iter()
+                .chain(iter::once(&desc.mesh_stage))
+                .chain(fragment_stage);
+            for stage in stages {
+                let shader_module = shader_module_guard
+                    .get(stage.module)
+                    .ok_or(InvalidIdError::ShaderModule(stage.module))?;
+                check_device(device_id, stage.module, shader_module.device_id.value)?;
+                check_shader_features(
+                    stage.module,
+                    shader_module.required_features,
+                    device.shader_features,
+                )?;
+            }
 
             let rp_key = RenderPassKey {
                 colors: color_states
@@ -2320,10 +4419,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             if ds.needs_stencil_reference() {
                 flags |= pipeline::PipelineFlags::STENCIL_REFERENCE;
             }
+            if ds.dynamic_stencil_masks {
+                flags |= pipeline::PipelineFlags::STENCIL_MASKS;
+            }
+        }
+        if rasterization_state.dynamic_depth_bias {
+            flags |= pipeline::PipelineFlags::DEPTH_BIAS;
         }
 
         let pipeline = pipeline::RenderPipeline {
-            raw: raw_pipeline,
+            raw: pipeline::PipelineRaw::Ready(raw_pipeline),
             layout_id: Stored {
                 value: desc.layout,
                 ref_count: layout_ref_count,
@@ -2334,39 +4439,51 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             },
             vertex_strides: Vec::new(),
             index_format: wgt::IndexFormat::Uint16,
+            strip_index_format: None,
+            topology: desc.primitive_topology,
             pass_context,
             flags,
             sample_count: sc,
             life_guard: LifeGuard::new(),
+            #[cfg(feature = "trace")]
+            trace_desc: trace::GraphicsPipelineDescriptor::Mesh(trace::MeshPipelineDescriptor {
+                layout: desc.layout,
+                task_stage: unsafe { desc.task_stage.as_ref() }
+                    .map(trace::ProgrammableStageDescriptor::new),
+                mesh_stage: trace::ProgrammableStageDescriptor::new(&desc.mesh_stage),
+                fragment_stage: unsafe { desc.fragment_stage.as_ref() }
+                    .map(trace::ProgrammableStageDescriptor::new),
+                primitive_topology: desc.primitive_topology,
+                rasterization_state: unsafe { desc.rasterization_state.as_ref() }.cloned(),
+                color_states: color_states.to_vec(),
+                depth_stencil_state: depth_stencil_state.cloned(),
+                sample_count: desc.sample_count,
+                sample_mask: desc.sample_mask,
+                alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
+                alpha_to_one_enabled: desc.alpha_to_one_enabled,
+                min_sample_shading: desc.min_sample_shading,
+            }),
         };
+        #[cfg(feature = "trace")]
+        let trace_desc = pipeline.trace_desc.clone();
 
         let id = hub
             .render_pipelines
             .register_identity(id_in, pipeline, &mut token);
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::RenderPipeline,
+            id,
+            ptr::null(),
+            0,
+        );
 
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateMeshPipeline {
-                id,
-                desc: trace::MeshPipelineDescriptor {
-                    layout: desc.layout,
-                    task_stage: unsafe { desc.task_stage.as_ref() }
-                        .map(trace::ProgrammableStageDescriptor::new),
-                    mesh_stage: trace::ProgrammableStageDescriptor::new(&desc.mesh_stage),
-                    fragment_stage: unsafe { desc.fragment_stage.as_ref() }
-                        .map(trace::ProgrammableStageDescriptor::new),
-                    primitive_topology: desc.primitive_topology,
-                    rasterization_state: unsafe { desc.rasterization_state.as_ref() }.cloned(),
-                    color_states: color_states.to_vec(),
-                    depth_stencil_state: depth_stencil_state.cloned(),
-                    sample_count: desc.sample_count,
-                    sample_mask: desc.sample_mask,
-                    alpha_to_coverage_enabled: desc.alpha_to_coverage_enabled,
-                },
-            }),
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace_desc.into_action(id)),
             None => (),
         };
-        id
+        Ok(id)
     }
 
     pub fn mesh_pipeline_destroy<B: GfxBackend>(&self, mesh_pipeline_id: id::RenderPipelineId) {
@@ -2376,20 +4493,21 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_id, layout_id) = {
             let (mut pipeline_guard, _) = hub.render_pipelines.write(&mut token);
-            let pipeline = &mut pipeline_guard[mesh_pipeline_id];
+            let pipeline = match pipeline_guard.get_mut(mesh_pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => {
+                    log::error!("Destroying an invalid mesh pipeline {:?}", mesh_pipeline_id);
+                    return;
+                }
+            };
             pipeline.life_guard.ref_count.take();
             (pipeline.device_id.value, pipeline.layout_id.clone())
         };
 
-        let mut life_lock = device_guard[device_id].lock_life(&mut token);
-        life_lock
-            .suspected_resources
-            .render_pipelines
-            .push(mesh_pipeline_id);
-        life_lock
-            .suspected_resources
-            .pipeline_layouts
-            .push(layout_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.render_pipelines.push(mesh_pipeline_id);
+            suspected.pipeline_layouts.push(layout_id);
+        });
     }
 
 
@@ -2398,17 +4516,108 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         device_id: id::DeviceId,
         desc: &pipeline::ComputePipelineDescriptor,
         id_in: Input<G, id::ComputePipelineId>,
-    ) -> id::ComputePipelineId {
+    ) -> Result<id::ComputePipelineId, pipeline::PipelineError> {
+        self.create_compute_pipeline::<B>(device_id, desc, id_in, None)
+    }
+
+    /// Create a compute pipeline, deferring the native pipeline compilation.
+    ///
+    /// See `device_create_render_pipeline_async`.
+    pub fn device_create_compute_pipeline_async<B: GfxBackend>(
+        &'static self,
+        device_id: id::DeviceId,
+        desc: &pipeline::ComputePipelineDescriptor,
+        id_in: Input<G, id::ComputePipelineId>,
+        callback: pipeline::PipelineCreatedCallback,
+        userdata: *mut u8,
+    ) -> Result<id::ComputePipelineId, pipeline::PipelineError>
+    where
+        Self: Sync,
+    {
+        let callback = pipeline::PipelineCallback { callback, userdata };
+        let id = self.create_compute_pipeline::<B>(device_id, desc, id_in, Some(callback))?;
+        self.spawn_pipeline_compiler::<B>(device_id);
+        Ok(id)
+    }
+
+    fn create_compute_pipeline<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        desc: &pipeline::ComputePipelineDescriptor,
+        id_in: Input<G, id::ComputePipelineId>,
+        callback: Option<pipeline::PipelineCallback>,
+    ) -> Result<id::ComputePipelineId, pipeline::PipelineError> {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        assert!(
+            device.downlevel.compute_shaders,
+            "This adapter does not support compute shaders"
+        );
+        let mut pending = None;
         let (raw_pipeline, layout_ref_count) = {
             let (pipeline_layout_guard, mut token) = hub.pipeline_layouts.read(&mut token);
-            let layout = &pipeline_layout_guard[desc.layout];
+            let layout = pipeline_layout_guard
+                .get(desc.layout)
+                .ok_or(InvalidIdError::PipelineLayout(desc.layout))?;
+            check_device(device_id, desc.layout, layout.device_id.value)?;
             let pipeline_stage = &desc.compute_stage;
-            let (shader_module_guard, _) = hub.shader_modules.read(&mut token);
+            let (shader_module_guard, mut token) = hub.shader_modules.read(&mut token);
+            let shader_module = shader_module_guard
+                .get(pipeline_stage.module)
+                .ok_or(InvalidIdError::ShaderModule(pipeline_stage.module))?;
+            check_device(
+                device_id,
+                pipeline_stage.module,
+                shader_module.device_id.value,
+            )?;
+            check_shader_features(
+                pipeline_stage.module,
+                shader_module.required_features,
+                device.shader_features,
+            )?;
+            {
+                let (bind_group_layout_guard, _) = hub.bind_group_layouts.read(&mut token);
+                let entry_point_name = unsafe { ffi::CStr::from_ptr(pipeline_stage.entry_point) }
+                    .to_str()
+                    .unwrap();
+                if let Some(ref module) = shader_module_guard[pipeline_stage.module].module {
+                    let pairs =
+                        texture_sampler_pairs(module, entry_point_name, ExecutionModel::GLCompute);
+                    validate_texture_sampler_pairs(&pairs, |(group, binding)| {
+                        let bgl_id = layout.bind_group_layout_ids.get(group as usize)?;
+                        bind_group_layout_guard[bgl_id.value].entries.get(&binding)
+                    })?;
+                    let used = used_bindings(module, entry_point_name, ExecutionModel::GLCompute);
+                    validate_binding_visibility(
+                        &used,
+                        wgt::ShaderStage::COMPUTE,
+                        layout_entries(layout, &*bind_group_layout_guard),
+                    )?;
+                    if let Err(e) = validate_workgroup_storage(
+                        module,
+                        entry_point_name,
+                        device.limits.max_compute_shared_memory_size,
+                    ) {
+                        panic!("Invalid compute shader workgroup storage: {:?}", e);
+                    }
+                }
+            }
+            let (pipeline_cache_guard, _) = hub.pipeline_caches.read(&mut token);
+            let cache = match desc.cache {
+                Some(cache_id) => {
+                    let cache = pipeline_cache_guard
+                        .get(cache_id)
+                        .ok_or(InvalidIdError::PipelineCache(cache_id))?;
+                    check_device(device_id, cache_id, cache.device_id.value)?;
+                    Some(&cache.raw)
+                }
+                None => None,
+            };
 
             let entry_point_name = unsafe { ffi::CStr::from_ptr(pipeline_stage.entry_point) }
                 .to_str()
@@ -2424,29 +4633,36 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 }
             }
 
-            let shader = hal::pso::EntryPoint::<B> {
-                entry: entry_point_name, // TODO
-                module: &shader_module.raw,
-                specialization: hal::pso::Specialization::EMPTY,
-            };
-
-            // TODO
-            let flags = hal::pso::PipelineCreationFlags::empty();
-            // TODO
-            let parent = hal::pso::BasePipeline::None;
-
-            let pipeline_desc = hal::pso::ComputePipelineDesc {
-                shader,
-                layout: &layout.raw,
-                flags,
-                parent,
-            };
-
-            let pipeline = unsafe {
-                device
-                    .raw
-                    .create_compute_pipeline(&pipeline_desc, None)
-                    .unwrap()
+            let pipeline = match callback {
+                Some(callback) => {
+                    pending = Some(pipeline::PendingComputePipeline {
+                        layout_id: desc.layout,
+                        cache_id: desc.cache,
+                        stage: pipeline::PendingStage {
+                            module: pipeline_stage.module,
+                            entry_point: entry_point_name.to_string(),
+                        },
+                        callback,
+                    });
+                    pipeline::PipelineRaw::Pending
+                }
+                None => {
+                    let raw = device
+                        .create_raw_compute_pipeline(
+                            &layout.raw,
+                            (entry_point_name, &shader_module.raw),
+                            cache,
+                        )
+                        .map_err(|error| {
+                            let stage = pipeline::StageContext::new(
+                                wgt::ShaderStage::COMPUTE,
+                                entry_point_name,
+                                shader_module,
+                            );
+                            pipeline::PipelineError::from_hal(error, &[stage])
+                        })?;
+                    pipeline::PipelineRaw::Ready(raw)
+                }
             };
             (pipeline, layout.life_guard.add_ref())
         };
@@ -2462,23 +4678,53 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 ref_count: device.life_guard.add_ref(),
             },
             life_guard: LifeGuard::new(),
+            #[cfg(feature = "trace")]
+            trace_desc: trace::ComputePipelineDescriptor {
+                layout: desc.layout,
+                compute_stage: trace::ProgrammableStageDescriptor::new(&desc.compute_stage),
+            },
         };
+        #[cfg(feature = "trace")]
+        let trace_desc = pipeline.trace_desc.clone();
         let id = hub
             .compute_pipelines
             .register_identity(id_in, pipeline, &mut token);
+        if let Some(job) = pending {
+            device.pipeline_queue.pending.lock().compute.insert(id, job);
+        }
+        #[cfg(feature = "resource-events")]
+        device.resource_events.lock().created(
+            events::ResourceKind::ComputePipeline,
+            id,
+            ptr::null(),
+            0,
+        );
 
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(trace::Action::CreateComputePipeline {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(trace::Action::CreateComputePipeline {
                 id,
-                desc: trace::ComputePipelineDescriptor {
-                    layout: desc.layout,
-                    compute_stage: trace::ProgrammableStageDescriptor::new(&desc.compute_stage),
-                },
+                desc: trace_desc,
             }),
             None => (),
         };
-        id
+        Ok(id)
+    }
+
+    /// The error that the compilation of the compute pipeline failed with,
+    /// if it did, for the `PipelineCreationStatus::Error` of its callback.
+    pub fn compute_pipeline_error<B: GfxBackend>(
+        &self,
+        compute_pipeline_id: id::ComputePipelineId,
+    ) -> Option<pipeline::PipelineError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (_device_guard, mut token) = hub.devices.read(&mut token);
+        let (pipeline_guard, _) = hub.compute_pipelines.read(&mut token);
+        match pipeline_guard.get(compute_pipeline_id)?.raw {
+            pipeline::PipelineRaw::Failed(ref error) => Some(error.clone()),
+            pipeline::PipelineRaw::Pending | pipeline::PipelineRaw::Ready(_) => None,
+        }
     }
 
     pub fn compute_pipeline_destroy<B: GfxBackend>(
@@ -2491,52 +4737,682 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_id, layout_id) = {
             let (mut pipeline_guard, _) = hub.compute_pipelines.write(&mut token);
-            let pipeline = &mut pipeline_guard[compute_pipeline_id];
+            let pipeline = match pipeline_guard.get_mut(compute_pipeline_id) {
+                Some(pipeline) => pipeline,
+                None => {
+                    log::error!(
+                        "Destroying an invalid compute pipeline {:?}",
+                        compute_pipeline_id
+                    );
+                    return;
+                }
+            };
             pipeline.life_guard.ref_count.take();
             (pipeline.device_id.value, pipeline.layout_id.clone())
         };
 
-        let mut life_lock = device_guard[device_id].lock_life(&mut token);
-        life_lock
-            .suspected_resources
-            .compute_pipelines
-            .push(compute_pipeline_id);
-        life_lock
-            .suspected_resources
-            .pipeline_layouts
-            .push(layout_id);
+        device_guard[device_id].release(self, &mut token, |suspected| {
+            suspected.compute_pipelines.push(compute_pipeline_id);
+            suspected.pipeline_layouts.push(layout_id);
+        });
     }
 
-    pub fn device_create_swap_chain<B: GfxBackend>(
+    /// Choose what happens when a pass uses a pipeline that is still pending.
+    pub fn device_set_pending_pipeline_policy<B: GfxBackend>(
         &self,
         device_id: id::DeviceId,
-        surface_id: id::SurfaceId,
-        desc: &wgt::SwapChainDescriptor,
-    ) -> id::SwapChainId {
-        fn validate_swap_chain_descriptor(
-            config: &mut hal::window::SwapchainConfig,
-            caps: &hal::window::SurfaceCapabilities,
-        ) {
-            let width = config.extent.width;
-            let height = config.extent.height;
-            if width < caps.extents.start().width
-                || width > caps.extents.end().width
-                || height < caps.extents.start().height
-                || height > caps.extents.end().height
-            {
-                log::warn!(
-                    "Requested size {}x{} is outside of the supported range: {:?}",
-                    width,
-                    height,
-                    caps.extents
-                );
+        policy: pipeline::PendingPipelinePolicy,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!("Setting the pipeline policy of an invalid device {:?}", device_id);
+                return;
             }
-            if !caps.present_modes.contains(config.present_mode) {
-                log::warn!(
-                    "Surface does not support present mode: {:?}, falling back to {:?}",
-                    config.present_mode,
-                    hal::window::PresentMode::FIFO
-                );
+        };
+        device.pipeline_queue.pending.lock().policy = policy;
+    }
+
+    /// Start a thread compiling the pending pipelines of the device,
+    /// unless one is running already.
+    ///
+    /// The thread exits once the queue is empty.
+    fn spawn_pipeline_compiler<B: GfxBackend>(&'static self, device_id: id::DeviceId)
+    where
+        Self: Sync,
+    {
+        let queue = {
+            let hub = B::hub(self);
+            let mut token = Token::root();
+            let (device_guard, _) = hub.devices.read(&mut token);
+            match device_guard.get(device_id) {
+                Some(device) => Arc::clone(&device.pipeline_queue),
+                None => return,
+            }
+        };
+        {
+            let mut pending = queue.pending.lock();
+            if pending.compiler_running {
+                return;
+            }
+            pending.compiler_running = true;
+        }
+        let worker_queue = Arc::clone(&queue);
+        let spawned = thread::Builder::new()
+            .name("wgpu pipeline compiler".to_string())
+            .spawn(move || self.compile_pending_pipelines::<B>(device_id, &worker_queue));
+        if let Err(e) = spawned {
+            // The pipelines are still compiled by the passes using them.
+            log::warn!("Unable to spawn the pipeline compiler thread: {:?}", e);
+            queue.pending.lock().compiler_running = false;
+        }
+    }
+
+    /// Compile the pipelines of the `queue` until it's empty.
+    fn compile_pending_pipelines<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        queue: &pipeline::PipelineQueue,
+    ) {
+        loop {
+            let mut pending = queue.pending.lock();
+            if let Some(&id) = pending.render.keys().next() {
+                let job = pending.take_render(id).unwrap();
+                drop(pending);
+                self.compile_render_pipeline::<B>(device_id, queue, id, job);
+            } else if let Some(&id) = pending.compute.keys().next() {
+                let job = pending.take_compute(id).unwrap();
+                drop(pending);
+                self.compile_compute_pipeline::<B>(device_id, queue, id, job);
+            } else {
+                pending.compiler_running = false;
+                break;
+            }
+        }
+    }
+
+    /// Make sure the pipelines used by a pass are compiled, compiling them
+    /// on the spot or waiting for the compiler thread as needed, unless
+    /// the policy of the device is to reject pending pipelines.
+    ///
+    /// Must be called without any locks held, since it may block.
+    pub(crate) fn wait_for_pipelines<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        render_pipelines: &[id::RenderPipelineId],
+        compute_pipelines: &[id::ComputePipelineId],
+    ) -> Result<(), command::CommandEncoderError> {
+        if render_pipelines.is_empty() && compute_pipelines.is_empty() {
+            return Ok(());
+        }
+        let hub = B::hub(self);
+        let queue = {
+            let mut token = Token::root();
+            let (device_guard, _) = hub.devices.read(&mut token);
+            Arc::clone(&device_guard[device_id].pipeline_queue)
+        };
+
+        let policy = queue.pending.lock().policy;
+        if policy == pipeline::PendingPipelinePolicy::Wait {
+            for &id in render_pipelines {
+                let mut pending = queue.pending.lock();
+                if let Some(job) = pending.take_render(id) {
+                    drop(pending);
+                    self.compile_render_pipeline::<B>(device_id, &queue, id, job);
+                    continue;
+                }
+                while pending.compiling_render.contains(&id) {
+                    queue.compiled.wait(&mut pending);
+                }
+            }
+            for &id in compute_pipelines {
+                let mut pending = queue.pending.lock();
+                if let Some(job) = pending.take_compute(id) {
+                    drop(pending);
+                    self.compile_compute_pipeline::<B>(device_id, &queue, id, job);
+                    continue;
+                }
+                while pending.compiling_compute.contains(&id) {
+                    queue.compiled.wait(&mut pending);
+                }
+            }
+        }
+
+        let mut token = Token::root();
+        let (_device_guard, mut token) = hub.devices.read(&mut token);
+        let (compute_pipeline_guard, mut token) = hub.compute_pipelines.read(&mut token);
+        let (render_pipeline_guard, _) = hub.render_pipelines.read(&mut token);
+        for &id in render_pipelines {
+            let pipeline = render_pipeline_guard
+                .get(id)
+                .ok_or(command::CommandEncoderError::InvalidRenderPipeline(id))?;
+            check_device(device_id, id, pipeline.device_id.value)?;
+            match pipeline.raw {
+                pipeline::PipelineRaw::Pending => {
+                    return Err(command::CommandEncoderError::RenderPipelineNotReady(id))
+                }
+                pipeline::PipelineRaw::Failed(_) => {
+                    return Err(command::CommandEncoderError::RenderPipelineFailed(id))
+                }
+                pipeline::PipelineRaw::Ready(_) => {}
+            }
+        }
+        for &id in compute_pipelines {
+            let pipeline = compute_pipeline_guard
+                .get(id)
+                .ok_or(command::CommandEncoderError::InvalidComputePipeline(id))?;
+            check_device(device_id, id, pipeline.device_id.value)?;
+            match pipeline.raw {
+                pipeline::PipelineRaw::Pending => {
+                    return Err(command::CommandEncoderError::ComputePipelineNotReady(id))
+                }
+                pipeline::PipelineRaw::Failed(_) => {
+                    return Err(command::CommandEncoderError::ComputePipelineFailed(id))
+                }
+                pipeline::PipelineRaw::Ready(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Compile the pending pipelines using a shader module about to be destroyed,
+    /// and wait for the ones being compiled by other threads.
+    fn flush_pipelines_using_module<B: GfxBackend>(&self, module_id: id::ShaderModuleId) {
+        let hub = B::hub(self);
+        let (device_id, queue) = {
+            let mut token = Token::root();
+            let (device_guard, mut token) = hub.devices.read(&mut token);
+            let (shader_module_guard, _) = hub.shader_modules.read(&mut token);
+            let device_id = match shader_module_guard.get(module_id) {
+                Some(module) => module.device_id.value,
+                None => return,
+            };
+            (
+                device_id,
+                Arc::clone(&device_guard[device_id].pipeline_queue),
+            )
+        };
+
+        let (render, compute) = queue.pending.lock().take_using_module(module_id);
+        for (id, job) in render {
+            self.compile_render_pipeline::<B>(device_id, &queue, id, job);
+        }
+        for (id, job) in compute {
+            self.compile_compute_pipeline::<B>(device_id, &queue, id, job);
+        }
+        let mut pending = queue.pending.lock();
+        while pending.is_compiling() {
+            queue.compiled.wait(&mut pending);
+        }
+    }
+
+    /// Create the descriptor sets needed to bind groups with unsized dynamic
+    /// bindings at the given dynamic offsets, see `BindGroup::derived_sets`.
+    ///
+    /// Invalid bind groups and offsets are skipped, the pass reports them.
+    pub(crate) fn prepare_derived_bind_groups<B: GfxBackend>(
+        &self,
+        bind_groups: &[(id::BindGroupId, Vec<wgt::DynamicOffset>)],
+    ) -> Result<(), AllocationError> {
+        if bind_groups.is_empty() {
+            return Ok(());
+        }
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let (bind_group_layout_guard, mut token) = hub.bind_group_layouts.read(&mut token);
+        let (mut bind_group_guard, mut token) = hub.bind_groups.write(&mut token);
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+
+        for &(bind_group_id, ref offsets) in bind_groups {
+            let bind_group = match bind_group_guard.get_mut(bind_group_id) {
+                Some(bind_group) => bind_group,
+                None => continue,
+            };
+            if !bind_group.needs_derived_set(offsets)
+                || bind_group.derived_sets.contains_key(offsets)
+            {
+                continue;
+            }
+            let sizes = match offsets
+                .iter()
+                .zip(&bind_group.dynamic_bindings)
+                .map(|(&offset, binding)| binding.bound_size(offset))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(sizes) => sizes,
+                None => continue,
+            };
+            let device = &device_guard[bind_group.device_id.value];
+            let layout = &bind_group_layout_guard[bind_group.layout_id];
+
+            let desc_set = device.allocate_descriptor_set(layout, "derived bind group")?;
+            let is_derived = |binding: u32| {
+                bind_group
+                    .dynamic_bindings
+                    .iter()
+                    .any(|dynamic| dynamic.binding == binding && dynamic.size.is_none())
+            };
+            let copies = layout
+                .entries
+                .keys()
+                .filter(|&&binding| !is_derived(binding))
+                .map(|&binding| hal::pso::DescriptorSetCopy {
+                    src_set: bind_group.raw.raw(),
+                    src_binding: binding,
+                    src_array_offset: 0,
+                    dst_set: desc_set.raw(),
+                    dst_binding: binding,
+                    dst_array_offset: 0,
+                    count: 1,
+                })
+                .collect::<Vec<_>>();
+            let writes = bind_group
+                .dynamic_bindings
+                .iter()
+                .zip(sizes)
+                .filter(|&(dynamic, _)| dynamic.size.is_none())
+                .map(|(dynamic, size)| hal::pso::DescriptorSetWrite {
+                    set: desc_set.raw(),
+                    binding: dynamic.binding,
+                    array_offset: 0,
+                    descriptors: iter::once(hal::pso::Descriptor::Buffer(
+                        &buffer_guard[dynamic.buffer_id].raw,
+                        hal::buffer::SubRange {
+                            offset: dynamic.offset,
+                            size: Some(size),
+                        },
+                    )),
+                })
+                .collect::<Vec<_>>();
+            unsafe {
+                device.raw.copy_descriptor_sets(copies);
+                device.raw.write_descriptor_sets(writes);
+            }
+            bind_group.derived_sets.insert(offsets.clone(), desc_set);
+        }
+        Ok(())
+    }
+
+    /// Create the ring of the inline uniform `writes` of a render pass, with
+    /// the copies of the bind groups pointing to it, if there are any writes.
+    ///
+    /// Writes that the pass rejects are skipped.
+    pub(crate) fn prepare_inline_uniforms<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        writes: &[(command::InlineSetKey, u32)],
+    ) -> Result<Option<command::InlineUniformRing<B>>, AllocationError> {
+        if writes.is_empty() {
+            return Ok(None);
+        }
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let (bind_group_layout_guard, mut token) = hub.bind_group_layouts.read(&mut token);
+        let (bind_group_guard, _) = hub.bind_groups.read(&mut token);
+        let device = &device_guard[device_id];
+
+        let mut slot_sizes = FastHashMap::default();
+        let mut size = 0;
+        for &(ref key, binding) in writes {
+            let dynamic = match bind_group_guard
+                .get(key.bind_group_id)
+                .and_then(|bind_group| {
+                    bind_group
+                        .dynamic_bindings
+                        .iter()
+                        .find(|dynamic| dynamic.binding == binding)
+                }) {
+                Some(dynamic) => dynamic,
+                None => continue,
+            };
+            if let Some(slot_size) = dynamic.size {
+                command::suballocate(&mut size, slot_size.get(), dynamic.alignment);
+                slot_sizes.insert((key.bind_group_id, binding), slot_size.get());
+            }
+        }
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let mut raw = unsafe {
+            device
+                .raw
+                .create_buffer(
+                    size,
+                    hal::buffer::Usage::UNIFORM | hal::buffer::Usage::TRANSFER_DST,
+                )
+                .unwrap()
+        };
+        let requirements = unsafe { device.raw.get_buffer_requirements(&raw) };
+        let memory = match device.allocate_memory(
+            &requirements,
+            gfx_memory::MemoryUsage::Private,
+            gfx_memory::Kind::General,
+            "inline uniform ring",
+        ) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { device.raw.destroy_buffer(raw) };
+                return Err(e);
+            }
+        };
+        unsafe {
+            device
+                .raw
+                .set_buffer_name(&mut raw, "<inline_uniform_ring>");
+            device
+                .raw
+                .bind_buffer_memory(memory.memory(), memory.segment().offset, &mut raw)
+                .unwrap();
+        }
+
+        let mut sets = FastHashMap::default();
+        for &(ref key, _) in writes {
+            if sets.contains_key(key) {
+                continue;
+            }
+            let bind_group = match bind_group_guard.get(key.bind_group_id) {
+                Some(bind_group) => bind_group,
+                None => continue,
+            };
+            let slot_size = |binding: u32| slot_sizes.get(&(key.bind_group_id, binding)).cloned();
+            if key
+                .bindings
+                .iter()
+                .any(|&binding| slot_size(binding).is_none())
+            {
+                continue;
+            }
+            let layout = &bind_group_layout_guard[bind_group.layout_id];
+
+            let desc_set = match device.allocate_descriptor_set(layout, "inline uniform ring") {
+                Ok(desc_set) => desc_set,
+                Err(e) => {
+                    // The ring is freed with the sets allocated so far.
+                    let ring = command::InlineUniformRing {
+                        raw,
+                        memory,
+                        size,
+                        sets,
+                    };
+                    device
+                        .life_tracker
+                        .lock()
+                        .retire_inline_uniforms(iter::once(ring), 0);
+                    return Err(e);
+                }
+            };
+            let source = bind_group.raw_for_offsets(&key.offsets);
+            let copies = layout
+                .entries
+                .keys()
+                .filter(|&&binding| key.bindings.binary_search(&binding).is_err())
+                .map(|&binding| hal::pso::DescriptorSetCopy {
+                    src_set: source,
+                    src_binding: binding,
+                    src_array_offset: 0,
+                    dst_set: desc_set.raw(),
+                    dst_binding: binding,
+                    dst_array_offset: 0,
+                    count: 1,
+                })
+                .collect::<Vec<_>>();
+            // The dynamic offsets of the redirected bindings are the slots.
+            let writes = key
+                .bindings
+                .iter()
+                .map(|&binding| hal::pso::DescriptorSetWrite {
+                    set: desc_set.raw(),
+                    binding,
+                    array_offset: 0,
+                    descriptors: iter::once(hal::pso::Descriptor::Buffer(
+                        &raw,
+                        hal::buffer::SubRange {
+                            offset: 0,
+                            size: slot_size(binding),
+                        },
+                    )),
+                })
+                .collect::<Vec<_>>();
+            unsafe {
+                device.raw.copy_descriptor_sets(copies);
+                device.raw.write_descriptor_sets(writes);
+            }
+            sets.insert(key.clone(), desc_set);
+        }
+
+        Ok(Some(command::InlineUniformRing {
+            raw,
+            memory,
+            size,
+            sets,
+        }))
+    }
+
+    fn compile_render_pipeline<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        queue: &pipeline::PipelineQueue,
+        pipeline_id: id::RenderPipelineId,
+        job: pipeline::PendingRenderPipeline,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                drop(device_guard);
+                queue.pending.lock().finish_render(pipeline_id);
+                queue.compiled.notify_all();
+                job.callback
+                    .fire(pipeline::PipelineCreationStatus::DeviceLost);
+                return;
+            }
+        };
+
+        let result = {
+            let (pipeline_layout_guard, mut token) = hub.pipeline_layouts.read(&mut token);
+            let (shader_module_guard, mut token) = hub.shader_modules.read(&mut token);
+            let (pipeline_cache_guard, _) = hub.pipeline_caches.read(&mut token);
+            // The cache may be gone by now, which only affects compilation time.
+            let cache = job
+                .cache_id
+                .and_then(|cache_id| pipeline_cache_guard.get(cache_id))
+                .map(|cache| &cache.raw);
+            let vertex = shader_module_guard
+                .get(job.vertex.module)
+                .map(|module| (job.vertex.entry_point.as_str(), module));
+            let fragment = match job.fragment {
+                Some(ref stage) => shader_module_guard
+                    .get(stage.module)
+                    .map(|module| Some((stage.entry_point.as_str(), module))),
+                None => Some(None),
+            };
+            match (pipeline_layout_guard.get(job.layout_id), vertex, fragment) {
+                (Some(layout), Some(vertex), Some(fragment)) => device
+                    .create_raw_render_pipeline(
+                        job.state,
+                        &layout.raw,
+                        (vertex.0, &vertex.1.raw),
+                        fragment.map(|(entry, module)| (entry, &module.raw)),
+                        cache,
+                    )
+                    .map_err(|error| {
+                        let stages = iter::once(pipeline::StageContext::new(
+                            wgt::ShaderStage::VERTEX,
+                            vertex.0,
+                            vertex.1,
+                        ))
+                        .chain(fragment.map(|(entry_point, module)| {
+                            pipeline::StageContext::new(
+                                wgt::ShaderStage::FRAGMENT,
+                                entry_point,
+                                module,
+                            )
+                        }))
+                        .collect::<Vec<_>>();
+                        pipeline::PipelineError::from_hal(error, &stages)
+                    }),
+                // The pipeline was dropped along with its layout.
+                (None, _, _) => Err(pipeline::PipelineError::InvalidId(
+                    InvalidIdError::PipelineLayout(job.layout_id),
+                )),
+                (_, None, _) => Err(pipeline::PipelineError::InvalidId(
+                    InvalidIdError::ShaderModule(job.vertex.module),
+                )),
+                (_, _, None) => Err(pipeline::PipelineError::InvalidId(
+                    InvalidIdError::ShaderModule(job.fragment.as_ref().unwrap().module),
+                )),
+            }
+        };
+        let status = match result {
+            Ok(_) => pipeline::PipelineCreationStatus::Success,
+            Err(ref error) => {
+                log::error!(
+                    "Failed to compile render pipeline {:?}: {:?}",
+                    pipeline_id,
+                    error
+                );
+                pipeline::PipelineCreationStatus::Error
+            }
+        };
+
+        {
+            let (mut pipeline_guard, _) = hub.render_pipelines.write(&mut token);
+            match (pipeline_guard.get_mut(pipeline_id), result) {
+                (Some(pipeline), Ok(raw)) => pipeline.raw = pipeline::PipelineRaw::Ready(raw),
+                (Some(pipeline), Err(error)) => pipeline.raw = pipeline::PipelineRaw::Failed(error),
+                (None, Ok(raw)) => unsafe { device.raw.destroy_graphics_pipeline(raw) },
+                (None, Err(_)) => {}
+            }
+        }
+        drop(device_guard);
+
+        queue.pending.lock().finish_render(pipeline_id);
+        queue.compiled.notify_all();
+        job.callback.fire(status);
+    }
+
+    fn compile_compute_pipeline<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        queue: &pipeline::PipelineQueue,
+        pipeline_id: id::ComputePipelineId,
+        job: pipeline::PendingComputePipeline,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                drop(device_guard);
+                queue.pending.lock().finish_compute(pipeline_id);
+                queue.compiled.notify_all();
+                job.callback
+                    .fire(pipeline::PipelineCreationStatus::DeviceLost);
+                return;
+            }
+        };
+
+        let result = {
+            let (pipeline_layout_guard, mut token) = hub.pipeline_layouts.read(&mut token);
+            let (shader_module_guard, mut token) = hub.shader_modules.read(&mut token);
+            let (pipeline_cache_guard, _) = hub.pipeline_caches.read(&mut token);
+            let cache = job
+                .cache_id
+                .and_then(|cache_id| pipeline_cache_guard.get(cache_id))
+                .map(|cache| &cache.raw);
+            match (
+                pipeline_layout_guard.get(job.layout_id),
+                shader_module_guard.get(job.stage.module),
+            ) {
+                (Some(layout), Some(module)) => device
+                    .create_raw_compute_pipeline(
+                        &layout.raw,
+                        (&job.stage.entry_point, &module.raw),
+                        cache,
+                    )
+                    .map_err(|error| {
+                        let stage = pipeline::StageContext::new(
+                            wgt::ShaderStage::COMPUTE,
+                            &job.stage.entry_point,
+                            module,
+                        );
+                        pipeline::PipelineError::from_hal(error, &[stage])
+                    }),
+                (None, _) => Err(pipeline::PipelineError::InvalidId(
+                    InvalidIdError::PipelineLayout(job.layout_id),
+                )),
+                (_, None) => Err(pipeline::PipelineError::InvalidId(
+                    InvalidIdError::ShaderModule(job.stage.module),
+                )),
+            }
+        };
+        let status = match result {
+            Ok(_) => pipeline::PipelineCreationStatus::Success,
+            Err(ref error) => {
+                log::error!(
+                    "Failed to compile compute pipeline {:?}: {:?}",
+                    pipeline_id,
+                    error
+                );
+                pipeline::PipelineCreationStatus::Error
+            }
+        };
+
+        {
+            let (mut pipeline_guard, _) = hub.compute_pipelines.write(&mut token);
+            match (pipeline_guard.get_mut(pipeline_id), result) {
+                (Some(pipeline), Ok(raw)) => pipeline.raw = pipeline::PipelineRaw::Ready(raw),
+                (Some(pipeline), Err(error)) => pipeline.raw = pipeline::PipelineRaw::Failed(error),
+                (None, Ok(raw)) => unsafe { device.raw.destroy_compute_pipeline(raw) },
+                (None, Err(_)) => {}
+            }
+        }
+        drop(device_guard);
+
+        queue.pending.lock().finish_compute(pipeline_id);
+        queue.compiled.notify_all();
+        job.callback.fire(status);
+    }
+
+    pub fn device_create_swap_chain<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        surface_id: id::SurfaceId,
+        desc: &wgt::SwapChainDescriptor,
+    ) -> Result<id::SwapChainId, InvalidIdError> {
+        fn validate_swap_chain_descriptor(
+            config: &mut hal::window::SwapchainConfig,
+            caps: &hal::window::SurfaceCapabilities,
+        ) {
+            let width = config.extent.width;
+            let height = config.extent.height;
+            if width < caps.extents.start().width
+                || width > caps.extents.end().width
+                || height < caps.extents.start().height
+                || height > caps.extents.end().height
+            {
+                log::warn!(
+                    "Requested size {}x{} is outside of the supported range: {:?}",
+                    width,
+                    height,
+                    caps.extents
+                );
+            }
+            if !caps.present_modes.contains(config.present_mode) {
+                log::warn!(
+                    "Surface does not support present mode: {:?}, falling back to {:?}",
+                    config.present_mode,
+                    hal::window::PresentMode::FIFO
+                );
                 config.present_mode = hal::window::PresentMode::FIFO;
             }
         }
@@ -2549,8 +5425,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let (adapter_guard, mut token) = hub.adapters.read(&mut token);
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (mut swap_chain_guard, _) = hub.swap_chains.write(&mut token);
-        let device = &device_guard[device_id];
-        let surface = &mut surface_guard[surface_id];
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        let surface = surface_guard
+            .get_mut(surface_id)
+            .ok_or(InvalidIdError::Surface(surface_id))?;
 
         let (caps, formats) = {
             let suf = B::get_surface_mut(surface);
@@ -2570,7 +5450,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .min(*caps.image_count.end());
         let mut config =
             swap_chain::swap_chain_descriptor_to_hal(&desc, num_frames, device.private_features);
-        if let Some(formats) = formats {
+        if let Some(ref formats) = formats {
             assert!(
                 formats.contains(&config.format),
                 "Requested format {:?} is not in supported list: {:?}",
@@ -2578,23 +5458,43 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 formats
             );
         }
+        let surface_formats =
+            swap_chain::surface_formats(formats.as_deref(), device.private_features);
+        assert!(
+            surface_formats
+                .iter()
+                .any(|sf| sf.color_space == desc.color_space),
+            "Requested color space {:?} is not in supported list: {:?}",
+            desc.color_space,
+            surface_formats
+        );
         validate_swap_chain_descriptor(&mut config, &caps);
 
+        #[cfg(any(target_os = "ios", target_os = "macos"))]
+        let adopted_layer = surface
+            .adopted_layer
+            .map(|layer| (layer, unsafe { layer.save() }));
         unsafe {
             B::get_surface_mut(surface)
                 .configure_swapchain(&device.raw, config)
                 .unwrap();
         }
+        #[cfg(any(target_os = "ios", target_os = "macos"))]
+        unsafe {
+            if let Some((layer, properties)) = adopted_layer {
+                layer.restore(properties);
+            }
+        }
 
         let sc_id = surface_id.to_swap_chain_id(B::VARIANT);
         if let Some(sc) = swap_chain_guard.remove(sc_id) {
             unsafe {
-                device.raw.destroy_semaphore(sc.semaphore);
+                sc.destroy_semaphores(&device.raw);
             }
         }
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(Action::CreateSwapChain {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(Action::CreateSwapChain {
                 id: sc_id,
                 desc: desc.clone(),
             }),
@@ -2609,12 +5509,14 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             },
             desc: desc.clone(),
             num_frames,
-            semaphore: device.raw.create_semaphore().unwrap(),
+            free_semaphores: Vec::new(),
             acquired_view_id: None,
+            acquired_frame: 0,
             acquired_framebuffers: Vec::new(),
+            frame_sync: swap_chain::FrameSync::new(),
         };
         swap_chain_guard.insert(sc_id, swap_chain);
-        sc_id
+        Ok(sc_id)
     }
 
     #[cfg(feature = "replay")]
@@ -2624,67 +5526,480 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        let device = &device_guard[device_id];
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!("Maintaining the ids of an invalid device {:?}", device_id);
+                return;
+            }
+        };
         device.lock_life(&mut token).triage_suspected(
             self,
             &device.trackers,
             #[cfg(feature = "trace")]
-            None,
+            &Mutex::new(None),
             &mut token,
         );
     }
 
-    pub fn device_poll<B: GfxBackend>(&self, device_id: id::DeviceId, force_wait: bool) {
+    pub fn device_poll<B: GfxBackend>(&self, device_id: id::DeviceId, force_wait: bool) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        #[cfg(feature = "resource-events")]
+        let event_batch;
+        let (callbacks, readbacks, work_done, lost_hook) = {
+            let (device_guard, mut token) = hub.devices.read(&mut token);
+            let device = match device_guard.get(device_id) {
+                Some(device) => device,
+                None => {
+                    log::error!("Polling an invalid device {:?}", device_id);
+                    return;
+                }
+            };
+            let result = device.maintain(self, force_wait, &mut token);
+            #[cfg(feature = "resource-events")]
+            {
+                event_batch = device.resource_events.lock().take();
+            }
+            result
+        };
+        if let Some(hook) = lost_hook {
+            hook.fire(device_id, DeviceLostReason::Timeout);
+        }
+        fire_map_callbacks(callbacks);
+        fire_read_callbacks(readbacks);
+        fire_work_done_callbacks(work_done);
+        #[cfg(feature = "resource-events")]
+        {
+            if let Some(batch) = event_batch {
+                batch.fire();
+            }
+        }
+    }
+
+    /// Register a callback to be called on creation and destruction of the
+    /// buffers, textures, views, samplers, bind groups and pipelines of the
+    /// device, or remove it by passing `None`.
+    ///
+    /// Only the resources created while the callback is registered are
+    /// reported. The events are delivered by `device_poll` and
+    /// `poll_all_devices`, after all the internal locks are released.
+    /// Resources still alive when the device is destroyed are reported
+    /// as destroyed along with it.
+    #[cfg(feature = "resource-events")]
+    pub fn device_set_resource_event_callback<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        callback: Option<events::ResourceEventCallback>,
+        userdata: *mut u8,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!(
+                    "Setting the resource event callback of an invalid device {:?}",
+                    device_id
+                );
+                return;
+            }
+        };
+        device
+            .resource_events
+            .lock()
+            .set_callback(callback, userdata);
+    }
+
+    /// Register a callback to be called when the device is lost, or remove it
+    /// by passing `None`.
+    ///
+    /// It's called from the thread polling the device, after all the internal
+    /// locks are released.
+    pub fn device_set_lost_callback<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        callback: Option<DeviceLostCallback>,
+        userdata: *mut u8,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!(
+                    "Setting the lost callback of an invalid device {:?}",
+                    device_id
+                );
+                return;
+            }
+        };
+        *device.lost_hook.lock() = callback.map(|callback| LostHook { callback, userdata });
+    }
+
+    /// Register a hook to be called whenever the device gets work that needs
+    /// `device_poll` to complete, or remove it by passing `None`.
+    ///
+    /// There is no hook by default. It's called from the thread submitting
+    /// the work, after all the internal locks are released.
+    pub fn device_set_maintain_hook<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+        callback: Option<DeviceMaintainHook>,
+        userdata: *mut u8,
+    ) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!(
+                    "Setting the maintain hook of an invalid device {:?}",
+                    device_id
+                );
+                return;
+            }
+        };
+        *device.maintain_hook.lock() = callback.map(|callback| MaintainHook { callback, userdata });
+    }
+
+    /// Limits the device was created with, which all the validation is done against.
+    pub fn device_limits<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+    ) -> Result<wgt::Limits, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        match device_guard.get(device_id) {
+            Some(device) => Ok(device.limits.clone()),
+            None => Err(InvalidIdError::Device(device_id)),
+        }
+    }
+
+    pub fn device_report<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+    ) -> Result<DeviceReport, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        let (command_pools, command_buffers) = device.com_allocator.pool_stats();
+        let (transit_buffers_allocated, transit_buffers_reused) =
+            device.com_allocator.transit_stats();
+        let submissions_in_flight = device.lock_life(&mut token).active_count();
+        Ok(DeviceReport {
+            command_pools,
+            command_buffers,
+            transient_blocks: device.transient_pool.lock().block_count(),
+            skipped_attachment_bytes: device.skipped_attachment_bytes.load(Ordering::Relaxed),
+            lazy_attachment_bytes: device.transient_pool.lock().lazy_bytes(),
+            free_staging_bytes: device.staging_belt.lock().free_bytes(),
+            submissions_in_flight,
+            transit_buffers_allocated,
+            transit_buffers_reused,
+        })
+    }
+
+    /// Query the work submitted to the device since the last reset of the counters.
+    ///
+    /// The counters are only accumulated with the `counters` feature enabled.
+    pub fn device_get_internal_counters<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+    ) -> Result<InternalCounters, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let _device = device_guard
+            .get(device_id)
+            .ok_or(InvalidIdError::Device(device_id))?;
+        #[cfg(feature = "counters")]
+        let counters = _device.counters.lock().clone();
+        #[cfg(not(feature = "counters"))]
+        let counters = InternalCounters::default();
+        Ok(counters)
+    }
+
+    pub fn device_reset_internal_counters<B: GfxBackend>(&self, device_id: id::DeviceId) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let _device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => {
+                log::error!(
+                    "Resetting the counters of an invalid device {:?}",
+                    device_id
+                );
+                return;
+            }
+        };
+        #[cfg(feature = "counters")]
+        {
+            *_device.counters.lock() = InternalCounters::default();
+        }
+    }
+
+    /// Ask the graphics debugger attached to the process (RenderDoc, PIX,
+    /// or the Metal frame capture) to start capturing the device work.
+    ///
+    /// The request is ignored by the backend if no debugger is attached.
+    /// Only the device is read-locked, so this can be called between any two
+    /// submissions, and the pending writes are left alone.
+    pub fn device_start_graphics_debugger_capture<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+    ) -> DebuggerCaptureStatus {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => return DebuggerCaptureStatus::InvalidDevice,
+        };
+
+        if device.debugger_capture.swap(true, Ordering::Acquire) {
+            return DebuggerCaptureStatus::AlreadyCapturing;
+        }
+        log::info!("Starting a debugger capture of device {:?}", device_id);
+        device.raw.start_capture();
+        DebuggerCaptureStatus::Requested
+    }
+
+    /// Finish the graphics debugger capture started with
+    /// `device_start_graphics_debugger_capture`.
+    pub fn device_stop_graphics_debugger_capture<B: GfxBackend>(
+        &self,
+        device_id: id::DeviceId,
+    ) -> DebuggerCaptureStatus {
         let hub = B::hub(self);
         let mut token = Token::root();
-        let callbacks = {
-            let (device_guard, mut token) = hub.devices.read(&mut token);
-            device_guard[device_id].maintain(self, force_wait, &mut token)
+        let (device_guard, _) = hub.devices.read(&mut token);
+        let device = match device_guard.get(device_id) {
+            Some(device) => device,
+            None => return DebuggerCaptureStatus::InvalidDevice,
         };
-        fire_map_callbacks(callbacks);
+
+        if !device.debugger_capture.swap(false, Ordering::Release) {
+            return DebuggerCaptureStatus::NotCapturing;
+        }
+        log::info!("Stopping the debugger capture of device {:?}", device_id);
+        device.raw.stop_capture();
+        DebuggerCaptureStatus::Requested
     }
 
     fn poll_devices<B: GfxBackend>(
         &self,
         force_wait: bool,
         callbacks: &mut Vec<BufferMapPendingCallback>,
+        readbacks: &mut Vec<BufferReadPendingCallback>,
+        work_done: &mut Vec<SubmittedWorkDonePendingCallback>,
+        lost_hooks: &mut Vec<(id::DeviceId, LostHook)>,
+        #[cfg(feature = "resource-events")] event_batches: &mut Vec<events::ResourceEventBatch>,
     ) {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (device_guard, mut token) = hub.devices.read(&mut token);
-        for (_, device) in device_guard.iter(B::VARIANT) {
-            let cbs = device.maintain(self, force_wait, &mut token);
+        for (id, device) in device_guard.iter(B::VARIANT) {
+            let (cbs, rbs, wds, lost_hook) = device.maintain(self, force_wait, &mut token);
             callbacks.extend(cbs);
+            readbacks.extend(rbs);
+            work_done.extend(wds);
+            lost_hooks.extend(lost_hook.map(|hook| (id, hook)));
+            #[cfg(feature = "resource-events")]
+            event_batches.extend(device.resource_events.lock().take());
         }
     }
 
     pub fn poll_all_devices(&self, force_wait: bool) {
         use crate::backend;
         let mut callbacks = Vec::new();
+        let mut readbacks = Vec::new();
+        let mut work_done = Vec::new();
+        let mut lost_hooks = Vec::new();
+        #[cfg(feature = "resource-events")]
+        let mut event_batches = Vec::new();
 
         #[cfg(any(
             not(any(target_os = "ios", target_os = "macos")),
             feature = "gfx-backend-vulkan"
         ))]
-        self.poll_devices::<backend::Vulkan>(force_wait, &mut callbacks);
+        self.poll_devices::<backend::Vulkan>(
+            force_wait,
+            &mut callbacks,
+            &mut readbacks,
+            &mut work_done,
+            &mut lost_hooks,
+            #[cfg(feature = "resource-events")]
+            &mut event_batches,
+        );
         #[cfg(windows)]
-        self.poll_devices::<backend::Dx11>(force_wait, &mut callbacks);
+        self.poll_devices::<backend::Dx11>(
+            force_wait,
+            &mut callbacks,
+            &mut readbacks,
+            &mut work_done,
+            &mut lost_hooks,
+            #[cfg(feature = "resource-events")]
+            &mut event_batches,
+        );
         #[cfg(windows)]
-        self.poll_devices::<backend::Dx12>(force_wait, &mut callbacks);
+        self.poll_devices::<backend::Dx12>(
+            force_wait,
+            &mut callbacks,
+            &mut readbacks,
+            &mut work_done,
+            &mut lost_hooks,
+            #[cfg(feature = "resource-events")]
+            &mut event_batches,
+        );
         #[cfg(any(target_os = "ios", target_os = "macos"))]
-        self.poll_devices::<backend::Metal>(force_wait, &mut callbacks);
+        self.poll_devices::<backend::Metal>(
+            force_wait,
+            &mut callbacks,
+            &mut readbacks,
+            &mut work_done,
+            &mut lost_hooks,
+            #[cfg(feature = "resource-events")]
+            &mut event_batches,
+        );
+        #[cfg(feature = "gl")]
+        self.poll_devices::<backend::Gl>(
+            force_wait,
+            &mut callbacks,
+            &mut readbacks,
+            &mut work_done,
+            &mut lost_hooks,
+            #[cfg(feature = "resource-events")]
+            &mut event_batches,
+        );
 
+        for (device_id, hook) in lost_hooks {
+            hook.fire(device_id, DeviceLostReason::Timeout);
+        }
         fire_map_callbacks(callbacks);
+        fire_read_callbacks(readbacks);
+        fire_work_done_callbacks(work_done);
+        #[cfg(feature = "resource-events")]
+        {
+            for batch in event_batches {
+                batch.fire();
+            }
+        }
+    }
+
+    /// Wait for the work of the devices and free whatever the user released,
+    /// like `poll_devices`, but take out every callback still pending instead
+    /// of firing the ones that completed. The lost hooks are left out.
+    fn abort_devices<B: GfxBackend>(&self, aborted: &mut AbortedCallbacks) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device_ids = device_guard
+            .iter(B::VARIANT)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for device_id in device_ids {
+            let device = device_guard.get_mut(device_id).unwrap();
+            let (callbacks, readbacks, work_done, _) = device.maintain(self, true, &mut token);
+            aborted
+                .mappings
+                .extend(callbacks.into_iter().map(|(operation, ..)| operation));
+            aborted
+                .readbacks
+                .extend(readbacks.into_iter().map(|(operation, ..)| operation));
+            aborted
+                .work_done
+                .extend(work_done.into_iter().map(|(closure, _)| closure));
+
+            let pending = {
+                let mut life_tracker = device.lock_life(&mut token);
+                aborted.readbacks.extend(life_tracker.drain_readbacks());
+                aborted.work_done.extend(life_tracker.drain_work_done());
+                life_tracker.drain_mappings()
+            };
+            let (mut buffer_guard, _) = hub.buffers.write(&mut token);
+            aborted.mappings.extend(
+                pending
+                    .into_iter()
+                    .filter_map(|id| buffer_guard[id].map_state.take_pending())
+                    .map(|mapping| mapping.op),
+            );
+            aborted
+                .readbacks
+                .extend(device.pending_writes.abort_readbacks());
+            aborted
+                .work_done
+                .extend(device.pending_writes.work_done.drain(..));
+            aborted
+                .pipelines
+                .extend(device.pipeline_queue.pending.lock().drain_callbacks());
+        }
+    }
+
+    /// Take out the callbacks still pending on the devices of all the backends,
+    /// once their work is done, for the global to abort them before it's cleared.
+    pub(crate) fn abort_all_devices(&self) -> AbortedCallbacks {
+        use crate::backend;
+        let mut aborted = AbortedCallbacks::default();
+
+        #[cfg(any(
+            not(any(target_os = "ios", target_os = "macos")),
+            feature = "gfx-backend-vulkan"
+        ))]
+        self.abort_devices::<backend::Vulkan>(&mut aborted);
+        #[cfg(windows)]
+        self.abort_devices::<backend::Dx11>(&mut aborted);
+        #[cfg(windows)]
+        self.abort_devices::<backend::Dx12>(&mut aborted);
+        #[cfg(any(target_os = "ios", target_os = "macos"))]
+        self.abort_devices::<backend::Metal>(&mut aborted);
+        #[cfg(feature = "gl")]
+        self.abort_devices::<backend::Gl>(&mut aborted);
+
+        aborted
     }
 
     pub fn device_destroy<B: GfxBackend>(&self, device_id: id::DeviceId) {
         let hub = B::hub(self);
         let mut token = Token::root();
-        let device = {
-            let (mut device, _) = hub.devices.unregister(device_id, &mut token);
-            device.prepare_to_die();
-            device
+        let (device, aborted_mappings, readbacks, work_done) = {
+            let (mut device, mut token) = match hub.devices.try_unregister(device_id, &mut token) {
+                Some(unregistered) => unregistered,
+                None => {
+                    log::error!("Destroying an invalid device {:?}", device_id);
+                    return;
+                }
+            };
+            let (readbacks, work_done) = device.prepare_to_die();
+            // The buffers can't be mapped without the device any more,
+            // but every pending request still gets its callback.
+            let pending = device.life_tracker.get_mut().drain_mappings();
+            let (mut buffer_guard, _) = hub.buffers.write(&mut token);
+            let aborted_mappings = pending
+                .into_iter()
+                .filter_map(|id| buffer_guard[id].map_state.take_pending())
+                .collect::<Vec<_>>();
+            (device, aborted_mappings, readbacks, work_done)
         };
+        for mapping in aborted_mappings {
+            mapping
+                .op
+                .fire(resource::BufferMapAsyncStatus::DeviceLost, ptr::null_mut());
+        }
+        fire_read_callbacks(readbacks);
+        fire_work_done_callbacks(work_done);
+        let pipeline_callbacks = device.pipeline_queue.pending.lock().drain_callbacks();
+        for callback in pipeline_callbacks {
+            callback.fire(pipeline::PipelineCreationStatus::DeviceLost);
+        }
 
         // Adapter is only referenced by the device and itself.
         // This isn't a robust way to destroy them, we should find a better one.
@@ -2715,7 +6030,16 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_id, ref_count) = {
             let (mut buffer_guard, _) = hub.buffers.write(&mut token);
-            let buffer = &mut buffer_guard[buffer_id];
+            let buffer = match buffer_guard.get_mut(buffer_id) {
+                Some(buffer) => buffer,
+                None => {
+                    log::error!("Mapping an invalid buffer {:?}", buffer_id);
+                    drop(buffer_guard);
+                    drop(device_guard);
+                    operation.fire(resource::BufferMapAsyncStatus::Error, ptr::null_mut());
+                    return;
+                }
+            };
 
             assert!(
                 buffer.usage.contains(pub_usage),
@@ -2723,6 +6047,26 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 buffer.usage,
                 pub_usage
             );
+            let misaligned = {
+                let device = &device_guard[buffer.device_id.value];
+                device.strict_webgpu
+                    && (range.start % wgt::MAP_OFFSET_ALIGNMENT != 0
+                        || (range.end - range.start) % wgt::MAP_SIZE_ALIGNMENT != 0)
+            };
+            if range.start > range.end || range.end > buffer.size || misaligned {
+                log::error!(
+                    "Mapping range {:?} is outside of the buffer of size {}, or misaligned",
+                    range,
+                    buffer.size
+                );
+                drop(buffer_guard);
+                drop(device_guard);
+                operation.fire(
+                    resource::BufferMapAsyncStatus::RangeInvalid,
+                    ptr::null_mut(),
+                );
+                return;
+            }
             buffer.map_state = match buffer.map_state {
                 resource::BufferMapState::Active { .. } => panic!("Buffer already mapped"),
                 resource::BufferMapState::Waiting(_) => {
@@ -2754,6 +6098,12 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .change_replace(buffer_id, &ref_count, (), internal_use);
 
         device.lock_life(&mut token).map(buffer_id, ref_count);
+
+        let hook = *device.maintain_hook.lock();
+        drop(device_guard);
+        if let Some(hook) = hook {
+            hook.fire(device_id);
+        }
     }
 
     pub fn buffer_unmap<B: GfxBackend>(&self, buffer_id: id::BufferId) {
@@ -2762,14 +6112,21 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (mut buffer_guard, _) = hub.buffers.write(&mut token);
-        let buffer = &mut buffer_guard[buffer_id];
+        let buffer = match buffer_guard.get_mut(buffer_id) {
+            Some(buffer) => buffer,
+            None => {
+                log::error!("Unmapping an invalid buffer {:?}", buffer_id);
+                return;
+            }
+        };
 
         log::debug!("Buffer {:?} map state -> Idle", buffer_id);
-        match buffer.map_state {
+        let aborted_mapping = match buffer.map_state {
             resource::BufferMapState::Idle => {
                 log::error!("Buffer already unmapped");
+                None
             }
-            resource::BufferMapState::Waiting(_) => {}
+            resource::BufferMapState::Waiting(_) => buffer.map_state.take_pending(),
             resource::BufferMapState::Active {
                 ptr,
                 ref sub_range,
@@ -2778,9 +6135,8 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 let device = &device_guard[buffer.device_id.value];
                 if host == HostMap::Write {
                     #[cfg(feature = "trace")]
-                    match device.trace {
-                        Some(ref trace) => {
-                            let mut trace = trace.lock();
+                    match *device.trace.lock() {
+                        Some(ref mut trace) => {
                             let size = sub_range.size_to(buffer.size);
                             let data = trace.make_binary("bin", unsafe {
                                 slice::from_raw_parts(ptr, size as usize)
@@ -2797,9 +6153,18 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     let _ = (ptr, sub_range);
                 }
                 unmap_buffer(&device.raw, buffer);
+                None
             }
-        }
+        };
         buffer.map_state = resource::BufferMapState::Idle;
+        drop(buffer_guard);
+        drop(device_guard);
+
+        if let Some(mapping) = aborted_mapping {
+            mapping
+                .op
+                .fire(resource::BufferMapAsyncStatus::Aborted, ptr::null_mut());
+        }
     }
 }
 
@@ -2808,6 +6173,41 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
 enum ShaderValidationError {
     /// Unable to find an entry point matching the specified execution model.
     MissingEntryPoint(ExecutionModel),
+    /// The workgroup storage declared by a compute shader, in bytes,
+    /// exceeds the `max_compute_shared_memory_size` limit.
+    WorkgroupStorageExceeded { declared: u32, allowed: u32 },
+}
+
+/// Make sure the color targets match the first one, unless the adapter supports
+/// independent blending. Write masks alone may differ where the adapter allows it.
+fn validate_blend_targets(
+    targets: &[hal::pso::ColorBlendDesc],
+    downlevel: &wgt::DownlevelCapabilities,
+) -> Result<(), pipeline::PipelineError> {
+    if downlevel.independent_blend {
+        return Ok(());
+    }
+    // Compare the HAL states, so that descriptors which only differ in ways
+    // that map to the same native state are still accepted.
+    let mismatched = match targets.split_first() {
+        Some((first, rest)) => rest
+            .iter()
+            .enumerate()
+            .filter(|&(_, target)| {
+                target.blend != first.blend
+                    || (target.mask != first.mask && !downlevel.independent_write_mask)
+            })
+            .map(|(i, _)| i + 1)
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    if mismatched.is_empty() {
+        Ok(())
+    } else {
+        Err(pipeline::PipelineError::IndependentBlendUnsupported {
+            targets: mismatched,
+        })
+    }
 }
 
 fn validate_shader(
@@ -2826,3 +6226,767 @@ fn validate_shader(
         None => Err(ShaderValidationError::MissingEntryPoint(execution_model)),
     }
 }
+
+/// Find the bindings of the textures and samplers sampled together by an entry point.
+fn texture_sampler_pairs(
+    module: &naga::Module,
+    entry_point_name: &str,
+    execution_model: ExecutionModel,
+) -> Vec<((u32, u32), (u32, u32))> {
+    let entry_point = match module.entry_points.iter().find(|entry_point| {
+        entry_point.name == entry_point_name && entry_point.exec_model == execution_model
+    }) {
+        Some(entry_point) => entry_point,
+        None => return Vec::new(),
+    };
+    let function = &module.functions[entry_point.function];
+
+    let binding_of = |mut expr: naga::Handle<naga::Expression>| loop {
+        match function.expressions[expr] {
+            naga::Expression::Load { pointer } => expr = pointer,
+            naga::Expression::GlobalVariable(var) => {
+                break match module.global_variables[var].binding {
+                    Some(naga::Binding::Descriptor { set, binding }) => Some((set, binding)),
+                    _ => None,
+                }
+            }
+            _ => break None,
+        }
+    };
+    function
+        .expressions
+        .iter()
+        .filter_map(|(_, expr)| match *expr {
+            naga::Expression::ImageSample { image, sampler, .. } => {
+                Some((binding_of(image)?, binding_of(sampler)?))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Make sure the depth related execution modes of a fragment shader
+/// can be honored with the depth-stencil state of the pipeline.
+fn validate_fragment_depth_modes(
+    modes: pipeline::FragmentDepthModes,
+    depth_stencil_state: Option<&wgt::DepthStencilStateDescriptor>,
+    downlevel: &wgt::DownlevelCapabilities,
+) -> Result<(), pipeline::PipelineError> {
+    if modes.early_fragment_tests {
+        if !downlevel.early_fragment_tests {
+            return Err(pipeline::PipelineError::EarlyFragmentTestsUnsupported);
+        }
+        if modes.depth_replacing {
+            return Err(pipeline::PipelineError::EarlyFragmentTestsWithDepthOutput);
+        }
+        if modes.discards && depth_stencil_state.map_or(false, |ds| ds.depth_write_enabled) {
+            return Err(pipeline::PipelineError::EarlyFragmentTestsWithDiscard);
+        }
+    }
+    if modes.conservative_depth.is_some() && !downlevel.conservative_depth {
+        return Err(pipeline::PipelineError::ConservativeDepthUnsupported);
+    }
+    Ok(())
+}
+
+/// Find the bindings of the global variables used by an entry point.
+///
+/// Like `texture_sampler_pairs`, this only looks into the entry point function itself.
+fn used_bindings(
+    module: &naga::Module,
+    entry_point_name: &str,
+    execution_model: ExecutionModel,
+) -> Vec<(u32, u32)> {
+    let entry_point = match module.entry_points.iter().find(|entry_point| {
+        entry_point.name == entry_point_name && entry_point.exec_model == execution_model
+    }) {
+        Some(entry_point) => entry_point,
+        None => return Vec::new(),
+    };
+    let mut bindings = module.functions[entry_point.function]
+        .expressions
+        .iter()
+        .filter_map(|(_, expr)| match *expr {
+            naga::Expression::GlobalVariable(var) => match module.global_variables[var].binding {
+                Some(naga::Binding::Descriptor { set, binding }) => Some((set, binding)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    bindings.sort();
+    bindings.dedup();
+    bindings
+}
+
+fn shader_stage(execution_model: ExecutionModel) -> wgt::ShaderStage {
+    match execution_model {
+        ExecutionModel::Vertex => wgt::ShaderStage::VERTEX,
+        ExecutionModel::Fragment => wgt::ShaderStage::FRAGMENT,
+        ExecutionModel::GLCompute => wgt::ShaderStage::COMPUTE,
+        other => panic!("Unexpected execution model {:?}", other),
+    }
+}
+
+/// List the bind group layout entries of a pipeline layout, along with their group index.
+fn layout_entries<'a, B: hal::Backend>(
+    layout: &'a binding_model::PipelineLayout<B>,
+    bind_group_layout_guard: &'a Storage<binding_model::BindGroupLayout<B>, id::BindGroupLayoutId>,
+) -> impl Iterator<Item = (u32, &'a binding_model::BindGroupLayoutEntry)> {
+    layout
+        .bind_group_layout_ids
+        .iter()
+        .enumerate()
+        .flat_map(move |(group, bgl_id)| {
+            bind_group_layout_guard[bgl_id.value]
+                .entries
+                .values()
+                .map(move |entry| (group as u32, entry))
+        })
+}
+
+/// Make sure the device has the features a shader module requires.
+fn check_shader_features(
+    module_id: id::ShaderModuleId,
+    required: pipeline::ShaderFeatures,
+    enabled: pipeline::ShaderFeatures,
+) -> Result<(), pipeline::PipelineError> {
+    let missing = required - enabled;
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(pipeline::PipelineError::MissingShaderFeatures {
+            module: module_id,
+            missing,
+        })
+    }
+}
+
+/// Make sure every binding used by a shader stage is visible to it.
+///
+/// Bindings that are visible to the stage without being used only get a warning,
+/// since another pipeline with the same layout may use them.
+fn validate_binding_visibility<'a>(
+    used: &[(u32, u32)],
+    stage: wgt::ShaderStage,
+    entries: impl Iterator<Item = (u32, &'a binding_model::BindGroupLayoutEntry)>,
+) -> Result<(), pipeline::PipelineError> {
+    for (group, entry) in entries {
+        let binding = entry.binding;
+        let is_used = used.contains(&(group, binding));
+        if is_used && !entry.visibility.contains(stage) {
+            return Err(pipeline::PipelineError::BindingNotVisible {
+                group,
+                binding,
+                stage,
+            });
+        }
+        if !is_used && entry.visibility.contains(stage) {
+            log::warn!(
+                "Binding {} of group {} is visible to the {:?} stage, which doesn't use it",
+                binding,
+                group,
+                stage
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Size in bytes of a type stored in workgroup memory, ignoring padding.
+fn workgroup_type_size(module: &naga::Module, ty: naga::Handle<naga::Type>) -> u32 {
+    match module.types[ty].inner {
+        naga::TypeInner::Scalar { width, .. } => width as u32,
+        naga::TypeInner::Vector { size, width, .. } => size as u32 * width as u32,
+        naga::TypeInner::Matrix {
+            columns,
+            rows,
+            width,
+            ..
+        } => columns as u32 * rows as u32 * width as u32,
+        naga::TypeInner::Pointer { base, .. } => workgroup_type_size(module, base),
+        naga::TypeInner::Array {
+            base,
+            size: naga::ArraySize::Static(count),
+            ..
+        } => count * workgroup_type_size(module, base),
+        naga::TypeInner::Struct { ref members } => members
+            .iter()
+            .map(|member| workgroup_type_size(module, member.ty))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Make sure the workgroup storage used by a compute entry point fits the limit.
+fn validate_workgroup_storage(
+    module: &naga::Module,
+    entry_point_name: &str,
+    allowed: u32,
+) -> Result<(), ShaderValidationError> {
+    let entry_point = match module.entry_points.iter().find(|entry_point| {
+        entry_point.name == entry_point_name && entry_point.exec_model == ExecutionModel::GLCompute
+    }) {
+        Some(entry_point) => entry_point,
+        None => return Ok(()),
+    };
+    let function = &module.functions[entry_point.function];
+
+    let mut variables = Vec::new();
+    for (_, expr) in function.expressions.iter() {
+        if let naga::Expression::GlobalVariable(var) = *expr {
+            if module.global_variables[var].class == spirv_headers::StorageClass::Workgroup
+                && !variables.contains(&var)
+            {
+                variables.push(var);
+            }
+        }
+    }
+    let declared = variables
+        .iter()
+        .map(|&var| workgroup_type_size(module, module.global_variables[var].ty))
+        .sum();
+
+    if declared > allowed {
+        Err(ShaderValidationError::WorkgroupStorageExceeded { declared, allowed })
+    } else {
+        Ok(())
+    }
+}
+
+/// Make sure the textures sampled with filtering samplers are filterable,
+/// according to the pipeline layout entries found by `layout_entry`.
+fn validate_texture_sampler_pairs<'a>(
+    pairs: &[((u32, u32), (u32, u32))],
+    layout_entry: impl Fn((u32, u32)) -> Option<&'a binding_model::BindGroupLayoutEntry>,
+) -> Result<(), pipeline::PipelineError> {
+    for &(texture, sampler) in pairs {
+        match (layout_entry(texture), layout_entry(sampler)) {
+            (Some(texture_entry), Some(sampler_entry))
+                if sampler_entry.ty == binding_model::BindingType::Sampler
+                    && texture_entry.ty == binding_model::BindingType::SampledTexture
+                    && !texture_entry.texture_component_type.is_filterable() =>
+            {
+                return Err(
+                    pipeline::PipelineError::FilteringSamplerWithUnfilterableTexture {
+                        texture,
+                        sampler,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    unsafe extern "C" fn mapped_read(
+        status: resource::BufferMapAsyncStatus,
+        ptr: *const u8,
+        userdata: *mut u8,
+    ) {
+        if status == resource::BufferMapAsyncStatus::DeviceLost && ptr.is_null() {
+            *(userdata as *mut u32) += 1;
+        }
+    }
+
+    unsafe extern "C" fn mapped_write(
+        status: resource::BufferMapAsyncStatus,
+        ptr: *mut u8,
+        userdata: *mut u8,
+    ) {
+        mapped_read(status, ptr, userdata);
+    }
+
+    unsafe extern "C" fn read(
+        status: resource::BufferMapAsyncStatus,
+        _data: *const u8,
+        size: usize,
+        userdata: *mut u8,
+    ) {
+        if status == resource::BufferMapAsyncStatus::DeviceLost && size == 0 {
+            *(userdata as *mut u32) += 1;
+        }
+    }
+
+    unsafe extern "C" fn work_done(status: SubmittedWorkDoneStatus, userdata: *mut u8) {
+        if status == SubmittedWorkDoneStatus::DeviceLost {
+            *(userdata as *mut u32) += 1;
+        }
+    }
+
+    unsafe extern "C" fn pipeline_created(
+        status: pipeline::PipelineCreationStatus,
+        userdata: *mut u8,
+    ) {
+        if let pipeline::PipelineCreationStatus::DeviceLost = status {
+            *(userdata as *mut u32) += 1;
+        }
+    }
+
+    #[test]
+    fn abort_pending_callbacks() {
+        let mut lost = 0u32;
+        let userdata = &mut lost as *mut u32 as *mut u8;
+        let aborted = AbortedCallbacks {
+            mappings: vec![
+                resource::BufferMapOperation::Read {
+                    callback: mapped_read,
+                    userdata,
+                },
+                resource::BufferMapOperation::Write {
+                    callback: mapped_write,
+                    userdata,
+                },
+            ],
+            readbacks: vec![resource::BufferReadOperation {
+                callback: read,
+                userdata,
+            }],
+            work_done: vec![SubmittedWorkDoneClosure {
+                callback: work_done,
+                userdata,
+            }],
+            pipelines: vec![pipeline::PipelineCallback {
+                callback: pipeline_created,
+                userdata,
+            }],
+        };
+
+        // Every callback still pending gets `DeviceLost`, exactly once.
+        aborted.fire();
+        assert_eq!(lost, 5);
+    }
+
+    #[test]
+    fn dedup_keys() {
+        use crate::id::TypedId;
+
+        let sampler = |label, lod_max_clamp| wgt::SamplerDescriptor {
+            label,
+            address_mode_u: wgt::AddressMode::Repeat,
+            address_mode_v: wgt::AddressMode::Repeat,
+            address_mode_w: wgt::AddressMode::Repeat,
+            mag_filter: wgt::FilterMode::Linear,
+            min_filter: wgt::FilterMode::Linear,
+            mipmap_filter: wgt::FilterMode::Linear,
+            lod_min_clamp: 0.0,
+            lod_max_clamp,
+            compare: wgt::CompareFunction::Undefined,
+        };
+        // Labels don't prevent sharing, unlike any sampling state.
+        assert_eq!(
+            SamplerKey::new(&sampler("a", 4.0)),
+            SamplerKey::new(&sampler("b", 4.0))
+        );
+        assert_ne!(
+            SamplerKey::new(&sampler("a", 4.0)),
+            SamplerKey::new(&sampler("a", 8.0))
+        );
+
+        let buffer = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let view = id::TextureViewId::zip(0, 1, wgt::Backend::Empty);
+        let buffer_entry = |offset| binding_model::BindGroupEntry {
+            binding: 0,
+            resource: binding_model::BindingResource::Buffer(binding_model::BufferBinding {
+                buffer,
+                offset,
+                size: None,
+            }),
+        };
+        let view_entry = || binding_model::BindGroupEntry {
+            binding: 1,
+            resource: binding_model::BindingResource::TextureView(view),
+        };
+        let layout = id::BindGroupLayoutId::zip(0, 1, wgt::Backend::Empty);
+        let key = BindGroupKey::new(layout, &[buffer_entry(0), view_entry()]);
+        // Entries are matched by binding, whatever their order.
+        assert_eq!(
+            BindGroupKey::new(layout, &[view_entry(), buffer_entry(0)]),
+            key
+        );
+        assert_ne!(
+            BindGroupKey::new(layout, &[buffer_entry(256), view_entry()]),
+            key
+        );
+    }
+
+    #[test]
+    fn dedup_cache() {
+        use crate::id::TypedId;
+
+        let sampler = |index| id::SamplerId::zip(index, 1, wgt::Backend::Empty);
+        let mut cache = DedupCache::new();
+        cache.insert(1u32, sampler(0));
+        assert_eq!(cache.find(&1), Some(sampler(0)));
+        assert_eq!(cache.find(&2), None);
+
+        // A sampler created while the cached one was being destroyed takes its key,
+        // and must outlive the destruction of the previous one.
+        cache.insert(1, sampler(1));
+        cache.remove(&1, sampler(0));
+        assert_eq!(cache.find(&1), Some(sampler(1)));
+        cache.remove(&1, sampler(1));
+        assert_eq!(cache.find(&1), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn blend_targets() {
+        use hal::pso::{BlendState, ColorBlendDesc, ColorMask};
+
+        let target = |mask, blend| ColorBlendDesc { mask, blend };
+        let targets = [
+            target(ColorMask::ALL, Some(BlendState::ALPHA)),
+            target(ColorMask::ALL, Some(BlendState::ALPHA)),
+            target(ColorMask::COLOR, Some(BlendState::ALPHA)),
+            target(ColorMask::ALL, Some(BlendState::ADD)),
+            target(ColorMask::ALL, None),
+        ];
+        let mut downlevel = wgt::DownlevelCapabilities {
+            independent_blend: false,
+            independent_write_mask: false,
+            ..wgt::DownlevelCapabilities::default()
+        };
+        assert_eq!(validate_blend_targets(&targets[..2], &downlevel), Ok(()));
+        assert_eq!(
+            validate_blend_targets(&targets, &downlevel),
+            Err(pipeline::PipelineError::IndependentBlendUnsupported {
+                targets: vec![2, 3, 4],
+            })
+        );
+        // Only the blend states have to match with independent write masks.
+        downlevel.independent_write_mask = true;
+        assert_eq!(
+            validate_blend_targets(&targets, &downlevel),
+            Err(pipeline::PipelineError::IndependentBlendUnsupported {
+                targets: vec![3, 4],
+            })
+        );
+        downlevel.independent_blend = true;
+        assert_eq!(validate_blend_targets(&targets, &downlevel), Ok(()));
+    }
+
+    #[test]
+    fn texture_component_types() {
+        use wgt::{TextureComponentType as Tct, TextureFormat as Tf};
+
+        assert!(Tct::Float.is_compatible_with(Tf::Rgba8Unorm));
+        assert!(!Tct::Float.is_compatible_with(Tf::Rgba32Float));
+        assert!(!Tct::Float.is_compatible_with(Tf::Depth32Float));
+        assert!(!Tct::Float.is_compatible_with(Tf::Rgba8Uint));
+        assert!(Tct::UnfilterableFloat.is_compatible_with(Tf::Rgba32Float));
+        assert!(Tct::UnfilterableFloat.is_compatible_with(Tf::Rgba8Unorm));
+        assert!(Tct::UnfilterableFloat.is_compatible_with(Tf::Depth24Plus));
+        assert!(!Tct::UnfilterableFloat.is_compatible_with(Tf::R32Sint));
+        assert!(Tct::Depth.is_compatible_with(Tf::Depth24PlusStencil8));
+        assert!(!Tct::Depth.is_compatible_with(Tf::R32Float));
+        assert!(Tct::Sint.is_compatible_with(Tf::Rg16Sint));
+        assert!(!Tct::Sint.is_compatible_with(Tf::Rg16Uint));
+        assert!(Tct::Uint.is_compatible_with(Tf::R8Uint));
+
+        assert!(Tct::Float.is_filterable());
+        for &ty in &[Tct::UnfilterableFloat, Tct::Depth, Tct::Sint, Tct::Uint] {
+            assert!(!ty.is_filterable());
+        }
+    }
+
+    /// Module with a fragment entry point sampling the texture at `texture`
+    /// with the sampler at `sampler`, both given as (group, binding).
+    fn sampling_module(texture: (u32, u32), sampler: (u32, u32)) -> naga::Module {
+        let mut types = naga::Arena::new();
+        let ty = types.append(naga::Type {
+            name: None,
+            inner: naga::TypeInner::Scalar {
+                kind: naga::ScalarKind::Float,
+                width: 32,
+            },
+        });
+        let mut global_variables = naga::Arena::new();
+        let mut global = |(set, binding)| {
+            global_variables.append(naga::GlobalVariable {
+                name: None,
+                class: spirv_headers::StorageClass::UniformConstant,
+                binding: Some(naga::Binding::Descriptor { set, binding }),
+                ty,
+            })
+        };
+        let texture = global(texture);
+        let sampler = global(sampler);
+
+        let mut expressions = naga::Arena::new();
+        let texture = expressions.append(naga::Expression::GlobalVariable(texture));
+        let image = expressions.append(naga::Expression::Load { pointer: texture });
+        let sampler = expressions.append(naga::Expression::GlobalVariable(sampler));
+        let sampler = expressions.append(naga::Expression::Load { pointer: sampler });
+        expressions.append(naga::Expression::ImageSample {
+            image,
+            sampler,
+            coordinate: image,
+            depth_ref: None,
+        });
+        let mut functions = naga::Arena::new();
+        let function = functions.append(naga::Function {
+            name: None,
+            control: spirv_headers::FunctionControl::empty(),
+            parameter_types: Vec::new(),
+            return_type: None,
+            global_usage: Vec::new(),
+            local_variables: naga::Arena::new(),
+            expressions,
+            body: Vec::new(),
+        });
+
+        naga::Module {
+            header: naga::Header {
+                version: (1, 0, 0),
+                generator: 0,
+            },
+            types,
+            constants: naga::Arena::new(),
+            global_variables,
+            functions,
+            entry_points: vec![naga::EntryPoint {
+                exec_model: ExecutionModel::Fragment,
+                name: "main".to_string(),
+                function,
+            }],
+        }
+    }
+
+    #[test]
+    fn sampled_pairs() {
+        let module = sampling_module((0, 1), (1, 0));
+        assert_eq!(
+            texture_sampler_pairs(&module, "main", ExecutionModel::Fragment),
+            [((0, 1), (1, 0))]
+        );
+        // Other entry points don't sample anything.
+        assert!(texture_sampler_pairs(&module, "main", ExecutionModel::Vertex).is_empty());
+        assert!(texture_sampler_pairs(&module, "other", ExecutionModel::Fragment).is_empty());
+    }
+
+    #[test]
+    fn filtering_sampler_pairs() {
+        let entry = |binding, ty, texture_component_type| binding_model::BindGroupLayoutEntry {
+            binding,
+            visibility: wgt::ShaderStage::FRAGMENT,
+            ty,
+            multisampled: false,
+            has_dynamic_offset: false,
+            view_dimension: wgt::TextureViewDimension::D2,
+            texture_component_type,
+            storage_texture_format: wgt::TextureFormat::Rgba8Unorm,
+        };
+        let groups = vec![
+            vec![
+                entry(
+                    0,
+                    binding_model::BindingType::SampledTexture,
+                    wgt::TextureComponentType::Float,
+                ),
+                entry(
+                    1,
+                    binding_model::BindingType::SampledTexture,
+                    wgt::TextureComponentType::UnfilterableFloat,
+                ),
+            ],
+            vec![
+                entry(
+                    0,
+                    binding_model::BindingType::Sampler,
+                    wgt::TextureComponentType::Float,
+                ),
+                entry(
+                    1,
+                    binding_model::BindingType::NonFilteringSampler,
+                    wgt::TextureComponentType::Float,
+                ),
+            ],
+        ];
+        let layout_entry = |(group, binding): (u32, u32)| {
+            groups
+                .get(group as usize)?
+                .iter()
+                .find(|entry| entry.binding == binding)
+        };
+
+        assert_eq!(
+            validate_texture_sampler_pairs(&[((0, 0), (1, 0)), ((0, 1), (1, 1))], layout_entry),
+            Ok(())
+        );
+        assert_eq!(
+            validate_texture_sampler_pairs(&[((0, 0), (1, 0)), ((0, 1), (1, 0))], layout_entry),
+            Err(
+                pipeline::PipelineError::FilteringSamplerWithUnfilterableTexture {
+                    texture: (0, 1),
+                    sampler: (1, 0),
+                }
+            )
+        );
+        // Bindings missing from the layout are reported by the backend instead.
+        assert_eq!(
+            validate_texture_sampler_pairs(&[((0, 1), (2, 0))], layout_entry),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn wrong_device() {
+        use crate::id::TypedId;
+
+        let device = id::DeviceId::zip(0, 1, wgt::Backend::Empty);
+        let other = id::DeviceId::zip(1, 1, wgt::Backend::Empty);
+        let buffer = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        assert_eq!(check_device(device, buffer, device), Ok(()));
+        assert_eq!(
+            check_device(device, buffer, other),
+            Err(WrongDeviceError {
+                resource: format!("{:?}", buffer),
+                expected: device,
+                actual: other,
+            })
+        );
+    }
+
+    #[test]
+    fn missing_shader_features() {
+        use crate::id::TypedId;
+        use pipeline::ShaderFeatures as Sf;
+
+        let module = id::ShaderModuleId::zip(0, 1, wgt::Backend::Empty);
+        let enabled = Sf::FLOAT64 | Sf::INT16;
+        assert_eq!(check_shader_features(module, Sf::empty(), enabled), Ok(()));
+        assert_eq!(check_shader_features(module, Sf::INT16, enabled), Ok(()));
+        assert_eq!(
+            check_shader_features(module, Sf::INT16 | Sf::FLOAT16 | Sf::INT64, enabled),
+            Err(pipeline::PipelineError::MissingShaderFeatures {
+                module,
+                missing: Sf::FLOAT16 | Sf::INT64,
+            })
+        );
+    }
+
+    #[test]
+    fn binding_visibility() {
+        let entry = |binding, ty, visibility| binding_model::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty,
+            multisampled: false,
+            has_dynamic_offset: false,
+            view_dimension: wgt::TextureViewDimension::D2,
+            texture_component_type: wgt::TextureComponentType::Float,
+            storage_texture_format: wgt::TextureFormat::Rgba8Unorm,
+        };
+        let texture = entry(
+            0,
+            binding_model::BindingType::SampledTexture,
+            wgt::ShaderStage::FRAGMENT,
+        );
+        let sampler = entry(
+            1,
+            binding_model::BindingType::Sampler,
+            wgt::ShaderStage::VERTEX,
+        );
+        let module = sampling_module((0, 0), (0, 1));
+        let used = used_bindings(&module, "main", ExecutionModel::Fragment);
+        assert_eq!(used, [(0, 0), (0, 1)]);
+
+        // The fragment shader samples with a sampler only visible to the vertex stage.
+        let entries = vec![(0, &texture), (0, &sampler)];
+        assert_eq!(
+            validate_binding_visibility(&used, wgt::ShaderStage::FRAGMENT, entries.into_iter()),
+            Err(pipeline::PipelineError::BindingNotVisible {
+                group: 0,
+                binding: 1,
+                stage: wgt::ShaderStage::FRAGMENT,
+            })
+        );
+        // Unused bindings can be visible to any stage.
+        let sampler = entry(
+            1,
+            binding_model::BindingType::Sampler,
+            wgt::ShaderStage::VERTEX | wgt::ShaderStage::FRAGMENT,
+        );
+        let entries = vec![(0, &texture), (0, &sampler)];
+        assert_eq!(
+            validate_binding_visibility(&used, wgt::ShaderStage::FRAGMENT, entries.into_iter()),
+            Ok(())
+        );
+        let entries = vec![(0, &texture), (0, &sampler)];
+        assert_eq!(
+            validate_binding_visibility(&[], wgt::ShaderStage::VERTEX, entries.into_iter()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn fragment_depth_modes() {
+        let depth_stencil = |depth_write_enabled| wgt::DepthStencilStateDescriptor {
+            format: wgt::TextureFormat::Depth32Float,
+            depth_write_enabled,
+            depth_compare: wgt::CompareFunction::Less,
+            stencil_front: wgt::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgt::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+            dynamic_stencil_masks: false,
+        };
+        let early = pipeline::FragmentDepthModes {
+            early_fragment_tests: true,
+            ..Default::default()
+        };
+        let downlevel = wgt::DownlevelCapabilities::default();
+        let writing = depth_stencil(true);
+        let reading = depth_stencil(false);
+        assert_eq!(
+            validate_fragment_depth_modes(early, Some(&writing), &downlevel),
+            Ok(())
+        );
+
+        // Discarded fragments would still write the depth.
+        let discarding = pipeline::FragmentDepthModes {
+            discards: true,
+            ..early
+        };
+        assert_eq!(
+            validate_fragment_depth_modes(discarding, Some(&writing), &downlevel),
+            Err(pipeline::PipelineError::EarlyFragmentTestsWithDiscard)
+        );
+        assert_eq!(
+            validate_fragment_depth_modes(discarding, Some(&reading), &downlevel),
+            Ok(())
+        );
+        let replacing = pipeline::FragmentDepthModes {
+            depth_replacing: true,
+            ..early
+        };
+        assert_eq!(
+            validate_fragment_depth_modes(replacing, None, &downlevel),
+            Err(pipeline::PipelineError::EarlyFragmentTestsWithDepthOutput)
+        );
+
+        let downlevel = wgt::DownlevelCapabilities {
+            early_fragment_tests: false,
+            conservative_depth: false,
+            ..downlevel
+        };
+        assert_eq!(
+            validate_fragment_depth_modes(early, None, &downlevel),
+            Err(pipeline::PipelineError::EarlyFragmentTestsUnsupported)
+        );
+        let conservative = pipeline::FragmentDepthModes {
+            conservative_depth: Some(pipeline::ConservativeDepth::Greater),
+            ..Default::default()
+        };
+        assert_eq!(
+            validate_fragment_depth_modes(conservative, None, &downlevel),
+            Err(pipeline::PipelineError::ConservativeDepthUnsupported)
+        );
+    }
+}
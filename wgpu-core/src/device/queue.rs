@@ -5,21 +5,90 @@
 #[cfg(feature = "trace")]
 use crate::device::trace::Action;
 use crate::{
-    command::{CommandAllocator, CommandBuffer},
-    hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Token},
+    command::{
+        validate_texture_copy_range, CommandAllocator, CommandBuffer, CommandEncoderStatus,
+        TextureCopyError, TextureCopyView, TextureDataLayout,
+    },
+    conv,
+    device::StagingChunk,
+    hub::{
+        Access, GfxBackend, Global, GlobalIdentityHandlerFactory, IdentityHandlerFactory, Input,
+        Registry, Storage, Token,
+    },
     id,
-    resource::{BufferMapState, BufferUse},
+    resource::{
+        Buffer, BufferMapAsyncStatus, BufferMapState, BufferReadOperation, BufferUse,
+        StagingBuffer, Texture, TextureUse,
+    },
+    swap_chain::FrameIndex,
+    FastHashMap, Stored,
 };
 
-use gfx_memory::{Block, Heaps, MemoryBlock};
+use gfx_memory::{Block, Heaps};
 use hal::{command::CommandBuffer as _, device::Device as _, queue::CommandQueue as _};
 use smallvec::SmallVec;
-use std::{iter, sync::atomic::Ordering};
+use std::{
+    iter,
+    ops::Range,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Number of timestamps written for each half of an estimated calibration.
+/// The one seen done soonest after its submission is kept.
+const CALIBRATION_SAMPLES: u32 = 4;
+/// Host time between the two halves of an estimated calibration,
+/// which the timestamp period is measured over.
+const CALIBRATION_INTERVAL: Duration = Duration::from_millis(10);
+/// Longest wait for a cross-device readback with the source device locked,
+/// so that other threads get to use it in between.
+const READBACK_WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// Buffer range copied into host-visible memory by `queue_read_buffer`,
+/// which is handed to the callback once the submission is done.
+#[derive(Debug)]
+pub(crate) struct PendingReadback<B: hal::Backend> {
+    pub chunk: StagingChunk<B>,
+    pub size: wgt::BufferAddress,
+    pub op: BufferReadOperation,
+}
+
+/// Buffer ranges written by the pending command buffer, so far.
+///
+/// Consecutive copies into a buffer don't change its tracked usage, so the
+/// tracker doesn't produce any barrier between them. Overlapping writes need
+/// one to be ordered on the GPU.
+#[derive(Debug, Default)]
+pub(crate) struct WrittenRanges {
+    buffers: FastHashMap<id::BufferId, Vec<Range<wgt::BufferAddress>>>,
+}
+
+impl WrittenRanges {
+    /// Record a write of `range`, returning `true` if it overlaps one
+    /// recorded before.
+    pub fn insert(&mut self, buffer_id: id::BufferId, range: Range<wgt::BufferAddress>) -> bool {
+        let ranges = self.buffers.entry(buffer_id).or_default();
+        let overlaps = ranges
+            .iter()
+            .any(|written| written.start < range.end && range.start < written.end);
+        ranges.push(range);
+        overlaps
+    }
+
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+}
 
 #[derive(Debug, Default)]
 pub(crate) struct PendingWrites<B: hal::Backend> {
     pub command_buffer: Option<B::CommandBuffer>,
-    pub temp_buffers: Vec<(B::Buffer, MemoryBlock<B>)>,
+    pub temp_buffers: Vec<StagingChunk<B>>,
+    pub readbacks: Vec<PendingReadback<B>>,
+    /// Callbacks waiting for the pending writes along with the earlier work.
+    pub work_done: Vec<super::SubmittedWorkDoneClosure>,
+    pub written_ranges: WrittenRanges,
 }
 
 impl<B: hal::Backend> PendingWrites<B> {
@@ -27,7 +96,47 @@ impl<B: hal::Backend> PendingWrites<B> {
         PendingWrites {
             command_buffer: None,
             temp_buffers: Vec::new(),
+            readbacks: Vec::new(),
+            work_done: Vec::new(),
+            written_ranges: WrittenRanges::default(),
+        }
+    }
+
+    /// Take the command buffer to submit, so that the following writes
+    /// start a new one.
+    pub fn take_command_buffer(&mut self) -> Option<B::CommandBuffer> {
+        self.written_ranges.clear();
+        self.command_buffer.take()
+    }
+
+    /// Take out the operations of the readbacks that are never going to be
+    /// submitted, leaving their buffers to be freed along with the writes.
+    pub fn abort_readbacks(&mut self) -> Vec<BufferReadOperation> {
+        let mut operations = Vec::with_capacity(self.readbacks.len());
+        for readback in self.readbacks.drain(..) {
+            self.temp_buffers.push(readback.chunk);
+            operations.push(readback.op);
+        }
+        operations
+    }
+
+    /// Barrier ordering a copy into `range` of the buffer after the earlier
+    /// pending copies that overlap it.
+    fn write_barrier<'a>(
+        &mut self,
+        buffer_id: id::BufferId,
+        raw: &'a B::Buffer,
+        range: Range<wgt::BufferAddress>,
+    ) -> Option<hal::memory::Barrier<'a, B>> {
+        if !self.written_ranges.insert(buffer_id, range) {
+            return None;
         }
+        Some(hal::memory::Barrier::Buffer {
+            states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::TRANSFER_WRITE,
+            target: raw,
+            range: hal::buffer::SubRange::WHOLE,
+            families: None,
+        })
     }
 
     pub fn dispose(
@@ -39,44 +148,1147 @@ impl<B: hal::Backend> PendingWrites<B> {
         if let Some(raw) = self.command_buffer {
             com_allocator.discard_internal(raw);
         }
-        for (buffer, memory) in self.temp_buffers {
-            mem_allocator.free(device, memory);
+        // The readbacks are aborted by `Device::prepare_to_die`.
+        debug_assert!(self.readbacks.is_empty());
+        let chunks = self
+            .temp_buffers
+            .into_iter()
+            .chain(self.readbacks.into_iter().map(|readback| readback.chunk));
+        for chunk in chunks {
+            mem_allocator.free(device, chunk.memory);
             unsafe {
-                device.destroy_buffer(buffer);
+                device.destroy_buffer(chunk.raw);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueSubmitError {
+    /// The same command buffer is listed more than once in a submission.
+    DuplicateCommandBuffer(id::CommandBufferId),
+    /// The command buffer doesn't exist, or was already consumed by a previous submission.
+    InvalidCommandBuffer(id::CommandBufferId),
+    /// The command encoder wasn't finished before the submission.
+    UnfinishedCommandBuffer(id::CommandBufferId),
+    /// The command buffer was recorded on a different device than the queue's.
+    WrongDevice(id::CommandBufferId),
+    /// The command buffer renders to a swap chain frame that was presented
+    /// before the submission.
+    SwapChainFramePresented {
+        command_buffer: id::CommandBufferId,
+        swap_chain: id::SwapChainId,
+        frame: FrameIndex,
+    },
+    /// The queue has `max_submissions_in_flight` submissions in flight,
+    /// and the device is configured not to wait for them.
+    QueueFull,
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+}
+
+/// Error returned by `queue_write_staging_buffer`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StagingBufferError {
+    /// The staging buffer was already written or destroyed.
+    Consumed(id::StagingBufferId),
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+    /// The destination buffer doesn't exist, or was destroyed.
+    /// The staging buffer is consumed regardless.
+    InvalidBuffer(id::BufferId),
+    /// The staging buffer doesn't fit into the destination buffer at the offset.
+    OutOfBounds {
+        size: wgt::BufferAddress,
+        offset: wgt::BufferAddress,
+        buffer_size: wgt::BufferAddress,
+    },
+    /// The staging or destination buffer belongs to another device.
+    /// The staging buffer is consumed regardless.
+    WrongDevice(super::WrongDeviceError),
+}
+
+impl From<super::WrongDeviceError> for StagingBufferError {
+    fn from(error: super::WrongDeviceError) -> Self {
+        StagingBufferError::WrongDevice(error)
+    }
+}
+
+/// Take a staging buffer out of its registry, to be written or destroyed.
+fn take_staging_buffer<'a, T, A, F>(
+    registry: &Registry<T, id::StagingBufferId, F>,
+    staging_id: id::StagingBufferId,
+    token: &'a mut Token<A>,
+) -> Result<(T, Token<'a, T>), StagingBufferError>
+where
+    A: Access<T>,
+    F: IdentityHandlerFactory<id::StagingBufferId>,
+{
+    registry
+        .try_unregister(staging_id, token)
+        .ok_or(StagingBufferError::Consumed(staging_id))
+}
+
+/// Error returned by the queue operations that upload to, or read back from, a buffer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueBufferError {
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+    /// The buffer doesn't exist, or was destroyed.
+    InvalidBuffer(id::BufferId),
+    /// The staging memory couldn't be allocated.
+    Allocation(super::AllocationError),
+    /// The readback couldn't be submitted.
+    Submit(QueueSubmitError),
+    /// The device was lost before the readback completed.
+    DeviceLost,
+    /// The buffer belongs to another device than the queue.
+    WrongDevice(super::WrongDeviceError),
+    /// The buffer usage doesn't contain `COPY_SRC`.
+    MissingCopySrcUsage(wgt::BufferUsage),
+    /// The buffer usage doesn't contain `COPY_DST`.
+    MissingCopyDstUsage(wgt::BufferUsage),
+    /// The range is inverted, or doesn't fit into the buffer.
+    OutOfBounds {
+        range: Range<wgt::BufferAddress>,
+        buffer_size: wgt::BufferAddress,
+    },
+    /// A bound of the range isn't a multiple of `COPY_BUFFER_ALIGNMENT`.
+    Unaligned(wgt::BufferAddress),
+    /// The buffer is mapped, or waiting to be.
+    BufferMapped(id::BufferId),
+    /// A copy across devices was asked for within a single queue, which
+    /// `command_encoder_copy_buffer_to_buffer` is for.
+    SameQueue(id::QueueId),
+}
+
+impl From<super::AllocationError> for QueueBufferError {
+    fn from(error: super::AllocationError) -> Self {
+        QueueBufferError::Allocation(error)
+    }
+}
+
+impl From<super::WrongDeviceError> for QueueBufferError {
+    fn from(error: super::WrongDeviceError) -> Self {
+        QueueBufferError::WrongDevice(error)
+    }
+}
+
+/// Check that `range` of a buffer can be read back by a queue.
+fn validate_read_range(
+    buffer_id: id::BufferId,
+    usage: wgt::BufferUsage,
+    buffer_size: wgt::BufferAddress,
+    map_state: &BufferMapState,
+    range: &Range<wgt::BufferAddress>,
+) -> Result<(), QueueBufferError> {
+    if !usage.contains(wgt::BufferUsage::COPY_SRC) {
+        return Err(QueueBufferError::MissingCopySrcUsage(usage));
+    }
+    match *map_state {
+        BufferMapState::Idle => {}
+        _ => return Err(QueueBufferError::BufferMapped(buffer_id)),
+    }
+    validate_copy_range(buffer_size, range)
+}
+
+/// Check that `range` is an aligned range of a buffer of `buffer_size` bytes.
+fn validate_copy_range(
+    buffer_size: wgt::BufferAddress,
+    range: &Range<wgt::BufferAddress>,
+) -> Result<(), QueueBufferError> {
+    if range.start > range.end || range.end > buffer_size {
+        return Err(QueueBufferError::OutOfBounds {
+            range: range.clone(),
+            buffer_size,
+        });
+    }
+    for &bound in &[range.start, range.end] {
+        if bound % wgt::COPY_BUFFER_ALIGNMENT != 0 {
+            return Err(QueueBufferError::Unaligned(bound));
+        }
+    }
+    Ok(())
+}
+
+/// Region of a batched texture upload, see `queue_write_texture_regions`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+#[cfg_attr(feature = "replay", derive(serde::Deserialize))]
+pub struct TextureWriteRegion {
+    pub mip_level: u32,
+    pub array_layer: u32,
+    pub origin: wgt::Origin3d,
+    pub size: wgt::Extent3d,
+    /// Offset of the first texel block of the region in the data.
+    pub data_offset: wgt::BufferAddress,
+}
+
+/// Layout of the data of `queue_write_texture_regions`, shared by all the regions.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "trace", derive(serde::Serialize))]
+#[cfg_attr(feature = "replay", derive(serde::Deserialize))]
+pub struct TextureWriteLayout {
+    /// Distance between the rows of texel blocks.
+    pub bytes_per_row: u32,
+    /// Number of block rows between the depth slices of a region,
+    /// or zero if the slices are packed by the region height.
+    pub rows_per_image: u32,
+}
+
+/// Error returned by `queue_write_texture_regions`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureWriteError {
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+    /// The texture doesn't exist, or was destroyed.
+    InvalidTexture(id::TextureId),
+    /// The texture usage doesn't contain `COPY_DST`.
+    MissingCopyDstUsage(wgt::TextureUsage),
+    /// The region doesn't fit into its mip level.
+    Region {
+        index: usize,
+        error: TextureCopyError,
+    },
+    /// The array layer of the region doesn't exist in the texture.
+    InvalidArrayLayer {
+        index: usize,
+        layer: u32,
+        layer_count: u32,
+    },
+    /// A row of texel blocks of the region is longer than `bytes_per_row`.
+    RowTooLong {
+        index: usize,
+        row_size: wgt::BufferAddress,
+        bytes_per_row: u32,
+    },
+    /// The region reads past the end of the data.
+    DataOutOfBounds {
+        index: usize,
+        end: wgt::BufferAddress,
+        data_size: wgt::BufferAddress,
+    },
+    /// The staging memory couldn't be allocated.
+    Allocation(super::AllocationError),
+    /// The texture belongs to another device than the queue.
+    WrongDevice(super::WrongDeviceError),
+}
+
+impl From<super::WrongDeviceError> for TextureWriteError {
+    fn from(error: super::WrongDeviceError) -> Self {
+        TextureWriteError::WrongDevice(error)
+    }
+}
+
+/// Layout of the data read back from a texture region of `size`, in a format
+/// of `block_dim` texel blocks of `block_bits` each.
+fn readback_layout(block_dim: (u8, u8), block_bits: u16, size: wgt::Extent3d) -> TextureDataLayout {
+    let row_size = size.width / u32::from(block_dim.0) * u32::from(block_bits / 8);
+    let alignment = wgt::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let bytes_per_row = (row_size + alignment - 1) / alignment * alignment;
+    let block_rows = size.height / u32::from(block_dim.1);
+    TextureDataLayout {
+        offset: 0,
+        bytes_per_row,
+        rows_per_image: size.height,
+        required_size: wgt::BufferAddress::from(bytes_per_row)
+            * wgt::BufferAddress::from(block_rows)
+            * wgt::BufferAddress::from(size.depth),
+    }
+}
+
+/// Error returned by `queue_read_texture`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TextureReadError {
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+    /// The texture doesn't exist, or was destroyed.
+    InvalidTexture(id::TextureId),
+    /// The texture usage doesn't contain `COPY_SRC`.
+    MissingCopySrcUsage(wgt::TextureUsage),
+    /// The region doesn't fit into its mip level.
+    Region(TextureCopyError),
+    /// The array layer of the region doesn't exist in the texture.
+    InvalidArrayLayer { layer: u32, layer_count: u32 },
+    /// The staging memory couldn't be allocated.
+    Allocation(super::AllocationError),
+    /// The texture belongs to another device than the queue.
+    WrongDevice(super::WrongDeviceError),
+}
+
+impl From<super::WrongDeviceError> for TextureReadError {
+    fn from(error: super::WrongDeviceError) -> Self {
+        TextureReadError::WrongDevice(error)
+    }
+}
+
+/// Split a batch of command buffer groups, once flattened, into the ranges
+/// of command buffers that go into each native submission.
+///
+/// Empty groups don't need a submission of their own, but there is always at
+/// least one, to carry the pending writes and the fence.
+fn submission_ranges(groups: &[&[id::CommandBufferId]]) -> SmallVec<[Range<usize>; 4]> {
+    let mut ranges = SmallVec::new();
+    let mut start = 0;
+    for group in groups.iter().filter(|group| !group.is_empty()) {
+        ranges.push(start..start + group.len());
+        start += group.len();
+    }
+    if ranges.is_empty() {
+        ranges.push(0..0);
+    }
+    ranges
+}
+
+/// Identifies the submission of a queue that carries some work.
+///
+/// The work is finished once `queue_is_submission_done` returns `Ok(true)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SubmissionToken {
+    pub queue_id: id::QueueId,
+    pub index: usize,
+}
+
+/// How a timestamp calibration was obtained.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimestampCalibrationKind {
+    /// The host and GPU clocks were sampled together by the driver.
+    Exact = 0,
+    /// The GPU timestamp was written by a submission, and paired with
+    /// the middle of the host time from submitting it to seeing it done.
+    Estimated = 1,
+}
+
+/// Paired sample of the host clock and the GPU timestamp counter,
+/// returned by `queue_get_timestamp_calibration`.
+#[derive(Clone, Copy, Debug)]
+pub struct TimestampCalibration {
+    pub host_instant: Instant,
+    pub gpu_timestamp: u64,
+    /// Nanoseconds per tick of the GPU timestamp counter.
+    pub timestamp_period: f64,
+    pub kind: TimestampCalibrationKind,
+    /// Bound on the distance between `host_instant` and the time the GPU
+    /// wrote `gpu_timestamp`, in nanoseconds. Zero if the sample is exact.
+    pub max_deviation_ns: u64,
+}
+
+/// Error returned by `queue_get_timestamp_calibration`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimestampCalibrationError {
+    /// The queue doesn't exist, or its device was destroyed.
+    InvalidQueue(id::QueueId),
+    /// The device doesn't support timestamp queries.
+    Unsupported,
+    /// The device was lost while waiting for the timestamps.
+    DeviceLost,
+}
+
+/// GPU timestamp written by a submission, and the host time span
+/// from submitting it to seeing it done.
+#[derive(Clone, Copy, Debug)]
+struct TimestampSample {
+    submitted: Instant,
+    done: Instant,
+    gpu_timestamp: u64,
+}
+
+impl TimestampSample {
+    fn host_instant(&self) -> Instant {
+        self.submitted + (self.done - self.submitted) / 2
+    }
+
+    fn max_deviation(&self) -> Duration {
+        (self.done - self.submitted) / 2
+    }
+}
+
+impl<B: GfxBackend> super::Device<B> {
+    /// Create a buffer in host-visible memory, filled with `data`, to copy from.
+    fn create_staging_buffer(
+        &self,
+        data: &[u8],
+    ) -> Result<StagingChunk<B>, super::AllocationError> {
+        let mut chunk = self.allocate_staging_buffer(data.len() as wgt::BufferAddress, false)?;
+        let mut mapped = chunk
+            .memory
+            .map(&self.raw, hal::memory::Segment::ALL)
+            .unwrap();
+        unsafe { mapped.write(&self.raw, hal::memory::Segment::ALL) }
+            .unwrap()
+            .slice[..data.len()]
+            .copy_from_slice(data);
+        Ok(chunk)
+    }
+
+    /// Get an unmapped buffer of at least `size` bytes in host-visible memory,
+    /// to copy from, or into if `read_back` is set.
+    ///
+    /// A free buffer of the staging belt is reused if there is one.
+    fn allocate_staging_buffer(
+        &self,
+        size: wgt::BufferAddress,
+        read_back: bool,
+    ) -> Result<StagingChunk<B>, super::AllocationError> {
+        if let Some(chunk) = self.staging_belt.lock().take(size, read_back) {
+            return Ok(chunk);
+        }
+
+        let size = super::staging::chunk_size(size);
+        let usage = if read_back {
+            hal::buffer::Usage::TRANSFER_DST
+        } else {
+            hal::buffer::Usage::TRANSFER_SRC
+        };
+        let mut raw = unsafe { self.raw.create_buffer(size, usage).unwrap() };
+        //TODO: do we need to transition into HOST_WRITE access first?
+        let requirements = unsafe { self.raw.get_buffer_requirements(&raw) };
+
+        let memory = match self.allocate_memory(
+            &requirements,
+            gfx_memory::MemoryUsage::Staging { read_back },
+            gfx_memory::Kind::Linear,
+            "staging buffer",
+        ) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe { self.raw.destroy_buffer(raw) };
+                return Err(e);
+            }
+        };
+        let name = if read_back {
+            "<read_buffer_temp>"
+        } else {
+            "<write_buffer_temp>"
+        };
+        unsafe {
+            self.raw.set_buffer_name(&mut raw, name);
+            self.raw
+                .bind_buffer_memory(memory.memory(), memory.segment().offset, &mut raw)
+                .unwrap();
+        }
+        Ok(StagingChunk {
+            raw,
+            memory,
+            size,
+            read_back,
+        })
+    }
+
+    /// Make room for one more submission, so that the bookkeeping of the work
+    /// in flight doesn't grow without limit.
+    ///
+    /// Waits for the oldest submission when there are `max_submissions_in_flight`
+    /// of them, or fails with `QueueFull` if the device is configured not to wait.
+    fn reserve_submission(&self, token: &mut Token<Self>) -> Result<(), QueueSubmitError> {
+        let mut life_tracker = self.lock_life(token);
+        life_tracker.triage_submissions(&self.raw, false);
+        if life_tracker.active_count() >= self.max_submissions_in_flight
+            && !self.lost.load(Ordering::Acquire)
+        {
+            if self.nonblocking_submit {
+                return Err(QueueSubmitError::QueueFull);
+            }
+            log::debug!(
+                "Queue has {} submissions in flight, waiting for the oldest",
+                life_tracker.active_count()
+            );
+            life_tracker.wait_for_oldest(&self.raw);
+            life_tracker.triage_submissions(&self.raw, false);
+        }
+        Ok(())
+    }
+
+    /// Submit the pending writes on their own, regardless of the submissions
+    /// in flight, and return the index of their submission, or `None` if
+    /// there was nothing to flush.
+    pub(super) fn flush_pending_writes(
+        &mut self,
+        token: &mut Token<Self>,
+    ) -> Option<crate::SubmissionIndex> {
+        let mut comb_raw = self.pending_writes.take_command_buffer()?;
+        unsafe {
+            comb_raw.finish();
+        }
+        // The writes already reserved the next submission index for the buffers they use.
+        let submit_index = 1 + self
+            .life_guard
+            .submission_index
+            .fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "trace")]
+        match *self.trace.lock() {
+            Some(ref mut trace) => trace.add(Action::FlushPendingWrites(submit_index)),
+            None => (),
+        }
+        #[cfg(feature = "counters")]
+        {
+            self.counters.lock().pending_write_flushes += 1;
+        }
+
+        let fence = self.raw.create_fence(false).unwrap();
+        unsafe {
+            self.queue_group.queues[0]
+                .submit_without_semaphores(iter::once(&comb_raw), Some(&fence));
+        }
+        self.com_allocator
+            .after_submit_internal(comb_raw, submit_index);
+
+        self.temp_suspected.clear();
+        super::Device::lock_life_internal(&self.life_tracker, token).track_submission(
+            submit_index,
+            fence,
+            &self.temp_suspected,
+            self.pending_writes.temp_buffers.drain(..),
+            self.pending_writes.readbacks.drain(..),
+            self.pending_writes.work_done.drain(..),
+        );
+        Some(submit_index)
+    }
+
+    /// Record a copy of `range` of the buffer into a new buffer in host-visible memory,
+    /// after the pending writes, and leave the pending command buffer open.
+    fn record_readback(
+        &mut self,
+        buffer_guard: &Storage<Buffer<B>, id::BufferId>,
+        buffer_id: id::BufferId,
+        range: Range<wgt::BufferAddress>,
+    ) -> Result<StagingChunk<B>, super::AllocationError> {
+        let src = &buffer_guard[buffer_id];
+        let size = range.end - range.start;
+        let chunk = self.allocate_staging_buffer(size, true)?;
+
+        let mut trackers = self.trackers.lock();
+        let (_, transition) =
+            trackers
+                .buffers
+                .use_replace(buffer_guard, buffer_id, (), BufferUse::COPY_SRC);
+
+        // Pending writes have to land before the readback, so record after them.
+        let mut comb = match self.pending_writes.command_buffer.take() {
+            Some(comb) => comb,
+            None => {
+                let mut comb = self.com_allocator.allocate_internal();
+                unsafe {
+                    comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+                }
+                comb
             }
+        };
+        let region = hal::command::BufferCopy {
+            src: range.start,
+            dst: 0,
+            size,
+        };
+        unsafe {
+            comb.pipeline_barrier(
+                super::all_buffer_stages()..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                transition.map(|pending| pending.into_hal(src)),
+            );
+            comb.copy_buffer(&src.raw, &chunk.raw, iter::once(region));
+            comb.pipeline_barrier(
+                hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::HOST,
+                hal::memory::Dependencies::empty(),
+                iter::once(hal::memory::Barrier::Buffer {
+                    states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::HOST_READ,
+                    target: &chunk.raw,
+                    range: hal::buffer::SubRange::WHOLE,
+                    families: None,
+                }),
+            );
         }
+        self.pending_writes.command_buffer = Some(comb);
+        Ok(chunk)
+    }
+
+    /// Record a copy of a texture region into a new staging buffer, laid out
+    /// as `layout`, after the pending writes.
+    fn record_texture_readback(
+        &mut self,
+        texture_guard: &Storage<Texture<B>, id::TextureId>,
+        source: &TextureCopyView,
+        size: wgt::Extent3d,
+        layout: &TextureDataLayout,
+    ) -> Result<StagingChunk<B>, super::AllocationError> {
+        let src = &texture_guard[source.texture];
+        let chunk = self.allocate_staging_buffer(layout.required_size, true)?;
+
+        let format_desc =
+            conv::map_texture_format(src.format, self.private_features).surface_desc();
+        let block_width = u32::from(format_desc.dim.0);
+        let bytes_per_block = u32::from(format_desc.bits / 8);
+        let level = source.mip_level as hal::image::Level;
+        let layer = source.array_layer as hal::image::Layer;
+        #[allow(clippy::range_plus_one)]
+        let (levels, layers) = (level..level + 1, layer..layer + 1);
+        let aspects = src.full_range.aspects;
+
+        let mut trackers = self.trackers.lock();
+        let (_, transition) = trackers.textures.use_replace(
+            texture_guard,
+            source.texture,
+            hal::image::SubresourceRange {
+                aspects,
+                levels,
+                layers: layers.clone(),
+            },
+            TextureUse::COPY_SRC,
+        );
+        let last_submit_index = self.life_guard.submission_index.load(Ordering::Relaxed);
+        src.life_guard.use_at(last_submit_index + 1);
+
+        let mut comb = match self.pending_writes.command_buffer.take() {
+            Some(comb) => comb,
+            None => {
+                let mut comb = self.com_allocator.allocate_internal();
+                unsafe {
+                    comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+                }
+                comb
+            }
+        };
+        let region = hal::command::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_width: layout.bytes_per_row / bytes_per_block * block_width,
+            buffer_height: layout.rows_per_image,
+            image_layers: hal::image::SubresourceLayers {
+                aspects,
+                level,
+                layers,
+            },
+            image_offset: conv::map_origin(source.origin),
+            image_extent: conv::map_extent(size),
+        };
+        unsafe {
+            comb.pipeline_barrier(
+                super::all_image_stages()..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                transition.map(|pending| pending.into_hal(src)),
+            );
+            comb.copy_image_to_buffer(
+                &src.raw,
+                hal::image::Layout::TransferSrcOptimal,
+                &chunk.raw,
+                iter::once(region),
+            );
+            comb.pipeline_barrier(
+                hal::pso::PipelineStage::TRANSFER..hal::pso::PipelineStage::HOST,
+                hal::memory::Dependencies::empty(),
+                iter::once(hal::memory::Barrier::Buffer {
+                    states: hal::buffer::Access::TRANSFER_WRITE..hal::buffer::Access::HOST_READ,
+                    target: &chunk.raw,
+                    range: hal::buffer::SubRange::WHOLE,
+                    families: None,
+                }),
+            );
+        }
+        self.pending_writes.command_buffer = Some(comb);
+        Ok(chunk)
+    }
+}
+
+impl<G: GlobalIdentityHandlerFactory> Global<G> {
+    /// Write `data` into the buffer at `buffer_offset`, through a staging buffer.
+    ///
+    /// The copy is recorded into the pending writes of the queue, which only
+    /// get submitted by the next `queue_submit` or `queue_flush_pending_writes`.
+    /// There, the pending writes execute before the command buffers of that
+    /// submission, in the order they were made, and after all the work of the
+    /// earlier submissions. So a write made right after a `queue_submit` does
+    /// not go along with that submission, but is still ordered after it.
+    pub fn queue_write_buffer<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        data: &[u8],
+        buffer_id: id::BufferId,
+        buffer_offset: wgt::BufferAddress,
+    ) -> Result<(), QueueBufferError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = device_guard
+            .get_mut(queue_id)
+            .ok_or(QueueBufferError::InvalidQueue(queue_id))?;
+        let (buffer_guard, _) = hub.buffers.read(&mut token);
+        let buffer = buffer_guard
+            .get(buffer_id)
+            .ok_or(QueueBufferError::InvalidBuffer(buffer_id))?;
+        super::check_device(queue_id, buffer_id, buffer.device_id.value)?;
+
+        #[cfg(feature = "trace")]
+        match *device.trace.lock() {
+            Some(ref mut trace) => {
+                let data_path = trace.make_binary("bin", data);
+                trace.add(Action::WriteBuffer {
+                    id: buffer_id,
+                    data: data_path,
+                    range: buffer_offset..buffer_offset + data.len() as wgt::BufferAddress,
+                    queued: true,
+                });
+            }
+            None => {}
+        }
+
+        // Writes that don't fit into a single staging allocation are split,
+        // and the chunks allocated so far are freed if one of them fails.
+        let chunk_size = device.staging_chunk_size as usize;
+        let mut chunks = Vec::with_capacity((data.len() + chunk_size - 1) / chunk_size);
+        for chunk in data.chunks(chunk_size) {
+            match device.create_staging_buffer(chunk) {
+                Ok(staging) => chunks.push(staging),
+                Err(e) => {
+                    let mut mem_allocator = device.mem_allocator.lock();
+                    let mut staging_belt = device.staging_belt.lock();
+                    for staging in chunks {
+                        staging_belt.recycle(&device.raw, &mut mem_allocator, staging);
+                    }
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let mut trackers = device.trackers.lock();
+        let (dst, transition) =
+            trackers
+                .buffers
+                .use_replace(&*buffer_guard, buffer_id, (), BufferUse::COPY_DST);
+        assert!(
+            dst.usage.contains(wgt::BufferUsage::COPY_DST),
+            "Write buffer usage {:?} must contain usage flag DST_SRC",
+            dst.usage
+        );
+
+        let last_submit_index = device.life_guard.submission_index.load(Ordering::Relaxed);
+        dst.life_guard.use_at(last_submit_index + 1);
+
+        let write_barrier = device.pending_writes.write_barrier(
+            buffer_id,
+            &dst.raw,
+            buffer_offset..buffer_offset + data.len() as wgt::BufferAddress,
+        );
+        let mut comb = match device.pending_writes.command_buffer.take() {
+            Some(comb) => comb,
+            None => {
+                let mut comb = device.com_allocator.allocate_internal();
+                unsafe {
+                    comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+                }
+                comb
+            }
+        };
+        unsafe {
+            comb.pipeline_barrier(
+                super::all_buffer_stages()..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                chunks
+                    .iter()
+                    .map(|staging| hal::memory::Barrier::Buffer {
+                        states: hal::buffer::Access::HOST_WRITE..hal::buffer::Access::TRANSFER_READ,
+                        target: &staging.raw,
+                        range: hal::buffer::SubRange::WHOLE,
+                        families: None,
+                    })
+                    .chain(transition.map(|pending| pending.into_hal(dst)))
+                    .chain(write_barrier),
+            );
+        }
+        for (index, (staging, chunk)) in chunks.into_iter().zip(data.chunks(chunk_size)).enumerate()
+        {
+            let region = hal::command::BufferCopy {
+                src: 0,
+                dst: buffer_offset + (index * chunk_size) as wgt::BufferAddress,
+                size: chunk.len() as wgt::BufferAddress,
+            };
+            unsafe {
+                comb.copy_buffer(&staging.raw, &dst.raw, iter::once(region));
+            }
+            device.pending_writes.temp_buffers.push(staging);
+        }
+        device.pending_writes.command_buffer = Some(comb);
+        #[cfg(feature = "counters")]
+        {
+            device.counters.lock().uploaded_bytes += data.len() as wgt::BufferAddress;
+        }
+        Ok(())
+    }
+
+    /// Write a batch of regions of `data` into the texture, through a single staging buffer.
+    ///
+    /// Each region is read from `data` at its `data_offset`, with the rows and slices
+    /// spaced as described by `layout`, and is written at its origin in the given
+    /// mip level and array layer. The rows are packed tightly in the staging memory,
+    /// and all the regions are uploaded by a single copy command, after one transition
+    /// of the subresources they cover. This is meant for many small updates of a large
+    /// texture, like glyphs of an atlas.
+    ///
+    /// All the regions are validated before anything is written. The copy becomes
+    /// a pending write of the queue, ordered like the ones of `queue_write_buffer`.
+    pub fn queue_write_texture_regions<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        texture_id: id::TextureId,
+        regions: &[TextureWriteRegion],
+        data: &[u8],
+        layout: TextureWriteLayout,
+    ) -> Result<(), TextureWriteError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = device_guard
+            .get_mut(queue_id)
+            .ok_or(TextureWriteError::InvalidQueue(queue_id))?;
+        let (texture_guard, _) = hub.textures.read(&mut token);
+        let texture = texture_guard
+            .get(texture_id)
+            .ok_or(TextureWriteError::InvalidTexture(texture_id))?;
+        if !texture.usage.contains(wgt::TextureUsage::COPY_DST) {
+            return Err(TextureWriteError::MissingCopyDstUsage(texture.usage));
+        }
+        super::check_device(queue_id, texture_id, texture.device_id.value)?;
+
+        let format_desc =
+            conv::map_texture_format(texture.format, device.private_features).surface_desc();
+        let (block_width, block_height) =
+            (u32::from(format_desc.dim.0), u32::from(format_desc.dim.1));
+        let bytes_per_block = wgt::BufferAddress::from(format_desc.bits / 8);
+        // Staging offsets of the regions have to be aligned to both the copy
+        // alignment and the block size, which are both powers of two.
+        let region_alignment = bytes_per_block.max(wgt::COPY_BUFFER_ALIGNMENT);
+        let bytes_per_row = wgt::BufferAddress::from(layout.bytes_per_row);
+
+        // Block counts and staging offset of each region, in the region order.
+        let mut packed = Vec::with_capacity(regions.len());
+        let mut staging_size = 0;
+        for (index, region) in regions.iter().enumerate() {
+            let view = TextureCopyView {
+                texture: texture_id,
+                mip_level: region.mip_level,
+                array_layer: region.array_layer,
+                origin: region.origin,
+            };
+            validate_texture_copy_range(
+                &view,
+                texture.kind.extent(),
+                texture.full_range.levels.end,
+                format_desc.dim,
+                &region.size,
+            )
+            .map_err(|error| TextureWriteError::Region { index, error })?;
+            let layer_count = u32::from(texture.full_range.layers.end);
+            if region.array_layer >= layer_count {
+                return Err(TextureWriteError::InvalidArrayLayer {
+                    index,
+                    layer: region.array_layer,
+                    layer_count,
+                });
+            }
+
+            let blocks_per_row = region.size.width / block_width;
+            let block_rows = region.size.height / block_height;
+            let row_size = wgt::BufferAddress::from(blocks_per_row) * bytes_per_block;
+            if row_size > bytes_per_row {
+                return Err(TextureWriteError::RowTooLong {
+                    index,
+                    row_size,
+                    bytes_per_row: layout.bytes_per_row,
+                });
+            }
+            let rows_per_image = match layout.rows_per_image {
+                0 => block_rows,
+                rows => rows,
+            };
+            let depth = wgt::BufferAddress::from(region.size.depth);
+            let block_rows = wgt::BufferAddress::from(block_rows);
+            if row_size == 0 || block_rows == 0 || depth == 0 {
+                packed.push(None);
+                continue;
+            }
+            let read_size = (depth - 1) * wgt::BufferAddress::from(rows_per_image) * bytes_per_row
+                + (block_rows - 1) * bytes_per_row
+                + row_size;
+            let end = region.data_offset.checked_add(read_size);
+            if end.map_or(true, |end| end > data.len() as wgt::BufferAddress) {
+                return Err(TextureWriteError::DataOutOfBounds {
+                    index,
+                    end: end.unwrap_or(!0),
+                    data_size: data.len() as wgt::BufferAddress,
+                });
+            }
+
+            let offset =
+                (staging_size + region_alignment - 1) / region_alignment * region_alignment;
+            staging_size = offset + row_size * block_rows * depth;
+            packed.push(Some((offset, rows_per_image, row_size, block_rows)));
+        }
+
+        #[cfg(feature = "trace")]
+        match *device.trace.lock() {
+            Some(ref mut trace) => {
+                let data_path = trace.make_binary("bin", data);
+                trace.add(Action::WriteTexture {
+                    id: texture_id,
+                    data: data_path,
+                    regions: regions.to_vec(),
+                    layout,
+                });
+            }
+            None => {}
+        }
+
+        if staging_size == 0 {
+            return Ok(());
+        }
+        let mut src_chunk = device
+            .allocate_staging_buffer(staging_size, false)
+            .map_err(TextureWriteError::Allocation)?;
+        {
+            let mut mapped = src_chunk
+                .memory
+                .map(&device.raw, hal::memory::Segment::ALL)
+                .unwrap();
+            let mut writer =
+                unsafe { mapped.write(&device.raw, hal::memory::Segment::ALL) }.unwrap();
+            let staging = &mut *writer.slice;
+            for (region, packing) in regions.iter().zip(&packed) {
+                let (offset, rows_per_image, row_size, block_rows) = match *packing {
+                    Some(packing) => packing,
+                    None => continue,
+                };
+                let row_size = row_size as usize;
+                let mut dst = offset as usize;
+                for z in 0..wgt::BufferAddress::from(region.size.depth) {
+                    let slice_start = region.data_offset
+                        + z * wgt::BufferAddress::from(rows_per_image) * bytes_per_row;
+                    for y in 0..block_rows {
+                        let src = (slice_start + y * bytes_per_row) as usize;
+                        staging[dst..dst + row_size].copy_from_slice(&data[src..src + row_size]);
+                        dst += row_size;
+                    }
+                }
+            }
+        }
+
+        // One transition for all the subresources touched by the regions.
+        let mut levels = hal::image::Level::max_value()..0;
+        let mut layers = hal::image::Layer::max_value()..0;
+        for (region, _) in regions.iter().zip(&packed).filter(|&(_, p)| p.is_some()) {
+            let level = region.mip_level as hal::image::Level;
+            let layer = region.array_layer as hal::image::Layer;
+            levels = levels.start.min(level)..levels.end.max(level + 1);
+            layers = layers.start.min(layer)..layers.end.max(layer + 1);
+        }
+        let aspects = texture.full_range.aspects;
+        let mut trackers = device.trackers.lock();
+        let (dst, transition) = trackers.textures.use_replace(
+            &*texture_guard,
+            texture_id,
+            hal::image::SubresourceRange {
+                aspects,
+                levels,
+                layers,
+            },
+            TextureUse::COPY_DST,
+        );
+
+        let last_submit_index = device.life_guard.submission_index.load(Ordering::Relaxed);
+        dst.life_guard.use_at(last_submit_index + 1);
+
+        let copies = regions
+            .iter()
+            .zip(&packed)
+            .filter_map(|(region, packing)| {
+                let (offset, _, row_size, block_rows) = (*packing)?;
+                let layer = region.array_layer as hal::image::Layer;
+                #[allow(clippy::range_plus_one)]
+                let layers = layer..layer + 1;
+                Some(hal::command::BufferImageCopy {
+                    buffer_offset: offset,
+                    buffer_width: (row_size / bytes_per_block) as u32 * block_width,
+                    buffer_height: block_rows as u32 * block_height,
+                    image_layers: hal::image::SubresourceLayers {
+                        aspects,
+                        level: region.mip_level as hal::image::Level,
+                        layers,
+                    },
+                    image_offset: conv::map_origin(region.origin),
+                    image_extent: conv::map_extent(region.size),
+                })
+            })
+            .collect::<SmallVec<[_; 8]>>();
+
+        let mut comb = match device.pending_writes.command_buffer.take() {
+            Some(comb) => comb,
+            None => {
+                let mut comb = device.com_allocator.allocate_internal();
+                unsafe {
+                    comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+                }
+                comb
+            }
+        };
+        unsafe {
+            comb.pipeline_barrier(
+                super::all_buffer_stages() | super::all_image_stages()
+                    ..hal::pso::PipelineStage::TRANSFER,
+                hal::memory::Dependencies::empty(),
+                iter::once(hal::memory::Barrier::Buffer {
+                    states: hal::buffer::Access::HOST_WRITE..hal::buffer::Access::TRANSFER_READ,
+                    target: &src_chunk.raw,
+                    range: hal::buffer::SubRange::WHOLE,
+                    families: None,
+                })
+                .chain(transition.map(|pending| pending.into_hal(dst))),
+            );
+            comb.copy_buffer_to_image(
+                &src_chunk.raw,
+                &dst.raw,
+                hal::image::Layout::TransferDstOptimal,
+                copies,
+            );
+        }
+        device.pending_writes.temp_buffers.push(src_chunk);
+        device.pending_writes.command_buffer = Some(comb);
+        #[cfg(feature = "counters")]
+        {
+            device.counters.lock().uploaded_bytes += staging_size;
+        }
+        Ok(())
+    }
+
+    /// Create a staging buffer of `size` bytes, and return it mapped for writing.
+    ///
+    /// The memory is meant to be filled sequentially, since it's usually
+    /// write-combined, and reading from it is slow. The pointer stays valid
+    /// until the staging buffer is written with `queue_write_staging_buffer`,
+    /// or destroyed.
+    pub fn queue_create_staging_buffer<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        size: wgt::BufferAddress,
+        id_in: Input<G, id::StagingBufferId>,
+    ) -> Result<(id::StagingBufferId, *mut u8), QueueBufferError> {
+        assert_ne!(size, 0, "Staging buffer size must not be zero");
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = device_guard
+            .get(queue_id)
+            .ok_or(QueueBufferError::InvalidQueue(queue_id))?;
+
+        let mut chunk = device.allocate_staging_buffer(size, false)?;
+        let (ptr, segment, needs_sync) = {
+            let mapped = chunk
+                .memory
+                .map(&device.raw, hal::memory::Segment::ALL)
+                .unwrap();
+            let mr = mapped.range();
+            let segment = hal::memory::Segment {
+                offset: mr.start,
+                size: Some(mr.end - mr.start),
+            };
+            (mapped.ptr().as_ptr(), segment, !mapped.is_coherent())
+        };
+        let staging = StagingBuffer {
+            device_id: Stored {
+                value: queue_id,
+                ref_count: device.life_guard.add_ref(),
+            },
+            chunk,
+            size,
+            ptr,
+            sync_mapped_writes: if needs_sync { Some(segment) } else { None },
+        };
+        let id = hub
+            .staging_buffers
+            .register_identity(id_in, staging, &mut token);
+        Ok((id, ptr))
     }
-}
 
-impl<G: GlobalIdentityHandlerFactory> Global<G> {
-    pub fn queue_write_buffer<B: GfxBackend>(
+    /// Copy the contents of a staging buffer into `buffer_id` at `buffer_offset`,
+    /// as a pending write of the queue, like `queue_write_buffer`.
+    ///
+    /// The staging buffer is consumed, and its memory goes back to the
+    /// staging belt of the device once the next submission is done.
+    pub fn queue_write_staging_buffer<B: GfxBackend>(
         &self,
         queue_id: id::QueueId,
-        data: &[u8],
+        staging_id: id::StagingBufferId,
         buffer_id: id::BufferId,
         buffer_offset: wgt::BufferAddress,
-    ) {
+    ) -> Result<(), StagingBufferError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        if !device_guard.contains(queue_id) {
+            return Err(StagingBufferError::InvalidQueue(queue_id));
+        }
+        let (staging, mut token) =
+            take_staging_buffer(&hub.staging_buffers, staging_id, &mut token)?;
+        if let Err(e) = super::check_device(queue_id, staging_id, staging.device_id.value) {
+            // The memory is freed by the device that allocated it, if it's still alive.
+            if let Some(owner) = device_guard.get(staging.device_id.value) {
+                owner.destroy_staging_buffer(staging);
+            }
+            return Err(e.into());
+        }
         let device = &mut device_guard[queue_id];
         let (buffer_guard, _) = hub.buffers.read(&mut token);
 
+        {
+            let buffer = match buffer_guard.get(buffer_id) {
+                Some(buffer) => buffer,
+                None => {
+                    device.destroy_staging_buffer(staging);
+                    return Err(StagingBufferError::InvalidBuffer(buffer_id));
+                }
+            };
+            if let Err(e) = super::check_device(queue_id, buffer_id, buffer.device_id.value) {
+                device.destroy_staging_buffer(staging);
+                return Err(e.into());
+            }
+            let buffer_size = buffer.size;
+            if buffer_offset > buffer_size || staging.size > buffer_size - buffer_offset {
+                device.destroy_staging_buffer(staging);
+                return Err(StagingBufferError::OutOfBounds {
+                    size: staging.size,
+                    offset: buffer_offset,
+                    buffer_size,
+                });
+            }
+        }
+
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => {
-                let mut trace = trace.lock();
+        match *device.trace.lock() {
+            Some(ref mut trace) => {
+                let data =
+                    unsafe { std::slice::from_raw_parts(staging.ptr, staging.size as usize) };
                 let data_path = trace.make_binary("bin", data);
                 trace.add(Action::WriteBuffer {
                     id: buffer_id,
                     data: data_path,
-                    range: buffer_offset..buffer_offset + data.len() as wgt::BufferAddress,
+                    range: buffer_offset..buffer_offset + staging.size,
                     queued: true,
                 });
             }
             None => {}
         }
 
+        if let Some(segment) = staging.sync_mapped_writes.clone() {
+            unsafe {
+                device
+                    .raw
+                    .flush_mapped_memory_ranges(iter::once((
+                        staging.chunk.memory.memory(),
+                        segment,
+                    )))
+                    .unwrap()
+            };
+        }
+
         let mut trackers = device.trackers.lock();
         let (dst, transition) =
             trackers
@@ -84,51 +1296,18 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 .use_replace(&*buffer_guard, buffer_id, (), BufferUse::COPY_DST);
         assert!(
             dst.usage.contains(wgt::BufferUsage::COPY_DST),
-            "Write buffer usage {:?} must contain usage flag DST_SRC",
+            "Write buffer usage {:?} must contain usage flag COPY_DST",
             dst.usage
         );
 
         let last_submit_index = device.life_guard.submission_index.load(Ordering::Relaxed);
         dst.life_guard.use_at(last_submit_index + 1);
 
-        let mut src_raw = unsafe {
-            device
-                .raw
-                .create_buffer(
-                    data.len() as wgt::BufferAddress,
-                    hal::buffer::Usage::TRANSFER_SRC,
-                )
-                .unwrap()
-        };
-        //TODO: do we need to transition into HOST_WRITE access first?
-        let requirements = unsafe { device.raw.get_buffer_requirements(&src_raw) };
-
-        let mut memory = device
-            .mem_allocator
-            .lock()
-            .allocate(
-                &device.raw,
-                &requirements,
-                gfx_memory::MemoryUsage::Staging { read_back: false },
-                gfx_memory::Kind::Linear,
-            )
-            .unwrap();
-        unsafe {
-            device
-                .raw
-                .set_buffer_name(&mut src_raw, "<write_buffer_temp>");
-            device
-                .raw
-                .bind_buffer_memory(memory.memory(), memory.segment().offset, &mut src_raw)
-                .unwrap();
-        }
-
-        let mut mapped = memory.map(&device.raw, hal::memory::Segment::ALL).unwrap();
-        unsafe { mapped.write(&device.raw, hal::memory::Segment::ALL) }
-            .unwrap()
-            .slice[..data.len()]
-            .copy_from_slice(data);
-
+        let write_barrier = device.pending_writes.write_barrier(
+            buffer_id,
+            &dst.raw,
+            buffer_offset..buffer_offset + staging.size,
+        );
         let mut comb = match device.pending_writes.command_buffer.take() {
             Some(comb) => comb,
             None => {
@@ -142,7 +1321,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let region = hal::command::BufferCopy {
             src: 0,
             dst: buffer_offset,
-            size: data.len() as _,
+            size: staging.size,
         };
         unsafe {
             comb.pipeline_barrier(
@@ -150,34 +1329,603 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 hal::memory::Dependencies::empty(),
                 iter::once(hal::memory::Barrier::Buffer {
                     states: hal::buffer::Access::HOST_WRITE..hal::buffer::Access::TRANSFER_READ,
-                    target: &src_raw,
+                    target: &staging.chunk.raw,
                     range: hal::buffer::SubRange::WHOLE,
                     families: None,
                 })
-                .chain(transition.map(|pending| pending.into_hal(dst))),
+                .chain(transition.map(|pending| pending.into_hal(dst)))
+                .chain(write_barrier),
             );
-            comb.copy_buffer(&src_raw, &dst.raw, iter::once(region));
+            comb.copy_buffer(&staging.chunk.raw, &dst.raw, iter::once(region));
+        }
+        #[cfg(feature = "counters")]
+        {
+            device.counters.lock().uploaded_bytes += staging.size;
         }
-        device.pending_writes.temp_buffers.push((src_raw, memory));
+        device.pending_writes.temp_buffers.push(staging.chunk);
         device.pending_writes.command_buffer = Some(comb);
+        Ok(())
+    }
+
+    /// Destroy a staging buffer without writing it anywhere.
+    pub fn staging_buffer_destroy<B: GfxBackend>(&self, staging_id: id::StagingBufferId) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let staging = match take_staging_buffer(&hub.staging_buffers, staging_id, &mut token) {
+            Ok((staging, _)) => staging,
+            Err(_) => {
+                log::error!("Destroying an invalid staging buffer {:?}", staging_id);
+                return;
+            }
+        };
+        device_guard[staging.device_id.value].destroy_staging_buffer(staging);
+    }
+
+    /// Copy a range of a buffer into the same range of a buffer owned by another device.
+    ///
+    /// The data is read back from the source device, handed over on the host, and then
+    /// uploaded on the destination queue along with its pending writes, which are flushed.
+    /// The readback is submitted to the source queue after all of its prior work, and
+    /// waited for.
+    ///
+    /// The returned token points to the destination queue submission that carries the upload,
+    /// which is also waited for by a `queue_on_submitted_work_done` on that queue.
+    pub fn queue_copy_buffer_across_devices<B: GfxBackend>(
+        &self,
+        src_queue_id: id::QueueId,
+        src_buffer_id: id::BufferId,
+        dst_queue_id: id::QueueId,
+        dst_buffer_id: id::BufferId,
+        range: Range<wgt::BufferAddress>,
+    ) -> Result<SubmissionToken, QueueBufferError> {
+        if src_queue_id == dst_queue_id {
+            return Err(QueueBufferError::SameQueue(src_queue_id));
+        }
+        let hub = B::hub(self);
+
+        {
+            let mut token = Token::root();
+            let (device_guard, mut token) = hub.devices.read(&mut token);
+            for &queue_id in &[src_queue_id, dst_queue_id] {
+                if !device_guard.contains(queue_id) {
+                    return Err(QueueBufferError::InvalidQueue(queue_id));
+                }
+            }
+            let (buffer_guard, _) = hub.buffers.read(&mut token);
+            let src = buffer_guard
+                .get(src_buffer_id)
+                .ok_or(QueueBufferError::InvalidBuffer(src_buffer_id))?;
+            super::check_device(src_queue_id, src_buffer_id, src.device_id.value)?;
+            validate_read_range(src_buffer_id, src.usage, src.size, &src.map_state, &range)?;
+            let dst = buffer_guard
+                .get(dst_buffer_id)
+                .ok_or(QueueBufferError::InvalidBuffer(dst_buffer_id))?;
+            super::check_device(dst_queue_id, dst_buffer_id, dst.device_id.value)?;
+            if !dst.usage.contains(wgt::BufferUsage::COPY_DST) {
+                return Err(QueueBufferError::MissingCopyDstUsage(dst.usage));
+            }
+            validate_copy_range(dst.size, &range)?;
+        }
+
+        let data =
+            self.read_buffer_through_staging::<B>(src_queue_id, src_buffer_id, range.clone())?;
+        if !data.is_empty() {
+            self.queue_write_buffer::<B>(dst_queue_id, &data, dst_buffer_id, range.start)?;
+        }
+
+        // The index is taken from the flush itself, so that submissions
+        // racing with this copy can't shift it.
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = device_guard
+            .get_mut(dst_queue_id)
+            .ok_or(QueueBufferError::InvalidQueue(dst_queue_id))?;
+        if device.pending_writes.command_buffer.is_some() {
+            device
+                .reserve_submission(&mut token)
+                .map_err(QueueBufferError::Submit)?;
+        }
+        let index = match device.flush_pending_writes(&mut token) {
+            Some(index) => index,
+            // The upload already went along with a submission in between, if any.
+            None => device.life_guard.submission_index.load(Ordering::Acquire),
+        };
+        Ok(SubmissionToken {
+            queue_id: dst_queue_id,
+            index,
+        })
+    }
+
+    /// Check if the GPU is done with the submission identified by `submission`.
+    pub fn queue_is_submission_done<B: GfxBackend>(
+        &self,
+        submission: SubmissionToken,
+    ) -> Result<bool, QueueSubmitError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = device_guard
+            .get(submission.queue_id)
+            .ok_or(QueueSubmitError::InvalidQueue(submission.queue_id))?;
+        let last_submit_index = device.life_guard.submission_index.load(Ordering::Acquire);
+        Ok(last_submit_index >= submission.index
+            && device.lock_life(&mut token).lowest_active_submission() > submission.index)
+    }
+
+    /// Call `callback` once the device is done with all the work submitted
+    /// to the queue so far, as well as the pending writes.
+    ///
+    /// Like the buffer mappings, the callback is fired by a `device_poll` seeing
+    /// the work done. It gets `DeviceLost` instead if the device is lost or
+    /// destroyed before that.
+    pub fn queue_on_submitted_work_done<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        callback: super::SubmittedWorkDoneCallback,
+        userdata: *mut u8,
+    ) -> Result<(), QueueSubmitError> {
+        let closure = super::SubmittedWorkDoneClosure { callback, userdata };
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = device_guard
+            .get_mut(queue_id)
+            .ok_or(QueueSubmitError::InvalidQueue(queue_id))?;
+        if device.pending_writes.command_buffer.is_some() {
+            device.pending_writes.work_done.push(closure);
+        } else {
+            device.lock_life(&mut token).add_work_done(closure);
+        }
+        Ok(())
+    }
+
+    /// Wait until everything submitted to the queue so far is done executing.
+    ///
+    /// Returns false if that didn't happen within `timeout_ms`. Unlike
+    /// `device_poll`, this doesn't free any resources or fire any callbacks,
+    /// so it's fine to call from a destructor.
+    pub fn queue_wait_idle<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        timeout_ms: u32,
+    ) -> Result<bool, QueueSubmitError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let device = device_guard
+            .get(queue_id)
+            .ok_or(QueueSubmitError::InvalidQueue(queue_id))?;
+        Ok(device
+            .lock_life(&mut token)
+            .wait_for_submissions(&device.raw, timeout_ms as u64 * 1_000_000))
+    }
+
+    /// Sample the host clock along with the GPU timestamp counter, to line up
+    /// the results of timestamp queries with host-side traces.
+    ///
+    /// None of the backends expose calibrated timestamps yet, so the result
+    /// is always `Estimated`: timestamps are written by tiny submissions
+    /// before and after `CALIBRATION_INTERVAL`, which the timestamp period
+    /// is measured over. This blocks the calling thread for that long.
+    pub fn queue_get_timestamp_calibration<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+    ) -> Result<TimestampCalibration, TimestampCalibrationError> {
+        let first = self.sample_timestamps::<B>(queue_id)?;
+        thread::sleep(CALIBRATION_INTERVAL);
+        let second = self.sample_timestamps::<B>(queue_id)?;
+
+        let ticks = second.gpu_timestamp.wrapping_sub(first.gpu_timestamp);
+        if ticks == 0 {
+            // the counter doesn't advance
+            return Err(TimestampCalibrationError::Unsupported);
+        }
+        let host_ns = (second.host_instant() - first.host_instant()).as_nanos();
+        Ok(TimestampCalibration {
+            host_instant: second.host_instant(),
+            gpu_timestamp: second.gpu_timestamp,
+            timestamp_period: host_ns as f64 / ticks as f64,
+            kind: TimestampCalibrationKind::Estimated,
+            max_deviation_ns: second.max_deviation().as_nanos() as u64,
+        })
+    }
+
+    /// Take `CALIBRATION_SAMPLES` timestamp samples, and keep the tightest one.
+    fn sample_timestamps<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+    ) -> Result<TimestampSample, TimestampCalibrationError> {
+        let mut best = self.sample_timestamp::<B>(queue_id)?;
+        for _ in 1..CALIBRATION_SAMPLES {
+            let sample = self.sample_timestamp::<B>(queue_id)?;
+            if sample.max_deviation() < best.max_deviation() {
+                best = sample;
+            }
+        }
+        Ok(best)
+    }
+
+    /// Submit a command buffer that only writes a timestamp, and wait for it.
+    ///
+    /// The devices are only locked for writing while submitting, so that
+    /// other threads can keep using the device during the wait.
+    fn sample_timestamp<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+    ) -> Result<TimestampSample, TimestampCalibrationError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+
+        let (pool, comb, fence, submitted) = {
+            let (mut device_guard, _) = hub.devices.write(&mut token);
+            let device = device_guard
+                .get_mut(queue_id)
+                .ok_or(TimestampCalibrationError::InvalidQueue(queue_id))?;
+            let pool = unsafe { device.raw.create_query_pool(hal::query::Type::Timestamp, 1) }
+                .map_err(|_| TimestampCalibrationError::Unsupported)?;
+            let fence = device.raw.create_fence(false).unwrap();
+            let mut comb = device.com_allocator.allocate_internal();
+            unsafe {
+                comb.begin_primary(hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+                comb.reset_query_pool(&pool, 0..1);
+                comb.write_timestamp(
+                    hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+                    hal::query::Query { pool: &pool, id: 0 },
+                );
+                comb.finish();
+            }
+            let submitted = Instant::now();
+            unsafe {
+                device.queue_group.queues[0]
+                    .submit_without_semaphores(iter::once(&comb), Some(&fence));
+            }
+            (pool, comb, fence, submitted)
+        };
+
+        let (device_guard, _) = hub.devices.read(&mut token);
+        // If the device went away in the meantime, so did the objects above.
+        let device = device_guard
+            .get(queue_id)
+            .ok_or(TimestampCalibrationError::InvalidQueue(queue_id))?;
+        let waited = unsafe { device.raw.wait_for_fence(&fence, !0) };
+        let done = Instant::now();
+        let mut data = [0u8; 8];
+        let result = match waited {
+            Ok(true) => unsafe {
+                device.raw.get_query_pool_results(
+                    &pool,
+                    0..1,
+                    &mut data,
+                    data.len() as hal::buffer::Offset,
+                    hal::query::ResultFlags::BITS_64 | hal::query::ResultFlags::WAIT,
+                )
+            },
+            Ok(false) | Err(_) => Ok(false),
+        };
+        unsafe {
+            device.raw.destroy_fence(fence);
+            device.raw.destroy_query_pool(pool);
+        }
+        device.com_allocator.discard_internal(comb);
+
+        match result {
+            Ok(true) => Ok(TimestampSample {
+                submitted,
+                done,
+                gpu_timestamp: u64::from_ne_bytes(data),
+            }),
+            Ok(false) | Err(_) => Err(TimestampCalibrationError::DeviceLost),
+        }
+    }
+
+    /// Copy a buffer range into host memory, waiting for the device to get there.
+    ///
+    /// The copy goes out as a regular submission of the pending writes. The
+    /// device is only locked for slices of `READBACK_WAIT_SLICE` while waiting.
+    fn read_buffer_through_staging<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        buffer_id: id::BufferId,
+        range: Range<wgt::BufferAddress>,
+    ) -> Result<Vec<u8>, QueueBufferError> {
+        let hub = B::hub(self);
+
+        let (size, mut chunk, submit_index) = {
+            let mut token = Token::root();
+            let (mut device_guard, mut token) = hub.devices.write(&mut token);
+            let device = device_guard
+                .get_mut(queue_id)
+                .ok_or(QueueBufferError::InvalidQueue(queue_id))?;
+            {
+                let (buffer_guard, _) = hub.buffers.read(&mut token);
+                let src = buffer_guard
+                    .get(buffer_id)
+                    .ok_or(QueueBufferError::InvalidBuffer(buffer_id))?;
+                super::check_device(queue_id, buffer_id, src.device_id.value)?;
+                validate_read_range(buffer_id, src.usage, src.size, &src.map_state, &range)?;
+            }
+            let size = range.end - range.start;
+            if size == 0 {
+                return Ok(Vec::new());
+            }
+            device
+                .reserve_submission(&mut token)
+                .map_err(QueueBufferError::Submit)?;
+
+            let (buffer_guard, _) = hub.buffers.read(&mut token);
+            let chunk = device.record_readback(&*buffer_guard, buffer_id, range)?;
+            drop(buffer_guard);
+            let submit_index = device.flush_pending_writes(&mut token).unwrap();
+            (size, chunk, submit_index)
+        };
+
+        loop {
+            let mut token = Token::root();
+            let (device_guard, mut token) = hub.devices.read(&mut token);
+            let device = device_guard
+                .get(queue_id)
+                .ok_or(QueueBufferError::InvalidQueue(queue_id))?;
+            let done = device.lock_life(&mut token).wait_for_submission(
+                &device.raw,
+                submit_index,
+                READBACK_WAIT_SLICE.as_nanos() as u64,
+            );
+            match done {
+                Ok(true) => {}
+                Ok(false) if !device.lost.load(Ordering::Acquire) => continue,
+                Ok(false) | Err(_) => {
+                    // The copy may still be running, so the staging buffer
+                    // has to stay alive for as long as its submission.
+                    device
+                        .lock_life(&mut token)
+                        .retire_staging(chunk, submit_index);
+                    return Err(QueueBufferError::DeviceLost);
+                }
+            }
+
+            let mut data = vec![0; size as usize];
+            {
+                let mut mapped = chunk
+                    .memory
+                    .map(&device.raw, hal::memory::Segment::ALL)
+                    .unwrap();
+                let slice = unsafe { mapped.read(&device.raw, hal::memory::Segment::ALL) }.unwrap();
+                data.copy_from_slice(&slice[..size as usize]);
+            }
+            let mut mem_allocator = device.mem_allocator.lock();
+            device
+                .staging_belt
+                .lock()
+                .recycle(&device.raw, &mut mem_allocator, chunk);
+            return Ok(data);
+        }
+    }
+
+    /// Read a range of a buffer back into host memory, without blocking.
+    ///
+    /// The copy is recorded into the pending writes of the queue, after all the
+    /// prior writes, and goes along with the next `queue_submit` or
+    /// `queue_flush_pending_writes`. Then `callback` receives the data once the
+    /// device is polled after that submission is done. The data is only valid
+    /// for the duration of the callback.
+    pub fn queue_read_buffer<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        buffer_id: id::BufferId,
+        range: Range<wgt::BufferAddress>,
+        callback: super::BufferReadCallback,
+        userdata: *mut u8,
+    ) -> Result<(), QueueBufferError> {
+        let op = BufferReadOperation { callback, userdata };
+        let hub = B::hub(self);
+        let empty_op = {
+            let mut token = Token::root();
+            let (mut device_guard, mut token) = hub.devices.write(&mut token);
+            let device = device_guard
+                .get_mut(queue_id)
+                .ok_or(QueueBufferError::InvalidQueue(queue_id))?;
+            let (buffer_guard, _) = hub.buffers.read(&mut token);
+
+            let src = buffer_guard
+                .get(buffer_id)
+                .ok_or(QueueBufferError::InvalidBuffer(buffer_id))?;
+            super::check_device(queue_id, buffer_id, src.device_id.value)?;
+            validate_read_range(buffer_id, src.usage, src.size, &src.map_state, &range)?;
+
+            let size = range.end - range.start;
+            if size == 0 {
+                Some(op)
+            } else {
+                let chunk = device.record_readback(&*buffer_guard, buffer_id, range)?;
+                device
+                    .pending_writes
+                    .readbacks
+                    .push(PendingReadback { chunk, size, op });
+                None
+            }
+        };
+        if let Some(op) = empty_op {
+            op.fire(BufferMapAsyncStatus::Success, &[]);
+        }
+        Ok(())
+    }
+
+    /// Read a region of a texture back into host memory, without blocking.
+    ///
+    /// Like with `queue_read_buffer`, the copy goes along with the pending
+    /// writes of the queue, and `callback` receives the data once the device
+    /// is polled after their submission is done. The rows of texel blocks
+    /// are padded as described by the returned layout.
+    pub fn queue_read_texture<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        source: &TextureCopyView,
+        size: wgt::Extent3d,
+        callback: super::BufferReadCallback,
+        userdata: *mut u8,
+    ) -> Result<TextureDataLayout, TextureReadError> {
+        let op = BufferReadOperation { callback, userdata };
+        let hub = B::hub(self);
+        let (layout, empty_op) = {
+            let mut token = Token::root();
+            let (mut device_guard, mut token) = hub.devices.write(&mut token);
+            let device = device_guard
+                .get_mut(queue_id)
+                .ok_or(TextureReadError::InvalidQueue(queue_id))?;
+            let (texture_guard, _) = hub.textures.read(&mut token);
+            let texture = texture_guard
+                .get(source.texture)
+                .ok_or(TextureReadError::InvalidTexture(source.texture))?;
+            if !texture.usage.contains(wgt::TextureUsage::COPY_SRC) {
+                return Err(TextureReadError::MissingCopySrcUsage(texture.usage));
+            }
+            super::check_device(queue_id, source.texture, texture.device_id.value)?;
+
+            let format_desc =
+                conv::map_texture_format(texture.format, device.private_features).surface_desc();
+            validate_texture_copy_range(
+                source,
+                texture.kind.extent(),
+                texture.full_range.levels.end,
+                format_desc.dim,
+                &size,
+            )
+            .map_err(TextureReadError::Region)?;
+            let layer_count = u32::from(texture.full_range.layers.end);
+            if source.array_layer >= layer_count {
+                return Err(TextureReadError::InvalidArrayLayer {
+                    layer: source.array_layer,
+                    layer_count,
+                });
+            }
+
+            let layout = readback_layout(format_desc.dim, format_desc.bits, size);
+            if layout.required_size == 0 {
+                (layout, Some(op))
+            } else {
+                let chunk = device
+                    .record_texture_readback(&*texture_guard, source, size, &layout)
+                    .map_err(TextureReadError::Allocation)?;
+                device.pending_writes.readbacks.push(PendingReadback {
+                    chunk,
+                    size: layout.required_size,
+                    op,
+                });
+                (layout, None)
+            }
+        };
+        if let Some(op) = empty_op {
+            op.fire(BufferMapAsyncStatus::Success, &[]);
+        }
+        Ok(layout)
+    }
+
+    /// Submit the pending writes of the queue on their own, without waiting
+    /// for the next `queue_submit`.
+    ///
+    /// The command buffers submitted afterwards execute after the writes.
+    /// Returns the token of the submission carrying the writes, or `None`
+    /// if there was nothing to flush.
+    ///
+    /// Like `queue_submit`, this waits for the oldest submission when the queue
+    /// has `max_submissions_in_flight` of them, or fails with `QueueFull` if the
+    /// device is configured not to wait.
+    pub fn queue_flush_pending_writes<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+    ) -> Result<Option<SubmissionToken>, QueueSubmitError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut device_guard, mut token) = hub.devices.write(&mut token);
+        let device = device_guard
+            .get_mut(queue_id)
+            .ok_or(QueueSubmitError::InvalidQueue(queue_id))?;
+
+        if device.pending_writes.command_buffer.is_none() {
+            return Ok(None);
+        }
+        device.reserve_submission(&mut token)?;
+        Ok(device
+            .flush_pending_writes(&mut token)
+            .map(|index| SubmissionToken { queue_id, index }))
     }
 
     pub fn queue_submit<B: GfxBackend>(
         &self,
         queue_id: id::QueueId,
         command_buffer_ids: &[id::CommandBufferId],
-    ) {
+    ) -> Result<(), QueueSubmitError> {
+        self.queue_submit_batch::<B>(queue_id, &[command_buffer_ids])
+    }
+
+    /// Submit several groups of command buffers at once, in order.
+    ///
+    /// This behaves like a `queue_submit` call per group: each group is a native submission
+    /// of its own, in order, and a swap chain frame is signaled by the last group rendering
+    /// to it. But the groups share a single fence, submission index, and life tracker entry.
+    /// The pending writes are flushed once, ahead of the first group. The whole batch is
+    /// validated before anything is submitted, so an error in any group leaves all of them
+    /// untouched.
+    pub fn queue_submit_batch<B: GfxBackend>(
+        &self,
+        queue_id: id::QueueId,
+        groups: &[&[id::CommandBufferId]],
+    ) -> Result<(), QueueSubmitError> {
         let hub = B::hub(self);
+        let command_buffer_ids = groups
+            .iter()
+            .flat_map(|group| group.iter().cloned())
+            .collect::<SmallVec<[_; 8]>>();
+        let command_buffer_ids = &command_buffer_ids[..];
+        let ranges = submission_ranges(groups);
 
-        let callbacks = {
+        let (callbacks, readbacks, work_done, hook) = {
             let mut token = Token::root();
             let (mut device_guard, mut token) = hub.devices.write(&mut token);
-            let device = &mut device_guard[queue_id];
+            let device = device_guard
+                .get_mut(queue_id)
+                .ok_or(QueueSubmitError::InvalidQueue(queue_id))?;
+
+            // Command buffers are single-use: they get unregistered at the end of
+            // the submission, so validate the whole list before touching anything.
+            {
+                let (swap_chain_guard, mut token) = hub.swap_chains.read(&mut token);
+                let (command_buffer_guard, _) = hub.command_buffers.read(&mut token);
+                for (i, &cmb_id) in command_buffer_ids.iter().enumerate() {
+                    if command_buffer_ids[..i].contains(&cmb_id) {
+                        return Err(QueueSubmitError::DuplicateCommandBuffer(cmb_id));
+                    }
+                    match command_buffer_guard.get(cmb_id) {
+                        Some(comb) if comb.status != CommandEncoderStatus::Finished => {
+                            return Err(QueueSubmitError::UnfinishedCommandBuffer(cmb_id));
+                        }
+                        Some(comb) if comb.device_id.value != queue_id => {
+                            return Err(QueueSubmitError::WrongDevice(cmb_id));
+                        }
+                        Some(comb) => {
+                            if let Some((ref sc_id, frame, _)) = comb.used_swap_chain {
+                                let sc = &swap_chain_guard[sc_id.value];
+                                if sc.acquired_view_id.is_none() || sc.acquired_frame != frame {
+                                    return Err(QueueSubmitError::SwapChainFramePresented {
+                                        command_buffer: cmb_id,
+                                        swap_chain: sc_id.value,
+                                        frame,
+                                    });
+                                }
+                            }
+                        }
+                        None => return Err(QueueSubmitError::InvalidCommandBuffer(cmb_id)),
+                    }
+                }
+            }
+
+            device.reserve_submission(&mut token)?;
+
             let pending_write_command_buffer =
                 device
                     .pending_writes
-                    .command_buffer
-                    .take()
+                    .take_command_buffer()
                     .map(|mut comb_raw| unsafe {
                         comb_raw.finish();
                         comb_raw
@@ -189,6 +1937,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 .submission_index
                 .fetch_add(1, Ordering::Relaxed);
 
+            // Semaphores of swap chain frames that are signaled again by this submission.
+            let mut superseded_semaphores = Vec::new();
+            // Inline uniform rings of the submitted command buffers.
+            let mut inline_rings = Vec::new();
             let fence = {
                 let mut signal_swapchain_semaphores = SmallVec::<[_; 1]>::new();
                 let (mut swap_chain_guard, mut token) = hub.swap_chains.write(&mut token);
@@ -211,27 +1963,56 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     // a temporary one, since the chains are not finished.
 
                     // finish all the command buffers first
-                    for &cmb_id in command_buffer_ids {
+                    for (position, &cmb_id) in command_buffer_ids.iter().enumerate() {
                         let comb = &mut command_buffer_guard[cmb_id];
                         #[cfg(feature = "trace")]
-                        match device.trace {
-                            Some(ref trace) => trace
-                                .lock()
-                                .add(Action::Submit(submit_index, comb.commands.take().unwrap())),
+                        match *device.trace.lock() {
+                            Some(ref mut trace) => match comb.commands.take() {
+                                Some(commands) => trace.add(Action::Submit(submit_index, commands)),
+                                // The encoder was created before the capture started.
+                                None => log::warn!(
+                                    "Command buffer {:?} is missing from the trace",
+                                    cmb_id
+                                ),
+                            },
                             None => (),
                         };
 
-                        if let Some((sc_id, fbo)) = comb.used_swap_chain.take() {
+                        if let Some((sc_id, _, fbo)) = comb.used_swap_chain.take() {
                             let sc = &mut swap_chain_guard[sc_id.value];
-                            assert!(sc.acquired_view_id.is_some(),
-                                "SwapChainOutput for {:?} was dropped before the respective command buffer {:?} got submitted!",
-                                sc_id.value, cmb_id);
-                            if sc.acquired_framebuffers.is_empty() {
-                                signal_swapchain_semaphores.push(sc_id.value);
+                            let free_semaphores = &mut sc.free_semaphores;
+                            let raw = &device.raw;
+                            let signal = sc.frame_sync.signal(
+                                submit_index,
+                                || {
+                                    free_semaphores
+                                        .pop()
+                                        .unwrap_or_else(|| raw.create_semaphore().unwrap())
+                                },
+                                &mut superseded_semaphores,
+                            );
+                            // The frame is signaled after the last command buffer rendering to it.
+                            if signal {
+                                signal_swapchain_semaphores.push((position, sc_id.value));
+                            } else if let Some(entry) = signal_swapchain_semaphores
+                                .iter_mut()
+                                .find(|&&mut (_, id)| id == sc_id.value)
+                            {
+                                entry.0 = position;
                             }
                             sc.acquired_framebuffers.push(fbo);
                         }
 
+                        #[cfg(feature = "counters")]
+                        {
+                            let mut counters = device.counters.lock();
+                            for pass in comb.passes.iter() {
+                                counters.add_pass(pass);
+                            }
+                        }
+
+                        inline_rings.extend(comb.inline_uniforms.drain(..));
+
                         // optimize the tracked states
                         comb.trackers.optimize();
 
@@ -280,7 +2061,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                         }
 
                         // execute resource transitions
-                        let mut transit = device.com_allocator.extend(comb);
+                        let mut transit = device.com_allocator.allocate_transit();
                         unsafe {
                             // the last buffer was open, closing now
                             comb.raw.last_mut().unwrap().finish();
@@ -304,22 +2085,48 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     log::debug!("Device after submission {}: {:#?}", submit_index, trackers);
                 }
 
-                // now prepare the GPU submission
+                #[cfg(feature = "counters")]
+                {
+                    let mut counters = device.counters.lock();
+                    if pending_write_command_buffer.is_some() {
+                        counters.pending_write_flushes += 1;
+                    }
+                    #[cfg(feature = "trace")]
+                    match *device.trace.lock() {
+                        Some(ref mut trace) => trace.add(Action::Counters(counters.clone())),
+                        None => (),
+                    }
+                }
+
+                // now prepare the GPU submissions, only the last one signals the fence
                 let fence = device.raw.create_fence(false).unwrap();
-                let submission = hal::queue::Submission {
-                    command_buffers: pending_write_command_buffer.as_ref().into_iter().chain(
-                        command_buffer_ids
+                let last = ranges.len() - 1;
+                for (i, range) in ranges.iter().enumerate() {
+                    let submission = hal::queue::Submission {
+                        command_buffers: pending_write_command_buffer
+                            .as_ref()
+                            .filter(|_| i == 0)
+                            .into_iter()
+                            .chain(
+                                command_buffer_ids[range.clone()]
+                                    .iter()
+                                    .flat_map(|&cmb_id| &command_buffer_guard[cmb_id].raw),
+                            ),
+                        wait_semaphores: Vec::new(),
+                        signal_semaphores: signal_swapchain_semaphores
                             .iter()
-                            .flat_map(|&cmb_id| &command_buffer_guard[cmb_id].raw),
-                    ),
-                    wait_semaphores: Vec::new(),
-                    signal_semaphores: signal_swapchain_semaphores
-                        .into_iter()
-                        .map(|sc_id| &swap_chain_guard[sc_id].semaphore),
-                };
-
-                unsafe {
-                    device.queue_group.queues[0].submit(submission, Some(&fence));
+                            .filter(|&&(position, _)| range.contains(&position))
+                            .map(|&(_, sc_id)| {
+                                swap_chain_guard[sc_id]
+                                    .frame_sync
+                                    .render_finished()
+                                    .unwrap()
+                            }),
+                    };
+                    let signal_fence = if i == last { Some(&fence) } else { None };
+                    unsafe {
+                        device.queue_group.queues[0].submit(submission, signal_fence);
+                    }
                 }
                 fence
             };
@@ -330,13 +2137,22 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     .after_submit_internal(comb_raw, submit_index);
             }
 
-            let callbacks = device.maintain(self, false, &mut token);
-            super::Device::lock_life_internal(&device.life_tracker, &mut token).track_submission(
-                submit_index,
-                fence,
-                &device.temp_suspected,
-                device.pending_writes.temp_buffers.drain(..),
-            );
+            // Only waiting for the device can find it hung.
+            let (callbacks, readbacks, work_done, _) = device.maintain(self, false, &mut token);
+            {
+                let mut life_tracker =
+                    super::Device::lock_life_internal(&device.life_tracker, &mut token);
+                life_tracker.track_submission(
+                    submit_index,
+                    fence,
+                    &device.temp_suspected,
+                    device.pending_writes.temp_buffers.drain(..),
+                    device.pending_writes.readbacks.drain(..),
+                    device.pending_writes.work_done.drain(..),
+                );
+                life_tracker.retire_semaphores(superseded_semaphores.into_iter());
+                life_tracker.retire_inline_uniforms(inline_rings.into_iter(), submit_index);
+            }
 
             // finally, return the command buffers to the allocator
             for &cmb_id in command_buffer_ids {
@@ -344,9 +2160,174 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 device.com_allocator.after_submit(cmd_buf, submit_index);
             }
 
-            callbacks
+            (
+                callbacks,
+                readbacks,
+                work_done,
+                *device.maintain_hook.lock(),
+            )
         };
 
         super::fire_map_callbacks(callbacks);
+        super::fire_read_callbacks(readbacks);
+        super::fire_work_done_callbacks(work_done);
+        if let Some(hook) = hook {
+            hook.fire(queue_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        hub::{IdentityManagerFactory, Root},
+        id::TypedId as _,
+    };
+
+    impl Access<u32> for Root {}
+
+    #[test]
+    fn consumed_staging_buffers() {
+        let registry = Registry::<u32, id::StagingBufferId, _>::new(
+            wgt::Backend::Empty,
+            &IdentityManagerFactory,
+        );
+        let mut token = Token::root();
+        let staging_id = registry.register_identity(std::marker::PhantomData, 7, &mut token);
+        let (staging, _) = take_staging_buffer(&registry, staging_id, &mut token).unwrap();
+        assert_eq!(staging, 7);
+        // Writing or destroying it again is an error, not a panic.
+        assert_eq!(
+            take_staging_buffer(&registry, staging_id, &mut token).err(),
+            Some(StagingBufferError::Consumed(staging_id))
+        );
+        // Its index is reused, with a new epoch.
+        let other_id = registry.register_identity(std::marker::PhantomData, 8, &mut token);
+        assert_ne!(other_id, staging_id);
+        assert_eq!(
+            take_staging_buffer(&registry, staging_id, &mut token).err(),
+            Some(StagingBufferError::Consumed(staging_id))
+        );
+    }
+
+    #[test]
+    fn overlapping_buffer_writes() {
+        let buffer = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let other = id::BufferId::zip(1, 1, wgt::Backend::Empty);
+        let mut written = WrittenRanges::default();
+        assert!(!written.insert(buffer, 0..16));
+        // touching ranges and other buffers don't need a barrier
+        assert!(!written.insert(buffer, 16..32));
+        assert!(!written.insert(other, 0..16));
+        assert!(written.insert(buffer, 8..12));
+        assert!(written.insert(buffer, 28..64));
+        // a new batch of pending writes starts over
+        written.clear();
+        assert!(!written.insert(buffer, 0..64));
+    }
+
+    #[test]
+    fn buffer_read_range() {
+        let buffer = id::BufferId::zip(0, 1, wgt::Backend::Empty);
+        let usage = wgt::BufferUsage::COPY_SRC | wgt::BufferUsage::MAP_WRITE;
+        let idle = BufferMapState::Idle;
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &(0..64)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &(16..16)),
+            Ok(())
+        );
+        assert_eq!(
+            validate_read_range(buffer, wgt::BufferUsage::COPY_DST, 64, &idle, &(0..64)),
+            Err(QueueBufferError::MissingCopySrcUsage(
+                wgt::BufferUsage::COPY_DST
+            ))
+        );
+        let mapped = BufferMapState::Active {
+            ptr: std::ptr::null_mut(),
+            sub_range: hal::buffer::SubRange::WHOLE,
+            host: crate::device::HostMap::Write,
+        };
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &mapped, &(0..64)),
+            Err(QueueBufferError::BufferMapped(buffer))
+        );
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &(32..68)),
+            Err(QueueBufferError::OutOfBounds {
+                range: 32..68,
+                buffer_size: 64,
+            })
+        );
+        // an inverted range must not wrap around to a huge size
+        let inverted = Range { start: 32, end: 16 };
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &inverted),
+            Err(QueueBufferError::OutOfBounds {
+                range: inverted.clone(),
+                buffer_size: 64,
+            })
+        );
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &(2..16)),
+            Err(QueueBufferError::Unaligned(2))
+        );
+        assert_eq!(
+            validate_read_range(buffer, usage, 64, &idle, &(0..10)),
+            Err(QueueBufferError::Unaligned(10))
+        );
+    }
+
+    #[test]
+    fn texture_readback_layout() {
+        let size = wgt::Extent3d {
+            width: 100,
+            height: 8,
+            depth: 2,
+        };
+        // 400-byte rows of RGBA8 texels are padded to 512 bytes
+        let layout = readback_layout((1, 1), 32, size);
+        assert_eq!((layout.bytes_per_row, layout.rows_per_image), (512, 8));
+        assert_eq!(layout.required_size, 512 * 8 * 2);
+        // 25 BC1 blocks of 8 bytes per row, in 2 rows of blocks of 4 texels
+        let layout = readback_layout((4, 4), 64, size);
+        assert_eq!((layout.bytes_per_row, layout.rows_per_image), (256, 8));
+        assert_eq!(layout.required_size, 256 * 2 * 2);
+        let empty = wgt::Extent3d { depth: 0, ..size };
+        assert_eq!(readback_layout((1, 1), 32, empty).required_size, 0);
+    }
+
+    #[test]
+    fn batch_submission_ranges() {
+        let ids = (0..5)
+            .map(|index| id::CommandBufferId::zip(index, 1, wgt::Backend::Empty))
+            .collect::<Vec<_>>();
+        // One submission per group, in order.
+        let groups: &[&[_]] = &[&ids[..2], &ids[2..3], &ids[3..]];
+        assert_eq!(submission_ranges(groups).as_slice(), &[0..2, 2..3, 3..5]);
+        // Empty groups don't get one.
+        let groups: &[&[_]] = &[&[], &ids[..2], &[], &ids[2..], &[]];
+        assert_eq!(submission_ranges(groups).as_slice(), &[0..2, 2..5]);
+        // But there is always one, for the pending writes and the fence.
+        assert_eq!(submission_ranges(&[]).as_slice(), &[0..0]);
+        assert_eq!(submission_ranges(&[&[], &[]]).as_slice(), &[0..0]);
+    }
+
+    #[test]
+    fn timestamp_sample_bounds() {
+        let submitted = Instant::now();
+        let sample = TimestampSample {
+            submitted,
+            done: submitted + Duration::from_micros(30),
+            gpu_timestamp: 0,
+        };
+        assert_eq!(sample.max_deviation(), Duration::from_micros(15));
+        assert_eq!(sample.host_instant(), submitted + Duration::from_micros(15));
+        assert!(sample.host_instant() - sample.max_deviation() >= sample.submitted);
+        assert!(sample.host_instant() + sample.max_deviation() <= sample.done);
     }
 }
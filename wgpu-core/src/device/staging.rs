@@ -0,0 +1,146 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use gfx_memory::{Heaps, MemoryBlock};
+use hal::device::Device as _;
+
+/// Size of the smallest staging buffer, so that small requests share buffers.
+const MIN_CHUNK_SIZE: u64 = 0x1000;
+/// Limit on the total size of the free staging buffers kept by a device.
+const MAX_FREE_BYTES: u64 = 0x400_0000;
+
+/// Size of the staging buffer created for a request of `size` bytes.
+///
+/// Sizes are rounded up to a power of two, so that the buffers of
+/// different requests can be reused for each other.
+pub(crate) fn chunk_size(size: u64) -> u64 {
+    size.max(MIN_CHUNK_SIZE).next_power_of_two()
+}
+
+/// Buffer in host-visible memory, to copy from or into.
+#[derive(Debug)]
+pub(crate) struct StagingChunk<B: hal::Backend> {
+    pub raw: B::Buffer,
+    pub memory: MemoryBlock<B>,
+    /// Size of the buffer, which may be larger than the request it serves.
+    pub size: u64,
+    /// Whether the memory is meant for the host to read, rather than write.
+    pub read_back: bool,
+}
+
+/// Bookkeeping of the free staging buffers, separate from the buffers.
+#[derive(Debug, Default)]
+struct FreeList {
+    /// Size and direction of each free buffer.
+    chunks: Vec<(u64, bool)>,
+    total: u64,
+}
+
+impl FreeList {
+    /// Find the smallest free buffer of at least `size` bytes, and take it out.
+    fn take(&mut self, size: u64, read_back: bool) -> Option<usize> {
+        let index = self
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|&(_, &(chunk_size, chunk_read_back))| {
+                chunk_read_back == read_back && chunk_size >= size
+            })
+            .min_by_key(|&(_, &(chunk_size, _))| chunk_size)
+            .map(|(index, _)| index)?;
+        let (chunk_size, _) = self.chunks.swap_remove(index);
+        self.total -= chunk_size;
+        Some(index)
+    }
+
+    /// Add a free buffer, unless it doesn't fit under `MAX_FREE_BYTES`.
+    fn add(&mut self, size: u64, read_back: bool) -> bool {
+        if self.total + size > MAX_FREE_BYTES {
+            return false;
+        }
+        self.chunks.push((size, read_back));
+        self.total += size;
+        true
+    }
+}
+
+/// Staging buffers of the finished uploads and readbacks, reused by
+/// the next ones instead of allocating new memory each time.
+///
+/// Buffers come back from the life tracker, once the GPU is done with
+/// their submission. Those that don't fit under `MAX_FREE_BYTES` are freed.
+#[derive(Debug)]
+pub(crate) struct StagingBelt<B: hal::Backend> {
+    list: FreeList,
+    chunks: Vec<StagingChunk<B>>,
+}
+
+impl<B: hal::Backend> StagingBelt<B> {
+    pub fn new() -> Self {
+        StagingBelt {
+            list: FreeList::default(),
+            chunks: Vec::new(),
+        }
+    }
+
+    /// Total size of the free staging buffers.
+    pub fn free_bytes(&self) -> u64 {
+        self.list.total
+    }
+
+    /// Take a free staging buffer of at least `size` bytes, if there is one.
+    pub fn take(&mut self, size: u64, read_back: bool) -> Option<StagingChunk<B>> {
+        let index = self.list.take(size, read_back)?;
+        Some(self.chunks.swap_remove(index))
+    }
+
+    /// Keep a staging buffer that the GPU is done with for the next requests.
+    pub fn recycle(&mut self, device: &B::Device, heaps: &mut Heaps<B>, chunk: StagingChunk<B>) {
+        if self.list.add(chunk.size, chunk.read_back) {
+            self.chunks.push(chunk);
+        } else {
+            free_chunk(device, heaps, chunk);
+        }
+    }
+
+    pub fn dispose(self, device: &B::Device, heaps: &mut Heaps<B>) {
+        for chunk in self.chunks {
+            free_chunk(device, heaps, chunk);
+        }
+    }
+}
+
+fn free_chunk<B: hal::Backend>(device: &B::Device, heaps: &mut Heaps<B>, chunk: StagingChunk<B>) {
+    heaps.free(device, chunk.memory);
+    unsafe {
+        device.destroy_buffer(chunk.raw);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_reuse_chunks() {
+        assert_eq!(chunk_size(1), MIN_CHUNK_SIZE);
+        assert_eq!(chunk_size(0x1001), 0x2000);
+
+        let mut list = FreeList::default();
+        assert!(list.add(0x4000, false));
+        assert!(list.add(0x1000, false));
+        assert!(list.add(0x1000, true));
+        // the smallest buffer that fits is taken, in the right direction
+        assert_eq!(list.take(0x800, false), Some(1));
+        assert_eq!(list.chunks, [(0x4000, false), (0x1000, true)]);
+        assert_eq!(list.take(0x2000, true), None);
+        assert_eq!(list.take(0x2000, false), Some(0));
+        assert_eq!(list.total, 0x1000);
+
+        // the free buffers are bounded in total
+        assert!(list.add(MAX_FREE_BYTES - 0x1000, false));
+        assert!(!list.add(0x1000, false));
+        assert_eq!(list.total, MAX_FREE_BYTES);
+    }
+}
@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    command::{BufferCopyView, TextureCopyView},
+    command::{BufferCopyRegion, BufferCopyView, TextureCopyView},
     id,
 };
 #[cfg(feature = "trace")]
@@ -16,20 +16,20 @@ type FileName = String;
 
 pub const FILE_NAME: &str = "trace.ron";
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub enum BindingResource {
     Buffer {
         id: id::BufferId,
         offset: wgt::BufferAddress,
-        size: wgt::BufferAddress,
+        size: Option<wgt::BufferSize>,
     },
     Sampler(id::SamplerId),
     TextureView(id::TextureViewId),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct ProgrammableStageDescriptor {
@@ -49,7 +49,7 @@ impl ProgrammableStageDescriptor {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct ComputePipelineDescriptor {
@@ -57,16 +57,17 @@ pub struct ComputePipelineDescriptor {
     pub compute_stage: ProgrammableStageDescriptor,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct VertexBufferLayoutDescriptor {
     pub array_stride: wgt::BufferAddress,
     pub step_mode: wgt::InputStepMode,
+    pub step_rate: u32,
     pub attributes: Vec<wgt::VertexAttributeDescriptor>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct VertexStateDescriptor {
@@ -74,7 +75,7 @@ pub struct VertexStateDescriptor {
     pub vertex_buffers: Vec<VertexBufferLayoutDescriptor>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct RenderPipelineDescriptor {
@@ -82,6 +83,7 @@ pub struct RenderPipelineDescriptor {
     pub vertex_stage: ProgrammableStageDescriptor,
     pub fragment_stage: Option<ProgrammableStageDescriptor>,
     pub primitive_topology: wgt::PrimitiveTopology,
+    pub strip_index_format: Option<wgt::IndexFormat>,
     pub rasterization_state: Option<wgt::RasterizationStateDescriptor>,
     pub color_states: Vec<wgt::ColorStateDescriptor>,
     pub depth_stencil_state: Option<wgt::DepthStencilStateDescriptor>,
@@ -89,8 +91,11 @@ pub struct RenderPipelineDescriptor {
     pub sample_count: u32,
     pub sample_mask: u32,
     pub alpha_to_coverage_enabled: bool,
+    pub alpha_to_one_enabled: bool,
+    pub min_sample_shading: f32,
 }
-#[derive(Debug)]
+
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "trace", derive(serde::Serialize))]
 #[cfg_attr(feature = "replay", derive(serde::Deserialize))]
 pub struct MeshPipelineDescriptor {
@@ -105,6 +110,49 @@ pub struct MeshPipelineDescriptor {
     pub sample_count: u32,
     pub sample_mask: u32,
     pub alpha_to_coverage_enabled: bool,
+    pub alpha_to_one_enabled: bool,
+    pub min_sample_shading: f32,
+}
+
+/// Creation parameters of a bind group, retained so that a capture started
+/// later in the session can re-create it.
+#[cfg(feature = "trace")]
+#[derive(Clone, Debug)]
+pub(crate) struct BindGroupDescriptor {
+    pub label: String,
+    pub layout_id: id::BindGroupLayoutId,
+    pub entries: std::collections::BTreeMap<u32, BindingResource>,
+}
+
+#[cfg(feature = "trace")]
+impl BindGroupDescriptor {
+    pub(crate) fn into_action(self, id: id::BindGroupId) -> Action {
+        Action::CreateBindGroup {
+            id,
+            label: self.label,
+            layout_id: self.layout_id,
+            entries: self.entries,
+        }
+    }
+}
+
+/// Creation parameters of a render or a mesh pipeline, which share
+/// the same storage.
+#[cfg(feature = "trace")]
+#[derive(Clone, Debug)]
+pub(crate) enum GraphicsPipelineDescriptor {
+    Render(RenderPipelineDescriptor),
+    Mesh(MeshPipelineDescriptor),
+}
+
+#[cfg(feature = "trace")]
+impl GraphicsPipelineDescriptor {
+    pub(crate) fn into_action(self, id: id::RenderPipelineId) -> Action {
+        match self {
+            GraphicsPipelineDescriptor::Render(desc) => Action::CreateRenderPipeline { id, desc },
+            GraphicsPipelineDescriptor::Mesh(desc) => Action::CreateMeshPipeline { id, desc },
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -114,6 +162,9 @@ pub enum Action {
     Init {
         desc: wgt::DeviceDescriptor,
         backend: wgt::Backend,
+        /// Whether the IDs were assigned deterministically.
+        #[cfg_attr(feature = "replay", serde(default))]
+        deterministic_ids: bool,
     },
     CreateBuffer {
         id: id::BufferId,
@@ -136,6 +187,9 @@ pub enum Action {
         desc: wgt::SamplerDescriptor<String>,
     },
     DestroySampler(id::SamplerId),
+    /// Creating an identical sampler returned this live one, see
+    /// `DeviceDescriptor::deduplicate_objects`.
+    ReuseSampler(id::SamplerId),
     CreateSwapChain {
         id: id::SwapChainId,
         desc: wgt::SwapChainDescriptor,
@@ -145,6 +199,7 @@ pub enum Action {
         parent_id: id::SwapChainId,
     },
     PresentSwapChain(id::SwapChainId),
+    DiscardSwapChainFrame(id::SwapChainId),
     CreateBindGroupLayout {
         id: id::BindGroupLayoutId,
         label: String,
@@ -163,8 +218,11 @@ pub enum Action {
         entries: std::collections::BTreeMap<u32, BindingResource>,
     },
     DestroyBindGroup(id::BindGroupId),
+    /// Creating an identical bind group returned this live one.
+    ReuseBindGroup(id::BindGroupId),
     CreateShaderModule {
         id: id::ShaderModuleId,
+        label: String,
         data: FileName,
     },
     DestroyShaderModule(id::ShaderModuleId),
@@ -189,7 +247,16 @@ pub enum Action {
         range: Range<wgt::BufferAddress>,
         queued: bool,
     },
+    WriteTexture {
+        id: id::TextureId,
+        data: FileName,
+        regions: Vec<super::TextureWriteRegion>,
+        layout: super::TextureWriteLayout,
+    },
     Submit(crate::SubmissionIndex, Vec<Command>),
+    FlushPendingWrites(crate::SubmissionIndex),
+    /// Counters of the device after a submission, see `InternalCounters`.
+    Counters(super::InternalCounters),
 }
 
 #[derive(Debug)]
@@ -203,6 +270,11 @@ pub enum Command {
         dst_offset: wgt::BufferAddress,
         size: wgt::BufferAddress,
     },
+    CopyBufferRegions {
+        src: id::BufferId,
+        dst: id::BufferId,
+        regions: Vec<BufferCopyRegion>,
+    },
     CopyBufferToTexture {
         src: BufferCopyView,
         dst: TextureCopyView,
@@ -221,12 +293,22 @@ pub enum Command {
     RunComputePass {
         commands: Vec<crate::command::ComputeCommand>,
         dynamic_offsets: Vec<wgt::DynamicOffset>,
+        /// Labels of the pushed debug groups.
+        debug_labels: Vec<String>,
+        /// Context of each command.
+        contexts: Vec<crate::command::PassCommandContext>,
     },
     RunRenderPass {
         target_colors: Vec<crate::command::RenderPassColorAttachmentDescriptor>,
         target_depth_stencil: Option<crate::command::RenderPassDepthStencilAttachmentDescriptor>,
         commands: Vec<crate::command::RenderCommand>,
         dynamic_offsets: Vec<wgt::DynamicOffset>,
+        /// Data of the inline uniform writes, concatenated.
+        inline_data: Vec<u8>,
+        /// Labels of the pushed debug groups.
+        debug_labels: Vec<String>,
+        /// Context of each command.
+        contexts: Vec<crate::command::PassCommandContext>,
     },
 }
 
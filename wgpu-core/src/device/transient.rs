@@ -0,0 +1,307 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+use hal::device::Device as _;
+
+/// Size of the memory blocks that transient resources are placed in.
+const BLOCK_SIZE: u64 = 0x400_0000;
+
+/// Location of a transient resource in the pool of its device.
+#[derive(Debug)]
+pub(crate) struct TransientAllocation {
+    block: usize,
+    pub properties: hal::memory::Properties,
+}
+
+/// Memory of a transient attachment, which the driver allocates lazily,
+/// if at all, since the contents can stay in tile memory.
+#[derive(Debug)]
+pub(crate) struct LazyAllocation<B: hal::Backend> {
+    memory: B::Memory,
+    size: u64,
+    pub properties: hal::memory::Properties,
+}
+
+#[derive(Debug)]
+struct BlockState {
+    memory_type: hal::MemoryTypeId,
+    size: u64,
+    /// Start of the free space, everything before it may be in use.
+    offset: u64,
+    /// Number of resources placed in the block that are not yet released.
+    live: usize,
+}
+
+/// Bookkeeping of the transient blocks, separate from the memory objects.
+///
+/// Resources are placed linearly into a block, and the space is only
+/// recycled as a whole, once every resource placed in the block is released.
+/// The life tracker releases them once the GPU is done with their last
+/// submission, so the next ones can alias the same memory.
+#[derive(Debug, Default)]
+pub(super) struct BlockRing {
+    blocks: Vec<BlockState>,
+}
+
+impl BlockRing {
+    /// Find space in an existing block, returning the block index and offset.
+    pub(super) fn place(
+        &mut self,
+        memory_type: hal::MemoryTypeId,
+        size: u64,
+        alignment: u64,
+    ) -> Option<(usize, u64)> {
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            if block.memory_type != memory_type {
+                continue;
+            }
+            if block.live == 0 {
+                block.offset = 0;
+            }
+            let offset = (block.offset + alignment - 1) / alignment * alignment;
+            if offset + size <= block.size {
+                block.offset = offset + size;
+                block.live += 1;
+                return Some((index, offset));
+            }
+        }
+        None
+    }
+
+    /// Add a block for a resource that doesn't fit in the existing ones,
+    /// placing the resource at its start.
+    pub(super) fn add(&mut self, memory_type: hal::MemoryTypeId, size: u64) -> usize {
+        self.blocks.push(BlockState {
+            memory_type,
+            size: size.max(BLOCK_SIZE),
+            offset: size,
+            live: 1,
+        });
+        self.blocks.len() - 1
+    }
+
+    pub(super) fn release(&mut self, allocation: TransientAllocation) {
+        self.blocks[allocation.block].live -= 1;
+    }
+
+    pub(super) fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Allocation of a resource placed in `block`.
+    #[cfg(test)]
+    pub(super) fn allocation(block: usize) -> TransientAllocation {
+        TransientAllocation {
+            block,
+            properties: hal::memory::Properties::DEVICE_LOCAL,
+        }
+    }
+}
+
+/// Memory that transient resources of a device are sub-allocated from.
+///
+/// The blocks are only freed together with the device.
+#[derive(Debug)]
+pub(crate) struct TransientPool<B: hal::Backend> {
+    memory_types: Vec<hal::adapter::MemoryType>,
+    pub(super) ring: BlockRing,
+    memories: Vec<B::Memory>,
+    /// Total size of the live lazy allocations.
+    lazy_bytes: u64,
+}
+
+impl<B: hal::Backend> TransientPool<B> {
+    pub fn new(memory_types: Vec<hal::adapter::MemoryType>) -> Self {
+        TransientPool {
+            memory_types,
+            ring: BlockRing::default(),
+            memories: Vec::new(),
+            lazy_bytes: 0,
+        }
+    }
+
+    /// Number of memory blocks allocated so far.
+    pub fn block_count(&self) -> usize {
+        self.memories.len()
+    }
+
+    /// Number of bytes of the transient attachments in lazily allocated memory.
+    pub fn lazy_bytes(&self) -> u64 {
+        self.lazy_bytes
+    }
+
+    /// Find a lazily allocated memory type allowed by `type_mask`, if the adapter has one.
+    pub fn lazy_memory_type(&self, type_mask: u64) -> Option<hal::MemoryTypeId> {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .position(|(index, ty)| {
+                type_mask & (1 << index) != 0
+                    && ty
+                        .properties
+                        .contains(hal::memory::Properties::LAZILY_ALLOCATED)
+            })
+            .map(hal::MemoryTypeId)
+    }
+
+    fn pick_memory_type(&self, type_mask: u64) -> hal::MemoryTypeId {
+        let allowed =
+            |&(index, _): &(usize, &hal::adapter::MemoryType)| type_mask & (1 << index) != 0;
+        self.memory_types
+            .iter()
+            .enumerate()
+            .filter(allowed)
+            .find(|&(_, ty)| {
+                ty.properties
+                    .contains(hal::memory::Properties::DEVICE_LOCAL)
+            })
+            .or_else(|| self.memory_types.iter().enumerate().find(allowed))
+            .map(|(index, _)| hal::MemoryTypeId(index))
+            .expect("No memory type is suitable for a transient resource")
+    }
+
+    /// Find space for a resource, allocating a new block if none has enough,
+    /// and return its allocation and offset.
+    fn place(
+        &mut self,
+        device: &B::Device,
+        requirements: &hal::memory::Requirements,
+    ) -> Result<(TransientAllocation, u64), hal::device::AllocationError> {
+        let memory_type = self.pick_memory_type(requirements.type_mask);
+        let (block, offset) =
+            match self
+                .ring
+                .place(memory_type, requirements.size, requirements.alignment)
+            {
+                Some(place) => place,
+                None => {
+                    let block = self.ring.add(memory_type, requirements.size);
+                    let size = self.ring.blocks[block].size;
+                    log::info!("Allocating transient block {} of {} bytes", block, size);
+                    let memory = match unsafe { device.allocate_memory(memory_type, size) } {
+                        Ok(memory) => memory,
+                        Err(e) => {
+                            self.ring.blocks.pop();
+                            return Err(e);
+                        }
+                    };
+                    self.memories.push(memory);
+                    (block, 0)
+                }
+            };
+        let allocation = TransientAllocation {
+            block,
+            properties: self.memory_types[memory_type.0].properties,
+        };
+        Ok((allocation, offset))
+    }
+
+    pub fn bind_buffer(
+        &mut self,
+        device: &B::Device,
+        requirements: &hal::memory::Requirements,
+        buffer: &mut B::Buffer,
+    ) -> Result<TransientAllocation, hal::device::AllocationError> {
+        let (allocation, offset) = self.place(device, requirements)?;
+        unsafe {
+            device
+                .bind_buffer_memory(&self.memories[allocation.block], offset, buffer)
+                .unwrap();
+        }
+        Ok(allocation)
+    }
+
+    pub fn bind_image(
+        &mut self,
+        device: &B::Device,
+        requirements: &hal::memory::Requirements,
+        image: &mut B::Image,
+    ) -> Result<TransientAllocation, hal::device::AllocationError> {
+        let (allocation, offset) = self.place(device, requirements)?;
+        unsafe {
+            device
+                .bind_image_memory(&self.memories[allocation.block], offset, image)
+                .unwrap();
+        }
+        Ok(allocation)
+    }
+
+    /// Bind a transient attachment to its own lazily allocated memory.
+    pub fn bind_lazy_image(
+        &mut self,
+        device: &B::Device,
+        memory_type: hal::MemoryTypeId,
+        requirements: &hal::memory::Requirements,
+        image: &mut B::Image,
+    ) -> Result<LazyAllocation<B>, hal::device::AllocationError> {
+        let memory = unsafe { device.allocate_memory(memory_type, requirements.size) }?;
+        unsafe {
+            device.bind_image_memory(&memory, 0, image).unwrap();
+        }
+        self.lazy_bytes += requirements.size;
+        Ok(LazyAllocation {
+            memory,
+            size: requirements.size,
+            properties: self.memory_types[memory_type.0].properties,
+        })
+    }
+
+    pub fn free_lazy(&mut self, device: &B::Device, allocation: LazyAllocation<B>) {
+        self.lazy_bytes -= allocation.size;
+        unsafe {
+            device.free_memory(allocation.memory);
+        }
+    }
+
+    /// Give the space of a destroyed resource back to the pool.
+    pub fn release(&mut self, allocation: TransientAllocation) {
+        self.ring.release(allocation);
+    }
+
+    pub fn dispose(self, device: &B::Device) {
+        for memory in self.memories {
+            unsafe {
+                device.free_memory(memory);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frames_reuse_blocks() {
+        const FRAMES_IN_FLIGHT: usize = 3;
+        const TEXTURE_SIZE: u64 = 0x80_0000;
+        let memory_type = hal::MemoryTypeId(0);
+        let mut ring = BlockRing::default();
+        let mut in_flight = Vec::new();
+        let mut warm_blocks = 0;
+
+        for frame_index in 0..100 {
+            if frame_index == 10 {
+                warm_blocks = ring.blocks.len();
+            }
+            let frame = (0..6)
+                .map(|_| {
+                    let block = match ring.place(memory_type, TEXTURE_SIZE, 0x100) {
+                        Some((block, _)) => block,
+                        None => ring.add(memory_type, TEXTURE_SIZE),
+                    };
+                    BlockRing::allocation(block)
+                })
+                .collect::<Vec<_>>();
+            in_flight.push(frame);
+            if in_flight.len() > FRAMES_IN_FLIGHT {
+                for allocation in in_flight.remove(0) {
+                    ring.release(allocation);
+                }
+            }
+        }
+
+        assert_eq!(ring.blocks.len(), warm_blocks);
+    }
+}
@@ -9,12 +9,12 @@ use crate::{
     device::Device,
     id::{
         AdapterId, BindGroupId, BindGroupLayoutId, BufferId, CommandBufferId, ComputePipelineId,
-        DeviceId, PipelineLayoutId, RenderPipelineId, SamplerId, ShaderModuleId, SurfaceId,
-        SwapChainId, TextureId, TextureViewId, TypedId,
+        DeviceId, PipelineCacheId, PipelineLayoutId, RenderPipelineId, SamplerId, ShaderModuleId,
+        StagingBufferId, SurfaceId, SwapChainId, TextureId, TextureViewId, TypedId,
     },
     instance::{Adapter, Instance, Surface},
-    pipeline::{ComputePipeline, RenderPipeline, ShaderModule},
-    resource::{Buffer, Sampler, Texture, TextureView},
+    pipeline::{ComputePipeline, PipelineCache, RenderPipeline, ShaderModule},
+    resource::{Buffer, Sampler, StagingBuffer, Texture, TextureView},
     swap_chain::SwapChain,
     Epoch, Index,
 };
@@ -32,6 +32,8 @@ use std::{fmt::Debug, marker::PhantomData, ops, thread};
 pub struct IdentityManager {
     free: Vec<Index>,
     epochs: Vec<Epoch>,
+    /// Never reuse the indices of freed objects.
+    deterministic: bool,
 }
 
 impl Default for IdentityManager {
@@ -39,6 +41,7 @@ impl Default for IdentityManager {
         IdentityManager {
             free: Default::default(),
             epochs: Default::default(),
+            deterministic: false,
         }
     }
 }
@@ -48,6 +51,19 @@ impl IdentityManager {
         IdentityManager {
             free: (0..min_index).collect(),
             epochs: vec![1; min_index as usize],
+            deterministic: false,
+        }
+    }
+
+    /// Create a manager that hands out the indices in order, and never reuses them.
+    ///
+    /// Objects are freed when the GPU is done with them, so the order
+    /// of reuse depends on timing. Without reuse, the IDs only depend on
+    /// the order of the API calls, at the cost of an ever growing storage.
+    pub fn deterministic(min_index: u32) -> Self {
+        IdentityManager {
+            deterministic: true,
+            ..Self::from_index(min_index)
         }
     }
 
@@ -72,7 +88,9 @@ impl IdentityManager {
         let pe = &mut self.epochs[index as usize];
         assert_eq!(*pe, epoch);
         *pe += 1;
-        self.free.push(index);
+        if !self.deterministic {
+            self.free.push(index);
+        }
     }
 }
 
@@ -111,6 +129,28 @@ impl<T, I: TypedId> Storage<T, I> {
         }
     }
 
+    /// Get a reference to an item behind a potentially invalid ID.
+    ///
+    /// Returns `None` if the ID is not registered, or refers to an older epoch.
+    pub fn get(&self, id: I) -> Option<&T> {
+        let (index, epoch, _) = id.unzip();
+        match self.map.get(index as usize) {
+            Some(&(ref value, storage_epoch)) if epoch == storage_epoch => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get a mutable reference to an item behind a potentially invalid ID.
+    ///
+    /// Returns `None` if the ID is not registered, or refers to an older epoch.
+    pub fn get_mut(&mut self, id: I) -> Option<&mut T> {
+        let (index, epoch, _) = id.unzip();
+        match self.map.get_mut(index as usize) {
+            Some(&mut (ref mut value, storage_epoch)) if epoch == storage_epoch => Some(value),
+            _ => None,
+        }
+    }
+
     pub fn insert(&mut self, id: I, value: T) -> Option<T> {
         let (index, epoch, _) = id.unzip();
         let old = self.map.insert(index as usize, (value, epoch));
@@ -132,6 +172,11 @@ impl<T, I: TypedId> Storage<T, I> {
             (I::zip(index as Index, *storage_epoch, backend), value)
         })
     }
+
+    /// IDs of all the stored objects, in index order.
+    fn ids(&self, backend: Backend) -> Vec<I> {
+        self.iter(backend).map(|(id, _)| id).collect()
+    }
 }
 
 /// Type system for enforcing the lock order on shared HUB structures.
@@ -162,6 +207,7 @@ impl<B: hal::Backend> Access<PipelineLayout<B>> for Device<B> {}
 impl<B: hal::Backend> Access<PipelineLayout<B>> for CommandBuffer<B> {}
 impl<B: hal::Backend> Access<BindGroupLayout<B>> for Root {}
 impl<B: hal::Backend> Access<BindGroupLayout<B>> for Device<B> {}
+impl<B: hal::Backend> Access<BindGroupLayout<B>> for ShaderModule<B> {}
 impl<B: hal::Backend> Access<BindGroup<B>> for Root {}
 impl<B: hal::Backend> Access<BindGroup<B>> for Device<B> {}
 impl<B: hal::Backend> Access<BindGroup<B>> for BindGroupLayout<B> {}
@@ -177,6 +223,9 @@ impl<B: hal::Backend> Access<RenderPipeline<B>> for BindGroup<B> {}
 impl<B: hal::Backend> Access<RenderPipeline<B>> for ComputePipeline<B> {}
 impl<B: hal::Backend> Access<ShaderModule<B>> for Device<B> {}
 impl<B: hal::Backend> Access<ShaderModule<B>> for PipelineLayout<B> {}
+impl<B: hal::Backend> Access<PipelineCache<B>> for Device<B> {}
+impl<B: hal::Backend> Access<PipelineCache<B>> for PipelineLayout<B> {}
+impl<B: hal::Backend> Access<PipelineCache<B>> for ShaderModule<B> {}
 impl<B: hal::Backend> Access<Buffer<B>> for Root {}
 impl<B: hal::Backend> Access<Buffer<B>> for Device<B> {}
 impl<B: hal::Backend> Access<Buffer<B>> for BindGroupLayout<B> {}
@@ -184,6 +233,9 @@ impl<B: hal::Backend> Access<Buffer<B>> for BindGroup<B> {}
 impl<B: hal::Backend> Access<Buffer<B>> for CommandBuffer<B> {}
 impl<B: hal::Backend> Access<Buffer<B>> for ComputePipeline<B> {}
 impl<B: hal::Backend> Access<Buffer<B>> for RenderPipeline<B> {}
+impl<B: hal::Backend> Access<Buffer<B>> for StagingBuffer<B> {}
+impl<B: hal::Backend> Access<StagingBuffer<B>> for Root {}
+impl<B: hal::Backend> Access<StagingBuffer<B>> for Device<B> {}
 impl<B: hal::Backend> Access<Texture<B>> for Root {}
 impl<B: hal::Backend> Access<Texture<B>> for Device<B> {}
 impl<B: hal::Backend> Access<Texture<B>> for Buffer<B> {}
@@ -273,6 +325,20 @@ impl<I: TypedId + Debug> IdentityHandlerFactory<I> for IdentityManagerFactory {
     }
 }
 
+/// Factory of identity managers that assign IDs deterministically,
+/// so that the same sequence of API calls produces the same trace.
+///
+/// The IDs are still subject to the order in which threads create objects.
+#[derive(Debug)]
+pub struct DeterministicIdentityManagerFactory;
+
+impl<I: TypedId + Debug> IdentityHandlerFactory<I> for DeterministicIdentityManagerFactory {
+    type Filter = Mutex<IdentityManager>;
+    fn spawn(&self, min_index: Index) -> Self::Filter {
+        Mutex::new(IdentityManager::deterministic(min_index))
+    }
+}
+
 pub trait GlobalIdentityHandlerFactory:
     IdentityHandlerFactory<AdapterId>
     + IdentityHandlerFactory<DeviceId>
@@ -285,15 +351,26 @@ pub trait GlobalIdentityHandlerFactory:
     + IdentityHandlerFactory<RenderPipelineId>
     + IdentityHandlerFactory<RenderPipelineId>
     + IdentityHandlerFactory<ComputePipelineId>
+    + IdentityHandlerFactory<PipelineCacheId>
     + IdentityHandlerFactory<BufferId>
+    + IdentityHandlerFactory<StagingBufferId>
     + IdentityHandlerFactory<TextureId>
     + IdentityHandlerFactory<TextureViewId>
     + IdentityHandlerFactory<SamplerId>
     + IdentityHandlerFactory<SurfaceId>
 {
+    /// Whether the IDs are assigned deterministically, which is recorded in traces.
+    fn deterministic_ids(&self) -> bool {
+        false
+    }
 }
 
 impl GlobalIdentityHandlerFactory for IdentityManagerFactory {}
+impl GlobalIdentityHandlerFactory for DeterministicIdentityManagerFactory {
+    fn deterministic_ids(&self) -> bool {
+        true
+    }
+}
 
 pub type Input<G, I> = <<G as IdentityHandlerFactory<I>>::Filter as IdentityHandler<I>>::Input;
 
@@ -305,7 +382,7 @@ pub struct Registry<T, I: TypedId, F: IdentityHandlerFactory<I>> {
 }
 
 impl<T, I: TypedId, F: IdentityHandlerFactory<I>> Registry<T, I, F> {
-    fn new(backend: Backend, factory: &F) -> Self {
+    pub(crate) fn new(backend: Backend, factory: &F) -> Self {
         Registry {
             identity: factory.spawn(0),
             data: RwLock::new(Storage {
@@ -373,11 +450,39 @@ impl<T, I: TypedId + Copy, F: IdentityHandlerFactory<I>> Registry<T, I, F> {
         (value, Token::new())
     }
 
+    /// Unregister an object, or return `None` if the ID is invalid.
+    pub fn try_unregister<'a, A: Access<T>>(
+        &self,
+        id: I,
+        _token: &'a mut Token<A>,
+    ) -> Option<(T, Token<'a, T>)> {
+        let value = {
+            let mut storage = self.data.write();
+            if !storage.contains(id) {
+                return None;
+            }
+            storage.remove(id).unwrap()
+        };
+        self.identity.free(id);
+        Some((value, Token::new()))
+    }
+
     pub fn free_id(&self, id: I) {
         self.identity.free(id)
     }
 }
 
+impl<T, I: TypedId + Copy + Debug, F: IdentityHandlerFactory<I>> Registry<T, I, F> {
+    /// Warn about the objects that the user never released, and return their IDs.
+    fn report_leaks(&self, name: &str) -> Vec<I> {
+        let leaked = self.data.read().ids(self.backend);
+        if !leaked.is_empty() {
+            log::warn!("Leaked {} {}: {:?}", leaked.len(), name, leaked);
+        }
+        leaked
+    }
+}
+
 #[derive(Debug)]
 pub struct Hub<B: hal::Backend, F: GlobalIdentityHandlerFactory> {
     pub adapters: Registry<Adapter<B>, AdapterId, F>,
@@ -390,7 +495,9 @@ pub struct Hub<B: hal::Backend, F: GlobalIdentityHandlerFactory> {
     pub command_buffers: Registry<CommandBuffer<B>, CommandBufferId, F>,
     pub render_pipelines: Registry<RenderPipeline<B>, RenderPipelineId, F>,
     pub compute_pipelines: Registry<ComputePipeline<B>, ComputePipelineId, F>,
+    pub pipeline_caches: Registry<PipelineCache<B>, PipelineCacheId, F>,
     pub buffers: Registry<Buffer<B>, BufferId, F>,
+    pub staging_buffers: Registry<StagingBuffer<B>, StagingBufferId, F>,
     pub textures: Registry<Texture<B>, TextureId, F>,
     pub texture_views: Registry<TextureView<B>, TextureViewId, F>,
     pub samplers: Registry<Sampler<B>, SamplerId, F>,
@@ -409,7 +516,9 @@ impl<B: GfxBackend, F: GlobalIdentityHandlerFactory> Hub<B, F> {
             command_buffers: Registry::new(B::VARIANT, factory),
             render_pipelines: Registry::new(B::VARIANT, factory),
             compute_pipelines: Registry::new(B::VARIANT, factory),
+            pipeline_caches: Registry::new(B::VARIANT, factory),
             buffers: Registry::new(B::VARIANT, factory),
+            staging_buffers: Registry::new(B::VARIANT, factory),
             textures: Registry::new(B::VARIANT, factory),
             texture_views: Registry::new(B::VARIANT, factory),
             samplers: Registry::new(B::VARIANT, factory),
@@ -418,13 +527,91 @@ impl<B: GfxBackend, F: GlobalIdentityHandlerFactory> Hub<B, F> {
 }
 
 impl<B: GfxBackend, F: GlobalIdentityHandlerFactory> Hub<B, F> {
+    /// Warn about the objects that the user never released.
+    fn report_leaks(&self) {
+        self.devices.report_leaks("devices");
+        self.swap_chains.report_leaks("swap chains");
+        self.pipeline_layouts.report_leaks("pipeline layouts");
+        self.shader_modules.report_leaks("shader modules");
+        self.bind_group_layouts.report_leaks("bind group layouts");
+        self.bind_groups.report_leaks("bind groups");
+        self.command_buffers.report_leaks("command buffers");
+        self.render_pipelines.report_leaks("render pipelines");
+        self.compute_pipelines.report_leaks("compute pipelines");
+        self.pipeline_caches.report_leaks("pipeline caches");
+        self.buffers.report_leaks("buffers");
+        self.staging_buffers.report_leaks("staging buffers");
+        self.textures.report_leaks("textures");
+        self.texture_views.report_leaks("texture views");
+        self.samplers.report_leaks("samplers");
+    }
+
+    /// Destroy everything left in the hub, children before their parents.
+    ///
+    /// The callbacks are expected to be aborted already, so none are fired here.
     fn clear(&mut self, surface_guard: &mut Storage<Surface, SurfaceId>) {
         use crate::resource::TextureViewInner;
         use hal::{device::Device as _, window::PresentationSurface as _};
 
+        self.report_leaks();
+
         let mut devices = self.devices.data.write();
         for (device, _) in devices.map.values_mut() {
-            device.prepare_to_die();
+            let (readbacks, work_done) = device.prepare_to_die();
+            debug_assert!(readbacks.is_empty() && work_done.is_empty());
+        }
+
+        for (_, (mut command_buffer, _)) in self.command_buffers.data.write().map.drain() {
+            let device = &devices[command_buffer.device_id.value];
+            for ring in command_buffer.inline_uniforms.drain(..) {
+                device.destroy_inline_uniform_ring(ring);
+            }
+            device.com_allocator.discard(command_buffer);
+        }
+        for (_, (bind_group, _)) in self.bind_groups.data.write().map.drain() {
+            let device = &devices[bind_group.device_id.value];
+            device.destroy_bind_group(bind_group);
+        }
+
+        for (_, (pipeline, _)) in self.compute_pipelines.data.write().map.drain() {
+            let device = &devices[pipeline.device_id.value];
+            if let Some(raw) = pipeline.raw.into_ready() {
+                unsafe {
+                    device.raw.destroy_compute_pipeline(raw);
+                }
+            }
+        }
+        for (_, (pipeline, _)) in self.render_pipelines.data.write().map.drain() {
+            let device = &devices[pipeline.device_id.value];
+            if let Some(raw) = pipeline.raw.into_ready() {
+                unsafe {
+                    device.raw.destroy_graphics_pipeline(raw);
+                }
+            }
+        }
+        for (_, (cache, _)) in self.pipeline_caches.data.write().map.drain() {
+            let device = &devices[cache.device_id.value];
+            unsafe {
+                device.raw.destroy_pipeline_cache(cache.raw);
+            }
+        }
+        for (_, (pipeline_layout, _)) in self.pipeline_layouts.data.write().map.drain() {
+            let device = &devices[pipeline_layout.device_id.value];
+            unsafe {
+                device.raw.destroy_pipeline_layout(pipeline_layout.raw);
+            }
+        }
+        for (_, (bgl, _)) in self.bind_group_layouts.data.write().map.drain() {
+            let device = &devices[bgl.device_id.value];
+            unsafe {
+                device.raw.destroy_descriptor_set_layout(bgl.raw);
+            }
+        }
+        for (_, (module, _)) in self.shader_modules.data.write().map.drain() {
+            let device = &devices[module.device_id.value];
+            unsafe {
+                device.raw.destroy_shader_module(module.raw);
+            }
         }
 
         for (_, (sampler, _)) in self.samplers.data.write().map.drain() {
@@ -444,57 +631,20 @@ impl<B: GfxBackend, F: GlobalIdentityHandlerFactory> Hub<B, F> {
                             device.raw.destroy_image_view(raw);
                         }
                     }
-                    TextureViewInner::SwapChain { .. } => {} //TODO
+                    // The swap chain owns the view of its frame.
+                    TextureViewInner::SwapChain { .. } => {}
                 }
             }
         }
-
         for (_, (texture, _)) in self.textures.data.write().map.drain() {
             devices[texture.device_id.value].destroy_texture(texture);
         }
+
         for (_, (buffer, _)) in self.buffers.data.write().map.drain() {
-            //TODO: unmap if needed
             devices[buffer.device_id.value].destroy_buffer(buffer);
         }
-        for (_, (command_buffer, _)) in self.command_buffers.data.write().map.drain() {
-            devices[command_buffer.device_id.value]
-                .com_allocator
-                .after_submit(command_buffer, 0);
-        }
-        for (_, (bind_group, _)) in self.bind_groups.data.write().map.drain() {
-            let device = &devices[bind_group.device_id.value];
-            device.destroy_bind_group(bind_group);
-        }
-
-        for (_, (module, _)) in self.shader_modules.data.write().map.drain() {
-            let device = &devices[module.device_id.value];
-            unsafe {
-                device.raw.destroy_shader_module(module.raw);
-            }
-        }
-        for (_, (bgl, _)) in self.bind_group_layouts.data.write().map.drain() {
-            let device = &devices[bgl.device_id.value];
-            unsafe {
-                device.raw.destroy_descriptor_set_layout(bgl.raw);
-            }
-        }
-        for (_, (pipeline_layout, _)) in self.pipeline_layouts.data.write().map.drain() {
-            let device = &devices[pipeline_layout.device_id.value];
-            unsafe {
-                device.raw.destroy_pipeline_layout(pipeline_layout.raw);
-            }
-        }
-        for (_, (pipeline, _)) in self.compute_pipelines.data.write().map.drain() {
-            let device = &devices[pipeline.device_id.value];
-            unsafe {
-                device.raw.destroy_compute_pipeline(pipeline.raw);
-            }
-        }
-        for (_, (pipeline, _)) in self.render_pipelines.data.write().map.drain() {
-            let device = &devices[pipeline.device_id.value];
-            unsafe {
-                device.raw.destroy_graphics_pipeline(pipeline.raw);
-            }
+        for (_, (staging, _)) in self.staging_buffers.data.write().map.drain() {
+            devices[staging.device_id.value].destroy_staging_buffer(staging);
         }
 
         for (index, (swap_chain, epoch)) in self.swap_chains.data.write().map.drain() {
@@ -502,7 +652,7 @@ impl<B: GfxBackend, F: GlobalIdentityHandlerFactory> Hub<B, F> {
             let surface = &mut surface_guard[TypedId::zip(index as Index, epoch, B::VARIANT)];
             let suf = B::get_surface_mut(surface);
             unsafe {
-                device.raw.destroy_semaphore(swap_chain.semaphore);
+                swap_chain.destroy_semaphores(&device.raw);
                 suf.unconfigure_swapchain(&device.raw);
             }
         }
@@ -526,6 +676,8 @@ pub struct Hubs<F: GlobalIdentityHandlerFactory> {
     dx12: Hub<backend::Dx12, F>,
     #[cfg(windows)]
     dx11: Hub<backend::Dx11, F>,
+    #[cfg(feature = "gl")]
+    gl: Hub<backend::Gl, F>,
 }
 
 impl<F: GlobalIdentityHandlerFactory> Hubs<F> {
@@ -542,6 +694,8 @@ impl<F: GlobalIdentityHandlerFactory> Hubs<F> {
             dx12: Hub::new(factory),
             #[cfg(windows)]
             dx11: Hub::new(factory),
+            #[cfg(feature = "gl")]
+            gl: Hub::new(factory),
         }
     }
 }
@@ -551,14 +705,20 @@ pub struct Global<G: GlobalIdentityHandlerFactory> {
     pub instance: Instance,
     pub surfaces: Registry<Surface, SurfaceId, G>,
     hubs: Hubs<G>,
+    pub(crate) deterministic_ids: bool,
 }
 
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
     pub fn new(name: &str, factory: G) -> Self {
+        Self::with_descriptor(name, factory, &wgt::InstanceDescriptor::default())
+    }
+
+    pub fn with_descriptor(name: &str, factory: G, desc: &wgt::InstanceDescriptor) -> Self {
         Global {
-            instance: Instance::new(name, 1),
+            instance: Instance::new(name, 1, desc),
             surfaces: Registry::without_backend(&factory),
             hubs: Hubs::new(&factory),
+            deterministic_ids: factory.deterministic_ids(),
         }
     }
 }
@@ -567,6 +727,9 @@ impl<G: GlobalIdentityHandlerFactory> Drop for Global<G> {
     fn drop(&mut self) {
         if !thread::panicking() {
             log::info!("Dropping Global");
+            // Let the devices finish their work and free whatever the user released,
+            // then abort the callbacks while the hubs are still intact.
+            self.abort_all_devices().fire();
             let mut surface_guard = self.surfaces.data.write();
             // destroy hubs
             #[cfg(any(
@@ -580,6 +743,8 @@ impl<G: GlobalIdentityHandlerFactory> Drop for Global<G> {
             self.hubs.dx12.clear(&mut *surface_guard);
             #[cfg(windows)]
             self.hubs.dx11.clear(&mut *surface_guard);
+            #[cfg(feature = "gl")]
+            self.hubs.gl.clear(&mut *surface_guard);
             // destroy surfaces
             for (_, (surface, _)) in surface_guard.map.drain() {
                 self.instance.destroy_surface(surface);
@@ -640,3 +805,180 @@ impl GfxBackend for backend::Dx11 {
         &mut surface.dx11
     }
 }
+
+#[cfg(feature = "gl")]
+impl GfxBackend for backend::Gl {
+    const VARIANT: Backend = Backend::Gl;
+    fn hub<G: GlobalIdentityHandlerFactory>(global: &Global<G>) -> &Hub<Self, G> {
+        &global.hubs.gl
+    }
+    fn get_surface_mut(surface: &mut Surface) -> &mut Self::Surface {
+        surface.gl.as_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic_ids() {
+        let mut manager = IdentityManager::default();
+        let first: BufferId = manager.alloc(Backend::Empty);
+        manager.free(first);
+        let second: BufferId = manager.alloc(Backend::Empty);
+        assert_eq!(second.unzip(), (0, 2, Backend::Empty));
+
+        let mut manager = IdentityManager::deterministic(0);
+        let first: BufferId = manager.alloc(Backend::Empty);
+        manager.free(first);
+        let second: BufferId = manager.alloc(Backend::Empty);
+        assert_eq!(second.unzip(), (1, 1, Backend::Empty));
+    }
+
+    #[test]
+    fn leaked_ids() {
+        let registry = Registry::<(), BufferId, _>::new(Backend::Empty, &IdentityManagerFactory);
+        let ids = (0..3)
+            .map(|_| registry.identity.process(PhantomData, Backend::Empty))
+            .collect::<Vec<_>>();
+        for &id in &ids {
+            registry.data.write().insert(id, ());
+        }
+        // Released objects are no leaks, even once their index is reused.
+        registry.data.write().remove(ids[1]);
+        registry.free_id(ids[1]);
+        let reused = registry.identity.process(PhantomData, Backend::Empty);
+        registry.data.write().insert(reused, ());
+        assert_eq!(reused.unzip(), (1, 2, Backend::Empty));
+        assert_eq!(registry.report_leaks("buffers"), [ids[0], reused, ids[2]]);
+    }
+
+    #[test]
+    fn invalid_storage_ids() {
+        let mut storage = Storage::<u32, BufferId> {
+            map: VecMap::new(),
+            _phantom: PhantomData,
+        };
+        let stale = BufferId::zip(0, 1, Backend::Empty);
+        let fresh = BufferId::zip(0, 2, Backend::Empty);
+        storage.insert(stale, 1);
+        storage.remove(stale);
+        storage.insert(fresh, 2);
+        // IDs carry no type, so the ID of another type is only rejected
+        // when its index and epoch don't name a live buffer, as here.
+        let mut textures = IdentityManager::default();
+        let (index, epoch, backend) = textures.alloc::<TextureId>(Backend::Empty).unzip();
+        let cross_type = BufferId::zip(index, epoch, backend);
+
+        for &id in &[BufferId::default(), stale, cross_type] {
+            assert!(!storage.contains(id));
+            assert_eq!(storage.get(id), None);
+            assert_eq!(storage.get_mut(id), None);
+        }
+        assert_eq!(storage.get(fresh), Some(&2));
+    }
+
+    #[test]
+    fn invalid_global_ids() {
+        use crate::{
+            command::{BufferCopyError, CommandEncoderError},
+            device::{AllocationError, InvalidIdError, QueueBufferError, QueueSubmitError},
+            id::{CommandEncoderId, QueueId},
+            resource::{BufferMapAsyncStatus, BufferMapOperation},
+        };
+
+        #[cfg(not(any(target_os = "ios", target_os = "macos")))]
+        type TestBackend = backend::Vulkan;
+        #[cfg(any(target_os = "ios", target_os = "macos"))]
+        type TestBackend = backend::Metal;
+
+        unsafe extern "C" fn map_callback(
+            status: BufferMapAsyncStatus,
+            _data: *const u8,
+            userdata: *mut u8,
+        ) {
+            *(userdata as *mut Option<BufferMapAsyncStatus>) = Some(status);
+        }
+
+        let global = Global::new("test", IdentityManagerFactory);
+        let mut textures = IdentityManager::default();
+        let (index, epoch, backend) = textures.alloc::<TextureId>(TestBackend::VARIANT).unzip();
+        let cross_type = BufferId::zip(index, epoch, backend);
+        let desc = wgt::BufferDescriptor {
+            label: std::ptr::null(),
+            size: 4,
+            usage: wgt::BufferUsage::COPY_DST,
+            memory_hint: wgt::MemoryHint::Performance,
+            transient: false,
+        };
+
+        assert_eq!(
+            global.queue_submit::<TestBackend>(QueueId::default(), &[]),
+            Err(QueueSubmitError::InvalidQueue(QueueId::default()))
+        );
+        assert_eq!(
+            global.queue_write_buffer::<TestBackend>(QueueId::default(), &[0; 4], cross_type, 0),
+            Err(QueueBufferError::InvalidQueue(QueueId::default()))
+        );
+        assert_eq!(
+            global.command_encoder_copy_buffer_to_buffer::<TestBackend>(
+                CommandEncoderId::default(),
+                BufferId::default(),
+                0,
+                cross_type,
+                0,
+                4,
+            ),
+            Err(BufferCopyError::Encoder(CommandEncoderError::Consumed))
+        );
+
+        for &buffer_id in &[BufferId::default(), cross_type] {
+            let mut status = None;
+            global.buffer_map_async::<TestBackend>(
+                buffer_id,
+                0..4,
+                BufferMapOperation::Read {
+                    callback: map_callback,
+                    userdata: &mut status as *mut Option<BufferMapAsyncStatus> as *mut u8,
+                },
+            );
+            assert_eq!(status, Some(BufferMapAsyncStatus::Error));
+            global.buffer_unmap::<TestBackend>(buffer_id);
+            global.buffer_destroy::<TestBackend>(buffer_id);
+        }
+        global.texture_destroy::<TestBackend>(TextureId::default());
+        global.command_encoder_destroy::<TestBackend>(CommandEncoderId::default());
+
+        assert_eq!(
+            global.device_create_buffer::<TestBackend>(DeviceId::default(), &desc, PhantomData),
+            Err(AllocationError::InvalidId(InvalidIdError::Device(
+                DeviceId::default()
+            )))
+        );
+        assert_eq!(
+            global.texture_create_view::<TestBackend>(TextureId::default(), None, PhantomData),
+            Err(InvalidIdError::Texture(TextureId::default()))
+        );
+        assert_eq!(
+            global.device_limits::<TestBackend>(DeviceId::default()),
+            Err(InvalidIdError::Device(DeviceId::default()))
+        );
+
+        // IDs of a backend that isn't enabled, or of no backend at all,
+        // get the error given to `gfx_select!`.
+        let forged = BufferId::from_raw(!0).unwrap();
+        assert_eq!(forged.backend(), Backend::Empty);
+        assert_eq!(
+            crate::gfx_select!(forged => global.buffer_get_size(forged)
+                else Err(InvalidIdError::Buffer(forged))),
+            Err(InvalidIdError::Buffer(forged))
+        );
+        let valid = BufferId::zip(0, 1, TestBackend::VARIANT);
+        assert_eq!(
+            crate::gfx_select!(valid => global.buffer_get_size(valid)
+                else Err(InvalidIdError::Buffer(valid))),
+            Err(InvalidIdError::Buffer(valid))
+        );
+    }
+}
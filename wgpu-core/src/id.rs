@@ -65,7 +65,9 @@ impl<T> Id<T> {
             3 => Backend::Dx12,
             4 => Backend::Dx11,
             5 => Backend::Gl,
-            _ => unreachable!(),
+            6 => Backend::BrowserWebGpu,
+            // Not a backend at all: the ID is forged, and belongs to no object.
+            _ => Backend::Empty,
         }
     }
 
@@ -155,6 +157,7 @@ pub type BufferId = Id<crate::resource::Buffer<Dummy>>;
 pub type TextureViewId = Id<crate::resource::TextureView<Dummy>>;
 pub type TextureId = Id<crate::resource::Texture<Dummy>>;
 pub type SamplerId = Id<crate::resource::Sampler<Dummy>>;
+pub type StagingBufferId = Id<crate::resource::StagingBuffer<Dummy>>;
 // Binding model
 pub type BindGroupLayoutId = Id<crate::binding_model::BindGroupLayout<Dummy>>;
 pub type PipelineLayoutId = Id<crate::binding_model::PipelineLayout<Dummy>>;
@@ -163,6 +166,7 @@ pub type BindGroupId = Id<crate::binding_model::BindGroup<Dummy>>;
 pub type ShaderModuleId = Id<crate::pipeline::ShaderModule<Dummy>>;
 pub type RenderPipelineId = Id<crate::pipeline::RenderPipeline<Dummy>>;
 pub type ComputePipelineId = Id<crate::pipeline::ComputePipeline<Dummy>>;
+pub type PipelineCacheId = Id<crate::pipeline::PipelineCache<Dummy>>;
 // Command
 pub type CommandBufferId = Id<crate::command::CommandBuffer<Dummy>>;
 pub type CommandEncoderId = CommandBufferId;
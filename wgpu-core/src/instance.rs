@@ -3,14 +3,15 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    backend,
-    device::Device,
+    backend, conv,
+    device::{Device, InvalidIdError, MAX_COLOR_TARGETS, MAX_VERTEX_BUFFERS},
     hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Input, Token},
     id::{AdapterId, DeviceId, SurfaceId},
-    power, LifeGuard, Stored,
+    pipeline::ShaderFeatures,
+    power, LifeGuard, PrivateFeatures, Stored,
 };
 
-use wgt::{Backend, BackendBit, DeviceDescriptor, PowerPreference, BIND_BUFFER_ALIGNMENT};
+use wgt::{Backend, BackendBit, DeviceDescriptor, PowerPreference};
 
 #[cfg(feature = "replay")]
 use serde::Deserialize;
@@ -32,6 +33,8 @@ use hal::{
 pub struct RequestAdapterOptions {
     pub power_preference: PowerPreference,
     pub compatible_surface: Option<SurfaceId>,
+    /// Only pick a software adapter, and none if there is no such adapter.
+    pub force_fallback_adapter: bool,
 }
 
 impl Default for RequestAdapterOptions {
@@ -39,6 +42,7 @@ impl Default for RequestAdapterOptions {
         RequestAdapterOptions {
             power_preference: PowerPreference::Default,
             compatible_surface: None,
+            force_fallback_adapter: false,
         }
     }
 }
@@ -56,22 +60,24 @@ pub struct Instance {
     pub dx12: Option<gfx_backend_dx12::Instance>,
     #[cfg(windows)]
     pub dx11: gfx_backend_dx11::Instance,
+    pub(crate) validation: bool,
 }
 
 impl Instance {
-    pub fn new(name: &str, version: u32) -> Self {
+    pub fn new(name: &str, version: u32, desc: &wgt::InstanceDescriptor) -> Self {
         Instance {
             #[cfg(any(
                 not(any(target_os = "ios", target_os = "macos")),
                 feature = "gfx-backend-vulkan"
             ))]
-            vulkan: gfx_backend_vulkan::Instance::create(name, version).ok(),
+            vulkan: create_vulkan_instance(name, version, desc),
             #[cfg(any(target_os = "ios", target_os = "macos"))]
             metal: gfx_backend_metal::Instance::create(name, version).unwrap(),
             #[cfg(windows)]
             dx12: gfx_backend_dx12::Instance::create(name, version).ok(),
             #[cfg(windows)]
             dx11: gfx_backend_dx11::Instance::create(name, version).unwrap(),
+            validation: desc.validation,
         }
     }
 
@@ -96,11 +102,104 @@ impl Instance {
             }
             self.dx11.destroy_surface(surface.dx11);
         }
+        // GL surfaces own their context, and release it when dropped.
+        #[cfg(feature = "gl")]
+        drop(surface.gl);
     }
 }
 
+/// Lowest `maxVertexAttribDivisor` that Vulkan allows to report,
+/// the other backends don't limit the step rate.
+const MAX_VERTEX_STEP_RATE: u32 = 0xFFFF;
+
+/// Vulkan is only available on Apple platforms through MoltenVK.
+const VULKAN_IS_PORTABILITY: bool = cfg!(any(target_os = "ios", target_os = "macos"));
+
+#[cfg(any(
+    not(any(target_os = "ios", target_os = "macos")),
+    feature = "gfx-backend-vulkan"
+))]
+fn create_vulkan_instance(
+    name: &str,
+    version: u32,
+    desc: &wgt::InstanceDescriptor,
+) -> Option<gfx_backend_vulkan::Instance> {
+    if VULKAN_IS_PORTABILITY && !desc.allow_portability {
+        log::info!("Skipping Vulkan, which is only provided by a portability implementation");
+        return None;
+    }
+    let instance = gfx_backend_vulkan::Instance::create(name, version).ok()?;
+    // The backend picks the extensions to enable on its own, so the requested
+    // ones can only be checked against what it ended up with.
+    let is_enabled = |name: &String| {
+        instance
+            .extensions
+            .iter()
+            .any(|extension| extension.to_str() == Ok(name.as_str()))
+    };
+    if let Some(name) = desc
+        .required_extensions
+        .iter()
+        .find(|name| !is_enabled(name))
+    {
+        log::warn!(
+            "Skipping Vulkan, the instance extension {} is missing",
+            name
+        );
+        return None;
+    }
+    for name in desc
+        .optional_extensions
+        .iter()
+        .filter(|name| !is_enabled(name))
+    {
+        log::info!("Vulkan instance extension {} is not available", name);
+    }
+    Some(instance)
+}
+
 type GfxSurface<B> = <B as hal::Backend>::Surface;
 
+/// Create the MoltenVK surface of a `CAMetalLayer`, which MoltenVK accepts
+/// in place of the view. gfx-backend-vulkan only creates surfaces from views.
+#[cfg(all(
+    any(target_os = "ios", target_os = "macos"),
+    feature = "gfx-backend-vulkan"
+))]
+unsafe fn create_vulkan_surface_from_layer(
+    instance: &gfx_backend_vulkan::Instance,
+    layer: *mut std::ffi::c_void,
+) -> Option<GfxSurface<backend::Vulkan>> {
+    use ash::{extensions::mvk, vk};
+
+    #[cfg(target_os = "macos")]
+    let extension = mvk::MacOSSurface::name();
+    #[cfg(target_os = "ios")]
+    let extension = mvk::IOSSurface::name();
+    if !instance.extensions.contains(&extension) {
+        return None;
+    }
+    let entry = ash::Entry::new().ok()?;
+    #[cfg(target_os = "macos")]
+    let surface = mvk::MacOSSurface::new(&entry, &instance.raw.0).create_mac_os_surface_mvk(
+        &vk::MacOSSurfaceCreateInfoMVK::builder().view(&*layer),
+        None,
+    );
+    #[cfg(target_os = "ios")]
+    let surface = mvk::IOSSurface::new(&entry, &instance.raw.0)
+        .create_ios_surface_mvk(&vk::IOSSurfaceCreateInfoMVK::builder().view(&*layer), None);
+    match surface {
+        Ok(surface) => Some(instance.create_surface_from_vk_surface_khr(surface)),
+        Err(error) => {
+            log::warn!(
+                "Failed to create a Vulkan surface of the layer: {:?}",
+                error
+            );
+            None
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Surface {
     #[cfg(any(
@@ -114,6 +213,11 @@ pub struct Surface {
     pub dx12: Option<GfxSurface<backend::Dx12>>,
     #[cfg(windows)]
     pub dx11: GfxSurface<backend::Dx11>,
+    #[cfg(feature = "gl")]
+    pub gl: Option<GfxSurface<backend::Gl>>,
+    /// Layer of the caller that the surface presents into, if any.
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    pub(crate) adopted_layer: Option<crate::swap_chain::AdoptedLayer>,
 }
 
 #[derive(Debug)]
@@ -129,6 +233,236 @@ impl<B: hal::Backend> Adapter<B> {
             life_guard: LifeGuard::new(),
         }
     }
+
+    fn capabilities(raw: &hal::adapter::Adapter<B>) -> wgt::Capabilities {
+        let features = raw.physical_device.features();
+        let mut caps = wgt::Capabilities::empty();
+        caps.set(
+            wgt::Capabilities::INDEPENDENT_BLEND,
+            features.contains(hal::Features::INDEPENDENT_BLENDING),
+        );
+        caps.set(
+            wgt::Capabilities::SAMPLE_SHADING,
+            features.contains(hal::Features::SAMPLE_RATE_SHADING),
+        );
+        caps.set(
+            wgt::Capabilities::VERTEX_INSTANCE_RATE,
+            features.contains(hal::Features::INSTANCE_RATE),
+        );
+        let limits = raw.physical_device.limits();
+        caps.set(
+            wgt::Capabilities::INLINE_UNIFORM_WRITES,
+            limits.max_descriptor_set_uniform_buffers_dynamic != 0
+                && limits.min_uniform_buffer_offset_alignment
+                    <= crate::command::MAX_INLINE_UNIFORM_SIZE as hal::buffer::Offset,
+        );
+        //TODO: LAYERED_RENDERING needs gfx-hal to expose VK_EXT_shader_viewport_index_layer
+        // and the Metal vertex amplification.
+        caps
+    }
+
+    fn limits(raw: &hal::adapter::Adapter<B>, backend: Backend) -> wgt::Limits {
+        let limits = raw.physical_device.limits();
+        let defaults = wgt::Limits::default();
+        // Some backends don't report every limit, assume the defaults are supported then.
+        let or_default = |value: usize, default: u32| match value {
+            0 => default,
+            value => value.min(u32::MAX as usize) as u32,
+        };
+        wgt::Limits {
+            max_bind_groups: match limits.max_bound_descriptor_sets {
+                0 => wgt::MAX_BIND_GROUPS as u32,
+                count => u32::from(count),
+            },
+            min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment.max(1)
+                as u32,
+            min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment.max(1)
+                as u32,
+            max_compute_shared_memory_size: or_default(
+                limits.max_compute_shared_memory_size,
+                defaults.max_compute_shared_memory_size,
+            ),
+            max_storage_buffers_per_shader_stage: or_default(
+                limits.max_per_stage_descriptor_storage_buffers,
+                defaults.max_storage_buffers_per_shader_stage,
+            ),
+            max_storage_textures_per_shader_stage: or_default(
+                limits.max_per_stage_descriptor_storage_images,
+                defaults.max_storage_textures_per_shader_stage,
+            ),
+            max_sampled_textures_per_shader_stage: or_default(
+                limits.max_per_stage_descriptor_sampled_images,
+                defaults.max_sampled_textures_per_shader_stage,
+            ),
+            max_samplers_per_shader_stage: or_default(
+                limits.max_per_stage_descriptor_samplers,
+                defaults.max_samplers_per_shader_stage,
+            ),
+            max_uniform_buffers_per_shader_stage: or_default(
+                limits.max_per_stage_descriptor_uniform_buffers,
+                defaults.max_uniform_buffers_per_shader_stage,
+            ),
+            // Binding numbers are only bounded by the descriptor set layout size.
+            max_bindings_per_bind_group: defaults.max_bindings_per_bind_group,
+            max_color_attachments: or_default(
+                limits.max_color_attachments,
+                defaults.max_color_attachments,
+            )
+            .min(MAX_COLOR_TARGETS as u32),
+            max_vertex_buffers: or_default(
+                limits.max_vertex_input_bindings,
+                defaults.max_vertex_buffers,
+            )
+            .min(MAX_VERTEX_BUFFERS as u32),
+            max_vertex_step_rate: if raw
+                .physical_device
+                .features()
+                .contains(hal::Features::INSTANCE_RATE)
+            {
+                MAX_VERTEX_STEP_RATE
+            } else {
+                1
+            },
+            max_buffer_size: {
+                // A buffer can't be larger than the largest memory heap,
+                // and gfx-backend-dx11 has 32-bit buffer sizes.
+                let heap_size = raw
+                    .physical_device
+                    .memory_properties()
+                    .memory_heaps
+                    .iter()
+                    .cloned()
+                    .max()
+                    .unwrap_or(defaults.max_buffer_size);
+                match backend {
+                    Backend::Dx11 => heap_size.min(u32::MAX as wgt::BufferAddress),
+                    _ => heap_size,
+                }
+            },
+            max_texture_dimension_1d: or_default(
+                limits.max_image_1d_size as usize,
+                defaults.max_texture_dimension_1d,
+            ),
+            max_texture_dimension_2d: or_default(
+                limits.max_image_2d_size as usize,
+                defaults.max_texture_dimension_2d,
+            ),
+            max_texture_dimension_3d: or_default(
+                limits.max_image_3d_size as usize,
+                defaults.max_texture_dimension_3d,
+            ),
+            max_texture_array_layers: or_default(
+                limits.max_image_array_layers as usize,
+                defaults.max_texture_array_layers,
+            ),
+        }
+    }
+
+    fn downlevel_capabilities(
+        raw: &hal::adapter::Adapter<B>,
+        backend: Backend,
+    ) -> wgt::DownlevelCapabilities {
+        let phd = &raw.physical_device;
+        let features = phd.features();
+        let mut downlevel = wgt::DownlevelCapabilities {
+            // gfx-backend-gl exposes general queues on GLES 3.0 contexts as well,
+            // which only shows in the compute limits being zero.
+            compute_shaders: raw
+                .queue_families
+                .iter()
+                .any(|family| family.queue_type().supports_compute())
+                && (backend != Backend::Gl || phd.limits().max_compute_work_group_count[0] != 0),
+            indirect_execution: phd.limits().max_draw_indirect_count != 0,
+            indirect_first_instance: features.contains(hal::Features::DRAW_INDIRECT_FIRST_INSTANCE),
+            base_vertex_instance: phd
+                .hints()
+                .contains(hal::Hints::BASE_VERTEX_INSTANCE_DRAWING),
+            cube_array_textures: features.contains(hal::Features::IMAGE_CUBE_ARRAY),
+            independent_blend: features.contains(hal::Features::INDEPENDENT_BLENDING),
+            // D3D10 level blend states keep a write mask per target.
+            independent_write_mask: features.contains(hal::Features::INDEPENDENT_BLENDING)
+                || backend == Backend::Dx11,
+            anisotropic_filtering: features.contains(hal::Features::SAMPLER_ANISOTROPY),
+            fragment_writable_storage: features
+                .contains(hal::Features::FRAGMENT_STORES_AND_ATOMICS),
+            depth_bias_clamp: features.contains(hal::Features::DEPTH_BIAS_CLAMP),
+            early_fragment_tests: true,
+            // Conservative depth outputs need shader model 5.0 on D3D,
+            // while gfx-backend-dx11 also runs on 10_x feature levels.
+            conservative_depth: backend != Backend::Dx11,
+        };
+        let workarounds =
+            AdapterWorkarounds::from_info(&AdapterInfo::from_gfx(raw.info.clone(), backend));
+        if workarounds.contains(AdapterWorkarounds::NO_INDIRECT_EXECUTION) {
+            downlevel.indirect_execution = false;
+        }
+        downlevel
+    }
+
+    pub(crate) fn private_features(raw: &hal::adapter::Adapter<B>) -> PrivateFeatures {
+        PrivateFeatures {
+            supports_texture_d24_s8: raw
+                .physical_device
+                .format_properties(Some(hal::format::Format::D24UnormS8Uint))
+                .optimal_tiling
+                .contains(hal::format::ImageFeature::DEPTH_STENCIL_ATTACHMENT),
+        }
+    }
+
+    pub(crate) fn texture_format_features(
+        raw: &hal::adapter::Adapter<B>,
+        format: wgt::TextureFormat,
+    ) -> wgt::TextureFormatFeatures {
+        use hal::format::ImageFeature as If;
+        use wgt::{TextureFormatFeatureFlags as Tff, TextureUsage as Tu};
+
+        let phd = &raw.physical_device;
+        let hal_format = conv::map_texture_format(format, Self::private_features(raw));
+        let features = phd.format_properties(Some(hal_format)).optimal_tiling;
+
+        let mut allowed_usages = Tu::empty();
+        // D24Plus formats may be backed by different formats, so their contents can't be copied
+        match format {
+            wgt::TextureFormat::Depth24Plus | wgt::TextureFormat::Depth24PlusStencil8 => {}
+            _ => allowed_usages |= Tu::COPY_SRC | Tu::COPY_DST,
+        }
+        allowed_usages.set(Tu::SAMPLED, features.contains(If::SAMPLED));
+        allowed_usages.set(Tu::STORAGE, features.contains(If::STORAGE));
+        let attachment = features.intersects(If::COLOR_ATTACHMENT | If::DEPTH_STENCIL_ATTACHMENT);
+        allowed_usages.set(Tu::OUTPUT_ATTACHMENT | Tu::TRANSIENT_ATTACHMENT, attachment);
+
+        let mut flags = Tff::empty();
+        flags.set(Tff::FILTERABLE, features.contains(If::SAMPLED_LINEAR));
+        flags.set(
+            Tff::BLENDABLE,
+            features.contains(If::COLOR_ATTACHMENT_BLEND),
+        );
+
+        // Multisampled textures are only useful as attachments
+        let sample_counts = if attachment {
+            let usage = if features.contains(If::COLOR_ATTACHMENT) {
+                hal::image::Usage::COLOR_ATTACHMENT
+            } else {
+                hal::image::Usage::DEPTH_STENCIL_ATTACHMENT
+            };
+            phd.image_format_properties(
+                hal_format,
+                2,
+                hal::image::Tiling::Optimal,
+                usage,
+                hal::image::ViewCapabilities::empty(),
+            )
+            .map_or(1, |properties| u32::from(properties.sample_count_mask) | 1)
+        } else {
+            1
+        };
+
+        wgt::TextureFormatFeatures {
+            allowed_usages,
+            flags,
+            sample_counts,
+        }
+    }
 }
 
 /// Metadata about a backend adapter.
@@ -146,6 +480,14 @@ pub struct AdapterInfo {
     pub device_type: DeviceType,
     /// Backend used for device
     pub backend: Backend,
+    /// Whether the adapter is provided by a portability implementation
+    pub portability: bool,
+    /// Whether the adapter renders in software on the CPU
+    pub is_fallback: bool,
+    /// Driver name, empty if unknown
+    pub driver: String,
+    /// Driver version, empty if unknown
+    pub driver_info: String,
 }
 
 impl AdapterInfo {
@@ -157,13 +499,182 @@ impl AdapterInfo {
             device_type,
         } = adapter_info;
 
+        let is_fallback = device_type == HalDeviceType::Cpu;
         AdapterInfo {
             name,
             vendor,
             device,
             device_type: device_type.into(),
             backend,
+            portability: backend == Backend::Vulkan && VULKAN_IS_PORTABILITY,
+            is_fallback,
+            //TODO: gfx-hal doesn't report the driver yet. Fill these from
+            // the Vulkan driver properties, the DXGI user mode driver version,
+            // and the OS version on Metal, once it does.
+            driver: String::new(),
+            driver_info: String::new(),
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// Workarounds for known driver bugs, enabled by wgpu-core on the affected adapters.
+    #[repr(transparent)]
+    pub struct AdapterWorkarounds: u32 {
+        /// Indirect draws and dispatches are broken, so the adapter
+        /// reports no support for indirect execution.
+        const NO_INDIRECT_EXECUTION = 1;
+    }
+}
+
+/// Driver bug that affects all drivers of a vendor older than the fix.
+struct DriverBug {
+    backend: Backend,
+    vendor: usize,
+    /// First driver version with the fix.
+    fixed_in: &'static [u32],
+    workarounds: AdapterWorkarounds,
+}
+
+const VENDOR_QUALCOMM: usize = 0x5143;
+
+const DRIVER_BUGS: &[DriverBug] = &[DriverBug {
+    backend: Backend::Vulkan,
+    vendor: VENDOR_QUALCOMM,
+    fixed_in: &[512],
+    workarounds: AdapterWorkarounds::NO_INDIRECT_EXECUTION,
+}];
+
+/// Names of the limits that are beyond the WebGPU defaults,
+/// which are also the maximums in strict WebGPU mode.
+fn exceeded_webgpu_limits(limits: &wgt::Limits) -> Vec<&'static str> {
+    let spec = wgt::Limits::default();
+    let mut exceeded = Vec::new();
+    if limits.min_uniform_buffer_offset_alignment < spec.min_uniform_buffer_offset_alignment {
+        exceeded.push("min_uniform_buffer_offset_alignment");
+    }
+    if limits.min_storage_buffer_offset_alignment < spec.min_storage_buffer_offset_alignment {
+        exceeded.push("min_storage_buffer_offset_alignment");
+    }
+    if limits.max_buffer_size > spec.max_buffer_size {
+        exceeded.push("max_buffer_size");
+    }
+    for &(name, requested, max) in &[
+        (
+            "max_bind_groups",
+            limits.max_bind_groups,
+            spec.max_bind_groups,
+        ),
+        (
+            "max_compute_shared_memory_size",
+            limits.max_compute_shared_memory_size,
+            spec.max_compute_shared_memory_size,
+        ),
+        (
+            "max_storage_buffers_per_shader_stage",
+            limits.max_storage_buffers_per_shader_stage,
+            spec.max_storage_buffers_per_shader_stage,
+        ),
+        (
+            "max_storage_textures_per_shader_stage",
+            limits.max_storage_textures_per_shader_stage,
+            spec.max_storage_textures_per_shader_stage,
+        ),
+        (
+            "max_sampled_textures_per_shader_stage",
+            limits.max_sampled_textures_per_shader_stage,
+            spec.max_sampled_textures_per_shader_stage,
+        ),
+        (
+            "max_samplers_per_shader_stage",
+            limits.max_samplers_per_shader_stage,
+            spec.max_samplers_per_shader_stage,
+        ),
+        (
+            "max_uniform_buffers_per_shader_stage",
+            limits.max_uniform_buffers_per_shader_stage,
+            spec.max_uniform_buffers_per_shader_stage,
+        ),
+        (
+            "max_bindings_per_bind_group",
+            limits.max_bindings_per_bind_group,
+            spec.max_bindings_per_bind_group,
+        ),
+        (
+            "max_color_attachments",
+            limits.max_color_attachments,
+            spec.max_color_attachments,
+        ),
+        (
+            "max_vertex_buffers",
+            limits.max_vertex_buffers,
+            spec.max_vertex_buffers,
+        ),
+        (
+            "max_vertex_step_rate",
+            limits.max_vertex_step_rate,
+            spec.max_vertex_step_rate,
+        ),
+        (
+            "max_texture_dimension_1d",
+            limits.max_texture_dimension_1d,
+            spec.max_texture_dimension_1d,
+        ),
+        (
+            "max_texture_dimension_2d",
+            limits.max_texture_dimension_2d,
+            spec.max_texture_dimension_2d,
+        ),
+        (
+            "max_texture_dimension_3d",
+            limits.max_texture_dimension_3d,
+            spec.max_texture_dimension_3d,
+        ),
+        (
+            "max_texture_array_layers",
+            limits.max_texture_array_layers,
+            spec.max_texture_array_layers,
+        ),
+    ] {
+        if requested > max {
+            exceeded.push(name);
+        }
+    }
+    exceeded
+}
+
+/// Parse the leading numbers of a version string, like "512.415.0".
+fn parse_driver_version(version: &str) -> Vec<u32> {
+    let mut numbers = Vec::new();
+    for part in version
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(|c: char| c == '.' || c == ' ')
+    {
+        match part.parse() {
+            Ok(number) => numbers.push(number),
+            Err(_) => break,
+        }
+    }
+    numbers
+}
+
+impl AdapterWorkarounds {
+    /// Look up the workarounds for the adapter in the table of known driver bugs.
+    ///
+    /// Bugs only apply when the driver version is known.
+    pub(crate) fn from_info(info: &AdapterInfo) -> Self {
+        let version = parse_driver_version(&info.driver_info);
+        if version.is_empty() {
+            return Self::empty();
         }
+        DRIVER_BUGS
+            .iter()
+            .filter(|bug| {
+                bug.backend == info.backend
+                    && bug.vendor == info.vendor
+                    && version.as_slice() < bug.fixed_in
+            })
+            .fold(Self::empty(), |all, bug| all | bug.workarounds)
     }
 }
 
@@ -216,6 +727,72 @@ impl<I: Clone> AdapterInputs<'_, I> {
     }
 }
 
+/// Create a GLES 3.0 context through EGL for an X11 or Wayland window,
+/// and a GL surface presenting into it.
+#[cfg(all(
+    feature = "gl",
+    feature = "raw-window-handle",
+    unix,
+    not(any(target_os = "ios", target_os = "macos", target_os = "android"))
+))]
+fn create_gl_surface(
+    handle: &impl raw_window_handle::HasRawWindowHandle,
+) -> Option<gfx_backend_gl::Surface> {
+    use gfx_backend_gl::glutin::{
+        self,
+        platform::unix::{x11::XConnection, RawContextExt as _},
+    };
+    use raw_window_handle::RawWindowHandle;
+
+    let builder = glutin::ContextBuilder::new()
+        .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGlEs, (3, 0)));
+    let context = unsafe {
+        match handle.raw_window_handle() {
+            RawWindowHandle::Xlib(handle) => {
+                let xconn = XConnection::new(None).ok()?;
+                builder.build_raw_x11_context(std::sync::Arc::new(xconn), handle.window)
+            }
+            // The EGL window is resized when the swap chain is configured.
+            RawWindowHandle::Wayland(handle) => {
+                builder.build_raw_wayland_context(handle.display as *const _, handle.surface, 1, 1)
+            }
+            _ => return None,
+        }
+    };
+    let context = match context {
+        Ok(context) => context,
+        Err(e) => {
+            log::warn!("Unable to create a GLES context for the window: {:?}", e);
+            return None;
+        }
+    };
+    match unsafe { context.make_current() } {
+        Ok(context) => Some(gfx_backend_gl::Surface::from_context(context)),
+        Err((_, e)) => {
+            log::warn!(
+                "Unable to make the GLES context of the window current: {:?}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// GL surfaces of other platforms only come from existing contexts.
+#[cfg(all(
+    feature = "gl",
+    feature = "raw-window-handle",
+    not(all(
+        unix,
+        not(any(target_os = "ios", target_os = "macos", target_os = "android"))
+    ))
+))]
+fn create_gl_surface(
+    _handle: &impl raw_window_handle::HasRawWindowHandle,
+) -> Option<gfx_backend_gl::Surface> {
+    None
+}
+
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
     #[cfg(feature = "raw-window-handle")]
     pub fn instance_create_surface(
@@ -245,6 +822,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     .and_then(|inst| inst.create_surface(handle).ok()),
                 #[cfg(windows)]
                 dx11: self.instance.dx11.create_surface(handle).unwrap(),
+                #[cfg(feature = "gl")]
+                gl: create_gl_surface(handle),
+                #[cfg(any(target_os = "ios", target_os = "macos"))]
+                adopted_layer: None,
             }
         };
 
@@ -252,6 +833,111 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         self.surfaces.register_identity(id_in, surface, &mut token)
     }
 
+    /// Create a surface that presents into an existing `CAMetalLayer`.
+    ///
+    /// The layer is adopted as is: it isn't attached to a view, moved,
+    /// or resized. The surface retains the layer, and only releases that
+    /// reference when it's destroyed, so the caller keeps owning the layer.
+    /// Creating a swap chain only changes the drawable size, the pixel format
+    /// and the device of the layer. The other properties, like the drawable
+    /// count and vsync, stay as the caller set them, so the present mode of
+    /// the swap chain is ignored, and its usage has to fit `framebufferOnly`.
+    ///
+    /// With MoltenVK, the layer also gets a Vulkan surface.
+    ///
+    /// # Safety
+    ///
+    /// `layer` must be a valid `CAMetalLayer`.
+    #[cfg(any(target_os = "ios", target_os = "macos"))]
+    pub unsafe fn instance_create_surface_from_metal_layer(
+        &self,
+        layer: *mut std::ffi::c_void,
+        id_in: Input<G, SurfaceId>,
+    ) -> SurfaceId {
+        let surface = Surface {
+            #[cfg(feature = "gfx-backend-vulkan")]
+            vulkan: self
+                .instance
+                .vulkan
+                .as_ref()
+                .and_then(|inst| create_vulkan_surface_from_layer(inst, layer)),
+            metal: self
+                .instance
+                .metal
+                .create_surface_from_layer(layer as *mut _, cfg!(debug_assertions)),
+            #[cfg(feature = "gl")]
+            gl: None,
+            adopted_layer: Some(crate::swap_chain::AdoptedLayer(layer as *mut _)),
+        };
+
+        let mut token = Token::root();
+        self.surfaces.register_identity(id_in, surface, &mut token)
+    }
+
+    /// Create a surface that presents into an existing window.
+    ///
+    /// The window isn't owned by the surface: it's neither reparented nor
+    /// resized, and destroying the surface leaves it alive. The window has
+    /// to outlive the surface.
+    ///
+    /// # Safety
+    ///
+    /// `hwnd` must be a valid window, created from the module `hinstance`.
+    #[cfg(windows)]
+    pub unsafe fn instance_create_surface_from_hwnd(
+        &self,
+        hinstance: *mut std::ffi::c_void,
+        hwnd: *mut std::ffi::c_void,
+        id_in: Input<G, SurfaceId>,
+    ) -> SurfaceId {
+        let surface = Surface {
+            vulkan: self
+                .instance
+                .vulkan
+                .as_ref()
+                .map(|inst| inst.create_surface_from_hwnd(hinstance, hwnd)),
+            dx12: self
+                .instance
+                .dx12
+                .as_ref()
+                .map(|inst| inst.create_surface_from_hwnd(hwnd)),
+            dx11: self.instance.dx11.create_surface_from_hwnd(hwnd),
+            #[cfg(feature = "gl")]
+            gl: None,
+        };
+
+        let mut token = Token::root();
+        self.surfaces.register_identity(id_in, surface, &mut token)
+    }
+
+    /// Create a surface that presents into the default framebuffer of an
+    /// existing GL or GLES context, like the EGL contexts made by glutin.
+    ///
+    /// The context is the GL adapter: GL adapters are only enumerated from
+    /// GL surfaces, created this way or by `instance_create_surface` for
+    /// X11 and Wayland windows, and can only present to their own surface.
+    /// No other backend can present to a surface created this way.
+    ///
+    /// Not available on Windows and Apple platforms, where every surface
+    /// also needs a DX11 or Metal counterpart.
+    #[cfg(all(
+        feature = "gl",
+        not(any(windows, target_os = "ios", target_os = "macos"))
+    ))]
+    pub fn instance_create_surface_from_gl_context(
+        &self,
+        context: gfx_backend_gl::glutin::RawContext<gfx_backend_gl::glutin::PossiblyCurrent>,
+        id_in: Input<G, SurfaceId>,
+    ) -> SurfaceId {
+        let surface = Surface {
+            vulkan: None,
+            gl: Some(gfx_backend_gl::Surface::from_context(context)),
+        };
+
+        let mut token = Token::root();
+        self.surfaces.register_identity(id_in, surface, &mut token)
+    }
+
     pub fn enumerate_adapters(&self, inputs: AdapterInputs<Input<G, AdapterId>>) -> Vec<AdapterId> {
         let instance = &self.instance;
         let mut token = Token::root();
@@ -318,6 +1004,23 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 }
             }
         }
+        #[cfg(feature = "gl")]
+        {
+            if let Some(id_gl) = inputs.find(Backend::Gl) {
+                let (surface_guard, mut token) = self.surfaces.read(&mut token);
+                for (_, surface) in surface_guard.iter(Backend::Empty) {
+                    for raw in surface.gl.iter().flat_map(|gl| gl.enumerate_adapters()) {
+                        let adapter = Adapter::new(raw);
+                        log::info!("Adapter Gl {:?}", adapter.raw.info);
+                        adapters.push(backend::Gl::hub(self).adapters.register_identity(
+                            id_gl.clone(),
+                            adapter,
+                            &mut token,
+                        ));
+                    }
+                }
+            }
+        }
 
         adapters
     }
@@ -330,13 +1033,26 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let instance = &self.instance;
         let mut token = Token::root();
         let (surface_guard, mut token) = self.surfaces.read(&mut token);
-        let compatible_surface = desc.compatible_surface.map(|id| &surface_guard[id]);
+        let compatible_surface = match desc.compatible_surface {
+            Some(id) => match surface_guard.get(id) {
+                Some(surface) => Some(surface),
+                None => {
+                    log::error!(
+                        "Picking an adapter compatible with an invalid surface {:?}",
+                        id
+                    );
+                    return None;
+                }
+            },
+            None => None,
+        };
         let mut device_types = Vec::new();
 
         let id_vulkan = inputs.find(Backend::Vulkan);
         let id_metal = inputs.find(Backend::Metal);
         let id_dx12 = inputs.find(Backend::Dx12);
         let id_dx11 = inputs.find(Backend::Dx11);
+        let id_gl = inputs.find(Backend::Gl);
 
         #[cfg(any(
             not(any(target_os = "ios", target_os = "macos")),
@@ -415,6 +1131,26 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         } else {
             Vec::new()
         };
+        // The adapters of GL contexts can only present to their own surface.
+        #[cfg(feature = "gl")]
+        let mut adapters_gl = if id_gl.is_some() {
+            let adapters = match compatible_surface {
+                Some(surface) => surface
+                    .gl
+                    .iter()
+                    .flat_map(|gl| gl.enumerate_adapters())
+                    .collect(),
+                None => surface_guard
+                    .iter(Backend::Empty)
+                    .filter_map(|(_, surface)| surface.gl.as_ref())
+                    .flat_map(|gl| gl.enumerate_adapters())
+                    .collect::<Vec<_>>(),
+            };
+            device_types.extend(adapters.iter().map(|ad| ad.info.device_type.clone()));
+            adapters
+        } else {
+            Vec::new()
+        };
 
         if device_types.is_empty() {
             log::warn!("No adapters are available!");
@@ -422,6 +1158,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         }
 
         let (mut integrated, mut discrete, mut virt, mut other) = (None, None, None, None);
+        let mut fallback = None;
 
         for (i, ty) in device_types.into_iter().enumerate() {
             match ty {
@@ -434,6 +1171,10 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 hal::adapter::DeviceType::VirtualGpu => {
                     virt = virt.or(Some(i));
                 }
+                hal::adapter::DeviceType::Cpu => {
+                    fallback = fallback.or(Some(i));
+                    other = other.or(Some(i));
+                }
                 _ => {
                     other = other.or(Some(i));
                 }
@@ -441,6 +1182,13 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         }
 
         let preferred_gpu = match desc.power_preference {
+            _ if desc.force_fallback_adapter => match fallback {
+                Some(index) => Some(index),
+                None => {
+                    log::warn!("No fallback adapter is available!");
+                    return None;
+                }
+            },
             PowerPreference::Default => match power::is_battery_discharging() {
                 Ok(false) => discrete.or(integrated).or(other).or(virt),
                 Ok(true) => integrated.or(discrete).or(other).or(virt),
@@ -513,39 +1261,239 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
             selected -= adapters_dx11.len();
         }
+        #[cfg(feature = "gl")]
+        {
+            if selected < adapters_gl.len() {
+                let adapter = Adapter::new(adapters_gl.swap_remove(selected));
+                log::info!("Adapter Gl {:?}", adapter.raw.info);
+                let id = backend::Gl::hub(self).adapters.register_identity(
+                    id_gl.unwrap(),
+                    adapter,
+                    &mut token,
+                );
+                return Some(id);
+            }
+            selected -= adapters_gl.len();
+        }
 
-        let _ = (selected, id_vulkan, id_metal, id_dx12, id_dx11);
+        let _ = (selected, id_vulkan, id_metal, id_dx12, id_dx11, id_gl);
         log::warn!("Some adapters are present, but enumerating them failed!");
         None
     }
 
-    pub fn adapter_get_info<B: GfxBackend>(&self, adapter_id: AdapterId) -> AdapterInfo {
+    pub fn adapter_get_info<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<AdapterInfo, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(AdapterInfo::from_gfx(
+            adapter.raw.info.clone(),
+            adapter_id.backend(),
+        ))
+    }
+
+    /// Query the driver workarounds that are active on the adapter,
+    /// which is useful to include in bug reports.
+    pub fn adapter_workarounds<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<AdapterWorkarounds, InvalidIdError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (adapter_guard, _) = hub.adapters.read(&mut token);
-        let adapter = &adapter_guard[adapter_id];
-        AdapterInfo::from_gfx(adapter.raw.info.clone(), adapter_id.backend())
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(AdapterWorkarounds::from_info(&AdapterInfo::from_gfx(
+            adapter.raw.info.clone(),
+            adapter_id.backend(),
+        )))
+    }
+
+    pub fn adapter_extensions<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<wgt::Extensions, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        let features = adapter.raw.physical_device.features();
+        Ok(wgt::Extensions {
+            anisotropic_filtering: features.contains(hal::Features::SAMPLER_ANISOTROPY),
+            mesh_shaders: features.contains(hal::Features::MESH_SHADER),
+            shader_float64: features.contains(hal::Features::SHADER_FLOAT64),
+            shader_int64: features.contains(hal::Features::SHADER_INT64),
+            shader_int16: features.contains(hal::Features::SHADER_INT16),
+            conservative_rasterization: features
+                .contains(hal::Features::CONSERVATIVE_RASTERIZATION),
+            adapter_specific_format_features: true,
+            // GLES can't map buffers that the GPU may be using.
+            mappable_primary_buffers: adapter_id.backend() != Backend::Gl,
+            trusted_content: true,
+        })
+    }
+
+    /// Query the usages, capabilities and sample counts that the adapter
+    /// supports for textures of `format`.
+    pub fn adapter_get_texture_format_features<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+        format: wgt::TextureFormat,
+    ) -> Result<wgt::TextureFormatFeatures, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(Adapter::texture_format_features(&adapter.raw, format))
+    }
+
+    /// Query the format and color space pairs that swap chains of the surface
+    /// can be created with on the adapter.
+    pub fn surface_get_supported_formats<B: GfxBackend>(
+        &self,
+        surface_id: SurfaceId,
+        adapter_id: AdapterId,
+    ) -> Result<Vec<wgt::SurfaceFormat>, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (mut surface_guard, mut token) = self.surfaces.write(&mut token);
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        let surface = surface_guard
+            .get_mut(surface_id)
+            .ok_or(InvalidIdError::Surface(surface_id))?;
+        let formats = B::get_surface_mut(surface).supported_formats(&adapter.raw.physical_device);
+        Ok(crate::swap_chain::surface_formats(
+            formats.as_deref(),
+            Adapter::private_features(&adapter.raw),
+        ))
+    }
+
+    /// Check if presenting to the surface with damage rectangles lets the
+    /// backend skip the undamaged regions. Otherwise the whole frame is
+    /// presented regardless of the damage.
+    pub fn surface_supports_present_damage<B: GfxBackend>(
+        &self,
+        _surface_id: SurfaceId,
+        _adapter_id: AdapterId,
+    ) -> bool {
+        // None of the gfx-hal backends expose incremental present yet.
+        false
+    }
+
+    pub fn adapter_capabilities<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<wgt::Capabilities, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(Adapter::capabilities(&adapter.raw))
+    }
+
+    /// Best limits supported by the adapter.
+    ///
+    /// They can be requested as-is for a device that doesn't need to be portable,
+    /// while `Limits::default()` and `Limits::downlevel_defaults()` are the profiles
+    /// to validate against for running on other adapters.
+    pub fn adapter_limits<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<wgt::Limits, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(Adapter::limits(&adapter.raw, adapter_id.backend()))
+    }
+
+    pub fn adapter_downlevel_capabilities<B: GfxBackend>(
+        &self,
+        adapter_id: AdapterId,
+    ) -> Result<wgt::DownlevelCapabilities, InvalidIdError> {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+        let (adapter_guard, _) = hub.adapters.read(&mut token);
+        let adapter = adapter_guard
+            .get(adapter_id)
+            .ok_or(InvalidIdError::Adapter(adapter_id))?;
+        Ok(Adapter::downlevel_capabilities(
+            &adapter.raw,
+            adapter_id.backend(),
+        ))
     }
 
     pub fn adapter_destroy<B: GfxBackend>(&self, adapter_id: AdapterId) {
         let hub = B::hub(self);
         let mut token = Token::root();
         let (mut guard, _) = hub.adapters.write(&mut token);
+        let adapter = match guard.get_mut(adapter_id) {
+            Some(adapter) => adapter,
+            None => {
+                log::error!("Destroying an invalid adapter {:?}", adapter_id);
+                return;
+            }
+        };
 
-        if guard[adapter_id]
-            .life_guard
-            .ref_count
-            .take()
-            .unwrap()
-            .load()
-            == 1
-        {
+        if adapter.life_guard.ref_count.take().unwrap().load() == 1 {
             hub.adapters.free_id(adapter_id);
             let _adapter = guard.remove(adapter_id).unwrap();
         }
     }
 }
 
+/// Error of `adapter_request_device`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RequestDeviceError {
+    /// The adapter id is invalid.
+    InvalidAdapter(AdapterId),
+    /// The adapter doesn't support the named extension of the descriptor.
+    UnsupportedExtension(&'static str),
+}
+
+/// Find an extension that is requested, but that the adapter `features` can't provide.
+///
+/// The other extensions are enabled on a best-effort basis.
+fn unsupported_extension(
+    extensions: &wgt::Extensions,
+    features: hal::Features,
+    downlevel: &wgt::DownlevelCapabilities,
+) -> Option<&'static str> {
+    let required = [
+        (
+            "anisotropic_filtering",
+            extensions.anisotropic_filtering,
+            downlevel.anisotropic_filtering,
+        ),
+        (
+            "conservative_rasterization",
+            extensions.conservative_rasterization,
+            features.contains(hal::Features::CONSERVATIVE_RASTERIZATION),
+        ),
+    ];
+    required
+        .iter()
+        .find(|&&(_, requested, supported)| requested && !supported)
+        .map(|&(name, _, _)| name)
+}
+
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
     pub fn adapter_request_device<B: GfxBackend>(
         &self,
@@ -553,13 +1501,22 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         desc: &DeviceDescriptor,
         trace_path: Option<&std::path::Path>,
         id_in: Input<G, DeviceId>,
-    ) -> DeviceId {
+    ) -> Result<DeviceId, RequestDeviceError> {
         let hub = B::hub(self);
         let mut token = Token::root();
         let device = {
             let (adapter_guard, _) = hub.adapters.read(&mut token);
-            let adapter = &adapter_guard[adapter_id];
+            let adapter = adapter_guard
+                .get(adapter_id)
+                .ok_or(RequestDeviceError::InvalidAdapter(adapter_id))?;
             let phd = &adapter.raw.physical_device;
+            if desc.strict_webgpu {
+                assert_eq!(
+                    desc.extensions,
+                    desc.extensions.webgpu_only(),
+                    "Native-only extensions can't be requested in strict WebGPU mode"
+                );
+            }
             let mut wishful_features = hal::Features::VERTEX_STORES_AND_ATOMICS
                 | hal::Features::FRAGMENT_STORES_AND_ATOMICS
                 | hal::Features::NDC_Y_UP;
@@ -567,13 +1524,63 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 wishful_features |= hal::Features::TASK_SHADER;
                 wishful_features |= hal::Features::MESH_SHADER;
             }
-            let enabled_features = adapter.raw.physical_device.features() & wishful_features;
+            if desc.extensions.shader_float64 {
+                wishful_features |= hal::Features::SHADER_FLOAT64;
+            }
+            if desc.extensions.shader_int64 {
+                wishful_features |= hal::Features::SHADER_INT64;
+            }
+            if desc.extensions.shader_int16 {
+                wishful_features |= hal::Features::SHADER_INT16;
+            }
+            if desc.extensions.anisotropic_filtering {
+                wishful_features |= hal::Features::SAMPLER_ANISOTROPY;
+            }
+            let downlevel = Adapter::downlevel_capabilities(&adapter.raw, adapter_id.backend());
+            if let Some(name) = unsupported_extension(&desc.extensions, phd.features(), &downlevel)
+            {
+                return Err(RequestDeviceError::UnsupportedExtension(name));
+            }
+            if desc.extensions.conservative_rasterization {
+                wishful_features |= hal::Features::CONSERVATIVE_RASTERIZATION;
+            }
+            let mut enabled_features = adapter.raw.physical_device.features() & wishful_features;
             if enabled_features != wishful_features {
                 log::warn!(
                     "Missing features: {:?}",
                     wishful_features - enabled_features
                 );
             }
+            // capabilities are enabled whenever they are available
+            let capabilities = Adapter::capabilities(&adapter.raw);
+            if capabilities.contains(wgt::Capabilities::INDEPENDENT_BLEND) {
+                enabled_features |= hal::Features::INDEPENDENT_BLENDING;
+            }
+            if capabilities.contains(wgt::Capabilities::SAMPLE_SHADING) {
+                enabled_features |= hal::Features::SAMPLE_RATE_SHADING;
+            }
+            if capabilities.contains(wgt::Capabilities::VERTEX_INSTANCE_RATE) {
+                enabled_features |= hal::Features::INSTANCE_RATE;
+            }
+            let workarounds = AdapterWorkarounds::from_info(&AdapterInfo::from_gfx(
+                adapter.raw.info.clone(),
+                adapter_id.backend(),
+            ));
+            if !workarounds.is_empty() {
+                log::info!("Enabled driver workarounds: {:?}", workarounds);
+            }
+            if downlevel.cube_array_textures {
+                enabled_features |= hal::Features::IMAGE_CUBE_ARRAY;
+            }
+            if downlevel.indirect_first_instance {
+                enabled_features |= hal::Features::DRAW_INDIRECT_FIRST_INSTANCE;
+            }
+            if downlevel.fragment_writable_storage {
+                enabled_features |= hal::Features::FRAGMENT_STORES_AND_ATOMICS;
+            }
+            if downlevel.depth_bias_clamp {
+                enabled_features |= hal::Features::DEPTH_BIAS_CLAMP;
+            }
 
             let family = adapter
                 .raw
@@ -581,19 +1588,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                 .iter()
                 .find(|family| family.queue_type().supports_graphics())
                 .unwrap();
-            let mut gpu = unsafe { phd.open(&[(family, &[1.0])], enabled_features).unwrap() };
-
             let limits = phd.limits();
-            assert_eq!(
-                0,
-                BIND_BUFFER_ALIGNMENT % limits.min_storage_buffer_offset_alignment,
-                "Adapter storage buffer offset alignment not compatible with WGPU"
-            );
-            assert_eq!(
-                0,
-                BIND_BUFFER_ALIGNMENT % limits.min_uniform_buffer_offset_alignment,
-                "Adapter uniform buffer offset alignment not compatible with WGPU"
-            );
             if limits.max_bound_descriptor_sets == 0 {
                 log::warn!("max_bind_groups limit is missing");
             } else {
@@ -602,12 +1597,150 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     "Adapter does not support the requested max_bind_groups"
                 );
             }
+            let adapter_limits = Adapter::limits(&adapter.raw, adapter_id.backend());
+            for &(name, requested, supported) in &[
+                (
+                    "min_uniform_buffer_offset_alignment",
+                    desc.limits.min_uniform_buffer_offset_alignment,
+                    adapter_limits.min_uniform_buffer_offset_alignment,
+                ),
+                (
+                    "min_storage_buffer_offset_alignment",
+                    desc.limits.min_storage_buffer_offset_alignment,
+                    adapter_limits.min_storage_buffer_offset_alignment,
+                ),
+            ] {
+                assert!(
+                    requested.is_power_of_two(),
+                    "Requested {} ({}) must be a power of two",
+                    name,
+                    requested
+                );
+                assert!(
+                    requested >= supported,
+                    "Requested {} ({}) is lower than the adapter supports ({})",
+                    name,
+                    requested,
+                    supported
+                );
+            }
+            for &(name, requested, supported) in &[
+                (
+                    "max_compute_shared_memory_size",
+                    desc.limits.max_compute_shared_memory_size,
+                    adapter_limits.max_compute_shared_memory_size,
+                ),
+                (
+                    "max_storage_buffers_per_shader_stage",
+                    desc.limits.max_storage_buffers_per_shader_stage,
+                    adapter_limits.max_storage_buffers_per_shader_stage,
+                ),
+                (
+                    "max_storage_textures_per_shader_stage",
+                    desc.limits.max_storage_textures_per_shader_stage,
+                    adapter_limits.max_storage_textures_per_shader_stage,
+                ),
+                (
+                    "max_sampled_textures_per_shader_stage",
+                    desc.limits.max_sampled_textures_per_shader_stage,
+                    adapter_limits.max_sampled_textures_per_shader_stage,
+                ),
+                (
+                    "max_samplers_per_shader_stage",
+                    desc.limits.max_samplers_per_shader_stage,
+                    adapter_limits.max_samplers_per_shader_stage,
+                ),
+                (
+                    "max_uniform_buffers_per_shader_stage",
+                    desc.limits.max_uniform_buffers_per_shader_stage,
+                    adapter_limits.max_uniform_buffers_per_shader_stage,
+                ),
+                (
+                    "max_bindings_per_bind_group",
+                    desc.limits.max_bindings_per_bind_group,
+                    adapter_limits.max_bindings_per_bind_group,
+                ),
+                (
+                    "max_color_attachments",
+                    desc.limits.max_color_attachments,
+                    adapter_limits.max_color_attachments,
+                ),
+                (
+                    "max_vertex_buffers",
+                    desc.limits.max_vertex_buffers,
+                    adapter_limits.max_vertex_buffers,
+                ),
+                (
+                    "max_vertex_step_rate",
+                    desc.limits.max_vertex_step_rate,
+                    adapter_limits.max_vertex_step_rate,
+                ),
+                (
+                    "max_texture_dimension_1d",
+                    desc.limits.max_texture_dimension_1d,
+                    adapter_limits.max_texture_dimension_1d,
+                ),
+                (
+                    "max_texture_dimension_2d",
+                    desc.limits.max_texture_dimension_2d,
+                    adapter_limits.max_texture_dimension_2d,
+                ),
+                (
+                    "max_texture_dimension_3d",
+                    desc.limits.max_texture_dimension_3d,
+                    adapter_limits.max_texture_dimension_3d,
+                ),
+                (
+                    "max_texture_array_layers",
+                    desc.limits.max_texture_array_layers,
+                    adapter_limits.max_texture_array_layers,
+                ),
+            ] {
+                assert!(
+                    requested <= supported,
+                    "Requested {} ({}) is higher than the adapter supports ({})",
+                    name,
+                    requested,
+                    supported
+                );
+            }
+            assert!(
+                desc.limits.max_buffer_size <= adapter_limits.max_buffer_size,
+                "Requested max_buffer_size ({}) is higher than the adapter supports ({})",
+                desc.limits.max_buffer_size,
+                adapter_limits.max_buffer_size
+            );
+            if desc.strict_webgpu {
+                let exceeded = exceeded_webgpu_limits(&desc.limits);
+                assert!(
+                    exceeded.is_empty(),
+                    "Requested limits {:?} are beyond the WebGPU defaults, which is not allowed in strict WebGPU mode",
+                    exceeded
+                );
+            }
+            if let Some(size) = desc.staging_chunk_size {
+                assert!(
+                    size != 0 && size % wgt::COPY_BUFFER_ALIGNMENT == 0,
+                    "Staging chunk size ({}) must be a non-zero multiple of {}",
+                    size,
+                    wgt::COPY_BUFFER_ALIGNMENT
+                );
+            }
+
+            let mut gpu = unsafe { phd.open(&[(family, &[1.0])], enabled_features).unwrap() };
 
             let mem_props = phd.memory_properties();
-            let supports_texture_d24_s8 = phd
-                .format_properties(Some(hal::format::Format::D24UnormS8Uint))
-                .optimal_tiling
-                .contains(hal::format::ImageFeature::DEPTH_STENCIL_ATTACHMENT);
+            let supports_texture_d24_s8 =
+                Adapter::private_features(&adapter.raw).supports_texture_d24_s8;
+            let unsupported_vertex_formats = wgt::VertexFormat::ALL
+                .iter()
+                .cloned()
+                .filter(|&format| {
+                    !phd.format_properties(Some(conv::map_vertex_format(format)))
+                        .buffer_features
+                        .contains(hal::format::BufferFeature::VERTEX)
+                })
+                .collect();
 
             Device::new(
                 gpu.device,
@@ -615,15 +1748,137 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     value: adapter_id,
                     ref_count: adapter.life_guard.add_ref(),
                 },
+                &AdapterInfo::from_gfx(adapter.raw.info.clone(), adapter_id.backend()),
                 gpu.queue_groups.swap_remove(0),
                 mem_props,
                 limits.non_coherent_atom_size as u64,
                 supports_texture_d24_s8,
+                unsupported_vertex_formats,
+                capabilities,
+                downlevel,
+                ShaderFeatures::from_hal(enabled_features),
                 desc,
                 trace_path,
+                self.deterministic_ids,
             )
         };
 
-        hub.devices.register_identity(id_in, device, &mut token)
+        Ok(hub.devices.register_identity(id_in, device, &mut token))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn adapter_info(vendor: usize, driver_info: &str) -> AdapterInfo {
+        AdapterInfo {
+            name: "Test GPU".to_string(),
+            vendor,
+            device: 1,
+            device_type: DeviceType::IntegratedGpu,
+            backend: Backend::Vulkan,
+            portability: false,
+            is_fallback: false,
+            driver: "Test driver".to_string(),
+            driver_info: driver_info.to_string(),
+        }
+    }
+
+    #[test]
+    fn unsupported_extensions() {
+        let extensions = wgt::Extensions {
+            conservative_rasterization: true,
+            ..wgt::Extensions::default()
+        };
+        let downlevel = wgt::DownlevelCapabilities::default();
+        assert_eq!(
+            unsupported_extension(&extensions, hal::Features::empty(), &downlevel),
+            Some("conservative_rasterization")
+        );
+        assert_eq!(
+            unsupported_extension(
+                &extensions,
+                hal::Features::CONSERVATIVE_RASTERIZATION,
+                &downlevel
+            ),
+            None
+        );
+        assert_eq!(
+            unsupported_extension(
+                &wgt::Extensions::default(),
+                hal::Features::empty(),
+                &downlevel
+            ),
+            None
+        );
+
+        let anisotropic = wgt::Extensions {
+            anisotropic_filtering: true,
+            ..wgt::Extensions::default()
+        };
+        let no_anisotropy = wgt::DownlevelCapabilities {
+            anisotropic_filtering: false,
+            ..downlevel
+        };
+        assert_eq!(
+            unsupported_extension(&anisotropic, hal::Features::empty(), &no_anisotropy),
+            Some("anisotropic_filtering")
+        );
+        assert_eq!(
+            unsupported_extension(&anisotropic, hal::Features::empty(), &downlevel),
+            None
+        );
+    }
+
+    #[test]
+    fn driver_version() {
+        assert_eq!(parse_driver_version("512.415.0"), vec![512, 415, 0]);
+        assert_eq!(parse_driver_version("V@331.0 (GIT@abc)"), vec![331, 0]);
+        assert_eq!(parse_driver_version(""), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn driver_workarounds() {
+        let old = adapter_info(VENDOR_QUALCOMM, "V@331.0");
+        assert_eq!(
+            AdapterWorkarounds::from_info(&old),
+            AdapterWorkarounds::NO_INDIRECT_EXECUTION
+        );
+        // fixed drivers, unknown versions, and other vendors are left alone
+        for info in &[
+            adapter_info(VENDOR_QUALCOMM, "512.415"),
+            adapter_info(VENDOR_QUALCOMM, ""),
+            adapter_info(0x10de, "331.0"),
+            AdapterInfo {
+                backend: Backend::Dx12,
+                ..old.clone()
+            },
+        ] {
+            assert_eq!(
+                AdapterWorkarounds::from_info(info),
+                AdapterWorkarounds::empty()
+            );
+        }
+    }
+
+    #[test]
+    fn webgpu_limits() {
+        assert!(exceeded_webgpu_limits(&wgt::Limits::default()).is_empty());
+        assert!(exceeded_webgpu_limits(&wgt::Limits::downlevel_defaults()).is_empty());
+        let limits = wgt::Limits {
+            min_uniform_buffer_offset_alignment: 64,
+            max_bind_groups: 8,
+            max_buffer_size: 1 << 30,
+            ..wgt::Limits::default()
+        };
+        assert_eq!(
+            exceeded_webgpu_limits(&limits),
+            vec![
+                "min_uniform_buffer_offset_alignment",
+                "max_buffer_size",
+                "max_bind_groups"
+            ]
+        );
     }
 }
@@ -15,6 +15,8 @@ pub mod backend {
     #[cfg(windows)]
     pub use gfx_backend_dx12::Backend as Dx12;
     pub use gfx_backend_empty::Backend as Empty;
+    #[cfg(feature = "gl")]
+    pub use gfx_backend_gl::Backend as Gl;
     #[cfg(any(target_os = "ios", target_os = "macos"))]
     pub use gfx_backend_metal::Backend as Metal;
     #[cfg(any(
@@ -24,6 +26,9 @@ pub mod backend {
     pub use gfx_backend_vulkan::Backend as Vulkan;
 }
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 pub mod binding_model;
 pub mod command;
 mod conv;
@@ -172,9 +177,18 @@ struct PrivateFeatures {
     pub supports_texture_d24_s8: bool,
 }
 
+/// Call a method of `Global` for the backend of `id`.
+///
+/// IDs of a backend that isn't enabled, such as IDs forged over FFI, panic,
+/// unless an `else` expression is given to evaluate instead, typically the
+/// error of the method for an invalid ID.
 #[macro_export]
 macro_rules! gfx_select {
     ($id:expr => $global:ident.$method:ident( $($param:expr),+ )) => {
+        $crate::gfx_select!($id => $global.$method( $($param),+ )
+            else panic!("Invalid backend {:?}", $id.backend()))
+    };
+    ($id:expr => $global:ident.$method:ident( $($param:expr),+ ) else $invalid:expr) => {
         match $id.backend() {
             #[cfg(any(not(any(target_os = "ios", target_os = "macos")), feature = "gfx-backend-vulkan"))]
             wgt::Backend::Vulkan => $global.$method::<$crate::backend::Vulkan>( $($param),+ ),
@@ -184,7 +198,9 @@ macro_rules! gfx_select {
             wgt::Backend::Dx12 => $global.$method::<$crate::backend::Dx12>( $($param),+ ),
             #[cfg(windows)]
             wgt::Backend::Dx11 => $global.$method::<$crate::backend::Dx11>( $($param),+ ),
-            _ => unreachable!()
+            #[cfg(feature = "gl")]
+            wgt::Backend::Gl => $global.$method::<$crate::backend::Gl>( $($param),+ ),
+            _ => $invalid,
         }
     };
 }
@@ -3,11 +3,16 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    device::RenderPassContext,
-    id::{DeviceId, PipelineLayoutId, ShaderModuleId},
-    LifeGuard, RawString, RefCount, Stored, U32Array,
+    device::{InvalidIdError, RenderPassContext, RenderPassKey, WrongDeviceError},
+    id::{
+        ComputePipelineId, DeviceId, PipelineCacheId, PipelineLayoutId, RenderPipelineId,
+        ShaderModuleId,
+    },
+    instance::AdapterInfo,
+    FastHashMap, LifeGuard, RawString, RefCount, Stored, U32Array,
 };
-use std::borrow::Borrow;
+use parking_lot::{Condvar, Mutex};
+use std::{borrow::Borrow, fmt};
 use wgt::{
     BufferAddress, ColorStateDescriptor, DepthStencilStateDescriptor, IndexFormat, InputStepMode,
     PrimitiveTopology, RasterizationStateDescriptor, VertexAttributeDescriptor,
@@ -18,6 +23,11 @@ use wgt::{
 pub struct VertexBufferLayoutDescriptor {
     pub array_stride: BufferAddress,
     pub step_mode: InputStepMode,
+    /// Number of instances using each element of a per-instance buffer.
+    ///
+    /// Must be 1 for per-vertex buffers. Other values than 1 require
+    /// `Capabilities::VERTEX_INSTANCE_RATE`.
+    pub step_rate: u32,
     pub attributes: *const VertexAttributeDescriptor,
     pub attributes_length: usize,
 }
@@ -34,13 +44,18 @@ pub struct VertexStateDescriptor {
 #[derive(Debug)]
 pub struct ShaderModuleDescriptor {
     pub code: U32Array,
+    pub label: RawString,
 }
 
 #[derive(Debug)]
 pub struct ShaderModule<B: hal::Backend> {
     pub(crate) raw: B::ShaderModule,
     pub(crate) device_id: Stored<DeviceId>,
+    pub(crate) label: String,
     pub(crate) module: Option<naga::Module>,
+    pub(crate) required_features: ShaderFeatures,
+    /// SPIR-V code, to locate the errors reported by the backend compiler.
+    pub(crate) code: Vec<u32>,
 }
 
 #[repr(C)]
@@ -55,14 +70,18 @@ pub struct ProgrammableStageDescriptor {
 pub struct ComputePipelineDescriptor {
     pub layout: PipelineLayoutId,
     pub compute_stage: ProgrammableStageDescriptor,
+    /// Cache to look up and store the compiled pipeline.
+    pub cache: Option<PipelineCacheId>,
 }
 
 #[derive(Debug)]
 pub struct ComputePipeline<B: hal::Backend> {
-    pub(crate) raw: B::ComputePipeline,
+    pub(crate) raw: PipelineRaw<B::ComputePipeline>,
     pub(crate) layout_id: Stored<PipelineLayoutId>,
     pub(crate) device_id: Stored<DeviceId>,
     pub(crate) life_guard: LifeGuard,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_desc: crate::device::trace::ComputePipelineDescriptor,
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for ComputePipeline<B> {
@@ -78,6 +97,10 @@ pub struct RenderPipelineDescriptor {
     pub vertex_stage: ProgrammableStageDescriptor,
     pub fragment_stage: *const ProgrammableStageDescriptor,
     pub primitive_topology: PrimitiveTopology,
+    /// Index format that restarts the strip at its maximum value.
+    /// Required for strip topologies, and has to be null for the others.
+    /// Indexed draws with this pipeline must use the same index format.
+    pub strip_index_format: *const IndexFormat,
     pub rasterization_state: *const RasterizationStateDescriptor,
     pub color_states: *const ColorStateDescriptor,
     pub color_states_length: usize,
@@ -86,6 +109,478 @@ pub struct RenderPipelineDescriptor {
     pub sample_count: u32,
     pub sample_mask: u32,
     pub alpha_to_coverage_enabled: bool,
+    pub alpha_to_one_enabled: bool,
+    /// Minimum fraction of samples to be shaded individually, within `0.0..=1.0`.
+    /// Zero disables per-sample shading. Requires `Capabilities::SAMPLE_SHADING`.
+    pub min_sample_shading: f32,
+    /// Cache to look up and store the compiled pipeline.
+    pub cache: Option<PipelineCacheId>,
+}
+
+bitflags::bitflags! {
+    /// Shader capabilities that depend on the device features.
+    #[repr(transparent)]
+    pub struct ShaderFeatures: u32 {
+        const FLOAT64 = 1;
+        const INT64 = 2;
+        const INT16 = 4;
+        const FLOAT16 = 8;
+        const SUBGROUP_OPERATIONS = 16;
+        /// Vertex shaders select the layer of a layered render target.
+        const LAYER_OUTPUT = 32;
+    }
+}
+
+impl ShaderFeatures {
+    /// Scan the capabilities of an adapter, given the features enabled on its device.
+    pub(crate) fn from_hal(features: hal::Features) -> Self {
+        let mut shader_features = ShaderFeatures::empty();
+        shader_features.set(
+            ShaderFeatures::FLOAT64,
+            features.contains(hal::Features::SHADER_FLOAT64),
+        );
+        shader_features.set(
+            ShaderFeatures::INT64,
+            features.contains(hal::Features::SHADER_INT64),
+        );
+        shader_features.set(
+            ShaderFeatures::INT16,
+            features.contains(hal::Features::SHADER_INT16),
+        );
+        //TODO: FLOAT16, SUBGROUP_OPERATIONS and LAYER_OUTPUT, once gfx-hal
+        // reports them. Support varies across the devices of every backend.
+        shader_features
+    }
+
+    /// Collect the features required by the `OpCapability` instructions of a SPIR-V module.
+    pub(crate) fn from_spirv(words: &[u32]) -> Self {
+        use spirv_headers::{Capability as C, Op};
+        const HEADER_LENGTH: usize = 5;
+
+        let mut shader_features = ShaderFeatures::empty();
+        let mut offset = HEADER_LENGTH;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xFFFF;
+            if opcode == Op::Capability as u32 && word_count == 2 && offset + 1 < words.len() {
+                let capability = words[offset + 1];
+                shader_features |= if capability == C::Float64 as u32 {
+                    ShaderFeatures::FLOAT64
+                } else if capability == C::Int64 as u32 {
+                    ShaderFeatures::INT64
+                } else if capability == C::Int16 as u32 {
+                    ShaderFeatures::INT16
+                } else if capability == C::Float16 as u32 {
+                    ShaderFeatures::FLOAT16
+                } else if capability >= C::GroupNonUniform as u32
+                    && capability <= C::GroupNonUniformQuad as u32
+                    || capability == C::SubgroupBallotKHR as u32
+                    || capability == C::SubgroupVoteKHR as u32
+                {
+                    ShaderFeatures::SUBGROUP_OPERATIONS
+                } else if capability == C::ShaderViewportIndexLayerEXT as u32 {
+                    ShaderFeatures::LAYER_OUTPUT
+                } else {
+                    ShaderFeatures::empty()
+                };
+            } else if opcode != Op::Capability as u32 && opcode != Op::Extension as u32 {
+                // Capabilities are declared first in a module.
+                break;
+            }
+            if word_count == 0 {
+                break;
+            }
+            offset += word_count;
+        }
+        shader_features
+    }
+}
+
+/// Direction in which a fragment shader declares to move the depth it writes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConservativeDepth {
+    Greater,
+    Less,
+    Unchanged,
+}
+
+/// Execution modes of a fragment entry point that affect the depth tests.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct FragmentDepthModes {
+    pub early_fragment_tests: bool,
+    /// The shader writes the fragment depth.
+    pub depth_replacing: bool,
+    pub conservative_depth: Option<ConservativeDepth>,
+    /// The module discards fragments, in any of its functions.
+    pub discards: bool,
+}
+
+impl FragmentDepthModes {
+    /// Reflect the `OpExecutionMode` instructions of a fragment entry point.
+    pub(crate) fn from_spirv(words: &[u32], entry_point: &str) -> Self {
+        use spirv_headers::{ExecutionMode as Em, ExecutionModel, Op};
+
+        let instructions = spirv_instructions(words);
+        let entry_point_id = instructions.iter().find_map(|&(opcode, operands)| {
+            if opcode == Op::EntryPoint as u32
+                && operands.len() >= 3
+                && operands[0] == ExecutionModel::Fragment as u32
+                && spirv_string(&operands[2..]) == entry_point
+            {
+                Some(operands[1])
+            } else {
+                None
+            }
+        });
+        let mut modes = FragmentDepthModes::default();
+        for &(opcode, operands) in instructions.iter() {
+            if opcode == Op::Kill as u32 {
+                modes.discards = true;
+            } else if opcode == Op::ExecutionMode as u32
+                && operands.len() >= 2
+                && Some(operands[0]) == entry_point_id
+            {
+                let mode = operands[1];
+                if mode == Em::EarlyFragmentTests as u32 {
+                    modes.early_fragment_tests = true;
+                } else if mode == Em::DepthReplacing as u32 {
+                    modes.depth_replacing = true;
+                } else if mode == Em::DepthGreater as u32 {
+                    modes.conservative_depth = Some(ConservativeDepth::Greater);
+                } else if mode == Em::DepthLess as u32 {
+                    modes.conservative_depth = Some(ConservativeDepth::Less);
+                } else if mode == Em::DepthUnchanged as u32 {
+                    modes.conservative_depth = Some(ConservativeDepth::Unchanged);
+                }
+            }
+        }
+        modes
+    }
+}
+
+/// Location in a SPIR-V module of the instruction that a shader error refers to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderErrorLocation {
+    /// Index of the instruction, counting from the first one after the header.
+    pub instruction: usize,
+    /// Line of the high-level source, as given by the last `OpLine` before the instruction.
+    pub line: Option<u32>,
+    /// The source line, if the source text is embedded with `OpSource`.
+    pub snippet: Option<String>,
+}
+
+/// Failure of the backend to translate or compile a shader stage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShaderError {
+    pub module_label: String,
+    pub stage: wgt::ShaderStage,
+    pub entry_point: String,
+    /// Message of the backend shader compiler.
+    pub message: String,
+    pub location: Option<ShaderErrorLocation>,
+}
+
+/// Error of creating the native pipeline.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PipelineError {
+    Shader(ShaderError),
+    /// The backend failed for a reason unrelated to the shaders.
+    Backend(hal::pso::CreationError),
+    /// The device, layout, shader module or cache of the pipeline is invalid.
+    InvalidId(InvalidIdError),
+    /// A shader samples a texture whose data can't be filtered with a filtering
+    /// sampler. Both are identified by their bind group index and binding.
+    FilteringSamplerWithUnfilterableTexture {
+        texture: (u32, u32),
+        sampler: (u32, u32),
+    },
+    /// Color targets, listed by index, don't match the state of the first target,
+    /// but the adapter doesn't support independent blending.
+    IndependentBlendUnsupported {
+        targets: Vec<usize>,
+    },
+    /// A binding used by a shader stage isn't visible to that stage
+    /// according to its bind group layout entry.
+    BindingNotVisible {
+        group: u32,
+        binding: u32,
+        stage: wgt::ShaderStage,
+    },
+    /// The fragment shader declares early fragment tests, which the adapter can't honor.
+    EarlyFragmentTestsUnsupported,
+    /// The fragment shader declares conservative depth, which the adapter can't honor.
+    ConservativeDepthUnsupported,
+    /// The fragment shader writes the depth, but the depth tests run before it.
+    EarlyFragmentTestsWithDepthOutput,
+    /// The fragment shader can discard fragments, but the depth tests run and write
+    /// the depth before it, so discarded fragments would still write the depth.
+    EarlyFragmentTestsWithDiscard,
+    /// The layout, a shader module or the cache belongs to another device.
+    WrongDevice(WrongDeviceError),
+    /// A shader module requires features which are not enabled on the device.
+    MissingShaderFeatures {
+        module: ShaderModuleId,
+        missing: ShaderFeatures,
+    },
+}
+
+impl From<InvalidIdError> for PipelineError {
+    fn from(error: InvalidIdError) -> Self {
+        PipelineError::InvalidId(error)
+    }
+}
+
+impl From<WrongDeviceError> for PipelineError {
+    fn from(error: WrongDeviceError) -> Self {
+        PipelineError::WrongDevice(error)
+    }
+}
+
+/// Shader stage of a pipeline being created, to describe its errors.
+pub(crate) struct StageContext<'a> {
+    pub stage: wgt::ShaderStage,
+    pub module_label: &'a str,
+    pub entry_point: &'a str,
+    pub code: &'a [u32],
+}
+
+impl<'a> StageContext<'a> {
+    pub(crate) fn new<B: hal::Backend>(
+        stage: wgt::ShaderStage,
+        entry_point: &'a str,
+        module: &'a ShaderModule<B>,
+    ) -> Self {
+        StageContext {
+            stage,
+            module_label: &module.label,
+            entry_point,
+            code: &module.code,
+        }
+    }
+}
+
+impl PipelineError {
+    /// Attribute a native pipeline creation error to one of its `stages`.
+    ///
+    /// Backends don't always report which stage of a render pipeline failed,
+    /// so it's guessed from the message, falling back to the first stage.
+    pub(crate) fn from_hal(error: hal::pso::CreationError, stages: &[StageContext]) -> Self {
+        use hal::device::ShaderError as Se;
+
+        let error = match error {
+            hal::pso::CreationError::Shader(error) => error,
+            other => return PipelineError::Backend(other),
+        };
+        let (message, stage) = match error {
+            Se::CompilationFailed(message) | Se::InterfaceMismatch(message) => {
+                let stage = guess_stage(&message, stages);
+                (message, stage)
+            }
+            Se::MissingEntryPoint(name) => {
+                let stage = stages.iter().find(|stage| stage.entry_point == name);
+                (format!("Missing entry point {}", name), stage)
+            }
+            Se::UnsupportedStage(hal_stage) => {
+                let stage = stages.iter().find(|stage| match hal_stage {
+                    hal::pso::Stage::Vertex => stage.stage == wgt::ShaderStage::VERTEX,
+                    hal::pso::Stage::Fragment => stage.stage == wgt::ShaderStage::FRAGMENT,
+                    hal::pso::Stage::Compute => stage.stage == wgt::ShaderStage::COMPUTE,
+                    _ => false,
+                });
+                (format!("Unsupported shader stage {:?}", hal_stage), stage)
+            }
+            other => (format!("{:?}", other), None),
+        };
+        let stage = stage.unwrap_or(&stages[0]);
+        PipelineError::Shader(ShaderError {
+            module_label: stage.module_label.to_string(),
+            stage: stage.stage,
+            entry_point: stage.entry_point.to_string(),
+            location: ShaderErrorLocation::find(stage.code, &message),
+            message,
+        })
+    }
+}
+
+/// Words that backend compilers use to name the shader stage in their messages.
+fn stage_keywords(stage: wgt::ShaderStage) -> &'static [&'static str] {
+    match stage {
+        wgt::ShaderStage::VERTEX => &["vertex", "vs_"],
+        wgt::ShaderStage::FRAGMENT => &["fragment", "pixel", "ps_"],
+        _ => &["compute", "kernel", "cs_"],
+    }
+}
+
+fn guess_stage<'a, 'b>(
+    message: &str,
+    stages: &'b [StageContext<'a>],
+) -> Option<&'b StageContext<'a>> {
+    let lower = message.to_lowercase();
+    stages.iter().find(|stage| {
+        stage_keywords(stage.stage)
+            .iter()
+            .any(|word| lower.contains(word))
+    })
+}
+
+/// Decode a nul-terminated SPIR-V literal string.
+fn spirv_string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Split a SPIR-V module into the opcodes and operands of its instructions.
+fn spirv_instructions(words: &[u32]) -> Vec<(u32, &[u32])> {
+    const HEADER_LENGTH: usize = 5;
+
+    let mut instructions = Vec::new();
+    let mut offset = HEADER_LENGTH;
+    while offset < words.len() {
+        let word_count = (words[offset] >> 16) as usize;
+        if word_count == 0 || offset + word_count > words.len() {
+            break;
+        }
+        let opcode = words[offset] & 0xFFFF;
+        instructions.push((opcode, &words[offset + 1..offset + word_count]));
+        offset += word_count;
+    }
+    instructions
+}
+
+/// Result id of an instruction, if it has one.
+fn spirv_result_id(opcode: u32, operands: &[u32]) -> Option<u32> {
+    use spirv_headers::Op;
+    const NO_RESULT: &[Op] = &[
+        Op::Nop,
+        Op::SourceContinued,
+        Op::Source,
+        Op::SourceExtension,
+        Op::Name,
+        Op::MemberName,
+        Op::Line,
+        Op::NoLine,
+        Op::Extension,
+        Op::MemoryModel,
+        Op::EntryPoint,
+        Op::ExecutionMode,
+        Op::Capability,
+        Op::TypeForwardPointer,
+        Op::FunctionEnd,
+        Op::Store,
+        Op::CopyMemory,
+        Op::CopyMemorySized,
+        Op::Decorate,
+        Op::MemberDecorate,
+        Op::GroupDecorate,
+        Op::GroupMemberDecorate,
+        Op::ImageWrite,
+        Op::EmitVertex,
+        Op::EndPrimitive,
+        Op::ControlBarrier,
+        Op::MemoryBarrier,
+        Op::AtomicStore,
+        Op::LoopMerge,
+        Op::SelectionMerge,
+        Op::Branch,
+        Op::BranchConditional,
+        Op::Switch,
+        Op::Kill,
+        Op::Return,
+        Op::ReturnValue,
+        Op::Unreachable,
+    ];
+    // These don't have a result type, so the result id comes first.
+    const RESULT_FIRST: &[Op] = &[
+        Op::String,
+        Op::ExtInstImport,
+        Op::Label,
+        Op::DecorationGroup,
+    ];
+
+    if NO_RESULT.iter().any(|&op| op as u32 == opcode) {
+        None
+    } else if RESULT_FIRST.iter().any(|&op| op as u32 == opcode)
+        || (opcode >= Op::TypeVoid as u32 && opcode <= Op::TypePipe as u32)
+    {
+        operands.first().cloned()
+    } else {
+        operands.get(1).cloned()
+    }
+}
+
+/// Ids referenced by a message as `%id` or `ID id`.
+fn message_ids(message: &str) -> Vec<u32> {
+    let lower = message.to_lowercase();
+    let mut ids = Vec::new();
+    for prefix in &["%", "id "] {
+        for (start, _) in lower.match_indices(prefix) {
+            if lower[..start].ends_with(char::is_alphanumeric) {
+                continue;
+            }
+            let digits = lower[start + prefix.len()..]
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect::<String>();
+            if let Ok(id) = digits.parse() {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+impl ShaderErrorLocation {
+    /// Find the instruction that a backend message refers to, either by
+    /// its result id, or by a quoted name given to it with `OpName`.
+    pub(crate) fn find(code: &[u32], message: &str) -> Option<Self> {
+        use spirv_headers::Op;
+
+        let instructions = spirv_instructions(code);
+        let mut source = String::new();
+        let mut names = Vec::new();
+        for &(opcode, operands) in &instructions {
+            if opcode == Op::Source as u32 && operands.len() > 3 {
+                source = spirv_string(&operands[3..]);
+            } else if opcode == Op::SourceContinued as u32 {
+                source.push_str(&spirv_string(operands));
+            } else if opcode == Op::Name as u32 && operands.len() > 1 {
+                names.push((operands[0], spirv_string(&operands[1..])));
+            }
+        }
+
+        let quoted_ids = message.split('\'').skip(1).step_by(2).filter_map(|quoted| {
+            names
+                .iter()
+                .find(|&&(_, ref name)| name == quoted)
+                .map(|&(id, _)| id)
+        });
+        let candidates = message_ids(message).into_iter().chain(quoted_ids);
+        for id in candidates {
+            let mut line = None;
+            for (index, &(opcode, operands)) in instructions.iter().enumerate() {
+                if opcode == Op::Line as u32 && operands.len() > 1 {
+                    line = Some(operands[1]);
+                } else if opcode == Op::NoLine as u32 {
+                    line = None;
+                } else if spirv_result_id(opcode, operands) == Some(id) {
+                    let snippet = line
+                        .and_then(|line| line.checked_sub(1))
+                        .and_then(|line| source.lines().nth(line as usize))
+                        .map(|snippet| snippet.trim().to_string());
+                    return Some(ShaderErrorLocation {
+                        instruction: index,
+                        line,
+                        snippet,
+                    });
+                }
+            }
+        }
+        None
+    }
 }
 
 bitflags::bitflags! {
@@ -93,20 +588,27 @@ bitflags::bitflags! {
     pub struct PipelineFlags: u32 {
         const BLEND_COLOR = 1;
         const STENCIL_REFERENCE = 2;
+        const DEPTH_BIAS = 4;
+        const STENCIL_MASKS = 8;
     }
 }
 
 #[derive(Debug)]
 pub struct RenderPipeline<B: hal::Backend> {
-    pub(crate) raw: B::GraphicsPipeline,
+    pub(crate) raw: PipelineRaw<B::GraphicsPipeline>,
     pub(crate) layout_id: Stored<PipelineLayoutId>,
     pub(crate) device_id: Stored<DeviceId>,
     pub(crate) pass_context: RenderPassContext,
     pub(crate) flags: PipelineFlags,
     pub(crate) index_format: IndexFormat,
+    pub(crate) strip_index_format: Option<IndexFormat>,
+    pub(crate) topology: PrimitiveTopology,
     pub(crate) sample_count: u8,
-    pub(crate) vertex_strides: Vec<(BufferAddress, InputStepMode)>,
+    /// Stride, step mode and step rate of each vertex buffer.
+    pub(crate) vertex_strides: Vec<(BufferAddress, InputStepMode, u32)>,
     pub(crate) life_guard: LifeGuard,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_desc: crate::device::trace::GraphicsPipelineDescriptor,
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for RenderPipeline<B> {
@@ -130,4 +632,500 @@ pub struct MeshPipelineDescriptor {
     pub sample_count: u32,
     pub sample_mask: u32,
     pub alpha_to_coverage_enabled: bool,
+    pub alpha_to_one_enabled: bool,
+    /// Minimum fraction of samples to be shaded individually, within `0.0..=1.0`.
+    /// Zero disables per-sample shading. Requires `Capabilities::SAMPLE_SHADING`.
+    pub min_sample_shading: f32,
+}
+
+#[derive(Debug)]
+pub struct PipelineCache<B: hal::Backend> {
+    pub(crate) raw: B::PipelineCache,
+    pub(crate) device_id: Stored<DeviceId>,
+}
+
+const PIPELINE_CACHE_MAGIC: &[u8; 4] = b"WGPC";
+const PIPELINE_CACHE_VERSION: u32 = 2;
+
+/// Check if the backend keeps compiled pipelines in its caches.
+///
+/// Caches of the other backends are still created, so that pipelines
+/// can refer to them, but they never take or produce any data.
+pub(crate) fn backend_supports_pipeline_caches(backend: wgt::Backend) -> bool {
+    match backend {
+        wgt::Backend::Vulkan => true,
+        _ => false,
+    }
+}
+
+/// Header of the serialized pipeline cache data.
+///
+/// Identifies the adapter and driver the data was produced on, so that
+/// data from a different GPU or driver version is never passed to the driver.
+pub(crate) fn pipeline_cache_header(info: &AdapterInfo) -> Vec<u8> {
+    let mut header =
+        Vec::with_capacity(32 + info.name.len() + info.driver.len() + info.driver_info.len());
+    header.extend_from_slice(PIPELINE_CACHE_MAGIC);
+    header.extend_from_slice(&PIPELINE_CACHE_VERSION.to_le_bytes());
+    header.extend_from_slice(&(info.backend as u32).to_le_bytes());
+    header.extend_from_slice(&(info.vendor as u64).to_le_bytes());
+    header.extend_from_slice(&(info.device as u64).to_le_bytes());
+    for text in &[&info.name, &info.driver, &info.driver_info] {
+        header.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        header.extend_from_slice(text.as_bytes());
+    }
+    header
+}
+
+/// Strip the header from the serialized data, if it matches this adapter.
+pub(crate) fn strip_pipeline_cache_header<'a>(header: &[u8], data: &'a [u8]) -> Option<&'a [u8]> {
+    if data.starts_with(header) {
+        Some(&data[header.len()..])
+    } else {
+        None
+    }
+}
+
+/// Native object of a pipeline, which is compiled later
+/// if the pipeline was created asynchronously.
+#[derive(Debug)]
+pub(crate) enum PipelineRaw<P> {
+    Pending,
+    Ready(P),
+    /// The compilation failed with this error, which is reported by
+    /// `render_pipeline_error` and `compute_pipeline_error`.
+    Failed(PipelineError),
+}
+
+impl<P> PipelineRaw<P> {
+    /// Get the native pipeline, which passes check before recording.
+    pub(crate) fn ready<I: fmt::Debug>(&self, id: I) -> &P {
+        match *self {
+            PipelineRaw::Ready(ref raw) => raw,
+            PipelineRaw::Pending | PipelineRaw::Failed(_) => {
+                unreachable!("Pipeline {:?} is not ready", id)
+            }
+        }
+    }
+
+    pub(crate) fn into_ready(self) -> Option<P> {
+        match self {
+            PipelineRaw::Ready(raw) => Some(raw),
+            PipelineRaw::Pending | PipelineRaw::Failed(_) => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub enum PipelineCreationStatus {
+    Success,
+    /// The compilation failed, `render_pipeline_error` or
+    /// `compute_pipeline_error` gives the error.
+    Error,
+    /// The device was destroyed before the pipeline was compiled.
+    DeviceLost,
+}
+
+pub type PipelineCreatedCallback =
+    unsafe extern "C" fn(status: PipelineCreationStatus, userdata: *mut u8);
+
+/// Behavior of a pass that uses a pipeline which is still compiling.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PendingPipelinePolicy {
+    /// Compile the pipeline on the spot, or wait for the thread compiling it.
+    Wait,
+    /// Reject the pass with `CommandEncoderError::PipelineNotReady`.
+    Error,
+}
+
+impl Default for PendingPipelinePolicy {
+    fn default() -> Self {
+        PendingPipelinePolicy::Wait
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PipelineCallback {
+    pub callback: PipelineCreatedCallback,
+    pub userdata: *mut u8,
+}
+
+unsafe impl Send for PipelineCallback {}
+unsafe impl Sync for PipelineCallback {}
+
+impl PipelineCallback {
+    pub(crate) fn fire(self, status: PipelineCreationStatus) {
+        unsafe {
+            (self.callback)(status, self.userdata);
+        }
+    }
+}
+
+/// Shader stage of a pipeline to be compiled later.
+///
+/// Destroying the module compiles the pipelines using it first.
+#[derive(Debug)]
+pub(crate) struct PendingStage {
+    pub module: ShaderModuleId,
+    pub entry_point: String,
+}
+
+/// Fixed-function state of a render pipeline, detached from the descriptor.
+#[derive(Debug)]
+pub(crate) struct RenderPipelineState {
+    pub rp_key: RenderPassKey,
+    pub vertex_buffers: Vec<hal::pso::VertexBufferDesc>,
+    pub attributes: Vec<hal::pso::AttributeDesc>,
+    pub input_assembler: hal::pso::InputAssemblerDesc,
+    pub rasterizer: hal::pso::Rasterizer,
+    pub blender: hal::pso::BlendDesc,
+    pub depth_stencil: hal::pso::DepthStencilDesc,
+    pub multisampling: Option<hal::pso::Multisampling>,
+    pub baked_states: hal::pso::BakedStates,
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingRenderPipeline {
+    pub layout_id: PipelineLayoutId,
+    pub cache_id: Option<PipelineCacheId>,
+    pub state: RenderPipelineState,
+    pub vertex: PendingStage,
+    pub fragment: Option<PendingStage>,
+    pub callback: PipelineCallback,
+}
+
+impl PendingRenderPipeline {
+    fn uses_module(&self, module: ShaderModuleId) -> bool {
+        self.vertex.module == module
+            || self
+                .fragment
+                .as_ref()
+                .map_or(false, |stage| stage.module == module)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingComputePipeline {
+    pub layout_id: PipelineLayoutId,
+    pub cache_id: Option<PipelineCacheId>,
+    pub stage: PendingStage,
+    pub callback: PipelineCallback,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PendingPipelines {
+    pub policy: PendingPipelinePolicy,
+    pub render: FastHashMap<RenderPipelineId, PendingRenderPipeline>,
+    pub compute: FastHashMap<ComputePipelineId, PendingComputePipeline>,
+    /// Pipelines taken out of the queue, which are being compiled right now.
+    pub compiling_render: Vec<RenderPipelineId>,
+    pub compiling_compute: Vec<ComputePipelineId>,
+    /// Whether a thread is compiling the queued pipelines in the background.
+    pub compiler_running: bool,
+}
+
+impl PendingPipelines {
+    pub(crate) fn take_render(&mut self, id: RenderPipelineId) -> Option<PendingRenderPipeline> {
+        let job = self.render.remove(&id)?;
+        self.compiling_render.push(id);
+        Some(job)
+    }
+
+    pub(crate) fn take_compute(&mut self, id: ComputePipelineId) -> Option<PendingComputePipeline> {
+        let job = self.compute.remove(&id)?;
+        self.compiling_compute.push(id);
+        Some(job)
+    }
+
+    pub(crate) fn finish_render(&mut self, id: RenderPipelineId) {
+        self.compiling_render.retain(|&other| other != id);
+    }
+
+    pub(crate) fn finish_compute(&mut self, id: ComputePipelineId) {
+        self.compiling_compute.retain(|&other| other != id);
+    }
+
+    /// Take the pipelines using the shader `module`, to compile them right away.
+    pub(crate) fn take_using_module(
+        &mut self,
+        module: ShaderModuleId,
+    ) -> (
+        Vec<(RenderPipelineId, PendingRenderPipeline)>,
+        Vec<(ComputePipelineId, PendingComputePipeline)>,
+    ) {
+        let render = self
+            .render
+            .iter()
+            .filter(|&(_, job)| job.uses_module(module))
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        let compute = self
+            .compute
+            .iter()
+            .filter(|&(_, job)| job.stage.module == module)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        (
+            render
+                .into_iter()
+                .map(|id| (id, self.take_render(id).unwrap()))
+                .collect(),
+            compute
+                .into_iter()
+                .map(|id| (id, self.take_compute(id).unwrap()))
+                .collect(),
+        )
+    }
+
+    pub(crate) fn is_compiling(&self) -> bool {
+        !self.compiling_render.is_empty() || !self.compiling_compute.is_empty()
+    }
+
+    /// Drop the queued pipelines, returning their callbacks.
+    pub(crate) fn drain_callbacks(&mut self) -> Vec<PipelineCallback> {
+        self.render
+            .drain()
+            .map(|(_, job)| job.callback)
+            .chain(self.compute.drain().map(|(_, job)| job.callback))
+            .collect()
+    }
+}
+
+/// Pipelines created asynchronously, shared with the threads compiling them.
+#[derive(Debug, Default)]
+pub(crate) struct PipelineQueue {
+    pub pending: Mutex<PendingPipelines>,
+    /// Signaled every time a pipeline is done compiling.
+    pub compiled: Condvar,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{id::TypedId as _, instance::DeviceType};
+
+    fn adapter_info(device: usize, driver_info: &str) -> AdapterInfo {
+        AdapterInfo {
+            name: "Test GPU".to_string(),
+            vendor: 0x10de,
+            device,
+            device_type: DeviceType::DiscreteGpu,
+            backend: wgt::Backend::Vulkan,
+            portability: false,
+            is_fallback: false,
+            driver: String::new(),
+            driver_info: driver_info.to_string(),
+        }
+    }
+
+    #[test]
+    fn pipeline_cache_header() {
+        let header = super::pipeline_cache_header(&adapter_info(1, "450.80"));
+        let mut data = header.clone();
+        data.extend_from_slice(&[1, 2, 3]);
+        assert_eq!(
+            strip_pipeline_cache_header(&header, &data),
+            Some(&[1u8, 2, 3][..])
+        );
+
+        let other = super::pipeline_cache_header(&adapter_info(2, "450.80"));
+        assert_eq!(strip_pipeline_cache_header(&other, &data), None);
+        let updated = super::pipeline_cache_header(&adapter_info(1, "455.23"));
+        assert_eq!(strip_pipeline_cache_header(&updated, &data), None);
+        assert_eq!(strip_pipeline_cache_header(&header, &data[..4]), None);
+        assert!(backend_supports_pipeline_caches(wgt::Backend::Vulkan));
+        assert!(!backend_supports_pipeline_caches(wgt::Backend::Gl));
+    }
+
+    fn instruction(opcode: spirv_headers::Op, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode as u32];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn string(text: &str) -> Vec<u32> {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.resize(text.len() / 4 * 4 + 4, 0);
+        bytes
+            .chunks(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn shader_features_from_spirv() {
+        use spirv_headers::{Capability as C, Op};
+
+        let capability = |capability: C| instruction(Op::Capability, &[capability as u32]);
+        let mut code = vec![0x0723_0203, 0x0001_0000, 0, 8, 0];
+        code.extend(capability(C::Shader));
+        code.extend(capability(C::Float16));
+        code.extend(capability(C::GroupNonUniformBallot));
+        code.extend(instruction(Op::Extension, &string("SPV_KHR_16bit_storage")));
+        code.extend(capability(C::Float64));
+        assert_eq!(
+            ShaderFeatures::from_spirv(&code),
+            ShaderFeatures::FLOAT16 | ShaderFeatures::SUBGROUP_OPERATIONS | ShaderFeatures::FLOAT64
+        );
+
+        let mut code = vec![0x0723_0203, 0x0001_0000, 0, 8, 0];
+        code.extend(capability(C::SubgroupBallotKHR));
+        code.extend(instruction(Op::MemoryModel, &[0, 1]));
+        // Not a capability declaration anymore, even if it looks like one.
+        code.extend(capability(C::Int64));
+        assert_eq!(
+            ShaderFeatures::from_spirv(&code),
+            ShaderFeatures::SUBGROUP_OPERATIONS
+        );
+    }
+
+    #[test]
+    fn shader_features_from_hal() {
+        let features = hal::Features::SHADER_FLOAT64 | hal::Features::SHADER_INT16;
+        assert_eq!(
+            ShaderFeatures::from_hal(features),
+            ShaderFeatures::FLOAT64 | ShaderFeatures::INT16
+        );
+        // Nothing is assumed from the backend alone.
+        assert_eq!(
+            ShaderFeatures::from_hal(hal::Features::empty()),
+            ShaderFeatures::empty()
+        );
+    }
+
+    #[test]
+    fn fragment_depth_modes() {
+        use spirv_headers::{ExecutionMode as Em, ExecutionModel, Op};
+
+        let mut code = vec![0x0723_0203, 0x0001_0000, 0, 8, 0];
+        let main = string("main");
+        let other = string("other");
+        code.extend(instruction(
+            Op::EntryPoint,
+            &[&[ExecutionModel::Fragment as u32, 1][..], &main].concat(),
+        ));
+        code.extend(instruction(
+            Op::EntryPoint,
+            &[&[ExecutionModel::Fragment as u32, 2][..], &other].concat(),
+        ));
+        code.extend(instruction(
+            Op::ExecutionMode,
+            &[1, Em::EarlyFragmentTests as u32],
+        ));
+        code.extend(instruction(
+            Op::ExecutionMode,
+            &[2, Em::DepthReplacing as u32],
+        ));
+        code.extend(instruction(
+            Op::ExecutionMode,
+            &[2, Em::DepthGreater as u32],
+        ));
+        code.extend(instruction(Op::Kill, &[]));
+
+        let modes = FragmentDepthModes::from_spirv(&code, "main");
+        assert!(modes.early_fragment_tests && modes.discards);
+        assert_eq!(modes.conservative_depth, None);
+        let modes = FragmentDepthModes::from_spirv(&code, "other");
+        assert!(!modes.early_fragment_tests && modes.depth_replacing);
+        assert_eq!(modes.conservative_depth, Some(ConservativeDepth::Greater));
+    }
+
+    #[test]
+    fn shader_error_location() {
+        use spirv_headers::Op;
+
+        let mut code = vec![0x0723_0203, 0x0001_0000, 0, 6, 0];
+        let text = string("void main() {\n  texture = 1.0;\n}");
+        code.extend(instruction(Op::Source, &[&[2, 450, 1][..], &text].concat()));
+        let name = string("texture");
+        code.extend(instruction(Op::Name, &[&[5][..], &name].concat()));
+        code.extend(instruction(Op::TypeFloat, &[4, 32]));
+        code.extend(instruction(Op::Line, &[1, 2, 3]));
+        code.extend(instruction(Op::Variable, &[4, 5, 6]));
+
+        let expected = Some(ShaderErrorLocation {
+            instruction: 4,
+            line: Some(2),
+            snippet: Some("texture = 1.0;".to_string()),
+        });
+        let by_name = "error: 'texture' : unknown variable";
+        assert_eq!(ShaderErrorLocation::find(&code, by_name), expected);
+        assert_eq!(ShaderErrorLocation::find(&code, "Invalid ID 5"), expected);
+        let by_type = ShaderErrorLocation::find(&code, "bad type %4").unwrap();
+        assert_eq!((by_type.instruction, by_type.line), (2, None));
+        assert_eq!(ShaderErrorLocation::find(&code, "error: 'foo'"), None);
+    }
+
+    unsafe extern "C" fn count_device_lost(status: PipelineCreationStatus, userdata: *mut u8) {
+        if let PipelineCreationStatus::DeviceLost = status {
+            *(userdata as *mut usize) += 1;
+        }
+    }
+
+    fn compute_job(module: ShaderModuleId, userdata: *mut u8) -> PendingComputePipeline {
+        PendingComputePipeline {
+            layout_id: PipelineLayoutId::zip(0, 1, wgt::Backend::Empty),
+            cache_id: None,
+            stage: PendingStage {
+                module,
+                entry_point: "main".to_string(),
+            },
+            callback: PipelineCallback {
+                callback: count_device_lost,
+                userdata,
+            },
+        }
+    }
+
+    #[test]
+    fn pending_pipelines_using_module() {
+        let module = |index| ShaderModuleId::zip(index, 1, wgt::Backend::Empty);
+        let pipeline = |index| ComputePipelineId::zip(index, 1, wgt::Backend::Empty);
+        let mut pending = PendingPipelines::default();
+        for &(index, module_index) in &[(0, 0), (1, 1), (2, 0)] {
+            let job = compute_job(module(module_index), std::ptr::null_mut());
+            pending.compute.insert(pipeline(index), job);
+        }
+
+        let (render, compute) = pending.take_using_module(module(0));
+        assert!(render.is_empty());
+        let mut taken = compute
+            .iter()
+            .map(|&(id, _)| id.unzip().0)
+            .collect::<Vec<_>>();
+        taken.sort();
+        assert_eq!(taken, [0, 2]);
+        assert!(pending.is_compiling());
+        pending.finish_compute(pipeline(0));
+        assert!(pending.is_compiling());
+        pending.finish_compute(pipeline(2));
+        assert!(!pending.is_compiling());
+        assert_eq!(pending.compute.keys().collect::<Vec<_>>(), [&pipeline(1)]);
+    }
+
+    #[test]
+    fn pending_pipelines_device_lost() {
+        let mut lost = 0usize;
+        let userdata = &mut lost as *mut usize as *mut u8;
+        let mut pending = PendingPipelines::default();
+        for index in 0..3 {
+            let module = ShaderModuleId::zip(0, 1, wgt::Backend::Empty);
+            let id = ComputePipelineId::zip(index, 1, wgt::Backend::Empty);
+            pending.compute.insert(id, compute_job(module, userdata));
+        }
+        // A pipeline being compiled gets its callback once it's done.
+        let compiling = pending
+            .take_compute(ComputePipelineId::zip(1, 1, wgt::Backend::Empty))
+            .unwrap();
+
+        for callback in pending.drain_callbacks() {
+            callback.fire(PipelineCreationStatus::DeviceLost);
+        }
+        assert_eq!(lost, 2);
+        assert!(pending.compute.is_empty());
+        assert!(pending.is_compiling());
+        drop(compiling);
+    }
 }
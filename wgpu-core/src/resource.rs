@@ -3,12 +3,14 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use crate::{
-    id::{DeviceId, SwapChainId, TextureId},
+    device::{LazyAllocation, StagingChunk, TextureViewKey, TransientAllocation},
+    id::{DeviceId, SwapChainId, TextureId, TextureViewId},
     track::DUMMY_SELECTOR,
-    LifeGuard, RefCount, Stored,
+    FastHashMap, LifeGuard, RefCount, Stored,
 };
 
 use gfx_memory::MemoryBlock;
+use parking_lot::Mutex;
 use wgt::{BufferAddress, BufferUsage, TextureFormat, TextureUsage};
 
 use std::{borrow::Borrow, fmt};
@@ -50,6 +52,12 @@ bitflags::bitflags! {
         const OUTPUT_ATTACHMENT = 8;
         const STORAGE_LOAD = 16;
         const STORAGE_STORE = 32;
+        /// Storage access happens in fragment shaders.
+        const STORAGE_FRAGMENT = 64;
+        /// Storage access happens in compute shaders.
+        const STORAGE_COMPUTE = 128;
+        /// The stages of storage access. If none are set, any shader stage may access it.
+        const STORAGE_STAGES = Self::STORAGE_FRAGMENT.bits | Self::STORAGE_COMPUTE.bits;
         /// The combination of all read-only usages.
         const READ_ALL = Self::COPY_SRC.bits | Self::SAMPLED.bits | Self::STORAGE_LOAD.bits;
         /// The combination of all write-only and read-write usages.
@@ -57,18 +65,62 @@ bitflags::bitflags! {
         /// The combination of all usages that the are guaranteed to be be ordered by the hardware.
         /// If a usage is not ordered, then even if it doesn't change between draw calls, there
         /// still need to be pipeline barriers inserted for synchronization.
-        const ORDERED = Self::READ_ALL.bits | Self::COPY_DST.bits | Self::OUTPUT_ATTACHMENT.bits;
+        const ORDERED = Self::READ_ALL.bits | Self::COPY_DST.bits | Self::OUTPUT_ATTACHMENT.bits | Self::STORAGE_STAGES.bits;
         const UNINITIALIZED = 0xFFFF;
     }
 }
 
+impl BufferUse {
+    /// The public usage flags that allow this usage.
+    pub(crate) fn to_public(self) -> BufferUsage {
+        let mut usage = BufferUsage::empty();
+        usage.set(BufferUsage::MAP_READ, self.contains(Self::MAP_READ));
+        usage.set(BufferUsage::MAP_WRITE, self.contains(Self::MAP_WRITE));
+        usage.set(BufferUsage::COPY_SRC, self.contains(Self::COPY_SRC));
+        usage.set(BufferUsage::COPY_DST, self.contains(Self::COPY_DST));
+        usage.set(BufferUsage::INDEX, self.contains(Self::INDEX));
+        usage.set(BufferUsage::VERTEX, self.contains(Self::VERTEX));
+        usage.set(BufferUsage::UNIFORM, self.contains(Self::UNIFORM));
+        usage.set(
+            BufferUsage::STORAGE,
+            self.intersects(Self::STORAGE_LOAD | Self::STORAGE_STORE),
+        );
+        usage.set(BufferUsage::INDIRECT, self.contains(Self::INDIRECT));
+        usage
+    }
+}
+
+impl TextureUse {
+    /// The public usage flags that allow this usage.
+    pub(crate) fn to_public(self) -> TextureUsage {
+        let mut usage = TextureUsage::empty();
+        usage.set(TextureUsage::COPY_SRC, self.contains(Self::COPY_SRC));
+        usage.set(TextureUsage::COPY_DST, self.contains(Self::COPY_DST));
+        usage.set(TextureUsage::SAMPLED, self.contains(Self::SAMPLED));
+        usage.set(
+            TextureUsage::STORAGE,
+            self.intersects(Self::STORAGE_LOAD | Self::STORAGE_STORE),
+        );
+        usage.set(
+            TextureUsage::OUTPUT_ATTACHMENT,
+            self.contains(Self::OUTPUT_ATTACHMENT),
+        );
+        usage
+    }
+}
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BufferMapAsyncStatus {
     Success,
     Error,
     Unknown,
-    ContextLost,
+    /// The device was destroyed before the mapping was done.
+    DeviceLost,
+    /// The buffer was destroyed or unmapped before the mapping was done.
+    Aborted,
+    /// The requested range is outside of the buffer.
+    RangeInvalid,
 }
 
 #[derive(Debug)]
@@ -88,6 +140,20 @@ pub enum BufferMapState {
 unsafe impl Send for BufferMapState {}
 unsafe impl Sync for BufferMapState {}
 
+impl BufferMapState {
+    /// Take out the mapping request that is still waiting, if any,
+    /// leaving the buffer unmapped.
+    pub(crate) fn take_pending(&mut self) -> Option<BufferPendingMapping> {
+        match std::mem::replace(self, BufferMapState::Idle) {
+            BufferMapState::Waiting(pending) => Some(pending),
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+}
+
 pub enum BufferMapOperation {
     Read {
         callback: crate::device::BufferMapReadCallback,
@@ -114,21 +180,49 @@ impl fmt::Debug for BufferMapOperation {
 }
 
 impl BufferMapOperation {
+    /// Call the user callback. The pointer is only valid with `BufferMapAsyncStatus::Success`.
+    ///
+    /// Note: nothing should be locked, the callback is free to call back into wgpu.
+    pub(crate) fn fire(self, status: BufferMapAsyncStatus, ptr: *mut u8) {
+        match self {
+            BufferMapOperation::Read { callback, userdata } => unsafe {
+                callback(status, ptr, userdata)
+            },
+            BufferMapOperation::Write { callback, userdata } => unsafe {
+                callback(status, ptr, userdata)
+            },
+        }
+    }
+
     pub(crate) fn call_error(self) {
         match self {
-            BufferMapOperation::Read { callback, userdata } => {
-                log::error!("wgpu_buffer_map_read_async failed: buffer mapping is pending");
-                unsafe {
-                    callback(BufferMapAsyncStatus::Error, std::ptr::null(), userdata);
-                }
+            BufferMapOperation::Read { .. } => {
+                log::error!("wgpu_buffer_map_read_async failed: buffer mapping is pending")
             }
-            BufferMapOperation::Write { callback, userdata } => {
-                log::error!("wgpu_buffer_map_write_async failed: buffer mapping is pending");
-                unsafe {
-                    callback(BufferMapAsyncStatus::Error, std::ptr::null_mut(), userdata);
-                }
+            BufferMapOperation::Write { .. } => {
+                log::error!("wgpu_buffer_map_write_async failed: buffer mapping is pending")
             }
         }
+        self.fire(BufferMapAsyncStatus::Error, std::ptr::null_mut());
+    }
+}
+
+/// Callback of a `queue_read_buffer` request.
+#[derive(Debug)]
+pub struct BufferReadOperation {
+    pub callback: crate::device::BufferReadCallback,
+    pub userdata: *mut u8,
+}
+
+unsafe impl Send for BufferReadOperation {}
+unsafe impl Sync for BufferReadOperation {}
+
+impl BufferReadOperation {
+    /// Call the user callback. The data is only valid during the call.
+    ///
+    /// Note: nothing should be locked, the callback is free to call back into wgpu.
+    pub(crate) fn fire(self, status: BufferMapAsyncStatus, data: &[u8]) {
+        unsafe { (self.callback)(status, data.as_ptr(), data.len(), self.userdata) }
     }
 }
 
@@ -145,14 +239,30 @@ pub struct Buffer<B: hal::Backend> {
     pub(crate) raw: B::Buffer,
     pub(crate) device_id: Stored<DeviceId>,
     pub(crate) usage: BufferUsage,
-    pub(crate) memory: MemoryBlock<B>,
+    pub(crate) memory_hint: wgt::MemoryHint,
+    pub(crate) memory: ResourceMemory<B>,
     pub(crate) size: BufferAddress,
     pub(crate) full_range: (),
     pub(crate) sync_mapped_writes: Option<hal::memory::Segment>,
     pub(crate) life_guard: LifeGuard,
     pub(crate) map_state: BufferMapState,
+    #[cfg(feature = "trace")]
+    pub(crate) label: String,
+}
+
+/// Host-visible buffer filled by the user, and consumed by `queue_write_staging_buffer`.
+#[derive(Debug)]
+pub struct StagingBuffer<B: hal::Backend> {
+    pub(crate) device_id: Stored<DeviceId>,
+    pub(crate) chunk: StagingChunk<B>,
+    pub(crate) size: BufferAddress,
+    pub(crate) ptr: *mut u8,
+    pub(crate) sync_mapped_writes: Option<hal::memory::Segment>,
 }
 
+unsafe impl<B: hal::Backend> Send for StagingBuffer<B> {}
+unsafe impl<B: hal::Backend> Sync for StagingBuffer<B> {}
+
 impl<B: hal::Backend> Borrow<RefCount> for Buffer<B> {
     fn borrow(&self) -> &RefCount {
         self.life_guard.ref_count.as_ref().unwrap()
@@ -173,8 +283,131 @@ pub struct Texture<B: hal::Backend> {
     pub(crate) kind: hal::image::Kind,
     pub(crate) format: TextureFormat,
     pub(crate) full_range: hal::image::SubresourceRange,
-    pub(crate) memory: MemoryBlock<B>,
+    pub(crate) memory: ResourceMemory<B>,
     pub(crate) life_guard: LifeGuard,
+    /// Views that can be handed out again, if the device deduplicates objects.
+    pub(crate) view_cache: Mutex<FastHashMap<TextureViewKey, TextureViewId>>,
+    #[cfg(feature = "trace")]
+    pub(crate) label: String,
+}
+
+/// Extent of a mip level of a texture with the given extent.
+///
+/// Each dimension is halved per level, rounding down, but never below 1.
+/// For compressed formats the result may be smaller than a texel block.
+pub(crate) fn mip_level_extent(
+    extent: hal::image::Extent,
+    level: hal::image::Level,
+) -> hal::image::Extent {
+    let halve = |size: u32| size.checked_shr(u32::from(level)).unwrap_or(0).max(1);
+    hal::image::Extent {
+        width: halve(extent.width),
+        height: halve(extent.height),
+        depth: halve(extent.depth),
+    }
+}
+
+/// A dimension of a texture descriptor that goes over a device limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureSizeExceeded {
+    pub limit: &'static str,
+    pub requested: u32,
+    pub maximum: u32,
+}
+
+impl fmt::Display for TextureSizeExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is {}, but the texture needs {}",
+            self.limit, self.maximum, self.requested
+        )
+    }
+}
+
+/// Check the size of a texture against the dimension limits of the device.
+///
+/// The depth of 1D and 2D textures is their number of array layers.
+pub(crate) fn check_texture_size(
+    dimension: wgt::TextureDimension,
+    size: wgt::Extent3d,
+    limits: &wgt::Limits,
+) -> Result<(), TextureSizeExceeded> {
+    let check = |limit, requested, maximum| {
+        if requested > maximum {
+            Err(TextureSizeExceeded {
+                limit,
+                requested,
+                maximum,
+            })
+        } else {
+            Ok(())
+        }
+    };
+    match dimension {
+        wgt::TextureDimension::D1 => {
+            check(
+                "max_texture_dimension_1d",
+                size.width,
+                limits.max_texture_dimension_1d,
+            )?;
+        }
+        wgt::TextureDimension::D2 => {
+            let max = limits.max_texture_dimension_2d;
+            check("max_texture_dimension_2d", size.width, max)?;
+            check("max_texture_dimension_2d", size.height, max)?;
+        }
+        wgt::TextureDimension::D3 => {
+            let max = limits.max_texture_dimension_3d;
+            check("max_texture_dimension_3d", size.width, max)?;
+            check("max_texture_dimension_3d", size.height, max)?;
+            return check("max_texture_dimension_3d", size.depth, max);
+        }
+    }
+    check(
+        "max_texture_array_layers",
+        size.depth,
+        limits.max_texture_array_layers,
+    )
+}
+
+#[derive(Debug)]
+pub(crate) enum ResourceMemory<B: hal::Backend> {
+    Block(MemoryBlock<B>),
+    /// Placed in the transient pool of the device.
+    Transient(TransientAllocation),
+    /// Lazily allocated memory of a transient attachment.
+    Lazy(LazyAllocation<B>),
+}
+
+impl<B: hal::Backend> ResourceMemory<B> {
+    /// Memory block of a mappable buffer, which is never transient.
+    pub(crate) fn block_mut(&mut self) -> &mut MemoryBlock<B> {
+        match *self {
+            ResourceMemory::Block(ref mut block) => block,
+            _ => unreachable!("Transient memory is never mapped"),
+        }
+    }
+
+    /// Whether the memory is placed in the transient pool of the device.
+    ///
+    /// Transient attachments may get lazily allocated memory instead,
+    /// which only depends on their usage.
+    pub(crate) fn is_transient(&self) -> bool {
+        match *self {
+            ResourceMemory::Transient(_) => true,
+            ResourceMemory::Block(_) | ResourceMemory::Lazy(_) => false,
+        }
+    }
+
+    pub(crate) fn properties(&self) -> hal::memory::Properties {
+        use gfx_memory::Block as _;
+        match *self {
+            ResourceMemory::Block(ref block) => block.properties(),
+            ResourceMemory::Transient(ref allocation) => allocation.properties,
+            ResourceMemory::Lazy(ref allocation) => allocation.properties,
+        }
+    }
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for Texture<B> {
@@ -198,18 +431,38 @@ pub(crate) enum TextureViewInner<B: hal::Backend> {
     SwapChain {
         image: <B::Surface as hal::window::PresentationSurface<B>>::SwapchainImage,
         source_id: Stored<SwapChainId>,
+        frame: crate::swap_chain::FrameIndex,
     },
 }
 
 #[derive(Debug)]
 pub struct TextureView<B: hal::Backend> {
     pub(crate) inner: TextureViewInner<B>,
-    //TODO: store device_id for quick access?
+    pub(crate) device_id: Stored<DeviceId>,
     pub(crate) format: TextureFormat,
+    pub(crate) dimension: wgt::TextureViewDimension,
     pub(crate) extent: hal::image::Extent,
     pub(crate) samples: hal::image::NumSamples,
     pub(crate) range: hal::image::SubresourceRange,
+    /// Subset of the texture usage the view can be bound or attached with.
+    pub(crate) usage: wgt::TextureUsage,
     pub(crate) life_guard: LifeGuard,
+    /// Handles returned by deduplication, each destroyed separately.
+    pub(crate) extra_handles: usize,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_desc: Option<wgt::TextureViewDescriptor<String>>,
+}
+
+impl<B: hal::Backend> TextureView<B> {
+    /// Component type that shaders sample from the view. Stencil aspect views
+    /// of depth-stencil textures are sampled as unsigned integers.
+    pub(crate) fn component_type(&self) -> wgt::TextureComponentType {
+        if self.range.aspects == hal::format::Aspects::STENCIL {
+            wgt::TextureComponentType::Uint
+        } else {
+            wgt::TextureComponentType::from(self.format)
+        }
+    }
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for TextureView<B> {
@@ -228,7 +481,15 @@ impl<B: hal::Backend> Borrow<()> for TextureView<B> {
 pub struct Sampler<B: hal::Backend> {
     pub(crate) raw: B::Sampler,
     pub(crate) device_id: Stored<DeviceId>,
+    /// True if any of the filters is linear.
+    pub(crate) filtering: bool,
     pub(crate) life_guard: LifeGuard,
+    /// Handles returned by deduplication, each destroyed separately.
+    pub(crate) extra_handles: usize,
+    /// Key of the sampler in the deduplication cache of the device.
+    pub(crate) cache_key: Option<crate::device::SamplerKey>,
+    #[cfg(feature = "trace")]
+    pub(crate) trace_desc: wgt::SamplerDescriptor<String>,
 }
 
 impl<B: hal::Backend> Borrow<RefCount> for Sampler<B> {
@@ -242,3 +503,124 @@ impl<B: hal::Backend> Borrow<()> for Sampler<B> {
         &DUMMY_SELECTOR
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LifeGuard;
+
+    unsafe extern "C" fn record_status(
+        status: BufferMapAsyncStatus,
+        data: *const u8,
+        userdata: *mut u8,
+    ) {
+        assert!(data.is_null());
+        (*(userdata as *mut Vec<BufferMapAsyncStatus>)).push(status);
+    }
+
+    #[test]
+    fn destroy_while_waiting() {
+        loom::model(|| {
+            let guard = LifeGuard::new();
+            let mut statuses = Vec::new();
+            let mut state = BufferMapState::Waiting(BufferPendingMapping {
+                sub_range: hal::buffer::SubRange::WHOLE,
+                op: BufferMapOperation::Read {
+                    callback: record_status,
+                    userdata: &mut statuses as *mut Vec<_> as *mut u8,
+                },
+                parent_ref_count: guard.add_ref(),
+            });
+
+            let mapping = state.take_pending().unwrap();
+            mapping
+                .op
+                .fire(BufferMapAsyncStatus::Aborted, std::ptr::null_mut());
+            assert!(state.take_pending().is_none());
+            assert_eq!(statuses, [BufferMapAsyncStatus::Aborted]);
+        });
+    }
+
+    #[test]
+    fn mip_level_extents() {
+        let extent = |width, height, depth| hal::image::Extent {
+            width,
+            height,
+            depth,
+        };
+        // Non-power-of-two sizes round down on every level.
+        let sizes = (0..7)
+            .map(|level| mip_level_extent(extent(100, 37, 1), level).width)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [100, 50, 25, 12, 6, 3, 1]);
+        // A 1-pixel wide texture stays 1 pixel wide.
+        for level in 0..7 {
+            assert_eq!(
+                mip_level_extent(extent(1, 64, 1), level),
+                extent(1, 64 >> level, 1)
+            );
+        }
+        // The depth of 3D textures is halved as well.
+        assert_eq!(mip_level_extent(extent(64, 64, 5), 1), extent(32, 32, 2));
+        assert_eq!(mip_level_extent(extent(64, 64, 5), 3), extent(8, 8, 1));
+        // Levels past the smallest one are clamped.
+        assert_eq!(mip_level_extent(extent(4, 4, 4), 5), extent(1, 1, 1));
+    }
+
+    #[test]
+    fn texture_size_limits() {
+        use wgt::TextureDimension as Td;
+        let limits = wgt::Limits::default();
+        let size = |width, height, depth| wgt::Extent3d {
+            width,
+            height,
+            depth,
+        };
+        assert_eq!(
+            check_texture_size(Td::D2, size(8192, 8192, 256), &limits),
+            Ok(())
+        );
+        assert_eq!(
+            check_texture_size(Td::D2, size(32768, 32768, 1), &limits),
+            Err(TextureSizeExceeded {
+                limit: "max_texture_dimension_2d",
+                requested: 32768,
+                maximum: 8192,
+            })
+        );
+        // array layers are limited separately from the depth of 3D textures
+        assert_eq!(
+            check_texture_size(Td::D1, size(64, 1, 257), &limits),
+            Err(TextureSizeExceeded {
+                limit: "max_texture_array_layers",
+                requested: 257,
+                maximum: 256,
+            })
+        );
+        assert_eq!(
+            check_texture_size(Td::D3, size(64, 64, 257), &limits),
+            Ok(())
+        );
+        assert_eq!(
+            check_texture_size(Td::D3, size(64, 64, 4096), &limits),
+            Err(TextureSizeExceeded {
+                limit: "max_texture_dimension_3d",
+                requested: 4096,
+                maximum: 2048,
+            })
+        );
+        // a texture that fits the defaults isn't portable to downlevel adapters
+        assert_eq!(
+            check_texture_size(
+                Td::D2,
+                size(4096, 4096, 1),
+                &wgt::Limits::downlevel_defaults()
+            ),
+            Err(TextureSizeExceeded {
+                limit: "max_texture_dimension_2d",
+                requested: 4096,
+                maximum: 2048,
+            })
+        );
+    }
+}
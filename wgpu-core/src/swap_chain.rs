@@ -26,10 +26,15 @@
     It always starts with `Uninitialized` and ends with `Present`, so that no barriers are
     needed when we need to actually present it.
 
-    In `queue_submit()` we make sure to signal the semaphore whenever we render to a swap
-    chain view.
+    The image returned by `get_next_image()` is ready to be rendered to, since gfx-hal
+    waits for the acquisition internally, so the submissions don't need to wait on anything.
+    In `queue_submit()` we make sure to signal a semaphore whenever we render to a swap
+    chain view, see `FrameSync` for how multiple submissions rendering to the same frame
+    are handled.
 
-    In `present()` we return the swap chain image back and wait on the semaphore.
+    In `present()` we return the swap chain image back and wait on the semaphore signaled
+    by the last submission. Alternatively, `discard_frame()` gives the image up without
+    presenting it.
 !*/
 
 #[cfg(feature = "trace")]
@@ -38,14 +43,58 @@ use crate::{
     conv,
     hub::{GfxBackend, Global, GlobalIdentityHandlerFactory, Input, Token},
     id::{DeviceId, SwapChainId, TextureViewId},
-    resource, LifeGuard, PrivateFeatures, Stored,
+    resource, LifeGuard, PrivateFeatures, Stored, SubmissionIndex,
 };
 
 use hal::{self, device::Device as _, queue::CommandQueue as _, window::PresentationSurface as _};
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+use objc::{
+    msg_send,
+    runtime::{Object, Sel, BOOL, YES},
+    sel, sel_impl,
+};
 use wgt::SwapChainDescriptor;
 
 const FRAME_TIMEOUT_MS: u64 = 1000;
 pub const DESIRED_NUM_FRAMES: u32 = 3;
+/// Number of presented frames a device remembers, to diagnose stale frame views.
+pub(crate) const PRESENTED_FRAME_HISTORY: usize = 16;
+
+/// Formats that surfaces commonly present, checked against the surface list.
+const PRESENTABLE_FORMATS: &[wgt::TextureFormat] = &[
+    wgt::TextureFormat::Bgra8UnormSrgb,
+    wgt::TextureFormat::Bgra8Unorm,
+    wgt::TextureFormat::Rgba8UnormSrgb,
+    wgt::TextureFormat::Rgba8Unorm,
+    wgt::TextureFormat::Rgb10a2Unorm,
+    wgt::TextureFormat::Rgba16Float,
+];
+
+/// Format and color space pairs that a surface with the given HAL formats
+/// can present. `None` means that the surface accepts any format.
+///
+/// gfx-hal doesn't report the color spaces and always presents in sRGB,
+/// so the wider color spaces are never listed for now.
+pub(crate) fn surface_formats(
+    formats: Option<&[hal::format::Format]>,
+    private_features: PrivateFeatures,
+) -> Vec<wgt::SurfaceFormat> {
+    PRESENTABLE_FORMATS
+        .iter()
+        .filter(|&&format| {
+            formats.map_or(true, |list| {
+                list.contains(&conv::map_texture_format(format, private_features))
+            })
+        })
+        .map(|&format| wgt::SurfaceFormat {
+            format,
+            color_space: wgt::ColorSpace::Srgb,
+        })
+        .collect()
+}
+
+/// Index of an acquired frame, counting from 1 for each swap chain.
+pub type FrameIndex = u64;
 
 #[derive(Debug)]
 pub struct SwapChain<B: hal::Backend> {
@@ -53,9 +102,91 @@ pub struct SwapChain<B: hal::Backend> {
     pub(crate) device_id: Stored<DeviceId>,
     pub(crate) desc: SwapChainDescriptor,
     pub(crate) num_frames: hal::window::SwapImageIndex,
-    pub(crate) semaphore: B::Semaphore,
+    /// Semaphores that are not signaled, to be reused by the next frames.
+    pub(crate) free_semaphores: Vec<B::Semaphore>,
     pub(crate) acquired_view_id: Option<Stored<TextureViewId>>,
+    /// Index of the most recently acquired frame.
+    pub(crate) acquired_frame: FrameIndex,
     pub(crate) acquired_framebuffers: Vec<B::Framebuffer>,
+    /// Synchronization of the acquired frame with its presentation.
+    pub(crate) frame_sync: FrameSync<B::Semaphore>,
+}
+
+impl<B: hal::Backend> SwapChain<B> {
+    /// Destroy the semaphores of the swap chain, which the GPU is expected to be done with.
+    pub(crate) unsafe fn destroy_semaphores(self, device: &B::Device) {
+        let render_finished = self
+            .frame_sync
+            .render_finished
+            .map(|(semaphore, _)| semaphore);
+        for semaphore in self.free_semaphores.into_iter().chain(render_finished) {
+            device.destroy_semaphore(semaphore);
+        }
+    }
+}
+
+/// Semaphore that orders the presentation of a frame after the rendering to it.
+///
+/// It has to be signaled by the last submission rendering to the frame, but that
+/// one is only known once the frame is presented. So every submission rendering
+/// to the frame signals a new semaphore, and the one signaled by an earlier
+/// submission is superseded. `present()` waits on the latest semaphore only,
+/// which covers the earlier submissions as well, since the queue signals it after
+/// all the prior work is done.
+#[derive(Debug)]
+pub(crate) struct FrameSync<S> {
+    /// Semaphore signaled by the latest submission rendering to the frame.
+    render_finished: Option<(S, SubmissionIndex)>,
+}
+
+impl<S> FrameSync<S> {
+    pub(crate) fn new() -> Self {
+        FrameSync {
+            render_finished: None,
+        }
+    }
+
+    /// Record that the submission `submit_index` renders to the frame.
+    ///
+    /// Returns true if the submission has to signal `render_finished()`, and false if
+    /// it's already signaling it, because several of its command buffers render to the
+    /// frame. A semaphore signaled by an earlier submission is pushed into `superseded`,
+    /// to be destroyed once that submission is done, since nobody is going to wait on it.
+    pub(crate) fn signal(
+        &mut self,
+        submit_index: SubmissionIndex,
+        new_semaphore: impl FnOnce() -> S,
+        superseded: &mut Vec<(S, SubmissionIndex)>,
+    ) -> bool {
+        match self.render_finished {
+            Some((_, index)) if index == submit_index => false,
+            _ => {
+                superseded.extend(self.render_finished.take());
+                self.render_finished = Some((new_semaphore(), submit_index));
+                true
+            }
+        }
+    }
+
+    /// Semaphore signaled by the latest submission rendering to the frame, if any.
+    pub(crate) fn render_finished(&self) -> Option<&S> {
+        self.render_finished
+            .as_ref()
+            .map(|&(ref semaphore, _)| semaphore)
+    }
+
+    /// Take the semaphore to wait on when the frame is presented or discarded.
+    pub(crate) fn take(&mut self) -> Option<S> {
+        self.render_finished.take().map(|(semaphore, _)| semaphore)
+    }
+}
+
+/// A swap chain frame view that was invalidated by `swap_chain_present`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PresentedFrame {
+    pub view_id: TextureViewId,
+    pub swap_chain_id: SwapChainId,
+    pub frame: FrameIndex,
 }
 
 pub(crate) fn swap_chain_descriptor_to_hal(
@@ -80,6 +211,104 @@ pub(crate) fn swap_chain_descriptor_to_hal(
     config
 }
 
+/// `CAMetalLayer` that a surface adopted from the caller.
+///
+/// Both gfx-backend-metal and MoltenVK set more properties of the layer than
+/// its drawable size and pixel format when configuring a swap chain, so the
+/// others are saved before and restored after. The device is the exception,
+/// since the drawables have to be created by the device that renders to them.
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AdoptedLayer(pub(crate) *mut Object);
+
+// The layer is only touched from `device_create_swap_chain`, under the surface lock.
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+unsafe impl Send for AdoptedLayer {}
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+unsafe impl Sync for AdoptedLayer {}
+
+/// Properties of an adopted layer that configuring a swap chain may change.
+/// The optional ones aren't available on all OS versions.
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+pub(crate) struct LayerProperties {
+    framebuffer_only: BOOL,
+    magnification_filter: *mut Object,
+    maximum_drawable_count: Option<u64>,
+    display_sync_enabled: Option<BOOL>,
+    allows_next_drawable_timeout: Option<BOOL>,
+    wants_extended_dynamic_range_content: Option<BOOL>,
+    colorspace: Option<*mut Object>,
+}
+
+#[cfg(any(target_os = "ios", target_os = "macos"))]
+impl AdoptedLayer {
+    unsafe fn responds_to(self, selector: Sel) -> bool {
+        let responds: BOOL = msg_send![self.0, respondsToSelector: selector];
+        responds == YES
+    }
+
+    /// Save the properties, retaining the objects among them.
+    pub(crate) unsafe fn save(self) -> LayerProperties {
+        macro_rules! get_if_available {
+            ($getter:ident) => {
+                if self.responds_to(sel!($getter)) {
+                    Some(msg_send![self.0, $getter])
+                } else {
+                    None
+                }
+            };
+        }
+        let retain = |object: *mut Object| {
+            if !object.is_null() {
+                let _: *mut Object = msg_send![object, retain];
+            }
+            object
+        };
+        let magnification_filter: *mut Object = msg_send![self.0, magnificationFilter];
+        let colorspace: Option<*mut Object> = get_if_available!(colorspace);
+        LayerProperties {
+            framebuffer_only: msg_send![self.0, framebufferOnly],
+            magnification_filter: retain(magnification_filter),
+            maximum_drawable_count: get_if_available!(maximumDrawableCount),
+            display_sync_enabled: get_if_available!(displaySyncEnabled),
+            allows_next_drawable_timeout: get_if_available!(allowsNextDrawableTimeout),
+            wants_extended_dynamic_range_content: get_if_available!(
+                wantsExtendedDynamicRangeContent
+            ),
+            colorspace: colorspace.map(retain),
+        }
+    }
+
+    /// Restore the saved properties, releasing the objects among them.
+    pub(crate) unsafe fn restore(self, properties: LayerProperties) {
+        let release = |object: *mut Object| {
+            if !object.is_null() {
+                let () = msg_send![object, release];
+            }
+        };
+        let layer = self.0;
+        let () = msg_send![layer, setFramebufferOnly: properties.framebuffer_only];
+        let () = msg_send![layer, setMagnificationFilter: properties.magnification_filter];
+        release(properties.magnification_filter);
+        if let Some(count) = properties.maximum_drawable_count {
+            let () = msg_send![layer, setMaximumDrawableCount: count];
+        }
+        if let Some(enabled) = properties.display_sync_enabled {
+            let () = msg_send![layer, setDisplaySyncEnabled: enabled];
+        }
+        if let Some(allows) = properties.allows_next_drawable_timeout {
+            let () = msg_send![layer, setAllowsNextDrawableTimeout: allows];
+        }
+        if let Some(wants) = properties.wants_extended_dynamic_range_content {
+            let () = msg_send![layer, setWantsExtendedDynamicRangeContent: wants];
+        }
+        if let Some(colorspace) = properties.colorspace {
+            let () = msg_send![layer, setColorspace: colorspace];
+            release(colorspace);
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct SwapChainOutput {
@@ -89,6 +318,10 @@ pub struct SwapChainOutput {
 #[derive(Debug)]
 pub enum SwapChainGetNextTextureError {
     GpuProcessingTimeout,
+    /// The previous frame is neither presented nor discarded.
+    FrameAlreadyAcquired,
+    /// The swap chain doesn't exist, or was replaced by a new one.
+    InvalidSwapChain(SwapChainId),
 }
 
 impl<G: GlobalIdentityHandlerFactory> Global<G> {
@@ -101,12 +334,25 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
         let mut token = Token::root();
 
         let (mut surface_guard, mut token) = self.surfaces.write(&mut token);
-        let surface = &mut surface_guard[swap_chain_id.to_surface_id()];
         let (device_guard, mut token) = hub.devices.read(&mut token);
         let (mut swap_chain_guard, mut token) = hub.swap_chains.write(&mut token);
-        let sc = &mut swap_chain_guard[swap_chain_id];
+        let (surface, sc) = match (
+            surface_guard.get_mut(swap_chain_id.to_surface_id()),
+            swap_chain_guard.get_mut(swap_chain_id),
+        ) {
+            (Some(surface), Some(sc)) => (surface, sc),
+            _ => {
+                return Err(SwapChainGetNextTextureError::InvalidSwapChain(
+                    swap_chain_id,
+                ))
+            }
+        };
         let device = &device_guard[sc.device_id.value];
 
+        if sc.acquired_view_id.is_some() {
+            return Err(SwapChainGetNextTextureError::FrameAlreadyAcquired);
+        }
+
         let (image, _) = {
             let suf = B::get_surface_mut(surface);
             match unsafe { suf.acquire_image(FRAME_TIMEOUT_MS * 1_000_000) } {
@@ -129,6 +375,7 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
         };
 
+        let frame = sc.acquired_frame + 1;
         let view = resource::TextureView {
             inner: resource::TextureViewInner::SwapChain {
                 image,
@@ -136,20 +383,30 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
                     value: swap_chain_id,
                     ref_count: sc.life_guard.add_ref(),
                 },
+                frame,
+            },
+            device_id: Stored {
+                value: sc.device_id.value,
+                ref_count: device.life_guard.add_ref(),
             },
             format: sc.desc.format,
+            dimension: wgt::TextureViewDimension::D2,
             extent: hal::image::Extent {
                 width: sc.desc.width,
                 height: sc.desc.height,
                 depth: 1,
             },
             samples: 1,
+            usage: sc.desc.usage,
             range: hal::image::SubresourceRange {
                 aspects: hal::format::Aspects::COLOR,
                 layers: 0..1,
                 levels: 0..1,
             },
             life_guard: LifeGuard::new(),
+            extra_handles: 0,
+            #[cfg(feature = "trace")]
+            trace_desc: None,
         };
         let ref_count = view.life_guard.add_ref();
         let id = hub
@@ -157,40 +414,68 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .register_identity(view_id_in, view, &mut token);
 
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(Action::GetSwapChainTexture {
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(Action::GetSwapChainTexture {
                 id,
                 parent_id: swap_chain_id,
             }),
             None => (),
         };
 
-        assert!(
-            sc.acquired_view_id.is_none(),
-            "Swap chain image is already acquired"
-        );
         sc.acquired_view_id = Some(Stored {
             value: id,
             ref_count,
         });
+        sc.acquired_frame = frame;
 
         Ok(SwapChainOutput { view_id: Some(id) })
     }
 
     pub fn swap_chain_present<B: GfxBackend>(&self, swap_chain_id: SwapChainId) {
+        self.swap_chain_present_with_rects::<B>(swap_chain_id, &[])
+    }
+
+    /// Present the acquired frame, hinting that only the `damage` regions
+    /// changed since the previous frame. An empty list means the whole frame.
+    ///
+    /// The damage is only a hint, see `surface_supports_present_damage`
+    /// for whether the backend can make use of it.
+    pub fn swap_chain_present_with_rects<B: GfxBackend>(
+        &self,
+        swap_chain_id: SwapChainId,
+        damage: &[wgt::RectanglePhysicalPixels],
+    ) {
         let hub = B::hub(self);
         let mut token = Token::root();
 
         let (mut surface_guard, mut token) = self.surfaces.write(&mut token);
-        let surface = &mut surface_guard[swap_chain_id.to_surface_id()];
         let (mut device_guard, mut token) = hub.devices.write(&mut token);
         let (mut swap_chain_guard, mut token) = hub.swap_chains.write(&mut token);
-        let sc = &mut swap_chain_guard[swap_chain_id];
+        let (surface, sc) = match (
+            surface_guard.get_mut(swap_chain_id.to_surface_id()),
+            swap_chain_guard.get_mut(swap_chain_id),
+        ) {
+            (Some(surface), Some(sc)) => (surface, sc),
+            _ => {
+                log::error!("Presenting an invalid swap chain {:?}", swap_chain_id);
+                return;
+            }
+        };
         let device = &mut device_guard[sc.device_id.value];
 
+        for rect in damage {
+            assert!(
+                rect.fits(sc.desc.width, sc.desc.height),
+                "Damage rectangle {:?} is outside of the {}x{} swap chain",
+                rect,
+                sc.desc.width,
+                sc.desc.height
+            );
+        }
+
         #[cfg(feature = "trace")]
-        match device.trace {
-            Some(ref trace) => trace.lock().add(Action::PresentSwapChain(swap_chain_id)),
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(Action::PresentSwapChain(swap_chain_id)),
             None => (),
         };
 
@@ -199,18 +484,34 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             .take()
             .expect("Swap chain image is not acquired");
         let (view, _) = hub.texture_views.unregister(view_id.value, &mut token);
-        let image = match view.inner {
+        let (image, frame) = match view.inner {
             resource::TextureViewInner::Native { .. } => unreachable!(),
-            resource::TextureViewInner::SwapChain { image, .. } => image,
+            resource::TextureViewInner::SwapChain { image, frame, .. } => (image, frame),
         };
+        {
+            let mut presented_frames = device.presented_frames.lock();
+            if presented_frames.len() == PRESENTED_FRAME_HISTORY {
+                presented_frames.pop_front();
+            }
+            presented_frames.push_back(PresentedFrame {
+                view_id: view_id.value,
+                swap_chain_id,
+                frame,
+            });
+        }
 
+        // gfx-hal has no way to pass the damage to VK_KHR_incremental_present
+        // or EGL_KHR_swap_buffers_with_damage, so the whole frame is presented.
+        let render_finished = sc.frame_sync.take();
         let err = unsafe {
             let queue = &mut device.queue_group.queues[0];
-            queue.present_surface(B::get_surface_mut(surface), image, Some(&sc.semaphore))
+            queue.present_surface(B::get_surface_mut(surface), image, render_finished.as_ref())
         };
         if let Err(e) = err {
             log::warn!("present failed: {:?}", e);
         }
+        // The presentation waits on the semaphore, so the next frames can signal it again.
+        sc.free_semaphores.extend(render_finished);
 
         for fbo in sc.acquired_framebuffers.drain(..) {
             unsafe {
@@ -218,4 +519,143 @@ impl<G: GlobalIdentityHandlerFactory> Global<G> {
             }
         }
     }
+
+    /// Release the acquired frame without presenting it, for example when
+    /// the window got minimized in the middle of the frame.
+    pub fn swap_chain_discard_frame<B: GfxBackend>(&self, swap_chain_id: SwapChainId) {
+        let hub = B::hub(self);
+        let mut token = Token::root();
+
+        let (mut surface_guard, mut token) = self.surfaces.write(&mut token);
+        let (device_guard, mut token) = hub.devices.read(&mut token);
+        let (mut swap_chain_guard, mut token) = hub.swap_chains.write(&mut token);
+        let (surface, sc) = match (
+            surface_guard.get_mut(swap_chain_id.to_surface_id()),
+            swap_chain_guard.get_mut(swap_chain_id),
+        ) {
+            (Some(surface), Some(sc)) => (surface, sc),
+            _ => {
+                log::error!(
+                    "Discarding a frame of an invalid swap chain {:?}",
+                    swap_chain_id
+                );
+                return;
+            }
+        };
+        let device = &device_guard[sc.device_id.value];
+
+        let view_id = match sc.acquired_view_id.take() {
+            Some(view_id) => view_id,
+            None => {
+                log::warn!("Swap chain {:?} has no frame to discard", swap_chain_id);
+                return;
+            }
+        };
+
+        #[cfg(feature = "trace")]
+        match *device.trace.lock() {
+            Some(ref mut trace) => trace.add(Action::DiscardSwapChainFrame(swap_chain_id)),
+            None => (),
+        };
+
+        let (view, _) = hub.texture_views.unregister(view_id.value, &mut token);
+        let (image, frame) = match view.inner {
+            resource::TextureViewInner::Native { .. } => unreachable!(),
+            resource::TextureViewInner::SwapChain { image, frame, .. } => (image, frame),
+        };
+        {
+            let mut presented_frames = device.presented_frames.lock();
+            if presented_frames.len() == PRESENTED_FRAME_HISTORY {
+                presented_frames.pop_front();
+            }
+            presented_frames.push_back(PresentedFrame {
+                view_id: view_id.value,
+                swap_chain_id,
+                frame,
+            });
+        }
+
+        unsafe {
+            // The frame was rendered to, so the semaphore is going to be signaled,
+            // and nobody is going to wait on it. Destroy it once the GPU is done.
+            if let Some(semaphore) = sc.frame_sync.take() {
+                device.raw.wait_idle().unwrap();
+                device.raw.destroy_semaphore(semaphore);
+            }
+            for fbo in sc.acquired_framebuffers.drain(..) {
+                device.raw.destroy_framebuffer(fbo);
+            }
+
+            // There is no way to give an image back without presenting it,
+            // but re-configuring the swap chain releases all of them.
+            drop(image);
+            let config =
+                swap_chain_descriptor_to_hal(&sc.desc, sc.num_frames, device.private_features);
+            B::get_surface_mut(surface)
+                .configure_swapchain(&device.raw, config)
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Record a submission of command buffers rendering to the frames
+    /// of the given swap chains, returning the semaphores it signals.
+    fn submit(
+        frames: &mut [FrameSync<u32>],
+        used: &[usize],
+        submit_index: SubmissionIndex,
+        last_semaphore: &mut u32,
+        superseded: &mut Vec<(u32, SubmissionIndex)>,
+    ) -> Vec<u32> {
+        let mut signaled = Vec::new();
+        for &sc in used {
+            let new_semaphore = || {
+                *last_semaphore += 1;
+                *last_semaphore
+            };
+            if frames[sc].signal(submit_index, new_semaphore, superseded) {
+                signaled.push(*frames[sc].render_finished().unwrap());
+            }
+        }
+        signaled
+    }
+
+    #[test]
+    fn two_windows() {
+        for &(first, second) in &[(0, 1), (1, 0)] {
+            let mut frames = [FrameSync::new(), FrameSync::new()];
+            let mut last_semaphore = 0;
+            let mut superseded = Vec::new();
+
+            // Both frames are rendered to by the command buffers of one submission,
+            // then the first one again, by two command buffers of the next submission.
+            let signaled = submit(
+                &mut frames,
+                &[first, second],
+                1,
+                &mut last_semaphore,
+                &mut superseded,
+            );
+            assert_eq!(signaled, vec![1, 2]);
+            assert!(superseded.is_empty());
+            let signaled = submit(
+                &mut frames,
+                &[first, first],
+                2,
+                &mut last_semaphore,
+                &mut superseded,
+            );
+            assert_eq!(signaled, vec![3]);
+            assert_eq!(superseded, vec![(1, 1)]);
+
+            // Each presentation waits on the last submission rendering to its frame.
+            assert_eq!(frames[first].take(), Some(3));
+            assert_eq!(frames[second].take(), Some(2));
+            assert_eq!(frames[first].take(), None);
+        }
+    }
 }
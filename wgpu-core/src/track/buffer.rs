@@ -9,7 +9,7 @@ use crate::{id::BufferId, resource::BufferUse};
 pub(crate) type BufferState = Unit<BufferUse>;
 
 impl PendingTransition<BufferState> {
-    fn collapse(self) -> Result<BufferUse, Self> {
+    pub(crate) fn collapse(self) -> Result<BufferUse, Self> {
         if self.usage.start.is_empty()
             || self.usage.start == self.usage.end
             || !BufferUse::WRITE_ALL.intersects(self.usage.start | self.usage.end)
@@ -34,6 +34,10 @@ impl BufferState {
     pub fn with_usage(usage: BufferUse) -> Self {
         Unit::new(usage)
     }
+
+    pub fn last_usage(&self) -> BufferUse {
+        self.last
+    }
 }
 
 impl ResourceState for BufferState {
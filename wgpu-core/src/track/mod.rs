@@ -14,7 +14,13 @@ use crate::{
 };
 
 use std::{
-    borrow::Borrow, collections::hash_map::Entry, fmt, marker::PhantomData, ops, vec::Drain,
+    borrow::Borrow,
+    collections::hash_map::Entry,
+    fmt,
+    marker::PhantomData,
+    ops,
+    sync::atomic::{AtomicU64, Ordering},
+    vec::Drain,
 };
 
 pub(crate) use buffer::BufferState;
@@ -47,11 +53,11 @@ impl<U: Copy> Unit<U> {
 /// a particular resource type, like a buffer or a texture.
 pub trait ResourceState: Clone + Default {
     /// Corresponding `HUB` identifier.
-    type Id: Copy + fmt::Debug + TypedId;
+    type Id: Copy + fmt::Debug + PartialEq + TypedId;
     /// A type specifying the sub-resources.
-    type Selector: fmt::Debug;
+    type Selector: Clone + fmt::Debug + PartialEq;
     /// Usage type for a `Unit` of a sub-resource.
-    type Usage: fmt::Debug;
+    type Usage: Copy + fmt::Debug + PartialEq;
 
     /// Check if all the selected sub-resources have the same
     /// usage, and return it.
@@ -162,6 +168,9 @@ pub struct ResourceTracker<S: ResourceState> {
     map: FastHashMap<Index, Resource<S>>,
     /// Temporary storage for collecting transitions.
     temp: Vec<PendingTransition<S>>,
+    /// The last successful `change_extend`. Extending only ever adds
+    /// usages, so repeating it is a no-op until a usage gets replaced.
+    last_extend: Option<(S::Id, S::Selector, S::Usage)>,
     /// The backend variant for all the tracked resources.
     backend: wgt::Backend,
 }
@@ -182,6 +191,7 @@ impl<S: ResourceState> ResourceTracker<S> {
         ResourceTracker {
             map: FastHashMap::default(),
             temp: Vec::new(),
+            last_extend: None,
             backend,
         }
     }
@@ -190,6 +200,7 @@ impl<S: ResourceState> ResourceTracker<S> {
     pub fn remove(&mut self, id: S::Id) -> bool {
         let (index, epoch, backend) = id.unzip();
         debug_assert_eq!(backend, self.backend);
+        self.last_extend = None;
         match self.map.remove(&index) {
             Some(resource) => {
                 assert_eq!(resource.epoch, epoch);
@@ -206,6 +217,7 @@ impl<S: ResourceState> ResourceTracker<S> {
         match self.map.entry(index) {
             Entry::Occupied(e) => {
                 if e.get().ref_count.load() == 1 {
+                    self.last_extend = None;
                     let res = e.remove();
                     assert_eq!(res.epoch, epoch);
                     true
@@ -232,9 +244,18 @@ impl<S: ResourceState> ResourceTracker<S> {
             .map(move |(&index, resource)| S::Id::zip(index, resource.epoch, backend))
     }
 
+    /// Return an iterator over used resources keys and their states.
+    pub fn used_states<'a>(&'a self) -> impl 'a + Iterator<Item = (S::Id, &'a S)> {
+        let backend = self.backend;
+        self.map.iter().map(move |(&index, resource)| {
+            (S::Id::zip(index, resource.epoch, backend), &resource.state)
+        })
+    }
+
     /// Clear the tracked contents.
-    fn clear(&mut self) {
+    pub(crate) fn clear(&mut self) {
         self.map.clear();
+        self.last_extend = None;
     }
 
     /// Returns true if the tracker is empty.
@@ -306,9 +327,16 @@ impl<S: ResourceState> ResourceTracker<S> {
         selector: S::Selector,
         usage: S::Usage,
     ) -> Result<(), PendingTransition<S>> {
+        if let Some((last_id, ref last_selector, last_usage)) = self.last_extend {
+            if last_id == id && *last_selector == selector && last_usage == usage {
+                return Ok(());
+            }
+        }
         Self::get_or_insert(self.backend, &mut self.map, id, ref_count)
             .state
-            .change(id, selector, usage, None)
+            .change(id, selector.clone(), usage, None)?;
+        self.last_extend = Some((id, selector, usage));
+        Ok(())
     }
 
     /// Replace the usage of a specified resource.
@@ -319,6 +347,7 @@ impl<S: ResourceState> ResourceTracker<S> {
         selector: S::Selector,
         usage: S::Usage,
     ) -> Drain<PendingTransition<S>> {
+        self.last_extend = None;
         let res = Self::get_or_insert(self.backend, &mut self.map, id, ref_count);
         res.state
             .change(id, selector, usage, Some(&mut self.temp))
@@ -348,6 +377,7 @@ impl<S: ResourceState> ResourceTracker<S> {
     /// Merge another tracker, adding it's transitions to `self`.
     /// Transitions the current usage to the new one.
     pub fn merge_replace<'a>(&'a mut self, other: &'a Self) -> Drain<PendingTransition<S>> {
+        self.last_extend = None;
         for (&index, new) in other.map.iter() {
             match self.map.entry(index) {
                 Entry::Vacant(e) => {
@@ -401,7 +431,7 @@ impl<S: ResourceState> ResourceTracker<S> {
     }
 }
 
-impl<I: Copy + fmt::Debug + TypedId> ResourceState for PhantomData<I> {
+impl<I: Copy + fmt::Debug + PartialEq + TypedId> ResourceState for PhantomData<I> {
     type Id = I;
     type Selector = ();
     type Usage = ();
@@ -434,6 +464,32 @@ impl<I: Copy + fmt::Debug + TypedId> ResourceState for PhantomData<I> {
 
 pub const DUMMY_SELECTOR: () = ();
 
+/// Identifies a `TrackerSet` for as long as its contents are only extended.
+pub(crate) type ScopeId = u64;
+
+static NEXT_SCOPE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn next_scope_id() -> ScopeId {
+    NEXT_SCOPE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Remembers the last scope an object was fully merged into, so that
+/// merging it again can be skipped with a single comparison.
+///
+/// Another scope may take over the stamp at any time, which just
+/// makes the next merge into the original scope go the slow way.
+#[derive(Debug, Default)]
+pub(crate) struct ScopeStamp(AtomicU64);
+
+impl ScopeStamp {
+    /// Stamp the object with `scope`.
+    ///
+    /// Returns false if it was already stamped with it.
+    pub fn enter(&self, scope: ScopeId) -> bool {
+        self.0.swap(scope, Ordering::Relaxed) != scope
+    }
+}
+
 /// A set of trackers for all relevant resources.
 #[derive(Debug)]
 pub(crate) struct TrackerSet {
@@ -444,6 +500,7 @@ pub(crate) struct TrackerSet {
     pub samplers: ResourceTracker<PhantomData<id::SamplerId>>,
     pub compute_pipes: ResourceTracker<PhantomData<id::ComputePipelineId>>,
     pub render_pipes: ResourceTracker<PhantomData<id::RenderPipelineId>>,
+    scope: ScopeId,
 }
 
 impl TrackerSet {
@@ -457,9 +514,15 @@ impl TrackerSet {
             samplers: ResourceTracker::new(backend),
             compute_pipes: ResourceTracker::new(backend),
             render_pipes: ResourceTracker::new(backend),
+            scope: next_scope_id(),
         }
     }
 
+    /// The scope of this set, only valid until it's cleared.
+    pub fn scope(&self) -> ScopeId {
+        self.scope
+    }
+
     /// Clear all the trackers.
     pub fn clear(&mut self) {
         self.buffers.clear();
@@ -469,6 +532,7 @@ impl TrackerSet {
         self.samplers.clear();
         self.compute_pipes.clear();
         self.render_pipes.clear();
+        self.scope = next_scope_id();
     }
 
     /// Try to optimize the tracking representation.
@@ -483,9 +547,10 @@ impl TrackerSet {
     }
 
     /// Merge all the trackers of another instance by extending
-    /// the usage. Panics on a conflict.
-    pub fn merge_extend(&mut self, other: &Self) {
-        self.buffers.merge_extend(&other.buffers).unwrap();
+    /// the usage. Returns the first conflicting buffer usage, and
+    /// panics on the other conflicts.
+    pub fn merge_extend(&mut self, other: &Self) -> Result<(), PendingTransition<BufferState>> {
+        self.buffers.merge_extend(&other.buffers)?;
         self.textures.merge_extend(&other.textures).unwrap();
         self.views.merge_extend(&other.views).unwrap();
         self.bind_groups.merge_extend(&other.bind_groups).unwrap();
@@ -494,6 +559,7 @@ impl TrackerSet {
             .merge_extend(&other.compute_pipes)
             .unwrap();
         self.render_pipes.merge_extend(&other.render_pipes).unwrap();
+        Ok(())
     }
 
     pub fn backend(&self) -> wgt::Backend {
@@ -37,6 +37,11 @@ impl<I: Copy + PartialOrd, T: Copy + PartialEq> RangedStates<I, T> {
         }
     }
 
+    /// Iterate over the values of all the ranges.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.ranges.iter().map(|&(_, ref value)| value)
+    }
+
     /// Clear all the ranges.
     pub fn clear(&mut self) {
         self.ranges.clear();
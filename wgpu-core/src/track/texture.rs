@@ -12,6 +12,10 @@ use std::{iter, ops::Range};
 //TODO: store `hal::image::State` here to avoid extra conversions
 type PlaneStates = RangedStates<hal::image::Layer, Unit<TextureUse>>;
 
+/// Tracking state of a texture, kept separately for each mip level
+/// and each range of array layers with the same usage.
+///
+/// Usages only conflict if the affected subresources actually overlap.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub(crate) struct TextureState {
     mips: ArrayVec<[PlaneStates; MAX_MIP_LEVELS]>,
@@ -45,6 +49,15 @@ impl TextureState {
             full: true,
         }
     }
+
+    /// Combine the last usages of all the tracked subresources.
+    pub fn last_usage(&self) -> TextureUse {
+        self.mips
+            .iter()
+            .flat_map(|mip| mip.values())
+            .filter(|unit| unit.last != TextureUse::UNINITIALIZED)
+            .fold(TextureUse::empty(), |usage, unit| usage | unit.last)
+    }
 }
 
 impl ResourceState for TextureState {
@@ -132,6 +145,8 @@ impl ResourceState for TextureState {
                     }
                 };
             }
+            // keep the common case of a uniform usage down to a single range
+            mip.coalesce();
         }
         Ok(())
     }
@@ -220,6 +235,7 @@ impl ResourceState for TextureState {
                 };
                 mip_self.append(layers, unit);
             }
+            mip_self.coalesce();
         }
 
         Ok(())
@@ -234,11 +250,25 @@ impl ResourceState for TextureState {
 
 #[cfg(test)]
 mod test {
-    //TODO: change() tests
     use super::*;
     use crate::id::Id;
     use hal::{format::Aspects, image::SubresourceRange};
 
+    #[test]
+    fn last_usage() {
+        let mut ts = TextureState::default();
+        ts.mips.push(PlaneStates::from_slice(&[
+            (0..1, Unit::new(TextureUse::SAMPLED)),
+            (1..2, Unit::new(TextureUse::UNINITIALIZED)),
+        ]));
+        ts.mips.push(PlaneStates::from_slice(&[(
+            0..2,
+            Unit::new(TextureUse::COPY_DST),
+        )]));
+
+        assert_eq!(ts.last_usage(), TextureUse::SAMPLED | TextureUse::COPY_DST);
+    }
+
     #[test]
     fn query() {
         let mut ts = TextureState::default();
@@ -287,6 +317,138 @@ mod test {
         );
     }
 
+    #[test]
+    fn change_extend() {
+        let id = Id::default();
+        let mut ts = TextureState::default();
+        ts.change(
+            id,
+            SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 0..1,
+                layers: 0..1,
+            },
+            TextureUse::OUTPUT_ATTACHMENT,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            ts.change(
+                id,
+                SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 1..2,
+                },
+                TextureUse::SAMPLED,
+                None,
+            ),
+            Ok(()),
+            "failed to extend a separate layer"
+        );
+        assert_eq!(
+            ts.change(
+                id,
+                SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 1..2,
+                    layers: 0..1,
+                },
+                TextureUse::SAMPLED,
+                None,
+            ),
+            Ok(()),
+            "failed to extend a separate level"
+        );
+        assert_eq!(
+            ts.change(
+                id,
+                SubresourceRange {
+                    aspects: Aspects::COLOR,
+                    levels: 0..1,
+                    layers: 0..2,
+                },
+                TextureUse::SAMPLED,
+                None,
+            ),
+            Err(PendingTransition {
+                id,
+                selector: SubresourceRange {
+                    aspects: Aspects::empty(),
+                    levels: 0..1,
+                    layers: 0..1,
+                },
+                usage: TextureUse::OUTPUT_ATTACHMENT..TextureUse::SAMPLED,
+            }),
+            "wrong error on extending an overlapping layer"
+        );
+    }
+
+    #[test]
+    fn change_replace() {
+        let id = Id::default();
+        let mut ts = TextureState::with_range(&SubresourceRange {
+            aspects: Aspects::COLOR,
+            levels: 0..2,
+            layers: 0..4,
+        });
+        let mut list = Vec::new();
+        ts.change(
+            id,
+            SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 1..2,
+                layers: 1..3,
+            },
+            TextureUse::COPY_DST,
+            Some(&mut list),
+        )
+        .unwrap();
+        assert_eq!(
+            &list,
+            &[PendingTransition {
+                id,
+                selector: SubresourceRange {
+                    aspects: Aspects::empty(),
+                    levels: 1..2,
+                    layers: 1..3,
+                },
+                usage: TextureUse::UNINITIALIZED..TextureUse::COPY_DST,
+            }],
+            "replacing produced wrong transitions"
+        );
+        assert_eq!(
+            ts.mips[0].query(&(0..4), |&v| v),
+            Some(Ok(Unit::new(TextureUse::UNINITIALIZED))),
+            "untouched level got changed"
+        );
+        assert_eq!(
+            ts.mips[1].query(&(0..1), |&v| v),
+            Some(Ok(Unit::new(TextureUse::UNINITIALIZED))),
+            "untouched layer got changed"
+        );
+
+        list.clear();
+        ts.change(
+            id,
+            SubresourceRange {
+                aspects: Aspects::COLOR,
+                levels: 1..2,
+                layers: 0..4,
+            },
+            TextureUse::COPY_DST,
+            Some(&mut list),
+        )
+        .unwrap();
+        assert_eq!(list.len(), 2, "unexpected transitions: {:?}", list);
+        ts.optimize();
+        assert_eq!(
+            ts.mips[1].query(&(0..4), |unit| unit.last),
+            Some(Ok(TextureUse::COPY_DST)),
+            "wrong final level 1 state"
+        );
+    }
+
     #[test]
     fn merge() {
         let id = Id::default();
@@ -68,6 +68,45 @@ impl From<Backend> for BackendBit {
 pub struct Extensions {
     pub anisotropic_filtering: bool,
     pub mesh_shaders: bool,
+    /// Allows shaders to use 64-bit floats (SPIR-V `Float64` capability).
+    pub shader_float64: bool,
+    /// Allows shaders to use 64-bit integers (SPIR-V `Int64` capability).
+    pub shader_int64: bool,
+    /// Allows shaders to use 16-bit integers (SPIR-V `Int16` capability).
+    pub shader_int16: bool,
+    /// Allows `RasterizationStateDescriptor::conservative`.
+    pub conservative_rasterization: bool,
+    /// Textures are validated against the format features of the adapter,
+    /// as returned by `adapter_get_texture_format_features`, allowing every
+    /// usage and sample count the adapter supports for their format.
+    pub adapter_specific_format_features: bool,
+    /// Allows mappable buffers to have any other usage. Without it, `MAP_READ`
+    /// can only be combined with `COPY_DST`, and `MAP_WRITE` with `COPY_SRC`.
+    ///
+    /// Such buffers are placed in memory that is both host-visible and
+    /// device-local if there is any, which is often small, and otherwise
+    /// in host memory, which is slow for the GPU to access. Discrete GPUs
+    /// are better served by copying to and from staging buffers.
+    pub mappable_primary_buffers: bool,
+    /// Skips the per-draw checks of render passes, like the ones of the drawn
+    /// vertex, instance and index ranges against the bound buffers, while
+    /// still validating the pipelines and bind groups.
+    ///
+    /// Native only. Drawing out of bounds is undefined behavior on some
+    /// backends, so this is only sound for content validated beforehand.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub trusted_content: bool,
+}
+
+impl Extensions {
+    /// Keep only the extensions that are part of the WebGPU specification,
+    /// dropping the native-only ones.
+    pub fn webgpu_only(&self) -> Self {
+        Extensions {
+            anisotropic_filtering: self.anisotropic_filtering,
+            ..Extensions::default()
+        }
+    }
 }
 
 #[repr(C)]
@@ -76,14 +115,203 @@ pub struct Extensions {
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 pub struct Limits {
     pub max_bind_groups: u32,
+    /// Required alignment of uniform buffer binding offsets, including dynamic offsets.
+    ///
+    /// Must be a power of two, and no lower than what the adapter reports.
+    pub min_uniform_buffer_offset_alignment: u32,
+    /// Required alignment of storage buffer binding offsets, including dynamic offsets.
+    ///
+    /// Must be a power of two, and no lower than what the adapter reports.
+    pub min_storage_buffer_offset_alignment: u32,
+    /// Maximum size in bytes of the workgroup storage declared by a compute shader.
+    pub max_compute_shared_memory_size: u32,
+    /// Maximum number of storage buffers visible to one shader stage of a pipeline layout.
+    pub max_storage_buffers_per_shader_stage: u32,
+    /// Maximum number of storage textures visible to one shader stage of a pipeline layout.
+    pub max_storage_textures_per_shader_stage: u32,
+    /// Maximum number of sampled textures visible to one shader stage of a pipeline layout.
+    pub max_sampled_textures_per_shader_stage: u32,
+    /// Maximum number of samplers visible to one shader stage of a pipeline layout.
+    pub max_samplers_per_shader_stage: u32,
+    /// Maximum number of uniform buffers visible to one shader stage of a pipeline layout.
+    pub max_uniform_buffers_per_shader_stage: u32,
+    /// Upper bound of the binding numbers of a bind group layout used by a pipeline layout.
+    pub max_bindings_per_bind_group: u32,
+    /// Maximum number of color attachments of a render pass.
+    pub max_color_attachments: u32,
+    /// Maximum number of vertex buffers of a render pipeline, and the number
+    /// of vertex buffer slots of a render pass.
+    pub max_vertex_buffers: u32,
+    /// Maximum `step_rate` of a per-instance vertex buffer, which is 1
+    /// without `Capabilities::VERTEX_INSTANCE_RATE`.
+    pub max_vertex_step_rate: u32,
+    /// Maximum size in bytes of a buffer.
+    pub max_buffer_size: BufferAddress,
+    /// Maximum width of a 1D texture.
+    pub max_texture_dimension_1d: u32,
+    /// Maximum width and height of a 2D texture.
+    pub max_texture_dimension_2d: u32,
+    /// Maximum width, height and depth of a 3D texture.
+    pub max_texture_dimension_3d: u32,
+    /// Maximum number of array layers of a 1D or 2D texture.
+    pub max_texture_array_layers: u32,
 }
 
 pub const MAX_BIND_GROUPS: usize = 4;
 
+bitflags::bitflags! {
+    /// Optional adapter capabilities that don't need to be requested
+    /// as extensions, but affect what is considered valid usage.
+    #[repr(transparent)]
+    #[cfg_attr(feature = "trace", derive(Serialize))]
+    #[cfg_attr(feature = "replay", derive(Deserialize))]
+    pub struct Capabilities: u64 {
+        /// Color targets of a pipeline can have different blend states and write masks.
+        const INDEPENDENT_BLEND = 1;
+        /// Fragment shading can be forced to run per sample in multisampled pipelines.
+        const SAMPLE_SHADING = 2;
+        /// Render passes can write small uniform data inline, rebinding dynamic
+        /// uniform buffers to the written data.
+        const INLINE_UNIFORM_WRITES = 4;
+        /// Per-instance vertex buffers can advance every `step_rate` instances,
+        /// up to the `max_vertex_step_rate` limit.
+        const VERTEX_INSTANCE_RATE = 8;
+        /// Render pass attachments can view several array layers, and vertex
+        /// shaders can select the layer to render into, without geometry shaders.
+        const LAYERED_RENDERING = 16;
+    }
+}
+
+/// Capabilities that full WebGPU adapters always have, but lower-end
+/// (GL/DX11-class) hardware may be missing.
+///
+/// These are reported by the adapter and can't be requested. Using a missing
+/// capability is a validation error rather than undefined backend behavior.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "trace", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct DownlevelCapabilities {
+    /// Compute pipelines and compute passes can be used.
+    pub compute_shaders: bool,
+    /// Indirect draws and dispatches can be recorded.
+    pub indirect_execution: bool,
+    /// Indirect draws can use a non-zero `first_instance`.
+    pub indirect_first_instance: bool,
+    /// Direct draws can use a non-zero base vertex and first instance.
+    pub base_vertex_instance: bool,
+    /// Texture views can have the `CubeArray` dimension.
+    pub cube_array_textures: bool,
+    /// Color targets of a pipeline can have different blend states and write masks.
+    pub independent_blend: bool,
+    /// Color targets of a pipeline can have different write masks,
+    /// even if their blend states have to match.
+    pub independent_write_mask: bool,
+    /// Samplers can use anisotropic filtering.
+    pub anisotropic_filtering: bool,
+    /// Fragment shaders can write to storage buffers and storage textures.
+    pub fragment_writable_storage: bool,
+    /// Depth bias can be clamped to a non-zero value.
+    pub depth_bias_clamp: bool,
+    /// Fragment shaders can force the depth and stencil tests to run before them.
+    pub early_fragment_tests: bool,
+    /// Fragment shaders writing the depth can declare that it only moves in one
+    /// direction, keeping the early depth tests enabled.
+    pub conservative_depth: bool,
+}
+
+impl Default for DownlevelCapabilities {
+    fn default() -> Self {
+        DownlevelCapabilities {
+            compute_shaders: true,
+            indirect_execution: true,
+            indirect_first_instance: true,
+            base_vertex_instance: true,
+            cube_array_textures: true,
+            independent_blend: true,
+            independent_write_mask: true,
+            anisotropic_filtering: true,
+            fragment_writable_storage: true,
+            depth_bias_clamp: true,
+            early_fragment_tests: true,
+            conservative_depth: true,
+        }
+    }
+}
+
+impl DownlevelCapabilities {
+    /// Returns true if the adapter supports everything WebGPU requires.
+    pub fn is_webgpu_compliant(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The limits guaranteed by WebGPU on every adapter.
 impl Default for Limits {
     fn default() -> Self {
         Limits {
             max_bind_groups: MAX_BIND_GROUPS as u32,
+            min_uniform_buffer_offset_alignment: BIND_BUFFER_ALIGNMENT as u32,
+            min_storage_buffer_offset_alignment: BIND_BUFFER_ALIGNMENT as u32,
+            max_compute_shared_memory_size: 16384,
+            max_storage_buffers_per_shader_stage: 4,
+            max_storage_textures_per_shader_stage: 4,
+            max_sampled_textures_per_shader_stage: 16,
+            max_samplers_per_shader_stage: 16,
+            max_uniform_buffers_per_shader_stage: 12,
+            max_bindings_per_bind_group: 640,
+            max_color_attachments: 4,
+            max_vertex_buffers: 8,
+            max_vertex_step_rate: 1,
+            max_buffer_size: 1 << 28,
+            max_texture_dimension_1d: 8192,
+            max_texture_dimension_2d: 8192,
+            max_texture_dimension_3d: 2048,
+            max_texture_array_layers: 256,
+        }
+    }
+}
+
+impl Limits {
+    /// Limits supported by downlevel adapters, like D3D11 and GL ES 3.0 ones,
+    /// which are lower than the WebGPU defaults.
+    pub fn downlevel_defaults() -> Self {
+        Limits {
+            max_texture_dimension_1d: 2048,
+            max_texture_dimension_2d: 2048,
+            max_texture_dimension_3d: 256,
+            ..Limits::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstanceDescriptor {
+    /// Validate the commands of passes as they are encoded, such as checking
+    /// draw calls against the sizes of the bound vertex and index buffers.
+    ///
+    /// Only the checks that are paid per command are affected. Turning them
+    /// off is only sound for trusted content. `Extensions::trusted_content`
+    /// turns them off for a single device instead.
+    pub validation: bool,
+    /// Vulkan instance extensions that have to be enabled. The Vulkan backend
+    /// is not used when any of them is missing.
+    pub required_extensions: Vec<String>,
+    /// Vulkan instance extensions that are used when available.
+    pub optional_extensions: Vec<String>,
+    /// Allow adapters of portability implementations, which are layered on top
+    /// of another API and don't fully conform, such as MoltenVK.
+    pub allow_portability: bool,
+}
+
+impl Default for InstanceDescriptor {
+    fn default() -> Self {
+        InstanceDescriptor {
+            validation: true,
+            required_extensions: Vec::new(),
+            optional_extensions: Vec::new(),
+            allow_portability: false,
         }
     }
 }
@@ -95,6 +323,41 @@ impl Default for Limits {
 pub struct DeviceDescriptor {
     pub extensions: Extensions,
     pub limits: Limits,
+    /// Return the existing sampler, bind group or texture view when one with
+    /// an identical descriptor is still alive, instead of creating a new object.
+    ///
+    /// Every returned handle still needs to be destroyed on its own.
+    /// This requires the identities to be managed by wgpu-core, since the
+    /// client can't know if the ID it provides ends up being used.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub deduplicate_objects: bool,
+    /// Size of the largest staging buffer of a queue write. Larger writes
+    /// are uploaded in several chunks. Defaults to 64 MiB.
+    ///
+    /// Must be a multiple of `COPY_BUFFER_ALIGNMENT`.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub staging_chunk_size: Option<BufferAddress>,
+    /// Total time in milliseconds to wait for the GPU when polling the device,
+    /// after which the device is considered hung, and gets lost.
+    /// Waits forever by default.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub hang_timeout_ms: Option<u64>,
+    /// Number of submissions the queue can have in flight before a submission
+    /// has to wait for the oldest one to complete. Defaults to 32.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub max_submissions_in_flight: Option<u32>,
+    /// Make a submission over `max_submissions_in_flight` fail instead of waiting,
+    /// so that the application can apply its own backpressure.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub nonblocking_submit: bool,
+    /// Validate exactly as the WebGPU specification requires, for runtimes
+    /// that expose wgpu-core as WebGPU.
+    ///
+    /// Native-only extensions and usages can't be used, the limits can't be
+    /// raised above the WebGPU defaults, and mapped ranges must be aligned
+    /// to `MAP_OFFSET_ALIGNMENT` and `MAP_SIZE_ALIGNMENT`.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub strict_webgpu: bool,
 }
 
 // TODO: This is copy/pasted from gfx-hal, so we need to find a new place to put
@@ -166,6 +429,9 @@ pub enum TextureViewDimension {
 
 pub type BufferAddress = u64;
 
+/// Size of a buffer range, which is never empty.
+pub type BufferSize = std::num::NonZeroU64;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 #[cfg_attr(feature = "trace", derive(Serialize))]
@@ -261,6 +527,15 @@ pub enum PrimitiveTopology {
     TriangleStrip = 4,
 }
 
+impl PrimitiveTopology {
+    pub fn is_strip(&self) -> bool {
+        match *self {
+            Self::LineStrip | Self::TriangleStrip => true,
+            Self::PointList | Self::LineList | Self::TriangleList => false,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 #[cfg_attr(feature = "trace", derive(Serialize))]
@@ -299,9 +574,16 @@ impl Default for CullMode {
 pub struct RasterizationStateDescriptor {
     pub front_face: FrontFace,
     pub cull_mode: CullMode,
+    /// Rasterize every pixel touched by a primitive, however little.
+    /// Requires the `conservative_rasterization` extension.
+    pub conservative: bool,
     pub depth_bias: i32,
     pub depth_bias_slope_scale: f32,
+    /// Anything other than zero requires `DownlevelCapabilities::depth_bias_clamp`.
     pub depth_bias_clamp: f32,
+    /// Ignore the bias values above, and take them from `set_depth_bias`
+    /// calls in the render pass instead.
+    pub dynamic_depth_bias: bool,
 }
 
 #[repr(C)]
@@ -394,6 +676,10 @@ pub struct DepthStencilStateDescriptor {
     pub stencil_back: StencilStateFaceDescriptor,
     pub stencil_read_mask: u32,
     pub stencil_write_mask: u32,
+    /// Ignore the stencil masks above, and take them from `set_stencil_read_mask`
+    /// and `set_stencil_write_mask` calls in the render pass instead.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub dynamic_stencil_masks: bool,
 }
 
 impl DepthStencilStateDescriptor {
@@ -539,6 +825,106 @@ pub enum VertexFormat {
     Int2 = 27,
     Int3 = 28,
     Int4 = 29,
+    Uchar = 30,
+    Char = 31,
+    UcharNorm = 32,
+    CharNorm = 33,
+    Ushort = 34,
+    Short = 35,
+    UshortNorm = 36,
+    ShortNorm = 37,
+    Half = 38,
+}
+
+impl VertexFormat {
+    /// Every vertex format, in declaration order.
+    pub const ALL: [VertexFormat; 39] = [
+        VertexFormat::Uchar2,
+        VertexFormat::Uchar4,
+        VertexFormat::Char2,
+        VertexFormat::Char4,
+        VertexFormat::Uchar2Norm,
+        VertexFormat::Uchar4Norm,
+        VertexFormat::Char2Norm,
+        VertexFormat::Char4Norm,
+        VertexFormat::Ushort2,
+        VertexFormat::Ushort4,
+        VertexFormat::Short2,
+        VertexFormat::Short4,
+        VertexFormat::Ushort2Norm,
+        VertexFormat::Ushort4Norm,
+        VertexFormat::Short2Norm,
+        VertexFormat::Short4Norm,
+        VertexFormat::Half2,
+        VertexFormat::Half4,
+        VertexFormat::Float,
+        VertexFormat::Float2,
+        VertexFormat::Float3,
+        VertexFormat::Float4,
+        VertexFormat::Uint,
+        VertexFormat::Uint2,
+        VertexFormat::Uint3,
+        VertexFormat::Uint4,
+        VertexFormat::Int,
+        VertexFormat::Int2,
+        VertexFormat::Int3,
+        VertexFormat::Int4,
+        VertexFormat::Uchar,
+        VertexFormat::Char,
+        VertexFormat::UcharNorm,
+        VertexFormat::CharNorm,
+        VertexFormat::Ushort,
+        VertexFormat::Short,
+        VertexFormat::UshortNorm,
+        VertexFormat::ShortNorm,
+        VertexFormat::Half,
+    ];
+
+    /// Size of an attribute of this format, in bytes.
+    pub fn size(self) -> BufferAddress {
+        match self {
+            VertexFormat::Uchar
+            | VertexFormat::Char
+            | VertexFormat::UcharNorm
+            | VertexFormat::CharNorm => 1,
+            VertexFormat::Uchar2
+            | VertexFormat::Char2
+            | VertexFormat::Uchar2Norm
+            | VertexFormat::Char2Norm
+            | VertexFormat::Ushort
+            | VertexFormat::Short
+            | VertexFormat::UshortNorm
+            | VertexFormat::ShortNorm
+            | VertexFormat::Half => 2,
+            VertexFormat::Uchar4
+            | VertexFormat::Char4
+            | VertexFormat::Uchar4Norm
+            | VertexFormat::Char4Norm
+            | VertexFormat::Ushort2
+            | VertexFormat::Short2
+            | VertexFormat::Ushort2Norm
+            | VertexFormat::Short2Norm
+            | VertexFormat::Half2
+            | VertexFormat::Float
+            | VertexFormat::Uint
+            | VertexFormat::Int => 4,
+            VertexFormat::Ushort4
+            | VertexFormat::Short4
+            | VertexFormat::Ushort4Norm
+            | VertexFormat::Short4Norm
+            | VertexFormat::Half4
+            | VertexFormat::Float2
+            | VertexFormat::Uint2
+            | VertexFormat::Int2 => 8,
+            VertexFormat::Float3 | VertexFormat::Uint3 | VertexFormat::Int3 => 12,
+            VertexFormat::Float4 | VertexFormat::Uint4 | VertexFormat::Int4 => 16,
+        }
+    }
+
+    /// Required alignment of an attribute offset, in bytes.
+    pub fn alignment(self) -> BufferAddress {
+        self.size().min(4)
+    }
 }
 
 bitflags::bitflags! {
@@ -558,6 +944,49 @@ bitflags::bitflags! {
     }
 }
 
+/// Hint for the kind of memory a buffer is placed in.
+///
+/// Placing a buffer in host-visible memory doesn't make it mappable:
+/// mapping still requires the `MAP_READ` or `MAP_WRITE` usage.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "trace", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub enum MemoryHint {
+    /// Pick the memory from the usage: device-local memory for buffers
+    /// that aren't mappable, and host memory suited to the mapping otherwise.
+    Performance = 0,
+    /// Prefer host memory, to save device-local memory for buffers
+    /// that the GPU rarely accesses.
+    MemoryUsage = 1,
+    /// Prefer host-visible memory that is fast for the GPU to read,
+    /// for data streamed from the host.
+    Upload = 2,
+    /// Prefer cached host memory, for data read back on the host.
+    Download = 3,
+}
+
+impl Default for MemoryHint {
+    fn default() -> Self {
+        MemoryHint::Performance
+    }
+}
+
+bitflags::bitflags! {
+    /// Properties of the memory a resource is placed in.
+    #[repr(transparent)]
+    pub struct MemoryProperties: u32 {
+        /// Fast for the GPU to access.
+        const DEVICE_LOCAL = 1;
+        /// Can be mapped on the host.
+        const HOST_VISIBLE = 2;
+        /// Host writes are visible to the GPU without flushing.
+        const HOST_COHERENT = 4;
+        /// Cached on the host, which makes reads from it fast.
+        const HOST_CACHED = 8;
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "trace", derive(Serialize))]
@@ -566,6 +995,15 @@ pub struct BufferDescriptor<L> {
     pub label: L,
     pub size: BufferAddress,
     pub usage: BufferUsage,
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub memory_hint: MemoryHint,
+    /// Hint that the buffer is only used within a frame, so its memory can be
+    /// sub-allocated from blocks owned by the device, and reused by the next
+    /// transient resources once the GPU is done with it.
+    ///
+    /// Transient buffers can't be mapped.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub transient: bool,
 }
 
 impl<L> BufferDescriptor<L> {
@@ -574,6 +1012,8 @@ impl<L> BufferDescriptor<L> {
             label: fun(&self.label),
             size: self.size,
             usage: self.usage,
+            memory_hint: self.memory_hint,
+            transient: self.transient,
         }
     }
 }
@@ -626,9 +1066,69 @@ bitflags::bitflags! {
         const SAMPLED = 4;
         const STORAGE = 8;
         const OUTPUT_ATTACHMENT = 16;
+        /// Makes an `OUTPUT_ATTACHMENT` live only within render passes, so that
+        /// it may stay in tile memory. It can't be copied, sampled, or used as
+        /// storage, and passes can neither load nor store its contents.
+        const TRANSIENT_ATTACHMENT = 32;
+    }
+}
+
+bitflags::bitflags! {
+    /// Capabilities of a texture format beyond the usages it allows.
+    #[repr(transparent)]
+    #[cfg_attr(feature = "trace", derive(Serialize))]
+    #[cfg_attr(feature = "replay", derive(Deserialize))]
+    pub struct TextureFormatFeatureFlags: u32 {
+        /// Textures of the format can be sampled with linear filtering.
+        const FILTERABLE = 1;
+        /// Output attachments of the format can be blended.
+        const BLENDABLE = 2;
+    }
+}
+
+/// Features of a texture format supported by an adapter.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "trace", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct TextureFormatFeatures {
+    /// Usages that textures of the format can be created with.
+    pub allowed_usages: TextureUsage,
+    pub flags: TextureFormatFeatureFlags,
+    /// Mask of the supported sample counts, with bit `n` set for `2^n` samples.
+    pub sample_counts: u32,
+}
+
+/// Color space that the presentation engine interprets swap chain images in.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "trace", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub enum ColorSpace {
+    /// Non-linear sRGB, the color space of standard displays.
+    Srgb = 0,
+    /// Non-linear Display-P3, a wider gamut with the sRGB transfer function.
+    DisplayP3 = 1,
+    /// Linear sRGB primaries, with values above 1.0 brighter than standard white.
+    ExtendedLinearSrgb = 2,
+}
+
+impl Default for ColorSpace {
+    fn default() -> Self {
+        ColorSpace::Srgb
     }
 }
 
+/// Format and color space that a surface can present swap chain images in.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "trace", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct SurfaceFormat {
+    pub format: TextureFormat,
+    pub color_space: ColorSpace,
+}
+
 #[repr(C)]
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "trace", derive(Serialize))]
@@ -639,6 +1139,28 @@ pub struct SwapChainDescriptor {
     pub width: u32,
     pub height: u32,
     pub present_mode: PresentMode,
+    /// Has to be one of the pairs with `format` returned by `surface_get_supported_formats`.
+    pub color_space: ColorSpace,
+}
+
+/// Region of a swap chain frame in physical pixels, counted from the top left corner.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct RectanglePhysicalPixels {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RectanglePhysicalPixels {
+    /// Returns true if the rectangle lies within a `width` by `height` frame.
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.width <= width
+            && self.height <= height
+            && self.x <= width - self.width
+            && self.y <= height - self.height
+    }
 }
 
 #[repr(C)]
@@ -649,6 +1171,9 @@ pub struct SwapChainDescriptor {
 pub enum LoadOp {
     Clear = 0,
     Load = 1,
+    /// The previous contents are undefined, and don't have to be read
+    /// into the tile memory. Every texel has to be drawn over by the pass.
+    DontCare = 2,
 }
 
 #[repr(C)]
@@ -792,6 +1317,11 @@ pub struct TextureDescriptor<L> {
     pub dimension: TextureDimension,
     pub format: TextureFormat,
     pub usage: TextureUsage,
+    /// Hint that the texture is only used within a frame, so its memory can be
+    /// sub-allocated from blocks owned by the device, and reused by the next
+    /// transient resources once the GPU is done with it.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub transient: bool,
 }
 
 impl<L> TextureDescriptor<L> {
@@ -804,6 +1334,7 @@ impl<L> TextureDescriptor<L> {
             dimension: self.dimension,
             format: self.format,
             usage: self.usage,
+            transient: self.transient,
         }
     }
 }
@@ -833,10 +1364,20 @@ pub struct TextureViewDescriptor<L> {
     pub format: TextureFormat,
     pub dimension: TextureViewDimension,
     pub aspect: TextureAspect,
+    /// First mip level visible through the view.
     pub base_mip_level: u32,
+    /// Number of mip levels, starting at `base_mip_level`.
+    /// Zero means all the remaining levels of the texture.
     pub level_count: u32,
+    /// First array layer visible through the view.
     pub base_array_layer: u32,
+    /// Number of array layers, starting at `base_array_layer`.
+    /// Zero means all the remaining layers of the texture.
     pub array_layer_count: u32,
+    /// Usages the view can be bound or attached with, which must be a subset
+    /// of the texture usage. All the usages of the texture if `None`.
+    #[cfg_attr(any(feature = "trace", feature = "replay"), serde(default))]
+    pub usage: Option<TextureUsage>,
 }
 
 impl<L> TextureViewDescriptor<L> {
@@ -850,6 +1391,7 @@ impl<L> TextureViewDescriptor<L> {
             level_count: self.level_count,
             base_array_layer: self.base_array_layer,
             array_layer_count: self.array_layer_count,
+            usage: self.usage,
         }
     }
 }
@@ -932,9 +1474,39 @@ pub struct CommandBufferDescriptor {
 #[cfg_attr(feature = "trace", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 pub enum TextureComponentType {
+    /// Float data that can be sampled with a filtering sampler.
     Float,
     Sint,
     Uint,
+    /// Float data that can only be sampled with a non-filtering sampler.
+    UnfilterableFloat,
+    /// Depth data.
+    Depth,
+}
+
+impl TextureComponentType {
+    /// Returns true if views of the given format can be bound
+    /// where this component type is expected.
+    pub fn is_compatible_with(self, format: TextureFormat) -> bool {
+        self.accepts(Self::from(format))
+    }
+
+    /// Returns true if views that are sampled as `actual` can be bound
+    /// where this component type is expected.
+    pub fn accepts(self, actual: Self) -> bool {
+        actual == self
+            || match (actual, self) {
+                // Filterable data can always be sampled without filtering.
+                (Self::Float, Self::UnfilterableFloat) => true,
+                (Self::Depth, Self::UnfilterableFloat) => true,
+                _ => false,
+            }
+    }
+
+    /// Returns true if the data can be sampled with a filtering sampler.
+    pub fn is_filterable(self) -> bool {
+        self == Self::Float
+    }
 }
 
 impl From<TextureFormat> for TextureComponentType {
@@ -963,26 +1535,44 @@ impl From<TextureFormat> for TextureComponentType {
             TextureFormat::R8Unorm
             | TextureFormat::R8Snorm
             | TextureFormat::R16Float
-            | TextureFormat::R32Float
             | TextureFormat::Rg8Unorm
             | TextureFormat::Rg8Snorm
             | TextureFormat::Rg16Float
             | TextureFormat::Rg11b10Float
-            | TextureFormat::Rg32Float
             | TextureFormat::Rgba8Snorm
             | TextureFormat::Rgba16Float
-            | TextureFormat::Rgba32Float
             | TextureFormat::Rgba8Unorm
             | TextureFormat::Rgba8UnormSrgb
             | TextureFormat::Bgra8Unorm
             | TextureFormat::Bgra8UnormSrgb
-            | TextureFormat::Rgb10a2Unorm
-            | TextureFormat::Depth32Float
+            | TextureFormat::Rgb10a2Unorm => Self::Float,
+
+            TextureFormat::R32Float | TextureFormat::Rg32Float | TextureFormat::Rgba32Float => {
+                Self::UnfilterableFloat
+            }
+
+            TextureFormat::Depth32Float
             | TextureFormat::Depth24Plus
-            | TextureFormat::Depth24PlusStencil8 => Self::Float,
+            | TextureFormat::Depth24PlusStencil8 => Self::Depth,
         }
     }
 }
 
-/// Bound uniform/storage buffer offsets must be aligned to this number.
+/// Default alignment of bound uniform/storage buffer offsets.
+///
+/// Adapters may support lower alignments, see `Limits`.
 pub const BIND_BUFFER_ALIGNMENT: u64 = 256;
+
+/// Buffer-texture copies must have `bytes_per_row` aligned to this number.
+pub const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+
+/// Buffer-buffer copies must have offsets and sizes aligned to this number.
+pub const COPY_BUFFER_ALIGNMENT: u64 = 4;
+
+/// Mapped ranges must start at a multiple of this number
+/// with `DeviceDescriptor::strict_webgpu`.
+pub const MAP_OFFSET_ALIGNMENT: u64 = 8;
+
+/// Mapped ranges must have a size that is a multiple of this number
+/// with `DeviceDescriptor::strict_webgpu`.
+pub const MAP_SIZE_ALIGNMENT: u64 = 4;